@@ -5,33 +5,45 @@ use std::process::Output;
 
 fn main() {
     set_git_ver_env_var();
+    set_git_commit_env_var();
 }
 
 fn set_git_ver_env_var() {
-    let git_ver = Command::new("git")
-        .args(&[
-            "describe",
-            "--tags",
-            "--first-parent",
-            "--always",
-            "--dirty",
-            "--broken",
-        ])
-        .output();
-
-    let git_ver = match git_ver {
+    let git_ver = git(&[
+        "describe",
+        "--tags",
+        "--first-parent",
+        "--always",
+        "--dirty",
+        "--broken",
+    ]);
+
+    eprintln!("Detected version from Git repository: {}", git_ver);
+
+    foreman::env_var("IRC_BOT_RS_GIT_VERSION", &git_ver);
+}
+
+fn set_git_commit_env_var() {
+    let git_commit = git(&["rev-parse", "--short", "HEAD"]);
+
+    eprintln!("Detected commit from Git repository: {}", git_commit);
+
+    foreman::env_var("IRC_BOT_RS_GIT_COMMIT", &git_commit);
+}
+
+/// Runs `git` with the given arguments, returning its output, or an empty string, with a warning,
+/// if it fails.
+fn git(args: &[&str]) -> String {
+    match Command::new("git").args(args).output() {
         Ok(Output {
             ref status,
             ref stdout,
             ..
-        }) if status.success() => {
-            let v = String::from_utf8_lossy(stdout);
-            eprintln!("Detected version from Git repository: {}", v);
-            v
-        }
+        }) if status.success() => String::from_utf8_lossy(stdout).trim().to_owned(),
         o => {
             foreman::warning(&format!(
-                "Error running `git describe`: {}",
+                "Error running `git {}`: {}",
+                args.join(" "),
                 match o {
                     Ok(Output { ref stderr, .. }) => String::from_utf8_lossy(stderr).to_string(),
                     Err(e) => e.to_string(),
@@ -39,7 +51,5 @@ fn set_git_ver_env_var() {
             ));
             "".into()
         }
-    };
-
-    foreman::env_var("IRC_BOT_RS_GIT_VERSION", &git_ver);
+    }
 }
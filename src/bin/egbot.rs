@@ -10,6 +10,9 @@ extern crate clap;
 extern crate log;
 
 use irc_bot::modules;
+use std::fs::File;
+use std::io;
+use std::io::BufReader;
 
 fn main() {
     let args = clap::App::new("egbot")
@@ -32,6 +35,25 @@ fn main() {
                 .case_insensitive(true)
                 .default_value("Display"),
         )
+        .arg(
+            clap::Arg::with_name("replay")
+                .long("replay")
+                .takes_value(true)
+                .value_name("recording-file")
+                .help("Replays a recorded session offline instead of connecting to servers"),
+        )
+        .arg(
+            clap::Arg::with_name("simulate")
+                .long("simulate")
+                .takes_value(true)
+                .value_name("input-file")
+                .conflicts_with("replay")
+                .help(
+                    "Handles raw IRC lines from the given file, or from standard input if it is \
+                     \"-\", and prints the lines that the bot would send, instead of connecting \
+                     to servers",
+                ),
+        )
         .get_matches();
 
     env_logger::init();
@@ -39,18 +61,53 @@ fn main() {
     let error_verbosity =
         value_t!(args, "error-verbosity", ErrorVerbosity).unwrap_or_else(|err| err.exit());
 
-    irc_bot::run(
-        irc_bot::Config::try_from_path(args.value_of("config-file").expect("default missing?")),
-        args.value_of("data-dir").expect("default missing?"),
-        move |err| {
-            match error_verbosity {
-                ErrorVerbosity::Display => error!("{}", err),
-                ErrorVerbosity::Debug => error!("{:?}", err),
-            }
-            irc_bot::ErrorReaction::Proceed
+    let config =
+        irc_bot::Config::try_from_path(args.value_of("config-file").expect("default missing?"));
+    let data_dir = args.value_of("data-dir").expect("default missing?");
+    let error_handler = move |err: irc_bot::Error| {
+        match error_verbosity {
+            ErrorVerbosity::Display => error!("{}", err),
+            ErrorVerbosity::Debug => error!("{:?}", err),
+        }
+        irc_bot::ErrorReaction::Proceed
+    };
+
+    match (args.value_of("replay"), args.value_of("simulate")) {
+        (Some(path), _) => match File::open(path) {
+            Ok(file) => irc_bot::replay(
+                config,
+                data_dir,
+                error_handler,
+                modules::ALL,
+                BufReader::new(file),
+            ),
+            Err(e) => error!("Failed to open recording {:?}: {}", path, e),
+        },
+        (None, Some("-")) => {
+            let stdin = io::stdin();
+
+            irc_bot::simulate(
+                config,
+                data_dir,
+                error_handler,
+                modules::ALL,
+                stdin.lock(),
+                io::stdout(),
+            )
+        }
+        (None, Some(path)) => match File::open(path) {
+            Ok(file) => irc_bot::simulate(
+                config,
+                data_dir,
+                error_handler,
+                modules::ALL,
+                BufReader::new(file),
+                io::stdout(),
+            ),
+            Err(e) => error!("Failed to open input {:?}: {}", path, e),
         },
-        modules::ALL,
-    );
+        (None, None) => irc_bot::run(config, data_dir, error_handler, modules::ALL),
+    }
 }
 
 arg_enum! {
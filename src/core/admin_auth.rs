@@ -0,0 +1,198 @@
+//! Challenge-response authentication, with which a user who knows the secret configured in the
+//! field `admin auth` may become an administrator of the bot for a limited time, regardless of the
+//! user's nickname, hostmask, or services account.
+//!
+//! A user asks for a challenge, which the bot issues as a random string, and answers it with the
+//! HMAC-SHA-256 of the challenge keyed with the secret, in hexadecimal. A challenge may be
+//! answered only once, within five minutes of being issued, and only by the user to whom it was
+//! issued. The session that a correct response grants lasts for the configured session length,
+//! during which the user's commands are authorized without further checks, unless it is ended
+//! sooner by the user's changing nickname, quitting, or being lost in a netsplit, or by the bot's
+//! reconnecting to the server, lest another user inherit it.
+
+use super::Result;
+use super::ServerId;
+use super::State;
+use super::UserId;
+use rand::Rng;
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+use util::sha256;
+
+/// How long a challenge may be answered after it has been issued
+const CHALLENGE_LIFETIME: Duration = Duration::from_secs(5 * 60);
+
+/// The number of random bytes of which a challenge is made
+const CHALLENGE_LEN: usize = 16;
+
+/// The challenges that have been issued to users of a server and not yet answered, and the
+/// sessions of the users who have answered them
+#[derive(Debug, Default)]
+pub(super) struct AdminAuthState {
+    /// The challenges, by the users to whom they were issued, with the times at which they were
+    /// issued
+    challenges: HashMap<UserId, (String, Instant)>,
+
+    /// The times at which users' sessions expire
+    sessions: HashMap<UserId, Instant>,
+}
+
+impl AdminAuthState {
+    fn has_session(&self, user: UserId) -> bool {
+        self.sessions
+            .get(&user)
+            .filter(|&&expiry| Instant::now() < expiry)
+            .is_some()
+    }
+
+    /// Ends the given user's session and withdraws any challenge issued to the user, e.g.,
+    /// because the user has changed nickname or quit.
+    pub(super) fn end(&mut self, user: UserId) {
+        self.challenges.remove(&user);
+        self.sessions.remove(&user);
+    }
+
+    /// Ends all sessions and withdraws all challenges, e.g., upon reconnecting to the server.
+    pub(super) fn clear(&mut self) {
+        self.challenges.clear();
+        self.sessions.clear();
+    }
+
+    fn expire(&mut self) {
+        let now = Instant::now();
+
+        self.challenges
+            .retain(|_, &mut (_, issued)| now.duration_since(issued) < CHALLENGE_LIFETIME);
+        self.sessions.retain(|_, &mut expiry| now < expiry);
+    }
+}
+
+impl State {
+    /// Returns whether the bot may authenticate administrators by challenge and response, i.e.,
+    /// whether the field `admin auth` of the configuration gives a secret.
+    pub fn admin_auth_enabled(&self) -> bool {
+        self.config().admin_auth.secret.is_some()
+    }
+
+    /// Issues a new challenge to the user on the given server with the given nickname, replacing
+    /// any that the user has not yet answered, and returns it, or returns `None` if the bot may
+    /// not authenticate administrators by challenge and response or doesn't know the user.
+    pub fn issue_admin_challenge(&self, server_id: ServerId, nick: &str) -> Result<Option<String>> {
+        if !self.admin_auth_enabled() {
+            return Ok(None);
+        }
+
+        let user = match self.user_id(server_id, nick)? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        let mut bytes = [0u8; CHALLENGE_LEN];
+        self.rng()?.fill(&mut bytes);
+
+        let challenge = sha256::to_hex(&bytes);
+
+        let mut server = self.write_server(server_id)?;
+        server.admin_auth.expire();
+        server
+            .admin_auth
+            .challenges
+            .insert(user, (challenge.clone(), Instant::now()));
+
+        Ok(Some(challenge))
+    }
+
+    /// Checks the given response to the challenge last issued to the user on the given server with
+    /// the given nickname, which may not be answered again, and, if it is correct, grants the user
+    /// a session as an administrator of the bot, returning how long it is to last. Returns `None`
+    /// if the response is incorrect or there is no challenge to answer.
+    pub fn answer_admin_challenge(
+        &self,
+        server_id: ServerId,
+        nick: &str,
+        response: &str,
+    ) -> Result<Option<Duration>> {
+        let (secret, session_length) = {
+            let config = self.config();
+
+            match config.admin_auth.secret {
+                Some(ref secret) => (secret.clone(), config.admin_auth.session_length()),
+                None => return Ok(None),
+            }
+        };
+
+        let user = match self.user_id(server_id, nick)? {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        let mut server = self.write_server(server_id)?;
+        server.admin_auth.expire();
+
+        let challenge = match server.admin_auth.challenges.remove(&user) {
+            Some((challenge, _)) => challenge,
+            None => return Ok(None),
+        };
+
+        let expected = sha256::to_hex(&sha256::hmac_sha256(
+            secret.as_bytes(),
+            challenge.as_bytes(),
+        ));
+
+        if !sha256::constant_time_eq(&expected, &response.trim().to_ascii_lowercase()) {
+            return Ok(None);
+        }
+
+        server
+            .admin_auth
+            .sessions
+            .insert(user, Instant::now() + session_length);
+
+        Ok(Some(session_length))
+    }
+
+    /// Returns whether the user on the given server with the given nickname has a session as an
+    /// administrator of the bot that has not yet expired.
+    pub(super) fn has_admin_session(&self, server_id: ServerId, nick: &str) -> Result<bool> {
+        let user = match self.user_id(server_id, nick)? {
+            Some(user) => user,
+            None => return Ok(false),
+        };
+
+        Ok(self.read_server(server_id)?.admin_auth.has_session(user))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::users::UserTable;
+    use super::*;
+    use util::irc::CaseMapping;
+
+    #[test]
+    fn sessions() {
+        let mut users = UserTable::default();
+        let alice = users.sighted(CaseMapping::Rfc1459, "alice").0;
+        let bob = users.sighted(CaseMapping::Rfc1459, "bob").0;
+        let mut auth = AdminAuthState::default();
+
+        auth.sessions
+            .insert(alice, Instant::now() + Duration::from_secs(60));
+        auth.sessions.insert(bob, Instant::now());
+        auth.challenges
+            .insert(bob, ("challenge".into(), Instant::now()));
+
+        assert!(auth.has_session(alice));
+        assert!(!auth.has_session(bob));
+
+        auth.expire();
+        assert!(!auth.sessions.contains_key(&bob));
+        assert!(auth.challenges.contains_key(&bob));
+
+        auth.end(alice);
+        auth.end(bob);
+        assert!(!auth.has_session(alice));
+        assert!(auth.challenges.is_empty());
+    }
+}
@@ -0,0 +1,189 @@
+use super::ErrorKind;
+use super::Result;
+use super::ServerId;
+use super::State;
+use serde_yaml;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::RwLockWriteGuard;
+use util::irc::casemapped_str_cmp;
+
+/// The placeholder in an alias's expansion for the rest of the command line with which the alias
+/// was used
+const ARGS_PLACEHOLDER: &str = "%args%";
+
+/// The command aliases that the bot's administrators have defined while the bot runs, by name
+#[derive(Debug, Default)]
+pub(super) struct RuntimeAliases {
+    aliases: BTreeMap<String, String>,
+
+    /// The file in which the aliases are stored, if one is configured
+    path: Option<PathBuf>,
+}
+
+impl RuntimeAliases {
+    /// Loads the aliases stored in the given file, if one is given and it exists.
+    pub(super) fn load(path: Option<PathBuf>) -> Result<Self> {
+        let aliases = match path {
+            Some(ref path) => match fs::File::open(path) {
+                Ok(file) => serde_yaml::from_reader(file)?,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+                Err(e) => return Err(e.into()),
+            },
+            None => BTreeMap::new(),
+        };
+
+        Ok(RuntimeAliases { aliases, path })
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        fs::write(path, serde_yaml::to_string(&self.aliases)?)?;
+
+        Ok(())
+    }
+}
+
+impl State {
+    /// Defines an alias with the given name for the given command line, replacing any alias that
+    /// the bot's administrators have defined with that name before, and stores it in the
+    /// configured `aliases file`, if any.
+    ///
+    /// When a message addressed to the bot begins with the alias's name, the name is replaced with
+    /// the command line before the bot looks up the command to run. Any occurrences of `%args%`
+    /// in the command line are replaced with the rest of the message; if there are none, the rest
+    /// of the message is appended to the command line. An alias cannot have the name of a
+    /// command.
+    pub fn set_alias(&self, name: &str, expansion: &str) -> Result<()> {
+        validate_alias(name, expansion)
+            .map_err(|problem| ErrorKind::InvalidAlias(name.to_owned(), problem))?;
+
+        ensure!(
+            self.read_commands()
+                .keys()
+                .all(|cmd| !cmd.eq_ignore_ascii_case(name)),
+            ErrorKind::InvalidAlias(name.to_owned(), "has the name of a command".into())
+        );
+
+        let mut aliases = self.write_aliases()?;
+        aliases
+            .aliases
+            .insert(name.to_owned(), expansion.to_owned());
+        aliases.save()
+    }
+
+    /// Removes the alias with the given name that the bot's administrators have defined, if there
+    /// is one, returning whether there was.
+    pub fn remove_alias(&self, name: &str) -> Result<bool> {
+        let mut aliases = self.write_aliases()?;
+
+        if aliases.aliases.remove(name).is_none() {
+            return Ok(false);
+        }
+
+        aliases.save()?;
+
+        Ok(true)
+    }
+
+    /// Returns the aliases defined in the configuration and by the bot's administrators, the
+    /// latter taking precedence.
+    pub fn aliases(&self) -> Result<BTreeMap<String, String>> {
+        let mut aliases = self.config().aliases.clone();
+
+        aliases.extend(
+            self.aliases
+                .read()
+                .map_err(|_| ErrorKind::LockPoisoned("the command aliases".into()))?
+                .aliases
+                .iter()
+                .map(|(name, expansion)| (name.clone(), expansion.clone())),
+        );
+
+        Ok(aliases)
+    }
+
+    fn write_aliases<'a>(&'a self) -> Result<RwLockWriteGuard<'a, RuntimeAliases>> {
+        Ok(self
+            .aliases
+            .write()
+            .map_err(|_| ErrorKind::LockPoisoned("the command aliases".into()))?)
+    }
+}
+
+/// Returns the given command line with its first word, if that is the name of an alias rather
+/// than of a command, replaced per the alias. Aliases are not expanded within aliases.
+pub(super) fn resolve<'a>(
+    state: &State,
+    server_id: ServerId,
+    cmd_ln: &'a str,
+) -> Result<Cow<'a, str>> {
+    let mut words = cmd_ln.splitn(2, char::is_whitespace);
+    let name = words.next().unwrap_or_default();
+    let args = words.next().unwrap_or_default().trim();
+
+    if name.is_empty() || state.command(server_id, name)?.is_some() {
+        return Ok(Cow::Borrowed(cmd_ln));
+    }
+
+    let casemapping = state.casemapping(server_id)?;
+
+    let expansion = state
+        .aliases()?
+        .into_iter()
+        .find(|(alias, _)| casemapped_str_cmp(casemapping, &alias[..], name) == Ordering::Equal)
+        .map(|(_, expansion)| expansion);
+
+    Ok(match expansion {
+        Some(expansion) => Cow::Owned(expand(&expansion, args)),
+        None => Cow::Borrowed(cmd_ln),
+    })
+}
+
+fn expand(expansion: &str, args: &str) -> String {
+    if expansion.contains(ARGS_PLACEHOLDER) {
+        expansion.replace(ARGS_PLACEHOLDER, args).trim().to_owned()
+    } else if args.is_empty() {
+        expansion.to_owned()
+    } else {
+        format!("{} {}", expansion, args)
+    }
+}
+
+/// Checks that the given alias name and expansion are valid, or else describes the problem.
+pub(super) fn validate_alias(name: &str, expansion: &str) -> ::std::result::Result<(), String> {
+    if name.is_empty() || name.contains(char::is_whitespace) {
+        return Err("has an empty name or one with whitespace".into());
+    }
+
+    if expansion.trim().is_empty() {
+        return Err("expands to nothing".into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expansion() {
+        assert_eq!(expand("latency", ""), "latency");
+        assert_eq!(expand("latency", "now"), "latency now");
+        assert_eq!(expand("google %args%", "rust irc"), "google rust irc");
+        assert_eq!(expand("google %args% -site:x", ""), "google  -site:x");
+        assert_eq!(
+            expand("say {to: '%args%', msg: hi}", "#c"),
+            "say {to: '#c', msg: hi}"
+        );
+    }
+}
@@ -0,0 +1,86 @@
+use super::irc_msgs::format_server_time;
+use super::BotCmdResult;
+use super::BotCommand;
+use super::MsgMetadata;
+use super::Result;
+use super::State;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::SystemTime;
+
+/// Records, as the configuration field `audit log` specifies, that the given command, at the
+/// authorization level `Admin`, was used with the given arguments, with the given result.
+pub(super) fn record(
+    state: &State,
+    cmd: &BotCommand,
+    metadata: &MsgMetadata,
+    cmd_args: &str,
+    result: &BotCmdResult,
+) {
+    if let Err(e) = try_record(state, cmd, metadata, cmd_args, result) {
+        error!(
+            "Failed to record the use of the command {:?} in the audit log: {}",
+            cmd.name, e
+        );
+    }
+}
+
+fn try_record(
+    state: &State,
+    cmd: &BotCommand,
+    metadata: &MsgMetadata,
+    cmd_args: &str,
+    result: &BotCmdResult,
+) -> Result<()> {
+    let cfg = &state.config().audit_log;
+
+    if cfg.file.is_none() && cfg.channel.is_none() {
+        return Ok(());
+    }
+
+    let server_id = metadata.dest.server_id;
+
+    let entry = format!(
+        "{prefix} used the command {cmd:?} with the arguments {args:?} in {target} on {server}: \
+         {outcome}",
+        prefix = metadata.prefix.to_owning()?.as_str(),
+        cmd = cmd.name,
+        args = cmd_args,
+        target = metadata.dest.target,
+        server = state.get_server_config(server_id)?.name,
+        outcome = describe_outcome(result),
+    );
+
+    if let Some(ref path) = cfg.file {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(
+                format!("[{}] {}\n", format_server_time(SystemTime::now()), entry).as_bytes(),
+            )?;
+    }
+
+    if let Some(ref channel) = cfg.channel {
+        state.send_privmsg(server_id, channel, &entry)?;
+    }
+
+    Ok(())
+}
+
+fn describe_outcome(result: &BotCmdResult) -> String {
+    match *result {
+        BotCmdResult::Ok(_) => "succeeded".into(),
+        BotCmdResult::Unauthorized => "refused, as the user is not an administrator".into(),
+        BotCmdResult::ParamUnauthorized(ref param) => {
+            format!("refused, as the user may not use the parameter {:?}", param)
+        }
+        BotCmdResult::SyntaxErr | BotCmdResult::ArgMissing(_) | BotCmdResult::ArgMissing1To1(_) => {
+            "failed, with a syntax error".into()
+        }
+        BotCmdResult::LibErr(ref e) => format!("failed: {}", e),
+        BotCmdResult::UserErrMsg(ref s) | BotCmdResult::BotErrMsg(ref s) => {
+            format!("failed: {}", s)
+        }
+    }
+}
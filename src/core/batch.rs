@@ -0,0 +1,401 @@
+use super::irc_msgs::OwningMsgPrefix;
+use super::users;
+use super::Result;
+use super::ServerId;
+use super::State;
+use irc::client::prelude as aatxe;
+use irc::proto::BatchSubCommand;
+use irc::proto::Message;
+
+/// The IRCv3 batches that a server has opened and not yet closed
+#[derive(Debug, Default)]
+pub(super) struct OpenBatches {
+    batches: Vec<OpenBatch>,
+}
+
+#[derive(Debug)]
+struct OpenBatch {
+    reference: String,
+    kind: BatchKind,
+}
+
+#[derive(Debug)]
+enum BatchKind {
+    /// A batch of the users who quit in a netsplit between the two named servers, along with
+    /// their nicknames as collected from the batch's `QUIT` messages
+    Netsplit {
+        servers: Option<(String, String)>,
+        nicks: Vec<String>,
+    },
+
+    /// A batch of the users who rejoined upon the end of a netsplit between the two named
+    /// servers, along with their nicknames as collected from the batch's `JOIN` messages
+    Netjoin {
+        servers: Option<(String, String)>,
+        nicks: Vec<String>,
+    },
+
+    /// A batch of past messages replayed from the server's history, which the bot must not treat
+    /// as having just been sent, e.g., by responding to bot commands in them, but which may have
+    /// been requested with `State::fetch_history`
+    History,
+
+    /// A batch of a type of which the bot handles the messages as it would if they were not
+    /// batched, such as a `labeled-response` batch
+    Other,
+}
+
+impl OpenBatches {
+    /// Forgets all open batches, e.g., upon reconnecting to the server.
+    pub(super) fn clear(&mut self) {
+        self.batches.clear()
+    }
+
+    fn get_mut(&mut self, reference: &str) -> Option<&mut OpenBatch> {
+        self.batches.iter_mut().find(|b| b.reference == reference)
+    }
+
+    fn take(&mut self, reference: &str) -> Option<OpenBatch> {
+        self.batches
+            .iter()
+            .position(|b| b.reference == reference)
+            .map(|i| self.batches.remove(i))
+    }
+}
+
+impl BatchKind {
+    fn new(subcmd: Option<&BatchSubCommand>, params: Option<&Vec<String>>) -> Self {
+        let servers = || match params.map(|p| (p.first(), p.get(1))) {
+            Some((Some(server_1), Some(server_2))) => Some((server_1.clone(), server_2.clone())),
+            _ => None,
+        };
+
+        match subcmd {
+            Some(BatchSubCommand::NETSPLIT) => BatchKind::Netsplit {
+                servers: servers(),
+                nicks: Vec::new(),
+            },
+            Some(BatchSubCommand::NETJOIN) => BatchKind::Netjoin {
+                servers: servers(),
+                nicks: Vec::new(),
+            },
+            Some(BatchSubCommand::CUSTOM(ref name)) if name == "CHATHISTORY" => BatchKind::History,
+            _ => BatchKind::Other,
+        }
+    }
+}
+
+/// Tracks the IRCv3 batches that the given server opens and closes, and collects the messages
+/// belonging to batches whose messages the bot handles as a unit.
+///
+/// Returns whether the given message has been handled, in which case the caller must not handle
+/// it further.
+pub(super) fn handle_msg(state: &State, server_id: ServerId, msg: &Message) -> Result<bool> {
+    let batch_tag = msg
+        .tags
+        .iter()
+        .flat_map(|tags| tags.iter())
+        .find(|tag| tag.0 == "batch")
+        .and_then(|tag| tag.1.as_ref());
+
+    match msg.command {
+        aatxe::Command::BATCH(ref reference, ref subcmd, ref params)
+            if reference.starts_with('+') =>
+        {
+            let mut server = state.write_server(server_id)?;
+            let server = &mut *server;
+            let batches = &mut server.batches;
+
+            // A batch nested in a batch of history is itself history.
+            let kind = match batch_tag.and_then(|parent| batches.get_mut(parent)) {
+                Some(&mut OpenBatch {
+                    kind: BatchKind::History,
+                    ..
+                }) => BatchKind::History,
+                _ => {
+                    let kind = BatchKind::new(subcmd.as_ref(), params.as_ref());
+
+                    if let (&BatchKind::History, Some(target)) =
+                        (&kind, params.as_ref().and_then(|p| p.first()))
+                    {
+                        server.history_fetches.batch_opened(
+                            server.capabilities.casemapping,
+                            &reference[1..],
+                            target,
+                        );
+                    }
+
+                    kind
+                }
+            };
+
+            batches.batches.push(OpenBatch {
+                reference: reference[1..].to_owned(),
+                kind,
+            });
+
+            Ok(true)
+        }
+        aatxe::Command::BATCH(ref reference, ..) if reference.starts_with('-') => {
+            let reference = &reference[1..];
+            let batch = state.write_server(server_id)?.batches.take(reference);
+
+            match batch.map(|b| b.kind) {
+                Some(BatchKind::Netsplit { servers, nicks }) => {
+                    users::handle_netsplit(state, server_id, servers, nicks)?
+                }
+                Some(BatchKind::Netjoin { servers, nicks }) => {
+                    users::handle_netjoin(state, server_id, servers, nicks)?
+                }
+                Some(BatchKind::History) => state
+                    .write_server(server_id)?
+                    .history_fetches
+                    .batch_closed(reference),
+                Some(BatchKind::Other) | None => {}
+            }
+
+            Ok(true)
+        }
+        ref command => {
+            let reference = match batch_tag {
+                Some(reference) => reference,
+                None => return Ok(false),
+            };
+
+            let nick = || {
+                msg.prefix.as_ref().and_then(|p| {
+                    OwningMsgPrefix::from_string(p.clone())
+                        .parse()
+                        .nick
+                        .map(ToOwned::to_owned)
+                })
+            };
+
+            let mut server = state.write_server(server_id)?;
+            let server = &mut *server;
+
+            match (
+                server.batches.get_mut(reference).map(|b| &mut b.kind),
+                command,
+            ) {
+                (Some(BatchKind::Netsplit { nicks, .. }), aatxe::Command::QUIT(..))
+                | (Some(BatchKind::Netjoin { nicks, .. }), aatxe::Command::JOIN(..)) => {
+                    nicks.extend(nick());
+
+                    // An extended `JOIN` in a netjoin batch still reports the user's account.
+                    match (command, nick()) {
+                        (aatxe::Command::JOIN(_, Some(account), _), Some(nick))
+                            if server.enabled_caps.contains("extended-join") =>
+                        {
+                            let casemapping = server.capabilities.casemapping;
+                            server.users.set_account(
+                                casemapping,
+                                &nick,
+                                users::parse_account(account),
+                            );
+                        }
+                        _ => {}
+                    }
+
+                    Ok(true)
+                }
+                (Some(BatchKind::History), _) => {
+                    server.history_fetches.record(reference, msg);
+                    Ok(true)
+                }
+                _ => Ok(false),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::mk_module;
+    use core::offline::OfflineBot;
+    use core::UserEvent;
+    use std::sync::Arc;
+    use std::sync::Mutex;
+
+    /// Returns an offline bot with a module that records the netsplits and netjoins of which it is
+    /// told, along with the record.
+    fn recording_bot() -> (OfflineBot, Arc<Mutex<Vec<String>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorder = events.clone();
+
+        let bot = OfflineBot::for_test(
+            "",
+            vec![move || {
+                let recorder = recorder.clone();
+
+                mk_module("recorder")
+                    .on_user_event(Box::new(move |_: &State, _, event: &UserEvent| {
+                        let line = match *event {
+                            UserEvent::Netsplit {
+                                ref servers,
+                                ref users,
+                            } => format!("netsplit {:?} {:?}", servers, nicks(users)),
+                            UserEvent::Netjoin {
+                                ref servers,
+                                ref users,
+                            } => format!("netjoin {:?} {:?}", servers, nicks(users)),
+                            _ => return Ok(()),
+                        };
+
+                        recorder.lock().unwrap().push(line);
+                        Ok(())
+                    }))
+                    .end()
+            }],
+        );
+
+        (bot, events)
+    }
+
+    fn nicks<T>(users: &[(T, String)]) -> Vec<&str> {
+        users.iter().map(|&(_, ref nick)| &nick[..]).collect()
+    }
+
+    /// Has the bot handle the given lines as batch messages, returning whether it handled each.
+    fn feed(state: &State, server_id: ServerId, lines: &[&str]) -> Vec<bool> {
+        lines
+            .iter()
+            .map(|line| {
+                let msg: Message = format!("{}\r\n", line).parse().unwrap();
+                handle_msg(state, server_id, &msg).unwrap()
+            })
+            .collect()
+    }
+
+    fn take(events: &Mutex<Vec<String>>) -> Vec<String> {
+        events.lock().unwrap().drain(..).collect()
+    }
+
+    #[test]
+    fn netsplit() {
+        let (bot, events) = recording_bot();
+        let state = bot.state();
+        let server_id = state.server_ids()[0];
+
+        let handled = feed(
+            state,
+            server_id,
+            &[
+                ":irc.invalid BATCH +s1 netsplit irc.a.net irc.b.net",
+                "@batch=s1 :alice!a@example.com QUIT :irc.a.net irc.b.net",
+                ":carol!c@example.com PRIVMSG #chan :Where did everyone go?",
+                "@batch=s1 :bob!b@example.com QUIT :irc.a.net irc.b.net",
+            ],
+        );
+        assert_eq!(handled, [true, true, false, true]);
+        assert!(take(&events).is_empty());
+
+        feed(state, server_id, &[":irc.invalid BATCH -s1"]);
+        assert_eq!(
+            take(&events),
+            [r#"netsplit Some(("irc.a.net", "irc.b.net")) ["alice", "bob"]"#]
+        );
+    }
+
+    #[test]
+    fn netjoin() {
+        let (bot, events) = recording_bot();
+        let state = bot.state();
+        let server_id = state.server_ids()[0];
+
+        let handled = feed(
+            state,
+            server_id,
+            &[
+                ":irc.invalid BATCH +j1 netjoin irc.a.net irc.b.net",
+                "@batch=j1 :alice!a@example.com JOIN #chan",
+                "@batch=j1 :alice!a@example.com PRIVMSG #chan :I'm back.",
+                "@batch=j1 :bob!b@example.com JOIN #chan",
+                ":irc.invalid BATCH -j1",
+            ],
+        );
+        assert_eq!(handled, [true, true, false, true, true]);
+        assert_eq!(
+            take(&events),
+            [r#"netjoin Some(("irc.a.net", "irc.b.net")) ["alice", "bob"]"#]
+        );
+    }
+
+    #[test]
+    fn unterminated_batch() {
+        let (bot, events) = recording_bot();
+        let state = bot.state();
+        let server_id = state.server_ids()[0];
+
+        feed(
+            state,
+            server_id,
+            &[
+                ":irc.invalid BATCH +s1 netsplit irc.a.net irc.b.net",
+                "@batch=s1 :alice!a@example.com QUIT :irc.a.net irc.b.net",
+            ],
+        );
+        assert!(take(&events).is_empty());
+
+        // Upon reconnection, the batch is forgotten, and its end, were it to come, ignored, as is
+        // the end of a batch that was never opened.
+        state.write_server(server_id).unwrap().batches.clear();
+
+        let handled = feed(
+            state,
+            server_id,
+            &[":irc.invalid BATCH -s1", ":irc.invalid BATCH -never"],
+        );
+        assert_eq!(handled, [true, true]);
+        assert!(take(&events).is_empty());
+    }
+
+    #[test]
+    fn nested_batches() {
+        let (bot, events) = recording_bot();
+        let state = bot.state();
+        let server_id = state.server_ids()[0];
+
+        let handled = feed(
+            state,
+            server_id,
+            &[
+                ":irc.invalid BATCH +outer labeled-response",
+                "@batch=outer :irc.invalid BATCH +s1 netsplit irc.a.net irc.b.net",
+                "@batch=outer :irc.invalid BATCH +s2 netsplit irc.c.net irc.d.net",
+                "@batch=s1 :alice!a@example.com QUIT :irc.a.net irc.b.net",
+                "@batch=s2 :bob!b@example.com QUIT :irc.c.net irc.d.net",
+                "@batch=outer :carol!c@example.com PRIVMSG #chan :Netsplits!",
+                ":irc.invalid BATCH -s2",
+                ":irc.invalid BATCH -s1",
+                ":irc.invalid BATCH -outer",
+            ],
+        );
+        assert_eq!(
+            handled,
+            [true, true, true, true, true, false, true, true, true]
+        );
+        assert_eq!(
+            take(&events),
+            [
+                r#"netsplit Some(("irc.c.net", "irc.d.net")) ["bob"]"#,
+                r#"netsplit Some(("irc.a.net", "irc.b.net")) ["alice"]"#,
+            ]
+        );
+
+        // A netsplit replayed from history is not one that has just happened.
+        feed(
+            state,
+            server_id,
+            &[
+                ":irc.invalid BATCH +h chathistory #chan",
+                "@batch=h :irc.invalid BATCH +s3 netsplit irc.a.net irc.b.net",
+                "@batch=s3 :dave!d@example.com QUIT :irc.a.net irc.b.net",
+                ":irc.invalid BATCH -s3",
+                ":irc.invalid BATCH -h",
+            ],
+        );
+        assert!(take(&events).is_empty());
+    }
+}
@@ -1,11 +1,17 @@
+use super::audit;
+use super::chan_cmds;
+use super::cmd_stats::CommandOutcome;
 use super::BotCmdHandler;
 use super::Error;
+use super::ErrorKind;
 use super::HandlerContext;
 use super::Module;
 use super::ModuleFeatureRef;
+use super::MsgDest;
 use super::MsgMetadata;
 use super::Reaction;
 use super::Result;
+use super::ServerId;
 use super::State;
 use irc;
 use rand;
@@ -15,11 +21,20 @@ use std;
 use std::borrow::Cow;
 use std::io;
 use std::num::ParseIntError;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
 use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
 use util;
 use walkdir;
 use yaml_rust::Yaml;
 
+/// How many similarly named commands to suggest at most when an unknown command is used
+const MAX_SUGGESTIONS: usize = 3;
+
 #[derive(CustomDebug)]
 pub struct BotCommand {
     pub name: Cow<'static, str>,
@@ -33,14 +48,53 @@ pub struct BotCommand {
 
     pub usage_str: Cow<'static, str>,
 
+    /// The syntax against which the command's arguments are checked, or `None` if they are to be
+    /// passed to the handler function unparsed, as a YAML string
     #[debug(skip)]
-    pub(super) usage_yaml: Yaml,
+    pub(super) usage_yaml: Option<Yaml>,
 
     pub help_msg: Cow<'static, str>,
+
+    /// How the command's output is delivered, unless the configuration specifies otherwise
+    pub reply_route: ReplyRoute,
+
+    /// The number of times that the command's handler function has panicked
+    pub(super) panic_count: AtomicUsize,
 }
 
 #[derive(Debug)]
-pub enum BotCmdAttr {}
+pub enum BotCmdAttr {
+    /// Deliver the command's output in the given way by default.
+    ReplyRoute(ReplyRoute),
+}
+
+/// How the output of a bot command is delivered
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+pub enum ReplyRoute {
+    /// Reply in the channel in which the command was used, or by private message if it was used
+    /// in private.
+    #[serde(rename = "channel")]
+    SameDest,
+
+    /// Reply to the user who used the command by `NOTICE`.
+    #[serde(rename = "notice")]
+    Notice,
+
+    /// Reply to the user who used the command by private message.
+    #[serde(rename = "private")]
+    Private,
+
+    /// Reply as with `SameDest`, unless the output would take more than the given number of
+    /// messages, in which case reply as with `Private`.
+    #[serde(rename = "private if over")]
+    PrivateIfOver(usize),
+}
+
+impl Default for ReplyRoute {
+    fn default() -> Self {
+        ReplyRoute::SameDest
+    }
+}
 
 #[derive(Debug)]
 pub enum BotCmdResult {
@@ -138,66 +192,121 @@ pub enum BotCmdAuthLvl {
 }
 
 pub(super) fn run(
-    state: &State,
+    state: &Arc<State>,
     cmd_name: &str,
     cmd_args: &str,
     metadata: &MsgMetadata,
 ) -> Result<Option<BotCmdResult>> {
-    let cmd_ref = match state.commands.get(cmd_name) {
+    let cmd = match state.command(metadata.dest.server_id, cmd_name)? {
         Some(c) => c,
         None => return Ok(None),
     };
+    let cmd_ref = &*cmd;
 
     let &BotCommand {
         ref name,
         ref provider,
         ref auth_lvl,
-        ref handler,
+        handler: _,
         ref usage_yaml,
         usage_str: _,
         help_msg: _,
+        reply_route: _,
+        panic_count: _,
     } = cmd_ref;
 
+    if cmd_ref.is_disabled(state) {
+        return Ok(Some(BotCmdResult::BotErrMsg(
+            state
+                .localize(
+                    metadata.dest,
+                    metadata.prefix,
+                    "command disabled",
+                    &[("command", &format!("{:?}", name))],
+                )
+                .into(),
+        )));
+    }
+
+    if !chan_cmds::command_enabled(
+        state,
+        metadata.dest.server_id,
+        metadata.dest.target,
+        cmd_ref,
+    )? {
+        return Ok(Some(BotCmdResult::UserErrMsg(
+            state
+                .localize(
+                    metadata.dest,
+                    metadata.prefix,
+                    "command not enabled here",
+                    &[("command", &format!("{:?}", name))],
+                )
+                .into(),
+        )));
+    }
+
     let invoker_prefix = metadata.prefix;
 
     let user_authorized = match auth_lvl {
         &BotCmdAuthLvl::Public => Ok(true),
-        &BotCmdAuthLvl::Admin => state.have_admin(invoker_prefix),
+        &BotCmdAuthLvl::Admin => state.have_admin(metadata.dest.server_id, invoker_prefix),
     };
 
-    let arg = match parse_arg(usage_yaml, cmd_args) {
-        Ok(arg) => arg,
-        Err(res) => return Ok(Some(res)),
+    let arg = match *usage_yaml {
+        Some(ref syntax) => parse_arg(syntax, cmd_args),
+        None => Ok(Yaml::String(cmd_args.to_owned())),
     };
 
-    let result = match user_authorized {
-        Ok(true) => {
+    let mut run_time = None;
+    let mut timed_out = false;
+
+    let result = match (arg, user_authorized) {
+        (Err(res), _) => res,
+        (Ok(arg), Ok(true)) => {
             debug!(
                 "Running bot command {:?} invoked by {:?} with argument {:?}",
                 name, invoker_prefix, cmd_args
             );
 
-            let ctx = HandlerContext {
-                state,
-                this_feature: ModuleFeatureRef::Command(cmd_ref),
-                request_origin: metadata.dest,
-                invoker: invoker_prefix,
-                __nonexhaustive: (),
+            let output = match state.config().timeout_for_command(name) {
+                Some(timeout) => {
+                    invoke_handler_with_timeout(state, cmd_ref, metadata, arg, timeout)?
+                        .ok_or(timeout)
+                }
+                None => Ok(invoke_handler(state, cmd_ref, metadata, &arg)),
             };
 
-            match util::run_handler("command", name.clone(), || handler.run(ctx, &arg)) {
-                Ok(r) => r,
-                Err(e) => BotCmdResult::LibErr(e),
+            match output {
+                Ok((result, elapsed)) => {
+                    run_time = Some(elapsed);
+                    result
+                }
+                Err(timeout) => {
+                    run_time = Some(timeout);
+                    timed_out = true;
+
+                    BotCmdResult::BotErrMsg(
+                        state
+                            .localize(
+                                metadata.dest,
+                                metadata.prefix,
+                                "command timed out",
+                                &[("command", &format!("{:?}", name))],
+                            )
+                            .into(),
+                    )
+                }
             }
         }
-        Ok(false) => BotCmdResult::Unauthorized,
-        Err(e) => BotCmdResult::LibErr(e),
+        (Ok(_), Ok(false)) => BotCmdResult::Unauthorized,
+        (Ok(_), Err(e)) => BotCmdResult::LibErr(e),
     };
 
     // TODO: Filter `QUIT`s in `irc_send` instead, and check `Reaction::RawMsg`s as well.
-    match result {
+    let result = match result {
         BotCmdResult::Ok(Reaction::Quit(ref s)) if *auth_lvl != BotCmdAuthLvl::Admin => {
-            Ok(Some(BotCmdResult::BotErrMsg(
+            BotCmdResult::BotErrMsg(
                 format!(
                     "Only commands at authorization level {auth_lvl_owner:?} \
                      may tell the bot to quit, but the command {cmd_name:?} \
@@ -211,10 +320,301 @@ pub(super) fn run(
                     quit_msg = s
                 )
                 .into(),
-            )))
+            )
         }
-        r => Ok(Some(r)),
+        r => r,
+    };
+
+    state.record_command_use(
+        name,
+        run_time,
+        match result {
+            _ if timed_out => CommandOutcome::TimedOut,
+            BotCmdResult::Ok(_) => CommandOutcome::Succeeded,
+            _ => CommandOutcome::Failed,
+        },
+    );
+
+    if *auth_lvl == BotCmdAuthLvl::Admin {
+        audit::record(state, cmd_ref, metadata, cmd_args, &result);
     }
+
+    Ok(Some(result))
+}
+
+/// Runs the handler of the given command with the given argument, returning the handler's result
+/// and how long it took to run.
+fn invoke_handler(
+    state: &State,
+    cmd: &BotCommand,
+    metadata: &MsgMetadata,
+    arg: &Yaml,
+) -> (BotCmdResult, Duration) {
+    let ctx = HandlerContext {
+        state,
+        this_feature: ModuleFeatureRef::Command(cmd),
+        request_origin: metadata.dest,
+        invoker: metadata.prefix,
+        request_tags: metadata.tags,
+        request_is_action: false,
+        __nonexhaustive: (),
+    };
+
+    let start = Instant::now();
+    let result = util::run_handler("command", cmd.name.clone(), || cmd.handler.run(ctx, arg));
+
+    let elapsed = start.elapsed();
+
+    state
+        .metrics
+        .record_handler_run("command", &cmd.name, elapsed);
+
+    state.record_module_run(
+        &cmd.provider.name,
+        elapsed,
+        match result {
+            Ok(BotCmdResult::LibErr(_)) | Ok(BotCmdResult::BotErrMsg(_)) | Err(_) => true,
+            Ok(_) => false,
+        },
+    );
+
+    let result = match result {
+        Ok(r) => r,
+        Err(e) => {
+            handle_panic(state, metadata.dest.server_id, cmd, &e);
+            BotCmdResult::LibErr(e)
+        }
+    };
+
+    (result, elapsed)
+}
+
+/// Runs the handler of the given command as `invoke_handler` does, but on a thread of its own,
+/// giving up on it, and returning `None`, if it does not finish within the given time. A handler
+/// that has been given up on is left to finish, and its result is discarded.
+fn invoke_handler_with_timeout(
+    state: &Arc<State>,
+    cmd: &BotCommand,
+    metadata: &MsgMetadata,
+    arg: Yaml,
+    timeout: Duration,
+) -> Result<Option<(BotCmdResult, Duration)>> {
+    let (output_tx, output_rx) = mpsc::channel();
+
+    let state = state.clone();
+    let name = cmd.name.clone();
+    let server_id = metadata.dest.server_id;
+    let target = metadata.dest.target.to_owned();
+    let prefix = metadata.prefix.to_owning()?;
+    let tags = metadata.tags.clone();
+
+    thread::Builder::new()
+        .name(format!("command {:?}", name))
+        .spawn(move || {
+            let cmd = match state.read_commands().get(&name) {
+                Some(cmd) => cmd.clone(),
+                None => return,
+            };
+
+            let metadata = MsgMetadata {
+                dest: MsgDest {
+                    server_id,
+                    target: &target,
+                },
+                prefix: prefix.parse(),
+                tags: &tags,
+            };
+
+            let output = invoke_handler(&state, &cmd, &metadata, &arg);
+
+            if output_tx.send(output).is_err() {
+                info!(
+                    "The bot command {:?} finished after timing out; discarding its result.",
+                    name
+                );
+            }
+        })
+        .map_err(ErrorKind::ThreadSpawnFailure)?;
+
+    match output_rx.recv_timeout(timeout) {
+        Ok(output) => Ok(Some(output)),
+        Err(mpsc::RecvTimeoutError::Timeout) => {
+            warn!(
+                "The bot command {:?} did not finish within {:?}; giving up on it.",
+                cmd.name, timeout
+            );
+            Ok(None)
+        }
+        Err(mpsc::RecvTimeoutError::Disconnected) => {
+            Err(ErrorKind::UnknownCommand(cmd.name.clone().into_owned()).into())
+        }
+    }
+}
+
+impl BotCommand {
+    /// Returns whether the command has been disabled for having panicked too many times, per the
+    /// configuration setting `max command panics`, or along with its module, for the module's
+    /// having exceeded one of its `module limits`.
+    fn is_disabled(&self, state: &State) -> bool {
+        let panicked_out = match state.config().max_command_panics {
+            Some(max) => self.panic_count.load(Ordering::SeqCst) >= max as usize,
+            None => false,
+        };
+
+        panicked_out || state.module_disabled(&self.provider.name)
+    }
+}
+
+/// Records that the given command's handler function has panicked, with the given error, and
+/// notifies the bot's administrators.
+fn handle_panic(state: &State, server_id: ServerId, cmd: &BotCommand, err: &Error) {
+    let panic_count = cmd.panic_count.fetch_add(1, Ordering::SeqCst) + 1;
+
+    let disabled_note = if cmd.is_disabled(state) {
+        format!(
+            " Having panicked {} times, the command has been disabled until the bot is restarted.",
+            panic_count
+        )
+    } else {
+        String::new()
+    };
+
+    let notice = format!(
+        "{err} (command {cmd_name:?} from module {provider_name:?}){disabled_note}",
+        err = err,
+        cmd_name = cmd.name,
+        provider_name = cmd.provider.name,
+        disabled_note = disabled_note,
+    );
+
+    if let Err(e) = state.notify_admins(server_id, &notice) {
+        error!("Failed to notify administrators of a panic: {}", e)
+    }
+}
+
+/// Returns a reply to a message that used the given command, which doesn't exist, suggesting the
+/// commands with similar names that the user who sent the message may use, if there are any.
+pub(super) fn suggest_commands(
+    state: &State,
+    cmd_name: &str,
+    metadata: &MsgMetadata,
+) -> Result<Option<String>> {
+    let cmd_name_lower = cmd_name.to_lowercase();
+    let max_distance = if cmd_name.chars().count() <= 3 { 1 } else { 2 };
+    let is_admin = state.have_admin(metadata.dest.server_id, metadata.prefix)?;
+
+    let commands = state.read_commands();
+
+    let mut candidates = commands
+        .values()
+        .filter(|cmd| is_admin || cmd.auth_lvl == BotCmdAuthLvl::Public)
+        .map(|cmd| {
+            let distance = util::edit_distance(&cmd.name.to_lowercase(), &cmd_name_lower);
+            (distance, &cmd.name)
+        })
+        .filter(|&(distance, _)| distance <= max_distance)
+        .collect::<Vec<_>>();
+
+    if candidates.is_empty() {
+        return Ok(None);
+    }
+
+    candidates.sort();
+
+    let names = candidates
+        .iter()
+        .take(MAX_SUGGESTIONS)
+        .map(|&(_, name)| format!("`{}`", name))
+        .collect::<Vec<_>>();
+
+    let localize =
+        |key, params: &[(&str, &str)]| state.localize(metadata.dest, metadata.prefix, key, params);
+
+    let names = match names.split_last() {
+        Some((last, [])) => last.clone(),
+        Some((last, rest)) => localize(
+            "alternatives",
+            &[("others", &rest.join(", ")), ("last", last)],
+        ),
+        None => unreachable!(),
+    };
+
+    Ok(Some(localize(
+        "unknown command",
+        &[("command", cmd_name), ("suggestions", &names)],
+    )))
+}
+
+/// Splits the given arguments of a bot command into words, as a shell would. Words are separated
+/// by whitespace, which may be included in a word by quoting it: text between single quotes is
+/// taken literally, while, between double quotes, a backslash escapes a following `"` or `\`.
+/// Outside of quotes, a backslash escapes any following character.
+///
+/// # Examples
+///
+/// ```
+/// use irc_bot::split_cmd_args;
+///
+/// assert_eq!(
+///     split_cmd_args(r#"remind "next tuesday" buy\ milk 'at 5'"#).unwrap(),
+///     ["remind", "next tuesday", "buy milk", "at 5"]
+/// );
+/// ```
+pub fn split_cmd_args(args: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut rest = args;
+
+    while let Some((word, after)) = next_cmd_arg(rest)? {
+        words.push(word);
+        rest = after;
+    }
+
+    Ok(words)
+}
+
+/// Takes the first word, as split by [`split_cmd_args`], from the given arguments of a bot
+/// command, returning it along with the rest of the arguments, or `None` if there are no more
+/// words.
+///
+/// [`split_cmd_args`]: <fn.split_cmd_args.html>
+pub fn next_cmd_arg(args: &str) -> Result<Option<(String, &str)>> {
+    let args = args.trim_start();
+
+    if args.is_empty() {
+        return Ok(None);
+    }
+
+    let mut word = String::new();
+    let mut quote = None;
+    let mut chars = args.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            (None, c) if c.is_whitespace() => return Ok(Some((word, &args[i..]))),
+            (None, '\'') | (None, '"') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '\\') | (Some('"'), '\\') => match chars.next() {
+                Some((_, escaped)) if quote.is_none() || escaped == '"' || escaped == '\\' => {
+                    word.push(escaped)
+                }
+                Some((_, other)) => {
+                    word.push('\\');
+                    word.push(other);
+                }
+                None => bail!(ErrorKind::CmdArgSyntax(
+                    "a backslash with nothing after it to escape".into()
+                )),
+            },
+            (_, c) => word.push(c),
+        }
+    }
+
+    ensure!(
+        quote.is_none(),
+        ErrorKind::CmdArgSyntax("a quotation mark that is not closed".into())
+    );
+
+    Ok(Some((word, "")))
 }
 
 fn parse_arg<'s>(syntax: &'s Yaml, arg_str: &str) -> std::result::Result<Yaml, BotCmdResult> {
@@ -232,6 +632,8 @@ fn parse_arg<'s>(syntax: &'s Yaml, arg_str: &str) -> std::result::Result<Yaml, B
 #[cfg(test)]
 mod tests {
     use super::*;
+    use core::mk_module;
+    use testing;
     use util::yaml::mk_str as s;
 
     fn pa(syntax_str: &str, arg_str: &str) -> std::result::Result<Yaml, String> {
@@ -310,4 +712,83 @@ mod tests {
             Ok(map(&[(s("k"), map(&[(s("j"), Yaml::Integer(123))]))]))
         );
     }
+
+    #[test]
+    fn splitting_args() {
+        let split = |s| split_cmd_args(s).map_err(|e| e.to_string());
+
+        assert_eq!(split(""), Ok(vec![]));
+        assert_eq!(split("  a  b "), Ok(vec!["a".into(), "b".into()]));
+        assert_eq!(
+            split(r#"say "hello, \"world\"" 'it''s' a"b c"d"#),
+            Ok(vec![
+                "say".into(),
+                r#"hello, "world""#.into(),
+                "its".into(),
+                "ab cd".into(),
+            ])
+        );
+        assert_eq!(
+            split(r#"'C:\dir' "C:\dir" C:\\dir "" x"#),
+            Ok(vec![
+                r"C:\dir".into(),
+                r"C:\dir".into(),
+                r"C:\dir".into(),
+                "".into(),
+                "x".into(),
+            ])
+        );
+        assert!(split("\"unclosed").is_err());
+        assert!(split("trailing\\").is_err());
+
+        assert_eq!(
+            next_cmd_arg(" \"next tuesday\"  buy milk ").unwrap(),
+            Some(("next tuesday".into(), "  buy milk "))
+        );
+    }
+
+    #[test]
+    fn reply_routes() {
+        let routes: Vec<ReplyRoute> =
+            serde_yaml::from_str("[channel, notice, private, {private if over: 3}]").unwrap();
+
+        assert_eq!(
+            routes,
+            [
+                ReplyRoute::SameDest,
+                ReplyRoute::Notice,
+                ReplyRoute::Private,
+                ReplyRoute::PrivateIfOver(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn timeout() {
+        let script = testing::Script::new()
+            .register("testbot")
+            .send(":alice!alice@example.com PRIVMSG testbot :nap")
+            .expect(r#"^PRIVMSG alice :.*"nap" took too long"#);
+
+        let nap = || {
+            mk_module("nap")
+                .command(
+                    "nap",
+                    "",
+                    "Take a while to reply.",
+                    BotCmdAuthLvl::Public,
+                    Box::new(|_: HandlerContext, _: &Yaml| -> BotCmdResult {
+                        thread::sleep(Duration::from_secs(3));
+                        Reaction::Reply("Yawn.".into()).into()
+                    }),
+                    &[],
+                )
+                .end()
+        };
+
+        assert_eq!(
+            testing::run(script, "command timeout: 1\n", vec![nap]),
+            Ok(())
+        );
+    }
 }
@@ -0,0 +1,327 @@
+//! Support for bridges to chat services other than IRC, such as Matrix, Slack, or XMPP, through
+//! which the rooms of those services appear to bot modules as channels.
+//!
+//! A bridge is provided by a bot module (see [`ModuleBuilder::bridge`]). Each room of a bridge
+//! named `name` appears as the channel `#name:room`, which, containing a colon, cannot be the
+//! name of any IRC channel. Messages that the bridge receives from its service are handled on a
+//! thread of the bridge's own as though they had been sent to that channel on the first server
+//! in the bot's configuration, so that they may use bot commands and match triggers, and are
+//! kept among the channel's recent messages (see [`State::recent_messages`]). Messages that the
+//! bot sends to such a channel, whether in answer to those messages or otherwise, e.g., with
+//! [`State::send_privmsg`], are sent through the bridge instead of to the server. Input filters,
+//! which see messages only as they are received from IRC servers, do not see bridged messages.
+//!
+//! The sender of a bridged message is given a message prefix whose hostname ends in
+//! `.bridge.invalid`, which no IRC server gives its users. A sender so marked is never an
+//! administrator of the bot, whatever its nickname, lest a user of the bridged service take on
+//! the authorization of an IRC user of the same nickname.
+//!
+//! [`ModuleBuilder::bridge`]: <../struct.ModuleBuilder.html#method.bridge>
+//! [`State::recent_messages`]: <../struct.State.html#method.recent_messages>
+//! [`State::send_privmsg`]: <../struct.State.html#method.send_privmsg>
+
+use super::chan_log;
+use super::irc_comm;
+use super::irc_msgs::Ctcp;
+use super::irc_msgs::OwningMsgPrefix;
+use super::irc_send::push_to_outbox;
+use super::LibReaction;
+use super::MsgPrefix;
+use super::MsgTags;
+use super::Result;
+use super::ServerId;
+use super::State;
+use irc::client::prelude as aatxe;
+use irc::proto::Message;
+use std::panic::RefUnwindSafe;
+use std::panic::UnwindSafe;
+use std::sync::Arc;
+
+/// The suffix of the hostnames in the message prefixes given to the senders of bridged messages,
+/// under the reserved top-level domain `invalid`
+const BRIDGE_HOST_SUFFIX: &str = ".bridge.invalid";
+
+/// A bridge to a chat service other than IRC
+pub trait Bridge: Send + Sync + UnwindSafe + RefUnwindSafe + 'static {
+    /// Returns the name of the bridge, which names its rooms' channels, and so should be short
+    /// and must not contain colons or spaces.
+    fn name(&self) -> &str;
+
+    /// Blocks until a message arrives from the bridged service, returning it, or returns `None`
+    /// if the bridge has been closed, in which case it is not polled again.
+    fn recv(&self) -> Result<Option<BridgedMsg>>;
+
+    /// Sends the given text to the given room of the bridged service, as an action (as with
+    /// `/me` on IRC) if `is_action` is `true`.
+    fn send(&self, room: &str, text: &str, is_action: bool) -> Result<()>;
+
+    /// Returns the nickname by which to present to bot modules the user of the bridged service
+    /// with the given identity, e.g., a Matrix user ID.
+    ///
+    /// By default, this is the given identity with any leading `@` and anything from the first
+    /// colon on removed, and with characters not allowed in IRC nicknames replaced with
+    /// underscores, so that, e.g., `@alice:example.org` becomes `alice`.
+    fn nick(&self, sender: &str) -> String {
+        let nick = sender
+            .trim_start_matches('@')
+            .split(':')
+            .next()
+            .unwrap_or_default()
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || "-[]\\`^_{|}".contains(c) {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+
+        if nick.is_empty() {
+            "_".into()
+        } else {
+            nick
+        }
+    }
+}
+
+/// A message received by a [`Bridge`] from the service that it bridges
+///
+/// [`Bridge`]: <trait.Bridge.html>
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BridgedMsg {
+    /// The room of the bridged service in which the message was sent
+    pub room: String,
+
+    /// The identity of the message's sender on the bridged service, which the bridge maps to a
+    /// nickname (see [`Bridge::nick`])
+    ///
+    /// [`Bridge::nick`]: <trait.Bridge.html#method.nick>
+    pub sender: String,
+
+    pub text: String,
+
+    /// Whether the message is an action, as sent with `/me` on IRC
+    pub is_action: bool,
+}
+
+/// Returns the name of the channel as which the given room of the bridge with the given name
+/// appears to bot modules.
+pub fn bridged_channel_name(bridge: &str, room: &str) -> String {
+    format!("#{}:{}", bridge, room)
+}
+
+/// Returns the message prefix to be given to the sender, with the given nickname, of a message
+/// from the bridge with the given name.
+fn bridged_sender_prefix(bridge_name: &str, nick: &str) -> OwningMsgPrefix {
+    OwningMsgPrefix::from_string(format!(
+        "{}!{}@{}{}",
+        nick, nick, bridge_name, BRIDGE_HOST_SUFFIX
+    ))
+}
+
+/// Returns whether the given message prefix is that of the sender of a bridged message.
+pub(super) fn is_bridge_prefix(prefix: MsgPrefix) -> bool {
+    prefix
+        .host
+        .filter(|host| host.ends_with(BRIDGE_HOST_SUFFIX))
+        .is_some()
+}
+
+/// Splits the given channel name, if it is that of a bridged room, into the name of the bridge
+/// and that of the room.
+fn parse_bridged_channel_name(channel: &str) -> Option<(&str, &str)> {
+    if !channel.starts_with('#') {
+        return None;
+    }
+
+    let mut parts = channel[1..].splitn(2, ':');
+
+    match (parts.next(), parts.next()) {
+        (Some(bridge), Some(room)) if !bridge.is_empty() && !room.is_empty() => {
+            Some((bridge, room))
+        }
+        _ => None,
+    }
+}
+
+pub(super) fn bridge_main(state: Arc<State>, bridge: Arc<Bridge>) -> Result<()> {
+    let server_id = match state.servers.keys().next() {
+        Some(&id) => id,
+        None => return Ok(()),
+    };
+
+    while let Some(msg) = bridge.recv()? {
+        if let Err(e) = handle_bridged_msg(&state, server_id, &*bridge, msg) {
+            error!(
+                "Failed to handle a message from bridge {:?}: {}",
+                bridge.name(),
+                e
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_bridged_msg(
+    state: &Arc<State>,
+    server_id: ServerId,
+    bridge: &Bridge,
+    BridgedMsg {
+        room,
+        sender,
+        text,
+        is_action,
+    }: BridgedMsg,
+) -> Result<()> {
+    let nick = bridge.nick(&sender);
+    let prefix = bridged_sender_prefix(bridge.name(), &nick);
+    let target = bridged_channel_name(bridge.name(), &room);
+
+    let raw_text = if is_action {
+        Ctcp::action(&text).to_string()
+    } else {
+        text.clone()
+    };
+
+    chan_log::log_privmsg(state, server_id, &nick, &target, &raw_text, None)?;
+
+    let addressed = irc_comm::command_line(state, server_id, &target, &text)?.is_some();
+
+    if !addressed && !state.has_always_watching_triggers() {
+        return Ok(());
+    }
+
+    let reaction = irc_comm::handle_bot_command_or_trigger(
+        state,
+        server_id,
+        &state.outbox,
+        prefix,
+        &MsgTags::new(),
+        target,
+        text,
+        is_action,
+        addressed,
+    );
+
+    push_to_outbox(&state.outbox, server_id, reaction);
+
+    Ok(())
+}
+
+/// Sends through the appropriate bridge each `PRIVMSG` or `NOTICE` in the given reaction that is
+/// addressed to a bridged room, returning the rest of the reaction, which is to be sent to the IRC
+/// server, if any remains.
+pub(super) fn divert(
+    state: &State,
+    reaction: LibReaction<Message>,
+) -> Option<LibReaction<Message>> {
+    match reaction {
+        LibReaction::RawMsg(ref msg) if send_bridged(state, msg) => None,
+        LibReaction::RawMsg(msg) => Some(LibReaction::RawMsg(msg)),
+        LibReaction::Multi(reactions) => {
+            let reactions = reactions
+                .into_iter()
+                .filter_map(|reaction| divert(state, reaction))
+                .collect::<Vec<_>>();
+
+            if reactions.is_empty() {
+                None
+            } else {
+                Some(LibReaction::Multi(reactions))
+            }
+        }
+    }
+}
+
+/// Sends the given message through the appropriate bridge if it is a `PRIVMSG` or `NOTICE`
+/// addressed to a bridged room, returning whether it was.
+fn send_bridged(state: &State, msg: &Message) -> bool {
+    let (target, text) = match msg.command {
+        aatxe::Command::PRIVMSG(ref target, ref text)
+        | aatxe::Command::NOTICE(ref target, ref text) => (target, text),
+        _ => return false,
+    };
+
+    let (bridge, room) = match parse_bridged_channel_name(target)
+        .and_then(|(name, room)| state.bridge(name).map(|bridge| (bridge, room)))
+    {
+        Some(found) => found,
+        None => return false,
+    };
+
+    let (text, is_action) = match Ctcp::parse(text) {
+        Some(ref ctcp) if ctcp.is_action() => (ctcp.params, true),
+        _ => (&text[..], false),
+    };
+
+    if let Err(e) = bridge.send(room, text, is_action) {
+        warn!(
+            "Failed to send a message through bridge {:?}: {}",
+            bridge.name(),
+            e
+        );
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::offline::OfflineBot;
+    use core::Module;
+
+    struct NullBridge;
+
+    impl Bridge for NullBridge {
+        fn name(&self) -> &str {
+            "matrix"
+        }
+
+        fn recv(&self) -> Result<Option<BridgedMsg>> {
+            Ok(None)
+        }
+
+        fn send(&self, _: &str, _: &str, _: bool) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn bridged_channels() {
+        let channel = bridged_channel_name("matrix", "!room:example.org");
+
+        assert_eq!(channel, "#matrix:!room:example.org");
+        assert_eq!(
+            parse_bridged_channel_name(&channel),
+            Some(("matrix", "!room:example.org"))
+        );
+        assert_eq!(parse_bridged_channel_name("#rust"), None);
+        assert_eq!(parse_bridged_channel_name("#:room"), None);
+
+        assert_eq!(NullBridge.nick("@alice:example.org"), "alice");
+        assert_eq!(NullBridge.nick("Bob Smith"), "Bob_Smith");
+        assert_eq!(NullBridge.nick("@"), "_");
+    }
+
+    #[test]
+    fn bridged_sender_not_admin() {
+        let bot = OfflineBot::for_test(
+            "admins:\n  \
+             - nick: alice\n",
+            Vec::<fn() -> Module>::new(),
+        );
+        let state = bot.state();
+        let server_id = state.server_ids()[0];
+
+        let irc_alice = OwningMsgPrefix::from_string("alice!alice@example.com".to_owned());
+        assert!(state.have_admin(server_id, irc_alice.parse()).unwrap());
+
+        let bridged_alice = bridged_sender_prefix("matrix", &NullBridge.nick("@alice:evil.org"));
+        assert!(is_bridge_prefix(bridged_alice.parse()));
+        assert_eq!(bridged_alice.parse().nick, Some("alice"));
+        assert!(!state.have_admin(server_id, bridged_alice.parse()).unwrap());
+    }
+}
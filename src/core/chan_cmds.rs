@@ -0,0 +1,151 @@
+use super::irc_comm::channel_setting;
+use super::BotCommand;
+use super::ErrorKind;
+use super::Result;
+use super::ServerId;
+use super::State;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use util::irc::casemapped_str_cmp;
+use util::irc::CaseMapping;
+
+/// The prefix that distinguishes an entry of a list of commands that names a module, all of whose
+/// commands the entry covers, from one that names a single command
+const MODULE_PREFIX: &str = "module:";
+
+/// The commands and modules that the bot's administrators have enabled or disabled in particular
+/// channels of a server while the bot runs, overriding the channels' `enabled commands` and
+/// `disabled commands` settings
+#[derive(Debug, Default)]
+pub(super) struct CommandToggles {
+    channels: Vec<(String, BTreeMap<String, bool>)>,
+}
+
+impl CommandToggles {
+    fn get(&self, casemapping: CaseMapping, channel: &str) -> Option<&BTreeMap<String, bool>> {
+        self.channels
+            .iter()
+            .find(|(c, _)| casemapped_str_cmp(casemapping, &c[..], channel) == Ordering::Equal)
+            .map(|(_, toggles)| toggles)
+    }
+
+    fn set(&mut self, casemapping: CaseMapping, channel: &str, entry: String, enabled: bool) {
+        let i =
+            match self.channels.iter().position(|(c, _)| {
+                casemapped_str_cmp(casemapping, &c[..], channel) == Ordering::Equal
+            }) {
+                Some(i) => i,
+                None => {
+                    self.channels.push((channel.to_owned(), BTreeMap::new()));
+                    self.channels.len() - 1
+                }
+            };
+
+        self.channels[i].1.insert(entry, enabled);
+    }
+}
+
+impl State {
+    /// Enables or disables, in the given channel, the given command or, if the name is preceded by
+    /// `module:`, all commands of the given module, overriding the channel's configuration until
+    /// the bot is restarted.
+    pub fn set_command_enabled(
+        &self,
+        server_id: ServerId,
+        channel: &str,
+        entry: &str,
+        enabled: bool,
+    ) -> Result<()> {
+        let entry = if entry.starts_with(MODULE_PREFIX) {
+            let name = &entry[MODULE_PREFIX.len()..];
+
+            match self
+                .module_names()
+                .into_iter()
+                .find(|m| m.eq_ignore_ascii_case(name))
+            {
+                Some(m) => format!("{}{}", MODULE_PREFIX, m),
+                None => bail!(ErrorKind::UnknownModule(name.to_owned())),
+            }
+        } else {
+            match self.command(server_id, entry)? {
+                Some(cmd) => cmd.name.to_string(),
+                None => bail!(ErrorKind::UnknownCommand(entry.to_owned())),
+            }
+        };
+
+        let mut server = self.write_server(server_id)?;
+        let casemapping = server.capabilities.casemapping;
+
+        server
+            .command_toggles
+            .set(casemapping, channel, entry, enabled);
+
+        Ok(())
+    }
+}
+
+/// Returns whether the given command may be used in the given channel, or query, of the given
+/// server. An entry naming the command takes precedence over one naming its module, and an entry
+/// set at runtime, over the channel's configuration.
+pub(super) fn command_enabled(
+    state: &State,
+    server_id: ServerId,
+    target: &str,
+    cmd: &BotCommand,
+) -> Result<bool> {
+    let is_channel = state
+        .read_server(server_id)?
+        .capabilities
+        .is_channel_name(target);
+
+    if !is_channel {
+        return Ok(true);
+    }
+
+    let module_entry = format!("{}{}", MODULE_PREFIX, cmd.provider.name);
+
+    let toggled = {
+        let server = state.read_server(server_id)?;
+        let casemapping = server.capabilities.casemapping;
+
+        server
+            .command_toggles
+            .get(casemapping, target)
+            .map(|toggles| {
+                (
+                    toggles.get(cmd.name.as_ref()).cloned(),
+                    toggles.get(&module_entry).cloned(),
+                )
+            })
+            .unwrap_or_default()
+    };
+
+    let (enabled, disabled) = channel_setting(state, server_id, target, |chan| {
+        Some((
+            chan.enabled_commands.clone(),
+            chan.disabled_commands.clone(),
+        ))
+    })?
+    .unwrap_or_default();
+
+    let listed = |list: &Option<Vec<String>>, entry: &str| -> Option<bool> {
+        list.as_ref()
+            .map(|list| list.iter().any(|e| e.eq_ignore_ascii_case(entry)))
+    };
+
+    let configured = |entry: &str| -> Option<bool> {
+        match (listed(&enabled, entry), listed(&disabled, entry)) {
+            (_, Some(true)) => Some(false),
+            (Some(true), _) => Some(true),
+            _ => None,
+        }
+    };
+
+    Ok(toggled
+        .0
+        .or_else(|| configured(&cmd.name))
+        .or(toggled.1)
+        .or_else(|| configured(&module_entry))
+        .unwrap_or_else(|| enabled.is_none()))
+}
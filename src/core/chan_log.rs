@@ -0,0 +1,307 @@
+use super::config::ChannelLogFormat;
+use super::irc_msgs::format_server_time;
+use super::irc_msgs::Ctcp;
+use super::Result;
+use super::ServerId;
+use super::State;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+use std::time::SystemTime;
+use util::fmt::json_str;
+use util::irc::casemapped_str_cmp;
+use util::irc::CaseMapping;
+
+/// A message sent to a channel, as returned by [`State::recent_messages`] and [`State::history`]
+///
+/// [`State::recent_messages`]: <struct.State.html#method.recent_messages>
+/// [`State::history`]: <struct.State.html#method.history>
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct LoggedMsg {
+    /// The time at which the message was sent, per the server if it reported this, or else the
+    /// time at which the bot received the message
+    pub time: SystemTime,
+
+    pub nick: String,
+
+    pub text: String,
+
+    /// Whether the message was a CTCP `ACTION`, i.e., a `/me` message
+    pub is_action: bool,
+}
+
+/// The most recent messages sent to each channel on a server
+#[derive(Debug, Default)]
+pub(super) struct RecentMsgs {
+    channels: Vec<(String, VecDeque<LoggedMsg>)>,
+}
+
+impl RecentMsgs {
+    fn push(&mut self, casemapping: CaseMapping, channel: &str, msg: LoggedMsg, capacity: usize) {
+        let i =
+            match self.channels.iter().position(|(c, _)| {
+                casemapped_str_cmp(casemapping, &c[..], channel) == Ordering::Equal
+            }) {
+                Some(i) => i,
+                None => {
+                    self.channels.push((channel.to_owned(), VecDeque::new()));
+                    self.channels.len() - 1
+                }
+            };
+
+        let msgs = &mut self.channels[i].1;
+
+        while msgs.len() >= capacity {
+            msgs.pop_front();
+        }
+
+        msgs.push_back(msg);
+    }
+
+    fn get(&self, casemapping: CaseMapping, channel: &str) -> Option<&VecDeque<LoggedMsg>> {
+        self.channels
+            .iter()
+            .find(|(c, _)| casemapped_str_cmp(casemapping, &c[..], channel) == Ordering::Equal)
+            .map(|(_, msgs)| msgs)
+    }
+}
+
+impl State {
+    /// Returns up to the given number of the most recent messages sent to the given channel on the
+    /// given server, oldest first.
+    ///
+    /// The bot remembers as many messages per channel as the configuration field
+    /// `channel logs: buffer size` specifies. The bot's own messages are included only if the
+    /// server echoes them back to it, as with the IRCv3 capability `echo-message`.
+    pub fn recent_messages(
+        &self,
+        server_id: ServerId,
+        channel: &str,
+        n: usize,
+    ) -> Result<Vec<LoggedMsg>> {
+        let server = self.read_server(server_id)?;
+
+        Ok(server
+            .recent_msgs
+            .get(server.capabilities.casemapping, channel)
+            .map(|msgs| {
+                msgs.iter()
+                    .skip(msgs.len().saturating_sub(n))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Returns all the messages that the bot remembers having been sent to the given channel on
+    /// the given server, oldest first, as for [`recent_messages`].
+    ///
+    /// The messages are kept in memory whether or not the bot writes the channel's log files, so
+    /// that bot modules that need the context of a message, e.g., to correct or to quote the
+    /// messages before it, can rely on them.
+    ///
+    /// [`recent_messages`]: <#method.recent_messages>
+    pub fn history(&self, server_id: ServerId, channel: &str) -> Result<Vec<LoggedMsg>> {
+        let server = self.read_server(server_id)?;
+
+        Ok(server
+            .recent_msgs
+            .get(server.capabilities.casemapping, channel)
+            .map(|msgs| msgs.iter().cloned().collect())
+            .unwrap_or_default())
+    }
+}
+
+/// Records a `PRIVMSG` received from the given server, if it was sent to a channel, in the
+/// channel's buffer of recent messages and, if so configured, in the channel's log file.
+pub(super) fn log_privmsg(
+    state: &State,
+    server_id: ServerId,
+    nick: &str,
+    target: &str,
+    text: &str,
+    time: Option<SystemTime>,
+) -> Result<()> {
+    let (text, is_action) = match Ctcp::parse(text) {
+        Some(ref ctcp) if ctcp.is_action() => (ctcp.params, true),
+        Some(_) => return Ok(()),
+        None => (text, false),
+    };
+
+    let msg = LoggedMsg {
+        time: time.unwrap_or_else(SystemTime::now),
+        nick: nick.to_owned(),
+        text: text.to_owned(),
+        is_action,
+    };
+
+    let cfg = &state.config().channel_logs;
+
+    {
+        let mut server = state.write_server(server_id)?;
+
+        if !server.capabilities.is_channel_name(target) {
+            return Ok(());
+        }
+
+        if cfg.buffer_size != 0 {
+            let casemapping = server.capabilities.casemapping;
+            server
+                .recent_msgs
+                .push(casemapping, target, msg.clone(), cfg.buffer_size);
+        }
+    }
+
+    let dir = match cfg.directory {
+        Some(ref dir) => dir
+            .join(&state.get_server_config(server_id)?.name)
+            .join(channel_dir_name(target)),
+        None => return Ok(()),
+    };
+
+    let timestamp = format_server_time(msg.time);
+    let date = &timestamp[..10];
+
+    let (extension, line) = match cfg.format {
+        ChannelLogFormat::Text => ("log", format_text_line(&msg, &timestamp)),
+        ChannelLogFormat::Jsonl => ("jsonl", format_json_line(&msg, &timestamp)),
+    };
+
+    let path = dir.join(format!("{}.{}", date, extension));
+
+    if !path.exists() {
+        fs::create_dir_all(&dir)?;
+
+        if let Some(keep_days) = cfg.keep_days {
+            delete_old_logs(&dir, msg.time, keep_days)?;
+        }
+    }
+
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?
+        .write_all(line.as_bytes())?;
+
+    Ok(())
+}
+
+/// Returns the name of the directory in which to write the log files of the given channel, which
+/// is the channel's name, lowercased, with any characters that are not safe in file names
+/// replaced.
+fn channel_dir_name(channel: &str) -> String {
+    channel
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' => '_',
+            c if c.is_control() => '_',
+            c => c.to_ascii_lowercase(),
+        })
+        .collect()
+}
+
+/// Deletes the log files in the given directory that are dated more than the given number of
+/// days before the given time.
+fn delete_old_logs(dir: &Path, now: SystemTime, keep_days: u16) -> Result<()> {
+    let cutoff = now - Duration::from_secs(u64::from(keep_days) * 86_400);
+    let cutoff = &format_server_time(cutoff)[..10];
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+
+        let is_old = match path.file_stem().and_then(|s| s.to_str()) {
+            // Dates in this format sort chronologically as strings.
+            Some(date) if date.len() == 10 => date <= cutoff,
+            _ => false,
+        };
+
+        if is_old {
+            fs::remove_file(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn format_text_line(msg: &LoggedMsg, timestamp: &str) -> String {
+    let time = &timestamp[11..19];
+
+    if msg.is_action {
+        format!("[{}] * {} {}\n", time, msg.nick, msg.text)
+    } else {
+        format!("[{}] <{}> {}\n", time, msg.nick, msg.text)
+    }
+}
+
+fn format_json_line(msg: &LoggedMsg, timestamp: &str) -> String {
+    format!(
+        "{{\"time\":{},\"nick\":{},\"text\":{},\"action\":{}}}\n",
+        json_str(timestamp),
+        json_str(&msg.nick),
+        json_str(&msg.text),
+        msg.is_action
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(text: &str, is_action: bool) -> LoggedMsg {
+        LoggedMsg {
+            time: SystemTime::now(),
+            nick: "alice".into(),
+            text: text.into(),
+            is_action,
+        }
+    }
+
+    #[test]
+    fn log_lines() {
+        let timestamp = "2011-10-19T16:40:51.620Z";
+
+        assert_eq!(
+            format_text_line(&msg("hello", false), timestamp),
+            "[16:40:51] <alice> hello\n"
+        );
+        assert_eq!(
+            format_text_line(&msg("waves", true), timestamp),
+            "[16:40:51] * alice waves\n"
+        );
+        assert_eq!(
+            format_json_line(&msg("say \"hi\"\\\u{1}", false), timestamp),
+            "{\"time\":\"2011-10-19T16:40:51.620Z\",\"nick\":\"alice\",\
+             \"text\":\"say \\\"hi\\\"\\\\\\u0001\",\"action\":false}\n"
+        );
+    }
+
+    #[test]
+    fn recent_msgs_are_bounded() {
+        let cm = CaseMapping::Rfc1459;
+        let mut recent = RecentMsgs::default();
+
+        for text in &["one", "two", "three"] {
+            recent.push(cm, "#Chan", msg(text, false), 2);
+        }
+
+        let texts = recent
+            .get(cm, "#chan")
+            .unwrap()
+            .iter()
+            .map(|m| &m.text[..])
+            .collect::<Vec<_>>();
+
+        assert_eq!(texts, ["two", "three"]);
+        assert!(recent.get(cm, "#other").is_none());
+    }
+
+    #[test]
+    fn channel_dir_names() {
+        assert_eq!(channel_dir_name("#Rust"), "#rust");
+        assert_eq!(channel_dir_name("#a/b\\c"), "#a_b_c");
+    }
+}
@@ -0,0 +1,445 @@
+use super::bot_cmd;
+use super::BotCmdHandler;
+use super::BotCmdResult;
+use super::HandlerContext;
+use super::Result;
+use std::borrow::Cow;
+use std::panic::RefUnwindSafe;
+use std::panic::UnwindSafe;
+use std::result;
+use std::time::Duration;
+use url::Url;
+use util::yaml::FW_SYNTAX_CHECK_FAIL;
+use yaml_rust::Yaml;
+
+/// A type of which a value may be parsed from the arguments of a bot command defined with
+/// [`typed_cmd!`]
+///
+/// [`typed_cmd!`]: <macro.typed_cmd.html>
+pub trait FromCmdArg: Sized {
+    /// Returns a description of the values that are accepted, e.g., "an integer", for use in
+    /// replies to users who give other values.
+    fn expected() -> Cow<'static, str>;
+
+    /// Parses a value from the given text of an argument, returning `None` if it is invalid.
+    fn from_cmd_arg(arg: &str) -> Option<Self>;
+
+    /// Takes the text of an argument from the beginning of the given remaining arguments, returning
+    /// `None` if there is none. By default, this takes one word, which may be quoted, as with
+    /// [`next_cmd_arg`].
+    ///
+    /// [`next_cmd_arg`]: <fn.next_cmd_arg.html>
+    fn take_text(args: &mut &str) -> Result<Option<String>> {
+        Ok(bot_cmd::next_cmd_arg(args)?.map(|(word, rest)| {
+            *args = rest;
+            word
+        }))
+    }
+
+    /// Returns the value to use if no argument is given, or `None` if the argument is required.
+    fn absent() -> Option<Self> {
+        None
+    }
+
+    /// Returns how an argument with the given name is written in the command's syntax, e.g.,
+    /// `<count>`.
+    fn usage(name: &str) -> String {
+        format!("<{}>", name)
+    }
+}
+
+/// A nickname, as an argument of a bot command
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Nick(pub String);
+
+/// A channel name, as an argument of a bot command
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct Channel(pub String);
+
+/// The rest of a bot command's arguments, taken verbatim, quotation marks and all, which, as the
+/// last argument of a command, may contain whitespace, but must not be empty
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct RestOfLine(pub String);
+
+macro_rules! impl_from_cmd_arg_for_int {
+    ($($int:ty),*) => {
+        $(
+            impl FromCmdArg for $int {
+                fn expected() -> Cow<'static, str> {
+                    format!(
+                        "an integer from {} through {}",
+                        <$int>::min_value(),
+                        <$int>::max_value()
+                    )
+                    .into()
+                }
+
+                fn from_cmd_arg(arg: &str) -> Option<Self> {
+                    arg.parse().ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_from_cmd_arg_for_int!(u8, u16, u32, u64, usize, i8, i16, i32, i64, isize);
+
+impl FromCmdArg for String {
+    fn expected() -> Cow<'static, str> {
+        "a word".into()
+    }
+
+    fn from_cmd_arg(arg: &str) -> Option<Self> {
+        Some(arg.to_owned())
+    }
+}
+
+impl FromCmdArg for Duration {
+    fn expected() -> Cow<'static, str> {
+        "a duration, such as `90`, `90s`, or `1h30m`".into()
+    }
+
+    fn from_cmd_arg(arg: &str) -> Option<Self> {
+        parse_duration(arg)
+    }
+}
+
+impl FromCmdArg for Url {
+    fn expected() -> Cow<'static, str> {
+        "a URL".into()
+    }
+
+    fn from_cmd_arg(arg: &str) -> Option<Self> {
+        Url::parse(arg).ok()
+    }
+}
+
+impl FromCmdArg for Nick {
+    fn expected() -> Cow<'static, str> {
+        "a nickname".into()
+    }
+
+    fn from_cmd_arg(arg: &str) -> Option<Self> {
+        let is_special = |c| "[]\\`_^{|}".contains(c);
+
+        let mut chars = arg.chars();
+
+        match chars.next() {
+            Some(c) if c.is_ascii_alphabetic() || is_special(c) => {}
+            _ => return None,
+        }
+
+        if chars.all(|c| c.is_ascii_alphanumeric() || is_special(c) || c == '-') {
+            Some(Nick(arg.to_owned()))
+        } else {
+            None
+        }
+    }
+}
+
+impl FromCmdArg for Channel {
+    fn expected() -> Cow<'static, str> {
+        "a channel name".into()
+    }
+
+    fn from_cmd_arg(arg: &str) -> Option<Self> {
+        let mut chars = arg.chars();
+
+        match chars.next() {
+            Some('#') | Some('&') | Some('+') | Some('!') => {}
+            _ => return None,
+        }
+
+        if chars.all(|c| c != ',' && c != '\u{7}' && !c.is_whitespace()) {
+            Some(Channel(arg.to_owned()))
+        } else {
+            None
+        }
+    }
+}
+
+impl FromCmdArg for RestOfLine {
+    fn expected() -> Cow<'static, str> {
+        "some text".into()
+    }
+
+    fn from_cmd_arg(arg: &str) -> Option<Self> {
+        Some(RestOfLine(arg.to_owned()))
+    }
+
+    fn take_text(args: &mut &str) -> Result<Option<String>> {
+        let text = args.trim();
+        *args = "";
+
+        Ok(if text.is_empty() {
+            None
+        } else {
+            Some(text.to_owned())
+        })
+    }
+
+    fn usage(name: &str) -> String {
+        format!("<{}...>", name)
+    }
+}
+
+/// An optional argument, which is `None` if it is not given
+impl<T> FromCmdArg for Option<T>
+where
+    T: FromCmdArg,
+{
+    fn expected() -> Cow<'static, str> {
+        T::expected()
+    }
+
+    fn from_cmd_arg(arg: &str) -> Option<Self> {
+        T::from_cmd_arg(arg).map(Some)
+    }
+
+    fn take_text(args: &mut &str) -> Result<Option<String>> {
+        T::take_text(args)
+    }
+
+    fn absent() -> Option<Self> {
+        Some(None)
+    }
+
+    fn usage(name: &str) -> String {
+        let usage = T::usage(name);
+
+        format!("[{}]", usage.trim_start_matches('<').trim_end_matches('>'))
+    }
+}
+
+/// A bot command's syntax and handler function, as produced by [`typed_cmd!`] and registered
+/// with [`ModuleBuilder::typed_command`]
+///
+/// [`typed_cmd!`]: <macro.typed_cmd.html>
+/// [`ModuleBuilder::typed_command`]: <struct.ModuleBuilder.html#method.typed_command>
+pub struct TypedCommand {
+    pub(super) usage: String,
+
+    pub(super) handler: Box<BotCmdHandler>,
+}
+
+impl TypedCommand {
+    /// Makes a command with arguments that are written as given, and a handler function that is
+    /// given the command's arguments as they were sent. This is used by [`typed_cmd!`].
+    ///
+    /// [`typed_cmd!`]: <macro.typed_cmd.html>
+    pub fn new<F, R>(params: Vec<String>, handler: F) -> Self
+    where
+        F: Fn(HandlerContext, &str) -> R + Send + Sync + UnwindSafe + RefUnwindSafe + 'static,
+        R: Into<BotCmdResult>,
+    {
+        TypedCommand {
+            usage: params.join(" "),
+            handler: Box::new(TypedHandler(handler)),
+        }
+    }
+}
+
+struct TypedHandler<F>(F);
+
+impl<F, R> BotCmdHandler for TypedHandler<F>
+where
+    F: Fn(HandlerContext, &str) -> R + Send + Sync + UnwindSafe + RefUnwindSafe,
+    R: Into<BotCmdResult>,
+{
+    fn run(&self, ctx: HandlerContext, arg: &Yaml) -> BotCmdResult {
+        (self.0)(ctx, arg.as_str().expect(FW_SYNTAX_CHECK_FAIL)).into()
+    }
+}
+
+/// Takes the argument with the given name from the beginning of the given remaining arguments of
+/// a bot command, returning the reply to send if it is missing or invalid. This is used by
+/// [`typed_cmd!`].
+///
+/// [`typed_cmd!`]: <macro.typed_cmd.html>
+pub fn take_cmd_arg<T>(args: &mut &str, name: &'static str) -> result::Result<T, BotCmdResult>
+where
+    T: FromCmdArg,
+{
+    let text = match T::take_text(args) {
+        Ok(Some(text)) => text,
+        Ok(None) => return T::absent().ok_or_else(|| BotCmdResult::ArgMissing(name.into())),
+        Err(e) => return Err(BotCmdResult::UserErrMsg(e.to_string().into())),
+    };
+
+    T::from_cmd_arg(&text).ok_or_else(|| {
+        BotCmdResult::UserErrMsg(
+            format!(
+                "The argument {:?} should be {}, but {:?} is not.",
+                name,
+                T::expected(),
+                text
+            )
+            .into(),
+        )
+    })
+}
+
+/// Checks that no arguments remain of those given to a bot command, after its arguments have been
+/// taken with `take_cmd_arg`. This is used by [`typed_cmd!`].
+///
+/// [`typed_cmd!`]: <macro.typed_cmd.html>
+pub fn finish_cmd_args(args: &str) -> result::Result<(), BotCmdResult> {
+    if args.trim().is_empty() {
+        Ok(())
+    } else {
+        Err(BotCmdResult::SyntaxErr)
+    }
+}
+
+/// Parses a duration given as a number of seconds, or as a sequence of numbers each followed by
+/// one of the units `w`, `d`, `h`, `m`, and `s`.
+fn parse_duration(s: &str) -> Option<Duration> {
+    if let Ok(secs) = s.parse() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let mut total: u64 = 0;
+    let mut n: Option<u64> = None;
+
+    for c in s.chars() {
+        if let Some(digit) = c.to_digit(10) {
+            n = Some(n.unwrap_or(0).checked_mul(10)?.checked_add(digit.into())?);
+            continue;
+        }
+
+        let unit = match c {
+            'w' => 7 * 24 * 60 * 60,
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+
+        total = total.checked_add(n.take()?.checked_mul(unit)?)?;
+    }
+
+    if n.is_some() || s.is_empty() {
+        return None;
+    }
+
+    Some(Duration::from_secs(total))
+}
+
+/// Defines a bot command's handler function in terms of the types of its arguments, which are
+/// parsed from the words of the command's arguments, in order, with [`FromCmdArg`]. This produces
+/// a [`TypedCommand`], whose syntax, for use with the command `help`, is derived from the names
+/// given to the arguments.
+///
+/// The handler function is written as a closure, the first parameter of which is the
+/// [`HandlerContext`]. If an argument is missing, or cannot be parsed, or more arguments are
+/// given than the command takes, the framework replies to the user who used the command with an
+/// error message, and the handler function is not called.
+///
+/// [`FromCmdArg`]: <trait.FromCmdArg.html>
+/// [`TypedCommand`]: <struct.TypedCommand.html>
+/// [`HandlerContext`]: <struct.HandlerContext.html>
+///
+/// # Examples
+///
+/// ```
+/// #[macro_use]
+/// extern crate irc_bot;
+///
+/// use irc_bot::BotCmdAuthLvl;
+/// use irc_bot::Channel;
+/// use irc_bot::Reaction;
+/// use irc_bot::RestOfLine;
+///
+/// # fn main() {
+/// let repeat = typed_cmd!(|_ctx, target: Channel, count: u8, text: RestOfLine| {
+///     Reaction::Msgs(
+///         (0..count)
+///             .map(|_| format!("{}: {}", target.0, text.0).into())
+///             .collect::<Vec<_>>()
+///             .into(),
+///     )
+/// });
+///
+/// let module = irc_bot::mk_module("repeat")
+///     .typed_command("repeat", "Repeat some text.", BotCmdAuthLvl::Admin, repeat, &[])
+///     .end();
+/// # }
+/// ```
+#[macro_export]
+macro_rules! typed_cmd {
+    (|$ctx:pat $(, $name:ident : $ty:ty)*| $body:expr) => {
+        $crate::TypedCommand::new(
+            vec![$(<$ty as $crate::FromCmdArg>::usage(stringify!($name))),*],
+            |$ctx: $crate::HandlerContext, args: &str| -> $crate::BotCmdResult {
+                #[allow(unused_mut)]
+                let mut args = args;
+
+                $(
+                    let $name: $ty = match $crate::take_cmd_arg(&mut args, stringify!($name)) {
+                        Ok(arg) => arg,
+                        Err(reply) => return reply,
+                    };
+                )*
+
+                if let Err(reply) = $crate::finish_cmd_args(args) {
+                    return reply;
+                }
+
+                $body.into()
+            },
+        )
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn take<T>(args: &mut &str) -> result::Result<T, String>
+    where
+        T: FromCmdArg,
+    {
+        take_cmd_arg(args, "arg").map_err(|reply| format!("{:?}", reply))
+    }
+
+    #[test]
+    fn taking_args() {
+        let mut args = "#chan  '3' some  'text' ";
+        assert_eq!(take::<Channel>(&mut args), Ok(Channel("#chan".into())));
+        assert_eq!(take::<u32>(&mut args), Ok(3));
+        assert_eq!(
+            take::<Option<Nick>>(&mut args),
+            Ok(Some(Nick("some".into())))
+        );
+        assert_eq!(
+            take::<RestOfLine>(&mut args),
+            Ok(RestOfLine("'text'".into()))
+        );
+        assert_eq!(take::<Option<u8>>(&mut args), Ok(None));
+        assert!(take::<RestOfLine>(&mut args).is_err());
+
+        let mut args = "300 nick";
+        assert!(take::<u8>(&mut args).is_err());
+        assert!(take::<Channel>(&mut args).is_err());
+        assert!(finish_cmd_args(" ").is_ok());
+        assert!(finish_cmd_args("x").is_err());
+
+        assert!(Nick::from_cmd_arg("[away]-2").is_some());
+        assert!(Nick::from_cmd_arg("2nick").is_none());
+        assert!(Url::from_cmd_arg("https://example.com/").is_some());
+        assert_eq!(<Option<RestOfLine>>::usage("text"), "[text...]");
+    }
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("90"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("90s"), Some(Duration::from_secs(90)));
+        assert_eq!(parse_duration("1h30m"), Some(Duration::from_secs(5400)));
+        assert_eq!(parse_duration("1w2d"), Some(Duration::from_secs(777_600)));
+        assert_eq!(parse_duration(""), None);
+        assert_eq!(parse_duration("1h30"), None);
+        assert_eq!(parse_duration("h"), None);
+        assert_eq!(parse_duration("5y"), None);
+    }
+}
@@ -0,0 +1,251 @@
+//! Statistics of the use of bot commands, which help the bot's operators to tell which commands,
+//! and so which modules, are used, and which are slow or failing.
+//!
+//! The statistics are kept in memory and, if the configuration gives a `command stats file`,
+//! saved there every few minutes and when the bot exits, and loaded from it when the bot starts.
+
+use super::ErrorKind;
+use super::Result;
+use super::State;
+use serde_yaml;
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::MutexGuard;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How many of a command's most recent run times to keep, from which to compute percentiles
+const MAX_LATENCY_SAMPLES: usize = 256;
+
+/// How often to save the statistics, if they have changed
+const SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How often to check whether the statistics are due to be saved, or the bot is shutting down
+const TICK: Duration = Duration::from_secs(1);
+
+/// Statistics of the use of a bot command
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct CommandUsage {
+    /// How many times the command has been used
+    pub invocations: u64,
+
+    /// How many of the command's uses have not succeeded, whether because of the user's error,
+    /// such as incorrect syntax or a lack of authorization, or the bot's
+    pub errors: u64,
+
+    /// How many of the command's uses have been given up on because the command's handler took
+    /// longer than the configuration field `command timeout` allows, which are included in
+    /// `errors`
+    #[serde(default)]
+    pub timeouts: u64,
+
+    /// The run times, in milliseconds, of the command's handler on its most recent runs, oldest
+    /// first
+    #[serde(default)]
+    latencies: VecDeque<u64>,
+}
+
+/// The outcome of a use of a bot command
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(super) enum CommandOutcome {
+    Succeeded,
+    Failed,
+
+    /// The command's handler did not finish in the time allowed.
+    TimedOut,
+}
+
+impl CommandUsage {
+    fn record(&mut self, run_time: Option<Duration>, outcome: CommandOutcome) {
+        self.invocations += 1;
+
+        match outcome {
+            CommandOutcome::Succeeded => {}
+            CommandOutcome::Failed => self.errors += 1,
+            CommandOutcome::TimedOut => {
+                self.errors += 1;
+                self.timeouts += 1;
+            }
+        }
+
+        if let Some(t) = run_time {
+            if self.latencies.len() >= MAX_LATENCY_SAMPLES {
+                self.latencies.pop_front();
+            }
+
+            self.latencies
+                .push_back(t.as_secs() * 1000 + u64::from(t.subsec_millis()));
+        }
+    }
+
+    /// Returns the given percentile, from 0 to 100, of the run times of the command's handler on
+    /// its most recent runs, or `None` if the handler has not been run.
+    pub fn latency_percentile(&self, percentile: u8) -> Option<Duration> {
+        let mut latencies = self.latencies.iter().cloned().collect::<Vec<_>>();
+        latencies.sort();
+
+        // The nearest-rank method
+        let rank = (f64::from(percentile.min(100)) / 100.0 * latencies.len() as f64).ceil();
+        let i = (rank as usize).saturating_sub(1);
+
+        latencies.get(i).map(|&ms| Duration::from_millis(ms))
+    }
+}
+
+/// The statistics of the use of all bot commands, by the commands' names
+#[derive(Debug, Default)]
+pub(super) struct CommandStats {
+    commands: BTreeMap<String, CommandUsage>,
+
+    /// The file in which the statistics are stored, if one is configured
+    path: Option<PathBuf>,
+
+    /// Whether the statistics have changed since they were last saved
+    dirty: bool,
+}
+
+impl CommandStats {
+    /// Loads the statistics stored in the given file, if one is given and it exists.
+    pub(super) fn load(path: Option<PathBuf>) -> Result<Self> {
+        let commands = match path {
+            Some(ref path) => match fs::File::open(path) {
+                Ok(file) => serde_yaml::from_reader(file)?,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+                Err(e) => return Err(e.into()),
+            },
+            None => BTreeMap::new(),
+        };
+
+        Ok(CommandStats {
+            commands,
+            path,
+            dirty: false,
+        })
+    }
+
+    fn save(&mut self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) if self.dirty => path,
+            _ => return Ok(()),
+        };
+
+        fs::write(path, serde_yaml::to_string(&self.commands)?)?;
+        self.dirty = false;
+
+        Ok(())
+    }
+}
+
+impl State {
+    fn lock_command_stats(&self) -> Result<MutexGuard<CommandStats>> {
+        self.command_stats
+            .lock()
+            .map_err(|_| ErrorKind::LockPoisoned("the command statistics".into()).into())
+    }
+
+    /// Returns the statistics of the use of the bot's commands, by the commands' names, including
+    /// those of commands that have been used but are no longer loaded.
+    pub fn command_usage(&self) -> Result<BTreeMap<String, CommandUsage>> {
+        Ok(self.lock_command_stats()?.commands.clone())
+    }
+
+    /// Records a use of the bot command with the given name, whose handler took the given time to
+    /// run, if it was run, with the given outcome.
+    pub(super) fn record_command_use(
+        &self,
+        name: &str,
+        run_time: Option<Duration>,
+        outcome: CommandOutcome,
+    ) {
+        match self.lock_command_stats() {
+            Ok(mut stats) => {
+                stats
+                    .commands
+                    .entry(name.to_owned())
+                    .or_default()
+                    .record(run_time, outcome);
+                stats.dirty = true;
+            }
+            Err(e) => error!("Failed to record use of command {:?}: {}", name, e),
+        }
+    }
+
+    /// Saves the statistics of the use of the bot's commands to the configured `command stats
+    /// file`, if there is one and they have changed since they were last saved.
+    pub(super) fn save_command_stats(&self) {
+        if let Err(e) = self.lock_command_stats().and_then(|mut stats| stats.save()) {
+            error!("Failed to save command statistics: {}", e);
+        }
+    }
+}
+
+/// Saves the statistics of the use of the bot's commands periodically until the bot shuts down.
+pub(super) fn cmd_stats_main(state: Arc<State>) -> Result<()> {
+    let mut last_save = Instant::now();
+
+    while !state.is_shutting_down() {
+        thread::sleep(TICK);
+
+        if last_save.elapsed() >= SAVE_INTERVAL {
+            state.save_command_stats();
+            last_save = Instant::now();
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn usage() {
+        let mut usage = CommandUsage::default();
+        assert_eq!(usage.latency_percentile(50), None);
+
+        for ms in (1..=100).rev() {
+            usage.record(
+                Some(Duration::from_millis(ms)),
+                if ms % 10 == 0 {
+                    CommandOutcome::Failed
+                } else {
+                    CommandOutcome::Succeeded
+                },
+            );
+        }
+
+        usage.record(None, CommandOutcome::TimedOut);
+
+        assert_eq!(usage.invocations, 101);
+        assert_eq!(usage.errors, 11);
+        assert_eq!(usage.timeouts, 1);
+        assert_eq!(usage.latency_percentile(0), Some(Duration::from_millis(1)));
+        assert_eq!(
+            usage.latency_percentile(50),
+            Some(Duration::from_millis(50))
+        );
+        assert_eq!(
+            usage.latency_percentile(99),
+            Some(Duration::from_millis(99))
+        );
+        assert_eq!(
+            usage.latency_percentile(100),
+            Some(Duration::from_millis(100))
+        );
+
+        for _ in 0..MAX_LATENCY_SAMPLES {
+            usage.record(Some(Duration::from_millis(500)), CommandOutcome::Succeeded);
+        }
+
+        assert_eq!(
+            usage.latency_percentile(0),
+            Some(Duration::from_millis(500))
+        );
+    }
+}
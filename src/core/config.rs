@@ -1,29 +1,45 @@
 use super::aatxe;
 use super::pkg_info;
 use super::ErrorKind;
+use super::ReplyRoute;
 use super::Result;
 use super::ServerConfigIndex;
+use super::Severity;
+use regex;
 use serde_yaml;
+use serde_yaml::Value;
 use smallvec::SmallVec;
+use std::collections::BTreeMap;
 use std::convert::TryInto;
+use std::env;
+use std::fs;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::net::IpAddr;
+use std::net::SocketAddr;
 use std::path::Path;
+use std::path::PathBuf;
+use std::result::Result as StdResult;
 use std::sync::Arc;
 use std::time::Duration;
+use toml;
+use url_serde::SerdeUrl;
 use util::irc::ChannelName;
 use util::lock::RoLock;
 use util::regex::config as rx_cfg;
 use util::regex::Regex;
 
 mod inner {
+    use super::ReplyRoute;
     use smallvec::SmallVec;
+    use std::collections::BTreeMap;
+    use std::path::PathBuf;
 
     /// Configuration structure that can be deserialized by Serde.
     ///
     /// This is hidden from the consumer because Serde won't validate the configuration.
-    #[derive(Debug, Default, Deserialize)]
+    #[derive(Debug, Deserialize)]
     pub(super) struct Config {
         pub(super) nickname: String,
 
@@ -36,11 +52,168 @@ mod inner {
         #[serde(default, rename = "join delay")]
         pub(super) join_delay: u16,
 
+        #[serde(default, rename = "max command panics")]
+        pub(super) max_command_panics: Option<u32>,
+
+        #[serde(default, rename = "command timeout")]
+        pub(super) command_timeout: Option<u32>,
+
+        #[serde(default, rename = "command timeouts")]
+        pub(super) command_timeouts: BTreeMap<String, u32>,
+
+        #[serde(default, rename = "rejoin on kick")]
+        pub(super) rejoin_on_kick: bool,
+
+        #[serde(default = "super::default_rejoin_delay", rename = "rejoin delay")]
+        pub(super) rejoin_delay: u16,
+
+        #[serde(default, rename = "join on invite")]
+        pub(super) join_on_invite: super::InvitePolicy,
+
+        #[serde(default, rename = "command prefixes")]
+        pub(super) command_prefixes: Vec<String>,
+
+        #[serde(default = "super::mk_true", rename = "address by nick")]
+        pub(super) address_by_nick: bool,
+
+        #[serde(default = "super::mk_true", rename = "bare commands in private")]
+        pub(super) bare_commands_in_private: bool,
+
+        #[serde(default = "super::mk_true", rename = "strip formatting")]
+        pub(super) strip_formatting: bool,
+
+        #[serde(default = "super::mk_true", rename = "suggest commands")]
+        pub(super) suggest_commands: bool,
+
+        #[serde(default = "super::default_locale")]
+        pub(super) locale: String,
+
+        #[serde(default)]
+        pub(super) messages: BTreeMap<String, BTreeMap<String, String>>,
+
+        #[serde(default, rename = "command replies")]
+        pub(super) command_replies: BTreeMap<String, ReplyRoute>,
+
+        #[serde(default, rename = "page length")]
+        pub(super) page_length: Option<usize>,
+
+        #[serde(default)]
+        pub(super) paste: Option<super::Paste>,
+
+        #[serde(default)]
+        pub(super) aliases: BTreeMap<String, String>,
+
+        #[serde(default, rename = "aliases file")]
+        pub(super) aliases_file: Option<PathBuf>,
+
+        #[serde(default, rename = "preferences file")]
+        pub(super) prefs_file: Option<PathBuf>,
+
+        #[serde(default, rename = "command stats file")]
+        pub(super) command_stats_file: Option<PathBuf>,
+
+        #[serde(default, rename = "session recording file")]
+        pub(super) session_recording_file: Option<PathBuf>,
+
+        #[serde(default, rename = "CTCP version")]
+        pub(super) ctcp_version: String,
+
+        #[serde(default, rename = "STS policy file")]
+        pub(super) sts_policy_file: Option<PathBuf>,
+
+        #[serde(default, rename = "control socket")]
+        pub(super) control_socket: Option<PathBuf>,
+
+        #[serde(default = "super::default_console")]
+        pub(super) console: bool,
+
+        #[serde(default, rename = "DCC")]
+        pub(super) dcc: super::Dcc,
+
+        #[serde(default)]
+        pub(super) outbox: super::Outbox,
+
+        #[serde(default, rename = "channel logs")]
+        pub(super) channel_logs: super::ChannelLogs,
+
+        #[serde(default, rename = "audit log")]
+        pub(super) audit_log: super::AuditLog,
+
+        #[serde(default, rename = "HTTP")]
+        pub(super) http: super::Http,
+
+        #[serde(default, rename = "module settings")]
+        pub(super) module_settings: BTreeMap<String, super::Value>,
+
+        #[serde(default, rename = "module limits")]
+        pub(super) module_limits: BTreeMap<String, super::ModuleLimits>,
+
+        #[serde(default, rename = "error notifications")]
+        pub(super) error_notifications: Option<super::ErrorNotifications>,
+
         // TODO: admins should be per-server.
         #[serde(default)]
         pub(super) admins: SmallVec<[super::Admin; 8]>,
 
-        pub(super) servers: SmallVec<[super::Server; 8]>,
+        #[serde(default, rename = "admin auth")]
+        pub(super) admin_auth: super::AdminAuth,
+
+        #[serde(default, rename = "shadow bans")]
+        pub(super) shadow_bans: super::ShadowBans,
+
+        #[serde(default, rename = "flood protection")]
+        pub(super) flood_protection: Option<super::FloodProtection>,
+
+        pub(super) servers: Vec<super::Server>,
+    }
+
+    impl Default for Config {
+        fn default() -> Self {
+            Config {
+                nickname: Default::default(),
+                username: Default::default(),
+                realname: Default::default(),
+                join_delay: Default::default(),
+                max_command_panics: Default::default(),
+                command_timeout: Default::default(),
+                command_timeouts: Default::default(),
+                rejoin_on_kick: Default::default(),
+                rejoin_delay: super::default_rejoin_delay(),
+                join_on_invite: Default::default(),
+                command_prefixes: Default::default(),
+                address_by_nick: true,
+                bare_commands_in_private: true,
+                strip_formatting: true,
+                suggest_commands: true,
+                locale: super::default_locale(),
+                messages: Default::default(),
+                command_replies: Default::default(),
+                page_length: Default::default(),
+                paste: Default::default(),
+                aliases: Default::default(),
+                aliases_file: Default::default(),
+                prefs_file: Default::default(),
+                command_stats_file: Default::default(),
+                session_recording_file: Default::default(),
+                ctcp_version: Default::default(),
+                sts_policy_file: Default::default(),
+                control_socket: Default::default(),
+                console: super::default_console(),
+                dcc: Default::default(),
+                outbox: Default::default(),
+                channel_logs: Default::default(),
+                audit_log: Default::default(),
+                http: Default::default(),
+                module_settings: Default::default(),
+                module_limits: Default::default(),
+                error_notifications: Default::default(),
+                admins: Default::default(),
+                admin_auth: Default::default(),
+                shadow_bans: Default::default(),
+                flood_protection: Default::default(),
+                servers: Default::default(),
+            }
+        }
     }
 }
 
@@ -54,9 +227,37 @@ mod inner {
 /// To configure the bot using a [YAML] configuration file, create such a file and then use
 /// [`Config::try_from_path`] to read and parse it into a [`Config`] structure.
 ///
+/// The configuration file may instead be written in [TOML] or in JSON, in which case its name
+/// should end in `.toml` or `.json`, respectively, or its format should be specified by means of
+/// [`Config::try_from_path_with_format`]. The fields are the same in every format; in TOML, keys
+/// that contain spaces must be quoted, as in `"join delay" = 5`, and a list of mappings such as
+/// `servers` may be written as an array of tables, e.g., `[[servers]]`. If the file is malformed,
+/// the resulting error specifies the line and column at which, and the field in which, the
+/// problem was found.
+///
+/// So that secrets need not be written in the configuration file itself, the value of each of the
+/// fields `nick password`, `server password`, and `TLS client certificate password` of a server,
+/// `token` of a webhook, and `secret` of `admin auth` may contain references of the form `${NAME}`
+/// to environment variables, which are replaced with the variables' values (`$$` standing for a
+/// literal `$`). Alternatively, any of these fields may be omitted in favor of a field of the same
+/// key followed by ` file`, such as `nick password file`, whose value should be the path of a file
+/// containing the secret, e.g., `"/run/secrets/irc_pass"`; a single trailing line break in the file
+/// is ignored.
+///
 /// The text of the configuration file should constitute a YAML mapping with the key-value pairs
 /// (hereinafter termed _fields_) that follow, listed by their keys:
 ///
+/// - `include` — The value of this field, if specified, should be a sequence of strings, each
+/// specifying the path, relative to the directory of the file in which it is given, of a file
+/// whose fields are to be merged into the configuration, such as `"servers.d/*.toml"`. In the
+/// last component of such a path, `*` matches any sequence of characters and `?` any single
+/// character, and matching files are included in the order of their names. The included files
+/// may be written in any of the supported formats, as indicated by their names' extensions, and
+/// may themselves include other files. Mappings are merged key by key and sequences, such as
+/// `servers`, are concatenated; where both specify some other value for the same key, that of a
+/// file included later takes precedence over that of a file included earlier, and that of the
+/// including file takes precedence over both. This field is optional.
+///
 /// - `nickname` — The value of this field should be a string, which is to be used as the bot's
 /// default IRC nickname.
 ///
@@ -75,15 +276,473 @@ mod inner {
 /// field is optional; its value defaults to zero seconds. TODO: This should be overridable
 /// per-server, or even per-channel.
 ///
+/// - `max command panics` — The value of this field, if specified, should be a positive integer,
+/// which is to be used as the number of times that a bot command's handler function may panic
+/// before the command is disabled, until the bot is restarted. Whenever a handler function
+/// panics, the bot's administrators (those with a `nick` specified) are notified by private
+/// message. This field is optional; if it is not specified, commands are never disabled.
+///
+/// - `command timeout` — The value of this field, if specified, should be a positive integer,
+/// which is to be used as the number of seconds for which the bot should wait for a bot command's
+/// handler function to finish before giving up on it, replying to the user who used the command
+/// that it timed out, and counting the timeout in the command's statistics (see `command stats
+/// file`). A handler function that times out is left to finish in the background, and whatever it
+/// returns is discarded. This field is optional; if it is not specified, the bot waits for
+/// handler functions indefinitely.
+///
+/// - `command timeouts` — The value of this field, if specified, should be a mapping from names
+/// of bot commands to positive integers, each of which overrides `command timeout` for the command
+/// that it is given for. This field is optional; its value defaults to an empty mapping.
+///
+/// - `rejoin on kick` — The value of this field, if specified, should be `true` or `false`,
+/// specifying whether the bot should attempt to rejoin a channel from which it has been kicked.
+/// This field is optional; its value defaults to `false`. It may be overridden per-channel with
+/// the per-channel setting of the same name.
+///
+/// - `rejoin delay` — The value of this field, if specified, should be a non-negative integer,
+/// which is to be used as a number of seconds to wait, after being kicked from a channel, before
+/// attempting to rejoin it, if the bot is to rejoin it per `rejoin on kick`. This field is
+/// optional; its value defaults to 5 seconds.
+///
+/// - `join on invite` — The value of this field, if specified, should be one of the strings
+/// `nobody`, `admins`, and `anyone`, specifying from whom the bot should accept invitations to
+/// channels, which it accepts by joining the channel: respectively, from no one; from the bot's
+/// administrators only; or from any user. This field is optional; its value defaults to `admins`.
+/// It may be overridden per-channel with the per-channel setting of the same name.
+///
+/// - `command prefixes` — The value of this field, if specified, should be a sequence of
+/// non-empty strings without whitespace, such as `["!", "."]`, each of which is to be accepted at
+/// the start of a message, immediately followed by the name of a command, as addressing the
+/// command to the bot, as in `!help`. This field is optional; its value defaults to an empty
+/// sequence. It may be overridden per-channel with the per-channel setting of the same name.
+///
+/// - `address by nick` — The value of this field, if specified, should be `true` or `false`,
+/// specifying whether messages in channels that begin with the bot's nickname followed by `:` or
+/// `,`, as in `bot: help`, are to be taken as addressed to the bot. If this is `false`, commands
+/// may be given in channels only with the `command prefixes`. This field is optional; its value
+/// defaults to `true`.
+///
+/// - `bare commands in private` — The value of this field, if specified, should be `true` or
+/// `false`, specifying whether private messages to the bot, as well as lines sent in DCC chat
+/// sessions and on the console, are to be taken as addressed to the bot even if they begin with
+/// neither the bot's nickname nor any of the `command prefixes`. This field is optional; its
+/// value defaults to `true`.
+///
+/// - `strip formatting` — The value of this field, if specified, should be `true` or `false`,
+/// specifying whether IRC formatting codes, such as those for colors and bold text, are to be
+/// removed from messages sent to the bot before they are parsed as commands or matched against
+/// triggers. This field is optional; its value defaults to `true`.
+///
+/// - `suggest commands` — The value of this field, if specified, should be `true` or `false`,
+/// specifying whether the bot is to reply to a message addressed to it that uses a command that
+/// doesn't exist, and that no trigger or module answers, by suggesting commands with similar
+/// names, if there are any. This field is optional; its value defaults to `true`.
+///
+/// - `locale` — The value of this field, if specified, should be a string naming the locale in
+/// which the bot is to send the replies that the framework itself generates, such as those to
+/// commands used without sufficient authority and those of the command `help`: either `en`, whose
+/// messages are built in, or one of the locales given in `messages`. This field is optional; its
+/// value defaults to `en`. It may be overridden per-channel with the per-channel setting of the
+/// same name, and users may choose locales of their own with the preference `locale` (see
+/// `preferences file`).
+///
+/// - `messages` — The value of this field, if specified, should be a mapping from names of
+/// locales, such as `de`, to mappings from the keys of messages to the templates with which they
+/// are to be rendered in those locales, e.g., `{de: {"syntax error": "Syntaxfehler."}}`. The keys
+/// and the built-in messages are listed in [`BUILT_IN_MESSAGES`]. In each template, each
+/// placeholder of the form `{name}`, such as `{command}`, is replaced with the value of the
+/// message's parameter of that name, and `{{` and `}}` stand for literal braces. Where a locale
+/// lacks a template for a message, that of `en` is used, which may also be given here to override
+/// the built-in one. Bot modules may define messages of their own, whose keys they document. This
+/// field is optional.
+///
+/// - `command replies` — The value of this field, if specified, should be a mapping from names
+/// of bot commands to the ways in which the commands' output is to be delivered, overriding the
+/// ways that the commands' modules specify. Each of these should be one of the strings `channel`,
+/// meaning in the channel in which the command was used (or privately, if it was used
+/// privately); `notice`, meaning to the user who used the command by `NOTICE`; and `private`,
+/// meaning to that user by private message; or a mapping of the form `{private if over: N}`,
+/// meaning in the channel unless the output would take more than `N` messages, and otherwise
+/// privately. This field is optional.
+///
+/// - `page length` — The value of this field, if specified, should be a positive integer,
+/// specifying the greatest number of messages that the bot is to send in reply to any one command.
+/// The bot sends only that many of the messages of longer output, followed by a note of how many
+/// remain, and holds the rest for the user who used the command, who may retrieve them a page at a
+/// time with the command `more` of the `default` module. This field is optional; if it is not
+/// specified, the bot sends all of any command's output at once.
+///
+/// - `paste` — The value of this field, if specified, should be a mapping, which enables the
+/// uploading of oversized command output to a paste service: when a command's output would take
+/// more messages than `lines` allows, the bot uploads its text and replies with a link to it
+/// instead, or, should the upload fail, sends the output as usual. This is done before any
+/// pagination per `page length`. This field is optional; if it is not specified, output is never
+/// uploaded. The fields of this mapping follow, listed by their keys:
+///
+///   - `lines` — The value of this field, if specified, should be a positive integer, which is to
+///   be used as the greatest number of messages of output that the bot is to send rather than
+///   upload. This field is optional; its value defaults to 10.
+///
+///   - `service` — The value of this field, if specified, should be a string naming the paste
+///   service to use: `bot`, meaning that the bot is to serve the pastes itself, keeping the most
+///   recent 64 in memory, at the path `/paste/<id>` of its HTTP listener, which must therefore be
+///   enabled with the field `HTTP: address`; `HTTP form`, meaning that the text is to be uploaded,
+///   as a form, to the `URL` given; or the name of a paste service provided by a bot module. This
+///   field is optional; its value defaults to `bot`.
+///
+///   - `URL` — The value of this field, which is required if the `service` is `HTTP form`, should
+///   be a string specifying the URL to which the bot is to send a `POST` request with the text as
+///   a form of type `application/x-www-form-urlencoded`. The link to the paste is taken from the
+///   response's `Location` header field if the service redirects, or else from the first line of
+///   its body.
+///
+///   - `field` — The value of this field, if specified, should be a string specifying the name of
+///   the form field in which the text is to be sent to an `HTTP form` service. This field is
+///   optional; its value defaults to `content`.
+///
+///   - `public URL` — The value of this field, which is required if the `service` is `bot`, should
+///   be a string specifying the URL at which users may reach the bot's HTTP listener, such as
+///   `https://bot.example.com`, to which `/paste/<id>` is appended to make each link.
+///
+/// - `aliases` — The value of this field, if specified, should be a mapping from names of command
+/// aliases, which must not contain whitespace, to the command lines for which they stand, e.g.,
+/// `{ping: latency, g: "google %args%"}`. When a message addressed to the bot begins with an
+/// alias's name, the name is replaced with the alias's command line before the command to run is
+/// looked up; any occurrences of `%args%` in the command line are replaced with the rest of the
+/// message, and, if there are none, the rest of the message is appended to the command line. An
+/// alias with the name of a command is ignored. Aliases may also be defined while the bot runs,
+/// with the command `alias` of the `admin` module, and these take precedence over those given
+/// here. This field is optional.
+///
+/// - `aliases file` — The value of this field, if specified, should be a string specifying the
+/// path of a file in which the bot should store the aliases defined while it runs. This field is
+/// optional; if it is not specified, the bot forgets those aliases when it exits.
+///
+/// - `preferences file` — The value of this field, if specified, should be a string specifying
+/// the path of a file in which the bot should store the preferences that users set for
+/// themselves with the command `set` of the `default` module, and show with the command `get`.
+/// A user's preferences are kept for the services account as which the user is logged in, if the
+/// bot knows it, or else for the username and hostname with which the user is connected. The
+/// preferences are `timezone`, an offset from UTC, such as `UTC+02:00`, in which bot modules may
+/// show times to the user; `locale`, overriding the field `locale` described above for replies to
+/// the user; `reply-style`, either `notice` or `private`, specifying how the bot is to reply to
+/// the user's commands that it would otherwise answer in the channel; and `logging`, either `on`
+/// or `off`, of which the latter specifies that the user's messages are to be neither written to
+/// channel log files nor kept among channels' recent messages. This field is optional; if it is
+/// not specified, the bot forgets users' preferences when it exits.
+///
+/// - `command stats file` — The value of this field, if specified, should be a string specifying
+/// the path of a file in which the bot should store the statistics that it keeps of the use of
+/// its commands: how many times each has been used, how many of those uses failed, and how long
+/// its recent uses took. The bot saves the statistics every five minutes and when it shuts down.
+/// Administrators may see them with the command `stats commands` of the `default` module, and
+/// they are included in the bot's metrics (see `HTTP: metrics`). This field is optional; if it
+/// is not specified, the bot forgets the statistics when it exits.
+///
+/// - `session recording file` — The value of this field, if specified, should be a string
+/// specifying the path of a file to which the bot should append every line that it receives from
+/// a server, and every line that it sends to one after registering its connection, with the time
+/// and the name of the server, so that a session in which the bot misbehaved may be replayed
+/// offline with the function [`replay`]. The file records everything said to the bot and by it,
+/// including any passwords, and so should be kept private. This field is optional; if it is not
+/// specified, the bot records nothing.
+///
+/// - `CTCP version` — The value of this field, if specified, should be a string, which is to be
+/// sent in reply to [CTCP] `VERSION` requests. This field is optional; its value defaults to
+/// information about the bot's software.
+///
+/// - `STS policy file` — The value of this field, if specified, should be a string specifying the
+/// path of a file in which the bot should cache the [strict transport security (STS)][STS]
+/// policies that servers advertise, which direct the bot to connect to those servers only with
+/// TLS, on the ports that they specify, until the policies expire. This field is optional; if it
+/// is not specified, the bot still honors STS policies, but forgets them when it exits.
+///
+/// - `control socket` — The value of this field, if specified, should be a string specifying the
+/// path at which the bot should create a Unix domain socket through which its operators may
+/// control it, e.g., with `socat - UNIX-CONNECT:PATH`. The bot accepts commands on the socket, one
+/// per line, such as `say #channel Hello` and `join #channel`; the command `help` lists them.
+/// Only the bot's own user may connect to the socket. A socket already at the path, as left by an
+/// earlier run of the bot, is replaced, but if anything else is at the path, the bot leaves it be
+/// and creates no control socket. This field is optional; if it is not specified, or if the bot
+/// is not running on a Unix-like system, the bot creates no control socket.
+///
+/// - `console` — The value of this field, if specified, should be `true` or `false`, specifying
+/// whether the bot, if its standard input is a terminal, should accept commands there. Lines that
+/// begin with `/` are read as the commands accepted on the `control socket`, such as
+/// `/join #channel`; other lines are handled as though an administrator of the bot had sent them
+/// to the bot by private message, and the bot's replies are written to its standard output. This
+/// field is optional; its value defaults to `true`.
+///
+/// - `admin auth` — The value of this field, if specified, should be a mapping, which configures
+/// the command `auth` of the `default` module, with which a user may become an administrator of
+/// the bot for a limited time by proving knowledge of a shared secret, regardless of the user's
+/// nickname, hostmask, or services account, as is useful on networks without services. A user
+/// who sends the bot `auth` by private message receives a one-time challenge, valid for five
+/// minutes, and answers it with `auth RESPONSE`, where `RESPONSE` is the HMAC-SHA-256 of the
+/// challenge keyed with the secret, in hexadecimal, such as the output of
+/// `printf %s CHALLENGE | openssl dgst -sha256 -hmac SECRET`. This field is optional. The fields
+/// of this mapping follow, listed by their keys:
+///
+///   - `secret` — The value of this field, if specified, should be a non-empty string, which is
+///   to be used as the shared secret. It may be given by file or with references to environment
+///   variables as described above. This field is optional; if it is not specified, the command
+///   `auth` always fails.
+///
+///   - `session length` — The value of this field, if specified, should be a positive integer,
+///   which is to be used as the number of seconds for which a user who answers a challenge
+///   correctly is to remain an administrator, unless the user changes nickname, quits, or is lost
+///   in a netsplit sooner. This field is optional; its value defaults to 3600 seconds.
+///
+/// - `shadow bans` — The value of this field, if specified, should be a mapping, which configures
+/// the quiet suppression of the bot's replies to users who abuse it. The messages of a
+/// shadow-banned user are handled as usual, but the bot's replies to them are not sent to the
+/// user. The bot's administrators may also shadow-ban users, and lift shadow bans, while the bot
+/// runs, with the commands `shadowban`, `unshadowban`, and `shadowbans` of the `admin` module,
+/// overriding this field until the bot is restarted. This field is optional. The fields of this
+/// mapping follow, listed by their keys:
+///
+///   - `masks` — The value of this field, if specified, should be a sequence of strings, each a
+///   mask matching the message prefixes of users who are to be shadow-banned on every server, such
+///   as `"*!*@example.com"` or `troll`, in which `*` matches any sequence of characters and `?`
+///   any single character. A mask without `@` is taken as a nickname, and one with `@` but
+///   without `!` as a username and hostname. This field is optional; its value defaults to an
+///   empty sequence.
+///
+///   - `review channel` — The value of this field, if specified, should be a string specifying the
+///   name of a channel to which the bot should send, on the same server, the replies that it
+///   withholds from shadow-banned users, each marked with the user's nickname and the channel in
+///   which it would have been sent. This field is optional; if it is not specified, such replies
+///   are discarded.
+///
+/// - `flood protection` — The value of this field, if specified, should be a mapping, which
+/// enables the detection of users who flood the channels that the bot is in, by sending too many
+/// messages, or too many identical messages, in too short a time. When a user is found doing so,
+/// the bot ignores the user's commands for a cooldown period, takes the configured `action`, and
+/// notifies bot modules with a [`UserEvent::Flood`], so that they may act on it themselves. The
+/// bot's administrators are exempt. This field is optional; if it is not specified, floods are
+/// not detected. The fields of this mapping follow, listed by their keys:
+///
+///   - `messages` — The value of this field, if specified, should be a positive integer, which is
+///   to be used as the greatest number of messages that a user may send to a channel within the
+///   `period`. This field is optional; its value defaults to 5.
+///
+///   - `repeats` — The value of this field, if specified, should be a positive integer, which is
+///   to be used as the greatest number of identical messages, ignoring case and surrounding
+///   whitespace, that a user may send to a channel within the `period`. This field is optional;
+///   its value defaults to 3.
+///
+///   - `period` — The value of this field, if specified, should be a positive integer, which is to
+///   be used as the number of seconds over which users' messages are counted. This field is
+///   optional; its value defaults to 10 seconds.
+///
+///   - `cooldown` — The value of this field, if specified, should be a positive integer, which is
+///   to be used as the number of seconds for which the bot is to ignore the commands of a user
+///   found flooding. This field is optional; its value defaults to 60 seconds.
+///
+///   - `action` — The value of this field, if specified, should be one of the strings `none`,
+///   `warn`, `quiet`, `kick`, and `ban`, specifying what the bot should do about a user found
+///   flooding a channel: respectively, nothing more; to warn the user in the channel; to quiet,
+///   kick, or ban the user's hostname (`*!*@host`) in the channel, which requires that the bot
+///   hold at least half-operator status there. This field is optional; its value defaults to
+///   `none`.
+///
+/// - `DCC` — The value of this field, if specified, should be a mapping, which configures the
+/// bot's support for the [Direct Client-to-Client (DCC)][DCC] protocol, through which
+/// administrators of the bot may use it by way of direct connections rather than the IRC server.
+/// This field is optional. The fields of this mapping follow, listed by their keys:
+///
+///   - `address` — The value of this field, if specified, should be a string specifying the IP
+///   address at which the bot should listen for, and tell users to make, incoming DCC
+///   connections. This field is optional, but the bot cannot offer DCC sessions without it.
+///
+///   - `timeout` — The value of this field, if specified, should be a non-negative integer, which
+///   is to be used as a number of seconds to wait for a DCC connection to be established before
+///   giving up. This field is optional; its value defaults to 120 seconds.
+///
+///   - `passive` — The value of this field, if specified, should be `true` or `false`, specifying
+///   whether the bot should offer files by _passive_ (or _reverse_) DCC, in which the recipient
+///   listens for a connection from the bot rather than the reverse, which is useful if the bot
+///   cannot accept incoming connections. This field is optional; its value defaults to `false`,
+///   unless `address` is not set, in which case passive DCC is always used. Passive DCC is also
+///   used for any recipient whose host has no known IP address, as the bot accepts incoming DCC
+///   connections only from the recipient's host.
+///
+///   - `send directories` — The value of this field, if specified, should be a sequence of
+///   strings, which specify the paths of directories from within which bot modules may offer
+///   files to users by DCC SEND. This field is optional; its value defaults to an empty sequence,
+///   in which case no files may be offered.
+///
+///   - `max send size` — The value of this field, if specified, should be a non-negative integer,
+///   which is to be used as the maximum size, in bytes, of a file that the bot may offer by DCC
+///   SEND. This field is optional; its value defaults to 16 MiB (16777216 bytes).
+///
+/// - `outbox` — The value of this field, if specified, should be a mapping, which configures the
+/// queue in which messages wait to be sent to servers. This field is optional. The fields of this
+/// mapping follow, listed by their keys:
+///
+///   - `capacity` — The value of this field, if specified, should be a positive integer, which is
+///   to be used as the maximum number of messages that may wait in the queue at once. This field
+///   is optional; its value defaults to 1024.
+///
+///   - `overflow policy` — The value of this field, if specified, should be one of the strings
+///   `drop newest`, `drop oldest`, and `disconnect`, specifying what the bot should do with a
+///   message that it tries to queue while the queue is full: respectively, to discard the new
+///   message; to discard the oldest message in the queue to make room for the new message; or to
+///   discard the new message and disconnect from the server to which it was to be sent. This
+///   field is optional; its value defaults to `drop newest`.
+///
+/// - `channel logs` — The value of this field, if specified, should be a mapping, which configures
+/// the bot's logging of the messages sent to the channels that it is in. The bot keeps the most
+/// recent messages of each channel in memory, for modules to query, and, if so configured, also
+/// writes all messages to log files, one for each channel per day (in UTC), at paths of the form
+/// `DIR/SERVER/CHANNEL/YYYY-MM-DD.log`, where `SERVER` is the `name` of the server. This field is
+/// optional. The fields of this mapping follow, listed by their keys:
+///
+///   - `directory` — The value of this field, if specified, should be a string specifying the path
+///   of the directory `DIR` in which the bot should write channel log files. This field is
+///   optional; if it is not specified, the bot does not write log files.
+///
+///   - `format` — The value of this field, if specified, should be one of the strings `text` and
+///   `JSONL`, specifying in which format the bot should write log files: respectively, as lines of
+///   plain text in the conventional style of IRC clients' logs, or as lines each of which is a
+///   JSON object with the fields `time`, `nick`, `text`, and `action`. Files in the latter format
+///   are named with the extension `.jsonl` rather than `.log`. This field is optional; its value
+///   defaults to `text`.
+///
+///   - `keep days` — The value of this field, if specified, should be a positive integer, which is
+///   to be used as the number of days' log files to keep for each channel; older files are
+///   deleted when the bot begins a new file. This field is optional; if it is not specified, log
+///   files are never deleted.
+///
+///   - `buffer size` — The value of this field, if specified, should be a non-negative integer,
+///   which is to be used as the number of each channel's most recent messages to keep in memory,
+///   for bot modules' use, whether or not log files are written. This field is optional; its
+///   value defaults to 100.
+///
+/// - `audit log` — The value of this field, if specified, should be a mapping, which configures
+/// the recording of each use of a bot command at the authorization level `Admin`, whether or not
+/// the user who used it was authorized to, with the time, the server, the channel or user to which
+/// the command was sent, the user who sent it, the command's full arguments, and the outcome. This
+/// field is optional. The fields of this mapping follow, listed by their keys:
+///
+///   - `file` — The value of this field, if specified, should be a string specifying the path of
+///   a file to which the bot should append a line for each such use of a command. This field is
+///   optional.
+///
+///   - `channel` — The value of this field, if specified, should be a string specifying the name
+///   of a channel to which the bot should send a message recording each such use of a command on
+///   the same server. This field is optional.
+///
+/// - `HTTP` — The value of this field, if specified, should be a mapping, which configures an HTTP
+/// server that the bot may run for the benefit of its operators. This field is optional. The
+/// fields of this mapping follow, listed by their keys:
+///
+///   - `address` — The value of this field, if specified, should be a string specifying the IP
+///   address and TCP port at which the bot should listen for HTTP requests, such as
+///   `127.0.0.1:9100`. This field is optional; if it is not specified, the bot does not run an
+///   HTTP server. The HTTP server always serves, at the path `/healthz`, a plain-text response
+///   with the status code 200 if the bot is registered with each of its servers and none of its
+///   connections has stalled, or with the status code 503 otherwise; and, at the path `/status`,
+///   a JSON report of the bot's uptime and, for each server, the state of the bot's connection,
+///   its nickname, and the channels that it has joined.
+///
+///   - `metrics` — The value of this field, if specified, should be `true` or `false`, specifying
+///   whether the bot should serve, at the path `/metrics`, metrics about its operation in the
+///   text format of the [Prometheus] monitoring system. This field is optional; its value
+///   defaults to `false`.
+///
+///   - `webhooks` — The value of this field, if specified, should be a sequence of mappings, each
+///   of which configures an endpoint to which other services may send `POST` requests with JSON
+///   bodies, such as the webhooks of code-hosting and continuous-integration services, for the
+///   bot to announce in IRC channels. This field is optional; its value defaults to an empty
+///   sequence. The fields of these mappings follow, listed by their keys:
+///
+///     - `path` — The value of this field should be a string beginning with `/`, which is to be
+///     used as the path of the endpoint.
+///
+///     - `token` — The value of this field should be a non-empty string, which requests to the
+///     endpoint must present, either in an `Authorization` header field of the form
+///     `Bearer TOKEN` or as the value of the query parameter `token`. Requests that do not
+///     present it are rejected.
+///
+///     - `template` — The value of this field should be a string, which is to be used as the
+///     template of the announcement. In it, each placeholder of the form `{a.b.c}` is replaced
+///     with the first line of the value found in the request's body by following the given keys
+///     of JSON objects or indices of JSON arrays, or with nothing if there is no such value;
+///     `{{` and `}}` stand for literal braces. Each line of the result is sent as a separate
+///     message.
+///
+///     - `server` — The value of this field, if specified, should be the `name` of one of the
+///     servers listed in `servers`, on which the announcements are to be made. This field is
+///     optional; if it is not specified, the announcements are made on every server.
+///
+///     - `channels` — The value of this field should be a non-empty sequence of strings, which
+///     are the channels to which the announcements are to be sent.
+///
+/// - `module settings` — The value of this field, if specified, should be a mapping from names of
+/// bot modules to the settings of those modules, each in the form that the module's documentation
+/// describes. Modules read their settings as they need them, so changes to this field take effect
+/// when the configuration is reloaded. This field is optional.
+///
+/// - `module limits` — The value of this field, if specified, should be a mapping from names of
+/// bot modules to mappings, which limit the resources that those modules may use while the bot
+/// runs (see also the admin command `modules stats`). A module that exceeds any of its limits is
+/// disabled, so that its commands, triggers, and other handlers are no longer run, other than its
+/// unload handlers, until the bot is restarted, and the bot's administrators (those with a `nick`
+/// specified) are notified by private message. This field is optional; if it is not specified,
+/// modules are not limited. The fields of these mappings follow, listed by their keys:
+///
+///   - `run time` — The value of this field, if specified, should be a positive integer, which is
+///   to be used as the number of seconds for which the module's handlers may run in all. This
+///   field is optional.
+///
+///   - `errors` — The value of this field, if specified, should be a positive integer, which is to
+///   be used as the number of times that the module's handlers may fail, whether by returning an
+///   error or by panicking. This field is optional.
+///
+///   - `storage` — The value of this field, if specified, should be a positive integer, which is
+///   to be used as the number of bytes that the module's data files may take up in the bot's
+///   module data directory. The module's data files are taken to be the file or directory, in that
+///   directory, that is named after the module, with or without an extension, as with the file
+///   `karma.yaml` of the module `karma`. This field is optional.
+///
+/// - `error notifications` — The value of this field, if specified, should be a mapping, which
+/// has the bot forward the errors that it encounters, such as the failures of its modules'
+/// handlers, by private message to its administrators (those with a `nick` specified), or to a
+/// channel, so that its operators learn of them without reading its logs. Each error is sent on
+/// the server in connection with which it occurred or, if it did not occur in connection with a
+/// particular server, on every server. This field is optional; if it is not specified, errors
+/// are only passed to the bot's error handler. The fields of this mapping follow, listed by their
+/// keys:
+///
+///   - `severity` — The value of this field, if specified, should be one of the strings `minor`,
+///   `major`, and `critical`, specifying the least serious errors that are to be forwarded (see
+///   [`Severity`]). This field is optional; its value defaults to `major`.
+///
+///   - `channel` — The value of this field, if specified, should be a string specifying the name
+///   of a channel to which the errors are to be sent instead of the administrators. The bot
+///   should be in the channel. This field is optional.
+///
+///   - `interval` — The value of this field, if specified, should be a non-negative integer,
+///   which is to be used as the least number of seconds that are to pass between the forwarding
+///   of one error and that of the next; errors that occur sooner are only logged. This field is
+///   optional; its value defaults to 10 seconds.
+///
+///   - `repeat interval` — The value of this field, if specified, should be a non-negative
+///   integer, which is to be used as the number of seconds within which an error that is
+///   described exactly as one already forwarded is not to be forwarded again. This field is
+///   optional; its value defaults to 600 seconds.
+///
 /// - `servers` — The value of this field should be a sequence of mappings, which specify IRC
-/// servers to which the bot should attempt to connect. The fields of these mappings are termed
-/// _per-server settings_ and are documented below.
+/// servers to which the bot should attempt to connect, all at once. The fields of these mappings
+/// are termed _per-server settings_ and are documented below.
 ///
 ///   The available per-server settings for each server follow, listed by their keys:
 ///
 ///   - `name` — The value of this field should be a string that does not include a US-ASCII
 ///   character considered a Common Separator in Unicode (namely `,`, `.`, `/`, or `:`). This field
-///   specifies a name to be used to identify the server.
+///   specifies a name to be used to identify the server, which must differ from those of the
+///   other servers.
 ///
 ///     A concept that depends on this field is the **_channel identifier_**. For each server, each
 ///     IRC channel thereon that is known to the bot is assigned a channel identifier, which is a
@@ -103,6 +762,13 @@ mod inner {
 ///   - `port` — The value of this field should be a non-negative integer specifying the number of
 ///   the TCP port at which the server serves IRC, such as `6697`.
 ///
+///   - `nickname`, `username`, and `realname` — The values of these fields, if specified, should
+///   be strings, which are to be used on this server instead of the values of the fields of the
+///   same keys described above. These fields are optional; where one is not specified, the value
+///   of the corresponding field described above is used, except that the `username` for a server
+///   that specifies a `nickname`, where no `username` is specified at all, defaults to that
+///   `nickname`.
+///
 ///   - `nick password` — The value of this field, if specified, should be a string specifying a
 ///   password to be used to verify that the bot is authorized to use the nickname that has been
 ///   specified, e.g., a NickServ password. This field is optional.
@@ -111,10 +777,62 @@ mod inner {
 ///   password to be used to verify that the bot is authorized to connect to the server, i.e., a
 ///   password to be sent with the IRC protocol command `PASS` at the start of the IRC session.
 ///
+///   - `oper` — The value of this field, if specified, should be a mapping with the fields `name`
+///   and `password`, whose values should be strings, which are to be sent with the IRC protocol
+///   command `OPER` upon registering with the server, for the bot to become an IRC operator. The
+///   `password` may be given by file or with references to environment variables as described
+///   above. This field is optional.
+///
+///   - `WEBIRC` — The value of this field, if specified, should be a mapping configuring the bot
+///   to identify itself to the server as a trusted gateway with the IRC protocol command
+///   `WEBIRC`, which is sent before any other command upon connecting, and with which the server
+///   is asked to regard the bot as connecting from the given address rather than the bot's own.
+///   The server must be configured to trust the gateway. This field is optional. The fields of
+///   this mapping follow, listed by their keys:
+///
+///     - `password` — The value of this field should be a string specifying the password that the
+///     server requires of the gateway. It may be given by file or with references to environment
+///     variables as described above.
+///
+///     - `gateway` — The value of this field should be a string naming the gateway, such as
+///     `"my-gateway"`.
+///
+///     - `IP` — The value of this field should be an IPv4 or IPv6 address, as a string, such as
+///     `"192.0.2.1"`, which is to be presented as the address from which the bot connects.
+///
+///     - `host` — The value of this field, if specified, should be a string specifying the
+///     hostname to be presented as that from which the bot connects. This field is optional; its
+///     value defaults to the given `IP`.
+///
 ///   - `TLS` — The value of this field, if specified, should be `true` or `false`, specifying
 ///   whether the bot should attempt to connect to the server using Transport Layer Security (TLS).
 ///   This field is optional; its value defaults to `true`.
 ///
+///   - `ping interval` — The value of this field, if specified, should be a positive integer,
+///   which is to be used as a number of seconds to wait between sending `PING`s to the server to
+///   check that the connection is alive and to measure its lag. This field is optional; its value
+///   defaults to 180 seconds.
+///
+///   - `ping timeout` — The value of this field, if specified, should be a positive integer, which
+///   is to be used as a number of seconds to wait for the server to answer a `PING` before
+///   considering the connection dead and closing it. This field is optional; its value defaults
+///   to 10 seconds.
+///
+///   - `TLS CA certificate` — The value of this field, if specified, should be a string specifying
+///   the path of a file containing a certificate, in DER format, of a certificate authority (CA)
+///   that is to be trusted, in addition to the system's trusted CAs, to vouch for the server's
+///   TLS certificate. This is useful, e.g., for test networks whose servers use self-signed
+///   certificates. This field is optional.
+///
+///   - `TLS client certificate` — The value of this field, if specified, should be a string
+///   specifying the path of a file containing a certificate and private key, in PKCS #12 format,
+///   with which the bot is to identify itself to the server, e.g., for NickServ's `CERTFP`
+///   authentication. This field is optional.
+///
+///   - `TLS client certificate password` — The value of this field, if specified, should be a
+///   string specifying the password with which the file specified with `TLS client certificate`
+///   is encrypted. This field is optional; its value defaults to the empty string.
+///
 ///   - `await registration mode` — The value of this field, if specified, should be a single
 ///   ASCII character, which is to be taken as a user mode expected to be set by the server to mark
 ///   the bot as identified to a user account. Setting this field means that the bot should wait
@@ -206,29 +924,178 @@ mod inner {
 ///     setting with the key `can see`. All channels whose identifiers match this regular
 ///     expression will be able to see the channel `C`.
 ///
+///     - `rejoin on kick` — The value of this per-channel setting, if specified, should be `true`
+///     or `false`, overriding, for the channel `C`, the value of the field `rejoin on kick`
+///     described above. This field is optional.
+///
+///     - `join on invite` — The value of this per-channel setting, if specified, should be one of
+///     the strings that the field `join on invite` described above accepts, overriding that
+///     field's value for invitations to the channel `C`. This field is optional.
+///
+///     - `command prefixes` — The value of this per-channel setting, if specified, should be a
+///     sequence of strings as the field `command prefixes` described above accepts, overriding
+///     that field's value for the channel `C`. This field is optional.
+///
+///     - `strip colors on output` — The value of this per-channel setting, if specified, should
+///     be `true` or `false`, specifying whether IRC formatting codes, such as those for colors and
+///     bold text, are to be removed from the messages that the bot sends to the channel `C`, as on
+///     networks on which the channel mode `+c` blocks or strips such messages. This field is
+///     optional; its value defaults to `false`.
+///
+///     - `anti-highlight` — The value of this per-channel setting, if specified, should be `true`
+///     or `false`, specifying whether the bot is to insert a zero-width space into the nickname of
+///     each member of the channel `C` that appears in the messages and actions that it sends to the
+///     channel, so that repeating what users have said, as in quotations, does not highlight them.
+///     The nickname of a user to whom the bot addresses a reply is not altered at the beginning of
+///     the reply. This field is optional; its value defaults to `false`.
+///
+///     - `locale` — The value of this per-channel setting, if specified, should be a string as
+///     the field `locale` described above accepts, overriding that field's value for replies sent
+///     to the channel `C`, except to users who have chosen locales of their own. This field is
+///     optional.
+///
+///     - `enabled commands` — The value of this per-channel setting, if specified, should be a
+///     sequence of strings, each of which is either the name of a bot command or `module:`
+///     followed by the name of a module, such as `module:game`. If this setting is specified, only
+///     the commands so named, and the commands of the modules so named, may be used in the channel
+///     `C`. This field is optional.
+///
+///     - `disabled commands` — The value of this per-channel setting, if specified, should be a
+///     sequence of strings as the per-channel setting `enabled commands` accepts, naming commands
+///     and modules whose commands may not be used in the channel `C`. An entry naming a command
+///     takes precedence over one naming the command's module in either setting, so that, e.g., a
+///     single command of a disabled module may be enabled. The bot's administrators may also
+///     enable and disable commands in a channel while the bot runs, with the commands `enable` and
+///     `disable` of the `admin` module, overriding these settings until the bot is restarted. This
+///     field is optional.
+///
 ///
+/// [CTCP]: <https://modern.ircdocs.horse/ctcp.html>
+/// [DCC]: <https://modern.ircdocs.horse/dcc.html>
+/// [Prometheus]: <https://prometheus.io/docs/instrumenting/exposition_formats/>
+/// [STS]: <https://ircv3.net/specs/extensions/sts>
+/// [`BUILT_IN_MESSAGES`]: <constant.BUILT_IN_MESSAGES.html>
+/// [`Severity`]: <enum.Severity.html>
+/// [`UserEvent::Flood`]: <enum.UserEvent.html#variant.Flood>
+/// [TOML]: <https://toml.io/>
 /// [YAML]: <https://en.wikipedia.org/wiki/YAML>
 /// [`Config::try_from_path`]: <struct.Config.html#method.try_from_path>
+/// [`Config::try_from_path_with_format`]: <struct.Config.html#method.try_from_path_with_format>
 /// [`Config`]: <struct.Config.html>
+/// [`replay`]: <fn.replay.html>
 /// [`Duration`]: <https://doc.rust-lang.org/std/time/struct.Duration.html>
 /// [`regex` flag]: <https://docs.rs/regex/*/regex/#grouping-and-flags>
 /// [`regex` syntax]: <https://docs.rs/regex/*/regex/#syntax>
 /// [`regex`]: <https://docs.rs/regex/*/regex/>
 #[derive(Debug)]
 pub struct Config {
-    pub(super) nickname: String,
+    pub(super) admins: SmallVec<[Admin; 8]>,
 
-    pub(super) username: String,
+    pub(super) admin_auth: AdminAuth,
 
-    pub(super) realname: String,
+    pub(super) shadow_bans: ShadowBans,
 
-    pub(super) admins: SmallVec<[Admin; 8]>,
+    pub(super) flood_protection: Option<FloodProtection>,
 
-    pub(super) servers: SmallVec<[Server; 8]>,
+    pub(super) servers: Vec<Server>,
 
     pub(super) aatxe_configs: SmallVec<[(ServerConfigIndex, Arc<aatxe::Config>); 8]>,
 
     pub(super) join_delay: Duration,
+
+    pub(super) max_command_panics: Option<u32>,
+
+    pub(super) command_timeout: Option<Duration>,
+
+    pub(super) command_timeouts: BTreeMap<String, Duration>,
+
+    pub(super) rejoin_on_kick: bool,
+
+    pub(super) rejoin_delay: Duration,
+
+    pub(super) join_on_invite: InvitePolicy,
+
+    pub(super) command_prefixes: Vec<String>,
+
+    pub(super) address_by_nick: bool,
+
+    pub(super) bare_commands_in_private: bool,
+
+    pub(super) strip_formatting: bool,
+
+    pub(super) suggest_commands: bool,
+
+    pub(super) locale: String,
+
+    pub(super) messages: BTreeMap<String, BTreeMap<String, String>>,
+
+    pub(super) command_replies: BTreeMap<String, ReplyRoute>,
+
+    pub(super) page_length: Option<usize>,
+
+    pub(super) paste: Option<Paste>,
+
+    pub(super) aliases: BTreeMap<String, String>,
+
+    pub(super) aliases_file: Option<PathBuf>,
+
+    pub(super) prefs_file: Option<PathBuf>,
+
+    pub(super) command_stats_file: Option<PathBuf>,
+
+    pub(super) session_recording_file: Option<PathBuf>,
+
+    pub(super) sts_policy_file: Option<PathBuf>,
+
+    pub(super) control_socket: Option<PathBuf>,
+
+    pub(super) console: bool,
+
+    pub(super) dcc: Dcc,
+
+    pub(super) outbox: Outbox,
+
+    pub(super) channel_logs: ChannelLogs,
+
+    pub(super) audit_log: AuditLog,
+
+    pub(super) http: Http,
+
+    pub(super) module_settings: BTreeMap<String, Value>,
+
+    pub(super) module_limits: BTreeMap<String, ModuleLimits>,
+
+    pub(super) error_notifications: Option<ErrorNotifications>,
+
+    /// The file from which the configuration was read, if any, from which it may be reloaded, and
+    /// the file's format
+    pub(super) path: Option<(PathBuf, ConfigFormat)>,
+}
+
+/// The formats in which a configuration may be written
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConfigFormat {
+    Yaml,
+
+    Toml,
+
+    /// JSON, which, being nearly a subset of YAML, is parsed as YAML
+    Json,
+}
+
+impl ConfigFormat {
+    /// Returns the format indicated by the extension of the given path: TOML for `.toml`, JSON for
+    /// `.json`, and YAML otherwise.
+    pub fn from_path<P>(path: P) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        match path.as_ref().extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("toml") => ConfigFormat::Toml,
+            Some(ext) if ext.eq_ignore_ascii_case("json") => ConfigFormat::Json,
+            _ => ConfigFormat::Yaml,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
@@ -241,75 +1108,461 @@ pub(super) struct Admin {
 
     #[serde(default)]
     pub host: Option<String>,
+
+    #[serde(default)]
+    pub account: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
-pub(super) struct Server {
-    // TODO: Use a `ServerName` newtype that checks that the string is a valid identifier.
-    pub name: String,
+pub(super) struct AdminAuth {
+    #[serde(default)]
+    pub(super) secret: Option<String>,
 
-    pub host: String,
+    #[serde(default, rename = "secret file")]
+    secret_file: Option<PathBuf>,
 
-    pub port: u16,
+    #[serde(default = "default_admin_session_length", rename = "session length")]
+    session_length: u32,
+}
 
-    #[serde(rename = "nick password")]
-    pub(super) nick_password: Option<String>,
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct ShadowBans {
+    #[serde(default)]
+    pub(super) masks: Vec<String>,
 
-    #[serde(rename = "server password")]
-    pub(super) server_password: Option<String>,
+    #[serde(default, rename = "review channel")]
+    pub(super) review_channel: Option<String>,
+}
 
-    #[serde(default = "mk_true", rename = "TLS")]
-    pub tls: bool,
+#[derive(Debug, Deserialize)]
+pub(super) struct FloodProtection {
+    #[serde(default = "default_flood_messages")]
+    pub(super) messages: usize,
+
+    #[serde(default = "default_flood_repeats")]
+    pub(super) repeats: usize,
+
+    #[serde(default = "default_flood_period")]
+    period: u16,
+
+    #[serde(default = "default_flood_cooldown")]
+    cooldown: u16,
 
     #[serde(default)]
-    pub channels: SmallVec<[Channel; 24]>,
+    pub(super) action: FloodAction,
+}
 
-    #[serde(default, rename = "await registration mode")]
-    pub(super) await_registration_mode: Option<char>,
+/// What the bot is to do about a user who floods a channel, beyond ignoring the user's commands
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+pub(super) enum FloodAction {
+    #[serde(rename = "none")]
+    Nothing,
+
+    #[serde(rename = "warn")]
+    Warn,
+
+    #[serde(rename = "quiet")]
+    Quiet,
+
+    #[serde(rename = "kick")]
+    Kick,
+
+    #[serde(rename = "ban")]
+    Ban,
 }
 
 #[derive(Debug, Deserialize)]
-pub(super) struct Channel {
-    pub name: ChannelName,
+pub(super) struct Paste {
+    #[serde(default = "default_paste_lines")]
+    pub(super) lines: usize,
 
-    #[serde(rename = "can see")]
-    pub can_see: Option<RoLock<Regex<rx_cfg::Anchored>>>,
+    #[serde(default = "default_paste_service")]
+    pub(super) service: String,
 
-    #[serde(rename = "seen by")]
-    pub seen_by: Option<RoLock<Regex<rx_cfg::Anchored>>>,
+    #[serde(default, rename = "URL")]
+    pub(super) url: Option<SerdeUrl>,
+
+    #[serde(default = "default_paste_field")]
+    pub(super) field: String,
+
+    #[serde(default, rename = "public URL")]
+    pub(super) public_url: Option<SerdeUrl>,
 }
 
-#[derive(Debug)]
-pub struct ConfigBuilder(Result<inner::Config>);
+#[derive(Debug, Deserialize)]
+pub(super) struct Dcc {
+    #[serde(default)]
+    pub(super) address: Option<IpAddr>,
 
-impl Config {
-    pub fn try_from<T>(input: T) -> Result<Config>
-    where
-        T: IntoConfig,
-    {
-        input.into_config()
-    }
+    #[serde(default = "default_dcc_timeout")]
+    timeout: u16,
 
-    pub fn try_from_path<P>(path: P) -> Result<Config>
-    where
-        P: AsRef<Path>,
-    {
-        Self::try_from(File::open(path)?)
-    }
+    #[serde(default)]
+    pub(super) passive: bool,
 
-    pub fn build() -> ConfigBuilder {
-        ConfigBuilder(Ok(Default::default()))
-    }
+    #[serde(default, rename = "send directories")]
+    pub(super) send_directories: Vec<PathBuf>,
+
+    #[serde(default = "default_dcc_max_send_size", rename = "max send size")]
+    pub(super) max_send_size: u64,
 }
 
-impl ConfigBuilder {
-    pub fn nickname<S>(self, nickname: S) -> Self
-    where
-        S: Into<String>,
-    {
-        let nickname = nickname.into();
+#[derive(Debug, Deserialize)]
+pub(super) struct Outbox {
+    #[serde(default = "default_outbox_capacity")]
+    pub(super) capacity: usize,
 
-        if nickname.is_empty() {
+    #[serde(default, rename = "overflow policy")]
+    pub(super) overflow_policy: OutboxOverflowPolicy,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct ModuleLimits {
+    #[serde(default, rename = "run time")]
+    run_time: Option<u32>,
+
+    #[serde(default)]
+    pub(super) errors: Option<u64>,
+
+    #[serde(default)]
+    pub(super) storage: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ErrorNotifications {
+    #[serde(default = "default_error_notification_severity")]
+    pub(super) severity: Severity,
+
+    #[serde(default)]
+    pub(super) channel: Option<String>,
+
+    #[serde(default = "default_error_notification_interval")]
+    interval: u32,
+
+    #[serde(
+        default = "default_error_notification_repeat_interval",
+        rename = "repeat interval"
+    )]
+    repeat_interval: u32,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct Http {
+    #[serde(default)]
+    pub(super) address: Option<SocketAddr>,
+
+    #[serde(default)]
+    pub(super) metrics: bool,
+
+    #[serde(default)]
+    pub(super) webhooks: Vec<Webhook>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Webhook {
+    pub(super) path: String,
+
+    #[serde(default)]
+    pub(super) token: String,
+
+    #[serde(default, rename = "token file")]
+    token_file: Option<PathBuf>,
+
+    pub(super) template: String,
+
+    #[serde(default)]
+    pub(super) server: Option<String>,
+
+    pub(super) channels: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub(super) struct AuditLog {
+    #[serde(default)]
+    pub(super) file: Option<PathBuf>,
+
+    #[serde(default)]
+    pub(super) channel: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct ChannelLogs {
+    #[serde(default)]
+    pub(super) directory: Option<PathBuf>,
+
+    #[serde(default)]
+    pub(super) format: ChannelLogFormat,
+
+    #[serde(default, rename = "keep days")]
+    pub(super) keep_days: Option<u16>,
+
+    #[serde(default = "default_channel_log_buffer_size", rename = "buffer size")]
+    pub(super) buffer_size: usize,
+}
+
+/// The format in which to write channel log files
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+pub(super) enum ChannelLogFormat {
+    #[serde(rename = "text")]
+    Text,
+
+    #[serde(rename = "JSONL")]
+    Jsonl,
+}
+
+/// What to do with a message that is to be queued for sending while the outbox is full
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+pub(super) enum OutboxOverflowPolicy {
+    #[serde(rename = "drop newest")]
+    DropNewest,
+
+    #[serde(rename = "drop oldest")]
+    DropOldest,
+
+    #[serde(rename = "disconnect")]
+    Disconnect,
+}
+
+/// From whom to accept invitations to channels
+#[derive(Clone, Copy, Debug, Deserialize, Eq, PartialEq)]
+pub(super) enum InvitePolicy {
+    #[serde(rename = "nobody")]
+    Nobody,
+
+    #[serde(rename = "admins")]
+    Admins,
+
+    #[serde(rename = "anyone")]
+    Anyone,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Server {
+    // TODO: Use a `ServerName` newtype that checks that the string is a valid identifier.
+    pub name: String,
+
+    pub host: String,
+
+    pub port: u16,
+
+    #[serde(default)]
+    pub(super) nickname: Option<String>,
+
+    #[serde(default)]
+    pub(super) username: Option<String>,
+
+    #[serde(default)]
+    pub(super) realname: Option<String>,
+
+    #[serde(rename = "nick password")]
+    pub(super) nick_password: Option<String>,
+
+    #[serde(default, rename = "nick password file")]
+    nick_password_file: Option<PathBuf>,
+
+    #[serde(rename = "server password")]
+    pub(super) server_password: Option<String>,
+
+    #[serde(default, rename = "server password file")]
+    server_password_file: Option<PathBuf>,
+
+    #[serde(default)]
+    pub(super) oper: Option<Oper>,
+
+    #[serde(default, rename = "WEBIRC")]
+    pub(super) webirc: Option<Webirc>,
+
+    #[serde(default = "mk_true", rename = "TLS")]
+    pub tls: bool,
+
+    #[serde(default, rename = "ping interval")]
+    pub(super) ping_interval: Option<u32>,
+
+    #[serde(default, rename = "ping timeout")]
+    pub(super) ping_timeout: Option<u32>,
+
+    #[serde(default, rename = "TLS CA certificate")]
+    pub(super) tls_ca_cert_path: Option<String>,
+
+    #[serde(default, rename = "TLS client certificate")]
+    pub(super) tls_client_cert_path: Option<String>,
+
+    #[serde(default, rename = "TLS client certificate password")]
+    pub(super) tls_client_cert_password: Option<String>,
+
+    #[serde(default, rename = "TLS client certificate password file")]
+    tls_client_cert_password_file: Option<PathBuf>,
+
+    #[serde(default)]
+    pub channels: SmallVec<[Channel; 24]>,
+
+    #[serde(default, rename = "await registration mode")]
+    pub(super) await_registration_mode: Option<char>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Oper {
+    pub(super) name: String,
+
+    #[serde(default)]
+    pub(super) password: String,
+
+    #[serde(default, rename = "password file")]
+    password_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Webirc {
+    #[serde(default)]
+    pub(super) password: String,
+
+    #[serde(default, rename = "password file")]
+    password_file: Option<PathBuf>,
+
+    pub(super) gateway: String,
+
+    #[serde(rename = "IP")]
+    pub(super) ip: IpAddr,
+
+    #[serde(default)]
+    pub(super) host: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(super) struct Channel {
+    pub name: ChannelName,
+
+    #[serde(rename = "can see")]
+    pub can_see: Option<RoLock<Regex<rx_cfg::Anchored>>>,
+
+    #[serde(rename = "seen by")]
+    pub seen_by: Option<RoLock<Regex<rx_cfg::Anchored>>>,
+
+    #[serde(default, rename = "rejoin on kick")]
+    pub(super) rejoin_on_kick: Option<bool>,
+
+    #[serde(default, rename = "join on invite")]
+    pub(super) join_on_invite: Option<InvitePolicy>,
+
+    #[serde(default, rename = "command prefixes")]
+    pub(super) command_prefixes: Option<Vec<String>>,
+
+    #[serde(default, rename = "strip colors on output")]
+    pub(super) strip_colors_on_output: bool,
+
+    #[serde(default, rename = "anti-highlight")]
+    pub(super) anti_highlight: bool,
+
+    #[serde(default)]
+    pub(super) locale: Option<String>,
+
+    #[serde(default, rename = "enabled commands")]
+    pub(super) enabled_commands: Option<Vec<String>>,
+
+    #[serde(default, rename = "disabled commands")]
+    pub(super) disabled_commands: Option<Vec<String>>,
+}
+
+#[derive(Debug)]
+pub struct ConfigBuilder(Result<inner::Config>);
+
+impl Config {
+    pub fn try_from<T>(input: T) -> Result<Config>
+    where
+        T: IntoConfig,
+    {
+        input.into_config()
+    }
+
+    /// Reads the configuration from the file at the given path, in the format indicated by the
+    /// path's extension (see [`ConfigFormat::from_path`]). A bot so configured may reload its
+    /// configuration from the file while running (see [`State::reload_config`]).
+    ///
+    /// [`ConfigFormat::from_path`]: <enum.ConfigFormat.html#method.from_path>
+    /// [`State::reload_config`]: <struct.State.html#method.reload_config>
+    pub fn try_from_path<P>(path: P) -> Result<Config>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+
+        Self::try_from_path_with_format(path, ConfigFormat::from_path(path))
+    }
+
+    /// Reads the configuration from the file at the given path, in the given format.
+    pub fn try_from_path_with_format<P>(path: P, format: ConfigFormat) -> Result<Config>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+
+        let mut text = String::new();
+        File::open(path)?.read_to_string(&mut text)?;
+
+        Ok(Config {
+            path: Some((path.to_owned(), format)),
+            ..parse_config(
+                &text,
+                format,
+                &format!("{:?}", path),
+                path.parent().unwrap_or_else(|| Path::new("")),
+            )?
+        })
+    }
+
+    /// Parses the configuration from the given text, in the given format.
+    pub fn try_from_str(input: &str, format: ConfigFormat) -> Result<Config> {
+        parse_config(input, format, "the given text", Path::new(""))
+    }
+
+    /// Reads the configuration anew from the file from which it was read.
+    pub(super) fn reread(&self) -> Result<Config> {
+        match self.path {
+            Some((ref path, format)) => Self::try_from_path_with_format(path, format),
+            None => Err(ErrorKind::ConfigNotReloadable.into()),
+        }
+    }
+
+    /// Returns how long to wait for the handler of the bot command with the given name to
+    /// finish, per `command timeouts` and `command timeout`, or `None` to wait indefinitely.
+    pub(super) fn timeout_for_command(&self, cmd: &str) -> Option<Duration> {
+        self.command_timeouts
+            .get(cmd)
+            .cloned()
+            .or(self.command_timeout)
+    }
+
+    /// Starts building a configuration in code, as an alternative to reading one from a file.
+    ///
+    /// ```
+    /// # extern crate irc_bot;
+    /// # fn main() -> irc_bot::Result<()> {
+    /// let config = irc_bot::Config::builder()
+    ///     .nick("bot")
+    ///     .server("net", "irc.example.net", 6697)
+    ///     .channel("#bot")
+    ///     .build()?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder(Ok(Default::default()))
+    }
+
+    /// Equivalent to [`builder`](#method.builder).
+    pub fn build() -> ConfigBuilder {
+        Self::builder()
+    }
+}
+
+impl ConfigBuilder {
+    pub fn nickname<S>(self, nickname: S) -> Self
+    where
+        S: Into<String>,
+    {
+        let nickname = nickname.into();
+
+        if nickname.is_empty() {
             return ConfigBuilder(Err(
                 ErrorKind::Config("nickname".into(), "is empty".into()).into()
             ));
@@ -337,8 +1590,147 @@ impl ConfigBuilder {
             ..cfg
         }))
     }
+
+    pub fn ctcp_version<S>(self, ctcp_version: S) -> Self
+    where
+        S: Into<String>,
+    {
+        ConfigBuilder(self.0.map(|cfg| inner::Config {
+            ctcp_version: ctcp_version.into(),
+            ..cfg
+        }))
+    }
+
+    /// Sets the bot's nickname; equivalent to [`nickname`](#method.nickname).
+    pub fn nick<S>(self, nick: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.nickname(nick)
+    }
+
+    /// Adds a server to which the bot is to connect, using TLS, with no channels; channels may be
+    /// added to it with [`channel`](#method.channel).
+    pub fn server<S1, S2>(self, name: S1, host: S2, port: u16) -> Self
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        let server = Server {
+            name: name.into(),
+            host: host.into(),
+            port,
+            nickname: None,
+            username: None,
+            realname: None,
+            nick_password: None,
+            nick_password_file: None,
+            server_password: None,
+            server_password_file: None,
+            oper: None,
+            webirc: None,
+            tls: true,
+            ping_interval: None,
+            ping_timeout: None,
+            tls_ca_cert_path: None,
+            tls_client_cert_path: None,
+            tls_client_cert_password: None,
+            tls_client_cert_password_file: None,
+            channels: Default::default(),
+            await_registration_mode: None,
+        };
+
+        ConfigBuilder(self.0.map(|mut cfg| {
+            cfg.servers.push(server);
+            cfg
+        }))
+    }
+
+    /// Sets whether the bot is to connect with TLS to the server most recently added.
+    pub fn tls(self, tls: bool) -> Self {
+        self.modify_last_server(|server| {
+            server.tls = tls;
+            Ok(())
+        })
+    }
+
+    /// Adds a channel for the bot to join on the server most recently added.
+    pub fn channel<S>(self, name: S) -> Self
+    where
+        S: AsRef<str>,
+    {
+        let name = name.as_ref();
+
+        self.modify_last_server(|server| {
+            let name = ChannelName::new(name).map_err(|_| {
+                ErrorKind::Config(
+                    "channels".into(),
+                    format!("includes an invalid channel name, {:?}", name),
+                )
+            })?;
+
+            server.channels.push(Channel {
+                name,
+                can_see: None,
+                seen_by: None,
+                rejoin_on_kick: None,
+                join_on_invite: None,
+                command_prefixes: None,
+                strip_colors_on_output: false,
+                anti_highlight: false,
+                locale: None,
+                enabled_commands: None,
+                disabled_commands: None,
+            });
+
+            Ok(())
+        })
+    }
+
+    /// Adds an administrator of the bot, identified by nickname.
+    pub fn admin<S>(self, nick: S) -> Self
+    where
+        S: Into<String>,
+    {
+        let admin = Admin {
+            nick: Some(nick.into()),
+            user: None,
+            host: None,
+            account: None,
+        };
+
+        ConfigBuilder(self.0.map(|mut cfg| {
+            cfg.admins.push(admin);
+            cfg
+        }))
+    }
+
+    /// Validates and returns the configuration built.
+    pub fn build(self) -> Result<Config> {
+        self.into_config()
+    }
+
+    fn modify_last_server<F>(self, f: F) -> Self
+    where
+        F: FnOnce(&mut Server) -> Result<()>,
+    {
+        ConfigBuilder(self.0.and_then(|mut cfg| {
+            match cfg.servers.last_mut() {
+                Some(server) => f(server)?,
+                None => bail!(ErrorKind::Config(
+                    "servers".into(),
+                    "is empty, but a server must be added before it is configured".into()
+                )),
+            }
+
+            Ok(cfg)
+        }))
+    }
 }
 
+/// The greatest depth to which configuration files may include other files
+const MAX_INCLUDE_DEPTH: usize = 16;
+
 // TODO: Switch to `TryFrom` once rustc 1.18 is stable.
 pub trait IntoConfig {
     fn into_config(self) -> Result<Config>;
@@ -385,34 +1777,246 @@ where
     }
 }
 
-impl IntoConfig for File {
-    fn into_config(self) -> Result<Config> {
-        BufReader::new(self).into_config()
+impl IntoConfig for File {
+    fn into_config(self) -> Result<Config> {
+        BufReader::new(self).into_config()
+    }
+}
+
+fn read_config(input: &str) -> Result<Config> {
+    parse_config(input, ConfigFormat::Yaml, "the given text", Path::new(""))
+}
+
+/// Parses the configuration from the given text, in the given format, naming the text's `source`
+/// in any syntax error and resolving any included files' paths relative to the directory `dir`.
+fn parse_config(input: &str, format: ConfigFormat, source: &str, dir: &Path) -> Result<Config> {
+    let syntax_err = |problem: String| ErrorKind::ConfigSyntax(source.to_owned(), problem);
+
+    let value = parse_config_value(input, format).map_err(&syntax_err)?;
+
+    let cfg = match value {
+        Value::Mapping(ref map) if map.contains_key(&include_key()) => {
+            serde_yaml::from_value(resolve_includes(value.clone(), dir, 0)?)
+                .map_err(|e| syntax_err(e.to_string()))?
+        }
+        // Without includes, the configuration is deserialized from its text, so that errors may
+        // be reported with their locations in the text.
+        _ => match format {
+            ConfigFormat::Yaml | ConfigFormat::Json => {
+                serde_yaml::from_str(input).map_err(|e| syntax_err(e.to_string()))?
+            }
+            ConfigFormat::Toml => toml::from_str(input).map_err(|e| syntax_err(e.to_string()))?,
+        },
+    };
+
+    cook_config(cfg)
+}
+
+/// Parses the given text, in the given format, into a generic value.
+fn parse_config_value(input: &str, format: ConfigFormat) -> StdResult<Value, String> {
+    match format {
+        ConfigFormat::Yaml | ConfigFormat::Json => {
+            serde_yaml::from_str(input).map_err(|e| e.to_string())
+        }
+        ConfigFormat::Toml => toml::from_str::<toml::Value>(input)
+            .map_err(|e| e.to_string())
+            .and_then(|value| serde_yaml::to_value(value).map_err(|e| e.to_string())),
+    }
+}
+
+fn include_key() -> Value {
+    Value::String("include".into())
+}
+
+/// Merges into the given configuration value the files that it includes, whose paths are relative
+/// to the directory `dir`, and those that they include in turn, to the given depth.
+fn resolve_includes(mut value: Value, dir: &Path, depth: usize) -> Result<Value> {
+    let include_err = |problem: String| ErrorKind::Config("include".into(), problem);
+
+    let patterns = match value {
+        Value::Mapping(ref mut map) => match map.remove(&include_key()) {
+            Some(Value::Sequence(patterns)) => patterns,
+            Some(_) => bail!(include_err("is not a sequence of strings".into())),
+            None => return Ok(value),
+        },
+        _ => return Ok(value),
+    };
+
+    ensure!(
+        depth < MAX_INCLUDE_DEPTH,
+        include_err("nests too deeply; do some included files include each other?".into())
+    );
+
+    let mut merged = Value::Mapping(Default::default());
+
+    for pattern in patterns {
+        let pattern = match pattern {
+            Value::String(pattern) => pattern,
+            _ => bail!(include_err("is not a sequence of strings".into())),
+        };
+
+        for path in expand_include_pattern(dir, &pattern)? {
+            let mut text = String::new();
+
+            File::open(&path)
+                .and_then(|mut file| file.read_to_string(&mut text))
+                .map_err(|e| {
+                    include_err(format!(
+                        "names a file that could not be read, {:?} ({})",
+                        path, e
+                    ))
+                })?;
+
+            let fragment = parse_config_value(&text, ConfigFormat::from_path(&path))
+                .map_err(|problem| ErrorKind::ConfigSyntax(format!("{:?}", path), problem))?;
+
+            let fragment_dir = path.parent().unwrap_or(dir);
+
+            merge_config_values(
+                &mut merged,
+                resolve_includes(fragment, fragment_dir, depth + 1)?,
+            );
+        }
+    }
+
+    merge_config_values(&mut merged, value);
+
+    Ok(merged)
+}
+
+/// Returns the paths of the files matched by the given `include` pattern, relative to the
+/// directory `dir`, in the order of their names.
+fn expand_include_pattern(dir: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+    let path = dir.join(pattern);
+
+    let (parent, name) = match (
+        path.parent(),
+        path.file_name().and_then(|name| name.to_str()),
+    ) {
+        (Some(parent), Some(name)) if name.contains(&['*', '?'][..]) => (parent, name),
+        _ => return Ok(vec![path.clone()]),
+    };
+
+    let name_rx = name.chars().fold(String::from("^"), |mut rx, c| {
+        match c {
+            '*' => rx.push_str(".*"),
+            '?' => rx.push('.'),
+            c => rx.push_str(&regex::escape(&c.to_string())),
+        }
+        rx
+    }) + "$";
+    let name_rx = regex::Regex::new(&name_rx)?;
+
+    let entries = fs::read_dir(parent).map_err(|e| {
+        ErrorKind::Config(
+            "include".into(),
+            format!(
+                "names a directory that could not be read, {:?} ({})",
+                parent, e
+            ),
+        )
+    })?;
+
+    let mut paths = Vec::new();
+
+    for entry in entries {
+        let path = entry?.path();
+
+        match path.file_name().and_then(|name| name.to_str()) {
+            Some(name) if name_rx.is_match(name) && path.is_file() => {}
+            _ => continue,
+        }
+
+        paths.push(path);
     }
+
+    paths.sort();
+
+    Ok(paths)
 }
 
-fn read_config(input: &str) -> Result<Config> {
-    serde_yaml::from_str(input)
-        .map_err(Into::into)
-        .and_then(cook_config)
+/// Merges the given overlay into the given base value: mappings key by key, sequences by
+/// concatenation, and otherwise by replacing the base with the overlay.
+fn merge_config_values(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (&mut Value::Mapping(ref mut base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                if let Some(base_value) = base.get_mut(&key) {
+                    merge_config_values(base_value, value);
+                    continue;
+                }
+
+                base.insert(key, value);
+            }
+        }
+        (&mut Value::Sequence(ref mut base), Value::Sequence(overlay)) => base.extend(overlay),
+        (base, overlay) => *base = overlay,
+    }
 }
 
 fn cook_config(mut cfg: inner::Config) -> Result<Config> {
+    resolve_secrets(&mut cfg)?;
+
     validate_config(&cfg)?;
 
     fill_in_config_defaults(&mut cfg)?;
 
     let inner::Config {
-        nickname,
-        username,
-        realname,
+        nickname: _,
+        username: _,
+        realname: _,
         admins,
+        admin_auth,
+        shadow_bans,
+        flood_protection,
         servers,
         join_delay,
+        max_command_panics,
+        command_timeout,
+        command_timeouts,
+        rejoin_on_kick,
+        rejoin_delay,
+        join_on_invite,
+        command_prefixes,
+        address_by_nick,
+        bare_commands_in_private,
+        strip_formatting,
+        suggest_commands,
+        locale,
+        messages,
+        command_replies,
+        page_length,
+        paste,
+        aliases,
+        aliases_file,
+        prefs_file,
+        command_stats_file,
+        session_recording_file,
+        ctcp_version,
+        sts_policy_file,
+        control_socket,
+        console,
+        dcc,
+        outbox,
+        channel_logs,
+        audit_log,
+        http,
+        module_settings,
+        module_limits,
+        error_notifications,
     } = cfg;
 
     let join_delay = Duration::from_secs(join_delay.into());
 
+    let rejoin_delay = Duration::from_secs(rejoin_delay.into());
+
+    let command_timeout = command_timeout.map(|secs| Duration::from_secs(secs.into()));
+
+    let command_timeouts = command_timeouts
+        .into_iter()
+        .map(|(cmd, secs)| (cmd, Duration::from_secs(secs.into())))
+        .collect();
+
     let aatxe_configs = servers
         .iter()
         .enumerate()
@@ -421,9 +2025,22 @@ fn cook_config(mut cfg: inner::Config) -> Result<Config> {
                 name: _,
                 ref host,
                 port,
+                ref nickname,
+                ref username,
+                ref realname,
                 tls,
+                ping_interval,
+                ping_timeout,
+                ref tls_ca_cert_path,
+                ref tls_client_cert_path,
+                ref tls_client_cert_password,
+                tls_client_cert_password_file: _,
                 ref nick_password,
+                nick_password_file: _,
                 ref server_password,
+                server_password_file: _,
+                oper: _,
+                webirc: _,
                 channels: _,
                 await_registration_mode: _,
             } = server_cfg;
@@ -431,15 +2048,21 @@ fn cook_config(mut cfg: inner::Config) -> Result<Config> {
             let server_cfg_idx = i.try_into()?;
 
             let aatxe_config = Arc::new(aatxe::Config {
-                // TODO: Allow nickname etc. to be configured per-server.
-                nickname: Some(nickname.clone()),
+                nickname: nickname.clone(),
                 nick_password: nick_password.clone(),
                 password: server_password.clone(),
-                username: Some(username.clone()),
-                realname: Some(realname.clone()),
+                username: username.clone(),
+                realname: realname.clone(),
                 server: Some(host.clone()),
                 port: Some(port),
                 use_ssl: Some(tls),
+                ping_time: ping_interval,
+                ping_timeout,
+                cert_path: tls_ca_cert_path.clone(),
+                client_cert_path: tls_client_cert_path.clone(),
+                client_cert_pass: tls_client_cert_password.clone(),
+                version: Some(ctcp_version.clone()),
+                source: Some(pkg_info::HOMEPAGE_STR.to_string()),
                 ..Default::default()
             });
 
@@ -448,32 +2071,475 @@ fn cook_config(mut cfg: inner::Config) -> Result<Config> {
         .collect::<Result<_>>()?;
 
     Ok(Config {
-        nickname,
-        username,
-        realname,
         admins,
+        admin_auth,
+        shadow_bans,
+        flood_protection,
         servers,
         aatxe_configs,
         join_delay,
+        max_command_panics,
+        command_timeout,
+        command_timeouts,
+        rejoin_on_kick,
+        rejoin_delay,
+        join_on_invite,
+        command_prefixes,
+        address_by_nick,
+        bare_commands_in_private,
+        strip_formatting,
+        suggest_commands,
+        locale,
+        messages,
+        command_replies,
+        page_length,
+        paste,
+        aliases,
+        aliases_file,
+        prefs_file,
+        command_stats_file,
+        session_recording_file,
+        sts_policy_file,
+        control_socket,
+        console,
+        dcc,
+        outbox,
+        channel_logs,
+        audit_log,
+        http,
+        module_settings,
+        module_limits,
+        error_notifications,
+        path: None,
     })
 }
 
+/// Expands references to environment variables in the secrets given in the configuration, and
+/// reads the secrets given by file.
+fn resolve_secrets(cfg: &mut inner::Config) -> Result<()> {
+    for server in &mut cfg.servers {
+        let name = &server.name;
+        let key = |field| format!("servers: {}: {}", name, field);
+
+        resolve_secret(
+            &key("nick password"),
+            &mut server.nick_password,
+            &server.nick_password_file,
+        )?;
+        resolve_secret(
+            &key("server password"),
+            &mut server.server_password,
+            &server.server_password_file,
+        )?;
+        resolve_secret(
+            &key("TLS client certificate password"),
+            &mut server.tls_client_cert_password,
+            &server.tls_client_cert_password_file,
+        )?;
+
+        if let Some(ref mut oper) = server.oper {
+            let mut password = Some(oper.password.clone()).filter(|s| !s.is_empty());
+
+            resolve_secret(&key("oper: password"), &mut password, &oper.password_file)?;
+
+            oper.password = password.unwrap_or_default();
+        }
+
+        if let Some(ref mut webirc) = server.webirc {
+            let mut password = Some(webirc.password.clone()).filter(|s| !s.is_empty());
+
+            resolve_secret(
+                &key("WEBIRC: password"),
+                &mut password,
+                &webirc.password_file,
+            )?;
+
+            webirc.password = password.unwrap_or_default();
+        }
+    }
+
+    resolve_secret(
+        "admin auth: secret",
+        &mut cfg.admin_auth.secret,
+        &cfg.admin_auth.secret_file,
+    )?;
+
+    for webhook in &mut cfg.http.webhooks {
+        let key = format!("HTTP: webhooks: {}: token", webhook.path);
+        let mut token = Some(webhook.token.clone()).filter(|s| !s.is_empty());
+
+        resolve_secret(&key, &mut token, &webhook.token_file)?;
+
+        webhook.token = token.unwrap_or_default();
+    }
+
+    Ok(())
+}
+
+fn resolve_secret(key: &str, value: &mut Option<String>, file: &Option<PathBuf>) -> Result<()> {
+    *value = match (value.take(), file) {
+        (Some(_), Some(_)) => bail!(ErrorKind::Config(
+            key.to_owned(),
+            format!("is specified, but so is {:?}", format!("{} file", key))
+        )),
+        (Some(value), None) => Some(expand_env_vars(key, &value)?),
+        (None, Some(path)) => {
+            let mut secret = String::new();
+
+            File::open(path)
+                .and_then(|mut file| file.read_to_string(&mut secret))
+                .map_err(|e| {
+                    ErrorKind::Config(
+                        format!("{} file", key),
+                        format!("names a file that could not be read ({})", e),
+                    )
+                })?;
+
+            if secret.ends_with('\n') {
+                secret.pop();
+
+                if secret.ends_with('\r') {
+                    secret.pop();
+                }
+            }
+
+            Some(secret)
+        }
+        (None, None) => None,
+    };
+
+    Ok(())
+}
+
+/// Replaces each reference of the form `${NAME}` in the given value of the given key with the
+/// value of the environment variable `NAME`, and each `$$` with `$`.
+fn expand_env_vars(key: &str, value: &str) -> Result<String> {
+    let mut output = String::with_capacity(value.len());
+    let mut rest = value;
+
+    while let Some(i) = rest.find('$') {
+        output.push_str(&rest[..i]);
+        rest = &rest[i + 1..];
+
+        if rest.starts_with('$') {
+            output.push('$');
+            rest = &rest[1..];
+        } else if rest.starts_with('{') {
+            let end = rest.find('}').ok_or_else(|| {
+                ErrorKind::Config(key.to_owned(), "contains an unterminated `${`".into())
+            })?;
+            let name = &rest[1..end];
+
+            output.push_str(&env::var(name).map_err(|e| {
+                ErrorKind::Config(
+                    key.to_owned(),
+                    format!(
+                        "refers to the environment variable {:?}, which {}",
+                        name,
+                        match e {
+                            env::VarError::NotPresent => "is not set",
+                            env::VarError::NotUnicode(_) => "is not valid Unicode",
+                        }
+                    ),
+                )
+            })?);
+            rest = &rest[end + 1..];
+        } else {
+            output.push('$');
+        }
+    }
+
+    output.push_str(rest);
+
+    Ok(output)
+}
+
 fn validate_config(cfg: &inner::Config) -> Result<()> {
     ensure!(
         !cfg.nickname.is_empty(),
         ErrorKind::Config("nickname".into(), "is empty".into())
     );
 
+    ensure!(
+        cfg.max_command_panics != Some(0),
+        ErrorKind::Config("max command panics".into(), "is zero".into())
+    );
+
+    ensure!(
+        cfg.command_timeout != Some(0),
+        ErrorKind::Config("command timeout".into(), "is zero".into())
+    );
+
+    for (cmd, &secs) in &cfg.command_timeouts {
+        ensure!(
+            secs != 0,
+            ErrorKind::Config(format!("command timeouts: {}", cmd), "is zero".into())
+        );
+    }
+
+    ensure!(
+        cfg.page_length != Some(0),
+        ErrorKind::Config("page length".into(), "is zero".into())
+    );
+
+    ensure!(
+        cfg.admin_auth.secret != Some(String::new()),
+        ErrorKind::Config("admin auth: secret".into(), "is empty".into())
+    );
+
+    ensure!(
+        cfg.admin_auth.session_length != 0,
+        ErrorKind::Config("admin auth: session length".into(), "is zero".into())
+    );
+
+    ensure!(
+        cfg.shadow_bans
+            .masks
+            .iter()
+            .all(|mask| !mask.trim().is_empty()),
+        ErrorKind::Config("shadow bans: masks".into(), "contains an empty mask".into())
+    );
+
+    for (module, limits) in &cfg.module_limits {
+        for &(value, field) in &[
+            (limits.run_time.map(u64::from), "run time"),
+            (limits.errors, "errors"),
+            (limits.storage, "storage"),
+        ] {
+            ensure!(
+                value != Some(0),
+                ErrorKind::Config(
+                    format!("module limits: {}: {}", module, field),
+                    "is zero".into()
+                )
+            );
+        }
+    }
+
+    if let Some(ref flood) = cfg.flood_protection {
+        for &(value, field) in &[
+            (flood.messages, "messages"),
+            (flood.repeats, "repeats"),
+            (flood.period.into(), "period"),
+            (flood.cooldown.into(), "cooldown"),
+        ] {
+            ensure!(
+                value != 0,
+                ErrorKind::Config(format!("flood protection: {}", field), "is zero".into())
+            );
+        }
+    }
+
+    if let Some(ref paste) = cfg.paste {
+        ensure!(
+            paste.lines != 0,
+            ErrorKind::Config("paste: lines".into(), "is zero".into())
+        );
+
+        match &paste.service[..] {
+            "bot" => {
+                ensure!(
+                    paste.public_url.is_some(),
+                    ErrorKind::Config(
+                        "paste: public URL".into(),
+                        "is not set, but the bot is to serve pastes itself".into()
+                    )
+                );
+                ensure!(
+                    cfg.http.address.is_some(),
+                    ErrorKind::Config(
+                        "HTTP: address".into(),
+                        "is not set, but the bot is to serve pastes itself".into()
+                    )
+                );
+            }
+            "HTTP form" => {
+                ensure!(
+                    paste.url.is_some(),
+                    ErrorKind::Config(
+                        "paste: URL".into(),
+                        "is not set, but pastes are to be uploaded as forms".into()
+                    )
+                );
+                ensure!(
+                    !paste.field.is_empty(),
+                    ErrorKind::Config("paste: field".into(), "is empty".into())
+                );
+            }
+            _ => {}
+        }
+    }
+
+    ensure!(
+        cfg.outbox.capacity != 0,
+        ErrorKind::Config("outbox: capacity".into(), "is zero".into())
+    );
+
+    ensure!(
+        cfg.channel_logs.keep_days != Some(0),
+        ErrorKind::Config("channel logs: keep days".into(), "is zero".into())
+    );
+
     ensure!(
         !cfg.servers.is_empty(),
         ErrorKind::Config("servers".into(), "is empty".into())
     );
 
+    validate_command_prefixes("command prefixes".into(), &cfg.command_prefixes)?;
+
+    for (name, expansion) in &cfg.aliases {
+        super::aliases::validate_alias(name, expansion)
+            .map_err(|problem| ErrorKind::Config(format!("aliases: {}", name), problem))?;
+    }
+
+    ensure!(
+        super::i18n::is_known_locale(&cfg.messages, &cfg.locale),
+        ErrorKind::Config(
+            "locale".into(),
+            "is neither `en` nor a locale given in `messages`".into()
+        )
+    );
+
+    for server in &cfg.servers {
+        for chan in &server.channels {
+            if let Some(ref locale) = chan.locale {
+                ensure!(
+                    super::i18n::is_known_locale(&cfg.messages, locale),
+                    ErrorKind::Config(
+                        format!("servers: {}: channels: {}: locale", server.name, chan.name),
+                        "is neither `en` nor a locale given in `messages`".into()
+                    )
+                );
+            }
+
+            if let Some(ref prefixes) = chan.command_prefixes {
+                validate_command_prefixes(
+                    format!(
+                        "servers: {}: channels: {}: command prefixes",
+                        server.name, chan.name
+                    ),
+                    prefixes,
+                )?;
+            }
+        }
+    }
+
+    for webhook in &cfg.http.webhooks {
+        let key = |field| format!("HTTP: webhooks: {}: {}", webhook.path, field);
+
+        ensure!(
+            webhook.path.starts_with('/'),
+            ErrorKind::Config(key("path"), "does not begin with `/`".into())
+        );
+        ensure!(
+            !webhook.token.is_empty(),
+            ErrorKind::Config(key("token"), "is empty".into())
+        );
+        ensure!(
+            !webhook.channels.is_empty(),
+            ErrorKind::Config(key("channels"), "is empty".into())
+        );
+
+        if let Some(ref name) = webhook.server {
+            ensure!(
+                cfg.servers.iter().any(|server| server.name == *name),
+                ErrorKind::Config(key("server"), "does not name a configured server".into())
+            );
+        }
+    }
+
+    for server in &cfg.servers {
+        ensure!(
+            server.nickname != Some(String::new()),
+            ErrorKind::Config(
+                format!("servers: {}: nickname", server.name),
+                "is empty".into()
+            )
+        );
+
+        if let Some(ref oper) = server.oper {
+            for &(key, value) in &[("name", &oper.name), ("password", &oper.password)] {
+                ensure!(
+                    !value.is_empty(),
+                    ErrorKind::Config(
+                        format!("servers: {}: oper: {}", server.name, key),
+                        "is empty".into()
+                    )
+                );
+            }
+        }
+
+        if let Some(ref webirc) = server.webirc {
+            for &(key, value) in &[("password", &webirc.password), ("gateway", &webirc.gateway)] {
+                ensure!(
+                    !value.is_empty() && !value.contains(' '),
+                    ErrorKind::Config(
+                        format!("servers: {}: WEBIRC: {}", server.name, key),
+                        "is empty or contains a space".into()
+                    )
+                );
+            }
+
+            if let Some(ref host) = webirc.host {
+                ensure!(
+                    !host.is_empty() && !host.contains(' '),
+                    ErrorKind::Config(
+                        format!("servers: {}: WEBIRC: host", server.name),
+                        "is empty or contains a space".into()
+                    )
+                );
+            }
+        }
+
+        for &(key, value) in &[
+            ("ping interval", server.ping_interval),
+            ("ping timeout", server.ping_timeout),
+        ] {
+            ensure!(
+                value != Some(0),
+                ErrorKind::Config(
+                    format!("servers: {}: {}", server.name, key),
+                    "is zero".into()
+                )
+            );
+        }
+
+        let tls_options_set = server.tls_ca_cert_path.is_some()
+            || server.tls_client_cert_path.is_some()
+            || server.tls_client_cert_password.is_some();
+
+        ensure!(
+            server.tls || !tls_options_set,
+            ErrorKind::Config(
+                format!("servers: {}: TLS", server.name),
+                "is `false`, but other TLS settings are specified for the server".into(),
+            )
+        );
+    }
+
+    for (i, server) in cfg.servers.iter().enumerate() {
+        ensure!(
+            cfg.servers[..i]
+                .iter()
+                .all(|other| other.name != server.name),
+            ErrorKind::Config(
+                format!("servers: {}", server.name),
+                "has the name of another server".into(),
+            )
+        );
+    }
+
+    Ok(())
+}
+
+fn validate_command_prefixes(key: String, prefixes: &[String]) -> Result<()> {
     ensure!(
-        cfg.servers.len() == 1,
+        prefixes
+            .iter()
+            .all(|prefix| !prefix.is_empty() && !prefix.contains(char::is_whitespace)),
         ErrorKind::Config(
-            "servers".into(),
-            "lists multiple servers, which is not yet supported".into(),
+            key,
+            "includes an empty string or one with whitespace".into()
         )
     );
 
@@ -481,6 +2547,20 @@ fn validate_config(cfg: &inner::Config) -> Result<()> {
 }
 
 fn fill_in_config_defaults(cfg: &mut inner::Config) -> Result<()> {
+    for server in &mut cfg.servers {
+        if server.nickname.is_none() {
+            server.nickname = Some(cfg.nickname.clone());
+        }
+
+        if server.username.is_none() {
+            server.username = if cfg.username.is_empty() {
+                server.nickname.clone()
+            } else {
+                Some(cfg.username.clone())
+            };
+        }
+    }
+
     if cfg.username.is_empty() {
         cfg.username = cfg.nickname.clone();
     }
@@ -489,9 +2569,383 @@ fn fill_in_config_defaults(cfg: &mut inner::Config) -> Result<()> {
         cfg.realname = pkg_info::BRIEF_CREDITS_STRING.clone();
     }
 
+    if cfg.ctcp_version.is_empty() {
+        cfg.ctcp_version = pkg_info::BRIEF_CREDITS_STRING.clone();
+    }
+
+    for server in &mut cfg.servers {
+        if server.realname.is_none() {
+            server.realname = Some(cfg.realname.clone());
+        }
+    }
+
     Ok(())
 }
 
+impl AdminAuth {
+    pub(super) fn session_length(&self) -> Duration {
+        Duration::from_secs(self.session_length.into())
+    }
+}
+
+impl Default for AdminAuth {
+    fn default() -> Self {
+        AdminAuth {
+            secret: None,
+            secret_file: None,
+            session_length: default_admin_session_length(),
+        }
+    }
+}
+
+impl ModuleLimits {
+    pub(super) fn run_time(&self) -> Option<Duration> {
+        self.run_time.map(|secs| Duration::from_secs(secs.into()))
+    }
+}
+
+impl ErrorNotifications {
+    pub(super) fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval.into())
+    }
+
+    pub(super) fn repeat_interval(&self) -> Duration {
+        Duration::from_secs(self.repeat_interval.into())
+    }
+}
+
+impl FloodProtection {
+    pub(super) fn period(&self) -> Duration {
+        Duration::from_secs(self.period.into())
+    }
+
+    pub(super) fn cooldown(&self) -> Duration {
+        Duration::from_secs(self.cooldown.into())
+    }
+}
+
+impl Default for FloodAction {
+    fn default() -> Self {
+        FloodAction::Nothing
+    }
+}
+
+impl Dcc {
+    pub(super) fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout.into())
+    }
+}
+
+impl Default for Dcc {
+    fn default() -> Self {
+        Dcc {
+            address: None,
+            timeout: default_dcc_timeout(),
+            passive: false,
+            send_directories: Vec::new(),
+            max_send_size: default_dcc_max_send_size(),
+        }
+    }
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Outbox {
+            capacity: default_outbox_capacity(),
+            overflow_policy: Default::default(),
+        }
+    }
+}
+
+impl Default for ChannelLogs {
+    fn default() -> Self {
+        ChannelLogs {
+            directory: None,
+            format: Default::default(),
+            keep_days: None,
+            buffer_size: default_channel_log_buffer_size(),
+        }
+    }
+}
+
+impl Default for ChannelLogFormat {
+    fn default() -> Self {
+        ChannelLogFormat::Text
+    }
+}
+
+impl Default for OutboxOverflowPolicy {
+    fn default() -> Self {
+        OutboxOverflowPolicy::DropNewest
+    }
+}
+
+impl Default for InvitePolicy {
+    fn default() -> Self {
+        InvitePolicy::Admins
+    }
+}
+
+fn default_rejoin_delay() -> u16 {
+    5
+}
+
+fn default_locale() -> String {
+    super::i18n::DEFAULT_LOCALE.into()
+}
+
+fn default_console() -> bool {
+    true
+}
+
+fn default_outbox_capacity() -> usize {
+    1024
+}
+
+fn default_channel_log_buffer_size() -> usize {
+    100
+}
+
+fn default_admin_session_length() -> u32 {
+    60 * 60
+}
+
+fn default_flood_messages() -> usize {
+    5
+}
+
+fn default_flood_repeats() -> usize {
+    3
+}
+
+fn default_error_notification_severity() -> Severity {
+    Severity::Major
+}
+
+fn default_error_notification_interval() -> u32 {
+    10
+}
+
+fn default_error_notification_repeat_interval() -> u32 {
+    600
+}
+
+fn default_flood_period() -> u16 {
+    10
+}
+
+fn default_flood_cooldown() -> u16 {
+    60
+}
+
+fn default_paste_lines() -> usize {
+    10
+}
+
+fn default_paste_service() -> String {
+    "bot".into()
+}
+
+fn default_paste_field() -> String {
+    "content".into()
+}
+
+fn default_dcc_timeout() -> u16 {
+    120
+}
+
+fn default_dcc_max_send_size() -> u64 {
+    16 * 1024 * 1024
+}
+
 fn mk_true() -> bool {
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats() {
+        let yaml = "nickname: bot\nservers:\n  - name: net\n    host: irc.example.net\n    \
+                    port: 6697\n    channels:\n      - name: '#bot'\n";
+        let toml =
+            "nickname = \"bot\"\n\n[[servers]]\nname = \"net\"\nhost = \"irc.example.net\"\n\
+                    port = 6697\nchannels = [{name = \"#bot\"}]\n";
+        let json = r##"{"nickname": "bot", "servers": [{"name": "net", "host": "irc.example.net",
+                        "port": 6697, "channels": [{"name": "#bot"}]}]}"##;
+
+        for &(input, format) in &[
+            (yaml, ConfigFormat::Yaml),
+            (toml, ConfigFormat::Toml),
+            (json, ConfigFormat::Json),
+        ] {
+            let cfg = Config::try_from_str(input, format).unwrap();
+            assert_eq!(cfg.servers[0].nickname, Some("bot".into()));
+            assert_eq!(cfg.servers[0].port, 6697);
+            assert_eq!(&*cfg.servers[0].channels[0].name, "#bot");
+        }
+
+        for &(input, format, needle) in &[
+            (
+                "nickname: bot\nservers: [{name: 1}]\n",
+                ConfigFormat::Yaml,
+                "servers[0]",
+            ),
+            (
+                "nickname = \"bot\"\n[[servers]]\nname = 1\n",
+                ConfigFormat::Toml,
+                "servers.name",
+            ),
+            (
+                "nickname = \"bot\"\nservers = [\n",
+                ConfigFormat::Toml,
+                "line 3",
+            ),
+        ] {
+            match *Config::try_from_str(input, format).unwrap_err().kind() {
+                ErrorKind::ConfigSyntax(_, ref problem) => {
+                    assert!(problem.contains(needle), "{}", problem)
+                }
+                ref kind => panic!("unexpected error: {}", kind),
+            }
+        }
+
+        assert_eq!(ConfigFormat::from_path("bot.TOML"), ConfigFormat::Toml);
+        assert_eq!(ConfigFormat::from_path("bot.json"), ConfigFormat::Json);
+        assert_eq!(ConfigFormat::from_path("bot.yaml"), ConfigFormat::Yaml);
+    }
+
+    #[test]
+    fn builder() {
+        let cfg = Config::builder()
+            .nick("bot")
+            .admin("owner")
+            .server("net", "irc.example.net", 6667)
+            .tls(false)
+            .channel("#bot")
+            .build()
+            .unwrap();
+
+        assert_eq!(cfg.servers[0].username, Some("bot".into()));
+        assert_eq!(cfg.admins[0].nick, Some("owner".into()));
+        assert!(!cfg.servers[0].tls);
+        assert_eq!(&*cfg.servers[0].channels[0].name, "#bot");
+        assert_eq!(cfg.rejoin_delay, Duration::from_secs(5));
+
+        assert!(Config::builder()
+            .nick("bot")
+            .channel("#bot")
+            .build()
+            .is_err());
+        assert!(Config::builder()
+            .nick("bot")
+            .server("net", "irc.example.net", 6697)
+            .channel("bot")
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn secrets() {
+        env::set_var("IRC_BOT_TEST_SECRET", "hunter2");
+
+        assert_eq!(
+            expand_env_vars("k", "a${IRC_BOT_TEST_SECRET}b$$c$").unwrap(),
+            "ahunter2b$c$"
+        );
+        assert!(expand_env_vars("k", "${IRC_BOT_TEST_UNSET_VARIABLE}").is_err());
+        assert!(expand_env_vars("k", "${IRC_BOT_TEST_SECRET").is_err());
+
+        let path = env::temp_dir().join(format!("irc-bot-test-secret-{}", ::std::process::id()));
+        ::std::fs::write(&path, "s3cret\n").unwrap();
+
+        let mut secret = None;
+        resolve_secret("k", &mut secret, &Some(path.clone())).unwrap();
+        assert_eq!(secret, Some("s3cret".into()));
+
+        let mut secret = Some("x".into());
+        assert!(resolve_secret("k", &mut secret, &Some(path.clone())).is_err());
+
+        ::std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn includes() {
+        let dir = env::temp_dir().join(format!("irc-bot-test-include-{}", ::std::process::id()));
+        fs::create_dir_all(dir.join("servers.d")).unwrap();
+
+        fs::write(
+            dir.join("bot.toml"),
+            "include = [\"servers.d/*.toml\", \"admins.yaml\"]\nnickname = \"bot\"\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("servers.d/net.toml"),
+            "nickname = \"fragment\"\n\n[[servers]]\nname = \"net\"\nhost = \"irc.example.net\"\n\
+             port = 6697\n",
+        )
+        .unwrap();
+        fs::write(dir.join("servers.d/ignored.yaml"), "nickname: ignored\n").unwrap();
+        fs::write(
+            dir.join("admins.yaml"),
+            "admins: [{nick: owner}]\nrejoin on kick: true\n",
+        )
+        .unwrap();
+
+        let cfg = Config::try_from_path(dir.join("bot.toml")).unwrap();
+
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(cfg.servers[0].nickname, Some("bot".into()));
+        assert_eq!(cfg.servers[0].name, "net");
+        assert_eq!(cfg.admins[0].nick, Some("owner".into()));
+        assert!(cfg.rejoin_on_kick);
+    }
+
+    #[test]
+    fn per_server_identity() {
+        let cfg = read_config(
+            "nickname: bot\nrealname: A bot\nservers:\n\
+             - {name: a, host: irc.a.example, port: 6697}\n\
+             - {name: b, host: irc.b.example, port: 6697, nickname: bee, realname: A bee}\n",
+        )
+        .unwrap();
+
+        let identity = |server: &Server| {
+            (
+                server.nickname.clone().unwrap(),
+                server.username.clone().unwrap(),
+                server.realname.clone().unwrap(),
+            )
+        };
+        assert_eq!(
+            identity(&cfg.servers[0]),
+            ("bot".into(), "bot".into(), "A bot".into())
+        );
+        assert_eq!(
+            identity(&cfg.servers[1]),
+            ("bee".into(), "bee".into(), "A bee".into())
+        );
+    }
+
+    #[test]
+    fn multiple_servers() {
+        let cfg = read_config(
+            "nickname: bot\nservers:\n\
+             - {name: a, host: irc.a.example, port: 6697}\n\
+             - {name: b, host: irc.b.example, port: 6697}\n",
+        )
+        .unwrap();
+        assert_eq!(cfg.servers.len(), 2);
+        assert_eq!(cfg.aatxe_configs.len(), 2);
+
+        assert!(read_config(
+            "nickname: bot\nservers:\n\
+             - {name: a, host: irc.a.example, port: 6697}\n\
+             - {name: a, host: irc.b.example, port: 6697}\n",
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,159 @@
+//! An interactive console on the bot's standard input, for use when the bot is run in the
+//! foreground, e.g., while developing modules.
+//!
+//! Lines beginning with `/` are operator commands, such as `/join #channel` and
+//! `/msg nick text`, the same as those accepted on the control socket; `/help` lists them. Other
+//! lines are handled as though they had been sent to the bot in one-to-one messaging by an
+//! administrator of the bot, and the bot's replies are written to standard output, while any
+//! messages that the bot is told to send to channels or to users on IRC are sent there.
+
+use super::control;
+use super::dcc;
+use super::irc_comm;
+use super::irc_msgs::OwningMsgPrefix;
+use super::irc_send::OutboxPort;
+use super::MsgPrefix;
+use super::MsgTags;
+use super::Result;
+use super::ServerId;
+use super::State;
+use std::io;
+use std::io::BufRead;
+use std::io::Write;
+use std::sync::Arc;
+
+/// The nickname of the virtual user as whom the console's lines are handled, which, containing
+/// `*`, cannot be the nickname of any user on IRC
+const CONSOLE_NICK: &str = "*console*";
+
+const CONSOLE_USER: &str = "console";
+
+const CONSOLE_HOST: &str = "console.invalid";
+
+/// Returns whether the given message prefix is that of the console's virtual user, who is treated
+/// as an administrator of the bot.
+pub(super) fn is_console_prefix(prefix: MsgPrefix) -> bool {
+    prefix.nick == Some(CONSOLE_NICK)
+        && prefix.user == Some(CONSOLE_USER)
+        && prefix.host == Some(CONSOLE_HOST)
+}
+
+pub(super) fn console_main(state: Arc<State>) -> Result<()> {
+    let mut server_id = match state.servers.keys().next() {
+        Some(&id) => id,
+        None => return Ok(()),
+    };
+
+    let prefix = OwningMsgPrefix::from_string(format!(
+        "{}!{}@{}",
+        CONSOLE_NICK, CONSOLE_USER, CONSOLE_HOST
+    ));
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+
+    info!("Accepting commands on standard input; try `/help`.");
+
+    for line in stdin.lock().lines() {
+        let line = line?;
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        let mut stdout = stdout.lock();
+
+        if line.starts_with('/') {
+            let mut output = Vec::new();
+
+            match control::run_command(&state, &mut server_id, &line[1..], &mut output) {
+                Ok(()) => {
+                    for line in output {
+                        writeln!(stdout, "{}", line)?;
+                    }
+                }
+                Err(e) => writeln!(stdout, "Error: {}", e)?,
+            }
+
+            continue;
+        }
+
+        handle_line(&state, server_id, &state.outbox, &prefix, line, &mut stdout)?;
+    }
+
+    Ok(())
+}
+
+/// Handles a line of the console's input that is not an operator command, as though the console's
+/// virtual user, with the given message prefix, had sent it to the bot in one-to-one messaging,
+/// writing the bot's replies to that user to the given writer and sending the rest of the bot's
+/// reaction to the IRC server.
+fn handle_line<W>(
+    state: &Arc<State>,
+    server_id: ServerId,
+    outbox: &OutboxPort,
+    prefix: &OwningMsgPrefix,
+    line: &str,
+    writer: &mut W,
+) -> Result<()>
+where
+    W: Write,
+{
+    let reaction = irc_comm::handle_bot_command_or_trigger(
+        state,
+        server_id,
+        outbox,
+        prefix.clone(),
+        &MsgTags::new(),
+        state.nick(server_id)?,
+        line.to_owned(),
+        false,
+        true,
+    );
+
+    match reaction {
+        Some(reaction) => {
+            dcc::forward_reaction(writer, state, outbox, server_id, CONSOLE_NICK, reaction)
+        }
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::irc_send::mk_outbox;
+    use core::offline::OfflineBot;
+    use modules;
+
+    #[test]
+    fn replies_and_other_messages() {
+        let bot = OfflineBot::for_test("", vec![modules::default, modules::admin]);
+        let state = bot.state();
+        let server_id = state.server_ids()[0];
+        let (outbox, outbox_receiver) = mk_outbox(&state.config().outbox);
+        let prefix = OwningMsgPrefix::from_string(format!(
+            "{}!{}@{}",
+            CONSOLE_NICK, CONSOLE_USER, CONSOLE_HOST
+        ));
+        let mut written = Vec::new();
+
+        handle_line(state, server_id, &outbox, &prefix, "ping", &mut written).unwrap();
+        handle_line(
+            state,
+            server_id,
+            &outbox,
+            &prefix,
+            "raw 'PRIVMSG #rust :Hello, channel'",
+            &mut written,
+        )
+        .unwrap();
+
+        assert_eq!(String::from_utf8(written).unwrap(), "pong\n");
+
+        let record = outbox_receiver.try_recv().unwrap();
+        assert!(format!("{:?}", record).contains("Hello, channel"));
+        assert!(outbox_receiver.try_recv().is_none());
+    }
+}
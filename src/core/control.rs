@@ -0,0 +1,151 @@
+use super::irc_send::push_to_outbox;
+use super::ErrorKind;
+use super::LibReaction;
+use super::Result;
+use super::ServerId;
+use super::State;
+use irc::client::prelude as aatxe;
+
+/// The commands that operators may give through the control socket or the console
+const HELP: &[&str] = &[
+    "server NAME            select the server to which later commands apply",
+    "servers                list the configured servers",
+    "modules                list the loaded modules",
+    "unload MODULE          unload MODULE and the commands and triggers that it provides",
+    "say TARGET TEXT        send TEXT to TARGET by PRIVMSG",
+    "join CHANNEL [KEY]     join CHANNEL",
+    "part CHANNEL [MSG]     part CHANNEL",
+    "raw LINE               send LINE to the server verbatim",
+    "reconnect [MSG]        reconnect to the server",
+    "reload                 reload the configuration file",
+    "quit [MSG]             shut the bot down",
+];
+
+/// Runs the given operator's command, which applies to the given server unless it selects another
+/// one, adding to `output` any lines that the command outputs.
+pub(super) fn run_command(
+    state: &State,
+    server_id: &mut ServerId,
+    line: &str,
+    output: &mut Vec<String>,
+) -> Result<()> {
+    let mut words = line.splitn(2, char::is_whitespace);
+    let cmd = words.next().unwrap_or_default();
+    let args = words.next().unwrap_or_default().trim();
+
+    let mut args_2 = args.splitn(2, char::is_whitespace);
+    let (arg_1, rest) = (
+        args_2.next().unwrap_or_default(),
+        args_2.next().map(str::trim).filter(|s| !s.is_empty()),
+    );
+
+    let syntax_err = || ErrorKind::ControlCommandSyntax(cmd.to_owned());
+
+    match cmd {
+        "help" => output.extend(HELP.iter().map(|&s| s.to_owned())),
+        "server" => {
+            *server_id = state
+                .server_named(args)
+                .ok_or_else(|| ErrorKind::UnknownServerName(args.to_owned()))?;
+        }
+        "servers" => {
+            for &id in state.servers.keys() {
+                let name = &state.get_server_config(id)?.name;
+                let marker = if id == *server_id { "*" } else { " " };
+                output.push(format!("{} {}", marker, name));
+            }
+        }
+        "modules" => output.extend(state.module_names()),
+        "unload" if !args.is_empty() => state.unload_module(args)?,
+        "say" | "msg" => match rest {
+            Some(text) if !arg_1.is_empty() => state.send_privmsg(*server_id, arg_1, text)?,
+            _ => bail!(syntax_err()),
+        },
+        "join" if !arg_1.is_empty() => push_to_outbox(
+            &state.outbox,
+            *server_id,
+            LibReaction::RawMsg(
+                aatxe::Command::JOIN(arg_1.to_owned(), rest.map(ToOwned::to_owned), None).into(),
+            ),
+        ),
+        "part" if !arg_1.is_empty() => push_to_outbox(
+            &state.outbox,
+            *server_id,
+            LibReaction::RawMsg(
+                aatxe::Command::PART(arg_1.to_owned(), rest.map(ToOwned::to_owned)).into(),
+            ),
+        ),
+        "raw" if !args.is_empty() => state.send_raw_msg(*server_id, args)?,
+        "reconnect" => state.reconnect(
+            *server_id,
+            if args.is_empty() {
+                None
+            } else {
+                Some(args.to_owned().into())
+            },
+        )?,
+        "reload" => state.reload_config()?,
+        "quit" => state.shutdown(if args.is_empty() {
+            None
+        } else {
+            Some(args.to_owned().into())
+        })?,
+        "unload" | "join" | "part" | "raw" => bail!(syntax_err()),
+        _ => bail!(ErrorKind::UnknownControlCommand(cmd.to_owned())),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::offline::OfflineBot;
+    use modules;
+
+    #[test]
+    fn unload() {
+        let bot = OfflineBot::for_test("", vec![modules::default, modules::admin]);
+        let state = bot.state();
+        let mut server_id = state.server_ids()[0];
+        let mut output = Vec::new();
+
+        run_command(state, &mut server_id, "unload admin", &mut output).unwrap();
+        assert!(!state.module_loaded("admin"));
+        assert!(state.module_loaded("default"));
+
+        run_command(state, &mut server_id, "modules", &mut output).unwrap();
+        assert_eq!(output, vec!["default".to_owned()]);
+
+        assert!(run_command(state, &mut server_id, "unload admin", &mut output).is_err());
+        assert!(run_command(state, &mut server_id, "unload", &mut output).is_err());
+    }
+
+    #[test]
+    fn reconnect() {
+        let bot = OfflineBot::for_test(
+            "servers:\n  \
+             - {name: a, host: irc.a.invalid, port: 6697}\n  \
+             - {name: b, host: irc.b.invalid, port: 6697}\n",
+            vec![modules::default],
+        );
+        let state = bot.state();
+        let (a, b) = (state.server_ids()[0], state.server_ids()[1]);
+        let mut server_id = a;
+        let mut output = Vec::new();
+
+        match *run_command(state, &mut server_id, "server c", &mut output)
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::UnknownServerName(ref name) => assert_eq!(name, "c"),
+            ref kind => panic!("Unexpected error: {}", kind),
+        }
+        assert_eq!(server_id, a);
+
+        run_command(state, &mut server_id, "server b", &mut output).unwrap();
+        run_command(state, &mut server_id, "reconnect", &mut output).unwrap();
+        assert!(!state.read_server(a).unwrap().reconnect_requested);
+        assert!(state.read_server(b).unwrap().reconnect_requested);
+    }
+}
@@ -0,0 +1,172 @@
+use super::control;
+use super::Result;
+use super::State;
+use std::fs;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::process;
+use std::sync::Arc;
+use std::thread;
+
+/// Listens for connections to the control socket at the given path, handling each connection in
+/// a new thread.
+pub(super) fn control_socket_main(state: Arc<State>, path: &Path) -> Result<()> {
+    let listener = bind(path)?;
+
+    info!("Listening for control connections at {}.", path.display());
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Failed to accept control connection: {}", e);
+                continue;
+            }
+        };
+
+        let state = state.clone();
+
+        let spawn_result = thread::Builder::new()
+            .name("control".into())
+            .spawn(move || {
+                if let Err(e) = handle_connection(&state, stream) {
+                    debug!("Error handling control connection: {}", e);
+                }
+            });
+
+        if let Err(e) = spawn_result {
+            error!("Failed to spawn thread to handle control connection: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Binds the control socket at the given path, replacing any socket left there by an earlier run,
+/// but failing if anything other than a socket is there.
+///
+/// As anyone who can connect to the socket controls the bot, the socket is bound in a new
+/// directory that only the bot's user can enter, made beside the given path, and moved to the
+/// given path only once it has been made accessible to the bot's user alone.
+fn bind(path: &Path) -> Result<UnixListener> {
+    match fs::symlink_metadata(path) {
+        Ok(ref metadata) if metadata.file_type().is_socket() => {}
+        Ok(_) => {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "Refusing to replace {}, which is not a socket, with the control socket.",
+                    path.display()
+                ),
+            )
+            .into())
+        }
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("The control socket path {} names no file.", path.display()),
+        )
+    })?;
+
+    let private_dir = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        process::id()
+    ));
+
+    fs::DirBuilder::new().mode(0o700).create(&private_dir)?;
+
+    let private_path = private_dir.join(file_name);
+
+    let bound = UnixListener::bind(&private_path).and_then(|listener| {
+        fs::set_permissions(&private_path, fs::Permissions::from_mode(0o600))?;
+        fs::rename(&private_path, path)?;
+        Ok(listener)
+    });
+
+    if let Err(e) = fs::remove_dir_all(&private_dir) {
+        warn!(
+            "Failed to remove directory {} made to bind the control socket: {}",
+            private_dir.display(),
+            e
+        );
+    }
+
+    Ok(bound?)
+}
+
+/// Runs the commands sent over a control connection, one per line, answering each with any lines
+/// of output, followed by a line reading `ok` or `error: ` and a description of the error.
+fn handle_connection(state: &State, stream: UnixStream) -> Result<()> {
+    let mut writer = stream.try_clone()?;
+
+    let mut server_id = match state.servers.keys().next() {
+        Some(&id) => id,
+        None => return Ok(()),
+    };
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let line = line.trim();
+
+        if line.is_empty() {
+            continue;
+        }
+
+        info!("Control command: {:?}", line);
+
+        let mut output = Vec::new();
+
+        match control::run_command(state, &mut server_id, line, &mut output) {
+            Ok(()) => {
+                for line in output {
+                    writeln!(writer, "{}", line)?;
+                }
+                writeln!(writer, "ok")?;
+            }
+            Err(e) => writeln!(writer, "error: {}", e)?,
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn bind_only_over_sockets() {
+        let dir = env::temp_dir().join(format!("irc-bot-control-test.{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("control");
+
+        fs::write(&path, "not a socket").unwrap();
+        assert!(bind(&path).is_err());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "not a socket");
+        fs::remove_file(&path).unwrap();
+
+        for _ in 0..2 {
+            drop(bind(&path).unwrap());
+
+            let metadata = fs::symlink_metadata(&path).unwrap();
+            assert!(metadata.file_type().is_socket());
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
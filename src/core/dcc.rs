@@ -0,0 +1,817 @@
+//! Support for the [Direct Client-to-Client (DCC)][DCC] protocol, by which the bot can hold
+//! conversations with users over direct TCP connections rather than through the IRC server.
+//!
+//! A DCC CHAT session with the bot works as an out-of-band console: each line sent to the bot is
+//! handled as though it had been sent to the bot in one-to-one messaging over IRC, and the bot's
+//! replies are sent back over the DCC connection. Only administrators of the bot may open DCC
+//! CHAT sessions with it.
+//!
+//! Bot modules may also offer files to users by DCC SEND, using either ordinary DCC, in which the
+//! user connects to the bot, or passive (a.k.a. reverse) DCC, in which the bot connects to the
+//! user. The bot does not accept files.
+//!
+//! When the user is to connect to the bot, the bot accepts a connection only from an address of
+//! the user's host, as the IRC server reports it, lest someone else who finds the bot's listening
+//! port take the user's place. If the user's host has no known address, as when the server hides
+//! it, the bot offers files by passive DCC instead, and does not offer DCC CHAT sessions.
+//!
+//! [DCC]: <https://modern.ircdocs.horse/dcc.html>
+
+use super::irc_comm;
+use super::irc_msgs::Ctcp;
+use super::irc_msgs::OwningMsgPrefix;
+use super::irc_send::push_to_outbox;
+use super::irc_send::OutboxPort;
+use super::spawn_thread;
+use super::ErrorKind;
+use super::LibReaction;
+use super::MsgTags;
+use super::Result;
+use super::ServerId;
+use super::State;
+use irc::client::prelude as aatxe;
+use irc::proto::Message;
+use rand::Rng;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::Shutdown;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::MutexGuard;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+const CHAT_GREETING: &str = "DCC CHAT session established. Lines sent here will be handled as if \
+                             they had been sent to me in one-to-one messaging.";
+
+/// How long to sleep between checks for an incoming connection when waiting for a user to accept
+/// a DCC offer.
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// A DCC request, as carried by a CTCP `DCC` message.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(super) enum DccRequest<'a> {
+    /// An offer of a DCC CHAT session, to be accepted by connecting to the given address.
+    Chat { addr: SocketAddr },
+
+    /// An offer of a file, to be accepted by connecting to the given address, or, if the address
+    /// is `None`, an offer of a file by passive DCC. If a token is given with an address, this is
+    /// a response to an offer of a file by passive DCC, identified by the token.
+    Send {
+        filename: &'a str,
+        addr: Option<SocketAddr>,
+        size: Option<u64>,
+        token: Option<&'a str>,
+    },
+
+    /// A DCC request of a type that the bot does not support.
+    Unsupported { kind: &'a str },
+}
+
+impl<'a> DccRequest<'a> {
+    /// Parses the parameters of a CTCP `DCC` message, such as `CHAT chat 2130706433 5000`.
+    pub(super) fn parse(params: &'a str) -> Option<Self> {
+        let params = params.trim_start();
+        let kind_end = params.find(' ').unwrap_or(params.len());
+        let kind = &params[..kind_end];
+
+        let is_chat = kind.eq_ignore_ascii_case("CHAT");
+        let is_send = kind.eq_ignore_ascii_case("SEND");
+
+        if kind.is_empty() {
+            return None;
+        } else if !is_chat && !is_send {
+            return Some(DccRequest::Unsupported { kind });
+        }
+
+        // The second parameter is the name of the file being offered, which may be quoted if it
+        // contains spaces, or, for DCC CHAT, conventionally `chat`, which is ignored.
+        let (arg, rest) = split_dcc_filename(params[kind_end..].trim_start())?;
+        let mut rest = rest.split_whitespace();
+        let ip = parse_dcc_ip_addr(rest.next()?)?;
+        let port = rest.next()?.parse().ok()?;
+
+        if is_chat {
+            return Some(DccRequest::Chat {
+                addr: SocketAddr::new(ip, port),
+            });
+        }
+
+        let size = match rest.next() {
+            Some(s) => Some(s.parse().ok()?),
+            None => None,
+        };
+
+        Some(DccRequest::Send {
+            filename: arg,
+            addr: if port == 0 {
+                None
+            } else {
+                Some(SocketAddr::new(ip, port))
+            },
+            size,
+            token: rest.next(),
+        })
+    }
+}
+
+/// Splits a file name, which may be enclosed in quotation marks, from the start of the given
+/// string, returning the file name and the rest of the string.
+fn split_dcc_filename(s: &str) -> Option<(&str, &str)> {
+    if s.starts_with('"') {
+        let end = s[1..].find('"')? + 1;
+        Some((&s[1..end], &s[end + 1..]))
+    } else {
+        let end = s.find(' ').unwrap_or(s.len());
+        if end == 0 {
+            return None;
+        }
+        Some((&s[..end], &s[end..]))
+    }
+}
+
+/// An offer of a file by passive DCC, awaiting the recipient's response.
+#[derive(Debug)]
+pub(super) struct PendingSend {
+    path: PathBuf,
+    peer_nick: String,
+    deadline: Instant,
+}
+
+impl State {
+    fn lock_dcc_pending_sends<'a>(
+        &'a self,
+    ) -> Result<MutexGuard<'a, BTreeMap<String, PendingSend>>> {
+        self.dcc_pending_sends.lock().map_err(|_| {
+            ErrorKind::LockPoisoned("the pending passive DCC file offers".into()).into()
+        })
+    }
+}
+
+/// Parses an IP address as given in a DCC request, where an IPv4 address is conventionally given
+/// as a decimal integer and an IPv6 address in its usual textual form.
+fn parse_dcc_ip_addr(s: &str) -> Option<IpAddr> {
+    match s.parse::<u32>() {
+        Ok(n) => Some(IpAddr::V4(Ipv4Addr::from(n))),
+        Err(_) => s.parse().ok(),
+    }
+}
+
+/// Formats an IP address for inclusion in a DCC request (see `parse_dcc_ip_addr`).
+fn fmt_dcc_ip_addr(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(v4) => u32::from(v4).to_string(),
+        IpAddr::V6(v6) => v6.to_string(),
+    }
+}
+
+/// Returns the IP addresses of the host of the user with the given message prefix, as resolved
+/// from the host name that the IRC server reports for the user, or an empty list if the host is
+/// unknown or cloaked, so that it does not resolve.
+fn peer_ips(peer: &OwningMsgPrefix) -> Vec<IpAddr> {
+    let host = match peer.parse().host {
+        Some(host) => host,
+        None => return Vec::new(),
+    };
+
+    if let Ok(ip) = host.parse() {
+        return vec![ip];
+    }
+
+    match (host, 0).to_socket_addrs() {
+        Ok(addrs) => addrs.map(|addr| addr.ip()).collect(),
+        Err(e) => {
+            debug!("Failed to resolve host {:?} of DCC peer: {}", host, e);
+            Vec::new()
+        }
+    }
+}
+
+/// Handles a CTCP `DCC` message sent to the bot.
+pub(super) fn handle_dcc_ctcp(
+    state: &Arc<State>,
+    server_id: ServerId,
+    outbox: &OutboxPort,
+    prefix: OwningMsgPrefix,
+    params: &str,
+) -> Result<()> {
+    match DccRequest::parse(params) {
+        Some(DccRequest::Chat { addr }) => accept_chat(state, server_id, outbox, prefix, addr),
+        Some(DccRequest::Send {
+            addr: Some(addr),
+            token: Some(token),
+            ..
+        }) => accept_passive_send(state, server_id, prefix, addr, token),
+        Some(DccRequest::Send { filename, .. }) => {
+            info!(
+                "Ignoring offer of file {:?} from {:?}; the bot does not accept files.",
+                filename,
+                prefix.parse().nick,
+            );
+            Ok(())
+        }
+        Some(DccRequest::Unsupported { kind }) => {
+            debug!(
+                "Ignoring DCC request of unsupported type {:?} from {:?}.",
+                kind,
+                prefix.parse().nick,
+            );
+            Ok(())
+        }
+        None => {
+            debug!(
+                "Ignoring malformed DCC request {:?} from {:?}.",
+                params,
+                prefix.parse().nick,
+            );
+            Ok(())
+        }
+    }
+}
+
+fn accept_chat(
+    state: &Arc<State>,
+    server_id: ServerId,
+    outbox: &OutboxPort,
+    peer: OwningMsgPrefix,
+    addr: SocketAddr,
+) -> Result<()> {
+    if !state.have_admin(server_id, peer.parse())? {
+        info!(
+            "Ignoring DCC CHAT offer from {:?}, who is not an administrator of the bot.",
+            peer.parse()
+        );
+        return Ok(());
+    }
+
+    let timeout = state.config().dcc.timeout();
+    let outbox = outbox.clone();
+
+    spawn_thread(
+        state,
+        addr.to_string(),
+        "dcc-chat",
+        |addr| format!("DCC CHAT session thread for {}", addr),
+        move |state| {
+            let stream = TcpStream::connect_timeout(&addr, timeout)?;
+            run_chat_session(&state, server_id, &outbox, peer, stream)
+        },
+    );
+
+    Ok(())
+}
+
+/// Offers a DCC CHAT session to the user with the given message prefix, returning the CTCP
+/// message that makes the offer, which is to be sent to the user.
+pub(super) fn offer_chat(
+    state: &Arc<State>,
+    server_id: ServerId,
+    outbox: &OutboxPort,
+    peer: OwningMsgPrefix,
+) -> Result<Option<LibReaction<Message>>> {
+    let peer_nick = peer
+        .parse()
+        .nick
+        .ok_or(ErrorKind::ReceivedMsgHasBadPrefix)?
+        .to_owned();
+
+    let local_ip = state.config().dcc.address.ok_or_else(|| {
+        ErrorKind::Config(
+            "DCC: address".into(),
+            "is not set, but it is needed to offer DCC sessions".into(),
+        )
+    })?;
+
+    let peer_ips = peer_ips(&peer);
+
+    if peer_ips.is_empty() {
+        return Err(ErrorKind::DccPeerUnverifiable(peer_nick).into());
+    }
+
+    let listener = TcpListener::bind((local_ip, 0))?;
+    let local_port = listener.local_addr()?.port();
+    let timeout = state.config().dcc.timeout();
+    let outbox = outbox.clone();
+
+    spawn_thread(
+        state,
+        format!("{}:{}", local_ip, local_port),
+        "dcc-chat",
+        |addr| format!("DCC CHAT session thread for {}", addr),
+        move |state| {
+            let stream = accept_with_timeout(&listener, &peer_ips, timeout)?;
+            run_chat_session(&state, server_id, &outbox, peer, stream)
+        },
+    );
+
+    let offer = format!("CHAT chat {} {}", fmt_dcc_ip_addr(local_ip), local_port);
+
+    Ok(Some(LibReaction::RawMsg(
+        aatxe::Command::PRIVMSG(
+            peer_nick,
+            Ctcp {
+                command: "DCC",
+                params: &offer,
+            }
+            .to_string(),
+        )
+        .into(),
+    )))
+}
+
+/// Offers the file at the given path to the user with the given message prefix, returning the CTCP
+/// message that makes the offer, which is to be sent to the user.
+pub(super) fn offer_file(
+    state: &Arc<State>,
+    peer: OwningMsgPrefix,
+    path: PathBuf,
+) -> Result<Option<LibReaction<Message>>> {
+    let peer_nick = peer
+        .parse()
+        .nick
+        .ok_or(ErrorKind::ReceivedMsgHasBadPrefix)?
+        .to_owned();
+
+    let (path, size) = check_file_offerable(state, &path)?;
+
+    let filename = match path.file_name() {
+        Some(s) => s.to_string_lossy().replace('"', "_"),
+        None => return Err(ErrorKind::DccFileRefused(path, "has no file name".into()).into()),
+    };
+    let filename = if filename.contains(' ') {
+        format!("\"{}\"", filename)
+    } else {
+        filename
+    };
+
+    let dcc_cfg = &state.config().dcc;
+    let timeout = dcc_cfg.timeout();
+    let peer_ips = if dcc_cfg.passive {
+        Vec::new()
+    } else {
+        peer_ips(&peer)
+    };
+
+    let offer = match dcc_cfg.address {
+        Some(local_ip) if !peer_ips.is_empty() => {
+            let listener = TcpListener::bind((local_ip, 0))?;
+            let local_port = listener.local_addr()?.port();
+
+            spawn_thread(
+                state,
+                format!("{}:{}", local_ip, local_port),
+                "dcc-send",
+                |addr| format!("DCC SEND thread for {}", addr),
+                move |_state| {
+                    let stream = accept_with_timeout(&listener, &peer_ips, timeout)?;
+                    send_file(stream, &path, timeout)
+                },
+            );
+
+            format!(
+                "SEND {} {} {} {}",
+                filename,
+                fmt_dcc_ip_addr(local_ip),
+                local_port,
+                size
+            )
+        }
+        _ => {
+            let token = state.rng()?.gen::<u32>().to_string();
+
+            let mut pending_sends = state.lock_dcc_pending_sends()?;
+            let now = Instant::now();
+            pending_sends.retain(|_, pending| pending.deadline > now);
+            pending_sends.insert(
+                token.clone(),
+                PendingSend {
+                    path,
+                    peer_nick: peer_nick.clone(),
+                    deadline: now + timeout,
+                },
+            );
+
+            format!("SEND {} 0 0 {} {}", filename, size, token)
+        }
+    };
+
+    info!("Offering file to {:?} by DCC: {:?}", peer_nick, offer);
+
+    Ok(Some(LibReaction::RawMsg(
+        aatxe::Command::PRIVMSG(
+            peer_nick,
+            Ctcp {
+                command: "DCC",
+                params: &offer,
+            }
+            .to_string(),
+        )
+        .into(),
+    )))
+}
+
+/// Checks that the file at the given path may be offered by DCC SEND, returning its canonical path
+/// and its size in bytes.
+fn check_file_offerable(state: &State, path: &Path) -> Result<(PathBuf, u64)> {
+    let refuse = |path: &Path, reason: &'static str| -> Result<(PathBuf, u64)> {
+        Err(ErrorKind::DccFileRefused(path.to_owned(), Cow::Borrowed(reason)).into())
+    };
+
+    let dcc_cfg = &state.config().dcc;
+    let path = path.canonicalize()?;
+
+    let whitelisted = dcc_cfg.send_directories.iter().any(|dir| {
+        dir.canonicalize()
+            .map(|dir| path.starts_with(dir))
+            .unwrap_or(false)
+    });
+
+    if !whitelisted {
+        return refuse(
+            &path,
+            "is not within any of the directories listed in the configuration setting \
+             `DCC: send directories`",
+        );
+    }
+
+    let metadata = fs::metadata(&path)?;
+
+    if !metadata.is_file() {
+        return refuse(&path, "is not a regular file");
+    }
+
+    if metadata.len() > dcc_cfg.max_send_size {
+        return refuse(
+            &path,
+            "is larger than is allowed by the configuration setting `DCC: max send size`",
+        );
+    }
+
+    Ok((path, metadata.len()))
+}
+
+/// Handles a user's response to an offer of a file by passive DCC.
+fn accept_passive_send(
+    state: &Arc<State>,
+    server_id: ServerId,
+    peer: OwningMsgPrefix,
+    addr: SocketAddr,
+    token: &str,
+) -> Result<()> {
+    let peer_nick = peer
+        .parse()
+        .nick
+        .ok_or(ErrorKind::ReceivedMsgHasBadPrefix)?;
+
+    let pending = {
+        let mut pending_sends = state.lock_dcc_pending_sends()?;
+
+        match pending_sends.get(token) {
+            Some(pending) if state.nicks_eq(server_id, &pending.peer_nick, peer_nick)? => {}
+            _ => {
+                debug!(
+                    "Ignoring response from {:?} to unknown passive DCC offer {:?}.",
+                    peer_nick, token
+                );
+                return Ok(());
+            }
+        }
+
+        match pending_sends.remove(token) {
+            Some(ref pending) if pending.deadline <= Instant::now() => {
+                debug!("Ignoring late response to passive DCC offer {:?}.", token);
+                return Ok(());
+            }
+            Some(pending) => pending,
+            None => return Ok(()),
+        }
+    };
+
+    let timeout = state.config().dcc.timeout();
+
+    spawn_thread(
+        state,
+        addr.to_string(),
+        "dcc-send",
+        |addr| format!("DCC SEND thread for {}", addr),
+        move |_state| {
+            let stream = TcpStream::connect_timeout(&addr, timeout)?;
+            send_file(stream, &pending.path, timeout)
+        },
+    );
+
+    Ok(())
+}
+
+/// Sends the file at the given path over the given connection, and then waits for the recipient to
+/// close the connection.
+fn send_file(stream: TcpStream, path: &Path, timeout: Duration) -> Result<()> {
+    let peer_addr = stream.peer_addr()?;
+    let mut file = File::open(path)?;
+
+    let bytes_sent = io::copy(&mut file, &mut &stream)?;
+    stream.shutdown(Shutdown::Write)?;
+
+    info!(
+        "Sent {} bytes of file {:?} by DCC to {}.",
+        bytes_sent,
+        path.display(),
+        peer_addr
+    );
+
+    // The recipient may acknowledge the data received, by sending the number of bytes received so
+    // far, and should then close the connection. These acknowledgements are of no use to us.
+    stream.set_read_timeout(Some(timeout))?;
+    match io::copy(&mut &stream, &mut io::sink()) {
+        Ok(_) => {}
+        Err(e) => debug!(
+            "Error while waiting for {} to close DCC connection: {}",
+            peer_addr, e
+        ),
+    }
+
+    Ok(())
+}
+
+/// Waits for a single incoming connection on the given listener from one of the given addresses,
+/// closing any connections from other addresses, and giving up after the given duration.
+fn accept_with_timeout(
+    listener: &TcpListener,
+    peer_ips: &[IpAddr],
+    timeout: Duration,
+) -> Result<TcpStream> {
+    let deadline = Instant::now() + timeout;
+
+    listener.set_nonblocking(true)?;
+
+    loop {
+        match listener.accept() {
+            Ok((_, addr)) if !peer_ips.contains(&addr.ip()) => warn!(
+                "Refused connection from {} in response to a DCC offer to a user at {:?}.",
+                addr, peer_ips
+            ),
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false)?;
+                return Ok(stream);
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock && Instant::now() < deadline => {
+                thread::sleep(ACCEPT_POLL_INTERVAL)
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "no connection was received in response to a DCC offer",
+                )
+                .into())
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+fn run_chat_session(
+    state: &Arc<State>,
+    server_id: ServerId,
+    outbox: &OutboxPort,
+    peer: OwningMsgPrefix,
+    stream: TcpStream,
+) -> Result<()> {
+    let peer_addr = stream.peer_addr()?;
+
+    info!(
+        "DCC CHAT session with {:?} at {} established.",
+        peer.parse(),
+        peer_addr
+    );
+
+    let mut writer = stream.try_clone()?;
+
+    writeln!(writer, "{}", CHAT_GREETING)?;
+
+    for line in BufReader::new(stream).lines() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (text, is_action) = match Ctcp::parse(line) {
+            Some(ref ctcp) if ctcp.is_action() => (ctcp.params, true),
+            _ => (line, false),
+        };
+
+        // Treat the line as having been sent to the bot in one-to-one messaging.
+        let reaction = irc_comm::handle_bot_command_or_trigger(
+            state,
+            server_id,
+            outbox,
+            peer.clone(),
+            &MsgTags::new(),
+            state.nick(server_id)?,
+            text.to_owned(),
+            is_action,
+            true,
+        );
+
+        if let Some(reaction) = reaction {
+            forward_reaction(
+                &mut writer,
+                state,
+                outbox,
+                server_id,
+                peer_nick(&peer)?,
+                reaction,
+            )?;
+        }
+    }
+
+    info!(
+        "DCC CHAT session with {:?} at {} ended.",
+        peer.parse(),
+        peer_addr
+    );
+
+    Ok(())
+}
+
+fn peer_nick(peer: &OwningMsgPrefix) -> Result<&str> {
+    peer.parse()
+        .nick
+        .ok_or_else(|| ErrorKind::ReceivedMsgHasBadPrefix.into())
+}
+
+/// Writes to the given writer the text of each `PRIVMSG` in the given reaction that is addressed to
+/// the user with the given nickname, as the bot's replies to that user are, and sends the rest of
+/// the reaction, such as messages to channels, to the IRC server.
+pub(super) fn forward_reaction<W>(
+    writer: &mut W,
+    state: &State,
+    outbox: &OutboxPort,
+    server_id: ServerId,
+    peer_nick: &str,
+    reaction: LibReaction<Message>,
+) -> Result<()>
+where
+    W: Write,
+{
+    match reaction {
+        LibReaction::RawMsg(Message {
+            command: aatxe::Command::PRIVMSG(ref target, ref text),
+            ..
+        }) if state.nicks_eq(server_id, target, peer_nick)? => writeln!(writer, "{}", text)?,
+        LibReaction::RawMsg(msg) => push_to_outbox(outbox, server_id, LibReaction::RawMsg(msg)),
+        LibReaction::Multi(reactions) => {
+            for reaction in reactions {
+                forward_reaction(writer, state, outbox, server_id, peer_nick, reaction)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::irc_send::mk_outbox;
+    use core::offline::OfflineBot;
+    use core::Module;
+
+    #[test]
+    fn dcc_chat_request_examples() {
+        assert_eq!(
+            DccRequest::parse("CHAT chat 2130706433 5000"),
+            Some(DccRequest::Chat {
+                addr: "127.0.0.1:5000".parse().unwrap(),
+            })
+        );
+        assert_eq!(
+            DccRequest::parse("CHAT chat ::1 5000"),
+            Some(DccRequest::Chat {
+                addr: "[::1]:5000".parse().unwrap(),
+            })
+        );
+        assert_eq!(
+            DccRequest::parse("RESUME file.txt 5000 0"),
+            Some(DccRequest::Unsupported { kind: "RESUME" })
+        );
+        assert_eq!(DccRequest::parse("CHAT chat 2130706433"), None);
+        assert_eq!(
+            DccRequest::parse("FOO"),
+            Some(DccRequest::Unsupported { kind: "FOO" })
+        );
+        assert_eq!(DccRequest::parse("CHAT chat localhost 5000"), None);
+        assert_eq!(DccRequest::parse(""), None);
+    }
+
+    #[test]
+    fn dcc_send_request_examples() {
+        assert_eq!(
+            DccRequest::parse("SEND log.txt 2130706433 5000 1024"),
+            Some(DccRequest::Send {
+                filename: "log.txt",
+                addr: Some("127.0.0.1:5000".parse().unwrap()),
+                size: Some(1024),
+                token: None,
+            })
+        );
+        assert_eq!(
+            DccRequest::parse("SEND \"my log.txt\" 2130706433 0 1024 42"),
+            Some(DccRequest::Send {
+                filename: "my log.txt",
+                addr: None,
+                size: Some(1024),
+                token: Some("42"),
+            })
+        );
+        assert_eq!(
+            DccRequest::parse("SEND log.txt 2130706433 5000 1024 42"),
+            Some(DccRequest::Send {
+                filename: "log.txt",
+                addr: Some("127.0.0.1:5000".parse().unwrap()),
+                size: Some(1024),
+                token: Some("42"),
+            })
+        );
+        assert_eq!(DccRequest::parse("SEND \"log.txt 2130706433 5000"), None);
+        assert_eq!(DccRequest::parse("SEND log.txt 2130706433 5000 big"), None);
+    }
+
+    #[test]
+    fn dcc_ip_addr_round_trip() {
+        for s in &["127.0.0.1", "203.0.113.7", "2001:db8::1"] {
+            let addr = s.parse().unwrap();
+            assert_eq!(parse_dcc_ip_addr(&fmt_dcc_ip_addr(addr)), Some(addr));
+        }
+        assert_eq!(fmt_dcc_ip_addr("127.0.0.1".parse().unwrap()), "2130706433");
+    }
+
+    #[test]
+    fn accept_only_from_peer() {
+        let timeout = Duration::from_secs(1);
+        let localhost = "127.0.0.1".parse().unwrap();
+        let elsewhere = "192.0.2.1".parse().unwrap();
+
+        let listener = TcpListener::bind((localhost, 0)).unwrap();
+        let _stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        assert!(accept_with_timeout(&listener, &[elsewhere], timeout).is_err());
+
+        let listener = TcpListener::bind((localhost, 0)).unwrap();
+        let _stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        assert!(accept_with_timeout(&listener, &[elsewhere, localhost], timeout).is_ok());
+    }
+
+    #[test]
+    fn forward_only_replies_to_peer() {
+        let bot = OfflineBot::for_test("", Vec::<fn() -> Module>::new());
+        let state = bot.state();
+        let server_id = state.server_ids()[0];
+        let (outbox, outbox_receiver) = mk_outbox(&state.config().outbox);
+
+        let privmsg = |target: &str, text: &str| {
+            LibReaction::RawMsg(aatxe::Command::PRIVMSG(target.into(), text.into()).into())
+        };
+
+        let mut written = Vec::new();
+
+        forward_reaction(
+            &mut written,
+            state,
+            &outbox,
+            server_id,
+            "Alice",
+            LibReaction::Multi(vec![
+                privmsg("alice", "Hello"),
+                privmsg("#rust", "Hello, channel"),
+                privmsg("bob", "Hello, Bob"),
+            ]),
+        )
+        .unwrap();
+
+        assert_eq!(String::from_utf8(written).unwrap(), "Hello\n");
+
+        let mut sent = Vec::new();
+
+        while let Some(record) = outbox_receiver.try_recv() {
+            sent.push(format!("{:?}", record));
+        }
+
+        assert_eq!(sent.len(), 2);
+        assert!(sent[0].contains("Hello, channel"));
+        assert!(sent[1].contains("Hello, Bob"));
+    }
+}
@@ -10,6 +10,7 @@ use std::borrow::Cow;
 use std::fmt;
 use std::io;
 use std::num::ParseIntError;
+use std::path::PathBuf;
 use util;
 use walkdir;
 
@@ -63,6 +64,46 @@ error_chain! {
                     new)
         }
 
+        ModuleDependenciesMissing(module: ModuleInfo, missing: Vec<String>) {
+            description("module dependencies missing")
+            display("Failed to load a module because it depends on modules that are neither loaded \
+                     nor available to be loaded. Module: {:?}; missing dependencies: {:?}.",
+                    module,
+                    missing)
+        }
+
+        ModuleDependenciesUnresolvable(module: ModuleInfo, unresolved: Vec<String>) {
+            description("module dependencies unresolvable")
+            display("Failed to load a module because it depends on modules that cannot be loaded, \
+                     either because their dependencies are missing or because the dependencies are \
+                     cyclic. Module: {:?}; unresolvable dependencies: {:?}.",
+                    module,
+                    unresolved)
+        }
+
+        ModuleHasDependents(module: ModuleInfo, dependents: Vec<String>) {
+            description("module has dependents")
+            display("Failed to unload a module because other loaded modules depend on it. Module: \
+                     {:?}; dependents: {:?}.",
+                    module,
+                    dependents)
+        }
+
+        UnknownModule(name: String) {
+            description("module name not recognized")
+            display("No module named {:?} is loaded.", name)
+        }
+
+        UnknownCommand(name: String) {
+            description("command name not recognized")
+            display("No command named {:?} is loaded.", name)
+        }
+
+        InvalidUserPref(name: String, problem: String) {
+            description("invalid user preference")
+            display("The preference {:?} {}.", name, problem)
+        }
+
         ServerRegistryClash(server_id: ServerId) {
             description("server registry ID clash")
             display("Failed to register a server because an existing server had the same ID: \
@@ -70,11 +111,67 @@ error_chain! {
                     server_id = server_id)
         }
 
+        ReconnectionFailure(server_id: ServerId) {
+            description("failed to reconnect to server")
+            display("Failed to set up a new connection to the server {server_id:?}.",
+                    server_id = server_id)
+        }
+
+        CapabilityNotEnabled(cap: String) {
+            description("IRCv3 capability not enabled")
+            display("The server has not enabled the IRCv3 capability {:?}.", cap)
+        }
+
+        QueryTimeout(request: String) {
+            description("timed out waiting for a response")
+            display("Timed out waiting for the server's response to {:?}.", request)
+        }
+
+        FetchFailed(url: String, problem: Cow<'static, str>) {
+            description("failed to fetch document")
+            display("Failed to fetch {:?}: {}.", url, problem)
+        }
+
+        HttpRequestMalformed(part: String) {
+            description("malformed HTTP request")
+            display("The HTTP request's {} is malformed.", part)
+        }
+
+        HttpRequestTooLarge {
+            description("HTTP request too large")
+            display("The HTTP request is too large.")
+        }
+
+        UnknownControlCommand(cmd: String) {
+            description("unknown control command")
+            display("Unknown command {:?}; try `help`.", cmd)
+        }
+
+        ControlCommandSyntax(cmd: String) {
+            description("control command syntax error")
+            display("Syntax error in command {:?}; try `help`.", cmd)
+        }
+
+        UnknownServerName(name: String) {
+            description("server name not recognized")
+            display("No server named {:?} is configured.", name)
+        }
+
         Config(key: String, problem: String) {
             description("configuration error")
             display("Configuration error: Key {:?} {}.", key, problem)
         }
 
+        ConfigSyntax(source: String, problem: String) {
+            description("malformed configuration")
+            display("Failed to parse the configuration from {}: {}", source, problem)
+        }
+
+        ConfigNotReloadable {
+            description("configuration not reloadable")
+            display("The configuration cannot be reloaded, as it was not read from a file.")
+        }
+
         ThreadSpawnFailure(io_err: io::Error) {
             description("failed to spawn thread")
             display("Failed to spawn thread: {}", io_err)
@@ -130,6 +227,55 @@ error_chain! {
                     idx = idx)
         }
 
+        InvalidClientTag(name: String) {
+            description("invalid client-only message tag")
+            display("The message tag {:?} cannot be attached to a message, as only client-only \
+                     tags, whose names begin with `+`, can be.", name)
+        }
+
+        InvalidAlias(name: String, problem: String) {
+            description("invalid command alias")
+            display("The command alias {:?} cannot be defined, because it {}.", name, problem)
+        }
+
+        CmdArgSyntax(problem: Cow<'static, str>) {
+            description("syntax error in command arguments")
+            display("The command's arguments contain {}.", problem)
+        }
+
+        NotInChannel(channel: String) {
+            description("bot not in channel")
+            display("The bot is not in the channel {:?}.", channel)
+        }
+
+        InsufficientChannelPrivileges(channel: String) {
+            description("insufficient channel privileges")
+            display("The bot lacks the channel privileges required to moderate the channel {:?}.",
+                    channel)
+        }
+
+        ChannelModeUnsupported(mode: char) {
+            description("channel mode not supported by server")
+            display("The server does not support the channel mode {:?}.", mode)
+        }
+
+        DccFileRefused(path: PathBuf, reason: Cow<'static, str>) {
+            description("refusal to offer a file by DCC")
+            display("Refused to offer the file {:?} by DCC SEND, because it {}.", path, reason)
+        }
+
+        DccPeerUnverifiable(nick: String) {
+            description("unknown address of user to whom to offer a DCC session")
+            display("Can't offer a DCC CHAT session to {:?}, as the address of their host is \
+                     unknown, so that their connection could not be told from others'; they may \
+                     offer a session to the bot instead.", nick)
+        }
+
+        InContext(inner: Box<Error>, context: ErrorContext) {
+            description("error with context")
+            display("{} [{}]", inner, context)
+        }
+
         Any(inner: Box<Any + Send + 'static>) {
             description("miscellaneous error")
             display("Error: {}", util::fmt::FmtAny(inner.as_ref()))
@@ -143,6 +289,163 @@ error_chain! {
     }
 }
 
+/// The maximum length, in `char`s, of the snippet of an IRC message stored in an `ErrorContext`
+const MSG_SNIPPET_MAX_LEN: usize = 100;
+
+/// Information about the circumstances in which an error occurred, as attached to an `Error` with
+/// [`ErrorKind::InContext`]
+///
+/// [`ErrorKind::InContext`]: <enum.ErrorKind.html#variant.InContext>
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ErrorContext {
+    /// The address of the server in connection with which the error occurred
+    pub server: Option<String>,
+
+    /// The channel, or other message target, in connection with which the error occurred
+    pub channel: Option<String>,
+
+    /// The name of the module in which the error occurred
+    pub module: Option<String>,
+
+    /// The name of the bot command or trigger in which the error occurred
+    pub command: Option<String>,
+
+    /// The beginning of the IRC message in the handling of which the error occurred
+    pub message: Option<String>,
+}
+
+impl ErrorContext {
+    /// Sets the snippet of the IRC message in the handling of which the error occurred, truncating
+    /// the message if necessary.
+    pub(super) fn set_message(&mut self, msg: &str) {
+        let mut snippet = msg.chars().take(MSG_SNIPPET_MAX_LEN).collect::<String>();
+
+        if snippet.len() < msg.len() {
+            snippet.push('…');
+        }
+
+        self.message = Some(snippet);
+    }
+
+    /// Fills in any fields of this structure that are not set with those of `other`.
+    fn fill_in_from(&mut self, other: ErrorContext) {
+        let ErrorContext {
+            server,
+            channel,
+            module,
+            command,
+            message,
+        } = other;
+
+        self.server = self.server.take().or(server);
+        self.channel = self.channel.take().or(channel);
+        self.module = self.module.take().or(module);
+        self.command = self.command.take().or(command);
+        self.message = self.message.take().or(message);
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut sep = "";
+
+        for &(label, value) in &[
+            ("server", &self.server),
+            ("channel", &self.channel),
+            ("module", &self.module),
+            ("command", &self.command),
+            ("message", &self.message),
+        ] {
+            if let Some(ref value) = *value {
+                write!(f, "{}{}: {:?}", sep, label, value)?;
+                sep = "; ";
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// How serious an error is, as used to decide whether to notify the bot's administrators of it
+/// (see the configuration field `error notifications`)
+#[derive(Clone, Copy, Debug, Deserialize, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Severity {
+    /// An error that is likely to be the fault of a user or of some other party outside the bot,
+    /// such as the syntactically incorrect use of a bot command or a failure to fetch a Web page
+    #[serde(rename = "minor")]
+    Minor,
+
+    /// An error that is likely to be the fault of the bot or of one of its modules
+    #[serde(rename = "major")]
+    Major,
+
+    /// An error that leaves the bot, or part of it, unable to work, such as a panic or a failure
+    /// to reconnect to a server
+    #[serde(rename = "critical")]
+    Critical,
+}
+
+impl Error {
+    /// Returns how serious this error is.
+    pub fn severity(&self) -> Severity {
+        match *self.without_context().kind() {
+            ErrorKind::HandlerPanic(..)
+            | ErrorKind::LockPoisoned(_)
+            | ErrorKind::ThreadSpawnFailure(_)
+            | ErrorKind::ReconnectionFailure(_) => Severity::Critical,
+
+            ErrorKind::UnknownModule(_)
+            | ErrorKind::UnknownCommand(_)
+            | ErrorKind::InvalidUserPref(..)
+            | ErrorKind::CapabilityNotEnabled(_)
+            | ErrorKind::QueryTimeout(_)
+            | ErrorKind::FetchFailed(..)
+            | ErrorKind::HttpRequestMalformed(_)
+            | ErrorKind::HttpRequestTooLarge
+            | ErrorKind::UnknownControlCommand(_)
+            | ErrorKind::ControlCommandSyntax(_)
+            | ErrorKind::UnknownServerName(_)
+            | ErrorKind::InvalidClientTag(_)
+            | ErrorKind::InvalidAlias(..)
+            | ErrorKind::CmdArgSyntax(_)
+            | ErrorKind::NotInChannel(_)
+            | ErrorKind::InsufficientChannelPrivileges(_)
+            | ErrorKind::ChannelModeUnsupported(_)
+            | ErrorKind::DccFileRefused(..)
+            | ErrorKind::DccPeerUnverifiable(_) => Severity::Minor,
+
+            _ => Severity::Major,
+        }
+    }
+
+    /// Attaches the given context to this error. If this error already has context attached, any
+    /// fields of the existing context that are not set are filled in from the given context.
+    pub(super) fn with_context(mut self, context: ErrorContext) -> Error {
+        if let ErrorKind::InContext(_, ref mut existing) = self.0 {
+            existing.fill_in_from(context);
+            return self;
+        }
+
+        ErrorKind::InContext(Box::new(self), context).into()
+    }
+
+    /// Returns the context attached to this error, if any.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match *self.kind() {
+            ErrorKind::InContext(_, ref context) => Some(context),
+            _ => None,
+        }
+    }
+
+    /// Returns this error without any context attached to it.
+    pub fn without_context(&self) -> &Error {
+        match *self.kind() {
+            ErrorKind::InContext(ref inner, _) => inner.without_context(),
+            _ => self,
+        }
+    }
+}
+
 impl From<irc::error::IrcError> for Error {
     fn from(orig: irc::error::IrcError) -> Self {
         ErrorKind::IrcCrate(orig).into()
@@ -0,0 +1,134 @@
+//! Forwarding of the errors that the bot encounters to its administrators, as configured with the
+//! field `error notifications`.
+
+use super::Error;
+use super::ServerId;
+use super::State;
+use std::collections::VecDeque;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The errors that the bot has recently forwarded, by which it limits the rate of forwarding and
+/// skips repeated errors
+#[derive(Debug, Default)]
+pub(super) struct ErrorNotifier {
+    /// When an error was last forwarded
+    last_sent: Option<Instant>,
+
+    /// The descriptions of the errors forwarded within the last `repeat interval`, each with when
+    /// it was forwarded, oldest first
+    recent: VecDeque<(String, Instant)>,
+}
+
+impl ErrorNotifier {
+    /// Returns whether an error with the given description, occurring at the given time, is to be
+    /// forwarded, given the least time that is to pass between forwardings and that within which
+    /// an error is not to be forwarded again, and, if so, records its forwarding.
+    fn admit(
+        &mut self,
+        desc: &str,
+        now: Instant,
+        interval: Duration,
+        repeat_interval: Duration,
+    ) -> bool {
+        while self
+            .recent
+            .front()
+            .filter(|&&(_, t)| now.duration_since(t) >= repeat_interval)
+            .is_some()
+        {
+            self.recent.pop_front();
+        }
+
+        if self
+            .last_sent
+            .filter(|&t| now.duration_since(t) < interval)
+            .is_some()
+        {
+            return false;
+        }
+
+        if self.recent.iter().any(|(d, _)| d == desc) {
+            return false;
+        }
+
+        self.last_sent = Some(now);
+        self.recent.push_back((desc.to_owned(), now));
+
+        true
+    }
+}
+
+impl State {
+    /// Forwards the given error, which occurred in connection with the given server, if any, to
+    /// the bot's administrators or their channel, if the configuration asks for errors of its
+    /// severity to be forwarded and it is not too soon after the last error, or a repeat of a
+    /// recent one.
+    pub(super) fn forward_err(&self, server_id: Option<ServerId>, err: &Error) {
+        let config = self.config();
+
+        let cfg = match config.error_notifications {
+            Some(ref cfg) if err.severity() >= cfg.severity => cfg,
+            _ => return,
+        };
+
+        let admitted = match self.error_notifier.lock() {
+            Ok(mut notifier) => notifier.admit(
+                &err.without_context().to_string(),
+                Instant::now(),
+                cfg.interval(),
+                cfg.repeat_interval(),
+            ),
+            Err(_) => {
+                error!("The error notifier is poisoned.");
+                return;
+            }
+        };
+
+        if !admitted {
+            debug!(
+                "Not forwarding error, as it follows the last forwarded too soon or repeats a \
+                 recent one: {}",
+                err
+            );
+            return;
+        }
+
+        let text = format!("Error: {}", err);
+
+        let server_ids = match server_id {
+            Some(server_id) => vec![server_id],
+            None => self.servers.keys().cloned().collect(),
+        };
+
+        for server_id in server_ids {
+            let result = match cfg.channel {
+                Some(ref channel) => self.send_privmsg(server_id, channel, &text),
+                None => self.notify_admins(server_id, &text),
+            };
+
+            if let Err(e) = result {
+                warn!("Failed to forward an error to the administrators: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn admit() {
+        let mut notifier = ErrorNotifier::default();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let (interval, repeat_interval) = (Duration::from_secs(10), Duration::from_secs(600));
+
+        assert!(notifier.admit("a", at(0), interval, repeat_interval));
+        assert!(!notifier.admit("b", at(5), interval, repeat_interval));
+        assert!(notifier.admit("b", at(10), interval, repeat_interval));
+        assert!(!notifier.admit("a", at(100), interval, repeat_interval));
+        assert!(notifier.admit("a", at(600), interval, repeat_interval));
+    }
+}
@@ -0,0 +1,145 @@
+//! Ready-made error handlers, which log each error and react to it according to a common policy.
+
+use super::Error;
+use super::ErrorHandler;
+use super::ErrorReaction;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+/// An `ErrorHandler` that logs each error, at the level `error`, and reacts to it according to one
+/// of a few common policies, so that the bot's users needn't write their own error handlers to
+/// count errors or escalate their response to them
+///
+/// # Examples
+///
+/// An error handler that tells the bot to quit upon the tenth error within a minute:
+///
+/// ```rust
+/// # extern crate irc_bot;
+/// # use irc_bot::Policy;
+/// # use std::time::Duration;
+/// # fn main() {
+/// let error_handler = Policy::quit_after(10, Duration::from_secs(60));
+/// # let _ = error_handler;
+/// # }
+/// ```
+#[derive(Debug)]
+pub struct Policy(PolicyKind);
+
+#[derive(Debug)]
+enum PolicyKind {
+    QuitAfter {
+        max_errors: usize,
+        window: Duration,
+
+        /// The times of the errors that have occurred within the last `window`, oldest first
+        recent_errors: Mutex<VecDeque<Instant>>,
+    },
+    ReconnectServer,
+    NotifyAdminsThenProceed,
+}
+
+impl Policy {
+    /// Returns an error handler that proceeds past errors, but tells the bot to quit upon the
+    /// error with which the given number of errors have occurred within the given period of time.
+    pub fn quit_after(max_errors: usize, window: Duration) -> Self {
+        Policy(PolicyKind::QuitAfter {
+            max_errors,
+            window,
+            recent_errors: Default::default(),
+        })
+    }
+
+    /// Returns an error handler that, upon each error, closes the bot's connection to the server in
+    /// connection with which the error occurred and connects to the server anew, as with
+    /// [`ErrorReaction::Reconnect`].
+    ///
+    /// [`ErrorReaction::Reconnect`]: <enum.ErrorReaction.html#variant.Reconnect>
+    pub fn reconnect_server() -> Self {
+        Policy(PolicyKind::ReconnectServer)
+    }
+
+    /// Returns an error handler that, upon each error, sends a description of the error to the
+    /// bot's administrators, as with [`ErrorReaction::NotifyAdmins`], and then proceeds.
+    ///
+    /// [`ErrorReaction::NotifyAdmins`]: <enum.ErrorReaction.html#variant.NotifyAdmins>
+    pub fn notify_admins_then_proceed() -> Self {
+        Policy(PolicyKind::NotifyAdminsThenProceed)
+    }
+}
+
+impl ErrorHandler for Policy {
+    fn run(&self, err: Error) -> ErrorReaction {
+        error!("{}", err);
+
+        match self.0 {
+            PolicyKind::QuitAfter {
+                max_errors,
+                window,
+                ref recent_errors,
+            } => {
+                // If another thread has panicked while holding the lock, the times within are
+                // intact regardless.
+                let mut recent_errors = match recent_errors.lock() {
+                    Ok(guard) => guard,
+                    Err(poisoned) => poisoned.into_inner(),
+                };
+
+                if count_error(&mut recent_errors, Instant::now(), window) >= max_errors {
+                    ErrorReaction::Quit(Some("Too many errors".into()))
+                } else {
+                    ErrorReaction::Proceed
+                }
+            }
+            PolicyKind::ReconnectServer => ErrorReaction::Reconnect(Some("Reconnecting".into())),
+            PolicyKind::NotifyAdminsThenProceed => {
+                ErrorReaction::NotifyAdmins(format!("Error: {}", err).into())
+            }
+        }
+    }
+}
+
+/// Records an error that occurred at the given time among the given times of recent errors,
+/// forgetting those that occurred longer than the given period of time before it, and returns how
+/// many errors have occurred within that period.
+fn count_error(recent_errors: &mut VecDeque<Instant>, now: Instant, window: Duration) -> usize {
+    recent_errors.push_back(now);
+
+    while recent_errors
+        .front()
+        .filter(|&&t| now.duration_since(t) > window)
+        .is_some()
+    {
+        recent_errors.pop_front();
+    }
+
+    recent_errors.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quit_after() {
+        let start = Instant::now();
+        let window = Duration::from_secs(60);
+        let mut recent_errors = VecDeque::new();
+
+        assert_eq!(count_error(&mut recent_errors, start, window), 1);
+        assert_eq!(
+            count_error(&mut recent_errors, start + Duration::from_secs(30), window),
+            2
+        );
+        assert_eq!(
+            count_error(&mut recent_errors, start + Duration::from_secs(61), window),
+            2
+        );
+        assert_eq!(
+            count_error(&mut recent_errors, start + Duration::from_secs(200), window),
+            1
+        );
+    }
+}
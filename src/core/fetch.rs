@@ -0,0 +1,395 @@
+use super::pkg_info;
+use super::ErrorKind;
+use super::Result;
+use native_tls::TlsConnector;
+use std::borrow::Cow;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+use std::time::Instant;
+use url::form_urlencoded;
+use url::Url;
+
+/// The maximum size, in bytes, of each line of a response's head
+const MAX_HEAD_LINE_LEN: u64 = 8 * 1024;
+
+/// The maximum number of header fields that a response may have
+const MAX_HEADERS: usize = 64;
+
+/// Limits on the fetching of a document with [`fetch`]
+///
+/// [`fetch`]: <fn.fetch.html>
+#[derive(Clone, Debug)]
+pub struct FetchLimits {
+    /// The maximum number of bytes of the document's body to read, beyond which the body is
+    /// truncated
+    pub max_size: usize,
+
+    /// How long to wait for the whole document, including any redirects, beyond which the fetch
+    /// fails
+    pub timeout: Duration,
+
+    /// The maximum number of redirects to follow
+    pub max_redirects: u8,
+}
+
+impl Default for FetchLimits {
+    fn default() -> Self {
+        FetchLimits {
+            max_size: 1024 * 1024,
+            timeout: Duration::from_secs(10),
+            max_redirects: 5,
+        }
+    }
+}
+
+/// A document fetched with [`fetch`]
+///
+/// [`fetch`]: <fn.fetch.html>
+#[derive(Clone, Debug)]
+pub struct FetchedDoc {
+    /// The URL from which the document was fetched, after any redirects
+    pub url: Url,
+
+    /// The HTTP status code with which the server answered
+    pub status: u16,
+
+    /// The document's media type, as given by the header field `Content-Type`, if any
+    pub content_type: Option<String>,
+
+    /// The document's body, up to the size limit
+    pub body: Vec<u8>,
+
+    /// Whether the body was truncated at the size limit
+    pub truncated: bool,
+}
+
+/// A response to a request sent by [`request`], whose body remains to be read
+///
+/// [`request`]: <fn.request.html>
+struct Response {
+    status: u16,
+    headers: Vec<(String, String)>,
+    body: BufReader<Box<Stream>>,
+}
+
+trait Stream: Read + Write {}
+
+impl<T: Read + Write> Stream for T {}
+
+/// Fetches the document at the given `http` or `https` URL with a `GET` request, following
+/// redirects, within the given limits.
+///
+/// This blocks until the document has been fetched or the time limit has passed, and so must not
+/// be called from handlers that are run while the bot is handling messages from a server; bot
+/// commands and triggers may call it.
+pub fn fetch(url: &Url, limits: &FetchLimits) -> Result<FetchedDoc> {
+    let deadline = Instant::now() + limits.timeout;
+    let mut url = url.clone();
+
+    for _ in 0..=limits.max_redirects {
+        let Response {
+            status,
+            headers,
+            body: mut reader,
+        } = request(&url, deadline, None)?;
+
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+        };
+
+        if let (300..=399, Some(location)) = (status, header("Location")) {
+            url = url.join(&location).map_err(|e| {
+                fetch_err(&url, format!("the server redirected to a bad URL: {}", e))
+            })?;
+            continue;
+        }
+
+        let (body, truncated) = read_body(&url, deadline, &mut reader, limits.max_size)?;
+
+        return Ok(FetchedDoc {
+            url,
+            status,
+            content_type: header("Content-Type"),
+            body,
+            truncated,
+        });
+    }
+
+    Err(fetch_err(&url, "the server redirected too many times"))
+}
+
+/// Sends a `POST` request with the given form fields, encoded as
+/// `application/x-www-form-urlencoded`, to the given `http` or `https` URL, within the given
+/// limits, returning the response's status code, the value of its header field `Location`, if
+/// any, and its body. Redirects are not followed.
+pub(super) fn post_form(
+    url: &Url,
+    fields: &[(&str, &str)],
+    limits: &FetchLimits,
+) -> Result<(u16, Option<String>, Vec<u8>)> {
+    let deadline = Instant::now() + limits.timeout;
+
+    let form = form_urlencoded::Serializer::new(String::new())
+        .extend_pairs(fields)
+        .finish();
+
+    let Response {
+        status,
+        headers,
+        body: mut reader,
+    } = request(url, deadline, Some(form.as_bytes()))?;
+
+    let location = headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case("Location"))
+        .map(|(_, value)| value.clone());
+
+    let (body, _) = read_body(url, deadline, &mut reader, limits.max_size)?;
+
+    Ok((status, location, body))
+}
+
+/// Reads up to the given number of bytes of a response's body, returning them along with whether
+/// the body was truncated.
+fn read_body(
+    url: &Url,
+    deadline: Instant,
+    reader: &mut BufReader<Box<Stream>>,
+    max_size: usize,
+) -> Result<(Vec<u8>, bool)> {
+    let mut body = Vec::new();
+
+    loop {
+        remaining(url, deadline)?;
+
+        let (len, available) = {
+            let buf = reader.fill_buf()?;
+            let len = buf.len().min(max_size - body.len());
+
+            body.extend_from_slice(&buf[..len]);
+            (len, buf.len())
+        };
+
+        reader.consume(len);
+
+        if len < available {
+            return Ok((body, true));
+        } else if len == 0 {
+            return Ok((body, false));
+        }
+    }
+}
+
+fn fetch_err<S>(url: &Url, problem: S) -> super::Error
+where
+    S: Into<Cow<'static, str>>,
+{
+    ErrorKind::FetchFailed(url.to_string(), problem.into()).into()
+}
+
+/// Returns how long remains until the given deadline for fetching the given URL, or an error if
+/// it has passed.
+fn remaining(url: &Url, deadline: Instant) -> Result<Duration> {
+    let now = Instant::now();
+
+    if now >= deadline {
+        Err(fetch_err(url, "the request timed out"))
+    } else {
+        Ok(deadline - now)
+    }
+}
+
+/// Sends a request for the given URL, returning the response once its head has been read. The
+/// request is a `POST` of the given form, if any, or else a `GET`.
+fn request(url: &Url, deadline: Instant, form: Option<&[u8]>) -> Result<Response> {
+    let tls = match url.scheme() {
+        "http" => false,
+        "https" => true,
+        scheme => {
+            return Err(fetch_err(
+                url,
+                format!("the URL has the unsupported scheme {:?}", scheme),
+            ))
+        }
+    };
+
+    let host = url
+        .host_str()
+        .ok_or_else(|| fetch_err(url, "the URL has no host"))?;
+    let port = url
+        .port_or_known_default()
+        .ok_or_else(|| fetch_err(url, "the URL has no port"))?;
+
+    let addr = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| fetch_err(url, "the URL's host has no addresses"))?;
+
+    let tcp = TcpStream::connect_timeout(&addr, remaining(url, deadline)?)?;
+    tcp.set_read_timeout(Some(remaining(url, deadline)?))?;
+    tcp.set_write_timeout(Some(remaining(url, deadline)?))?;
+
+    let mut stream: Box<Stream> = if tls {
+        let connector =
+            TlsConnector::new().map_err(|e| fetch_err(url, format!("TLS error: {}", e)))?;
+
+        Box::new(
+            connector
+                .connect(host, tcp)
+                .map_err(|e| fetch_err(url, format!("TLS error: {}", e)))?,
+        )
+    } else {
+        Box::new(tcp)
+    };
+
+    let path = match url.query() {
+        Some(query) => format!("{}?{}", url.path(), query),
+        None => url.path().to_owned(),
+    };
+
+    let host_header = match url.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_owned(),
+    };
+
+    let (method, form_headers) = match form {
+        Some(form) => (
+            "POST",
+            format!(
+                "Content-Type: application/x-www-form-urlencoded\r\nContent-Length: {}\r\n",
+                form.len()
+            ),
+        ),
+        None => ("GET", String::new()),
+    };
+
+    // HTTP/1.0 is used so that the server doesn't use chunked transfer encoding.
+    write!(
+        stream,
+        "{} {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: {}/{}\r\nAccept: */*\r\n{}\
+         Connection: close\r\n\r\n",
+        method,
+        path,
+        host_header,
+        *pkg_info::NAME_STR,
+        *pkg_info::VERSION_STR,
+        form_headers,
+    )?;
+
+    if let Some(form) = form {
+        stream.write_all(form)?;
+    }
+
+    stream.flush()?;
+
+    let mut reader = BufReader::new(stream);
+
+    let status_line = read_head_line(url, &mut reader)?;
+    let status = status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse().ok())
+        .filter(|_| status_line.starts_with("HTTP/"))
+        .ok_or_else(|| fetch_err(url, "the server answered with a malformed status line"))?;
+
+    let mut headers = Vec::new();
+
+    loop {
+        let line = read_head_line(url, &mut reader)?;
+
+        if line.is_empty() {
+            break;
+        }
+
+        if headers.len() == MAX_HEADERS {
+            return Err(fetch_err(
+                url,
+                "the server answered with too many header fields",
+            ));
+        }
+
+        let mut parts = line.splitn(2, ':');
+        let name = parts.next().unwrap_or_default().trim().to_owned();
+        let value = parts.next().unwrap_or_default().trim().to_owned();
+
+        headers.push((name, value));
+    }
+
+    Ok(Response {
+        status,
+        headers,
+        body: reader,
+    })
+}
+
+fn read_head_line<R>(url: &Url, reader: &mut R) -> Result<String>
+where
+    R: BufRead,
+{
+    let mut line = String::new();
+    reader
+        .by_ref()
+        .take(MAX_HEAD_LINE_LEN)
+        .read_line(&mut line)?;
+
+    if !line.ends_with('\n') {
+        return Err(fetch_err(url, "the server answered with a malformed head"));
+    }
+
+    Ok(line.trim_end().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn fetching() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let responses = [
+                "HTTP/1.0 301 Moved Permanently\r\nLocation: /final?x=1\r\n\r\n",
+                "HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\nHello, world!",
+            ];
+
+            for response in &responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut head = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+
+                while head.is_empty() || !head.ends_with("\r\n\r\n") {
+                    reader.read_line(&mut head).unwrap();
+                }
+
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        let url = Url::parse(&format!("http://{}/start", addr)).unwrap();
+        let limits = FetchLimits {
+            max_size: 5,
+            ..Default::default()
+        };
+
+        let doc = fetch(&url, &limits).unwrap();
+        server.join().unwrap();
+
+        assert_eq!(doc.url.path(), "/final");
+        assert_eq!(doc.status, 200);
+        assert_eq!(doc.content_type, Some("text/plain".into()));
+        assert_eq!(doc.body, b"Hello");
+        assert!(doc.truncated);
+    }
+}
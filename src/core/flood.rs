@@ -0,0 +1,252 @@
+//! Detection of users who flood channels, as configured with the field `flood protection`.
+
+use super::config::FloodAction;
+use super::config::FloodProtection;
+use super::irc_msgs::OwningMsgPrefix;
+use super::MsgDest;
+use super::Result;
+use super::ServerId;
+use super::State;
+use super::UserEvent;
+use super::UserId;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::time::Instant;
+use util::irc::casefold;
+
+/// How a user flooded a channel
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FloodKind {
+    /// The user sent more messages within the configured period than the configuration allows.
+    Rate,
+
+    /// The user sent the same message more times within the configured period than the
+    /// configuration allows.
+    Repeat,
+}
+
+/// The users' recent messages to the channels of a server, and the users whose commands are
+/// ignored for flooding
+#[derive(Debug, Default)]
+pub(super) struct FloodTracker {
+    /// The times and hashes of the contents of users' recent messages, by the casefolded name of
+    /// the channel to which they were sent and the user who sent them
+    recent: HashMap<(String, UserId), VecDeque<(Instant, u64)>>,
+
+    /// The times at which users' cooldowns end
+    cooldowns: HashMap<UserId, Instant>,
+}
+
+impl FloodTracker {
+    /// Records a message with the given contents from the given user to the given channel,
+    /// returning how the user has flooded the channel, if the user has.
+    fn record(
+        &mut self,
+        cfg: &FloodProtection,
+        channel: String,
+        user: UserId,
+        text: &str,
+    ) -> Option<FloodKind> {
+        let now = Instant::now();
+        let period = cfg.period();
+
+        self.recent.retain(|_, msgs| {
+            while msgs
+                .front()
+                .filter(|&&(sent, _)| now.duration_since(sent) >= period)
+                .is_some()
+            {
+                msgs.pop_front();
+            }
+
+            !msgs.is_empty()
+        });
+
+        let hash = content_hash(text);
+        let msgs = self.recent.entry((channel, user)).or_default();
+        msgs.push_back((now, hash));
+
+        let kind = if msgs.iter().filter(|&&(_, h)| h == hash).count() > cfg.repeats {
+            FloodKind::Repeat
+        } else if msgs.len() > cfg.messages {
+            FloodKind::Rate
+        } else {
+            return None;
+        };
+
+        msgs.clear();
+        self.cooldowns.insert(user, now + cfg.cooldown());
+
+        Some(kind)
+    }
+
+    fn in_cooldown(&mut self, user: UserId) -> bool {
+        let now = Instant::now();
+
+        self.cooldowns.retain(|_, &mut end| now < end);
+        self.cooldowns.contains_key(&user)
+    }
+}
+
+/// Records the given message from the user with the given message prefix to the given target, if
+/// flood protection is configured, dealing with the user if the user has flooded a channel, and
+/// returns whether the user's commands are to be ignored.
+pub(super) fn check(
+    state: &State,
+    server_id: ServerId,
+    prefix: &OwningMsgPrefix,
+    target: &str,
+    text: &str,
+) -> Result<bool> {
+    let config = state.config();
+
+    let cfg = match config.flood_protection {
+        Some(ref cfg) => cfg,
+        None => return Ok(false),
+    };
+
+    let nick = match prefix.parse().nick {
+        Some(nick) => nick,
+        None => return Ok(false),
+    };
+
+    let user = match state.user_id(server_id, nick)? {
+        Some(user) => user,
+        None => return Ok(false),
+    };
+
+    if state.have_admin(server_id, prefix.parse())? {
+        return Ok(false);
+    }
+
+    let is_channel = state
+        .server_capabilities(server_id)?
+        .is_channel_name(target);
+
+    let kind = {
+        let mut server = state.write_server(server_id)?;
+
+        if server.flood.in_cooldown(user) {
+            return Ok(true);
+        }
+
+        if !is_channel {
+            return Ok(false);
+        }
+
+        let channel = casefold(server.capabilities.casemapping, target);
+
+        match server.flood.record(cfg, channel, user, text) {
+            Some(kind) => kind,
+            None => return Ok(false),
+        }
+    };
+
+    info!(
+        "[{}] User {:?} flooded {:?} ({:?}); ignoring the user's commands for {:?}.",
+        state.server_socket_addr_dbg_string(server_id),
+        nick,
+        target,
+        kind,
+        cfg.cooldown()
+    );
+
+    if let Err(e) = take_action(state, server_id, prefix, target, cfg) {
+        warn!("Failed to act on {:?}'s flooding {:?}: {}", nick, target, e);
+    }
+
+    state.run_user_event_handlers(
+        server_id,
+        &UserEvent::Flood {
+            user,
+            nick: nick.to_owned(),
+            channel: target.to_owned(),
+            kind,
+        },
+    );
+
+    Ok(true)
+}
+
+fn take_action(
+    state: &State,
+    server_id: ServerId,
+    prefix: &OwningMsgPrefix,
+    channel: &str,
+    cfg: &FloodProtection,
+) -> Result<()> {
+    let user = prefix.parse();
+    let nick = user.nick.unwrap_or_default();
+    let mask = format!("*!*@{}", user.host.unwrap_or("*"));
+    let dest = MsgDest {
+        server_id,
+        target: channel,
+    };
+
+    match cfg.action {
+        FloodAction::Nothing => Ok(()),
+        FloodAction::Warn => state.send_privmsg(
+            server_id,
+            channel,
+            &state.localize(
+                dest,
+                user,
+                "flood: warning",
+                &[
+                    ("nick", nick),
+                    ("seconds", &cfg.cooldown().as_secs().to_string()),
+                ],
+            ),
+        ),
+        FloodAction::Quiet => state.quiet_mask(server_id, channel, &mask),
+        FloodAction::Kick => state.kick(
+            server_id,
+            channel,
+            nick,
+            Some(&state.localize(dest, user, "flood: kick reason", &[])),
+        ),
+        FloodAction::Ban => state.ban_mask(server_id, channel, &mask),
+    }
+}
+
+/// Hashes the given message's text, ignoring case and surrounding whitespace.
+fn content_hash(text: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    text.trim().to_lowercase().hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::users::UserTable;
+    use super::*;
+    use serde_yaml;
+    use util::irc::CaseMapping;
+
+    #[test]
+    fn floods() {
+        let cfg: FloodProtection = serde_yaml::from_str("{messages: 3, repeats: 2}").unwrap();
+
+        let mut users = UserTable::default();
+        let alice = users.sighted(CaseMapping::Rfc1459, "alice").0;
+        let bob = users.sighted(CaseMapping::Rfc1459, "bob").0;
+        let mut tracker = FloodTracker::default();
+
+        let mut record = |user, text| tracker.record(&cfg, "#chan".into(), user, text);
+
+        assert_eq!(record(alice, "hi"), None);
+        assert_eq!(record(alice, " HI "), None);
+        assert_eq!(record(bob, "hi"), None);
+        assert_eq!(record(alice, "hi"), Some(FloodKind::Repeat));
+
+        assert_eq!(record(bob, "a"), None);
+        assert_eq!(record(bob, "b"), None);
+        assert_eq!(record(bob, "c"), Some(FloodKind::Rate));
+
+        assert!(tracker.in_cooldown(alice));
+        assert!(tracker.in_cooldown(bob));
+    }
+}
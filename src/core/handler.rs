@@ -2,15 +2,25 @@ use super::BotCmdResult;
 use super::BotCommand;
 use super::Error;
 use super::ErrorReaction;
+use super::IncomingMsg;
+use super::InputVerdict;
 use super::MsgDest;
 use super::MsgMetadata;
 use super::MsgPrefix;
+use super::MsgTags;
+use super::OutgoingMsg;
+use super::OutputVerdict;
+use super::Presence;
 use super::Result;
+use super::ServerCapabilities;
+use super::ServerId;
 use super::State;
 use super::Trigger;
+use super::UserEvent;
 use regex::Captures;
 use std::panic::RefUnwindSafe;
 use std::panic::UnwindSafe;
+use std::sync::Mutex;
 use yaml_rust::Yaml;
 
 pub trait ErrorHandler: Send + Sync + UnwindSafe + RefUnwindSafe + 'static {
@@ -30,6 +40,98 @@ where
     }
 }
 
+/// An error handler that may mutate its own state, e.g., to count errors and escalate its
+/// response to repeated failures
+///
+/// To use such an error handler where an `ErrorHandler` is required, wrap it in a
+/// [`StatefulErrorHandler`], which synchronizes access to it.
+///
+/// [`StatefulErrorHandler`]: <struct.StatefulErrorHandler.html>
+pub trait ErrorHandlerMut: Send + 'static {
+    /// Handles an error.
+    fn run(&mut self, Error) -> ErrorReaction;
+}
+
+impl<T> ErrorHandlerMut for T
+where
+    T: FnMut(Error) -> ErrorReaction + Send + 'static,
+{
+    fn run(&mut self, err: Error) -> ErrorReaction {
+        self(err)
+    }
+}
+
+/// An `ErrorHandler` that wraps an [`ErrorHandlerMut`], allowing the error handler to keep state
+///
+/// # Examples
+///
+/// An error handler that tells the bot to quit upon the tenth error within a minute:
+///
+/// ```rust
+/// # extern crate irc_bot;
+/// # use irc_bot::Error;
+/// # use irc_bot::ErrorReaction;
+/// # use irc_bot::StatefulErrorHandler;
+/// # use std::collections::VecDeque;
+/// # use std::time::Duration;
+/// # use std::time::Instant;
+/// # fn main() {
+/// let mut recent_errors = VecDeque::new();
+///
+/// let error_handler = StatefulErrorHandler::new(move |err: Error| {
+///     let now = Instant::now();
+///
+///     recent_errors.push_back(now);
+///
+///     while recent_errors.front().map_or(false, |&t| now - t > Duration::from_secs(60)) {
+///         recent_errors.pop_front();
+///     }
+///
+///     eprintln!("Error: {}", err);
+///
+///     if recent_errors.len() >= 10 {
+///         ErrorReaction::Quit(Some("Too many errors".into()))
+///     } else {
+///         ErrorReaction::Proceed
+///     }
+/// });
+/// # let _ = error_handler;
+/// # }
+/// ```
+///
+/// For this common case, [`Policy::quit_after`] offers a ready-made error handler.
+///
+/// [`ErrorHandlerMut`]: <trait.ErrorHandlerMut.html>
+/// [`Policy::quit_after`]: <struct.Policy.html#method.quit_after>
+pub struct StatefulErrorHandler<H>(Mutex<H>)
+where
+    H: ErrorHandlerMut;
+
+impl<H> StatefulErrorHandler<H>
+where
+    H: ErrorHandlerMut,
+{
+    pub fn new(handler: H) -> Self {
+        StatefulErrorHandler(Mutex::new(handler))
+    }
+}
+
+impl<H> ErrorHandler for StatefulErrorHandler<H>
+where
+    H: ErrorHandlerMut,
+{
+    fn run(&self, err: Error) -> ErrorReaction {
+        // If the handler has panicked while handling an earlier error, carry on regardless, as
+        // there is nothing better to be done.
+        let mut handler = match self.0.lock() {
+            Ok(guard) => guard,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+
+        handler.run(err)
+    }
+}
+
 pub trait BotCmdHandler: Send + Sync + UnwindSafe + RefUnwindSafe {
     fn run(&self, HandlerContext, &Yaml) -> BotCmdResult;
 }
@@ -72,6 +174,154 @@ where
     }
 }
 
+pub trait ModuleUnloadHandler: Send + Sync + UnwindSafe + RefUnwindSafe + 'static {
+    fn run(&self, &State) -> Result<()>;
+}
+
+impl<F, R> ModuleUnloadHandler for F
+where
+    F: Fn(&State) -> R + Send + Sync + UnwindSafe + RefUnwindSafe + 'static,
+    R: Into<Result<()>>,
+{
+    fn run(&self, state: &State) -> Result<()> {
+        self(state).into()
+    }
+}
+
+pub trait ServerConnectionHandler: Send + Sync + UnwindSafe + RefUnwindSafe + 'static {
+    fn run(&self, &State, ServerId) -> Result<()>;
+}
+
+impl<F, R> ServerConnectionHandler for F
+where
+    F: Fn(&State, ServerId) -> R + Send + Sync + UnwindSafe + RefUnwindSafe + 'static,
+    R: Into<Result<()>>,
+{
+    fn run(&self, state: &State, server_id: ServerId) -> Result<()> {
+        self(state, server_id).into()
+    }
+}
+
+pub trait EchoedMsgHandler: Send + Sync + UnwindSafe + RefUnwindSafe + 'static {
+    fn run(&self, &State, &MsgMetadata, &str) -> Result<()>;
+}
+
+impl<F, R> EchoedMsgHandler for F
+where
+    F: Fn(&State, &MsgMetadata, &str) -> R + Send + Sync + UnwindSafe + RefUnwindSafe + 'static,
+    R: Into<Result<()>>,
+{
+    fn run(&self, state: &State, metadata: &MsgMetadata, text: &str) -> Result<()> {
+        self(state, metadata, text).into()
+    }
+}
+
+pub trait InputFilter: Send + Sync + UnwindSafe + RefUnwindSafe + 'static {
+    fn run(&self, &State, &mut IncomingMsg) -> Result<InputVerdict>;
+}
+
+impl<F, R> InputFilter for F
+where
+    F: Fn(&State, &mut IncomingMsg) -> R + Send + Sync + UnwindSafe + RefUnwindSafe + 'static,
+    R: Into<Result<InputVerdict>>,
+{
+    fn run(&self, state: &State, msg: &mut IncomingMsg) -> Result<InputVerdict> {
+        self(state, msg).into()
+    }
+}
+
+pub trait OutputFilter: Send + Sync + UnwindSafe + RefUnwindSafe + 'static {
+    fn run(&self, &State, &mut OutgoingMsg) -> Result<OutputVerdict>;
+}
+
+impl<F, R> OutputFilter for F
+where
+    F: Fn(&State, &mut OutgoingMsg) -> R + Send + Sync + UnwindSafe + RefUnwindSafe + 'static,
+    R: Into<Result<OutputVerdict>>,
+{
+    fn run(&self, state: &State, msg: &mut OutgoingMsg) -> Result<OutputVerdict> {
+        self(state, msg).into()
+    }
+}
+
+pub trait PeriodicHandler: Send + Sync + UnwindSafe + RefUnwindSafe + 'static {
+    fn run(&self, &State) -> Result<()>;
+}
+
+impl<F, R> PeriodicHandler for F
+where
+    F: Fn(&State) -> R + Send + Sync + UnwindSafe + RefUnwindSafe + 'static,
+    R: Into<Result<()>>,
+{
+    fn run(&self, state: &State) -> Result<()> {
+        self(state).into()
+    }
+}
+
+pub trait PresenceHandler: Send + Sync + UnwindSafe + RefUnwindSafe + 'static {
+    fn run(&self, &State, ServerId, &str, Presence) -> Result<()>;
+}
+
+impl<F, R> PresenceHandler for F
+where
+    F: Fn(&State, ServerId, &str, Presence) -> R
+        + Send
+        + Sync
+        + UnwindSafe
+        + RefUnwindSafe
+        + 'static,
+    R: Into<Result<()>>,
+{
+    fn run(
+        &self,
+        state: &State,
+        server_id: ServerId,
+        nick: &str,
+        presence: Presence,
+    ) -> Result<()> {
+        self(state, server_id, nick, presence).into()
+    }
+}
+
+pub trait UnknownCommandHandler: Send + Sync + UnwindSafe + RefUnwindSafe + 'static {
+    fn run(&self, &State, &MsgMetadata, &str, &str) -> Option<BotCmdResult>;
+}
+
+impl<F, R> UnknownCommandHandler for F
+where
+    F: Fn(&State, &MsgMetadata, &str, &str) -> Option<R>
+        + Send
+        + Sync
+        + UnwindSafe
+        + RefUnwindSafe
+        + 'static,
+    R: Into<BotCmdResult>,
+{
+    fn run(
+        &self,
+        state: &State,
+        metadata: &MsgMetadata,
+        cmd_name: &str,
+        cmd_args: &str,
+    ) -> Option<BotCmdResult> {
+        self(state, metadata, cmd_name, cmd_args).map(Into::into)
+    }
+}
+
+pub trait UserEventHandler: Send + Sync + UnwindSafe + RefUnwindSafe + 'static {
+    fn run(&self, &State, ServerId, &UserEvent) -> Result<()>;
+}
+
+impl<F, R> UserEventHandler for F
+where
+    F: Fn(&State, ServerId, &UserEvent) -> R + Send + Sync + UnwindSafe + RefUnwindSafe + 'static,
+    R: Into<Result<()>>,
+{
+    fn run(&self, state: &State, server_id: ServerId, event: &UserEvent) -> Result<()> {
+        self(state, server_id, event).into()
+    }
+}
+
 #[derive(CustomDebug)]
 pub struct HandlerContext<'s, 'm> {
     /// The bot state
@@ -87,6 +337,15 @@ pub struct HandlerContext<'s, 'm> {
     /// This field identifies the user (or fellow bot) who caused this handler to be run.
     pub invoker: MsgPrefix<'m>,
 
+    /// This field holds the IRCv3 message tags with which the request that caused this handler to
+    /// be run was sent, such as `msgid`, if the server supports message tags.
+    pub request_tags: &'m MsgTags,
+
+    /// This field is `true` if the message that caused this handler to be run was an action (as
+    /// sent with the IRC client command `/me`) rather than an ordinary message. Only triggers are
+    /// run for actions; bot commands are not.
+    pub request_is_action: bool,
+
     #[debug(skip)]
     #[doc(hidden)]
     pub(super) __nonexhaustive: (),
@@ -104,6 +363,7 @@ impl<'s, 'm> HandlerContext<'s, 'm> {
         MsgMetadata {
             dest: self.request_origin,
             prefix: self.invoker,
+            tags: self.request_tags,
         }
     }
 
@@ -116,6 +376,40 @@ impl<'s, 'm> HandlerContext<'s, 'm> {
         self.state.guess_reply_dest(&self.request_metadata())
     }
 
+    /// Returns the `ServerId` of the server from which the request came.
+    pub fn server_id(&self) -> ServerId {
+        self.request_origin.server_id
+    }
+
+    /// Returns the bot's current nickname on the server from which the request came.
+    pub fn own_nick(&self) -> Result<String> {
+        self.state.nick(self.server_id())
+    }
+
+    /// Returns the name of the IRC network from which the request came, if the server has
+    /// advertised it (see [`State::network_name`]).
+    ///
+    /// [`State::network_name`]: <struct.State.html#method.network_name>
+    pub fn network_name(&self) -> Result<Option<String>> {
+        self.state.network_name(self.server_id())
+    }
+
+    /// Returns the hostname and port number of the server from which the request came (see
+    /// [`State::server_addr`]).
+    ///
+    /// [`State::server_addr`]: <struct.State.html#method.server_addr>
+    pub fn server_addr(&self) -> Result<(String, u16)> {
+        self.state.server_addr(self.server_id())
+    }
+
+    /// Returns the features and limits that the server from which the request came has
+    /// advertised (see [`State::server_capabilities`]).
+    ///
+    /// [`State::server_capabilities`]: <struct.State.html#method.server_capabilities>
+    pub fn server_capabilities(&self) -> Result<ServerCapabilities> {
+        self.state.server_capabilities(self.server_id())
+    }
+
     // TODO
     // pub fn module_data(&self) -> Result<...> {
     //     let module_id = &self.this_feature.provider().(...);
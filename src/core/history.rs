@@ -0,0 +1,203 @@
+use super::irc_msgs::format_server_time;
+use super::irc_msgs::OwningMsgPrefix;
+use super::irc_send::push_to_outbox;
+use super::ErrorKind;
+use super::LibReaction;
+use super::MsgTags;
+use super::Result;
+use super::ServerId;
+use super::State;
+use crossbeam_channel;
+use irc::client::prelude as aatxe;
+use irc::proto::Message;
+use std::cmp::Ordering;
+use std::time::Duration;
+use std::time::SystemTime;
+use util::irc::casemapped_str_cmp;
+use util::irc::CaseMapping;
+
+/// How long to wait for a server to respond to a request for history
+const FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A message from a channel's history, as returned by [`State::fetch_history`]
+///
+/// [`State::fetch_history`]: <struct.State.html#method.fetch_history>
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HistoryMsg {
+    /// The message prefix of the message's sender, such as `nick!user@host`
+    pub prefix: String,
+
+    pub text: String,
+
+    /// Whether the message was sent as a `NOTICE` rather than as a `PRIVMSG`
+    pub notice: bool,
+
+    pub tags: MsgTags,
+}
+
+impl HistoryMsg {
+    /// Returns the nickname of the message's sender.
+    pub fn nick(&self) -> Option<String> {
+        OwningMsgPrefix::from_string(self.prefix.clone())
+            .parse()
+            .nick
+            .map(ToOwned::to_owned)
+    }
+
+    /// Returns the time at which the message was sent, per its `time` tag.
+    pub fn time(&self) -> Option<SystemTime> {
+        self.tags.server_time()
+    }
+}
+
+/// The requests for history sent with `State::fetch_history` to a server that await their
+/// complete responses
+#[derive(Debug, Default)]
+pub(super) struct PendingFetches {
+    pending: Vec<PendingFetch>,
+
+    /// A counter used to identify the requests
+    counter: u64,
+}
+
+#[derive(Debug)]
+struct PendingFetch {
+    id: u64,
+    target: String,
+
+    /// The reference tag of the `chathistory` batch in which the server is sending its response,
+    /// once the server has begun it
+    batch: Option<String>,
+
+    msgs: Vec<HistoryMsg>,
+
+    reply_sender: crossbeam_channel::Sender<Vec<HistoryMsg>>,
+}
+
+impl PendingFetches {
+    /// Forgets all pending requests, e.g., because the connection has been closed. Their callers
+    /// will receive errors.
+    pub(super) fn clear(&mut self) {
+        self.pending.clear()
+    }
+
+    /// Records that the server has begun a `chathistory` batch with the given reference tag, for
+    /// the given target, which the server presumably is sending in response to the oldest of the
+    /// pending requests for that target's history.
+    pub(super) fn batch_opened(&mut self, casemapping: CaseMapping, reference: &str, target: &str) {
+        if let Some(fetch) = self.pending.iter_mut().find(|f| {
+            f.batch.is_none()
+                && casemapped_str_cmp(casemapping, &f.target[..], target) == Ordering::Equal
+        }) {
+            fetch.batch = Some(reference.to_owned());
+        }
+    }
+
+    /// Records a message that the server has sent in the batch with the given reference tag.
+    pub(super) fn record(&mut self, reference: &str, msg: &Message) {
+        let fetch = match self.find(reference) {
+            Some(i) => &mut self.pending[i],
+            None => return,
+        };
+
+        let (text, notice) = match msg.command {
+            aatxe::Command::PRIVMSG(_, ref text) => (text, false),
+            aatxe::Command::NOTICE(_, ref text) => (text, true),
+            _ => return,
+        };
+
+        fetch.msgs.push(HistoryMsg {
+            prefix: msg.prefix.clone().unwrap_or_default(),
+            text: text.clone(),
+            notice,
+            tags: MsgTags::from_aatxe(msg.tags.clone()),
+        });
+    }
+
+    /// Passes the messages collected in the batch with the given reference tag, which the server
+    /// has closed, to the caller that requested them.
+    pub(super) fn batch_closed(&mut self, reference: &str) {
+        if let Some(i) = self.find(reference) {
+            let PendingFetch {
+                msgs, reply_sender, ..
+            } = self.pending.remove(i);
+
+            // The caller may have given up waiting, in which case the messages are of no
+            // interest.
+            let _ = reply_sender.send(msgs);
+        }
+    }
+
+    fn find(&self, reference: &str) -> Option<usize> {
+        self.pending
+            .iter()
+            .position(|f| f.batch.as_ref().map(|b| &b[..]) == Some(reference))
+    }
+}
+
+impl State {
+    /// Requests from the given server up to `limit` messages sent to the given channel before the
+    /// given time, or the latest messages if no time is given, and waits for the server's
+    /// response, returning the messages in the order in which they were sent.
+    ///
+    /// This uses the IRCv3 draft capability `draft/chathistory`, which some servers and bouncers
+    /// support; if the server has not enabled it, an error is returned without anything being
+    /// sent. The server may return fewer messages than requested, e.g., if it limits the number of
+    /// messages per request, as it may advertise with the `RPL_ISUPPORT` parameter `CHATHISTORY`.
+    ///
+    /// As this function blocks until the response arrives, it must not be called from handlers
+    /// that are run while the bot is handling messages from the server; bot commands and triggers
+    /// may call it.
+    pub fn fetch_history(
+        &self,
+        server_id: ServerId,
+        channel: &str,
+        before: Option<SystemTime>,
+        limit: usize,
+    ) -> Result<Vec<HistoryMsg>> {
+        ensure!(
+            self.capability_enabled(server_id, "draft/chathistory")?,
+            ErrorKind::CapabilityNotEnabled("draft/chathistory".into())
+        );
+
+        let (subcmd, reference) = match before {
+            Some(time) => ("BEFORE", format!("timestamp={}", format_server_time(time))),
+            None => ("LATEST", "*".to_owned()),
+        };
+
+        let request = format!("CHATHISTORY {} {} {} {}", subcmd, channel, reference, limit);
+        let msg: Message = request.parse()?;
+
+        let (reply_sender, reply_receiver) = crossbeam_channel::bounded(1);
+
+        let id = {
+            let mut server = self.write_server(server_id)?;
+            let fetches = &mut server.history_fetches;
+
+            fetches.counter += 1;
+
+            fetches.pending.push(PendingFetch {
+                id: fetches.counter,
+                target: channel.to_owned(),
+                batch: None,
+                msgs: Vec::new(),
+                reply_sender,
+            });
+
+            fetches.counter
+        };
+
+        push_to_outbox(&self.outbox, server_id, LibReaction::RawMsg(msg));
+
+        match reply_receiver.recv_timeout(FETCH_TIMEOUT) {
+            Ok(msgs) => Ok(msgs),
+            Err(_) => {
+                if let Ok(mut server) = self.write_server(server_id) {
+                    server.history_fetches.pending.retain(|f| f.id != id);
+                }
+
+                Err(ErrorKind::QueryTimeout(request).into())
+            }
+        }
+    }
+}
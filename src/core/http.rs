@@ -0,0 +1,491 @@
+use super::metrics;
+use super::webhook;
+use super::ErrorKind;
+use super::Result;
+use super::ServerId;
+use super::State;
+use irc::client::prelude::Client as AatxeClient;
+use std::fmt::Write as FmtWrite;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use url::form_urlencoded;
+use util::fmt::json_str;
+
+/// How long to wait for a client to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The maximum size, in bytes, of each line of a request's head
+const MAX_HEAD_LINE_LEN: u64 = 8 * 1024;
+
+/// The maximum number of header fields that a request may have
+const MAX_HEADERS: usize = 64;
+
+/// The maximum size, in bytes, of a request's body
+const MAX_BODY_LEN: usize = 1024 * 1024;
+
+/// An HTTP request received by the bot's HTTP server
+#[derive(Debug)]
+pub(super) struct Request {
+    pub(super) method: String,
+    pub(super) path: String,
+    headers: Vec<(String, String)>,
+    pub(super) body: Vec<u8>,
+}
+
+impl Request {
+    /// Returns the value of the first header field with the given name, which is matched
+    /// case-insensitively.
+    pub(super) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| &value[..])
+    }
+
+    /// Returns the value of the first parameter with the given name in the request's query
+    /// string, if any.
+    pub(super) fn query_param(&self, name: &str) -> Option<String> {
+        let query = self.path.splitn(2, '?').nth(1)?;
+
+        form_urlencoded::parse(query.as_bytes())
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.into_owned())
+    }
+}
+
+/// An HTTP response to be sent by the bot's HTTP server
+#[derive(Debug)]
+pub(super) struct Response {
+    pub(super) status: u16,
+    pub(super) content_type: &'static str,
+    pub(super) body: String,
+}
+
+impl Response {
+    pub(super) fn text<S>(status: u16, body: S) -> Self
+    where
+        S: Into<String>,
+    {
+        Response {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: body.into(),
+        }
+    }
+
+    fn json(status: u16, body: String) -> Self {
+        Response {
+            status,
+            content_type: "application/json",
+            body,
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            202 => "Accepted",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            500 => "Internal Server Error",
+            503 => "Service Unavailable",
+            _ => "",
+        }
+    }
+}
+
+/// Listens for HTTP requests at the given address, handling each connection in a new thread.
+pub(super) fn http_server_main(state: Arc<State>, addr: SocketAddr) -> Result<()> {
+    let listener = TcpListener::bind(addr)?;
+
+    info!("Listening for HTTP requests at {}.", addr);
+
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(s) => s,
+            Err(e) => {
+                warn!("Failed to accept HTTP connection: {}", e);
+                continue;
+            }
+        };
+
+        let state = state.clone();
+
+        let spawn_result = thread::Builder::new()
+            .name(format!("HTTP[{}]", addr))
+            .spawn(move || {
+                if let Err(e) = handle_connection(&state, stream) {
+                    debug!("Error handling HTTP connection: {}", e);
+                }
+            });
+
+        if let Err(e) = spawn_result {
+            error!("Failed to spawn thread to handle HTTP connection: {}", e);
+        }
+    }
+
+    Ok(())
+}
+
+fn handle_connection(state: &State, mut stream: TcpStream) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+
+    let response = match read_request(&mut BufReader::new(&stream)) {
+        Ok(request) => {
+            trace!("HTTP request: {} {}", request.method, request.path);
+            route(state, &request)
+        }
+        Err(e) => match *e.kind() {
+            ErrorKind::HttpRequestTooLarge => Response::text(413, "Request too large\n"),
+            _ => Response::text(400, format!("Bad request: {}\n", e)),
+        },
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len()
+    )?;
+    stream.write_all(response.body.as_bytes())?;
+    stream.flush()?;
+
+    Ok(())
+}
+
+fn route(state: &State, request: &Request) -> Response {
+    let path = request.path.split('?').next().unwrap_or_default();
+
+    if let Some(webhook) = state.config().http.webhooks.iter().find(|w| w.path == path) {
+        return match &request.method[..] {
+            "POST" => webhook::handle_request(state, webhook, request),
+            _ => Response::text(405, "Method not allowed\n"),
+        };
+    }
+
+    match (&request.method[..], path) {
+        ("GET", "/metrics") if state.config().http.metrics => Response {
+            status: 200,
+            content_type: metrics::CONTENT_TYPE,
+            body: state.metrics.render(state),
+        },
+        (_, "/metrics") if state.config().http.metrics => {
+            Response::text(405, "Method not allowed\n")
+        }
+        ("GET", p) if p.starts_with("/paste/") => match state.hosted_paste(&p["/paste/".len()..]) {
+            Ok(Some(text)) => Response::text(200, text),
+            Ok(None) => Response::text(404, "Not found\n"),
+            Err(e) => Response::text(500, format!("Internal error: {}\n", e)),
+        },
+        ("GET", "/healthz") => healthz(state),
+        ("GET", "/status") => status(state),
+        (_, "/healthz") | (_, "/status") => Response::text(405, "Method not allowed\n"),
+        _ => Response::text(404, "Not found\n"),
+    }
+}
+
+/// The state of the bot's connection to a server, as reported at `/status`
+#[derive(Debug)]
+struct ServerStatus {
+    name: String,
+    address: String,
+
+    /// Whether the bot has finished registering with the server on the current connection
+    registered: bool,
+
+    /// How long ago the bot finished registering with the server on the current connection
+    registered_for: Option<Duration>,
+
+    /// The connection's lag, per `State::server_lag`
+    lag: Option<Duration>,
+
+    /// How much lag to tolerate before considering the connection to have stalled
+    lag_limit: Duration,
+
+    nick: Option<String>,
+    channels: Vec<String>,
+}
+
+impl ServerStatus {
+    fn get(state: &State, server_id: ServerId) -> Result<Self> {
+        let (address, registered_for, lag_limit) = {
+            let server = state.read_server(server_id)?;
+            (
+                server.socket_addr_string.clone(),
+                server.registered_since.map(|t| t.elapsed()),
+                Duration::from_secs(server.aatxe_config.ping_timeout().into()),
+            )
+        };
+
+        let channels = state
+            .with_aatxe_client(server_id, |client| Ok(client.list_channels()))
+            .ok()
+            .and_then(|channels| channels)
+            .unwrap_or_default();
+
+        Ok(ServerStatus {
+            name: state.get_server_config(server_id)?.name.clone(),
+            address,
+            registered: registered_for.is_some(),
+            registered_for,
+            lag: state.server_lag(server_id)?,
+            lag_limit,
+            nick: state.nick(server_id).ok(),
+            channels,
+        })
+    }
+
+    /// Returns why the connection is unhealthy, if it is.
+    fn problem(&self) -> Option<&'static str> {
+        match self.lag {
+            _ if !self.registered => Some("not registered"),
+            Some(lag) if lag > self.lag_limit => Some("stalled"),
+            _ => None,
+        }
+    }
+
+    fn to_json(&self) -> String {
+        let mut output = format!(
+            "{{\"name\":{},\"address\":{},\"registered\":{},\"healthy\":{}",
+            json_str(&self.name),
+            json_str(&self.address),
+            self.registered,
+            self.problem().is_none()
+        );
+
+        if let Some(time) = self.registered_for {
+            let _ = write!(output, ",\"registered_for_seconds\":{}", time.as_secs());
+        }
+
+        if let Some(lag) = self.lag {
+            let _ = write!(output, ",\"lag_milliseconds\":{}", duration_millis(lag));
+        }
+
+        if let Some(ref nick) = self.nick {
+            let _ = write!(output, ",\"nick\":{}", json_str(nick));
+        }
+
+        let channels = self
+            .channels
+            .iter()
+            .map(|c| json_str(c))
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let _ = write!(output, ",\"channels\":[{}]}}", channels);
+
+        output
+    }
+}
+
+fn server_statuses(state: &State) -> Vec<ServerStatus> {
+    state
+        .servers
+        .keys()
+        .filter_map(|&server_id| match ServerStatus::get(state, server_id) {
+            Ok(status) => Some(status),
+            Err(e) => {
+                warn!(
+                    "Failed to determine status of server {:?}: {}",
+                    server_id, e
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+/// Responds with whether the bot is healthy, which is to say that it is not shutting down and
+/// that each of its connections is registered and has not stalled.
+fn healthz(state: &State) -> Response {
+    if state.shutting_down.load(Ordering::SeqCst) {
+        return Response::text(503, "shutting down\n");
+    }
+
+    let problems = server_statuses(state)
+        .iter()
+        .filter_map(|s| s.problem().map(|p| format!("{}: {}\n", s.name, p)))
+        .collect::<String>();
+
+    if problems.is_empty() {
+        Response::text(200, "ok\n")
+    } else {
+        Response::text(503, problems)
+    }
+}
+
+/// Responds with a JSON report of the bot's uptime and the state of each of its connections.
+fn status(state: &State) -> Response {
+    let servers = server_statuses(state)
+        .iter()
+        .map(ServerStatus::to_json)
+        .collect::<Vec<_>>()
+        .join(",");
+
+    Response::json(
+        200,
+        format!(
+            "{{\"uptime_seconds\":{},\"shutting_down\":{},\"servers\":[{}]}}\n",
+            state.started.elapsed().as_secs(),
+            state.shutting_down.load(Ordering::SeqCst),
+            servers
+        ),
+    )
+}
+
+fn duration_millis(d: Duration) -> u64 {
+    d.as_secs() * 1000 + u64::from(d.subsec_millis())
+}
+
+fn read_request<R>(reader: &mut R) -> Result<Request>
+where
+    R: BufRead,
+{
+    let request_line = read_head_line(reader)?;
+    let mut request_line = request_line.split(' ');
+
+    let (method, path) = match (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) {
+        (Some(method), Some(path), Some(version)) if version.starts_with("HTTP/1.") => {
+            (method.to_owned(), path.to_owned())
+        }
+        _ => bail!(ErrorKind::HttpRequestMalformed("request line".into())),
+    };
+
+    let mut headers = Vec::new();
+
+    loop {
+        let line = read_head_line(reader)?;
+
+        if line.is_empty() {
+            break;
+        }
+
+        ensure!(headers.len() < MAX_HEADERS, ErrorKind::HttpRequestTooLarge);
+
+        let mut field = line.splitn(2, ':');
+
+        match (field.next(), field.next()) {
+            (Some(name), Some(value)) if !name.is_empty() => {
+                headers.push((name.to_owned(), value.trim().to_owned()))
+            }
+            _ => bail!(ErrorKind::HttpRequestMalformed("header field".into())),
+        }
+    }
+
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: Vec::new(),
+    };
+
+    let body_len = match request.header("Content-Length") {
+        Some(len) => len
+            .parse::<usize>()
+            .map_err(|_| ErrorKind::HttpRequestMalformed("Content-Length".into()))?,
+        None => 0,
+    };
+
+    ensure!(body_len <= MAX_BODY_LEN, ErrorKind::HttpRequestTooLarge);
+
+    request.body.resize(body_len, 0);
+    reader.read_exact(&mut request.body)?;
+
+    Ok(request)
+}
+
+/// Reads a line of a request's head, without the line terminator.
+fn read_head_line<R>(reader: &mut R) -> Result<String>
+where
+    R: BufRead,
+{
+    let mut line = String::new();
+
+    reader
+        .by_ref()
+        .take(MAX_HEAD_LINE_LEN)
+        .read_line(&mut line)?;
+
+    if !line.ends_with('\n') {
+        if line.len() as u64 >= MAX_HEAD_LINE_LEN {
+            bail!(ErrorKind::HttpRequestTooLarge)
+        } else {
+            bail!(ErrorKind::HttpRequestMalformed("head".into()))
+        }
+    }
+
+    Ok(line.trim_end_matches(&['\r', '\n'][..]).to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn requests() {
+        let mut input: &[u8] = b"GET /metrics?x=1 HTTP/1.1\r\nHost: bot\r\nAccept: */*\r\n\r\n";
+
+        let request = read_request(&mut input).unwrap();
+
+        assert_eq!(request.method, "GET");
+        assert_eq!(request.path, "/metrics?x=1");
+        assert_eq!(request.header("host"), Some("bot"));
+        assert_eq!(request.query_param("x"), Some("1".into()));
+        assert!(request.body.is_empty());
+
+        let mut post: &[u8] = b"POST /hook HTTP/1.1\r\nContent-Length: 2\r\n\r\n{}";
+        assert_eq!(read_request(&mut post).unwrap().body, b"{}");
+
+        let mut truncated: &[u8] = b"GET / HTTP/1.1\r\nHost: bot\r\n";
+        assert!(read_request(&mut truncated).is_err());
+
+        let mut garbage: &[u8] = b"hello\r\n\r\n";
+        assert!(read_request(&mut garbage).is_err());
+    }
+
+    #[test]
+    fn server_status_json() {
+        let mut status = ServerStatus {
+            name: "net".into(),
+            address: "irc.example.org:6697".into(),
+            registered: true,
+            registered_for: Some(Duration::from_secs(90)),
+            lag: Some(Duration::from_millis(1500)),
+            lag_limit: Duration::from_secs(1),
+            nick: Some("bot".into()),
+            channels: vec!["#a".into(), "#b".into()],
+        };
+
+        assert_eq!(status.problem(), Some("stalled"));
+        assert_eq!(
+            status.to_json(),
+            "{\"name\":\"net\",\"address\":\"irc.example.org:6697\",\"registered\":true,\
+             \"healthy\":false,\"registered_for_seconds\":90,\"lag_milliseconds\":1500,\
+             \"nick\":\"bot\",\"channels\":[\"#a\",\"#b\"]}"
+        );
+
+        status.lag = None;
+        assert_eq!(status.problem(), None);
+
+        status.registered = false;
+        assert_eq!(status.problem(), Some("not registered"));
+    }
+}
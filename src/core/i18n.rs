@@ -0,0 +1,345 @@
+//! Localization of the replies that the framework itself sends, such as those to commands used
+//! without sufficient authority, and of those of bot modules that choose to use it.
+//!
+//! Each such reply is identified by a key, such as `unauthorized`, and rendered from the template
+//! given for that key in the locale chosen for the user or channel to which the reply is sent. The
+//! templates of the built-in locale `en` are listed in [`BUILT_IN_MESSAGES`]; the configuration
+//! field `messages` may give templates for other locales, and may override the built-in ones.
+//!
+//! [`BUILT_IN_MESSAGES`]: <constant.BUILT_IN_MESSAGES.html>
+
+use super::irc_comm::channel_setting;
+use super::prefs::PREF_LOCALE;
+use super::MsgDest;
+use super::MsgPrefix;
+use super::Result;
+use super::State;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
+/// The locale whose templates are built in, and in which a reply is rendered if the locale chosen
+/// for it lacks a template for it
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// The templates of the built-in locale `en`, by key
+///
+/// In each template, each placeholder of the form `{name}` is replaced with the value of the
+/// parameter `name` of the reply, and `{{` and `}}` stand for literal braces.
+pub const BUILT_IN_MESSAGES: &[(&str, &str)] = &[
+    (
+        "unauthorized",
+        "My apologies, but you do not appear to have sufficient authority to use my {command} \
+         command.",
+    ),
+    (
+        "parameter unauthorized",
+        "My apologies, but you do not appear to have sufficient authority to use the parameter \
+         {parameter} of my {command} command.",
+    ),
+    ("syntax error", "Syntax error. Try my `help` command."),
+    (
+        "argument missing",
+        "Syntax error: For command {command}, the argument {argument} is required, but it was \
+         not given.",
+    ),
+    (
+        "argument missing outside channel",
+        "Syntax error: When command {command} is used outside of a channel, the argument \
+         {argument} is required, but it was not given.",
+    ),
+    ("error", "Error: {error}"),
+    ("user error", "User error: {error}"),
+    ("internal error", "Internal error: {error}"),
+    (
+        "handling error",
+        "Encountered error while trying to handle message: {error}",
+    ),
+    (
+        "command disabled",
+        "The command {command} has been disabled because it has malfunctioned too many times.",
+    ),
+    (
+        "command not enabled here",
+        "The command {command} is not enabled in this channel.",
+    ),
+    (
+        "command timed out",
+        "The command {command} took too long, and has been given up on.",
+    ),
+    (
+        "unknown command",
+        "Unknown command `{command}`; did you mean {suggestions}?",
+    ),
+    ("alternatives", "{others}, or {last}"),
+    (
+        "help: one thing at a time",
+        "Please ask for help with one thing at a time.",
+    ),
+    ("help: command not found", "Command {command} not found."),
+    ("help: command", "= Help for command {command}:"),
+    (
+        "help: command details",
+        "- [module {module}, auth level {auth level}]",
+    ),
+    ("help: command syntax", "- Syntax: {command} {syntax}"),
+    ("help: commands", "Available commands: {commands}"),
+    ("help: lists", "Available lists: {lists}"),
+    (
+        "help: list not found",
+        "List {list} not found. Available lists: {lists}",
+    ),
+    (
+        "help: introduction",
+        "For help with a command named 'foo', try `help cmd: foo`.",
+    ),
+    (
+        "help: introduction to lists",
+        "To see a list of all available commands, try `help list: commands`.",
+    ),
+    (
+        "help: documentation",
+        "For this bot software's documentation, including an introduction to the command syntax, \
+         see <{homepage}>",
+    ),
+    (
+        "more: one message",
+        "({count} more message; use the command `more` to see it)",
+    ),
+    (
+        "more: messages",
+        "({count} more messages; use the command `more` to see them)",
+    ),
+    ("more: nothing", "There is nothing more to show."),
+    (
+        "paste: link",
+        "The output is too long to show here; see {url}",
+    ),
+    ("preferences", "Your preferences: {preferences}"),
+    (
+        "no preferences",
+        "You have set no preferences. Those that you may set: {preferences}",
+    ),
+    ("preference", "Your preference {name} is {value}."),
+    (
+        "preference not set",
+        "You have not set the preference {name}.",
+    ),
+    ("preference set", "Your preference {name} is now {value}."),
+    ("preference unset", "Your preference {name} has been unset."),
+    (
+        "auth: use privately",
+        "Please use my `auth` command in a private message.",
+    ),
+    (
+        "auth: unavailable",
+        "Authentication by challenge and response is not available.",
+    ),
+    (
+        "auth: challenge",
+        "Your challenge is {challenge}. Within five minutes, answer it with `auth RESPONSE`, \
+         where RESPONSE is the HMAC-SHA-256 of the challenge keyed with the shared secret, in \
+         hexadecimal.",
+    ),
+    (
+        "auth: granted",
+        "You are authenticated as an administrator for the next {seconds} seconds.",
+    ),
+    (
+        "auth: denied",
+        "That response is incorrect, or there was no challenge to answer. Use `auth` to request \
+         a new challenge.",
+    ),
+    (
+        "stats: bot",
+        "Version {version} (commit {commit}); up for {uptime}; using {memory} of memory; modules loaded: \
+         {modules}.",
+    ),
+    (
+        "stats: connected",
+        "{server}: connected for {age}, with {lag} of lag; {received} messages received and \
+         {sent} sent.",
+    ),
+    (
+        "stats: disconnected",
+        "{server}: not connected; {received} messages received and {sent} sent.",
+    ),
+    (
+        "stats: command",
+        "{command}: used {uses} times, {errors} of them unsuccessfully ({timeouts} timed out); run \
+         time {p50} (median), {p90} (90th percentile), {p99} (99th percentile).",
+    ),
+    ("stats: command unused", "{command}: never used."),
+    ("stats: unknown", "an unknown amount"),
+    (
+        "flood: warning",
+        "{nick}: Please slow down. I shall ignore your commands for {seconds} seconds.",
+    ),
+    ("flood: kick reason", "Flooding"),
+];
+
+impl State {
+    /// Returns the names of the locales in which the bot may reply: the built-in locale `en` and
+    /// those for which the configuration field `messages` gives templates.
+    pub fn locales(&self) -> Vec<String> {
+        let mut locales = vec![DEFAULT_LOCALE.to_owned()];
+
+        locales.extend(
+            self.config()
+                .messages
+                .keys()
+                .filter(|locale| *locale != DEFAULT_LOCALE)
+                .cloned(),
+        );
+
+        locales
+    }
+
+    /// Returns the locale in which the bot is to reply to the given message destination and user:
+    /// that which the user has chosen with the preference `locale` (see [`State::user_pref`]),
+    /// that configured for the channel, or the configured default, whichever is found first.
+    ///
+    /// [`State::user_pref`]: <#method.user_pref>
+    pub fn locale(&self, dest: MsgDest, user: MsgPrefix) -> Result<String> {
+        if let Some(locale) = self.user_pref(dest.server_id, user, PREF_LOCALE)? {
+            if is_known_locale(&self.config().messages, &locale) {
+                return Ok(locale);
+            }
+        }
+
+        Ok(channel_setting(self, dest.server_id, dest.target, |chan| {
+            chan.locale.clone()
+        })?
+        .unwrap_or_else(|| self.config().locale.clone()))
+    }
+
+    /// Renders the reply with the given key and parameters in the locale in which the bot is to
+    /// reply to the given message destination and user (see [`State::locale`]).
+    ///
+    /// The template for the reply is that given for the locale in the configuration field
+    /// `messages`, or else that given there for the locale `en`, or else the built-in one (see
+    /// [`BUILT_IN_MESSAGES`]). A module may give its replies keys of its own, for which operators
+    /// may give templates in the configuration; a key for which no template is found is rendered
+    /// as it is.
+    ///
+    /// [`BUILT_IN_MESSAGES`]: <constant.BUILT_IN_MESSAGES.html>
+    /// [`State::locale`]: <#method.locale>
+    pub fn localize(
+        &self,
+        dest: MsgDest,
+        user: MsgPrefix,
+        key: &str,
+        params: &[(&str, &str)],
+    ) -> String {
+        let locale = self.locale(dest, user).unwrap_or_else(|e| {
+            warn!("Failed to determine the locale of a reply: {}", e);
+            DEFAULT_LOCALE.to_owned()
+        });
+
+        let config = self.config();
+
+        let template = [&locale[..], DEFAULT_LOCALE]
+            .iter()
+            .filter_map(|locale| {
+                config
+                    .messages
+                    .iter()
+                    .find(|(l, _)| l.eq_ignore_ascii_case(locale))
+            })
+            .filter_map(|(_, templates)| templates.get(key))
+            .map(|template| Cow::Owned(template.clone()))
+            .next()
+            .unwrap_or(Cow::Borrowed(built_in(key)));
+
+        render(&template, params)
+    }
+}
+
+/// Returns the built-in template for the given key, or the key itself if there is none.
+pub(super) fn built_in(key: &str) -> &str {
+    BUILT_IN_MESSAGES
+        .iter()
+        .find(|&&(k, _)| k == key)
+        .map_or(key, |&(_, template)| template)
+}
+
+/// Replaces each placeholder `{name}` in the given template with the value of the parameter
+/// `name`, if there is such a parameter, and `{{` and `}}` with single braces.
+pub(super) fn render(template: &str, params: &[(&str, &str)]) -> String {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(i) = rest.find(&['{', '}'][..]) {
+        rendered.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        if rest.starts_with("{{") || rest.starts_with("}}") {
+            rendered.push_str(&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+
+        let param = if rest.starts_with('{') {
+            rest.find('}').and_then(|end| {
+                let name = &rest[1..end];
+
+                params
+                    .iter()
+                    .find(|&&(n, _)| n == name)
+                    .map(|&(_, value)| (value, end + 1))
+            })
+        } else {
+            None
+        };
+
+        match param {
+            Some((value, len)) => {
+                rendered.push_str(value);
+                rest = &rest[len..];
+            }
+            None => {
+                rendered.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Checks that the given locale is one of `en` and the locales given in the configuration field
+/// `messages`.
+pub(super) fn is_known_locale(
+    messages: &BTreeMap<String, BTreeMap<String, String>>,
+    locale: &str,
+) -> bool {
+    locale.eq_ignore_ascii_case(DEFAULT_LOCALE)
+        || messages.keys().any(|l| l.eq_ignore_ascii_case(locale))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendering() {
+        let params = [("command", "\"ping\""), ("error", "oops")];
+
+        assert_eq!(render("Error: {error}", &params), "Error: oops");
+        assert_eq!(
+            render("{command} {{error}} {missing} }{", &params),
+            "\"ping\" {error} {missing} }{"
+        );
+        assert_eq!(render("{error", &params), "{error");
+        assert_eq!(render("", &params), "");
+
+        let mut keys = BUILT_IN_MESSAGES
+            .iter()
+            .map(|&(key, _)| key)
+            .collect::<Vec<_>>();
+        let len = keys.len();
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), len);
+    }
+}
@@ -0,0 +1,134 @@
+use super::MsgTags;
+use super::Result;
+use super::ServerId;
+use super::State;
+use irc::proto::Message;
+
+/// A message received from a server, as passed to the input filters that modules register with
+/// [`ModuleBuilder::input_filter`]
+///
+/// [`ModuleBuilder::input_filter`]: <struct.ModuleBuilder.html#method.input_filter>
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct IncomingMsg {
+    pub server_id: ServerId,
+
+    /// The message's IRCv3 message tags, which handlers of the message, such as bot commands, see
+    /// in its [`MsgMetadata`], and to which a filter may add its own annotations
+    ///
+    /// [`MsgMetadata`]: <struct.MsgMetadata.html>
+    pub tags: MsgTags,
+
+    /// The message's prefix, e.g., `nick!user@host`, if it has one
+    pub prefix: Option<String>,
+
+    /// The message's command, e.g., `PRIVMSG` or `001`
+    pub command: String,
+
+    /// The message's parameters, the last of which may contain spaces
+    pub params: Vec<String>,
+}
+
+/// What is to become of a message that an input filter has been given
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum InputVerdict {
+    /// Pass the message, as the filter may have rewritten it, on to the next filter, or else
+    /// handle it.
+    Handle,
+
+    /// Ignore the message, as though it had not been received.
+    Drop,
+
+    /// Pass the given messages, in order, on to the next filter, or else handle them, in place of
+    /// the message that the filter was given.
+    Replace(Vec<IncomingMsg>),
+}
+
+impl IncomingMsg {
+    fn from_aatxe(server_id: ServerId, msg: &Message) -> Self {
+        let line = String::from(&msg.command);
+        let (head, trailing) = match line.find(" :") {
+            Some(i) => (&line[..i], Some(&line[i + 2..])),
+            None => (&line[..], None),
+        };
+        let mut words = head.split(' ').filter(|word| !word.is_empty());
+
+        IncomingMsg {
+            server_id,
+            tags: MsgTags::from_aatxe(msg.tags.clone()),
+            prefix: msg.prefix.clone(),
+            command: words.next().unwrap_or_default().to_owned(),
+            params: words.chain(trailing).map(ToOwned::to_owned).collect(),
+        }
+    }
+
+    fn to_aatxe(&self) -> Result<Message> {
+        let (suffix, args) = match self.params.split_last() {
+            Some((last, rest)) => (Some(&last[..]), rest.iter().map(|a| &a[..]).collect()),
+            None => (None, Vec::new()),
+        };
+
+        Ok(Message::with_tags(
+            self.tags.to_aatxe(),
+            self.prefix.as_ref().map(|p| &p[..]),
+            &self.command,
+            args,
+            suffix,
+        )?)
+    }
+}
+
+/// Passes the given message received from the given server through the loaded modules' input
+/// filters, returning the messages that remain to be handled.
+pub(super) fn filter_input(state: &State, server_id: ServerId, msg: Message) -> Vec<Message> {
+    if !state.has_input_filters() {
+        return vec![msg];
+    }
+
+    let original = IncomingMsg::from_aatxe(server_id, &msg);
+
+    let filtered = state.run_input_filters(original.clone());
+
+    if filtered == [original] {
+        return vec![msg];
+    }
+
+    filtered
+        .iter()
+        .filter_map(|incoming| match incoming.to_aatxe() {
+            Ok(msg) => Some(msg),
+            Err(e) => {
+                warn!(
+                    "Discarding malformed message {:?} from input filters: {}",
+                    incoming, e
+                );
+                None
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ServerConfigIndex;
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        let server_id = ServerId::new(ServerConfigIndex(0));
+        let msg: Message = "@+spam=no :nick!user@host PRIVMSG #chan :hello  world\r\n"
+            .parse()
+            .unwrap();
+
+        let incoming = IncomingMsg::from_aatxe(server_id, &msg);
+        assert_eq!(incoming.tags, MsgTags::new().with("+spam", "no"));
+        assert_eq!(incoming.prefix, Some("nick!user@host".to_owned()));
+        assert_eq!(incoming.command, "PRIVMSG");
+        assert_eq!(incoming.params, vec!["#chan", "hello  world"]);
+        assert_eq!(incoming.to_aatxe().unwrap(), msg);
+
+        let msg: Message = "PING server".parse().unwrap();
+        let incoming = IncomingMsg::from_aatxe(server_id, &msg);
+        assert_eq!(incoming.params, vec!["server"]);
+        assert_eq!(incoming.to_aatxe().unwrap(), msg);
+    }
+}
@@ -1,23 +1,46 @@
+use super::aliases;
+use super::batch;
 use super::bot_cmd;
-use super::irc_msgs::is_msg_to_nick;
+use super::chan_log;
+use super::config;
+use super::config::InvitePolicy;
+use super::dcc;
+use super::flood;
+use super::input;
+use super::irc_msgs::parse_command_line;
+use super::irc_msgs::Addressing;
+use super::irc_msgs::Ctcp;
 use super::irc_msgs::OwningMsgPrefix;
 use super::irc_send::push_to_outbox;
 use super::irc_send::OutboxPort;
-use super::parse_msg_to_nick;
+use super::labeled;
+use super::lag;
+use super::pager;
+use super::paste;
 use super::pkg_info;
+use super::presence;
 use super::reaction::LibReaction;
+use super::shadow_ban;
+use super::spawn_thread;
+use super::sts;
 use super::trigger;
+use super::users;
 use super::BotCmdResult;
 use super::ErrorKind;
 use super::MsgDest;
 use super::MsgMetadata;
 use super::MsgPrefix;
+use super::MsgTags;
+use super::Presence;
 use super::Reaction;
+use super::ReplyRoute;
 use super::Result;
 use super::Server;
 use super::ServerId;
 use super::State;
 use irc::client::prelude as aatxe;
+use irc::client::prelude::Client as AatxeClient;
+use irc::proto::CapSubCommand;
 use irc::proto::Message;
 use itertools::Itertools;
 use smallvec::SmallVec;
@@ -29,10 +52,36 @@ use std::iter;
 use std::sync::Arc;
 use std::sync::RwLockWriteGuard;
 use std::thread;
+use std::time::Instant;
+use util;
+use util::format;
 
 const UPDATE_MSG_PREFIX_STR: &'static str = "!!! UPDATE MESSAGE PREFIX !!!";
 
 impl State {
+    /// Sends the given text by private message to each of the bot's administrators for whom a
+    /// nickname is configured, on the given server.
+    pub(super) fn notify_admins(&self, server_id: ServerId, text: &str) -> Result<()> {
+        for nick in self.config().admins.iter().filter_map(|a| a.nick.as_ref()) {
+            self.send_privmsg(server_id, nick, text)?;
+        }
+
+        Ok(())
+    }
+
+    /// Queues the given text to be sent by `PRIVMSG` to the given channel or user on the given
+    /// server, splitting it into multiple messages if it is too long for one.
+    ///
+    /// This allows modules to send messages other than in reply to the message that they are
+    /// handling.
+    pub fn send_privmsg(&self, server_id: ServerId, target: &str, text: &str) -> Result<()> {
+        let dest = MsgDest { server_id, target };
+
+        push_to_outbox(&self.outbox, server_id, self.compose_msg(dest, "", text)?);
+
+        Ok(())
+    }
+
     fn compose_msg<S1, S2>(
         &self,
         dest: MsgDest,
@@ -43,6 +92,8 @@ impl State {
         S1: Borrow<str>,
         S2: Display,
     {
+        let msg = self.anti_highlight(dest, msg.to_string())?;
+
         let final_msg = format!(
             "{}{}{}",
             addressee.borrow(),
@@ -56,12 +107,75 @@ impl State {
 
         info!("Sending message to {:?}: {:?}", dest, final_msg);
 
+        self.compose_privmsgs(dest, &final_msg, None)
+    }
+
+    fn compose_action<S>(&self, dest: MsgDest, action: S) -> Result<Option<LibReaction<Message>>>
+    where
+        S: Display,
+    {
+        let action = self.anti_highlight(dest, action.to_string())?;
+
+        info!("Sending action to {:?}: {:?}", dest, action);
+
+        self.compose_privmsgs(dest, &action, Some("ACTION"))
+    }
+
+    /// Inserts a zero-width space into each nickname of a member of the given channel that appears
+    /// in the given text, if the channel's `anti-highlight` setting is enabled, so that sending the
+    /// text won't highlight those users.
+    fn anti_highlight(&self, dest: MsgDest, text: String) -> Result<String> {
+        let enabled = channel_setting(self, dest.server_id, dest.target, |chan| {
+            Some(chan.anti_highlight)
+        })?
+        .unwrap_or(false);
+
+        if !enabled {
+            return Ok(text);
+        }
+
+        let users = self.with_aatxe_client(dest.server_id, |client| {
+            Ok(client.list_users(dest.target).unwrap_or_default())
+        })?;
+
+        Ok(util::zwsp_munge(&text, users.iter().map(|user| user.get_nickname())).collect())
+    }
+
+    /// Composes one or more `PRIVMSG`s carrying the given text, wrapping it as necessary, and, if
+    /// a CTCP command is given, framing each `PRIVMSG`'s text as a CTCP message with that command.
+    fn compose_privmsgs(
+        &self,
+        dest: MsgDest,
+        text: &str,
+        ctcp_command: Option<&str>,
+    ) -> Result<Option<LibReaction<Message>>> {
+        let text = if channel_setting(self, dest.server_id, dest.target, |chan| {
+            Some(chan.strip_colors_on_output)
+        })?
+        .unwrap_or(false)
+        {
+            format::strip_formatting(text)
+        } else {
+            Cow::Borrowed(text)
+        };
+
+        // Two delimiters and a space separating the CTCP command from the text.
+        let ctcp_overhead = ctcp_command.map(|cmd| cmd.len() + 3).unwrap_or(0);
+
         let mut wrapped_msg = SmallVec::<[_; 1]>::new();
 
-        for input_line in final_msg.lines() {
-            wrap_msg(self, dest, input_line, |output_line| {
+        for input_line in text.lines() {
+            wrap_msg(self, dest, input_line, ctcp_overhead, |output_line| {
+                let output_line = match ctcp_command {
+                    Some(command) => Ctcp {
+                        command,
+                        params: output_line,
+                    }
+                    .to_string(),
+                    None => output_line.to_owned(),
+                };
                 wrapped_msg.push(LibReaction::RawMsg(
-                    aatxe::Command::PRIVMSG(dest.target.to_owned(), output_line.to_owned()).into(),
+                    aatxe::Command::PRIVMSG(dest.target.to_owned(), output_line).into(),
                 ));
                 Ok(())
             })?;
@@ -114,11 +228,12 @@ impl State {
                     user: _,
                     host: _,
                 },
+            tags: _,
         }: &MsgMetadata<'a>,
     ) -> Result<MsgDest<'a>> {
         Ok(MsgDest {
             server_id,
-            target: if target == self.nick(server_id)? {
+            target: if self.is_own_nick(server_id, target)? {
                 // The message was sent to the bot in one-to-one messaging, so replies should be
                 // sent in one-to-one messaging to the sender.
                 nick.ok_or(ErrorKind::ReceivedMsgHasBadPrefix)?
@@ -140,7 +255,7 @@ impl State {
     pub fn privmsg_content_max_len(&self, MsgDest { server_id, target }: MsgDest) -> Result<usize> {
         // :nick!user@host PRIVMSG target :message
         // :nick!user@host NOTICE target :message
-        let raw_len_limit = 512;
+        let raw_len_limit = self.read_server(server_id)?.capabilities.linelen;
         let punctuation_len = {
             let line_terminator_len = 2;
             let spaces = 3;
@@ -153,11 +268,13 @@ impl State {
     }
 }
 
-fn wrap_msg<F>(state: &State, msg_dest: MsgDest, msg: &str, mut f: F) -> Result<()>
+/// Splits the given message into pieces that fit in `PRIVMSG`s to the given destination, leaving
+/// room in each for `overhead` further bytes, and passes each piece to the given function.
+fn wrap_msg<F>(state: &State, msg_dest: MsgDest, msg: &str, overhead: usize, mut f: F) -> Result<()>
 where
     F: FnMut(&str) -> Result<()>,
 {
-    let msg_len_limit = state.privmsg_content_max_len(msg_dest)?;
+    let msg_len_limit = state.privmsg_content_max_len(msg_dest)? - overhead;
 
     if msg.len() < msg_len_limit {
         return f(msg);
@@ -205,41 +322,160 @@ where
 fn handle_reaction(
     state: &Arc<State>,
     server_id: ServerId,
+    outbox: &OutboxPort,
     prefix: OwningMsgPrefix,
     target: &str,
     reaction: Reaction,
-    bot_nick: String,
+    route: ReplyRoute,
 ) -> Result<Option<LibReaction<Message>>> {
-    let (reply_target, reply_addressee) = if target == bot_nick {
-        (prefix.parse().nick.unwrap(), "")
-    } else {
-        (target, prefix.parse().nick.unwrap_or(""))
+    let reply_privately = match route {
+        ReplyRoute::SameDest => false,
+        ReplyRoute::Notice | ReplyRoute::Private => true,
+        ReplyRoute::PrivateIfOver(max_lines) => reaction_line_count(&reaction) > max_lines,
     };
 
+    let (reply_target, reply_addressee) =
+        if reply_privately || state.is_own_nick(server_id, target)? {
+            (
+                prefix
+                    .parse()
+                    .nick
+                    .ok_or(ErrorKind::ReceivedMsgHasBadPrefix)?,
+                "",
+            )
+        } else {
+            (target, prefix.parse().nick.unwrap_or(""))
+        };
+
     let reply_dest = MsgDest {
         server_id,
         target: reply_target,
     };
 
-    match reaction {
+    let origin = MsgDest { server_id, target };
+
+    let nick = prefix.parse().nick.map(ToOwned::to_owned);
+
+    let output = match reaction {
         Reaction::None => Ok(None),
         Reaction::Msg(s) => state.compose_msg(reply_dest, "", &s),
         Reaction::Msgs(a) => state.compose_msgs(reply_dest, "", a.iter()),
         Reaction::Reply(s) => state.compose_msg(reply_dest, reply_addressee, &s),
         Reaction::Replies(a) => state.compose_msgs(reply_dest, reply_addressee, a.iter()),
         Reaction::RawMsg(s) => Ok(Some(LibReaction::RawMsg(s.parse()?))),
+        Reaction::Action(s) => state.compose_action(reply_dest, &s),
+        Reaction::OfferDccChat => dcc::offer_chat(state, server_id, outbox, prefix.clone()),
+        Reaction::OfferFile(path) => dcc::offer_file(state, prefix.clone(), path),
+        Reaction::Disconnect(server_id) => state.disconnect(server_id, None).map(|()| None),
+        Reaction::Reconnect(server_id) => state.reconnect(server_id, None).map(|()| None),
         Reaction::Quit(msg) => Ok(Some(mk_quit(msg))),
+        Reaction::NextPage => match nick {
+            Some(_) => match pager::next_page(state, origin, prefix.parse())? {
+                Some(page) => return Ok(Some(page)),
+                None => state.compose_msg(
+                    reply_dest,
+                    reply_addressee,
+                    state.localize(origin, prefix.parse(), "more: nothing", &[]),
+                ),
+            },
+            None => Ok(None),
+        },
+        Reaction::WithTags(reaction, tags) => {
+            if let Some((name, _)) = tags.iter().find(|&(name, _)| !name.starts_with('+')) {
+                bail!(ErrorKind::InvalidClientTag(name.to_owned()))
+            }
+
+            let output =
+                handle_reaction(state, server_id, outbox, prefix, target, *reaction, route)?;
+
+            return if state.capability_enabled(server_id, "message-tags")? {
+                Ok(output.map(|output| attach_tags(output, &tags)))
+            } else {
+                Ok(output)
+            };
+        }
+    }?;
+
+    let output = if route == ReplyRoute::Notice {
+        output.map(into_notices)
+    } else {
+        output
+    };
+
+    match (output, nick) {
+        (Some(output), Some(_)) => {
+            let output = paste::offload(state, origin, prefix.parse(), output);
+            pager::paginate(state, origin, prefix.parse(), output).map(Some)
+        }
+        (output, _) => Ok(output),
+    }
+}
+
+/// Returns the number of lines of text that the given reaction would send as messages.
+fn reaction_line_count(reaction: &Reaction) -> usize {
+    match *reaction {
+        Reaction::Msg(ref s) | Reaction::Reply(ref s) => s.lines().count(),
+        Reaction::Msgs(ref a) | Reaction::Replies(ref a) => {
+            a.iter().map(|s| s.lines().count()).sum()
+        }
+        Reaction::WithTags(ref reaction, _) => reaction_line_count(reaction),
+        _ => 0,
+    }
+}
+
+/// Turns the `PRIVMSG`s, other than CTCP messages, in the given output into `NOTICE`s.
+fn into_notices(output: LibReaction<Message>) -> LibReaction<Message> {
+    match output {
+        LibReaction::RawMsg(mut msg) => {
+            let notice = match msg.command {
+                aatxe::Command::PRIVMSG(ref target, ref text) if !text.starts_with('\u{1}') => {
+                    Some(aatxe::Command::NOTICE(target.clone(), text.clone()))
+                }
+                _ => None,
+            };
+
+            if let Some(notice) = notice {
+                msg.command = notice;
+            }
+
+            LibReaction::RawMsg(msg)
+        }
+        LibReaction::Multi(outputs) => {
+            LibReaction::Multi(outputs.into_iter().map(into_notices).collect())
+        }
     }
 }
 
-fn handle_bot_command_or_trigger(
+/// Attaches the given message tags to every message in the given output.
+fn attach_tags(output: LibReaction<Message>, tags: &MsgTags) -> LibReaction<Message> {
+    match output {
+        LibReaction::RawMsg(mut msg) => {
+            msg.tags = tags.to_aatxe();
+            LibReaction::RawMsg(msg)
+        }
+        LibReaction::Multi(outputs) => LibReaction::Multi(
+            outputs
+                .into_iter()
+                .map(|output| attach_tags(output, tags))
+                .collect(),
+        ),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) fn handle_bot_command_or_trigger(
     state: &Arc<State>,
     server_id: ServerId,
+    outbox: &OutboxPort,
     prefix: OwningMsgPrefix,
+    tags: &MsgTags,
     target: String,
     msg: String,
-    bot_nick: String,
+    is_action: bool,
+    addressed: bool,
 ) -> Option<LibReaction<Message>> {
+    let msg = incoming_text(state, msg);
+
     let reaction = (|| {
         let metadata = MsgMetadata {
             prefix: prefix.parse(),
@@ -247,74 +483,177 @@ fn handle_bot_command_or_trigger(
                 server_id,
                 target: &target,
             },
+            tags,
         };
 
-        let cmd_ln = parse_msg_to_nick(&msg, metadata.dest.target, &bot_nick).unwrap_or("");
+        if !addressed {
+            let reaction = trigger::run_any_matching(state, &msg, &metadata, is_action, false)?
+                .map(|r| bot_command_reaction(state, &metadata, "<trigger>", r))
+                .unwrap_or(Reaction::None);
+
+            return Ok((reaction, ReplyRoute::default()));
+        }
 
-        let mut cmd_name_and_args = cmd_ln.splitn(2, char::is_whitespace);
+        let cmd_ln = command_line(state, server_id, metadata.dest.target, &msg)?.unwrap_or("");
+
+        // Triggers are matched against the message as it was sent, rather than as any alias
+        // with which it begins is expanded.
+        let expanded_cmd_ln = if is_action {
+            Cow::Borrowed(cmd_ln)
+        } else {
+            aliases::resolve(state, server_id, cmd_ln)?
+        };
+
+        let mut cmd_name_and_args = expanded_cmd_ln.splitn(2, char::is_whitespace);
         let cmd_name = cmd_name_and_args.next().unwrap_or("");
         let cmd_args = cmd_name_and_args.next().unwrap_or("").trim();
 
-        if let Some(r) = bot_cmd::run(state, cmd_name, cmd_args, &metadata)? {
-            Ok(bot_command_reaction(cmd_name, r))
-        } else if let Some(r) = trigger::run_any_matching(state, cmd_ln, &metadata)? {
-            Ok(bot_command_reaction("<trigger>", r))
-        } else {
-            Ok(Reaction::None)
+        if is_action {
+            // Actions are not treated as bot commands.
+        } else if let Some(r) = bot_cmd::run(state, cmd_name, cmd_args, &metadata)? {
+            return Ok((
+                bot_command_reaction(state, &metadata, cmd_name, r),
+                state.user_reply_route(
+                    server_id,
+                    metadata.prefix,
+                    state.reply_route(server_id, cmd_name)?,
+                )?,
+            ));
         }
+
+        if let Some(r) = trigger::run_any_matching(state, cmd_ln, &metadata, is_action, true)? {
+            return Ok((
+                bot_command_reaction(state, &metadata, "<trigger>", r),
+                ReplyRoute::default(),
+            ));
+        }
+
+        if is_action || cmd_name.is_empty() {
+            // The message wasn't addressed to the bot as a command.
+        } else if let Some(r) = state.run_unknown_command_handlers(&metadata, cmd_name, cmd_args) {
+            return Ok((
+                bot_command_reaction(state, &metadata, cmd_name, r),
+                ReplyRoute::default(),
+            ));
+        } else if state.config().suggest_commands {
+            if let Some(s) = bot_cmd::suggest_commands(state, cmd_name, &metadata)? {
+                return Ok((Reaction::Reply(s.into()), ReplyRoute::default()));
+            }
+        }
+
+        Ok((Reaction::None, ReplyRoute::default()))
     })();
 
-    match reaction
-        .and_then(|reaction| handle_reaction(state, server_id, prefix, &target, reaction, bot_nick))
-    {
+    let invoker = prefix.clone();
+
+    let reaction = match reaction.and_then(|(reaction, route)| {
+        handle_reaction(state, server_id, outbox, prefix, &target, reaction, route)
+    }) {
         Ok(r) => r,
-        Err(e) => Some(LibReaction::RawMsg(
-            aatxe::Command::PRIVMSG(
-                target,
-                format!("Encountered error while trying to handle message: {}", e),
-            )
-            .into(),
-        )),
+        Err(e) => {
+            let text = state.localize(
+                MsgDest {
+                    server_id,
+                    target: &target,
+                },
+                invoker.parse(),
+                "handling error",
+                &[("error", &e.to_string())],
+            );
+
+            Some(LibReaction::RawMsg(
+                aatxe::Command::PRIVMSG(target.clone(), text).into(),
+            ))
+        }
+    };
+
+    shadow_ban::divert(state, server_id, &invoker, &target, reaction)
+}
+
+/// Returns the given text of a message sent to the bot, with any formatting codes removed if the
+/// configuration so specifies.
+fn incoming_text(state: &State, text: String) -> String {
+    if state.config().strip_formatting {
+        match format::strip_formatting(&text) {
+            Cow::Borrowed(_) => {}
+            Cow::Owned(stripped) => return stripped,
+        }
     }
+
+    text
 }
 
-fn bot_command_reaction(cmd_name: &str, result: BotCmdResult) -> Reaction {
-    let cmd_result = match result {
-        BotCmdResult::Ok(r) => Ok(r),
-        BotCmdResult::Unauthorized => Err(format!(
-            "My apologies, but you do not appear to have sufficient \
-             authority to use my {:?} command.",
-            cmd_name
-        )
-        .into()),
-        BotCmdResult::ParamUnauthorized(param_name) => Err(format!(
-            "My apologies, but you do not appear to have sufficient \
-             authority to use the parameter {:?} of my {:?} command.",
-            param_name, cmd_name
-        )
-        .into()),
-        BotCmdResult::SyntaxErr => Err("Syntax error. Try my `help` command.".into()),
-        BotCmdResult::ArgMissing(arg_name) => Err(format!(
-            "Syntax error: For command {:?}, the argument {:?} \
-             is required, but it was not given.",
-            cmd_name, arg_name
-        )
-        .into()),
-        BotCmdResult::ArgMissing1To1(arg_name) => Err(format!(
-            "Syntax error: When command {:?} is used \
-             outside of a channel, the argument {:?} is \
-             required, but it was not given.",
-            cmd_name, arg_name
+/// Returns the command line addressed to the bot in the given message sent to the given target,
+/// if the message is addressed to the bot in any of the ways that the configuration allows.
+pub(super) fn command_line<'msg>(
+    state: &State,
+    server_id: ServerId,
+    target: &str,
+    msg: &'msg str,
+) -> Result<Option<&'msg str>> {
+    let config = state.config();
+
+    let sigils = channel_setting(state, server_id, target, |chan| {
+        chan.command_prefixes.clone()
+    })?
+    .unwrap_or_else(|| config.command_prefixes.clone());
+
+    Ok(parse_command_line(
+        state.casemapping(server_id)?,
+        msg,
+        target,
+        &state.nick(server_id)?,
+        Addressing {
+            sigils: &sigils,
+            by_nick: config.address_by_nick,
+            bare_in_private: config.bare_commands_in_private,
+        },
+    ))
+}
+
+fn bot_command_reaction(
+    state: &State,
+    metadata: &MsgMetadata,
+    cmd_name: &str,
+    result: BotCmdResult,
+) -> Reaction {
+    let cmd_name = format!("{:?}", cmd_name);
+    let localize = |key, params: &[(&str, &str)]| {
+        Reaction::Msg(
+            state
+                .localize(metadata.dest, metadata.prefix, key, params)
+                .into(),
         )
-        .into()),
-        BotCmdResult::LibErr(e) => Err(format!("Error: {}", e).into()),
-        BotCmdResult::UserErrMsg(s) => Err(format!("User error: {}", s).into()),
-        BotCmdResult::BotErrMsg(s) => Err(format!("Internal error: {}", s).into()),
     };
 
-    match cmd_result {
-        Ok(r) => r,
-        Err(s) => Reaction::Msg(s),
+    match result {
+        BotCmdResult::Ok(r) => r,
+        BotCmdResult::Unauthorized => localize("unauthorized", &[("command", &cmd_name)]),
+        BotCmdResult::ParamUnauthorized(param_name) => localize(
+            "parameter unauthorized",
+            &[
+                ("command", &cmd_name),
+                ("parameter", &format!("{:?}", param_name)),
+            ],
+        ),
+        BotCmdResult::SyntaxErr => localize("syntax error", &[]),
+        BotCmdResult::ArgMissing(arg_name) => localize(
+            "argument missing",
+            &[
+                ("command", &cmd_name),
+                ("argument", &format!("{:?}", arg_name)),
+            ],
+        ),
+        BotCmdResult::ArgMissing1To1(arg_name) => localize(
+            "argument missing outside channel",
+            &[
+                ("command", &cmd_name),
+                ("argument", &format!("{:?}", arg_name)),
+            ],
+        ),
+        BotCmdResult::LibErr(e) => localize("error", &[("error", &e.to_string())]),
+        BotCmdResult::UserErrMsg(s) => localize("user error", &[("error", &s)]),
+        BotCmdResult::BotErrMsg(s) => localize("internal error", &[("error", &s)]),
     }
 }
 
@@ -372,16 +711,37 @@ pub(super) fn handle_msg(
         }
     };
 
+    for msg in input::filter_input(state, server_id, msg) {
+        handle_filtered_msg(state, server_id, outbox, msg)?;
+    }
+
+    Ok(())
+}
+
+/// Handles a message received from the given server, as the modules' input filters have left it.
+fn handle_filtered_msg(
+    state: &Arc<State>,
+    server_id: ServerId,
+    outbox: &OutboxPort,
+    msg: Message,
+) -> Result<()> {
+    labeled::handle_msg(state, server_id, &msg)?;
+
+    if batch::handle_msg(state, server_id, &msg)? {
+        return Ok(());
+    }
+
     match msg {
         Message {
             command: aatxe::Command::PRIVMSG(target, msg),
             prefix,
-            ..
+            tags,
         } => handle_privmsg(
             state,
             server_id,
             outbox,
             OwningMsgPrefix::from_string(prefix.unwrap_or_default()),
+            MsgTags::from_aatxe(tags),
             target,
             msg,
         ),
@@ -404,6 +764,135 @@ pub(super) fn handle_msg(
             push_to_outbox(outbox, server_id, handle_004(state, server_id)?);
             Ok(())
         }
+        Message {
+            command: aatxe::Command::PONG(ref token, None),
+            ..
+        }
+        | Message {
+            command: aatxe::Command::PONG(_, Some(ref token)),
+            ..
+        } => lag::handle_pong(state, server_id, token),
+        Message {
+            command: aatxe::Command::Response(aatxe::Response::RPL_ISUPPORT, args, suffix),
+            ..
+        } => handle_005(state, server_id, args, suffix),
+        Message {
+            command: aatxe::Command::CAP(_, CapSubCommand::ACK, caps_1, caps_2),
+            ..
+        } => handle_cap_ack(state, server_id, caps_2.or(caps_1).unwrap_or_default()),
+        Message {
+            command: aatxe::Command::CAP(_, CapSubCommand::LS, caps_1, caps_2),
+            ..
+        }
+        | Message {
+            command: aatxe::Command::CAP(_, CapSubCommand::NEW, caps_1, caps_2),
+            ..
+        } => sts::handle_cap_list(state, server_id, &caps_2.or(caps_1).unwrap_or_default()),
+        Message {
+            command: aatxe::Command::KICK(channel, nick, _),
+            ..
+        } => handle_kick(state, server_id, channel, nick),
+        Message {
+            command: aatxe::Command::INVITE(nick, channel),
+            prefix,
+            ..
+        } => handle_invite(
+            state,
+            server_id,
+            outbox,
+            OwningMsgPrefix::from_string(prefix.unwrap_or_default()),
+            nick,
+            channel,
+        ),
+        Message {
+            command: aatxe::Command::JOIN(_, account, _),
+            prefix: Some(prefix),
+            ..
+        } => handle_join(
+            state,
+            server_id,
+            OwningMsgPrefix::from_string(prefix),
+            account,
+        ),
+        Message {
+            command: aatxe::Command::ACCOUNT(account),
+            prefix: Some(prefix),
+            ..
+        } => match OwningMsgPrefix::from_string(prefix).parse().nick {
+            Some(nick) => users::handle_account(state, server_id, nick, &account),
+            None => Ok(()),
+        },
+        Message {
+            command: aatxe::Command::NICK(new_nick),
+            prefix: Some(prefix),
+            ..
+        } => match OwningMsgPrefix::from_string(prefix).parse().nick {
+            Some(old_nick) => {
+                if state.is_own_nick(server_id, old_nick)? {
+                    update_prefix_info(
+                        state,
+                        server_id,
+                        &MsgPrefix {
+                            nick: Some(&new_nick),
+                            user: None,
+                            host: None,
+                        },
+                    )?;
+                }
+
+                users::handle_nick_change(state, server_id, old_nick, &new_nick)
+            }
+            None => Ok(()),
+        },
+        Message {
+            command: aatxe::Command::QUIT(reason),
+            prefix: Some(prefix),
+            ..
+        } => match OwningMsgPrefix::from_string(prefix).parse().nick {
+            Some(nick) => users::handle_quit(state, server_id, nick, reason),
+            None => Ok(()),
+        },
+        Message {
+            command: aatxe::Command::Response(aatxe::Response::RPL_NAMREPLY, _, Some(names)),
+            ..
+        } => handle_353(state, server_id, &names),
+        Message {
+            command: aatxe::Command::Response(aatxe::Response::RPL_MONONLINE, _, Some(targets)),
+            ..
+        } => presence::handle_monitor_reply(state, server_id, &targets, Presence::Online),
+        Message {
+            command: aatxe::Command::Response(aatxe::Response::RPL_MONOFFLINE, _, Some(targets)),
+            ..
+        } => presence::handle_monitor_reply(state, server_id, &targets, Presence::Offline),
+        Message {
+            command: aatxe::Command::Response(aatxe::Response::RPL_ISON, _, online),
+            ..
+        } => presence::handle_ison_reply(state, server_id, &online.unwrap_or_default()),
+        Message {
+            command: aatxe::Command::Response(aatxe::Response::RPL_YOUREOPER, ..),
+            ..
+        } => {
+            info!(
+                "[{}] Became an IRC operator.",
+                state.server_socket_addr_dbg_string(server_id)
+            );
+            Ok(())
+        }
+        Message {
+            command: aatxe::Command::Response(aatxe::Response::ERR_PASSWDMISMATCH, ..),
+            ..
+        }
+        | Message {
+            command: aatxe::Command::Response(aatxe::Response::ERR_NOOPERHOST, ..),
+            ..
+        } => {
+            warn!(
+                "[{}] The server rejected the configured `server password` or `oper` \
+                 credentials.",
+                state.server_socket_addr_dbg_string(server_id)
+            );
+            Ok(())
+        }
         _ => Ok(()),
     }
 }
@@ -413,6 +902,7 @@ fn handle_privmsg(
     server_id: ServerId,
     outbox: &OutboxPort,
     prefix: OwningMsgPrefix,
+    tags: MsgTags,
     target: String,
     msg: String,
 ) -> Result<()> {
@@ -422,35 +912,137 @@ fn handle_privmsg(
         msg
     );
 
-    let bot_nick = state.nick(server_id)?;
+    // The `irc` crate automatically replies to the CTCP requests `VERSION`, `PING`, `TIME`, etc.,
+    // so, of CTCP messages, only actions are of interest here.
+    let from_self = match prefix.parse().nick {
+        Some(nick) => {
+            users::handle_user_sighting(state, server_id, nick)?;
+
+            // A failure to log the message should not prevent it from being handled.
+            if let Err(e) = state
+                .user_opted_out_of_logging(server_id, prefix.parse())
+                .and_then(|opted_out| {
+                    if opted_out {
+                        return Ok(());
+                    }
+
+                    chan_log::log_privmsg(state, server_id, nick, &target, &msg, tags.server_time())
+                })
+            {
+                error!(
+                    "[{}] Failed to log message: {}",
+                    state.server_socket_addr_dbg_string(server_id),
+                    e
+                );
+            }
 
-    if !is_msg_to_nick(&target, &msg, &bot_nick) {
+            state.is_own_nick(server_id, nick)?
+        }
+        None => false,
+    };
+
+    // The bot's own messages, as echoed back by servers with the capability `echo-message`, must
+    // not be handled as requests, lest the bot answer itself. The exception is the message with
+    // which the bot learns its own message prefix, which it sends to itself.
+    if from_self && msg.trim() != UPDATE_MSG_PREFIX_STR {
+        return handle_echoed_msg(state, server_id, &prefix, &tags, &target, &msg);
+    }
+
+    let action_text = match Ctcp::parse(&msg) {
+        Some(ref ctcp) if ctcp.is_action() => Some(ctcp.params.to_owned()),
+        Some(ref ctcp) if ctcp.command == "DCC" && state.is_own_nick(server_id, &target)? => {
+            return dcc::handle_dcc_ctcp(state, server_id, outbox, prefix, ctcp.params);
+        }
+        Some(ctcp) => {
+            debug!(
+                "[{}] Received CTCP {:?} message from {:?}; not handling it further.",
+                state.server_socket_addr_dbg_string(server_id),
+                ctcp.command,
+                prefix.parse().nick,
+            );
+            return Ok(());
+        }
+        None => None,
+    };
+    let is_action = action_text.is_some();
+    let msg = incoming_text(state, action_text.unwrap_or(msg));
+
+    if flood::check(state, server_id, &prefix, &target, &msg)? {
         return Ok(());
     }
 
-    if prefix.parse().nick == Some(&target) && msg.trim() == UPDATE_MSG_PREFIX_STR {
+    let addressed = command_line(state, server_id, &target, &msg)?.is_some();
+
+    if !addressed && !state.has_always_watching_triggers() {
+        return Ok(());
+    }
+
+    if addressed
+        && !is_action
+        && prefix.parse().nick == Some(&target)
+        && msg.trim() == UPDATE_MSG_PREFIX_STR
+    {
         update_prefix_info(state, server_id, &prefix.parse())
     } else {
         // This could take a while or panic, so do it in a new thread.
 
+        // When replaying a recorded session, wait for the thread, so that the messages are
+        // handled in order, as they were received.
+        let offline = state.offline;
+
         // These are cheap to clone, supposedly.
         let state = state.clone();
         let outbox = outbox.clone();
 
         let thread_spawn_result = thread::Builder::new().spawn(move || {
-            let lib_reaction =
-                handle_bot_command_or_trigger(&state, server_id, prefix, target, msg, bot_nick);
+            let lib_reaction = handle_bot_command_or_trigger(
+                &state, server_id, &outbox, prefix, &tags, target, msg, is_action, addressed,
+            );
 
             push_to_outbox(&outbox, server_id, lib_reaction);
         });
 
         match thread_spawn_result {
-            Ok(thread::JoinHandle { .. }) => Ok(()),
+            Ok(handle) => {
+                if offline {
+                    // A panic will have been reported already, by the panic hook.
+                    let _ = handle.join();
+                }
+                Ok(())
+            }
             Err(e) => Err(ErrorKind::ThreadSpawnFailure(e).into()),
         }
     }
 }
 
+fn handle_echoed_msg(
+    state: &State,
+    server_id: ServerId,
+    prefix: &OwningMsgPrefix,
+    tags: &MsgTags,
+    target: &str,
+    msg: &str,
+) -> Result<()> {
+    if !state.capability_enabled(server_id, "echo-message")? {
+        trace!(
+            "[{}] Ignoring message from myself: {:?}",
+            state.server_socket_addr_dbg_string(server_id),
+            msg
+        );
+        return Ok(());
+    }
+
+    let metadata = MsgMetadata {
+        dest: MsgDest { server_id, target },
+        prefix: prefix.parse(),
+        tags,
+    };
+
+    state.run_echoed_msg_handlers(&metadata, msg);
+
+    Ok(())
+}
+
 fn handle_user_modes_change(
     state: &State,
     server_id: ServerId,
@@ -478,7 +1070,7 @@ fn handle_user_mode_change(
         mode = mode
     );
 
-    match (nick == state.nick(server_id)?, mode) {
+    match (state.is_own_nick(server_id, nick)?, mode) {
         (true, aatxe::Mode::Plus(aatxe::UserMode::Unknown(ch), _))
             if Some(ch) == state.get_server_config(server_id)?.await_registration_mode =>
         {
@@ -491,7 +1083,7 @@ fn handle_user_mode_change(
     }
 }
 
-fn handle_motd_end(state: &State, server_id: ServerId, outbox: &OutboxPort) -> Result<()> {
+fn handle_motd_end(state: &Arc<State>, server_id: ServerId, outbox: &OutboxPort) -> Result<()> {
     trace!(
         "[{server}] Handling end (or absence) of MotD",
         server = state.server_socket_addr_dbg_string(server_id)
@@ -499,10 +1091,44 @@ fn handle_motd_end(state: &State, server_id: ServerId, outbox: &OutboxPort) -> R
 
     let mut server = state.write_server(server_id)?;
 
+    let newly_connected = !server.motd_finished;
+
     server.motd_finished = true;
 
+    if newly_connected {
+        server.registered_since = Some(Instant::now());
+    }
+
+    let addr = server.socket_addr_string.clone();
+
     maybe_join_channels(state, server, outbox)?;
 
+    if newly_connected {
+        if let Some(ref oper) = state.get_server_config(server_id)?.oper {
+            push_to_outbox(
+                outbox,
+                server_id,
+                LibReaction::RawMsg(
+                    aatxe::Command::OPER(oper.name.clone(), oper.password.clone()).into(),
+                ),
+            );
+        }
+
+        presence::resubscribe(state, server_id)?;
+
+        // The modules' handlers could take a while or panic, so run them in a new thread.
+        spawn_thread(
+            state,
+            addr,
+            "connect",
+            |addr| format!("connection handler thread for server {}", addr),
+            move |state| {
+                state.run_connect_handlers(server_id);
+                Ok(())
+            },
+        );
+    }
+
     Ok(())
 }
 
@@ -542,7 +1168,7 @@ fn maybe_join_channels(
         }
     }
 
-    let join_delay = state.config.join_delay;
+    let join_delay = state.config().join_delay;
     if join_delay != Default::default() {
         debug!(
             "[{server}] Sleeping before joining channels, for {delay:?}",
@@ -563,25 +1189,175 @@ fn maybe_join_channels(
     Ok(true)
 }
 
-fn update_prefix_info(state: &State, _server_id: ServerId, prefix: &MsgPrefix) -> Result<()> {
+/// Handles a `JOIN` message, which, if the server has enabled the IRCv3 capability
+/// `extended-join`, includes the services account of the joining user.
+fn handle_join(
+    state: &State,
+    server_id: ServerId,
+    prefix: OwningMsgPrefix,
+    account: Option<String>,
+) -> Result<()> {
+    let nick = match prefix.parse().nick {
+        Some(nick) => nick,
+        None => return Ok(()),
+    };
+
+    users::handle_user_sighting(state, server_id, nick)?;
+
+    match account {
+        Some(ref account) if state.capability_enabled(server_id, "extended-join")? => {
+            users::handle_account(state, server_id, nick, account)
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Records the IRCv3 capabilities that the server has acknowledged enabling or disabling.
+fn handle_cap_ack(state: &State, server_id: ServerId, caps: String) -> Result<()> {
+    let mut server = state.write_server(server_id)?;
+
+    for cap in caps.split_whitespace() {
+        debug!(
+            "[{server}] Server acknowledged capability change: {cap:?}",
+            server = server.socket_addr_string,
+            cap = cap
+        );
+
+        if cap.starts_with('-') {
+            server.enabled_caps.remove(&cap[1..]);
+        } else {
+            server.enabled_caps.insert(cap.to_owned());
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the per-channel setting that the given function reads from the given channel's
+/// settings, if the channel is configured and the setting is specified.
+pub(super) fn channel_setting<F, T>(
+    state: &State,
+    server_id: ServerId,
+    channel: &str,
+    setting: F,
+) -> Result<Option<T>>
+where
+    F: FnOnce(&config::Channel) -> Option<T>,
+{
+    for chan in &state.get_server_config(server_id)?.channels {
+        if state.nicks_eq(server_id, &chan.name, channel)? {
+            return Ok(setting(chan));
+        }
+    }
+
+    Ok(None)
+}
+
+fn handle_kick(
+    state: &Arc<State>,
+    server_id: ServerId,
+    channel: String,
+    nick: String,
+) -> Result<()> {
+    if !state.is_own_nick(server_id, &nick)? {
+        return Ok(());
+    }
+
+    let addr = state.server_socket_addr_dbg_string(server_id);
+
+    let rejoin = channel_setting(state, server_id, &channel, |chan| chan.rejoin_on_kick)?
+        .unwrap_or(state.config().rejoin_on_kick);
+
+    if !rejoin {
+        info!(
+            "[{server}] Kicked from {channel:?}; not rejoining it.",
+            server = addr,
+            channel = channel
+        );
+        return Ok(());
+    }
+
+    let rejoin_delay = state.config().rejoin_delay;
+
+    info!(
+        "[{server}] Kicked from {channel:?}; rejoining it in {delay:?}.",
+        server = addr,
+        channel = channel,
+        delay = rejoin_delay
+    );
+
+    // Don't hold up the handling of other messages while waiting to rejoin.
+    spawn_thread(
+        state,
+        addr,
+        "rejoin",
+        |addr| format!("thread for rejoining a channel on server {}", addr),
+        move |state| {
+            thread::sleep(rejoin_delay);
+
+            push_to_outbox(
+                &state.outbox,
+                server_id,
+                LibReaction::RawMsg(aatxe::Command::JOIN(channel, None, None).into()),
+            );
+
+            Ok(())
+        },
+    );
+
+    Ok(())
+}
+
+fn handle_invite(
+    state: &State,
+    server_id: ServerId,
+    outbox: &OutboxPort,
+    prefix: OwningMsgPrefix,
+    nick: String,
+    channel: String,
+) -> Result<()> {
+    if !state.is_own_nick(server_id, &nick)? {
+        return Ok(());
+    }
+
+    let policy = channel_setting(state, server_id, &channel, |chan| chan.join_on_invite)?
+        .unwrap_or(state.config().join_on_invite);
+
+    let accept = match policy {
+        InvitePolicy::Nobody => false,
+        InvitePolicy::Admins => state.have_admin(server_id, prefix.parse())?,
+        InvitePolicy::Anyone => true,
+    };
+
+    info!(
+        "[{server}] Invited to {channel:?} by {inviter:?}; {action} the invitation.",
+        server = state.server_socket_addr_dbg_string(server_id),
+        channel = channel,
+        inviter = prefix.parse().nick,
+        action = if accept { "accepting" } else { "ignoring" }
+    );
+
+    if accept {
+        push_to_outbox(
+            outbox,
+            server_id,
+            LibReaction::RawMsg(aatxe::Command::JOIN(channel, None, None).into()),
+        );
+    }
+
+    Ok(())
+}
+
+fn update_prefix_info(state: &State, server_id: ServerId, prefix: &MsgPrefix) -> Result<()> {
     debug!(
         "Updating stored message prefix information from received {:?}",
         prefix
     );
 
-    match state.msg_prefix.write() {
-        Ok(guard) => guard,
-        Err(poisoned_guard) => {
-            // The lock was poisoned, you say? That's strange, unfortunate, and unlikely to be a
-            // problem here, because we're just going to overwrite the contents anyway.
-            warn!(
-                "Stored message prefix was poisoned by thread panic! Discarding it, replacing it, \
-                 and moving on."
-            );
-            poisoned_guard.into_inner()
-        }
-    }
-    .update_from(prefix);
+    state
+        .write_server(server_id)?
+        .msg_prefix
+        .update_from(prefix);
 
     Ok(())
 }
@@ -592,7 +1368,45 @@ fn handle_004(state: &State, server_id: ServerId) -> Result<LibReaction<Message>
     send_msg_prefix_update_request(state, server_id)
 }
 
+fn handle_005(
+    state: &State,
+    server_id: ServerId,
+    args: Vec<String>,
+    suffix: Option<String>,
+) -> Result<()> {
+    // The first argument is our nickname. Any suffix is customarily the human-readable text "are
+    // supported by this server", but, if it contains no space, it may be a final parameter whose
+    // sender didn't need to make it a suffix.
+    let suffix = suffix.filter(|s| !s.contains(' '));
+    let tokens = args.iter().skip(1).chain(suffix.iter()).map(AsRef::as_ref);
+
+    let mut server = state.write_server(server_id)?;
+
+    server.capabilities.update_from_isupport_tokens(tokens);
+
+    trace!(
+        "[{server}] Updated server capabilities from `RPL_ISUPPORT`: {caps:?}",
+        server = server.socket_addr_string,
+        caps = server.capabilities,
+    );
+
+    Ok(())
+}
+
 // TODO: Run `send_msg_prefix_update_request` periodically.
+/// Handles a `RPL_NAMREPLY` message, noting the presence of the users it lists.
+fn handle_353(state: &State, server_id: ServerId, names: &str) -> Result<()> {
+    let prefixes = state.server_capabilities(server_id)?.prefix;
+
+    for name in names.split_whitespace() {
+        let nick = name.trim_start_matches(|c| prefixes.iter().any(|&(_, p)| p == c));
+
+        users::handle_user_sighting(state, server_id, nick)?;
+    }
+
+    Ok(())
+}
+
 fn send_msg_prefix_update_request(
     state: &State,
     server_id: ServerId,
@@ -1,6 +1,15 @@
 use super::Result;
 use super::ServerId;
+use irc::proto::message::Tag;
+use std::cmp::Ordering;
+use std::collections::btree_map;
+use std::collections::BTreeMap;
 use std::fmt;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use util::irc::casemapped_str_cmp;
+use util::irc::CaseMapping;
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub struct MsgDest<'a> {
@@ -21,9 +30,272 @@ pub struct MsgPrefix<'a> {
 pub struct MsgMetadata<'a> {
     pub dest: MsgDest<'a>,
     pub prefix: MsgPrefix<'a>,
+
+    /// The IRCv3 message tags with which the message was sent, if any
+    pub tags: &'a MsgTags,
 }
 
-#[derive(Debug)]
+impl<'a> MsgMetadata<'a> {
+    /// Returns the time at which the server received the message, as given by its `time` tag,
+    /// which servers supporting the IRCv3 capability `server-time` attach to messages. This
+    /// differs from the time at which the bot received the message, e.g., for messages played
+    /// back by a bouncer.
+    pub fn server_time(&self) -> Option<SystemTime> {
+        self.tags.server_time()
+    }
+}
+
+/// A set of [IRCv3 message tags], mapping tag names to values
+///
+/// Tags that are present without a value are given the empty string as their value, which the
+/// IRCv3 specification considers equivalent. Values are stored unescaped.
+///
+/// [IRCv3 message tags]: <https://ircv3.net/specs/extensions/message-tags>
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MsgTags {
+    tags: BTreeMap<String, String>,
+}
+
+impl MsgTags {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Returns the value of the tag with the given name, if it is present.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.tags.get(name).map(|v| &v[..])
+    }
+
+    /// Returns whether the tag with the given name is present.
+    pub fn contains(&self, name: &str) -> bool {
+        self.tags.contains_key(name)
+    }
+
+    /// Sets the tag with the given name to the given value, which may be empty.
+    pub fn insert<S1, S2>(&mut self, name: S1, value: S2)
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        self.tags.insert(name.into(), value.into());
+    }
+
+    /// Adds the tag with the given name and value, returning the resulting set of tags.
+    pub fn with<S1, S2>(mut self, name: S1, value: S2) -> Self
+    where
+        S1: Into<String>,
+        S2: Into<String>,
+    {
+        self.insert(name, value);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    /// Returns the time given by the `time` tag, as used by the IRCv3 capability `server-time`,
+    /// if the tag is present and holds a valid timestamp.
+    pub fn server_time(&self) -> Option<SystemTime> {
+        self.get("time").and_then(parse_server_time)
+    }
+
+    /// Returns an iterator over the names and values of the tags, in order by name.
+    pub fn iter<'a>(&'a self) -> MsgTagsIter<'a> {
+        MsgTagsIter(self.tags.iter())
+    }
+
+    pub(super) fn from_aatxe(tags: Option<Vec<Tag>>) -> Self {
+        MsgTags {
+            tags: tags
+                .unwrap_or_default()
+                .into_iter()
+                .map(|Tag(name, value)| {
+                    let value = value.as_ref().map(|v| unescape_tag_value(v));
+                    (name, value.unwrap_or_default())
+                })
+                .collect(),
+        }
+    }
+
+    pub(super) fn to_aatxe(&self) -> Option<Vec<Tag>> {
+        if self.is_empty() {
+            return None;
+        }
+
+        Some(
+            self.iter()
+                .map(|(name, value)| {
+                    let value = if value.is_empty() {
+                        None
+                    } else {
+                        Some(escape_tag_value(value))
+                    };
+                    Tag(name.to_owned(), value)
+                })
+                .collect(),
+        )
+    }
+}
+
+/// An iterator over the names and values of a set of message tags, as returned by
+/// [`MsgTags::iter`]
+///
+/// [`MsgTags::iter`]: <struct.MsgTags.html#method.iter>
+#[derive(Clone, Debug)]
+pub struct MsgTagsIter<'a>(btree_map::Iter<'a, String, String>);
+
+impl<'a> Iterator for MsgTagsIter<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.0.next().map(|(name, value)| (&name[..], &value[..]))
+    }
+}
+
+/// Parses a timestamp in the format used by the IRCv3 capability `server-time`, such as
+/// `2011-10-19T16:40:51.620Z`, i.e., an ISO 8601 UTC timestamp with millisecond precision.
+fn parse_server_time(s: &str) -> Option<SystemTime> {
+    fn num<T: ::std::str::FromStr>(s: &str, len: usize) -> Option<T> {
+        if s.len() == len && s.bytes().all(|b| b.is_ascii_digit()) {
+            s.parse().ok()
+        } else {
+            None
+        }
+    }
+
+    let mut date_and_time = s.splitn(2, 'T');
+    let date = date_and_time.next()?;
+    let time = date_and_time.next()?;
+
+    if !time.ends_with('Z') {
+        return None;
+    }
+    let time = &time[..time.len() - 1];
+
+    let mut date = date.split('-');
+    let year: i64 = num(date.next()?, 4)?;
+    let month: u32 = num(date.next()?, 2)?;
+    let day: u32 = num(date.next()?, 2)?;
+
+    let mut time_and_fraction = time.splitn(2, '.');
+    let mut time = time_and_fraction.next()?.split(':');
+    let hours: u64 = num(time.next()?, 2)?;
+    let minutes: u64 = num(time.next()?, 2)?;
+    let seconds: u64 = num(time.next()?, 2)?;
+
+    let nanos = match time_and_fraction.next() {
+        Some(frac) => {
+            if frac.is_empty() || frac.len() > 9 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            frac.parse::<u32>().ok()? * 10u32.pow(9 - frac.len() as u32)
+        }
+        None => 0,
+    };
+
+    if date.next().is_some() || time.next().is_some() {
+        return None;
+    }
+
+    match (month, day, hours, minutes, seconds) {
+        (1..=12, 1..=31, 0..=23, 0..=59, 0..=60) => {}
+        _ => return None,
+    }
+
+    // Count days since the Unix epoch, per Howard Hinnant's `days_from_civil` algorithm
+    // (<https://howardhinnant.github.io/date_algorithms.html#days_from_civil>).
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let year_of_era = y - era * 400;
+    let day_of_year = (153 * i64::from((month + 9) % 12) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+
+    if days < 0 {
+        return None;
+    }
+
+    let secs = days as u64 * 86_400 + hours * 3_600 + minutes * 60 + seconds;
+
+    Some(UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+/// Formats a timestamp in the format used by the IRCv3 capability `server-time`, as parsed by
+/// `parse_server_time`. Times before the Unix epoch are formatted as the epoch.
+pub(super) fn format_server_time(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let secs = since_epoch.as_secs();
+
+    // Find the calendar date, per Howard Hinnant's `civil_from_days` algorithm
+    // (<https://howardhinnant.github.io/date_algorithms.html#civil_from_days>).
+    let days = (secs / 86_400) as i64 + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year,
+        month,
+        day,
+        secs % 86_400 / 3_600,
+        secs % 3_600 / 60,
+        secs % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Unescapes the value of a message tag, per
+/// <https://ircv3.net/specs/extensions/message-tags#escaping-values>.
+fn unescape_tag_value(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+    let mut chars = value.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some(':') => output.push(';'),
+            Some('s') => output.push(' '),
+            Some('r') => output.push('\r'),
+            Some('n') => output.push('\n'),
+            Some(c) => output.push(c),
+            // A trailing backslash is dropped.
+            None => {}
+        }
+    }
+
+    output
+}
+
+fn escape_tag_value(value: &str) -> String {
+    let mut output = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            ';' => output.push_str("\\:"),
+            ' ' => output.push_str("\\s"),
+            '\\' => output.push_str("\\\\"),
+            '\r' => output.push_str("\\r"),
+            '\n' => output.push_str("\\n"),
+            c => output.push(c),
+        }
+    }
+
+    output
+}
+
+#[derive(Clone, Debug)]
 pub struct OwningMsgPrefix {
     backing: String,
 }
@@ -46,27 +318,209 @@ fn prefix_from_pircolate<'a>(
     }
 }
 
-pub(super) fn is_msg_to_nick(target: &str, msg: &str, nick: &str) -> bool {
-    target == nick
-        || msg == nick
-        || (msg.starts_with(nick)
-            && (msg.find(|c: char| [':', ','].contains(&c)) == Some(nick.len())))
+/// A string that compares case-insensitively according to an IRC server's case-mapping rules, as
+/// are used for nicknames and channel names.
+///
+/// When two `IrcCaseInsensitive` values with different case-mapping rules are compared, the rules
+/// of the left-hand operand are used.
+#[derive(Clone, Copy, Debug)]
+pub struct IrcCaseInsensitive<'a> {
+    casemapping: CaseMapping,
+    inner: &'a str,
+}
+
+impl<'a> IrcCaseInsensitive<'a> {
+    pub fn new(casemapping: CaseMapping, s: &'a str) -> Self {
+        IrcCaseInsensitive {
+            casemapping,
+            inner: s,
+        }
+    }
+
+    /// Returns the wrapped string, with its case unchanged.
+    pub fn as_str(&self) -> &'a str {
+        self.inner
+    }
+
+    /// Returns whether the given string begins with the wrapped string, comparing
+    /// case-insensitively.
+    pub fn is_prefix_of(&self, s: &str) -> bool {
+        s.get(..self.inner.len())
+            .map(|prefix| *self == prefix)
+            .unwrap_or(false)
+    }
+}
+
+impl<'a, 'b> PartialEq<IrcCaseInsensitive<'b>> for IrcCaseInsensitive<'a> {
+    fn eq(&self, other: &IrcCaseInsensitive<'b>) -> bool {
+        *self == other.inner
+    }
+}
+
+impl<'a> Eq for IrcCaseInsensitive<'a> {}
+
+impl<'a, 'b> PartialEq<&'b str> for IrcCaseInsensitive<'a> {
+    fn eq(&self, other: &&'b str) -> bool {
+        casemapped_str_cmp(self.casemapping, self.inner, *other) == Ordering::Equal
+    }
+}
+
+impl<'a> PartialEq<str> for IrcCaseInsensitive<'a> {
+    fn eq(&self, other: &str) -> bool {
+        *self == other
+    }
+}
+
+impl<'a> Ord for IrcCaseInsensitive<'a> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        casemapped_str_cmp(self.casemapping, self.inner, other.inner)
+    }
+}
+
+impl<'a> PartialOrd for IrcCaseInsensitive<'a> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'a> fmt::Display for IrcCaseInsensitive<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.inner)
+    }
+}
+
+/// A message of the [Client-to-Client Protocol (CTCP)][CTCP], as may be carried in the text of a
+/// `PRIVMSG` or `NOTICE`.
+///
+/// [CTCP]: <https://modern.ircdocs.horse/ctcp.html>
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Ctcp<'a> {
+    /// The CTCP command, such as `ACTION` or `VERSION`.
+    pub command: &'a str,
+
+    /// The text of the message following the command, which may be empty.
+    pub params: &'a str,
+}
+
+impl<'a> Ctcp<'a> {
+    /// Parses the text of a `PRIVMSG` or `NOTICE` as a CTCP message, returning `None` if it is not
+    /// one.
+    ///
+    /// As some clients do, this tolerates the omission of the final delimiter (`\x01`).
+    pub fn parse(text: &'a str) -> Option<Self> {
+        if !text.starts_with('\x01') {
+            return None;
+        }
+
+        let body = text[1..].trim_end_matches('\x01');
+        let mut command_and_params = body.splitn(2, ' ');
+        let command = command_and_params.next().unwrap_or("");
+        let params = command_and_params.next().unwrap_or("");
+
+        if command.is_empty() {
+            return None;
+        }
+
+        Some(Ctcp { command, params })
+    }
+
+    /// Constructs a CTCP `ACTION` message, as is sent by the IRC client command `/me`.
+    pub fn action(text: &'a str) -> Self {
+        Ctcp {
+            command: "ACTION",
+            params: text,
+        }
+    }
+
+    /// Returns whether this is an `ACTION` message.
+    pub fn is_action(&self) -> bool {
+        self.command.eq_ignore_ascii_case("ACTION")
+    }
+}
+
+/// Formats the CTCP message for sending as the text of a `PRIVMSG` or `NOTICE`, with its
+/// delimiters.
+impl<'a> fmt::Display for Ctcp<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("\x01")?;
+        f.write_str(self.command)?;
+        if !self.params.is_empty() {
+            f.write_str(" ")?;
+            f.write_str(self.params)?;
+        }
+        f.write_str("\x01")
+    }
+}
+
+/// The ways in which messages may address commands to the bot
+#[derive(Clone, Copy, Debug)]
+pub(super) struct Addressing<'a> {
+    /// The prefixes, such as `!`, with which a message may begin to address a command to the bot
+    pub(super) sigils: &'a [String],
+
+    /// Whether a message in a channel may address the bot by beginning with its nickname
+    pub(super) by_nick: bool,
+
+    /// Whether every private message is addressed to the bot, even without any prefix
+    pub(super) bare_in_private: bool,
+}
+
+impl<'a> Default for Addressing<'a> {
+    fn default() -> Self {
+        Addressing {
+            sigils: &[],
+            by_nick: true,
+            bare_in_private: true,
+        }
+    }
 }
 
-pub(super) fn parse_msg_to_nick<'msg>(
+/// Returns the command line addressed to the bot, whose nickname is `nick`, in the given message
+/// text sent to the given target, if the text is so addressed in any of the given ways.
+pub(super) fn parse_command_line<'msg>(
+    casemapping: CaseMapping,
     text: &'msg str,
     target: &str,
     nick: &str,
+    addressing: Addressing,
 ) -> Option<&'msg str> {
-    if is_msg_to_nick(target, text, nick) {
-        Some(
-            text.trim_start_matches(nick)
-                .trim_start_matches(|c: char| [':', ','].contains(&c))
-                .trim(),
-        )
-    } else {
-        None
+    let nick_ci = IrcCaseInsensitive::new(casemapping, nick);
+    let private = nick_ci == target;
+
+    if private || addressing.by_nick {
+        if nick_ci == text {
+            return Some("");
+        }
+
+        if nick_ci.is_prefix_of(text)
+            && text[nick.len()..].starts_with(|c: char| [':', ','].contains(&c))
+        {
+            return Some(
+                text[nick.len()..]
+                    .trim_start_matches(|c: char| [':', ','].contains(&c))
+                    .trim(),
+            );
+        }
     }
+
+    for sigil in addressing.sigils {
+        if sigil.is_empty() || !text.starts_with(&sigil[..]) {
+            continue;
+        }
+
+        let rest = &text[sigil.len()..];
+
+        match rest.chars().next() {
+            Some(c) if !c.is_whitespace() => return Some(rest.trim()),
+            _ => {}
+        }
+    }
+
+    if private && addressing.bare_in_private {
+        return Some(text.trim());
+    }
+
+    None
 }
 
 pub(super) fn parse_prefix(prefix: &str) -> MsgPrefix {
@@ -147,6 +601,11 @@ impl OwningMsgPrefix {
         self.backing.len()
     }
 
+    /// Returns the message prefix as it would be written in a message, e.g., `nick!user@host`.
+    pub fn as_str(&self) -> &str {
+        &self.backing
+    }
+
     /// Write each non-`None` field of the given message prefix over the corresponding field in
     /// `self`.
     pub(super) fn update_from(&mut self, new: &MsgPrefix) {
@@ -172,3 +631,195 @@ impl OwningMsgPrefix {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tag_value_escaping() {
+        for &(raw, escaped) in &[
+            ("plain", "plain"),
+            ("a;b c", "a\\:b\\sc"),
+            ("back\\slash", "back\\\\slash"),
+            ("line\r\nbreak", "line\\r\\nbreak"),
+        ] {
+            assert_eq!(escape_tag_value(raw), escaped);
+            assert_eq!(unescape_tag_value(escaped), raw);
+        }
+
+        assert_eq!(unescape_tag_value("\\x\\"), "x");
+    }
+
+    #[test]
+    fn server_time_parsing() {
+        assert_eq!(
+            parse_server_time("2011-10-19T16:40:51.620Z"),
+            Some(UNIX_EPOCH + Duration::from_millis(1_319_042_451_620))
+        );
+        assert_eq!(parse_server_time("1970-01-01T00:00:00Z"), Some(UNIX_EPOCH));
+        assert_eq!(
+            parse_server_time("2024-02-29T23:59:59.5Z"),
+            Some(UNIX_EPOCH + Duration::from_millis(1_709_251_199_500))
+        );
+
+        for &bad in &[
+            "",
+            "2011-10-19",
+            "2011-10-19T16:40:51.620",
+            "2011-13-19T16:40:51Z",
+            "2011-10-19T24:00:00Z",
+            "2011-10-19T16:40:51.Z",
+            "1969-12-31T23:59:59Z",
+            "11-10-19T16:40:51Z",
+        ] {
+            assert_eq!(parse_server_time(bad), None, "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn server_time_formatting() {
+        for &s in &[
+            "1970-01-01T00:00:00.000Z",
+            "2000-02-29T12:00:00.001Z",
+            "2011-10-19T16:40:51.620Z",
+            "2024-12-31T23:59:59.999Z",
+        ] {
+            assert_eq!(format_server_time(parse_server_time(s).unwrap()), s);
+        }
+    }
+
+    #[test]
+    fn tags_from_aatxe() {
+        let tags = MsgTags::from_aatxe(Some(vec![
+            Tag("msgid".into(), Some("abc\\:1".into())),
+            Tag("+draft/typing".into(), None),
+        ]));
+
+        assert_eq!(tags.get("msgid"), Some("abc;1"));
+        assert_eq!(tags.get("+draft/typing"), Some(""));
+        assert_eq!(tags.get("time"), None);
+        assert_eq!(MsgTags::from_aatxe(None), MsgTags::new());
+    }
+
+    #[test]
+    fn msg_to_nick_examples() {
+        let cm = CaseMapping::Rfc1459;
+        let parse_msg_to_nick = |cm, text, target, nick| {
+            parse_command_line(cm, text, target, nick, Addressing::default())
+        };
+
+        assert_eq!(
+            parse_msg_to_nick(cm, "egbot: help", "#c", "egbot"),
+            Some("help")
+        );
+        assert_eq!(
+            parse_msg_to_nick(cm, "EgBot, help", "#c", "egbot"),
+            Some("help")
+        );
+        assert_eq!(parse_msg_to_nick(cm, "egbot", "#c", "egbot"), Some(""));
+        assert_eq!(
+            parse_msg_to_nick(cm, "help", "egbot", "egbot"),
+            Some("help")
+        );
+        assert_eq!(
+            parse_msg_to_nick(cm, "egbot: egbot", "#c", "egbot"),
+            Some("egbot")
+        );
+        assert_eq!(
+            parse_msg_to_nick(cm, "{bot}: hi", "#c", "[bot]"),
+            Some("hi")
+        );
+        assert_eq!(parse_msg_to_nick(cm, "egbots: help", "#c", "egbot"), None);
+        assert_eq!(parse_msg_to_nick(cm, "egbot help", "#c", "egbot"), None);
+        assert_eq!(
+            parse_msg_to_nick(CaseMapping::Ascii, "{bot}: hi", "#c", "[bot]"),
+            None
+        );
+    }
+
+    #[test]
+    fn command_prefix_examples() {
+        let cm = CaseMapping::Rfc1459;
+        let sigils = ["!".to_owned(), "..".to_owned()];
+        let addressing = Addressing {
+            sigils: &sigils,
+            by_nick: false,
+            bare_in_private: false,
+        };
+
+        assert_eq!(
+            parse_command_line(cm, "!help me", "#c", "egbot", addressing),
+            Some("help me")
+        );
+        assert_eq!(
+            parse_command_line(cm, "..help", "#c", "egbot", addressing),
+            Some("help")
+        );
+        assert_eq!(
+            parse_command_line(cm, "! help", "#c", "egbot", addressing),
+            None
+        );
+        assert_eq!(parse_command_line(cm, "!", "#c", "egbot", addressing), None);
+        assert_eq!(
+            parse_command_line(cm, "egbot: help", "#c", "egbot", addressing),
+            None
+        );
+        assert_eq!(
+            parse_command_line(cm, "egbot: help", "egbot", "egbot", addressing),
+            Some("help")
+        );
+        assert_eq!(
+            parse_command_line(cm, "help", "egbot", "egbot", addressing),
+            None
+        );
+    }
+
+    #[test]
+    fn ctcp_examples() {
+        assert_eq!(
+            Ctcp::parse("\x01ACTION waves\x01"),
+            Some(Ctcp::action("waves"))
+        );
+        assert_eq!(
+            Ctcp::parse("\x01VERSION\x01"),
+            Some(Ctcp {
+                command: "VERSION",
+                params: "",
+            })
+        );
+        assert_eq!(
+            Ctcp::parse("\x01PING 123 456"),
+            Some(Ctcp {
+                command: "PING",
+                params: "123 456",
+            })
+        );
+        assert_eq!(Ctcp::parse("\x01\x01"), None);
+        assert_eq!(Ctcp::parse("ACTION waves"), None);
+        assert!(Ctcp::parse("\x01action waves\x01").unwrap().is_action());
+        assert_eq!(Ctcp::action("waves").to_string(), "\x01ACTION waves\x01");
+        assert_eq!(
+            Ctcp {
+                command: "VERSION",
+                params: "",
+            }
+            .to_string(),
+            "\x01VERSION\x01"
+        );
+    }
+
+    #[test]
+    fn irc_case_insensitive_examples() {
+        let ci = |s| IrcCaseInsensitive::new(CaseMapping::Rfc1459, s);
+        let strict = |s| IrcCaseInsensitive::new(CaseMapping::StrictRfc1459, s);
+
+        assert_eq!(ci("Nick[away]"), ci("nick{AWAY}"));
+        assert_eq!(ci("c74d~"), ci("C74D^"));
+        assert_ne!(strict("c74d~"), strict("C74D^"));
+        assert_eq!(strict("A|b"), strict("a\\B"));
+        assert!(ci("abc") < ci("ABD"));
+        assert!(ci("egbot").is_prefix_of("EGBOT: hi"));
+        assert!(!ci("egbot").is_prefix_of("egbo"));
+    }
+}
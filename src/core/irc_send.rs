@@ -1,3 +1,8 @@
+use super::bridge;
+use super::config;
+use super::config::OutboxOverflowPolicy;
+use super::irc_comm::mk_quit;
+use super::output;
 use super::ErrorKind;
 use super::LibReaction;
 use super::ServerId;
@@ -9,12 +14,168 @@ use crossbeam_channel;
 use irc::client::prelude as aatxe;
 use irc::client::prelude::Client as AatxeClient;
 use irc::proto::Message;
+use std::collections::BTreeSet;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::thread;
 
-pub(super) const OUTBOX_SIZE: usize = 1024;
+/// The capacity of the outbox's lane for urgent messages (see `Lane::Urgent`)
+const URGENT_LANE_SIZE: usize = 64;
 
-pub(super) type OutboxPort = crossbeam_channel::Sender<OutboxRecord>;
+/// The sending end of the outbox, the queue of messages waiting to be sent to servers
+#[derive(Clone, Debug)]
+pub(super) struct OutboxPort {
+    sender: crossbeam_channel::Sender<OutboxRecord>,
+
+    /// The sending end of the lane for urgent messages, which are sent before any messages waiting
+    /// in the ordinary lane
+    urgent_sender: crossbeam_channel::Sender<OutboxRecord>,
+
+    /// A receiving end of the outbox, used to discard the oldest messages in the outbox under the
+    /// overflow policy `DropOldest`
+    receiver: crossbeam_channel::Receiver<OutboxRecord>,
+
+    overflow_policy: OutboxOverflowPolicy,
+
+    /// The number of messages that have been discarded because the outbox was full
+    dropped: Arc<AtomicUsize>,
+
+    /// Servers from which the sending thread is to disconnect because the outbox overflowed,
+    /// under the overflow policy `Disconnect`
+    overflowed_servers: Arc<Mutex<BTreeSet<ServerId>>>,
+}
+
+/// The receiving end of the outbox
+pub(super) struct OutboxReceiver {
+    receiver: crossbeam_channel::Receiver<OutboxRecord>,
+    urgent_receiver: crossbeam_channel::Receiver<OutboxRecord>,
+}
+
+/// Statistics about the queue of messages waiting to be sent to servers, as returned by
+/// [`State::outbox_stats`]
+///
+/// [`State::outbox_stats`]: <struct.State.html#method.outbox_stats>
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct OutboxStats {
+    /// The number of messages currently waiting in the queue
+    pub len: usize,
+
+    /// The maximum number of messages that may wait in the queue at once, as configured with the
+    /// setting `outbox` → `capacity`
+    pub capacity: usize,
+
+    /// The number of messages that have been discarded because the queue was full
+    pub dropped: usize,
+}
+
+pub(super) fn mk_outbox(cfg: &config::Outbox) -> (OutboxPort, OutboxReceiver) {
+    let (sender, receiver) = crossbeam_channel::bounded(cfg.capacity);
+    let (urgent_sender, urgent_receiver) = crossbeam_channel::bounded(URGENT_LANE_SIZE);
+
+    let port = OutboxPort {
+        sender,
+        urgent_sender,
+        receiver: receiver.clone(),
+        overflow_policy: cfg.overflow_policy,
+        dropped: Default::default(),
+        overflowed_servers: Default::default(),
+    };
+
+    let receiver = OutboxReceiver {
+        receiver,
+        urgent_receiver,
+    };
+
+    (port, receiver)
+}
+
+impl OutboxPort {
+    pub(super) fn len(&self) -> usize {
+        self.sender.len() + self.urgent_sender.len()
+    }
+
+    pub(super) fn is_empty(&self) -> bool {
+        self.sender.is_empty() && self.urgent_sender.is_empty()
+    }
+
+    pub(super) fn stats(&self) -> OutboxStats {
+        OutboxStats {
+            len: self.len(),
+            capacity: self.sender.capacity().unwrap_or_default(),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record_drop(&self, record: &OutboxRecord, reason: &str) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+
+        error!(
+            "Outbox full!!! {reason} {record:?}",
+            reason = reason,
+            record = record
+        )
+    }
+
+    /// Handles a record that could not be queued because the outbox was full, according to the
+    /// overflow policy.
+    fn handle_overflow(&self, record: OutboxRecord) {
+        match self.overflow_policy {
+            OutboxOverflowPolicy::DropNewest => self.record_drop(&record, "Could not send"),
+            OutboxOverflowPolicy::DropOldest => {
+                if let Ok(oldest) = self.receiver.try_recv() {
+                    self.record_drop(&oldest, "Discarding oldest message")
+                }
+
+                if let Err(e) = self.sender.try_send(record) {
+                    self.record_drop(&e.into_inner(), "Could not send")
+                }
+            }
+            OutboxOverflowPolicy::Disconnect => {
+                self.record_drop(&record, "Disconnecting from the server, and could not send");
+
+                match self.overflowed_servers.lock() {
+                    Ok(mut servers) => {
+                        servers.insert(record.server_id);
+                    }
+                    Err(_) => error!("The set of servers whose outbox overflowed is poisoned."),
+                }
+            }
+        }
+    }
+
+    /// Queues the given record in the lane for urgent messages if it is urgent and that lane has
+    /// room, or in the ordinary lane otherwise.
+    fn push(&self, record: OutboxRecord) {
+        let record = if lane(&record.output) == Lane::Urgent {
+            match self.urgent_sender.try_send(record) {
+                Ok(()) => return,
+                Err(e) => e.into_inner(),
+            }
+        } else {
+            record
+        };
+
+        match self.sender.try_send(record) {
+            Ok(()) => {}
+            Err(crossbeam_channel::TrySendError::Full(record)) => self.handle_overflow(record),
+            Err(crossbeam_channel::TrySendError::Disconnected(record)) => error!(
+                "Outbox receiver disconnected!!! Could not send {record:?}",
+                record = record
+            ),
+        }
+    }
+
+    /// Returns whether the outbox has overflowed with messages for the given server under the
+    /// overflow policy `Disconnect`, and clears this condition.
+    fn take_overflow(&self, server_id: ServerId) -> bool {
+        match self.overflowed_servers.lock() {
+            Ok(mut servers) => servers.remove(&server_id),
+            Err(_) => false,
+        }
+    }
+}
 
 #[derive(Debug)]
 pub(super) struct OutboxRecord {
@@ -31,71 +192,159 @@ where
         None => return,
     };
 
-    let result = outbox_sender.try_send(OutboxRecord { server_id, output });
+    outbox_sender.push(OutboxRecord { server_id, output })
+}
+
+/// The lanes of the outbox
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Lane {
+    /// The lane for messages that should be sent ahead of any ordinary messages waiting to be
+    /// sent, i.e., messages that keep the connection alive or change its state, rather than bulk
+    /// `PRIVMSG` output
+    Urgent,
 
-    match result {
-        Ok(()) => {}
-        Err(crossbeam_channel::TrySendError::Full(record)) => {
-            error!("Outbox full!!! Could not send {record:?}", record = record)
+    /// The lane for all other messages
+    Ordinary,
+}
+
+/// Returns the lane of the outbox in which the given output should wait to be sent.
+///
+/// The `irc` crate answers the server's `PING`s itself, bypassing the outbox, but `PONG`s are
+/// treated as urgent in case a module sends them.
+fn lane(output: &LibReaction<Message>) -> Lane {
+    let msg = match *output {
+        LibReaction::RawMsg(ref msg) => msg,
+        LibReaction::Multi(_) => return Lane::Ordinary,
+    };
+
+    match msg.command {
+        aatxe::Command::PING(..)
+        | aatxe::Command::PONG(..)
+        | aatxe::Command::QUIT(..)
+        | aatxe::Command::NICK(..) => Lane::Urgent,
+        _ => Lane::Ordinary,
+    }
+}
+
+impl OutboxReceiver {
+    /// Waits for a record to be sent, taking records from the lane for urgent messages before
+    /// any from the ordinary lane. Returns `None` once all senders have been dropped.
+    fn recv(&self) -> Option<OutboxRecord> {
+        if let Ok(record) = self.urgent_receiver.try_recv() {
+            return Some(record);
         }
-        Err(crossbeam_channel::TrySendError::Disconnected(record)) => error!(
-            "Outbox receiver disconnected!!! Could not send {record:?}",
-            record = record
-        ),
+
+        crossbeam_channel::select! {
+            recv(self.urgent_receiver) -> record => match record {
+                Ok(record) => Some(record),
+                Err(_) => self.receiver.recv().ok(),
+            },
+            recv(self.receiver) -> record => match record {
+                Ok(record) => Some(record),
+                Err(_) => self.urgent_receiver.recv().ok(),
+            },
+        }
+    }
+
+    /// Returns the next record in the outbox, if it has any, without waiting for one.
+    pub(super) fn try_recv(&self) -> Option<OutboxRecord> {
+        self.urgent_receiver
+            .try_recv()
+            .or_else(|_| self.receiver.try_recv())
+            .ok()
     }
 }
 
-pub(super) fn send_main(
-    state: Arc<State>,
-    outbox_receiver: crossbeam_channel::Receiver<OutboxRecord>,
-) -> Result<()> {
+pub(super) fn send_main(state: Arc<State>, outbox_receiver: OutboxReceiver) -> Result<()> {
     let current_thread = thread::current();
     let thread_label = current_thread.name().expect(THREAD_NAME_FAIL);
 
     // [2018-01-08 - c74d] At least with `crossbeam_channel`'s MPSC queue implementation, this loop
     // will run until — and the sending thread will exit when — all receiving (and
     // command-handling, etc.) threads have exited. Not having to implement that myself is nice.
-    for record in outbox_receiver.iter() {
-        let OutboxRecord {
-            server_id, output, ..
-        } = match process_outgoing_msg(&state, thread_label, record) {
-            Some(a) => a,
-            None => continue,
-        };
+    //
+    // Since the `State` holds a sender as well (for `State::shutdown`), in practice this thread
+    // runs until the process exits.
+    while let Some(record) = outbox_receiver.recv() {
+        dispatch(&state, thread_label, record)?;
+    }
 
-        let aatxe_clients = match state.aatxe_clients.read() {
-            Ok(map) => map,
-            Err(_) => {
-                // TODO: This lock being poisoned is so grave that it deserves its own error kind.
-                return Err(ErrorKind::LockPoisoned(
-                    "the associative array of IRC connections".into(),
-                )
-                .into());
-            }
-        };
+    Ok(())
+}
 
-        let aatxe_client = match aatxe_clients.get(&server_id) {
-            Some(client) => client.clone(),
-            None => {
-                warn!(
-                    "Can't send to unknown server {server_id:?}. Discarding {output:?}.",
-                    server_id = server_id,
-                    output = output
-                );
-                continue;
-            }
-        };
+/// Sends the messages of the given record from the outbox to their server or, if the bot is
+/// offline, writes or logs them instead.
+pub(super) fn dispatch(state: &State, thread_label: &str, record: OutboxRecord) -> Result<()> {
+    let OutboxRecord {
+        server_id, output, ..
+    } = match process_outgoing_msg(state, thread_label, record) {
+        Some(a) => a,
+        None => return Ok(()),
+    };
+
+    let output = match bridge::divert(state, output) {
+        Some(output) => output,
+        None => return Ok(()),
+    };
 
-        send_reaction(&state, &aatxe_client, thread_label, output)
+    if state.offline {
+        state.emit_offline(server_id, output);
+        return Ok(());
     }
 
+    let aatxe_clients = match state.aatxe_clients.read() {
+        Ok(map) => map,
+        Err(_) => {
+            // TODO: This lock being poisoned is so grave that it deserves its own error kind.
+            return Err(
+                ErrorKind::LockPoisoned("the associative array of IRC connections".into()).into(),
+            );
+        }
+    };
+
+    let aatxe_client = match aatxe_clients.get(&server_id) {
+        Some(client) => client.clone(),
+        None => {
+            warn!(
+                "Can't send to unknown server {server_id:?}. Discarding {output:?}.",
+                server_id = server_id,
+                output = output
+            );
+            return Ok(());
+        }
+    };
+
+    state.metrics.record_sent(server_id, msg_count(&output));
+
+    if state.outbox.take_overflow(server_id) {
+        warn!(
+            "Disconnecting from server {server_id:?} because the outbox overflowed.",
+            server_id = server_id
+        );
+
+        // Bypass the outbox, which may well be full still.
+        send_reaction(state, server_id, &aatxe_client, thread_label, mk_quit(None));
+    }
+
+    state.record_reaction(server_id, &output);
+
+    send_reaction(state, server_id, &aatxe_client, thread_label, output);
+
     Ok(())
 }
 
+/// Returns the number of messages that the given reaction comprises.
+fn msg_count(reaction: &LibReaction<Message>) -> u64 {
+    match *reaction {
+        LibReaction::RawMsg(_) => 1,
+        LibReaction::Multi(ref reactions) => reactions.iter().map(msg_count).sum(),
+    }
+}
+
 /// All server-bound messages are to be passed through this function, which may modify them, and
 /// may prevent a message from being sent by returning `None`.
 pub(super) fn process_outgoing_msg(
-    _state: &State,
+    state: &State,
     _thread_label: &str,
     OutboxRecord { server_id, output }: OutboxRecord,
 ) -> Option<OutboxRecord> {
@@ -103,39 +352,55 @@ pub(super) fn process_outgoing_msg(
     // the same channel/query.
     //
     // TODO: Deny sending a `QUIT` if the originating command lacks `Admin` authorization.
-    if true {
-        debug!("Sending {:?}", output);
-        Some(OutboxRecord { server_id, output })
-    } else {
-        debug!("Dropping {:?}", output);
-        None
+    match output::filter_output(state, server_id, output) {
+        Some(output) => {
+            debug!("Sending {:?}", output);
+            Some(OutboxRecord { server_id, output })
+        }
+        None => None,
     }
 }
 
 fn send_reaction(
     state: &State,
+    server_id: ServerId,
     aatxe_client: &aatxe::IrcClient,
     thread_label: &str,
     reaction: LibReaction<Message>,
 ) {
-    send_reaction_with_err_cb(state, aatxe_client, thread_label, reaction, |err| {
-        let err_reaction = match state.handle_err_generic(err) {
-            Some(r) => r,
-            None => return,
-        };
+    send_reaction_with_err_cb(
+        state,
+        server_id,
+        aatxe_client,
+        thread_label,
+        reaction,
+        |err| {
+            let err_reaction = match state.handle_err_generic(server_id, err) {
+                Some(r) => r,
+                None => return,
+            };
 
-        send_reaction_with_err_cb(state, aatxe_client, thread_label, err_reaction, |err| {
-            error!(
-                "Encountered error {:?} while handling error; stopping error handling to avoid \
-                 potential infinite recursion.",
-                err
+            send_reaction_with_err_cb(
+                state,
+                server_id,
+                aatxe_client,
+                thread_label,
+                err_reaction,
+                |err| {
+                    error!(
+                        "Encountered error {:?} while handling error; stopping error handling \
+                         to avoid potential infinite recursion.",
+                        err
+                    )
+                },
             )
-        })
-    })
+        },
+    )
 }
 
 fn send_reaction_with_err_cb<ErrCb>(
     state: &State,
+    server_id: ServerId,
     aatxe_client: &aatxe::IrcClient,
     thread_label: &str,
     reaction: LibReaction<Message>,
@@ -144,14 +409,155 @@ fn send_reaction_with_err_cb<ErrCb>(
     ErrCb: Fn(Error) -> (),
 {
     match reaction {
-        LibReaction::RawMsg(msg) => match aatxe_client.send(msg) {
-            Ok(()) => {}
-            Err(e) => err_cb(e.into()),
-        },
+        LibReaction::RawMsg(msg) => {
+            // Record the `QUIT` before sending it, lest the server close the connection first.
+            if let aatxe::Command::QUIT(..) = msg.command {
+                state.record_quit_sent(server_id);
+            }
+
+            match aatxe_client.send(msg) {
+                Ok(()) => {}
+                Err(e) => err_cb(e.into()),
+            }
+        }
         LibReaction::Multi(reactions) => {
             for reaction in reactions {
-                send_reaction(state, aatxe_client, thread_label, reaction)
+                send_reaction(state, server_id, aatxe_client, thread_label, reaction)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::ServerConfigIndex;
+    use super::*;
+
+    fn outbox(
+        capacity: usize,
+        overflow_policy: OutboxOverflowPolicy,
+    ) -> (OutboxPort, OutboxReceiver) {
+        mk_outbox(&config::Outbox {
+            capacity,
+            overflow_policy,
+        })
+    }
+
+    fn push_line(outbox: &OutboxPort, server_id: ServerId, line: &str) {
+        let msg: Message = line.parse().unwrap();
+        push_to_outbox(outbox, server_id, LibReaction::RawMsg(msg));
+    }
+
+    /// Takes the records waiting in the outbox, in the order in which they would be sent,
+    /// returning their messages.
+    fn drain(outbox: &OutboxPort, receiver: &OutboxReceiver) -> Vec<String> {
+        let mut lines = Vec::new();
+
+        while !outbox.is_empty() {
+            match receiver.recv().map(|record| record.output) {
+                Some(LibReaction::RawMsg(msg)) => lines.push(msg.to_string().trim_end().to_owned()),
+                other => panic!("unexpected record: {:?}", other),
+            }
+        }
+
+        lines
+    }
+
+    #[test]
+    fn urgent_messages_jump_the_queue() {
+        let server_id = ServerId::new(ServerConfigIndex(0));
+        let (outbox, receiver) = outbox(16, OutboxOverflowPolicy::DropNewest);
+
+        for line in &[
+            "PRIVMSG #chan :1\r\n",
+            "PRIVMSG #chan :2\r\n",
+            "PONG irc.example.net\r\n",
+            "PRIVMSG #chan :3\r\n",
+            "NICK testbot_\r\n",
+            "QUIT :Bye\r\n",
+        ] {
+            push_line(&outbox, server_id, line);
+        }
+
+        assert_eq!(
+            drain(&outbox, &receiver),
+            [
+                "PONG :irc.example.net",
+                "NICK :testbot_",
+                "QUIT :Bye",
+                "PRIVMSG #chan :1",
+                "PRIVMSG #chan :2",
+                "PRIVMSG #chan :3",
+            ]
+        );
+    }
+
+    #[test]
+    fn urgent_messages_jump_a_full_queue() {
+        let server_id = ServerId::new(ServerConfigIndex(0));
+        let (outbox, receiver) = outbox(2, OutboxOverflowPolicy::DropNewest);
+
+        push_line(&outbox, server_id, "PRIVMSG #chan :1\r\n");
+        push_line(&outbox, server_id, "PRIVMSG #chan :2\r\n");
+        push_line(&outbox, server_id, "PONG irc.example.net\r\n");
+
+        assert_eq!(outbox.stats().dropped, 0);
+        assert_eq!(
+            drain(&outbox, &receiver),
+            [
+                "PONG :irc.example.net",
+                "PRIVMSG #chan :1",
+                "PRIVMSG #chan :2",
+            ]
+        );
+    }
+
+    #[test]
+    fn overflow_policies() {
+        let server_id = ServerId::new(ServerConfigIndex(0));
+
+        for &(policy, expected) in &[
+            (
+                OutboxOverflowPolicy::DropNewest,
+                ["PRIVMSG #chan :1", "PRIVMSG #chan :2"],
+            ),
+            (
+                OutboxOverflowPolicy::DropOldest,
+                ["PRIVMSG #chan :2", "PRIVMSG #chan :3"],
+            ),
+            (
+                OutboxOverflowPolicy::Disconnect,
+                ["PRIVMSG #chan :1", "PRIVMSG #chan :2"],
+            ),
+        ] {
+            let (outbox, receiver) = outbox(2, policy);
+
+            for line in &[
+                "PRIVMSG #chan :1\r\n",
+                "PRIVMSG #chan :2\r\n",
+                "PRIVMSG #chan :3\r\n",
+            ] {
+                push_line(&outbox, server_id, line);
             }
+
+            assert_eq!(
+                outbox.stats(),
+                OutboxStats {
+                    len: 2,
+                    capacity: 2,
+                    dropped: 1,
+                },
+                "{:?}",
+                policy
+            );
+            assert_eq!(
+                outbox.take_overflow(server_id),
+                policy == OutboxOverflowPolicy::Disconnect,
+                "{:?}",
+                policy
+            );
+            assert!(!outbox.take_overflow(server_id), "{:?}", policy);
+            assert_eq!(drain(&outbox, &receiver), expected, "{:?}", policy);
         }
     }
 }
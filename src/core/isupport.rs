@@ -0,0 +1,257 @@
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use util::irc::CaseMapping;
+
+/// The maximum length of an IRC message, including the terminating CR-LF sequence but excluding
+/// any IRCv3 message tags, that is to be assumed unless a server advertises otherwise.
+const DEFAULT_LINE_LEN: usize = 512;
+
+/// Information about an IRC server's features and limits, as advertised by the server with the
+/// numeric reply [`RPL_ISUPPORT`] (`005`).
+///
+/// Until the server has sent `RPL_ISUPPORT`, or for any parameter that the server does not
+/// advertise, the defaults specified in the [Modern IRC documentation][`RPL_ISUPPORT`] are
+/// assumed.
+///
+/// [`RPL_ISUPPORT`]: <https://modern.ircdocs.horse/#rplisupport-005>
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServerCapabilities {
+    /// The case-folding rules that the server uses to compare nicknames and channel names, as
+    /// advertised with the `CASEMAPPING` parameter.
+    pub casemapping: CaseMapping,
+
+    /// The characters that may begin a channel name on the server, as advertised with the
+    /// `CHANTYPES` parameter.
+    pub chantypes: Cow<'static, str>,
+
+    /// The channel membership prefixes supported by the server, as advertised with the `PREFIX`
+    /// parameter, as pairs of (0) the channel mode that grants a membership status and (1) the
+    /// character that prefixes the nicknames of channel members with that status, in descending
+    /// order of rank.
+    pub prefix: Cow<'static, [(char, char)]>,
+
+    /// The maximum length, in bytes, of a nickname on the server, as advertised with the `NICKLEN`
+    /// parameter.
+    pub nicklen: Option<usize>,
+
+    /// The maximum length, in bytes, of an IRC message that the server will accept, including the
+    /// terminating CR-LF sequence but excluding any IRCv3 message tags, as advertised with the
+    /// `LINELEN` parameter.
+    pub linelen: usize,
+
+    /// All parameters advertised by the server, including those not parsed into the other fields
+    /// of this structure, with their (unescaped) values, if any.
+    pub params: BTreeMap<String, Option<String>>,
+}
+
+impl Default for ServerCapabilities {
+    fn default() -> Self {
+        ServerCapabilities {
+            casemapping: Default::default(),
+            chantypes: "#&".into(),
+            prefix: Cow::Borrowed(&[('o', '@'), ('v', '+')]),
+            nicklen: None,
+            linelen: DEFAULT_LINE_LEN,
+            params: Default::default(),
+        }
+    }
+}
+
+impl ServerCapabilities {
+    /// Returns the name of the IRC network to which the server belongs, as advertised with the
+    /// `NETWORK` parameter, if the server has advertised it.
+    pub fn network(&self) -> Option<&str> {
+        self.params
+            .get("NETWORK")
+            .and_then(Option::as_ref)
+            .map(String::as_str)
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Updates this structure with the parameters from an `RPL_ISUPPORT` message.
+    ///
+    /// The `tokens` should be the message's parameters excluding the first (the client's
+    /// nickname) and the trailing human-readable text (typically "are supported by this server").
+    pub(super) fn update_from_isupport_tokens<'a, I>(&mut self, tokens: I)
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        for token in tokens {
+            if token.is_empty() {
+                continue;
+            }
+
+            if token.starts_with('-') {
+                let key = &token[1..];
+                self.params.remove(key);
+                self.reset_param(key);
+                continue;
+            }
+
+            let mut key_and_value = token.splitn(2, '=');
+            let key = key_and_value.next().unwrap_or("");
+            let value = key_and_value.next().map(unescape_isupport_value);
+
+            self.apply_param(key, value.as_ref().map(AsRef::as_ref));
+            self.params.insert(key.to_owned(), value);
+        }
+    }
+
+    fn apply_param(&mut self, key: &str, value: Option<&str>) {
+        let value = value.unwrap_or("");
+
+        match key {
+            "CASEMAPPING" => match value.parse() {
+                Ok(casemapping) => self.casemapping = casemapping,
+                Err(()) => {
+                    debug!("Unrecognized `CASEMAPPING` {:?}; ignoring it.", value);
+                }
+            },
+            "CHANTYPES" => self.chantypes = value.to_owned().into(),
+            "PREFIX" => match parse_prefix_param(value) {
+                Some(prefix) => self.prefix = prefix.into(),
+                None => debug!("Malformed `PREFIX` {:?}; ignoring it.", value),
+            },
+            "NICKLEN" => self.nicklen = value.parse().ok(),
+            "LINELEN" => match value.parse() {
+                Ok(n) if n >= DEFAULT_LINE_LEN => self.linelen = n,
+                _ => debug!("Unusable `LINELEN` {:?}; ignoring it.", value),
+            },
+            _ => {}
+        }
+    }
+
+    fn reset_param(&mut self, key: &str) {
+        let default = Self::default();
+
+        match key {
+            "CASEMAPPING" => self.casemapping = default.casemapping,
+            "CHANTYPES" => self.chantypes = default.chantypes,
+            "PREFIX" => self.prefix = default.prefix,
+            "NICKLEN" => self.nicklen = default.nicklen,
+            "LINELEN" => self.linelen = default.linelen,
+            _ => {}
+        }
+    }
+
+    /// Returns whether the given string appears to be the name of a channel, judging by whether
+    /// it starts with one of the channel type characters the server has advertised.
+    pub fn is_channel_name(&self, s: &str) -> bool {
+        s.chars()
+            .next()
+            .map(|c| self.chantypes.contains(c))
+            .unwrap_or(false)
+    }
+}
+
+/// Parses the value of a `PREFIX` parameter, such as `(ov)@+`.
+fn parse_prefix_param(value: &str) -> Option<Vec<(char, char)>> {
+    if value.is_empty() {
+        return Some(Vec::new());
+    }
+
+    if !value.starts_with('(') {
+        return None;
+    }
+
+    let mut parts = value[1..].splitn(2, ')');
+    let modes = parts.next()?;
+    let prefixes = parts.next()?;
+
+    if modes.chars().count() != prefixes.chars().count() {
+        return None;
+    }
+
+    Some(modes.chars().zip(prefixes.chars()).collect())
+}
+
+/// Unescapes a parameter value from an `RPL_ISUPPORT` message, in which characters may be escaped
+/// with the sequence `\xHH`, where `HH` is the character's code in hexadecimal.
+fn unescape_isupport_value(value: &str) -> String {
+    let mut output = Vec::with_capacity(value.len());
+    let bytes = value.as_bytes();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && bytes.get(i + 1) == Some(&b'x') {
+            if let Some(n) = value
+                .get(i + 2..i + 4)
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            {
+                output.push(n);
+                i += 4;
+                continue;
+            }
+        }
+
+        output.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&output).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn caps(tokens: &[&str]) -> ServerCapabilities {
+        let mut caps = ServerCapabilities::default();
+        caps.update_from_isupport_tokens(tokens.iter().cloned());
+        caps
+    }
+
+    #[test]
+    fn isupport_examples() {
+        let c = caps(&[
+            "CASEMAPPING=ascii",
+            "CHANTYPES=#",
+            "PREFIX=(qaohv)~&@%+",
+            "NICKLEN=30",
+            "NETWORK=Example\\x20Net",
+            "EXCEPTS",
+        ]);
+
+        assert_eq!(c.casemapping, CaseMapping::Ascii);
+        assert_eq!(c.chantypes, "#");
+        assert_eq!(
+            &*c.prefix,
+            &[('q', '~'), ('a', '&'), ('o', '@'), ('h', '%'), ('v', '+')]
+        );
+        assert_eq!(c.nicklen, Some(30));
+        assert_eq!(c.linelen, DEFAULT_LINE_LEN);
+        assert_eq!(
+            c.params.get("NETWORK"),
+            Some(&Some("Example Net".to_owned()))
+        );
+        assert_eq!(c.network(), Some("Example Net"));
+        assert_eq!(c.params.get("EXCEPTS"), Some(&None));
+        assert!(c.is_channel_name("#rust"));
+        assert!(!c.is_channel_name("&rust"));
+    }
+
+    #[test]
+    fn isupport_negation_restores_defaults() {
+        let mut c = caps(&["CASEMAPPING=strict-rfc1459", "LINELEN=2048"]);
+        assert_eq!(c.casemapping, CaseMapping::StrictRfc1459);
+        assert_eq!(c.linelen, 2048);
+
+        c.update_from_isupport_tokens(vec!["-CASEMAPPING", "-LINELEN"]);
+        assert_eq!(c, ServerCapabilities::default());
+    }
+
+    #[test]
+    fn isupport_ignores_malformed_values() {
+        let c = caps(&[
+            "CASEMAPPING=bogus",
+            "PREFIX=(ov)@",
+            "LINELEN=12",
+            "NICKLEN=x",
+        ]);
+
+        assert_eq!(c.casemapping, CaseMapping::Rfc1459);
+        assert_eq!(&*c.prefix, &[('o', '@'), ('v', '+')]);
+        assert_eq!(c.linelen, DEFAULT_LINE_LEN);
+        assert_eq!(c.nicklen, None);
+    }
+}
@@ -0,0 +1,198 @@
+use super::irc_send::push_to_outbox;
+use super::ErrorKind;
+use super::LibReaction;
+use super::Result;
+use super::ServerId;
+use super::State;
+use crossbeam_channel;
+use irc::client::prelude as aatxe;
+use irc::proto::message::Tag;
+use irc::proto::Message;
+use std::time::Duration;
+
+/// The prefix of the labels attached to queries sent with `State::query`
+const LABEL_PREFIX: &str = "irc-bot-";
+
+/// The queries sent with `State::query` to a server that await their complete responses
+#[derive(Debug, Default)]
+pub(super) struct LabeledQueries {
+    pending: Vec<PendingQuery>,
+
+    /// A counter used to make the labels unique
+    counter: u64,
+}
+
+#[derive(Debug)]
+struct PendingQuery {
+    label: String,
+
+    /// The reference tag of the `labeled-response` batch in which the server is sending its
+    /// response, if it is sending a batch
+    batch: Option<String>,
+
+    lines: Vec<String>,
+
+    reply_sender: crossbeam_channel::Sender<Vec<String>>,
+}
+
+impl LabeledQueries {
+    /// Forgets all pending queries, e.g., because the connection has been closed. Their callers
+    /// will receive errors.
+    pub(super) fn clear(&mut self) {
+        self.pending.clear()
+    }
+
+    fn take<P>(&mut self, pred: P) -> Option<PendingQuery>
+    where
+        P: Fn(&PendingQuery) -> bool,
+    {
+        self.pending
+            .iter()
+            .position(pred)
+            .map(|i| self.pending.remove(i))
+    }
+}
+
+impl State {
+    /// Sends the given raw IRC message, such as `WHOIS alice` or `MODE #channel b`, to the given
+    /// server and waits for the server's complete response, returning the raw lines of the
+    /// response, of which there may be none.
+    ///
+    /// The response is correlated with the request by means of the IRCv3 capability
+    /// `labeled-response`, which ensures that it is not confused with the responses to other
+    /// requests, whether sent by other modules or by users. If the server has not enabled this
+    /// capability, an error is returned without anything being sent. An error also is returned if
+    /// the server does not respond within the given timeout.
+    ///
+    /// As this function blocks until the response arrives, it must not be called from handlers
+    /// that are run while the bot is handling messages from the server, such as those given to
+    /// [`ModuleBuilder::on_user_event`]; bot commands and triggers may call it.
+    ///
+    /// [`ModuleBuilder::on_user_event`]: <struct.ModuleBuilder.html#method.on_user_event>
+    pub fn query(
+        &self,
+        server_id: ServerId,
+        request: &str,
+        timeout: Duration,
+    ) -> Result<Vec<String>> {
+        ensure!(
+            self.capability_enabled(server_id, "labeled-response")?,
+            ErrorKind::CapabilityNotEnabled("labeled-response".into())
+        );
+
+        let mut msg: Message = request.parse()?;
+
+        let (reply_sender, reply_receiver) = crossbeam_channel::bounded(1);
+
+        let label = {
+            let mut server = self.write_server(server_id)?;
+            let queries = &mut server.labeled_queries;
+
+            queries.counter += 1;
+            let label = format!("{}{}", LABEL_PREFIX, queries.counter);
+
+            queries.pending.push(PendingQuery {
+                label: label.clone(),
+                batch: None,
+                lines: Vec::new(),
+                reply_sender,
+            });
+
+            label
+        };
+
+        msg.tags
+            .get_or_insert_with(Vec::new)
+            .push(Tag("label".into(), Some(label.clone())));
+
+        push_to_outbox(&self.outbox, server_id, LibReaction::RawMsg(msg));
+
+        match reply_receiver.recv_timeout(timeout) {
+            Ok(lines) => Ok(lines),
+            Err(_) => {
+                if let Ok(mut server) = self.write_server(server_id) {
+                    server.labeled_queries.take(|q| q.label == label);
+                }
+
+                Err(ErrorKind::QueryTimeout(request.to_owned()).into())
+            }
+        }
+    }
+}
+
+/// Checks whether the given message from the given server is part of the response to a query sent
+/// with `State::query`, and, if so, records it, passing the whole response to the query's caller
+/// once the response is complete.
+pub(super) fn handle_msg(state: &State, server_id: ServerId, msg: &Message) -> Result<()> {
+    let tag = |name: &str| {
+        msg.tags
+            .iter()
+            .flat_map(|tags| tags.iter())
+            .find(|tag| tag.0 == name)
+            .and_then(|tag| tag.1.as_ref())
+    };
+
+    let label = tag("label");
+    let batch = tag("batch");
+
+    if state
+        .read_server(server_id)?
+        .labeled_queries
+        .pending
+        .is_empty()
+    {
+        return Ok(());
+    }
+
+    let line = || msg.to_string().trim_end_matches("\r\n").to_owned();
+
+    let completed = {
+        let mut server = state.write_server(server_id)?;
+        let queries = &mut server.labeled_queries;
+
+        match (label, &msg.command) {
+            (Some(label), aatxe::Command::BATCH(reference, ..)) if reference.starts_with('+') => {
+                if let Some(query) = queries.pending.iter_mut().find(|q| q.label == *label) {
+                    query.batch = Some(reference[1..].to_owned());
+                }
+                None
+            }
+            (Some(label), command) => queries.take(|q| q.label == *label).map(|mut query| {
+                match command {
+                    // An `ACK` signifies that the response is empty.
+                    aatxe::Command::Raw(cmd, ..) if cmd == "ACK" => {}
+                    _ => query.lines.push(line()),
+                }
+                query
+            }),
+            (None, aatxe::Command::BATCH(reference, ..)) if reference.starts_with('-') => {
+                let reference = &reference[1..];
+                queries.take(|q| q.batch.as_ref().map(|b| &b[..]) == Some(reference))
+            }
+            (None, _) => {
+                if let Some(batch) = batch {
+                    if let Some(query) = queries
+                        .pending
+                        .iter_mut()
+                        .find(|q| q.batch.as_ref() == Some(batch))
+                    {
+                        query.lines.push(line());
+                    }
+                }
+                None
+            }
+        }
+    };
+
+    if let Some(PendingQuery {
+        lines,
+        reply_sender,
+        ..
+    }) = completed
+    {
+        // The caller may have given up waiting, in which case the response is of no interest.
+        let _ = reply_sender.send(lines);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,233 @@
+use super::Result;
+use super::ServerId;
+use super::State;
+use irc::client::prelude as aatxe;
+use irc::client::prelude::Client as AatxeClient;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The prefix of the tokens of the `PING`s sent to measure lag, which distinguishes them from the
+/// `PING`s that the `irc` crate sends for its own purposes.
+const LAG_PING_TOKEN_PREFIX: &str = "irc-bot-lag-";
+
+/// The state of lag measurement for a server connection.
+#[derive(Debug, Default)]
+pub(super) struct LagProbe {
+    /// The token and time of sending of the lag-measuring `PING` to which a `PONG` is awaited, if
+    /// any.
+    outstanding: Option<(String, Instant)>,
+
+    /// The most recently measured round-trip time.
+    last_lag: Option<Duration>,
+
+    /// A counter used to make the `PING` tokens unique.
+    counter: u64,
+}
+
+/// What the lag watchdog is to do next
+enum WatchdogStep {
+    /// Wait for the given time before checking again
+    Wait(Duration),
+
+    /// Send a `PING` with the given token
+    Ping(String),
+
+    /// Close the stalled connection
+    Abort,
+}
+
+impl State {
+    /// Returns the round-trip lag of the connection to the given server, as measured by the time
+    /// taken for the server to answer a `PING`, or `None` if no lag has been measured yet.
+    ///
+    /// If the server is late in answering a `PING`, the time elapsed since the `PING` was sent is
+    /// returned, so that the lag reported grows while a connection has stalled.
+    pub fn server_lag(&self, server_id: ServerId) -> Result<Option<Duration>> {
+        let server = self.read_server(server_id)?;
+        let probe = &server.lag_probe;
+
+        Ok(match (&probe.outstanding, probe.last_lag) {
+            (&Some((_, sent)), Some(lag)) if sent.elapsed() > lag => Some(sent.elapsed()),
+            (&Some((_, sent)), None) => Some(sent.elapsed()),
+            (_, lag) => lag,
+        })
+    }
+}
+
+/// Periodically sends `PING`s to the given server to measure the connection's lag, until sending
+/// fails or the connection with the given generation number has been replaced by a new one.
+///
+/// If the server fails to answer a `PING` within the per-server setting `ping timeout`, the
+/// connection is taken to have stalled, and is closed at once and replaced by a new one.
+pub(super) fn lag_watchdog_main(
+    state: Arc<State>,
+    server_id: ServerId,
+    generation: u64,
+) -> Result<()> {
+    let (interval, timeout) = {
+        let server = state.read_server(server_id)?;
+        let cfg = &server.aatxe_config;
+        (
+            Duration::from_secs(cfg.ping_time().into()),
+            Duration::from_secs(cfg.ping_timeout().into()),
+        )
+    };
+
+    // When the next lag-measuring `PING` is due
+    let mut next_ping = Instant::now() + interval;
+
+    loop {
+        let step = {
+            let mut server = state.write_server(server_id)?;
+
+            if server.connection_generation != generation {
+                return Ok(());
+            }
+
+            let now = Instant::now();
+            let probe = &mut server.lag_probe;
+
+            match probe.outstanding {
+                Some((_, sent)) if now.duration_since(sent) >= timeout => {
+                    warn!(
+                        "[{}] No answer to lag-measuring PING after {} seconds; reconnecting.",
+                        server.socket_addr_string,
+                        now.duration_since(sent).as_secs()
+                    );
+                    WatchdogStep::Abort
+                }
+                // No `PING` replaces one that is awaiting an answer, lest the connection's
+                // stalling go unnoticed.
+                Some((_, sent)) => {
+                    let until_timeout = timeout - now.duration_since(sent);
+
+                    WatchdogStep::Wait(if next_ping > now {
+                        until_timeout.min(next_ping - now)
+                    } else {
+                        until_timeout
+                    })
+                }
+                None if now >= next_ping => {
+                    probe.counter += 1;
+                    let token = format!("{}{}", LAG_PING_TOKEN_PREFIX, probe.counter);
+                    probe.outstanding = Some((token.clone(), now));
+                    next_ping = now + interval;
+                    WatchdogStep::Ping(token)
+                }
+                None => WatchdogStep::Wait(next_ping - now),
+            }
+        };
+
+        match step {
+            WatchdogStep::Wait(duration) => thread::sleep(duration),
+            WatchdogStep::Ping(token) => {
+                state.with_aatxe_client(server_id, |client| {
+                    client
+                        .send(aatxe::Command::PING(token, None))
+                        .map_err(Into::into)
+                })?;
+
+                thread::sleep(interval.min(timeout));
+            }
+            WatchdogStep::Abort => return state.abort_connection(server_id),
+        }
+    }
+}
+
+/// Handles a `PONG` from the given server, which may answer one of our lag-measuring `PING`s.
+pub(super) fn handle_pong(state: &State, server_id: ServerId, token: &str) -> Result<()> {
+    if !token.starts_with(LAG_PING_TOKEN_PREFIX) {
+        return Ok(());
+    }
+
+    let mut server = state.write_server(server_id)?;
+
+    let lag = match server.lag_probe.outstanding {
+        Some((ref expected, sent)) if expected == token => sent.elapsed(),
+        _ => return Ok(()),
+    };
+
+    server.lag_probe.outstanding = None;
+    server.lag_probe.last_lag = Some(lag);
+
+    trace!(
+        "[{}] Measured lag: {} ms",
+        server.socket_addr_string,
+        lag.as_secs() * 1000 + u64::from(lag.subsec_millis())
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::offline;
+    use std::io::BufRead;
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::time::Duration;
+    use std::time::Instant;
+    use testing;
+
+    #[test]
+    fn reconnect_on_ping_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+
+        let bot = testing::spawn_bot(
+            offline::test_config(&format!(
+                "servers:\n  \
+                 - name: stalling\n    \
+                 host: {:?}\n    \
+                 port: {}\n    \
+                 TLS: false\n    \
+                 ping interval: 1\n    \
+                 ping timeout: 3\n",
+                addr.ip().to_string(),
+                addr.port(),
+            )),
+            done.clone(),
+        );
+
+        // On the first connection, the server answers the `irc` crate's own `PING`s but not the
+        // lag-measuring ones, which the bot should notice, although its `ping interval` is shorter
+        // than its `ping timeout`, and replace the connection with a second.
+        let mut first = testing::accept_silently(&listener);
+        let start = Instant::now();
+
+        let answerer = thread::spawn(move || {
+            let mut line = String::new();
+
+            while first.read_line(&mut line).unwrap_or(0) > 0 {
+                if line.starts_with("PING ") {
+                    let token = line["PING ".len()..].trim().trim_start_matches(':');
+
+                    if !token.starts_with(LAG_PING_TOKEN_PREFIX) {
+                        let pong = format!("PONG :{}\r\n", token);
+                        if first.get_mut().write_all(pong.as_bytes()).is_err() {
+                            break;
+                        }
+                    }
+                }
+
+                line.clear();
+            }
+        });
+
+        let second = testing::accept_silently(&listener);
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        done.store(true, Ordering::SeqCst);
+        testing::await_quit(second);
+        answerer.join().unwrap();
+
+        bot.join().unwrap();
+    }
+}
@@ -0,0 +1,362 @@
+use super::ServerId;
+use super::State;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// The media type of the Prometheus text exposition format
+pub(super) const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// The upper bounds, in seconds, of the buckets of the histograms of handlers' run times
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// The percentiles of commands' run times to report, with the corresponding quantiles
+const LATENCY_PERCENTILES: &[(u8, &str)] = &[(50, "0.5"), (90, "0.9"), (99, "0.99")];
+
+/// Counters of the bot's activity, which the bot serves in the Prometheus text exposition format
+/// if so configured
+#[derive(Debug, Default)]
+pub(super) struct Metrics {
+    inner: Mutex<MetricsData>,
+}
+
+#[derive(Debug, Default)]
+struct MetricsData {
+    msgs_received: BTreeMap<ServerId, u64>,
+    msgs_sent: BTreeMap<ServerId, u64>,
+    reconnects: BTreeMap<ServerId, u64>,
+
+    /// The run times of bot commands' and triggers' handlers and of modules' input filters, by the
+    /// kind of handler (`command`, `trigger`, or `input filter`) and the name of the command,
+    /// trigger, or module
+    handler_runs: BTreeMap<(&'static str, String), Histogram>,
+}
+
+#[derive(Debug)]
+struct Histogram {
+    /// The number of observations that fell into each of the `LATENCY_BUCKETS`, not counting
+    /// those that fell into lower buckets
+    buckets: Vec<u64>,
+
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            buckets: vec![0; LATENCY_BUCKETS.len()],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = LATENCY_BUCKETS.iter().position(|&bound| value <= bound) {
+            self.buckets[i] += 1;
+        }
+
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+impl Metrics {
+    fn with<F>(&self, f: F)
+    where
+        F: FnOnce(&mut MetricsData),
+    {
+        match self.inner.lock() {
+            Ok(mut data) => f(&mut data),
+            Err(_) => error!("The metrics' lock is poisoned."),
+        }
+    }
+
+    pub(super) fn record_received(&self, server_id: ServerId) {
+        self.with(|data| *data.msgs_received.entry(server_id).or_insert(0) += 1)
+    }
+
+    pub(super) fn record_sent(&self, server_id: ServerId, n: u64) {
+        self.with(|data| *data.msgs_sent.entry(server_id).or_insert(0) += n)
+    }
+
+    pub(super) fn record_reconnect(&self, server_id: ServerId) {
+        self.with(|data| *data.reconnects.entry(server_id).or_insert(0) += 1)
+    }
+
+    /// Returns the numbers of messages received from and sent to the given server.
+    pub(super) fn msg_counts(&self, server_id: ServerId) -> (u64, u64) {
+        let mut counts = (0, 0);
+
+        self.with(|data| {
+            counts = (
+                data.msgs_received.get(&server_id).cloned().unwrap_or(0),
+                data.msgs_sent.get(&server_id).cloned().unwrap_or(0),
+            )
+        });
+
+        counts
+    }
+
+    /// Records that the handler of the bot command, trigger, or module's input filter (per `kind`)
+    /// with the given name took the given time to run.
+    pub(super) fn record_handler_run(&self, kind: &'static str, name: &str, time: Duration) {
+        let secs = secs(time);
+
+        self.with(|data| {
+            data.handler_runs
+                .entry((kind, name.to_owned()))
+                .or_insert_with(Histogram::new)
+                .observe(secs)
+        })
+    }
+
+    /// Renders the metrics in the Prometheus text exposition format.
+    pub(super) fn render(&self, state: &State) -> String {
+        let mut out = String::new();
+
+        let server_name = |server_id: ServerId| match state.get_server_config(server_id) {
+            Ok(cfg) => cfg.name.clone(),
+            Err(_) => format!("{:?}", server_id),
+        };
+
+        let data = match self.inner.lock() {
+            Ok(data) => data,
+            Err(_) => return out,
+        };
+
+        for &(name, help, counts) in &[
+            (
+                "irc_bot_messages_received_total",
+                "Messages received from the server.",
+                &data.msgs_received,
+            ),
+            (
+                "irc_bot_messages_sent_total",
+                "Messages sent to the server.",
+                &data.msgs_sent,
+            ),
+            (
+                "irc_bot_reconnects_total",
+                "Reconnections to the server.",
+                &data.reconnects,
+            ),
+        ] {
+            header(&mut out, name, help, "counter");
+
+            for (&server_id, count) in counts {
+                let _ = writeln!(
+                    out,
+                    "{}{{server={}}} {}",
+                    name,
+                    label_value(&server_name(server_id)),
+                    count
+                );
+            }
+        }
+
+        header(
+            &mut out,
+            "irc_bot_command_invocations_total",
+            "Invocations of bot commands.",
+            "counter",
+        );
+
+        for (&(kind, ref name), histogram) in &data.handler_runs {
+            if kind == "command" {
+                let _ = writeln!(
+                    out,
+                    "irc_bot_command_invocations_total{{command={}}} {}",
+                    label_value(name),
+                    histogram.count
+                );
+            }
+        }
+
+        let usage = state.command_usage().unwrap_or_default();
+
+        header(
+            &mut out,
+            "irc_bot_command_uses_total",
+            "Uses of bot commands, including those before the bot last started if the statistics \
+             are stored.",
+            "counter",
+        );
+
+        for (name, usage) in &usage {
+            let _ = writeln!(
+                out,
+                "irc_bot_command_uses_total{{command={}}} {}",
+                label_value(name),
+                usage.invocations
+            );
+        }
+
+        header(
+            &mut out,
+            "irc_bot_command_errors_total",
+            "Uses of bot commands that did not succeed.",
+            "counter",
+        );
+
+        for (name, usage) in &usage {
+            let _ = writeln!(
+                out,
+                "irc_bot_command_errors_total{{command={}}} {}",
+                label_value(name),
+                usage.errors
+            );
+        }
+
+        header(
+            &mut out,
+            "irc_bot_command_timeouts_total",
+            "Uses of bot commands that were given up on because their handlers took too long.",
+            "counter",
+        );
+
+        for (name, usage) in &usage {
+            let _ = writeln!(
+                out,
+                "irc_bot_command_timeouts_total{{command={}}} {}",
+                label_value(name),
+                usage.timeouts
+            );
+        }
+
+        header(
+            &mut out,
+            "irc_bot_command_latency_seconds",
+            "Percentiles of the run times of bot commands' handlers on their most recent runs.",
+            "gauge",
+        );
+
+        for (name, usage) in &usage {
+            for &(percentile, quantile) in LATENCY_PERCENTILES {
+                if let Some(latency) = usage.latency_percentile(percentile) {
+                    let _ = writeln!(
+                        out,
+                        "irc_bot_command_latency_seconds{{command={},quantile=\"{}\"}} {}",
+                        label_value(name),
+                        quantile,
+                        secs(latency)
+                    );
+                }
+            }
+        }
+
+        header(
+            &mut out,
+            "irc_bot_handler_duration_seconds",
+            "Run times of bot commands' and triggers' handlers and of input filters.",
+            "histogram",
+        );
+
+        for (&(kind, ref name), histogram) in &data.handler_runs {
+            let labels = format!("kind={},name={}", label_value(kind), label_value(name));
+            let mut cumulative = 0;
+
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(&histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(
+                    out,
+                    "irc_bot_handler_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    labels, bound, cumulative
+                );
+            }
+
+            let _ = writeln!(
+                out,
+                "irc_bot_handler_duration_seconds_bucket{{{},le=\"+Inf\"}} {}",
+                labels, histogram.count
+            );
+            let _ = writeln!(
+                out,
+                "irc_bot_handler_duration_seconds_sum{{{}}} {}",
+                labels, histogram.sum
+            );
+            let _ = writeln!(
+                out,
+                "irc_bot_handler_duration_seconds_count{{{}}} {}",
+                labels, histogram.count
+            );
+        }
+
+        let outbox = state.outbox_stats();
+
+        header(
+            &mut out,
+            "irc_bot_outbox_messages",
+            "Messages waiting to be sent.",
+            "gauge",
+        );
+        let _ = writeln!(out, "irc_bot_outbox_messages {}", outbox.len);
+
+        header(
+            &mut out,
+            "irc_bot_outbox_dropped_total",
+            "Messages discarded because the outbox was full.",
+            "counter",
+        );
+        let _ = writeln!(out, "irc_bot_outbox_dropped_total {}", outbox.dropped);
+
+        out
+    }
+}
+
+fn secs(time: Duration) -> f64 {
+    time.as_secs() as f64 + f64::from(time.subsec_nanos()) / 1e9
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Formats the given string as a quoted label value, escaping it as the exposition format
+/// requires.
+fn label_value(s: &str) -> String {
+    let mut output = String::with_capacity(s.len() + 2);
+
+    output.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            c => output.push(c),
+        }
+    }
+
+    output.push('"');
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_buckets() {
+        let mut histogram = Histogram::new();
+
+        histogram.observe(0.001);
+        histogram.observe(0.3);
+        histogram.observe(60.0);
+
+        assert_eq!(histogram.count, 3);
+        assert_eq!(histogram.buckets[0], 1);
+        assert_eq!(histogram.buckets[6], 1);
+        assert_eq!(histogram.buckets.iter().sum::<u64>(), 2);
+    }
+
+    #[test]
+    fn label_values() {
+        assert_eq!(label_value("a\"b\\c\nd"), "\"a\\\"b\\\\c\\nd\"");
+    }
+}
@@ -1,74 +1,227 @@
+pub use self::bot_cmd::next_cmd_arg;
+pub use self::bot_cmd::split_cmd_args;
 pub use self::bot_cmd::BotCmdAttr;
 pub use self::bot_cmd::BotCmdAuthLvl;
 pub use self::bot_cmd::BotCmdResult;
 pub use self::bot_cmd::BotCommand;
+pub use self::bot_cmd::ReplyRoute;
+pub use self::bridge::bridged_channel_name;
+pub use self::bridge::Bridge;
+pub use self::bridge::BridgedMsg;
+pub use self::chan_log::LoggedMsg;
+pub use self::cmd_arg::finish_cmd_args;
+pub use self::cmd_arg::take_cmd_arg;
+pub use self::cmd_arg::Channel;
+pub use self::cmd_arg::FromCmdArg;
+pub use self::cmd_arg::Nick;
+pub use self::cmd_arg::RestOfLine;
+pub use self::cmd_arg::TypedCommand;
+pub use self::cmd_stats::CommandUsage;
 pub use self::config::Config;
+pub use self::config::ConfigBuilder;
+pub use self::config::ConfigFormat;
 pub use self::config::IntoConfig;
 pub use self::err::Error;
+pub use self::err::ErrorContext;
 pub use self::err::ErrorKind;
 pub use self::err::Result;
+pub use self::err::Severity;
+pub use self::err_policy::Policy;
+pub use self::fetch::fetch;
+pub use self::fetch::FetchLimits;
+pub use self::fetch::FetchedDoc;
+pub use self::flood::FloodKind;
 pub use self::handler::BotCmdHandler;
+pub use self::handler::EchoedMsgHandler;
 pub use self::handler::ErrorHandler;
+pub use self::handler::ErrorHandlerMut;
 pub use self::handler::HandlerContext;
+pub use self::handler::InputFilter;
 pub use self::handler::ModuleFeatureRef;
 pub use self::handler::ModuleLoadHandler;
+pub use self::handler::ModuleUnloadHandler;
+pub use self::handler::OutputFilter;
+pub use self::handler::PeriodicHandler;
+pub use self::handler::PresenceHandler;
+pub use self::handler::ServerConnectionHandler;
+pub use self::handler::StatefulErrorHandler;
 pub use self::handler::TriggerHandler;
-use self::irc_msgs::parse_msg_to_nick;
+pub use self::handler::UnknownCommandHandler;
+pub use self::handler::UserEventHandler;
+pub use self::history::HistoryMsg;
+pub use self::i18n::BUILT_IN_MESSAGES;
+pub use self::input::IncomingMsg;
+pub use self::input::InputVerdict;
+pub use self::irc_msgs::Ctcp;
+pub use self::irc_msgs::IrcCaseInsensitive;
 pub use self::irc_msgs::MsgDest;
 pub use self::irc_msgs::MsgMetadata;
 pub use self::irc_msgs::MsgPrefix;
+pub use self::irc_msgs::MsgTags;
+pub use self::irc_msgs::MsgTagsIter;
 use self::irc_msgs::OwningMsgPrefix;
 use self::irc_send::push_to_outbox;
+use self::irc_send::OutboxPort;
+pub use self::irc_send::OutboxStats;
+pub use self::isupport::ServerCapabilities;
 use self::misc_traits::GetDebugInfo;
+pub use self::modl_stats::ModuleUsage;
 pub use self::modl_sys::mk_module;
 pub use self::modl_sys::Module;
 use self::modl_sys::ModuleFeatureInfo;
 use self::modl_sys::ModuleInfo;
 use self::modl_sys::ModuleLoadMode;
+pub use self::offline::simulate;
+pub use self::output::OutgoingMsg;
+pub use self::output::OutputVerdict;
+pub use self::paste::PasteService;
+pub use self::prefs::parse_utc_offset;
+pub use self::prefs::USER_PREFS;
+pub use self::presence::Presence;
 pub use self::reaction::ErrorReaction;
 use self::reaction::LibReaction;
 pub use self::reaction::Reaction;
+pub use self::recording::replay;
 pub use self::trigger::Trigger;
 pub use self::trigger::TriggerAttr;
 pub use self::trigger::TriggerPriority;
-use crossbeam_channel;
+pub use self::users::QuitKind;
+pub use self::users::UserEvent;
+pub use self::users::UserId;
+use atty;
+use futures;
+use futures::future::Either;
+use futures::future::Loop;
+use futures::sync::oneshot;
+use futures::Future;
+use futures::Stream;
+use irc;
 use irc::client::prelude as aatxe;
+use irc::client::prelude::Client as AatxeClient;
 use irc::client::prelude::ClientExt as AatxeClientExt;
+use irc::client::PackedIrcClient;
 use irc::proto::Message;
 use rand::EntropyRng;
 use rand::SeedableRng;
 use rand::StdRng;
 use std::borrow::Borrow;
 use std::borrow::Cow;
+use std::cmp;
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::convert::TryFrom;
 use std::convert::TryInto;
+use std::io::Write;
+use std::path::Path;
 use std::path::PathBuf;
+#[cfg(unix)]
+use std::process;
+use std::slice;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::sync::RwLock;
 use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use tokio_core::reactor::Handle;
+use tokio_core::reactor::Timeout;
 use util;
 use uuid::Uuid;
 
 pub(crate) mod bot_cmd;
 
+mod admin_auth;
+mod aliases;
+mod audit;
+mod batch;
+mod bridge;
+mod chan_cmds;
+mod chan_log;
+#[macro_use]
+mod cmd_arg;
+mod cmd_stats;
 mod config;
+mod console;
+mod control;
+#[cfg(unix)]
+mod control_socket;
+mod dcc;
 mod err;
+mod err_notify;
+mod err_policy;
+mod fetch;
+mod flood;
 mod handler;
+mod history;
+mod http;
+mod i18n;
+mod input;
 mod irc_comm;
 mod irc_msgs;
 mod irc_send;
+mod isupport;
+mod labeled;
+mod lag;
+mod metrics;
 mod misc_traits;
+mod moderation;
+mod modl_stats;
 mod modl_sys;
+mod offline;
+mod output;
+mod pager;
+mod paste;
 mod pkg_info;
+mod prefs;
+mod presence;
 mod reaction;
+mod recording;
+mod reload;
+mod scheduler;
+mod shadow_ban;
 mod state;
+mod stats;
+mod sts;
 mod trigger;
+mod users;
+mod webhook;
 
 const THREAD_NAME_FAIL: &str = "This thread is unnamed?! We specifically gave it a name; what \
                                 happened?!";
 
+/// The IRCv3 capabilities that the bot requests of servers
+const CAPS_TO_REQUEST: &[aatxe::Capability] = &[
+    aatxe::Capability::MultiPrefix,
+    aatxe::Capability::Custom("message-tags"),
+    aatxe::Capability::ServerTime,
+    aatxe::Capability::EchoMessage,
+    aatxe::Capability::Batch,
+    aatxe::Capability::Custom("labeled-response"),
+    aatxe::Capability::AccountNotify,
+    aatxe::Capability::ExtendedJoin,
+    aatxe::Capability::Custom("draft/chathistory"),
+];
+
+/// How long `State::shutdown` waits for the queue of outgoing messages to be flushed.
+const SHUTDOWN_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long to wait, after beginning to shut down in response to a signal, for the bot's
+/// connections to close before exiting regardless.
+#[cfg(unix)]
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// How long to wait before retrying a failed reconnection at first, which doubles with each
+/// further failure up to `RECONNECT_MAX_DELAY`
+const RECONNECT_MIN_DELAY: Duration = Duration::from_secs(1);
+
+/// The longest time to wait before retrying a failed reconnection
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(300);
+
+/// How often to check whether the bot is shutting down while waiting to retry a reconnection
+const RECONNECT_TICK: Duration = Duration::from_secs(1);
+
 const LOCK_EARLY_POISON_FAIL: &str =
     "A lock was poisoned?! Already?! We really oughtn't have panicked yet, so let's panic some \
      more....";
@@ -79,25 +232,68 @@ pub struct State {
 
     addressee_suffix: Cow<'static, str>,
 
-    commands: BTreeMap<Cow<'static, str>, BotCommand>,
+    aliases: RwLock<aliases::RuntimeAliases>,
+
+    commands: RwLock<BTreeMap<Cow<'static, str>, Arc<BotCommand>>>,
 
-    config: config::Config,
+    /// The statistics of the use of the bot's commands
+    command_stats: Mutex<cmd_stats::CommandStats>,
+
+    config: RwLock<Arc<config::Config>>,
+
+    /// The connection settings with which the bot has reconnected to servers, each kept once for
+    /// as long as the program runs, as the `irc` crate requires of the settings of a connection
+    /// established on the reactor
+    connection_configs: Mutex<Vec<&'static aatxe::Config>>,
+
+    dcc_pending_sends: Mutex<BTreeMap<String, dcc::PendingSend>>,
 
     #[debug(skip)]
     error_handler: Arc<ErrorHandler>,
 
+    /// The errors recently forwarded to the bot's administrators
+    error_notifier: Mutex<err_notify::ErrorNotifier>,
+
+    metrics: metrics::Metrics,
+
     module_data_path: PathBuf,
 
-    modules: BTreeMap<Cow<'static, str>, Arc<Module>>,
+    modules: RwLock<BTreeMap<Cow<'static, str>, Arc<Module>>>,
+
+    /// The resources that the bot's modules have used, by the modules' names
+    module_usage: Mutex<BTreeMap<String, ModuleUsage>>,
 
-    // TODO: This is server-specific.
-    msg_prefix: RwLock<OwningMsgPrefix>,
+    /// Whether the bot is running offline rather than connected to servers, in which case the
+    /// messages that it would send are written to `offline_output` instead, or logged
+    offline: bool,
+
+    #[debug(skip)]
+    offline_output: Mutex<Option<Box<Write + Send>>>,
+
+    #[debug(skip)]
+    outbox: OutboxPort,
+
+    /// The pastes that the bot serves from its own HTTP listener
+    hosted_pastes: Mutex<paste::HostedPastes>,
+
+    /// The preferences that users have set for themselves
+    prefs: RwLock<prefs::UserPrefs>,
 
     rng: Mutex<StdRng>,
 
     servers: BTreeMap<ServerId, RwLock<Server>>,
 
-    triggers: BTreeMap<TriggerPriority, Vec<Trigger>>,
+    /// The file to which the bot's session is being recorded, if any
+    session_recorder: Mutex<recording::SessionRecorder>,
+
+    shutting_down: AtomicBool,
+
+    /// The time at which the bot started
+    started: Instant,
+
+    sts_policies: RwLock<sts::StsPolicies>,
+
+    triggers: RwLock<BTreeMap<TriggerPriority, Vec<Arc<Trigger>>>>,
 }
 
 #[derive(Debug)]
@@ -106,15 +302,70 @@ struct Server {
     aatxe_config: Arc<aatxe::Config>,
     socket_addr_string: String,
     motd_finished: bool,
+
+    /// The bot's own message prefix on the server, as last learned
+    msg_prefix: OwningMsgPrefix,
+
+    /// The time at which the bot finished registering with the server on the current connection
+    registered_since: Option<Instant>,
+
     registration_mode_obtained: bool,
+    capabilities: ServerCapabilities,
+    lag_probe: lag::LagProbe,
+    connection_generation: u64,
+    reconnect_requested: bool,
+
+    /// Whether the bot has sent `QUIT` over the current connection, so that the connection's end
+    /// is not to be taken for a dropped connection
+    quit_sent: bool,
+
+    /// The sender by which to close the current connection at once, without waiting for the
+    /// server to close it
+    connection_abort: Option<oneshot::Sender<()>>,
+
+    users: users::UserTable,
+    presence_watches: presence::PresenceWatches,
+
+    /// The IRCv3 capabilities that the server has acknowledged enabling for the connection
+    enabled_caps: BTreeSet<String>,
+
+    labeled_queries: labeled::LabeledQueries,
+
+    /// The IRCv3 batches that the server has opened and not yet closed
+    batches: batch::OpenBatches,
+
+    /// Whether the current connection to the server uses TLS
+    connection_secure: bool,
+
+    history_fetches: history::PendingFetches,
+
+    /// The most recent messages sent to each channel, which are kept across reconnections
+    recent_msgs: chan_log::RecentMsgs,
+
+    /// The output of commands held for the users who used them, to be retrieved a page at a time
+    pending_pages: pager::PendingPages,
+
+    /// The commands enabled or disabled in particular channels by the bot's administrators
+    command_toggles: chan_cmds::CommandToggles,
+
+    /// The challenges issued to users who would authenticate as administrators of the bot, and
+    /// the sessions of those who have
+    admin_auth: admin_auth::AdminAuthState,
+
+    /// The users shadow-banned or unbanned by the bot's administrators
+    shadow_bans: shadow_ban::ShadowBanToggles,
+
+    /// The users' recent messages to channels, kept to detect floods
+    flood: flood::FloodTracker,
 }
 
 #[derive(Copy, Clone, CustomDebug, Eq, PartialEq, PartialOrd, Ord)]
 pub struct ServerId {
+    // Being compared first, this orders servers as the configuration lists them.
+    config_idx: ServerConfigIndex,
+
     #[debug(with = "util::fmt::debug_uuid")]
     uuid: Uuid,
-
-    config_idx: ServerConfigIndex,
     // TODO: Maybe add a `Weak` pointing to the `State` containing the map of servers, so that
     // `ServerId`'s `Debug` implementation can return some information about the server other than
     // its UUID, such as its domain name.
@@ -148,36 +399,62 @@ impl State {
         config: config::Config,
         module_data_path: PathBuf,
         error_handler: ErrF,
+        outbox: OutboxPort,
     ) -> Result<State>
     where
         ErrF: ErrorHandler,
     {
-        let msg_prefix = RwLock::new(OwningMsgPrefix::from_string(format!(
-            "{}!{}@",
-            config.nickname, config.username
-        )));
+        let aliases = aliases::RuntimeAliases::load(config.aliases_file.clone())?;
+        let prefs = prefs::UserPrefs::load(config.prefs_file.clone())?;
+        let command_stats = cmd_stats::CommandStats::load(config.command_stats_file.clone())?;
+        let sts_policies = sts::StsPolicies::load(config.sts_policy_file.clone())?;
 
         Ok(State {
             aatxe_clients: Default::default(),
             addressee_suffix: ": ".into(),
+            aliases: RwLock::new(aliases),
             commands: Default::default(),
-            config: config,
+            command_stats: Mutex::new(command_stats),
+            config: RwLock::new(Arc::new(config)),
+            connection_configs: Default::default(),
+            dcc_pending_sends: Default::default(),
             error_handler: Arc::new(error_handler),
+            error_notifier: Default::default(),
+            metrics: Default::default(),
             module_data_path,
             modules: Default::default(),
-            msg_prefix,
+            module_usage: Default::default(),
+            offline: false,
+            offline_output: Default::default(),
+            outbox,
+            hosted_pastes: Default::default(),
+            prefs: RwLock::new(prefs),
             rng: Mutex::new(StdRng::from_rng(EntropyRng::new())?),
             servers: Default::default(),
+            session_recorder: Default::default(),
+            shutting_down: AtomicBool::new(false),
+            started: Instant::now(),
+            sts_policies: RwLock::new(sts_policies),
             triggers: Default::default(),
         })
     }
 
-    fn handle_err<S>(&self, err: Error, desc: S) -> Option<LibReaction<Message>>
+    /// Passes the given error, which occurred in connection with the given server, if any, to the
+    /// error handler, and returns any reaction to it that is to be sent to that server or, if none
+    /// is given, to all servers.
+    fn handle_err<S>(
+        &self,
+        server_id: Option<ServerId>,
+        err: Error,
+        desc: S,
+    ) -> Option<LibReaction<Message>>
     where
         S: Borrow<str>,
     {
         let desc = desc.borrow();
 
+        self.forward_err(server_id, &err);
+
         let reaction = self.error_handler.run(err);
 
         match reaction {
@@ -199,11 +476,218 @@ impl State {
                 );
                 Some(irc_comm::mk_quit(msg))
             }
+            ErrorReaction::Reconnect(msg) => {
+                let server_id = match server_id {
+                    Some(server_id) => server_id,
+                    None => {
+                        warn!(
+                            "Proceeding despite error{}{}{}, as it did not occur in connection \
+                             with a particular server to reconnect to.",
+                            if desc.is_empty() { "" } else { " (" },
+                            desc,
+                            if desc.is_empty() { "" } else { ")" }
+                        );
+                        return None;
+                    }
+                };
+
+                trace!(
+                    "Reconnecting to server {:?} because of error{}{}{}.",
+                    server_id,
+                    if desc.is_empty() { "" } else { " (" },
+                    desc,
+                    if desc.is_empty() { "" } else { ")" }
+                );
+
+                match self.write_server(server_id) {
+                    Ok(mut server) => server.reconnect_requested = true,
+                    Err(e) => error!("Failed to request reconnection: {}", e),
+                }
+
+                Some(irc_comm::mk_quit(msg))
+            }
+            ErrorReaction::NotifyAdmins(text) => {
+                trace!(
+                    "Notifying administrators of error{}{}{}.",
+                    if desc.is_empty() { "" } else { " (" },
+                    desc,
+                    if desc.is_empty() { "" } else { ")" }
+                );
+
+                let server_ids = match server_id {
+                    Some(server_id) => vec![server_id],
+                    None => self.servers.keys().cloned().collect(),
+                };
+
+                for server_id in server_ids {
+                    if let Err(e) = self.notify_admins(server_id, &text) {
+                        error!("Failed to notify administrators of an error: {}", e)
+                    }
+                }
+
+                None
+            }
+        }
+    }
+
+    fn handle_err_generic(&self, server_id: ServerId, err: Error) -> Option<LibReaction<Message>> {
+        self.handle_err(Some(server_id), err, "")
+    }
+
+    /// Shuts the bot down gracefully, by sending `QUIT` to every server, after any messages
+    /// already queued for sending, and waiting briefly for the queue of outgoing messages to be
+    /// flushed.
+    ///
+    /// Once the servers have closed the bot's connections in response to the `QUIT`s, the function
+    /// [`run`] will run the modules' unload handlers (see [`ModuleBuilder::on_unload`]) and
+    /// return. Calling this function again after the first time has no effect.
+    ///
+    /// [`run`]: <fn.run.html>
+    /// [`ModuleBuilder::on_unload`]: <struct.ModuleBuilder.html#method.on_unload>
+    pub fn shutdown(&self, quit_msg: Option<Cow<'static, str>>) -> Result<()> {
+        if self.shutting_down.swap(true, Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        info!("Shutting down....");
+
+        let deadline = Instant::now() + SHUTDOWN_FLUSH_TIMEOUT;
+
+        // `QUIT`s skip ahead of ordinary messages in the outbox, so wait for the messages already
+        // queued to be sent first.
+        self.await_empty_outbox(deadline);
+
+        for &server_id in self.servers.keys() {
+            push_to_outbox(&self.outbox, server_id, irc_comm::mk_quit(quit_msg.clone()));
+        }
+
+        self.await_empty_outbox(deadline);
+
+        if !self.outbox.is_empty() {
+            warn!(
+                "Gave up waiting for the outbox to be flushed, with {} messages left in it.",
+                self.outbox.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    fn await_empty_outbox(&self, deadline: Instant) {
+        while !self.outbox.is_empty() && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(50));
+        }
+    }
+
+    /// Returns statistics about the queue of messages waiting to be sent to servers.
+    pub fn outbox_stats(&self) -> OutboxStats {
+        self.outbox.stats()
+    }
+
+    /// Returns whether `State::shutdown` has been called.
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Closes the bot's connection to the given server, by sending `QUIT` to it. The `QUIT` is sent
+    /// ahead of any ordinary messages already queued for sending to the server, which are lost.
+    ///
+    /// The bot does not reconnect unless [`State::reconnect`] is called instead. Once the bot has
+    /// disconnected from all servers, the function [`run`] returns as it does after
+    /// [`State::shutdown`].
+    ///
+    /// [`run`]: <fn.run.html>
+    /// [`State::reconnect`]: <struct.State.html#method.reconnect>
+    /// [`State::shutdown`]: <struct.State.html#method.shutdown>
+    pub fn disconnect(
+        &self,
+        server_id: ServerId,
+        quit_msg: Option<Cow<'static, str>>,
+    ) -> Result<()> {
+        self.write_server(server_id)?.reconnect_requested = false;
+
+        push_to_outbox(&self.outbox, server_id, irc_comm::mk_quit(quit_msg));
+
+        Ok(())
+    }
+
+    /// Closes the bot's connection to the given server, as with [`State::disconnect`], and then
+    /// connects to the server anew.
+    ///
+    /// [`State::disconnect`]: <struct.State.html#method.disconnect>
+    pub fn reconnect(
+        &self,
+        server_id: ServerId,
+        quit_msg: Option<Cow<'static, str>>,
+    ) -> Result<()> {
+        self.write_server(server_id)?.reconnect_requested = true;
+
+        push_to_outbox(&self.outbox, server_id, irc_comm::mk_quit(quit_msg));
+
+        Ok(())
+    }
+
+    /// Closes the bot's connection to the server with the given name in the configuration, with
+    /// the given quit message, and then connects to the server anew, registering and joining
+    /// channels as on first connecting, as with [`State::reconnect`]. The bot's connections to
+    /// other servers are left be.
+    ///
+    /// [`State::reconnect`]: <struct.State.html#method.reconnect>
+    pub fn reconnect_server(&self, name: &str, quit_msg: Option<Cow<'static, str>>) -> Result<()> {
+        let server_id = self
+            .server_named(name)
+            .ok_or_else(|| ErrorKind::UnknownServerName(name.to_owned()))?;
+
+        self.reconnect(server_id, quit_msg)
+    }
+
+    /// Closes the bot's connection to the given server at once, without sending `QUIT` or
+    /// waiting for the server to close the connection, as is needed once the connection has
+    /// stalled, and then connects to the server anew.
+    pub(super) fn abort_connection(&self, server_id: ServerId) -> Result<()> {
+        let mut server = self.write_server(server_id)?;
+
+        server.reconnect_requested = true;
+
+        if let Some(abort) = server.connection_abort.take() {
+            // The connection may have ended already, in which case there is nothing to abort.
+            let _ = abort.send(());
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether a reconnection to the given server has been requested, and clears the
+    /// request.
+    fn take_reconnect_request(&self, server_id: ServerId) -> bool {
+        match self.write_server(server_id) {
+            Ok(mut server) => std::mem::replace(&mut server.reconnect_requested, false),
+            Err(e) => {
+                error!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// Returns whether the bot has sent `QUIT` to the given server over the connection that has
+    /// just ended.
+    fn quit_sent(&self, server_id: ServerId) -> bool {
+        match self.read_server(server_id) {
+            Ok(server) => server.quit_sent,
+            Err(e) => {
+                error!("{}", e);
+                true
+            }
         }
     }
 
-    fn handle_err_generic(&self, err: Error) -> Option<LibReaction<Message>> {
-        self.handle_err(err, "")
+    /// Records that the bot is sending `QUIT` to the given server, so that the server's closing
+    /// of the connection is expected.
+    pub(super) fn record_quit_sent(&self, server_id: ServerId) {
+        match self.write_server(server_id) {
+            Ok(mut server) => server.quit_sent = true,
+            Err(e) => error!("{}", e),
+        }
     }
 }
 
@@ -219,7 +703,154 @@ pub fn run<Cfg, ModlData, ErrF, ModlCtor, Modls>(
     Modls: IntoIterator<Item = ModlCtor>,
     ModlCtor: Fn() -> Module,
 {
-    let module_data_path = module_data_path.into();
+    let (mut state, outbox_sender, outbox_receiver) =
+        match assemble_state(config, module_data_path.into(), error_handler, modules) {
+            Some(a) => a,
+            None => return,
+        };
+
+    let recording_path = state.config().session_recording_file.clone();
+
+    state.session_recorder =
+        match recording::SessionRecorder::open(recording_path.as_ref().map(AsRef::as_ref)) {
+            Ok(recorder) => Mutex::new(recorder),
+            Err(e) => {
+                error!(
+                    "Terminal error: Failed to open session recording file: {}",
+                    e
+                );
+                return;
+            }
+        };
+
+    let state = Arc::new(state);
+    trace!("Stored bot state onto heap.");
+
+    let mut aatxe_reactor = match aatxe::IrcReactor::new() {
+        Ok(r) => {
+            trace!("Successfully initialized IRC reactor.");
+            r
+        }
+        Err(e) => {
+            error!("Terminal error: Failed to initialize IRC reactor: {}", e);
+            return;
+        }
+    };
+
+    spawn_thread(
+        &state,
+        "*".into(),
+        "send",
+        |_| "sending thread".into(),
+        |state| irc_send::send_main(state, outbox_receiver),
+    );
+
+    if let Some(addr) = state.config().http.address {
+        spawn_thread(
+            &state,
+            addr.to_string(),
+            "HTTP",
+            |addr| format!("HTTP server thread for address {}", addr),
+            move |state| http::http_server_main(state, addr),
+        );
+    }
+
+    if state.config().console && atty::is(atty::Stream::Stdin) {
+        spawn_thread(
+            &state,
+            "*".into(),
+            "console",
+            |_| "console thread".into(),
+            console::console_main,
+        );
+    }
+
+    if let Some(ref path) = state.config().control_socket {
+        spawn_control_socket_thread(&state, path);
+    }
+
+    for bridge in state.bridges() {
+        spawn_thread(
+            &state,
+            bridge.name().to_owned(),
+            "bridge",
+            |name| format!("thread for bridge {:?}", name),
+            move |state| bridge::bridge_main(state, bridge),
+        );
+    }
+
+    if state.config().command_stats_file.is_some() {
+        spawn_thread(
+            &state,
+            "*".into(),
+            "command stats",
+            |_| "command statistics thread".into(),
+            cmd_stats::cmd_stats_main,
+        );
+    }
+
+    if state
+        .config()
+        .module_limits
+        .values()
+        .any(|limits| limits.storage.is_some())
+    {
+        spawn_thread(
+            &state,
+            "*".into(),
+            "module stats",
+            |_| "module statistics thread".into(),
+            modl_stats::modl_stats_main,
+        );
+    }
+
+    if state.has_periodic_handlers() {
+        spawn_thread(
+            &state,
+            "*".into(),
+            "scheduler",
+            |_| "scheduler thread".into(),
+            scheduler::scheduler_main,
+        );
+    }
+
+    for &server_id in state.servers.keys() {
+        aatxe_reactor.register_future(connect(
+            state.clone(),
+            server_id,
+            outbox_sender.clone(),
+            aatxe_reactor.inner_handle(),
+            RECONNECT_MIN_DELAY,
+        ));
+    }
+
+    #[cfg(unix)]
+    install_signal_handlers(&state);
+
+    match aatxe_reactor.run() {
+        Ok(()) => trace!("IRC reactor shut down normally."),
+        Err(e) => error!("IRC reactor shut down abnormally: {}", e),
+    }
+
+    state.save_command_stats();
+    state.run_unload_handlers();
+}
+
+/// Loads the given configuration and modules and assembles the bot's state from them, with a
+/// record for each configured server, returning the state and the two ends of its outbox, or
+/// `None`, after logging why, if this fails.
+fn assemble_state<Cfg, ErrF, ModlCtor, Modls>(
+    config: Cfg,
+    module_data_path: PathBuf,
+    error_handler: ErrF,
+    modules: Modls,
+) -> Option<(State, OutboxPort, irc_send::OutboxReceiver)>
+where
+    Cfg: IntoConfig,
+    ErrF: ErrorHandler,
+    Modls: IntoIterator<Item = ModlCtor>,
+    ModlCtor: Fn() -> Module,
+{
     info!(
         "This bot is set to look for modules' operator-provided data in: {}",
         module_data_path.display()
@@ -233,18 +864,25 @@ pub fn run<Cfg, ModlData, ErrF, ModlCtor, Modls>(
         Err(e) => {
             error_handler.run(e);
             error!("Terminal error: Failed to load configuration.");
-            return;
+            return None;
         }
     };
 
-    let mut state = match State::new(config, module_data_path, error_handler) {
+    let (outbox_sender, outbox_receiver) = irc_send::mk_outbox(&config.outbox);
+
+    let mut state = match State::new(
+        config,
+        module_data_path,
+        error_handler,
+        outbox_sender.clone(),
+    ) {
         Ok(s) => {
             trace!("Assembled bot state.");
             s
         }
         Err(e) => {
             error!("Terminal error while assembling bot state: {}", e);
-            return;
+            return None;
         }
     };
 
@@ -253,31 +891,30 @@ pub fn run<Cfg, ModlData, ErrF, ModlCtor, Modls>(
         Err(errs) => {
             for err in errs {
                 match state.error_handler.run(err) {
-                    ErrorReaction::Proceed => {}
+                    ErrorReaction::Proceed
+                    | ErrorReaction::Reconnect(_)
+                    | ErrorReaction::NotifyAdmins(_) => {}
                     ErrorReaction::Quit(msg) => {
                         error!(
                             "Terminal error while loading modules: {:?}",
                             msg.unwrap_or_default().as_ref()
                         );
-                        return;
+                        return None;
                     }
                 }
             }
         }
     }
 
-    info!(
-        "Loaded modules: {:?}",
-        state.modules.keys().collect::<Vec<_>>()
-    );
+    info!("Loaded modules: {:?}", state.module_names());
     info!(
         "Loaded commands: {:?}",
-        state.commands.keys().collect::<Vec<_>>()
+        state.read_commands().keys().collect::<Vec<_>>()
     );
 
     let mut servers = BTreeMap::new();
 
-    for (i, aatxe_config) in &state.config.aatxe_configs {
+    for (i, aatxe_config) in &state.config().aatxe_configs {
         let server_id = ServerId::new(*i);
 
         let socket_addr_string = match (&aatxe_config.server, aatxe_config.port) {
@@ -292,7 +929,28 @@ pub fn run<Cfg, ModlData, ErrF, ModlCtor, Modls>(
             aatxe_config: aatxe_config.clone(),
             socket_addr_string,
             motd_finished: false,
+            msg_prefix: initial_msg_prefix(aatxe_config),
+            registered_since: None,
             registration_mode_obtained: false,
+            capabilities: Default::default(),
+            lag_probe: Default::default(),
+            connection_generation: 0,
+            reconnect_requested: false,
+            quit_sent: false,
+            connection_abort: None,
+            users: Default::default(),
+            presence_watches: Default::default(),
+            enabled_caps: Default::default(),
+            labeled_queries: Default::default(),
+            batches: Default::default(),
+            connection_secure: false,
+            history_fetches: Default::default(),
+            recent_msgs: Default::default(),
+            pending_pages: Default::default(),
+            command_toggles: Default::default(),
+            admin_auth: Default::default(),
+            shadow_bans: Default::default(),
+            flood: Default::default(),
         };
 
         match servers.insert(server_id, RwLock::new(server)) {
@@ -304,121 +962,441 @@ pub fn run<Cfg, ModlData, ErrF, ModlCtor, Modls>(
                     server_id = server_id,
                     other_server = other_server.read().expect(LOCK_EARLY_POISON_FAIL),
                 );
-                return;
+                return None;
             }
         }
     }
 
     state.servers = servers;
 
-    let state = Arc::new(state);
-    trace!("Stored bot state onto heap.");
+    Some((state, outbox_sender, outbox_receiver))
+}
 
-    let mut aatxe_reactor = match aatxe::IrcReactor::new() {
-        Ok(r) => {
-            trace!("Successfully initialized IRC reactor.");
-            r
-        }
+/// Sets up a new connection to a server, by sending the IRCv3 capability request and the
+/// identification sequence, recording the connection in `state.aatxe_clients`, and starting the
+/// connection's lag watchdog. Returns whether this succeeded.
+fn begin_session(
+    state: &Arc<State>,
+    server_id: ServerId,
+    socket_addr_string: &str,
+    aatxe_client: &aatxe::IrcClient,
+) -> bool {
+    match send_webirc(state, server_id, aatxe_client) {
+        Ok(true) => debug!(
+            "recv[{}]: Identified to server as a WEBIRC gateway.",
+            socket_addr_string
+        ),
+        Ok(false) => {}
         Err(e) => {
-            error!("Terminal error: Failed to initialize IRC reactor: {}", e);
-            return;
+            error!(
+                "recv[{}]: Failed to identify to server as a WEBIRC gateway: {}",
+                socket_addr_string, e
+            );
+            return false;
         }
-    };
-
-    let (outbox_sender, outbox_receiver) = crossbeam_channel::bounded(irc_send::OUTBOX_SIZE);
-
-    spawn_thread(
-        &state,
-        "*".into(),
-        "send",
-        |_| "sending thread".into(),
-        |state| irc_send::send_main(state, outbox_receiver),
-    );
-
-    for (&server_id, server) in &state.servers {
-        let server = server.read().expect(LOCK_EARLY_POISON_FAIL);
-
-        let state_alias = state.clone();
-
-        let outbox_sender_clone = outbox_sender.clone();
-
-        let aatxe_client = match aatxe_reactor.prepare_client_and_connect(&server.aatxe_config) {
-            Ok(client) => {
-                trace!("Connected to server {:?}.", server.socket_addr_string);
-                client
-            }
-            Err(err) => {
-                error!(
-                    "Failed to connect to server {:?}: {} ({:?})",
-                    server.socket_addr_string, err, err,
-                );
-                continue;
-            }
-        };
+    }
 
-        let caps_to_request = &[aatxe::Capability::MultiPrefix];
+    // Ask for the list of the server's capabilities, which may include an STS policy.
+    match aatxe_client.send(aatxe::Command::CAP(
+        None,
+        irc::proto::CapSubCommand::LS,
+        Some("302".into()),
+        None,
+    )) {
+        Ok(()) => debug!(
+            "recv[{}]: Asked server to list its IRCv3 capabilities.",
+            socket_addr_string
+        ),
+        Err(e) => error!(
+            "recv[{}]: Failed to ask server to list its IRCv3 capabilities: {}",
+            socket_addr_string, e
+        ),
+    }
 
-        match aatxe_client.send_cap_req(caps_to_request) {
+    // A server rejects a capability request as a whole if it doesn't support any one of the
+    // capabilities requested, so request each capability separately.
+    for cap in CAPS_TO_REQUEST {
+        match aatxe_client.send_cap_req(slice::from_ref(cap)) {
             Ok(()) => debug!(
                 // TODO: drop colon
                 "recv[{}]: Sent IRCv3 capability request to server, requesting: {:?}",
-                server.socket_addr_string, caps_to_request
+                socket_addr_string, cap
             ),
             Err(e) => {
                 error!(
                     "recv[{}]: Failed to send IRCv3 capability request (for {:?}) to server: {}",
-                    server.socket_addr_string, caps_to_request, e
+                    socket_addr_string, cap, e
                 );
                 // This is not a fatal error, although we can expect the next step, sending the
                 // identification sequence, to fail, which is a fatal error for this particular
                 // attempt to connect to a server.
             }
         }
+    }
 
-        match aatxe_client.identify() {
-            Ok(()) => debug!(
-                "recv[{}]: Sent identification sequence to server.",
-                server.socket_addr_string
-            ),
-            Err(e) => {
-                error!(
-                    "recv[{}]: Failed to send identification sequence to server: {}",
-                    server.socket_addr_string, e
-                );
-                continue;
-            }
+    match aatxe_client.identify() {
+        Ok(()) => debug!(
+            "recv[{}]: Sent identification sequence to server.",
+            socket_addr_string
+        ),
+        Err(e) => {
+            error!(
+                "recv[{}]: Failed to send identification sequence to server: {}",
+                socket_addr_string, e
+            );
+            return false;
         }
+    }
 
-        match state
-            .aatxe_clients
-            .write()
-            .expect(LOCK_EARLY_POISON_FAIL)
-            .insert(server_id, aatxe_client.clone())
-        {
-            None => {}
-            Some(_other_aatxe_client) => {
-                // TODO: If <https://github.com/aatxe/irc/issues/104> is resolved in favor of
-                // `IrcServer` implementing `Debug`, add the other server to this message.
-                error!(
-                    "This shouldn't happen, but there was already a server registered \
-                     with ID {server_id:?}!",
-                    server_id = server_id,
-                );
-                return;
-            }
+    let generation = match state.read_server(server_id) {
+        Ok(server) => server.connection_generation,
+        Err(e) => {
+            error!("recv[{}]: {}", socket_addr_string, e);
+            return false;
         }
+    };
 
-        aatxe_reactor.register_client_with_handler(aatxe_client, move |_aatxe_client, msg| {
-            handle_msg(&state_alias, server_id, &outbox_sender_clone, Ok(msg));
+    state
+        .aatxe_clients
+        .write()
+        .expect(LOCK_EARLY_POISON_FAIL)
+        .insert(server_id, aatxe_client.clone());
 
-            Ok(())
-        });
+    spawn_thread(
+        state,
+        socket_addr_string.to_owned(),
+        "lag",
+        |addr| format!("lag watchdog thread for server {}", addr),
+        move |state| lag::lag_watchdog_main(state, server_id, generation),
+    );
+
+    spawn_thread(
+        state,
+        socket_addr_string.to_owned(),
+        "ISON",
+        |addr| format!("ISON polling thread for server {}", addr),
+        move |state| presence::ison_poller_main(state, server_id, generation),
+    );
+
+    true
+}
+
+type ConnectionFuture = Box<Future<Item = (), Error = irc::error::IrcError>>;
+
+/// Returns a future that handles the messages received over the given connection until the
+/// connection is closed, then runs the modules' disconnection handlers and, if a reconnection has
+/// been requested (see [`State::reconnect`]) or the connection ended without the bot's having sent
+/// `QUIT`, reconnects to the server and continues with the new connection.
+///
+/// The future ends without error once the bot is done with the server, even if the connection
+/// failed, so that the reactor keeps running the bot's connections to the other servers.
+///
+/// [`State::reconnect`]: <struct.State.html#method.reconnect>
+fn connection_future(
+    state: Arc<State>,
+    server_id: ServerId,
+    outbox: OutboxPort,
+    reactor: Handle,
+    aatxe_client: aatxe::IrcClient,
+) -> ConnectionFuture {
+    let state_alias = state.clone();
+    let outbox_alias = outbox.clone();
+
+    let (abort_sender, abort_receiver) = oneshot::channel();
+
+    match state.write_server(server_id) {
+        Ok(mut server) => server.connection_abort = Some(abort_sender),
+        Err(e) => error!("{}", e),
     }
 
-    match aatxe_reactor.run() {
-        Ok(()) => trace!("IRC reactor shut down normally."),
-        Err(e) => error!("IRC reactor shut down abnormally: {}", e),
+    // If the sender is dropped instead, the connection is not to be aborted.
+    let aborted = abort_receiver
+        .or_else(|_| futures::future::empty::<(), irc::error::IrcError>())
+        .map(move |()| warn!("Aborted connection to server {:?}.", server_id));
+
+    Box::new(
+        aatxe_client
+            .stream()
+            .for_each(move |msg| {
+                handle_msg(&state_alias, server_id, &outbox_alias, Ok(msg));
+
+                Ok(())
+            })
+            .select(aborted)
+            .map(|((), _)| ())
+            .map_err(|(e, _)| e)
+            .then(move |result| -> ConnectionFuture {
+                match result {
+                    Ok(()) => info!("Disconnected from server {:?}.", server_id),
+                    Err(ref e) => error!("Disconnected from server {:?}: {}", server_id, e),
+                }
+
+                state.run_disconnect_handlers(server_id);
+
+                // The client's outgoing message queue, which runs on the reactor alongside this
+                // future, ends only once every handle to the client has been dropped.
+                forget_connection(&state, server_id);
+
+                let reconnect_requested = state.take_reconnect_request(server_id);
+
+                if !state.is_shutting_down() && (reconnect_requested || !state.quit_sent(server_id))
+                {
+                    if !reconnect_requested {
+                        warn!(
+                            "The connection to server {:?} ended unexpectedly; reconnecting.",
+                            server_id
+                        );
+                    }
+
+                    return reconnect(state, server_id, outbox, reactor, None);
+                }
+
+                Box::new(futures::future::ok(()))
+            }),
+    )
+}
+
+/// Forgets the bot's connection to the given server, so that nothing more is sent over it.
+fn forget_connection(state: &State, server_id: ServerId) {
+    match state.aatxe_clients.write() {
+        Ok(mut aatxe_clients) => drop(aatxe_clients.remove(&server_id)),
+        Err(_) => error!(
+            "Failed to forget connection to server {:?}: lock poisoned",
+            server_id
+        ),
+    }
+}
+
+/// Sends the `WEBIRC` command to the given server if the configuration so specifies, returning
+/// whether it did.
+fn send_webirc(
+    state: &State,
+    server_id: ServerId,
+    aatxe_client: &aatxe::IrcClient,
+) -> Result<bool> {
+    let server_config = state.get_server_config(server_id)?;
+
+    let webirc = match server_config.webirc {
+        Some(ref webirc) => webirc,
+        None => return Ok(false),
+    };
+
+    let mut ip = webirc.ip.to_string();
+
+    // An IPv6 address such as `::1` must not begin with `:`, lest it be taken for a final
+    // parameter.
+    if ip.starts_with(':') {
+        ip.insert(0, '0');
     }
+
+    let host = webirc.host.clone().unwrap_or_else(|| ip.clone());
+
+    aatxe_client.send(aatxe::Command::Raw(
+        "WEBIRC".into(),
+        vec![webirc.password.clone(), webirc.gateway.clone(), host, ip],
+        None,
+    ))?;
+
+    Ok(true)
+}
+
+/// Returns the message prefix that the bot is expected to have upon connecting with the given
+/// settings, until the server reveals the rest of it.
+fn initial_msg_prefix(aatxe_config: &aatxe::Config) -> OwningMsgPrefix {
+    OwningMsgPrefix::from_string(format!(
+        "{}!{}@",
+        aatxe_config.nickname().unwrap_or_default(),
+        aatxe_config.username()
+    ))
+}
+
+/// Returns a future that opens a connection to the given server on the reactor and continues with
+/// it as [`connection_future`] does.
+///
+/// If connecting fails, the future tries again, as [`reconnect`] does, after the given time.
+///
+/// [`connection_future`]: <fn.connection_future.html>
+/// [`reconnect`]: <fn.reconnect.html>
+fn connect(
+    state: Arc<State>,
+    server_id: ServerId,
+    outbox: OutboxPort,
+    reactor: Handle,
+    retry_delay: Duration,
+) -> ConnectionFuture {
+    let socket_addr_string = state.server_socket_addr_dbg_string(server_id);
+
+    let retry = {
+        let (state, outbox, reactor) = (state.clone(), outbox.clone(), reactor.clone());
+
+        move |err: Error| -> ConnectionFuture {
+            error!(
+                "Failed to connect to server {:?}; retrying in {} seconds: {}",
+                server_id,
+                retry_delay.as_secs(),
+                err
+            );
+
+            reconnect(state, server_id, outbox, reactor, Some(retry_delay))
+        }
+    };
+
+    let connecting = match sts::prepare_connection(&state, server_id)
+        .and_then(|aatxe_config| lasting_aatxe_config(&state, &aatxe_config))
+        .and_then(|aatxe_config| {
+            aatxe::IrcClient::new_future(reactor.clone(), aatxe_config).map_err(Into::into)
+        }) {
+        Ok(connecting) => connecting,
+        Err(e) => return retry(e),
+    };
+
+    Box::new(connecting.then(move |result| -> ConnectionFuture {
+        let PackedIrcClient(aatxe_client, outgoing) = match result {
+            Ok(packed) => packed,
+            Err(e) => return retry(e.into()),
+        };
+
+        trace!("Connected to server {:?}.", socket_addr_string);
+
+        // Run the client's outgoing message queue apart from the future of the connection, so
+        // that its failure doesn't stop the reactor, and with it the bot's other connections.
+        reactor.spawn(
+            outgoing.map_err(move |e| {
+                error!("Failed to send messages to server {:?}: {}", server_id, e)
+            }),
+        );
+
+        if begin_session(&state, server_id, &socket_addr_string, &aatxe_client) {
+            connection_future(state, server_id, outbox, reactor, aatxe_client)
+        } else {
+            retry(ErrorKind::ReconnectionFailure(server_id).into())
+        }
+    }))
+}
+
+/// Returns a future that waits for the given time, if any, and then connects to the given server
+/// anew, after resetting the state pertaining to the previous connection, as [`connect`] does.
+///
+/// If connecting fails, the future tries again, waiting twice as long as the last time, or
+/// `RECONNECT_MIN_DELAY` at first, up to `RECONNECT_MAX_DELAY`, until it succeeds or the bot shuts
+/// down.
+///
+/// [`connect`]: <fn.connect.html>
+fn reconnect(
+    state: Arc<State>,
+    server_id: ServerId,
+    outbox: OutboxPort,
+    reactor: Handle,
+    delay: Option<Duration>,
+) -> ConnectionFuture {
+    let wait = match delay {
+        Some(delay) => Either::A(wait_unless_shutting_down(
+            state.clone(),
+            reactor.clone(),
+            delay,
+        )),
+        None => Either::B(futures::future::ok(())),
+    };
+
+    let next_delay = delay
+        .map(|delay| cmp::min(delay * 2, RECONNECT_MAX_DELAY))
+        .unwrap_or(RECONNECT_MIN_DELAY);
+
+    Box::new(wait.then(move |_| -> ConnectionFuture {
+        if state.is_shutting_down() {
+            return Box::new(futures::future::ok(()));
+        }
+
+        if let Err(e) = reset_connection_state(&state, server_id) {
+            error!(
+                "Failed to prepare to reconnect to server {:?}; retrying in {} seconds: {}",
+                server_id,
+                next_delay.as_secs(),
+                e
+            );
+
+            return reconnect(state, server_id, outbox, reactor, Some(next_delay));
+        }
+
+        state.metrics.record_reconnect(server_id);
+
+        info!(
+            "Reconnecting to server {:?}....",
+            state.server_socket_addr_dbg_string(server_id)
+        );
+
+        connect(state, server_id, outbox, reactor, next_delay)
+    }))
+}
+
+/// Returns a future that waits on the reactor for the given time, or until the bot begins to shut
+/// down, whichever comes first.
+fn wait_unless_shutting_down(
+    state: Arc<State>,
+    reactor: Handle,
+    delay: Duration,
+) -> impl Future<Item = (), Error = ()> {
+    futures::future::loop_fn(delay, move |remaining| {
+        let tick = cmp::min(remaining, RECONNECT_TICK);
+        let state = state.clone();
+
+        futures::future::result(Timeout::new(tick, &reactor))
+            .flatten()
+            .map(move |()| {
+                if remaining <= tick || state.is_shutting_down() {
+                    Loop::Break(())
+                } else {
+                    Loop::Continue(remaining - tick)
+                }
+            })
+    })
+    .map_err(|e| error!("Reconnection timer failed: {}", e))
+}
+
+/// Resets the state pertaining to the bot's previous connection to the given server.
+fn reset_connection_state(state: &State, server_id: ServerId) -> Result<()> {
+    let mut server = state.write_server(server_id)?;
+
+    server.motd_finished = false;
+    server.msg_prefix = initial_msg_prefix(&server.aatxe_config);
+    server.registered_since = None;
+    server.registration_mode_obtained = false;
+    server.capabilities = Default::default();
+    server.lag_probe = Default::default();
+    server.users.clear();
+    server.admin_auth.clear();
+    server.enabled_caps.clear();
+    server.labeled_queries.clear();
+    server.batches.clear();
+    server.history_fetches.clear();
+    server.quit_sent = false;
+    server.connection_generation += 1;
+
+    Ok(())
+}
+
+/// Returns a copy of the given connection settings that lasts as long as the program does. Each
+/// distinct set of settings is copied only once, so that memory is spent anew only when the
+/// settings change, as upon an STS upgrade or a reload of the configuration, rather than upon
+/// every reconnection.
+fn lasting_aatxe_config(
+    state: &State,
+    aatxe_config: &aatxe::Config,
+) -> Result<&'static aatxe::Config> {
+    let mut configs = state
+        .connection_configs
+        .lock()
+        .map_err(|_| ErrorKind::LockPoisoned("the settings of reestablished connections".into()))?;
+
+    if let Some(&lasting) = configs.iter().find(|&&cfg| cfg == aatxe_config) {
+        return Ok(lasting);
+    }
+
+    let lasting: &'static aatxe::Config = Box::leak(Box::new(aatxe_config.clone()));
+
+    configs.push(lasting);
+
+    Ok(lasting)
 }
 
 fn handle_msg(
@@ -427,12 +1405,121 @@ fn handle_msg(
     outbox: &irc_send::OutboxPort,
     input: Result<Message>,
 ) {
-    match input.and_then(|msg| irc_comm::handle_msg(&state, server_id, outbox, msg)) {
+    state.metrics.record_received(server_id);
+
+    let mut context = ErrorContext {
+        server: Some(state.server_socket_addr_dbg_string(server_id)),
+        ..Default::default()
+    };
+
+    if let Ok(ref msg) = input {
+        state.record_msg(server_id, recording::Direction::In, msg);
+    }
+
+    let result = input.and_then(|msg| {
+        context.channel = msg.response_target().map(ToOwned::to_owned);
+        context.set_message(msg.to_string().trim_end());
+
+        irc_comm::handle_msg(&state, server_id, outbox, msg)
+    });
+
+    match result {
         Ok(()) => {}
-        Err(e) => push_to_outbox(outbox, server_id, state.handle_err_generic(e)),
+        Err(e) => push_to_outbox(
+            outbox,
+            server_id,
+            state.handle_err_generic(server_id, e.with_context(context)),
+        ),
     }
 }
 
+#[cfg(unix)]
+fn spawn_control_socket_thread(state: &Arc<State>, path: &Path) {
+    let path = path.to_owned();
+
+    spawn_thread(
+        state,
+        path.display().to_string(),
+        "control",
+        |path| format!("control socket thread for {}", path),
+        move |state| control_socket::control_socket_main(state, &path),
+    );
+}
+
+#[cfg(not(unix))]
+fn spawn_control_socket_thread(_: &Arc<State>, _: &Path) {
+    warn!("Control sockets are supported only on Unix-like systems; not opening one.");
+}
+
+/// Arranges for the bot to shut down gracefully upon receiving the signal `SIGINT` or `SIGTERM`,
+/// and to exit immediately upon receiving a second such signal, and to reload its configuration
+/// upon receiving the signal `SIGHUP`.
+#[cfg(unix)]
+fn install_signal_handlers(state: &Arc<State>) {
+    use signal_hook::iterator::Signals;
+
+    let signals = match Signals::new(
+        [
+            signal_hook::SIGINT,
+            signal_hook::SIGTERM,
+            signal_hook::SIGHUP,
+        ]
+        .iter(),
+    ) {
+        Ok(s) => s,
+        Err(e) => {
+            error!("Failed to install signal handlers: {}", e);
+            return;
+        }
+    };
+
+    spawn_thread(
+        state,
+        "*".into(),
+        "signal",
+        |_| "signal-handling thread".into(),
+        move |state| {
+            for signal in signals.forever() {
+                if signal == signal_hook::SIGHUP {
+                    info!("Received signal {}; reloading configuration.", signal);
+
+                    if let Err(e) = state.reload_config() {
+                        error!("Failed to reload configuration: {}", e);
+                    }
+
+                    continue;
+                }
+
+                if state.is_shutting_down() {
+                    warn!(
+                        "Received signal {} while shutting down; exiting now.",
+                        signal
+                    );
+                    process::exit(1);
+                }
+
+                info!("Received signal {}; shutting down.", signal);
+
+                state.shutdown(None)?;
+
+                spawn_thread(
+                    &state,
+                    "*".into(),
+                    "shutdown-timer",
+                    |_| "shutdown grace period timer thread".into(),
+                    |_| {
+                        thread::sleep(SHUTDOWN_GRACE_PERIOD);
+                        warn!("The bot's connections did not close in time; exiting now.");
+                        process::exit(1)
+                    },
+                );
+            }
+
+            Ok(())
+        },
+    );
+}
+
 fn spawn_thread<F, PurposeF>(
     state: &Arc<State>,
     addr: String,
@@ -466,7 +1553,9 @@ fn spawn_thread<F, PurposeF>(
             trace!("Spawned {purpose}.", purpose = purpose_desc_full(&addr));
         }
         Err(err) => match state.error_handler.run(err.into()) {
-            ErrorReaction::Proceed => error!(
+            ErrorReaction::Proceed
+            | ErrorReaction::Reconnect(_)
+            | ErrorReaction::NotifyAdmins(_) => error!(
                 "Failed to create {purpose}; ignoring.",
                 purpose = purpose_desc_full(&addr),
             ),
@@ -478,3 +1567,122 @@ fn spawn_thread<F, PurposeF>(
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::offline;
+    use super::offline::OfflineBot;
+    use super::ErrorKind;
+    use super::ErrorReaction;
+    use modules;
+    use std::env;
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::atomic::Ordering;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use testing;
+    use testing::SharedOutput;
+
+    /// The configuration of a bot with the administrator `alice`, connected to the servers `a` and
+    /// `b`
+    fn two_servers() -> String {
+        offline::test_config(
+            "admins:\n  \
+             - nick: alice\n\
+             servers:\n  \
+             - {name: a, host: irc.a.invalid, port: 6697}\n  \
+             - {name: b, host: irc.b.invalid, port: 6697}\n",
+        )
+    }
+
+    #[test]
+    fn reconnect_server() {
+        let bot = OfflineBot::for_test_config(two_servers(), vec![modules::default]);
+        let state = bot.state();
+        let (a, b) = (state.server_ids()[0], state.server_ids()[1]);
+
+        match *state.reconnect_server("c", None).unwrap_err().kind() {
+            ErrorKind::UnknownServerName(ref name) => assert_eq!(name, "c"),
+            ref kind => panic!("Unexpected error: {}", kind),
+        }
+        assert!(!state.read_server(a).unwrap().reconnect_requested);
+        assert!(!state.read_server(b).unwrap().reconnect_requested);
+
+        state.reconnect_server("b", None).unwrap();
+        assert!(!state.read_server(a).unwrap().reconnect_requested);
+        assert!(state.read_server(b).unwrap().reconnect_requested);
+    }
+
+    #[test]
+    fn reconnect_command() {
+        let output = SharedOutput::default();
+        let bot = OfflineBot::new(
+            two_servers(),
+            env::temp_dir(),
+            |_| ErrorReaction::Proceed,
+            vec![modules::default, modules::admin],
+            Some(Box::new(output.clone())),
+        )
+        .unwrap();
+        let state = bot.state();
+        let (a, b) = (state.server_ids()[0], state.server_ids()[1]);
+        let command = |arg: &str| {
+            let line = format!(
+                ":alice!alice@example.com PRIVMSG testbot :reconnect {}",
+                arg
+            );
+            bot.handle(a, line.parse().map_err(Into::into));
+        };
+
+        command("server: c");
+        assert!(output.text().contains("No server named \"c\""));
+        assert!(!state.read_server(a).unwrap().reconnect_requested);
+        assert!(!state.read_server(b).unwrap().reconnect_requested);
+
+        command("server: b");
+        assert!(output.text().contains("Reconnecting to \"b\"."));
+        assert!(!state.read_server(a).unwrap().reconnect_requested);
+        assert!(state.read_server(b).unwrap().reconnect_requested);
+
+        // The bot does not announce a reconnection to the server on which it was asked for.
+        command("server: a");
+        assert!(!output.text().contains("Reconnecting to \"a\"."));
+        assert!(state.read_server(a).unwrap().reconnect_requested);
+    }
+
+    #[test]
+    fn retry_reconnection() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let done = Arc::new(AtomicBool::new(false));
+
+        let bot = testing::spawn_bot(
+            offline::test_config(&format!(
+                "servers:\n  \
+                 - name: flaky\n    \
+                 host: {:?}\n    \
+                 port: {}\n    \
+                 TLS: false\n",
+                addr.ip().to_string(),
+                addr.port(),
+            )),
+            done.clone(),
+        );
+
+        // Drop the first connection, and then refuse connections for long enough that the bot's
+        // first attempt or two to reconnect fail.
+        drop(testing::accept_silently(&listener));
+        drop(listener);
+        thread::sleep(Duration::from_millis(1500));
+
+        let listener = TcpListener::bind(addr).unwrap();
+        let second = testing::accept_silently(&listener);
+
+        done.store(true, Ordering::SeqCst);
+        testing::await_quit(second);
+
+        bot.join().unwrap();
+    }
+}
@@ -0,0 +1,178 @@
+use super::irc_send::push_to_outbox;
+use super::ErrorKind;
+use super::LibReaction;
+use super::Result;
+use super::ServerId;
+use super::State;
+use irc::client::data::AccessLevel;
+use irc::client::prelude as aatxe;
+use irc::client::prelude::Client as AatxeClient;
+use irc::proto::ChannelMode;
+use irc::proto::Mode;
+use std::cmp::Ordering;
+use util::irc::casemapped_str_cmp;
+
+/// The channel mode with which some servers (e.g., those running charybdis or solanum) allow
+/// users matching a mask to be _quieted_, i.e., prevented from speaking.
+const QUIET_MODE: char = 'q';
+
+/// Channel moderation
+///
+/// These functions queue the appropriate IRC messages for sending, provided that the bot is in
+/// the given channel and holds at least half-operator status there, as tracked from the
+/// channel's `NAMES` list and subsequent mode changes. Otherwise, they return an error without
+/// sending anything.
+impl State {
+    /// Kicks the user with the given nickname from the given channel, with the given reason, if
+    /// any.
+    pub fn kick(
+        &self,
+        server_id: ServerId,
+        channel: &str,
+        nick: &str,
+        reason: Option<&str>,
+    ) -> Result<()> {
+        let channel = self.check_channel_privileges(server_id, channel)?;
+
+        self.send_moderation_cmd(
+            server_id,
+            aatxe::Command::KICK(channel, nick.to_owned(), reason.map(ToOwned::to_owned)),
+        )
+    }
+
+    /// Changes the modes of the given channel, per the given mode string, such as
+    /// `"+o-v alice bob"`.
+    pub fn set_mode(&self, server_id: ServerId, channel: &str, modes: &str) -> Result<()> {
+        let modes = Mode::as_channel_modes(modes)?;
+
+        self.set_channel_modes(server_id, channel, modes)
+    }
+
+    /// Bans users matching the given mask, such as `"*!*@example.com"`, from the given channel.
+    pub fn ban_mask(&self, server_id: ServerId, channel: &str, mask: &str) -> Result<()> {
+        self.set_channel_modes(
+            server_id,
+            channel,
+            vec![Mode::plus(ChannelMode::Ban, Some(mask))],
+        )
+    }
+
+    /// Lifts a ban, from the given channel, on users matching the given mask.
+    pub fn unban_mask(&self, server_id: ServerId, channel: &str, mask: &str) -> Result<()> {
+        self.set_channel_modes(
+            server_id,
+            channel,
+            vec![Mode::minus(ChannelMode::Ban, Some(mask))],
+        )
+    }
+
+    /// Quiets users matching the given mask in the given channel, if the server supports this, as
+    /// it advertises with the `RPL_ISUPPORT` parameter `CHANMODES`.
+    pub fn quiet_mask(&self, server_id: ServerId, channel: &str, mask: &str) -> Result<()> {
+        self.check_quiet_supported(server_id)?;
+
+        self.set_channel_modes(
+            server_id,
+            channel,
+            vec![Mode::plus(ChannelMode::Unknown(QUIET_MODE), Some(mask))],
+        )
+    }
+
+    /// Lifts a quieting, in the given channel, of users matching the given mask.
+    pub fn unquiet_mask(&self, server_id: ServerId, channel: &str, mask: &str) -> Result<()> {
+        self.check_quiet_supported(server_id)?;
+
+        self.set_channel_modes(
+            server_id,
+            channel,
+            vec![Mode::minus(ChannelMode::Unknown(QUIET_MODE), Some(mask))],
+        )
+    }
+
+    /// Sets the topic of the given channel.
+    pub fn set_topic(&self, server_id: ServerId, channel: &str, topic: &str) -> Result<()> {
+        let channel = self.check_channel_privileges(server_id, channel)?;
+
+        self.send_moderation_cmd(
+            server_id,
+            aatxe::Command::TOPIC(channel, Some(topic.to_owned())),
+        )
+    }
+
+    fn set_channel_modes(
+        &self,
+        server_id: ServerId,
+        channel: &str,
+        modes: Vec<Mode<ChannelMode>>,
+    ) -> Result<()> {
+        let channel = self.check_channel_privileges(server_id, channel)?;
+
+        self.send_moderation_cmd(server_id, aatxe::Command::ChannelMODE(channel, modes))
+    }
+
+    fn send_moderation_cmd(&self, server_id: ServerId, cmd: aatxe::Command) -> Result<()> {
+        push_to_outbox(&self.outbox, server_id, LibReaction::RawMsg(cmd.into()));
+
+        Ok(())
+    }
+
+    /// Checks that the bot is in the given channel with at least half-operator status, and
+    /// returns the name of the channel as the server spells it.
+    fn check_channel_privileges(&self, server_id: ServerId, channel: &str) -> Result<String> {
+        let casemapping = self.casemapping(server_id)?;
+        let own_nick = self.nick(server_id)?;
+
+        self.with_aatxe_client(server_id, |client| {
+            let channel = client
+                .list_channels()
+                .unwrap_or_default()
+                .into_iter()
+                .find(|c| casemapped_str_cmp(casemapping, &c[..], channel) == Ordering::Equal)
+                .ok_or_else(|| ErrorKind::NotInChannel(channel.to_owned()))?;
+
+            let own_access_lvl = client
+                .list_users(&channel)
+                .unwrap_or_default()
+                .iter()
+                .find(|user| {
+                    casemapped_str_cmp(casemapping, user.get_nickname(), &own_nick[..])
+                        == Ordering::Equal
+                })
+                .map(|user| user.highest_access_level())
+                .unwrap_or(AccessLevel::Member);
+
+            match own_access_lvl {
+                AccessLevel::Owner
+                | AccessLevel::Admin
+                | AccessLevel::Oper
+                | AccessLevel::HalfOp => Ok(channel),
+                AccessLevel::Voice | AccessLevel::Member => {
+                    Err(ErrorKind::InsufficientChannelPrivileges(channel).into())
+                }
+            }
+        })
+    }
+
+    fn check_quiet_supported(&self, server_id: ServerId) -> Result<()> {
+        let caps = self.server_capabilities(server_id)?;
+
+        // The first comma-separated group of `CHANMODES` lists the modes that take a mask.
+        let supported = caps
+            .params
+            .get("CHANMODES")
+            .and_then(Option::as_ref)
+            .map(|chanmodes| {
+                chanmodes
+                    .split(',')
+                    .take(1)
+                    .any(|list_modes| list_modes.contains(QUIET_MODE))
+            })
+            .unwrap_or(false);
+
+        if supported {
+            Ok(())
+        } else {
+            Err(ErrorKind::ChannelModeUnsupported(QUIET_MODE).into())
+        }
+    }
+}
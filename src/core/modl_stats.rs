@@ -0,0 +1,266 @@
+//! Accounting of the resources that bot modules use, which helps the bot's operators to tell which
+//! modules are slow, failing, or hoarding data, and enforcement of the configured `module limits`.
+//!
+//! The accounts are kept in memory, from when the bot starts. A module's run time is the time
+//! that its handlers have taken to run, as measured by the wall clock, which, for handlers that
+//! don't wait on the network or on locks, approximates the processor time that they have used.
+
+use super::ErrorKind;
+use super::Result;
+use super::State;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::MutexGuard;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+use walkdir::WalkDir;
+
+/// How often to check whether modules have exceeded their `storage` limits
+const STORAGE_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often to check whether the storage is due to be checked, or the bot is shutting down
+const TICK: Duration = Duration::from_secs(1);
+
+/// The resources that a bot module has used since the bot started
+#[derive(Clone, Debug, Default)]
+pub struct ModuleUsage {
+    /// How many times the module's handlers have been run
+    pub invocations: u64,
+
+    /// How many of the module's handlers' runs have failed, by returning an error or by panicking
+    pub errors: u64,
+
+    /// How long the module's handlers have taken to run, in all
+    pub run_time: Duration,
+
+    /// Whether the module has been disabled for exceeding one of its `module limits`
+    pub disabled: bool,
+}
+
+impl State {
+    fn lock_module_usage(&self) -> Result<MutexGuard<BTreeMap<String, ModuleUsage>>> {
+        self.module_usage
+            .lock()
+            .map_err(|_| ErrorKind::LockPoisoned("the module resource accounts".into()).into())
+    }
+
+    /// Returns the resources that each loaded bot module has used since the bot started, by the
+    /// modules' names.
+    pub fn module_usage(&self) -> Result<BTreeMap<String, ModuleUsage>> {
+        let usage = self.lock_module_usage()?;
+
+        Ok(self
+            .read_modules()
+            .keys()
+            .map(|name| {
+                let name = name.to_string();
+                let entry = usage.get(&name).cloned().unwrap_or_default();
+                (name, entry)
+            })
+            .collect())
+    }
+
+    /// Returns the number of bytes that the data files of the bot module with the given name take
+    /// up in the bot's module data directory. These are taken to be the file or directory, in
+    /// that directory, that is named after the module, with or without an extension.
+    pub fn module_storage_size(&self, name: &str) -> Result<u64> {
+        let mut size = 0;
+
+        let entries = match fs::read_dir(&self.module_data_path) {
+            Ok(entries) => entries,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+
+        for entry in entries {
+            let path = entry?.path();
+
+            if path.file_stem().filter(|stem| *stem == name).is_none() {
+                continue;
+            }
+
+            size += storage_size(&path)?;
+        }
+
+        Ok(size)
+    }
+
+    /// Returns whether the bot module with the given name has been disabled for exceeding one of
+    /// its `module limits`.
+    pub(super) fn module_disabled(&self, name: &str) -> bool {
+        match self.lock_module_usage() {
+            Ok(usage) => usage.get(name).filter(|usage| usage.disabled).is_some(),
+            Err(e) => {
+                error!(
+                    "Failed to check whether module {:?} is disabled: {}",
+                    name, e
+                );
+                false
+            }
+        }
+    }
+
+    /// Records a run of a handler of the bot module with the given name, which took the given
+    /// time and failed or not, as given, and disables the module if it has thereby exceeded its
+    /// `run time` or `errors` limit.
+    pub(super) fn record_module_run(&self, name: &str, run_time: Duration, failed: bool) {
+        let exceeded = {
+            let mut usage = match self.lock_module_usage() {
+                Ok(usage) => usage,
+                Err(e) => {
+                    error!("Failed to record run of module {:?}: {}", name, e);
+                    return;
+                }
+            };
+
+            let usage = usage.entry(name.to_owned()).or_default();
+
+            usage.invocations += 1;
+            usage.run_time += run_time;
+
+            if failed {
+                usage.errors += 1;
+            }
+
+            if usage.disabled {
+                return;
+            }
+
+            let config = self.config();
+            let limits = match config.module_limits.get(name) {
+                Some(limits) => limits,
+                None => return,
+            };
+
+            if limits
+                .run_time()
+                .filter(|&max| usage.run_time > max)
+                .is_some()
+            {
+                "run time"
+            } else if limits.errors.filter(|&max| usage.errors >= max).is_some() {
+                "errors"
+            } else {
+                return;
+            }
+        };
+
+        self.disable_module(name, exceeded);
+    }
+
+    /// Disables each loaded bot module whose data files take up more than its `storage` limit.
+    fn check_module_storage(&self) {
+        let config = self.config();
+
+        for (name, limits) in &config.module_limits {
+            let max = match limits.storage {
+                Some(max) if self.module_loaded(name) && !self.module_disabled(name) => max,
+                _ => continue,
+            };
+
+            match self.module_storage_size(name) {
+                Ok(size) if size > max => self.disable_module(name, "storage"),
+                Ok(_) => {}
+                Err(e) => error!("Failed to measure storage of module {:?}: {}", name, e),
+            }
+        }
+    }
+
+    /// Disables the bot module with the given name, for exceeding its limit of the given name,
+    /// and notifies the bot's administrators.
+    fn disable_module(&self, name: &str, limit: &str) {
+        match self.lock_module_usage() {
+            Ok(mut usage) => usage.entry(name.to_owned()).or_default().disabled = true,
+            Err(e) => {
+                error!("Failed to disable module {:?}: {}", name, e);
+                return;
+            }
+        }
+
+        let notice = format!(
+            "Having exceeded its {:?} limit, the module {:?} has been disabled until the bot is \
+             restarted.",
+            limit, name
+        );
+
+        warn!("{}", notice);
+
+        for &server_id in self.servers.keys() {
+            if let Err(e) = self.notify_admins(server_id, &notice) {
+                error!(
+                    "Failed to notify administrators of the disabling of a module: {}",
+                    e
+                )
+            }
+        }
+    }
+}
+
+/// Returns the number of bytes that the file or directory at the given path takes up.
+fn storage_size(path: &Path) -> Result<u64> {
+    let mut size = 0;
+
+    for entry in WalkDir::new(path) {
+        let metadata = entry?.metadata()?;
+
+        if metadata.is_file() {
+            size += metadata.len();
+        }
+    }
+
+    Ok(size)
+}
+
+/// Checks periodically, until the bot shuts down, whether any bot module has exceeded its
+/// `storage` limit.
+pub(super) fn modl_stats_main(state: Arc<State>) -> Result<()> {
+    let mut last_check = None;
+
+    while !state.is_shutting_down() {
+        if last_check
+            .filter(|t: &Instant| t.elapsed() < STORAGE_CHECK_INTERVAL)
+            .is_none()
+        {
+            state.check_module_storage();
+            last_check = Some(Instant::now());
+        }
+
+        thread::sleep(TICK);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::offline::OfflineBot;
+    use modules;
+
+    #[test]
+    fn errors_limit() {
+        let bot = OfflineBot::for_test(
+            "module limits:\n  \
+             default:\n    \
+             errors: 2\n",
+            vec![modules::default],
+        );
+        let state = bot.state();
+
+        state.record_module_run("default", Duration::from_millis(5), true);
+        assert!(!state.module_disabled("default"));
+
+        state.record_module_run("default", Duration::from_millis(5), false);
+        state.record_module_run("default", Duration::from_millis(5), true);
+        assert!(state.module_disabled("default"));
+
+        let usage = state.module_usage().unwrap();
+        assert_eq!(usage["default"].invocations, 3);
+        assert_eq!(usage["default"].errors, 2);
+        assert_eq!(usage["default"].run_time, Duration::from_millis(15));
+    }
+}
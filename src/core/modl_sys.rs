@@ -1,24 +1,54 @@
+use super::irc_send::push_to_outbox;
 use super::trigger::TriggerPriority;
 use super::BotCmdAttr;
 use super::BotCmdAuthLvl;
 use super::BotCmdHandler;
+use super::BotCmdResult;
 use super::BotCommand;
+use super::Bridge;
+use super::EchoedMsgHandler;
 use super::Error;
+use super::ErrorContext;
 use super::ErrorKind;
 use super::GetDebugInfo;
+use super::IncomingMsg;
+use super::InputFilter;
+use super::InputVerdict;
 use super::ModuleLoadHandler;
+use super::ModuleUnloadHandler;
+use super::MsgMetadata;
+use super::OutgoingMsg;
+use super::OutputFilter;
+use super::OutputVerdict;
+use super::PasteService;
+use super::PeriodicHandler;
+use super::Reaction;
+use super::ReplyRoute;
 use super::Result;
+use super::ServerConnectionHandler;
+use super::ServerId;
 use super::State;
 use super::Trigger;
 use super::TriggerAttr;
 use super::TriggerHandler;
+use super::TypedCommand;
+use super::UnknownCommandHandler;
+use super::UserEvent;
+use super::UserEventHandler;
 use itertools;
 use regex::Regex;
 use smallvec::SmallVec;
 use std;
 use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
+use std::sync::PoisonError;
 use std::sync::RwLock;
+use std::sync::RwLockReadGuard;
+use std::sync::RwLockWriteGuard;
+use std::time::Duration;
+use std::time::Instant;
 use util;
 use uuid::Uuid;
 use yaml_rust::Yaml;
@@ -32,8 +62,43 @@ pub struct Module {
 
     features: Vec<ModuleFeature>,
 
+    dependencies: Vec<Cow<'static, str>>,
+
     #[debug(skip)]
     on_load: SmallVec<[Box<ModuleLoadHandler>; 1]>,
+
+    #[debug(skip)]
+    on_connect: SmallVec<[Box<ServerConnectionHandler>; 1]>,
+
+    #[debug(skip)]
+    on_disconnect: SmallVec<[Box<ServerConnectionHandler>; 1]>,
+
+    #[debug(skip)]
+    on_unload: SmallVec<[Box<ModuleUnloadHandler>; 1]>,
+
+    #[debug(skip)]
+    on_user_event: SmallVec<[Box<UserEventHandler>; 1]>,
+
+    #[debug(skip)]
+    on_echoed_msg: SmallVec<[Box<EchoedMsgHandler>; 1]>,
+
+    #[debug(skip)]
+    on_unknown_command: SmallVec<[Box<UnknownCommandHandler>; 1]>,
+
+    #[debug(skip)]
+    periodic: SmallVec<[(Duration, Box<PeriodicHandler>); 1]>,
+
+    #[debug(skip)]
+    bridges: SmallVec<[Arc<Bridge>; 1]>,
+
+    #[debug(skip)]
+    paste_services: SmallVec<[Arc<PasteService>; 1]>,
+
+    #[debug(skip)]
+    input_filters: SmallVec<[(i16, Box<InputFilter>); 1]>,
+
+    #[debug(skip)]
+    output_filters: SmallVec<[(i16, Box<OutputFilter>); 1]>,
 }
 
 impl PartialEq for Module {
@@ -62,25 +127,104 @@ impl GetDebugInfo for Module {
 pub struct ModuleBuilder {
     name: Cow<'static, str>,
     features: Vec<ModuleFeature>,
+    dependencies: Vec<Cow<'static, str>>,
     on_load: SmallVec<[Box<ModuleLoadHandler>; 1]>,
+    on_connect: SmallVec<[Box<ServerConnectionHandler>; 1]>,
+    on_disconnect: SmallVec<[Box<ServerConnectionHandler>; 1]>,
+    on_unload: SmallVec<[Box<ModuleUnloadHandler>; 1]>,
+    on_user_event: SmallVec<[Box<UserEventHandler>; 1]>,
+    on_echoed_msg: SmallVec<[Box<EchoedMsgHandler>; 1]>,
+    on_unknown_command: SmallVec<[Box<UnknownCommandHandler>; 1]>,
+    periodic: SmallVec<[(Duration, Box<PeriodicHandler>); 1]>,
+    bridges: SmallVec<[Arc<Bridge>; 1]>,
+    paste_services: SmallVec<[Arc<PasteService>; 1]>,
+    input_filters: SmallVec<[(i16, Box<InputFilter>); 1]>,
+    output_filters: SmallVec<[(i16, Box<OutputFilter>); 1]>,
 }
 
-pub fn mk_module<'modl, S>(name: S) -> ModuleBuilder
+pub fn mk_module<S>(name: S) -> ModuleBuilder
 where
     S: Into<Cow<'static, str>>,
 {
     ModuleBuilder {
         name: name.into(),
         features: Default::default(),
+        dependencies: Default::default(),
         on_load: Default::default(),
+        on_connect: Default::default(),
+        on_disconnect: Default::default(),
+        on_unload: Default::default(),
+        on_user_event: Default::default(),
+        on_echoed_msg: Default::default(),
+        on_unknown_command: Default::default(),
+        periodic: Default::default(),
+        bridges: Default::default(),
+        paste_services: Default::default(),
+        input_filters: Default::default(),
+        output_filters: Default::default(),
     }
 }
 
 impl ModuleBuilder {
     pub fn command<'attr, Attrs, S1, S2, S3>(
+        self,
+        name: S1,
+        syntax: S2,
+        help_msg: S3,
+        auth_lvl: BotCmdAuthLvl,
+        handler: Box<BotCmdHandler>,
+        attrs: Attrs,
+    ) -> Self
+    where
+        S1: Into<Cow<'static, str>>,
+        S2: Into<Cow<'static, str>>,
+        S3: Into<Cow<'static, str>>,
+        Attrs: IntoIterator<Item = &'attr BotCmdAttr>,
+    {
+        let syntax = syntax.into();
+        let usage_yaml = util::yaml::parse_node(&syntax)
+            .unwrap()
+            .unwrap_or(Yaml::Hash(Default::default()));
+
+        self.push_command(
+            name,
+            syntax,
+            Some(usage_yaml),
+            help_msg,
+            auth_lvl,
+            handler,
+            attrs,
+        )
+    }
+
+    /// Adds a bot command whose arguments are parsed according to their types, as defined with
+    /// [`typed_cmd!`], rather than as YAML.
+    ///
+    /// [`typed_cmd!`]: <macro.typed_cmd.html>
+    pub fn typed_command<'attr, Attrs, S1, S2>(
+        self,
+        name: S1,
+        help_msg: S2,
+        auth_lvl: BotCmdAuthLvl,
+        cmd: TypedCommand,
+        attrs: Attrs,
+    ) -> Self
+    where
+        S1: Into<Cow<'static, str>>,
+        S2: Into<Cow<'static, str>>,
+        Attrs: IntoIterator<Item = &'attr BotCmdAttr>,
+    {
+        let TypedCommand { usage, handler } = cmd;
+
+        self.push_command(name, usage, None, help_msg, auth_lvl, handler, attrs)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn push_command<'attr, Attrs, S1, S2, S3>(
         mut self,
         name: S1,
         syntax: S2,
+        usage_yaml: Option<Yaml>,
         help_msg: S3,
         auth_lvl: BotCmdAuthLvl,
         handler: Box<BotCmdHandler>,
@@ -100,26 +244,24 @@ impl ModuleBuilder {
             name.as_ref()
         );
 
-        let syntax = syntax.into();
-        let usage_yaml = util::yaml::parse_node(&syntax)
-            .unwrap()
-            .unwrap_or(Yaml::Hash(Default::default()));
+        let mut reply_route = ReplyRoute::default();
+
+        for attr in attrs {
+            match *attr {
+                BotCmdAttr::ReplyRoute(route) => reply_route = route,
+            }
+        }
 
         let cmd = ModuleFeature::Command {
             name: name,
-            usage_str: syntax,
+            usage_str: syntax.into(),
             usage_yaml,
             help_msg: help_msg.into(),
             auth_lvl: auth_lvl,
             handler: handler.into(),
+            reply_route,
         };
 
-        for attr in attrs {
-            match *attr {
-                // ...
-            }
-        }
-
         self.features.push(cmd);
 
         self
@@ -140,9 +282,11 @@ impl ModuleBuilder {
         S2: Into<Cow<'static, str>>,
         Attrs: IntoIterator<Item = &'attr TriggerAttr>,
     {
+        let mut always_watching = false;
+
         for attr in attrs {
             match attr {
-                &TriggerAttr::AlwaysWatching => unimplemented!(),
+                &TriggerAttr::AlwaysWatching => always_watching = true,
             }
         }
 
@@ -155,6 +299,7 @@ impl ModuleBuilder {
             help_msg: help_msg.into(),
             handler: handler.into(),
             priority,
+            always_watching,
             uuid: Uuid::new_v4(),
         };
 
@@ -177,21 +322,241 @@ impl ModuleBuilder {
         self
     }
 
+    /// Sets a handler function to be called upon connecting to a server.
+    ///
+    /// The given `handler` function will be called, with the `ServerId` of the server, each time
+    /// the bot finishes registering its connection to a server, i.e., once the server has sent
+    /// its message of the day (MotD), if any. This is suitable, e.g., for announcing the bot's
+    /// presence.
+    pub fn on_connect(mut self, handler: Box<ServerConnectionHandler>) -> Self {
+        self.on_connect.push(handler);
+
+        self
+    }
+
+    /// Sets a handler function to be called upon disconnecting from a server.
+    ///
+    /// The given `handler` function will be called, with the `ServerId` of the server, each time
+    /// the bot's connection to a server is closed, whether deliberately or otherwise.
+    pub fn on_disconnect(mut self, handler: Box<ServerConnectionHandler>) -> Self {
+        self.on_disconnect.push(handler);
+
+        self
+    }
+
+    /// Sets a handler function to be called when the module is unloaded.
+    ///
+    /// The given `handler` function will be called when the bot shuts down, after it has
+    /// disconnected from all servers. This is suitable, e.g., for flushing any state that the
+    /// module keeps in memory to persistent storage.
+    pub fn on_unload(mut self, handler: Box<ModuleUnloadHandler>) -> Self {
+        self.on_unload.push(handler);
+
+        self
+    }
+
+    /// Sets a handler function to be called when a user changes nicknames or quits, or returns
+    /// from a netsplit.
+    ///
+    /// The given `handler` function will be called, with the `ServerId` of the server and a
+    /// [`UserEvent`] describing what happened, while the bot is handling the message from the
+    /// server that announced the event; it therefore should return promptly. Users are
+    /// identified by [`UserId`]s, which stay the same across nickname changes and netsplits.
+    ///
+    /// [`UserEvent`]: <enum.UserEvent.html>
+    /// [`UserId`]: <struct.UserId.html>
+    pub fn on_user_event(mut self, handler: Box<UserEventHandler>) -> Self {
+        self.on_user_event.push(handler);
+
+        self
+    }
+
+    /// Sets a handler function to be called for each message that the bot sends, as echoed back
+    /// by the server.
+    ///
+    /// If the server supports the IRCv3 capability `echo-message`, it repeats back to the bot each
+    /// `PRIVMSG` that the bot sends, as the server relayed it. The given `handler` function will be
+    /// called with the metadata and text of each such message, which is suitable, e.g., for
+    /// keeping an accurate log of what the bot has said. Echoed messages are never treated as bot
+    /// commands or matched against triggers.
+    pub fn on_echoed_msg(mut self, handler: Box<EchoedMsgHandler>) -> Self {
+        self.on_echoed_msg.push(handler);
+
+        self
+    }
+
+    /// Sets a handler function to be called when a message addressed to the bot uses a command
+    /// that doesn't exist, and no trigger matches the message.
+    ///
+    /// The given `handler` function will be called with the message's metadata, the name of the
+    /// command, and the rest of the message, and may answer the message, as a bot command would,
+    /// e.g., by looking the name up in a database of factoids; or return `None` to leave the
+    /// message to other modules' such handlers, which are tried in order by the modules' names.
+    /// If no handler answers, the bot suggests any commands with similar names, if its
+    /// `suggest commands` setting is enabled. Answers are treated as those of commands at the
+    /// authorization level `Public`.
+    pub fn on_unknown_command(mut self, handler: Box<UnknownCommandHandler>) -> Self {
+        self.on_unknown_command.push(handler);
+
+        self
+    }
+
+    /// Sets a handler function to be called periodically, at the given interval, while the bot
+    /// runs.
+    ///
+    /// The given `handler` function is first called once the interval has passed after the bot
+    /// has started, and then each time the interval passes again; it is suitable, e.g., for
+    /// polling a Web service for news to announce with [`State::send_privmsg`]. All modules'
+    /// periodic handlers are called in turn on a single thread of their own, so that they may
+    /// block, e.g., to fetch a document with [`fetch`], but one that blocks for long delays the
+    /// others. The interval is measured with a resolution of one second.
+    ///
+    /// [`State::send_privmsg`]: <struct.State.html#method.send_privmsg>
+    /// [`fetch`]: <fn.fetch.html>
+    pub fn periodic(mut self, interval: Duration, handler: Box<PeriodicHandler>) -> Self {
+        self.periodic.push((interval, handler));
+
+        self
+    }
+
+    /// Adds a bridge to a chat service other than IRC, whose rooms are to appear to bot modules as
+    /// channels; see [`Bridge`].
+    ///
+    /// Once the bot has started, it polls the bridge for messages on a thread of the bridge's
+    /// own, until the bridge is closed. The bridge's name should differ from those of other
+    /// modules' bridges, which shadow it if they come first in order by the modules' names.
+    ///
+    /// [`Bridge`]: <trait.Bridge.html>
+    pub fn bridge(mut self, bridge: Box<Bridge>) -> Self {
+        self.bridges.push(bridge.into());
+
+        self
+    }
+
+    /// Adds a paste service, to which the bot may upload oversized command output if the
+    /// configuration field `paste: service` names it; see [`PasteService`].
+    ///
+    /// The service's name should differ from those of other modules' paste services, which
+    /// shadow it if they come first in order by the modules' names, and from the names of the
+    /// built-in services, `bot` and `HTTP form`, which shadow it always.
+    ///
+    /// [`PasteService`]: <trait.PasteService.html>
+    pub fn paste_service(mut self, service: Box<PasteService>) -> Self {
+        self.paste_services.push(service.into());
+
+        self
+    }
+
+    /// Adds a filter through which each message that the bot receives from a server is to be
+    /// passed before the bot handles it.
+    ///
+    /// The given `handler` function sees each message before any bot command or trigger, or any
+    /// other handler, does. It may rewrite the message, e.g., to annotate it with client-only
+    /// message tags for the handlers to see; drop it, e.g., as spam, by returning
+    /// [`InputVerdict::Drop`]; or have other messages handled in its place, by returning
+    /// [`InputVerdict::Replace`]. The filters of all loaded modules form a chain, ordered as
+    /// output filters are (see [`output_filter`]). Filters run as each message is received, and so
+    /// should return promptly; their run times are included in the bot's metrics. If a filter
+    /// fails, the message is passed on to the next filter as the failed filter was given it.
+    ///
+    /// [`InputVerdict::Drop`]: <enum.InputVerdict.html#variant.Drop>
+    /// [`InputVerdict::Replace`]: <enum.InputVerdict.html#variant.Replace>
+    /// [`output_filter`]: <#method.output_filter>
+    pub fn input_filter(mut self, order: i16, handler: Box<InputFilter>) -> Self {
+        self.input_filters.push((order, handler));
+
+        self
+    }
+
+    /// Adds a filter through which each `PRIVMSG` and `NOTICE` that the bot sends is to be passed
+    /// before it is sent.
+    ///
+    /// The given `handler` function may rewrite the message's text, its target, and its IRCv3
+    /// message tags, or veto sending the message by returning [`OutputVerdict::Drop`]. The filters
+    /// of all loaded modules form a chain, each filter being given the message as the filters
+    /// before it have left it: filters with a lower `order` run before those with a higher one,
+    /// and filters with the same `order` run in order by their modules' names. Filters run as
+    /// messages leave the outbox, and so should return promptly. If a filter fails, the message is
+    /// passed on to the next filter as the failed filter was given it.
+    ///
+    /// [`OutputVerdict::Drop`]: <enum.OutputVerdict.html#variant.Drop>
+    pub fn output_filter(mut self, order: i16, handler: Box<OutputFilter>) -> Self {
+        self.output_filters.push((order, handler));
+
+        self
+    }
+
+    /// Declares that the module depends on the module with the given name.
+    ///
+    /// When modules are loaded with [`State::load_modules`], they are loaded in an order such that
+    /// each module is loaded after the modules on which it depends. A module is not loaded if any
+    /// of its dependencies cannot be loaded.
+    ///
+    /// To integrate optionally with another module, without depending on it, use
+    /// [`State::module_loaded`] instead.
+    ///
+    /// [`State::load_modules`]: <struct.State.html#method.load_modules>
+    /// [`State::module_loaded`]: <struct.State.html#method.module_loaded>
+    pub fn depends_on<S>(mut self, module_name: S) -> Self
+    where
+        S: Into<Cow<'static, str>>,
+    {
+        self.dependencies.push(module_name.into());
+
+        self
+    }
+
     pub fn end(self) -> Module {
         let ModuleBuilder {
             name,
             mut features,
+            mut dependencies,
             mut on_load,
+            mut on_connect,
+            mut on_disconnect,
+            mut on_unload,
+            mut on_user_event,
+            mut on_echoed_msg,
+            mut on_unknown_command,
+            mut periodic,
+            mut bridges,
+            mut paste_services,
+            mut input_filters,
+            mut output_filters,
         } = self;
 
         features.shrink_to_fit();
+        dependencies.shrink_to_fit();
         on_load.shrink_to_fit();
+        on_connect.shrink_to_fit();
+        on_disconnect.shrink_to_fit();
+        on_unload.shrink_to_fit();
+        on_user_event.shrink_to_fit();
+        on_echoed_msg.shrink_to_fit();
+        on_unknown_command.shrink_to_fit();
+        periodic.shrink_to_fit();
+        bridges.shrink_to_fit();
+        paste_services.shrink_to_fit();
+        input_filters.shrink_to_fit();
+        output_filters.shrink_to_fit();
 
         Module {
             name: name,
             uuid: Uuid::new_v4(),
             features: features,
+            dependencies,
             on_load,
+            on_connect,
+            on_disconnect,
+            on_unload,
+            on_user_event,
+            on_echoed_msg,
+            on_unknown_command,
+            periodic,
+            bridges,
+            paste_services,
+            input_filters,
+            output_filters,
         }
     }
 }
@@ -209,7 +574,7 @@ enum ModuleFeature {
 
         usage_str: Cow<'static, str>,
 
-        usage_yaml: Yaml,
+        usage_yaml: Option<Yaml>,
 
         help_msg: Cow<'static, str>,
 
@@ -217,6 +582,8 @@ enum ModuleFeature {
 
         #[debug(skip)]
         handler: Arc<BotCmdHandler>,
+
+        reply_route: ReplyRoute,
     },
     Trigger {
         name: Cow<'static, str>,
@@ -230,6 +597,8 @@ enum ModuleFeature {
 
         priority: TriggerPriority,
 
+        always_watching: bool,
+
         uuid: Uuid,
     },
 }
@@ -312,22 +681,92 @@ pub enum ModuleLoadMode {
     Force,
 }
 
+// The tables of modules and of their features are changed only by insertions and removals, which
+// leave them intact even if a thread panics while holding one of their locks, so a poisoned lock
+// is simply taken over.
 impl State {
+    pub(super) fn read_modules(
+        &self,
+    ) -> RwLockReadGuard<'_, BTreeMap<Cow<'static, str>, Arc<Module>>> {
+        self.modules.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_modules(&self) -> RwLockWriteGuard<'_, BTreeMap<Cow<'static, str>, Arc<Module>>> {
+        self.modules.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(super) fn read_commands(
+        &self,
+    ) -> RwLockReadGuard<'_, BTreeMap<Cow<'static, str>, Arc<BotCommand>>> {
+        self.commands.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_commands(&self) -> RwLockWriteGuard<'_, BTreeMap<Cow<'static, str>, Arc<BotCommand>>> {
+        self.commands
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(super) fn read_triggers(
+        &self,
+    ) -> RwLockReadGuard<'_, BTreeMap<TriggerPriority, Vec<Arc<Trigger>>>> {
+        self.triggers.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_triggers(&self) -> RwLockWriteGuard<'_, BTreeMap<TriggerPriority, Vec<Arc<Trigger>>>> {
+        self.triggers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns the loaded modules, in order. Their handlers are to be run from this list rather
+    /// than while the table of modules is locked, so that a handler may load or unload modules.
+    pub(super) fn loaded_modules(&self) -> Vec<Arc<Module>> {
+        self.read_modules().values().cloned().collect()
+    }
+
+    /// Returns whether a module with the given name is loaded.
+    pub fn module_loaded(&self, name: &str) -> bool {
+        self.read_modules().contains_key(name)
+    }
+
+    /// Returns the names of the loaded modules, in order.
+    pub fn module_names(&self) -> Vec<String> {
+        self.read_modules()
+            .keys()
+            .map(|name| name.to_string())
+            .collect()
+    }
+
+    /// Loads the given modules, in an order such that each module is loaded after the modules on
+    /// which it depends (see [`ModuleBuilder::depends_on`]).
+    ///
+    /// Modules whose dependencies are neither already loaded nor among the given modules, or
+    /// whose dependencies are cyclic, are not loaded. Errors are returned for these modules, but
+    /// the other modules are still loaded.
+    ///
+    /// [`ModuleBuilder::depends_on`]: <struct.ModuleBuilder.html#method.depends_on>
     pub fn load_modules<Modls>(
-        &mut self,
+        &self,
         modules: Modls,
         mode: ModuleLoadMode,
     ) -> std::result::Result<(), Vec<Error>>
     where
         Modls: IntoIterator<Item = Module>,
     {
-        let errs = itertools::flatten(modules.into_iter().filter_map(|module| {
-            match self.load_module(module, mode) {
+        let (modules, mut errs) = {
+            let already_loaded = self.read_modules();
+            sort_by_dependencies(modules.into_iter().collect(), |name| {
+                already_loaded.contains_key(name)
+            })
+        };
+
+        errs.extend(itertools::flatten(modules.into_iter().filter_map(
+            |module| match self.load_module(module, mode) {
                 Ok(()) => None,
                 Err(e) => Some(e),
-            }
-        }))
-        .collect::<Vec<Error>>();
+            },
+        )));
 
         if errs.is_empty() {
             Ok(())
@@ -337,7 +776,7 @@ impl State {
     }
 
     pub fn load_module(
-        &mut self,
+        &self,
         module: Module,
         mode: ModuleLoadMode,
     ) -> std::result::Result<(), Vec<Error>> {
@@ -352,20 +791,38 @@ impl State {
                 .collect::<Vec<_>>()
         );
 
-        if let Some(existing_module) = match (mode, self.modules.get(module.name.as_ref())) {
+        let existing_module = match (mode, self.read_modules().get(module.name.as_ref())) {
             (_, None) | (ModuleLoadMode::Replace, _) | (ModuleLoadMode::Force, _) => None,
-            (ModuleLoadMode::Add, Some(old)) => Some(old),
-        } {
+            (ModuleLoadMode::Add, Some(old)) => Some(old.dbg_info()),
+        };
+
+        if let Some(existing_module) = existing_module {
             return Err(vec![ErrorKind::ModuleRegistryClash(
-                existing_module.dbg_info(),
+                existing_module,
+                module.dbg_info(),
+            )
+            .into()]);
+        }
+
+        let missing_deps = module
+            .dependencies
+            .iter()
+            .filter(|dep| !self.module_loaded(dep))
+            .map(ToString::to_string)
+            .collect::<Vec<_>>();
+
+        if !missing_deps.is_empty() {
+            return Err(vec![ErrorKind::ModuleDependenciesMissing(
                 module.dbg_info(),
+                missing_deps,
             )
             .into()]);
         }
 
         let module = Arc::new(module);
 
-        self.modules.insert(module.name.clone(), module.clone());
+        self.write_modules()
+            .insert(module.name.clone(), module.clone());
 
         let errs = module
             .features
@@ -392,21 +849,412 @@ impl State {
         Ok(())
     }
 
-    fn load_module_feature<'modl>(
-        &mut self,
+    /// Unloads the module with the given name, removing the bot commands and triggers that it
+    /// provides and running its unload handlers (see [`ModuleBuilder::on_unload`]).
+    ///
+    /// A module cannot be unloaded while other loaded modules depend on it. Any of the module's
+    /// handlers that are running already, such as that of the command by which the module is being
+    /// unloaded, finish running.
+    ///
+    /// [`ModuleBuilder::on_unload`]: <struct.ModuleBuilder.html#method.on_unload>
+    pub fn unload_module(&self, name: &str) -> Result<()> {
+        debug!("Unloading module {:?}", name);
+
+        let module = {
+            let mut modules = self.write_modules();
+
+            let module = match modules.get(name) {
+                Some(m) => m.clone(),
+                None => bail!(ErrorKind::UnknownModule(name.to_owned())),
+            };
+
+            let dependents = modules
+                .values()
+                .filter(|m| m.dependencies.iter().any(|dep| dep == name))
+                .map(|m| m.name.to_string())
+                .collect::<Vec<_>>();
+
+            if !dependents.is_empty() {
+                bail!(ErrorKind::ModuleHasDependents(
+                    module.dbg_info(),
+                    dependents
+                ))
+            }
+
+            modules.remove(name);
+
+            module
+        };
+
+        self.write_commands()
+            .retain(|_, cmd| !Arc::ptr_eq(&cmd.provider, &module));
+
+        for triggers in self.write_triggers().values_mut() {
+            triggers.retain(|trigger| !Arc::ptr_eq(&trigger.provider, &module));
+        }
+
+        for handler in &module.on_unload {
+            self.run_lifecycle_handler(&module, "unload handler", None, || handler.run(self));
+        }
+
+        Ok(())
+    }
+
+    /// Runs the connection handlers of all loaded modules for the given server.
+    pub(super) fn run_connect_handlers(&self, server_id: ServerId) {
+        for module in &self.loaded_modules() {
+            for handler in &module.on_connect {
+                self.run_lifecycle_handler(module, "connection handler", Some(server_id), || {
+                    handler.run(self, server_id)
+                });
+            }
+        }
+    }
+
+    /// Runs the disconnection handlers of all loaded modules for the given server.
+    pub(super) fn run_disconnect_handlers(&self, server_id: ServerId) {
+        for module in &self.loaded_modules() {
+            for handler in &module.on_disconnect {
+                self.run_lifecycle_handler(
+                    module,
+                    "disconnection handler",
+                    Some(server_id),
+                    || handler.run(self, server_id),
+                );
+            }
+        }
+    }
+
+    /// Runs the user event handlers of all loaded modules for the given server.
+    pub(super) fn run_user_event_handlers(&self, server_id: ServerId, event: &UserEvent) {
+        for module in &self.loaded_modules() {
+            for handler in &module.on_user_event {
+                self.run_lifecycle_handler(module, "user event handler", Some(server_id), || {
+                    handler.run(self, server_id, event)
+                });
+            }
+        }
+    }
+
+    /// Runs the echoed message handlers of all loaded modules.
+    pub(super) fn run_echoed_msg_handlers(&self, metadata: &MsgMetadata, text: &str) {
+        let server_id = metadata.dest.server_id;
+
+        for module in &self.loaded_modules() {
+            for handler in &module.on_echoed_msg {
+                self.run_lifecycle_handler(
+                    module,
+                    "echoed message handler",
+                    Some(server_id),
+                    || handler.run(self, metadata, text),
+                );
+            }
+        }
+    }
+
+    /// Runs the unknown-command handlers of all loaded modules, in order, until one answers the
+    /// message with the given metadata, returning the answer.
+    pub(super) fn run_unknown_command_handlers(
+        &self,
+        metadata: &MsgMetadata,
+        cmd_name: &str,
+        cmd_args: &str,
+    ) -> Option<BotCmdResult> {
+        for module in &self.loaded_modules() {
+            if self.module_disabled(&module.name) {
+                continue;
+            }
+
+            for handler in &module.on_unknown_command {
+                let start = Instant::now();
+                let result =
+                    util::run_handler("unknown-command handler", module.name.clone(), || {
+                        handler.run(self, metadata, cmd_name, cmd_args)
+                    });
+
+                self.record_module_run(
+                    &module.name,
+                    start.elapsed(),
+                    match result {
+                        Ok(Some(BotCmdResult::LibErr(_)))
+                        | Ok(Some(BotCmdResult::BotErrMsg(_)))
+                        | Err(_) => true,
+                        Ok(_) => false,
+                    },
+                );
+
+                match result {
+                    Ok(None) => {}
+                    Ok(Some(BotCmdResult::Ok(Reaction::Quit(_)))) => {
+                        return Some(BotCmdResult::BotErrMsg(
+                            format!(
+                                "The unknown-command handler of module {:?} tried to tell the bot \
+                                 to quit, which only commands at authorization level {:?} may do.",
+                                module.name,
+                                BotCmdAuthLvl::Admin
+                            )
+                            .into(),
+                        ));
+                    }
+                    Ok(Some(result)) => return Some(result),
+                    Err(e) => return Some(BotCmdResult::LibErr(e)),
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Returns the bridges of all loaded modules.
+    pub(super) fn bridges(&self) -> Vec<Arc<Bridge>> {
+        self.read_modules()
+            .values()
+            .flat_map(|module| module.bridges.iter().cloned())
+            .collect()
+    }
+
+    /// Returns the bridge with the given name, if any loaded module has one.
+    pub(super) fn bridge(&self, name: &str) -> Option<Arc<Bridge>> {
+        self.read_modules()
+            .values()
+            .flat_map(|module| module.bridges.iter())
+            .find(|bridge| bridge.name() == name)
+            .cloned()
+    }
+
+    /// Returns the paste service with the given name, if any loaded module has one.
+    pub(super) fn paste_service(&self, name: &str) -> Option<Arc<PasteService>> {
+        self.read_modules()
+            .values()
+            .flat_map(|module| module.paste_services.iter())
+            .find(|service| service.name() == name)
+            .cloned()
+    }
+
+    /// Returns whether any loaded module has a periodic handler.
+    pub(super) fn has_periodic_handlers(&self) -> bool {
+        self.read_modules()
+            .values()
+            .any(|module| !module.periodic.is_empty())
+    }
+
+    /// Runs each periodic handler of each loaded module whose interval has passed since it was
+    /// last run, or, if it hasn't been run, since the given start time. The times at which the
+    /// handlers were last run are kept in `last_runs`, by module name and handler index.
+    pub(super) fn run_due_periodic_handlers(
+        &self,
+        start: Instant,
+        last_runs: &mut BTreeMap<(Cow<'static, str>, usize), Instant>,
+    ) {
+        for module in &self.loaded_modules() {
+            for (i, &(interval, ref handler)) in module.periodic.iter().enumerate() {
+                let key = (module.name.clone(), i);
+                let last_run = last_runs.get(&key).cloned().unwrap_or(start);
+
+                if last_run.elapsed() < interval {
+                    continue;
+                }
+
+                last_runs.insert(key, Instant::now());
+
+                self.run_lifecycle_handler(module, "periodic handler", None, || handler.run(self));
+            }
+        }
+    }
+
+    /// Returns whether any loaded module has an input filter.
+    pub(super) fn has_input_filters(&self) -> bool {
+        self.read_modules()
+            .values()
+            .any(|module| !module.input_filters.is_empty())
+    }
+
+    /// Passes the given message through the input filters of all loaded modules, in order,
+    /// returning the messages that remain to be handled.
+    pub(super) fn run_input_filters(&self, msg: IncomingMsg) -> Vec<IncomingMsg> {
+        let server_id = msg.server_id;
+
+        let modules = self.loaded_modules();
+
+        let mut filters = modules
+            .iter()
+            .flat_map(|module| {
+                module
+                    .input_filters
+                    .iter()
+                    .map(move |&(order, ref filter)| (order, module, filter))
+            })
+            .collect::<Vec<_>>();
+
+        filters.sort_by_key(|&(order, _, _)| order);
+
+        let mut msgs = vec![msg];
+
+        for (_, module, filter) in filters {
+            let mut remaining = Vec::with_capacity(msgs.len());
+
+            for msg in msgs {
+                let mut filtered = msg.clone();
+                let mut verdict = None;
+                let start = Instant::now();
+
+                self.run_lifecycle_handler(
+                    module,
+                    "input filter",
+                    Some(server_id),
+                    AssertUnwindSafe(|| {
+                        verdict = Some(filter.run(self, &mut filtered)?);
+                        Ok(())
+                    }),
+                );
+
+                self.metrics
+                    .record_handler_run("input filter", &module.name, start.elapsed());
+
+                match verdict {
+                    Some(InputVerdict::Handle) => remaining.push(filtered),
+                    Some(InputVerdict::Drop) => {}
+                    Some(InputVerdict::Replace(replacements)) => remaining.extend(replacements),
+                    None => remaining.push(msg),
+                }
+            }
+
+            msgs = remaining;
+        }
+
+        msgs
+    }
+
+    /// Passes the given message through the output filters of all loaded modules, in order,
+    /// stopping if any filter drops it.
+    pub(super) fn run_output_filters(&self, msg: &mut OutgoingMsg) -> OutputVerdict {
+        let server_id = msg.server_id;
+
+        let modules = self.loaded_modules();
+
+        let mut filters = modules
+            .iter()
+            .flat_map(|module| {
+                module
+                    .output_filters
+                    .iter()
+                    .map(move |&(order, ref filter)| (order, module, filter))
+            })
+            .collect::<Vec<_>>();
+
+        filters.sort_by_key(|&(order, _, _)| order);
+
+        for (_, module, filter) in filters {
+            let mut filtered = msg.clone();
+            let mut verdict = None;
+
+            self.run_lifecycle_handler(
+                module,
+                "output filter",
+                Some(server_id),
+                AssertUnwindSafe(|| {
+                    verdict = Some(filter.run(self, &mut filtered)?);
+                    Ok(())
+                }),
+            );
+
+            match verdict {
+                Some(OutputVerdict::Send) => *msg = filtered,
+                Some(OutputVerdict::Drop) => return OutputVerdict::Drop,
+                None => {}
+            }
+        }
+
+        OutputVerdict::Send
+    }
+
+    /// Runs the unload handlers of all loaded modules.
+    pub(super) fn run_unload_handlers(&self) {
+        for module in &self.loaded_modules() {
+            for handler in &module.on_unload {
+                self.run_lifecycle_handler(module, "unload handler", None, || handler.run(self));
+            }
+        }
+    }
+
+    /// Runs a module's lifecycle handler, passing any error that it returns to the error handler.
+    /// Any reaction to such an error is sent to the given server or, if none is given, to all
+    /// servers.
+    fn run_lifecycle_handler<F>(
+        &self,
+        module: &Module,
+        handler_kind: &'static str,
+        server_id: Option<ServerId>,
+        handler_invocation: F,
+    ) where
+        F: FnOnce() -> Result<()> + std::panic::UnwindSafe,
+    {
+        // A disabled module's unload handlers are still run, so that it may save its data.
+        if handler_kind != "unload handler" && self.module_disabled(&module.name) {
+            return;
+        }
+
+        let start = Instant::now();
+        let result = util::run_handler(handler_kind, module.name.clone(), handler_invocation);
+
+        self.record_module_run(
+            &module.name,
+            start.elapsed(),
+            match result {
+                Ok(Ok(())) => false,
+                Ok(Err(_)) | Err(_) => true,
+            },
+        );
+
+        let err = match result {
+            Ok(Ok(())) => return,
+            Ok(Err(e)) | Err(e) => e,
+        };
+
+        let err = err.with_context(ErrorContext {
+            server: server_id.map(|id| self.server_socket_addr_dbg_string(id)),
+            module: Some(module.name.to_string()),
+            ..Default::default()
+        });
+
+        let reaction = match self.handle_err(
+            server_id,
+            err,
+            format!("in {} of module {:?}", handler_kind, module.name),
+        ) {
+            Some(r) => r,
+            None => return,
+        };
+
+        match server_id {
+            Some(server_id) => push_to_outbox(&self.outbox, server_id, reaction),
+            None => {
+                for &server_id in self.servers.keys() {
+                    push_to_outbox(&self.outbox, server_id, reaction.clone())
+                }
+            }
+        }
+    }
+
+    fn load_module_feature(
+        &self,
         provider: Arc<Module>,
-        feature: &'modl ModuleFeature,
+        feature: &ModuleFeature,
         mode: ModuleLoadMode,
     ) -> Result<()> {
         trace!("Loading module feature (phase 1): {:?}", feature.dbg_info());
 
         if let Some(existing_feature) = match feature {
-            &ModuleFeature::Command { .. } => match (mode, self.commands.get(feature.name())) {
-                (_, None) | (ModuleLoadMode::Force, _) => None,
-                (ModuleLoadMode::Replace, Some(old)) if old.provider.name == provider.name => None,
-                (ModuleLoadMode::Replace, Some(old)) => Some(old.dbg_info()),
-                (ModuleLoadMode::Add, Some(old)) => Some(old.dbg_info()),
-            },
+            &ModuleFeature::Command { .. } => {
+                match (mode, self.read_commands().get(feature.name())) {
+                    (_, None) | (ModuleLoadMode::Force, _) => None,
+                    (ModuleLoadMode::Replace, Some(old)) if old.provider.name == provider.name => {
+                        None
+                    }
+                    (ModuleLoadMode::Replace, Some(old)) => Some(old.dbg_info()),
+                    (ModuleLoadMode::Add, Some(old)) => Some(old.dbg_info()),
+                }
+            }
             &ModuleFeature::Trigger { .. } => None,
         } {
             bail!(ErrorKind::ModuleFeatureRegistryClash(
@@ -420,11 +1268,7 @@ impl State {
         Ok(())
     }
 
-    fn force_load_module_feature<'modl>(
-        &mut self,
-        provider: Arc<Module>,
-        feature: &'modl ModuleFeature,
-    ) {
+    fn force_load_module_feature(&self, provider: Arc<Module>, feature: &ModuleFeature) {
         trace!("Loading module feature (phase 2): {:?}", feature.dbg_info());
 
         match feature {
@@ -435,10 +1279,11 @@ impl State {
                 ref usage_str,
                 ref usage_yaml,
                 ref help_msg,
+                reply_route,
             } => {
-                self.commands.insert(
+                self.write_commands().insert(
                     name.clone(),
-                    BotCommand {
+                    Arc::new(BotCommand {
                         provider: provider,
                         name: name.clone(),
                         auth_lvl: auth_lvl.clone(),
@@ -446,7 +1291,9 @@ impl State {
                         usage_str: usage_str.clone(),
                         usage_yaml: usage_yaml.clone(),
                         help_msg: help_msg.clone(),
-                    },
+                        reply_route,
+                        panic_count: Default::default(),
+                    }),
                 );
             }
             &ModuleFeature::Trigger {
@@ -455,21 +1302,140 @@ impl State {
                 ref handler,
                 ref help_msg,
                 priority,
+                always_watching,
                 uuid,
             } => {
-                self.triggers
+                self.write_triggers()
                     .entry(priority)
                     .or_insert_with(Default::default)
-                    .push(Trigger {
+                    .push(Arc::new(Trigger {
                         provider,
                         name: name.clone(),
                         regex: regex.clone(),
                         handler: handler.clone(),
                         priority,
+                        always_watching,
                         help_msg: help_msg.clone(),
                         uuid,
-                    });
+                    }));
             }
         };
     }
 }
+
+/// Orders the given modules such that each module comes after the modules on which it depends.
+///
+/// Dependencies for which `is_loaded` returns `true` are considered satisfied already. Modules
+/// whose dependencies cannot be satisfied are omitted from the ordering, and errors are returned
+/// for them instead.
+fn sort_by_dependencies<F>(mut pending: Vec<Module>, is_loaded: F) -> (Vec<Module>, Vec<Error>)
+where
+    F: Fn(&str) -> bool,
+{
+    let mut sorted: Vec<Module> = Vec::with_capacity(pending.len());
+
+    loop {
+        let next_idx = pending.iter().position(|module| {
+            module
+                .dependencies
+                .iter()
+                .all(|dep| is_loaded(dep) || sorted.iter().any(|m| m.name == *dep))
+        });
+
+        match next_idx {
+            Some(idx) => sorted.push(pending.remove(idx)),
+            None => break,
+        }
+    }
+
+    // Any modules left over have dependencies that are missing, or that are left over themselves,
+    // in which case the dependencies are cyclic or have missing dependencies in turn.
+    let errs = pending
+        .iter()
+        .map(|module| {
+            let (missing, unresolved): (Vec<_>, Vec<_>) = module
+                .dependencies
+                .iter()
+                .filter(|dep| !is_loaded(dep) && !sorted.iter().any(|m| m.name == **dep))
+                .map(ToString::to_string)
+                .partition(|dep| !pending.iter().any(|m| m.name == *dep));
+
+            if missing.is_empty() {
+                ErrorKind::ModuleDependenciesUnresolvable(module.dbg_info(), unresolved).into()
+            } else {
+                ErrorKind::ModuleDependenciesMissing(module.dbg_info(), missing).into()
+            }
+        })
+        .collect();
+
+    (sorted, errs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::offline::OfflineBot;
+    use modules;
+
+    fn names(modules: &[Module]) -> Vec<&str> {
+        modules.iter().map(|m| m.name.as_ref()).collect()
+    }
+
+    #[test]
+    fn sort_by_dependencies_examples() {
+        let (sorted, errs) = sort_by_dependencies(
+            vec![
+                mk_module("c").depends_on("b").depends_on("a").end(),
+                mk_module("b").depends_on("a").end(),
+                mk_module("a").end(),
+                mk_module("d").depends_on("default").end(),
+            ],
+            |name| name == "default",
+        );
+        assert_eq!(names(&sorted), ["a", "b", "c", "d"]);
+        assert!(errs.is_empty());
+
+        let (sorted, errs) = sort_by_dependencies(
+            vec![
+                mk_module("a").depends_on("missing").end(),
+                mk_module("b").depends_on("a").end(),
+                mk_module("c").depends_on("d").end(),
+                mk_module("d").depends_on("c").end(),
+                mk_module("e").end(),
+            ],
+            |_| false,
+        );
+        assert_eq!(names(&sorted), ["e"]);
+        let errs = errs.into_iter().map(|e| e.to_string()).collect::<Vec<_>>();
+        assert_eq!(errs.len(), 4);
+        assert!(errs[0].contains("\"missing\""));
+        assert!(errs[1].contains("\"a\""));
+        assert!(errs[2].contains("\"d\""));
+    }
+
+    #[test]
+    fn unload_module() {
+        let bot = OfflineBot::for_test("", vec![modules::default, modules::admin]);
+        let state = bot.state();
+        let server_id = state.server_ids()[0];
+
+        state
+            .load_module(
+                mk_module("dependent").depends_on("admin").end(),
+                ModuleLoadMode::Add,
+            )
+            .unwrap();
+        assert!(state.unload_module("admin").is_err());
+        assert!(state.module_loaded("admin"));
+
+        state.unload_module("dependent").unwrap();
+        assert!(state.command(server_id, "raw").unwrap().is_some());
+
+        state.unload_module("admin").unwrap();
+        assert!(!state.module_loaded("admin"));
+        assert!(state.command(server_id, "raw").unwrap().is_none());
+        assert!(state.command(server_id, "help").unwrap().is_some());
+
+        assert!(state.unload_module("admin").is_err());
+    }
+}
@@ -0,0 +1,303 @@
+//! Running the bot offline, without connecting to any server, to replay a recorded session (see
+//! [`replay`]) or to try out a configuration and modules (see [`simulate`]).
+//!
+//! An offline bot handles each message given to it completely, including any bot command that
+//! the message invokes, before it takes the next, and the messages that it would send in response
+//! are written to an output, or logged, rather than sent. Neither its HTTP listener, control
+//! socket, console, nor periodic handlers are run.
+//!
+//! [`replay`]: <../fn.replay.html>
+//! [`simulate`]: <../fn.simulate.html>
+
+use super::assemble_state;
+use super::handle_msg;
+use super::irc_send;
+use super::irc_send::OutboxPort;
+use super::irc_send::OutboxReceiver;
+use super::pager;
+use super::ErrorHandler;
+use super::IntoConfig;
+use super::LibReaction;
+use super::Module;
+use super::Result;
+use super::ServerId;
+use super::State;
+use irc::proto::Message;
+use std::io::BufRead;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// A bot running offline, with the two ends of its outbox
+pub(super) struct OfflineBot {
+    state: Arc<State>,
+    outbox: OutboxPort,
+    outbox_receiver: OutboxReceiver,
+}
+
+impl OfflineBot {
+    /// Assembles an offline bot as the function [`run`] would assemble a bot, with the given
+    /// output for the messages that it would send, or `None` if they are to be logged. Returns
+    /// `None`, after logging why, if this fails.
+    ///
+    /// [`run`]: <../fn.run.html>
+    pub(super) fn new<Cfg, ErrF, ModlCtor, Modls>(
+        config: Cfg,
+        module_data_path: PathBuf,
+        error_handler: ErrF,
+        modules: Modls,
+        output: Option<Box<Write + Send>>,
+    ) -> Option<Self>
+    where
+        Cfg: IntoConfig,
+        ErrF: ErrorHandler,
+        Modls: IntoIterator<Item = ModlCtor>,
+        ModlCtor: Fn() -> Module,
+    {
+        let (mut state, outbox, outbox_receiver) =
+            assemble_state(config, module_data_path, error_handler, modules)?;
+
+        state.offline = true;
+        state.offline_output = Mutex::new(output);
+
+        Some(OfflineBot {
+            state: Arc::new(state),
+            outbox,
+            outbox_receiver,
+        })
+    }
+
+    /// Assembles an offline bot for a test, with the given modules and the configuration that
+    /// [`test_config`] returns for the given further settings. Panics if this fails.
+    ///
+    /// [`test_config`]: <fn.test_config.html>
+    #[cfg(test)]
+    pub(super) fn for_test<ModlCtor, Modls>(extra_config: &str, modules: Modls) -> Self
+    where
+        Modls: IntoIterator<Item = ModlCtor>,
+        ModlCtor: Fn() -> Module,
+    {
+        Self::for_test_config(test_config(extra_config), modules)
+    }
+
+    /// Assembles an offline bot for a test, with the given configuration and modules, keeping its
+    /// module data in the system's temporary directory and proceeding past any errors. Panics if
+    /// this fails.
+    #[cfg(test)]
+    pub(super) fn for_test_config<Cfg, ModlCtor, Modls>(config: Cfg, modules: Modls) -> Self
+    where
+        Cfg: IntoConfig,
+        Modls: IntoIterator<Item = ModlCtor>,
+        ModlCtor: Fn() -> Module,
+    {
+        Self::new(
+            config,
+            ::std::env::temp_dir(),
+            |_| super::ErrorReaction::Proceed,
+            modules,
+            None,
+        )
+        .expect("Failed to assemble an offline bot for a test.")
+    }
+
+    pub(super) fn state(&self) -> &Arc<State> {
+        &self.state
+    }
+
+    /// Handles the given message as though the bot had received it from the given server, and
+    /// then puts out the messages that the bot would send in response.
+    pub(super) fn handle(&self, server_id: ServerId, msg: Result<Message>) {
+        handle_msg(&self.state, server_id, &self.outbox, msg);
+
+        self.flush();
+    }
+
+    /// Puts out the messages waiting in the bot's outbox.
+    fn flush(&self) {
+        while let Some(record) = self.outbox_receiver.try_recv() {
+            if let Err(e) = irc_send::dispatch(&self.state, "offline", record) {
+                error!("{}", e);
+            }
+        }
+    }
+
+    /// Shuts the bot down, running its modules' unload handlers, without sending `QUIT`s.
+    pub(super) fn finish(self) {
+        self.state.shutting_down.store(true, Ordering::SeqCst);
+        self.state.run_unload_handlers();
+
+        self.flush();
+    }
+}
+
+impl State {
+    /// Writes the messages of the given reaction, which the bot would send to the given server
+    /// were it not offline, to its offline output, one line apiece, or logs them if it has none.
+    pub(super) fn emit_offline(&self, server_id: ServerId, output: LibReaction<Message>) {
+        let mut msgs = Vec::new();
+        pager::flatten(output, &mut msgs);
+
+        let mut output = match self.offline_output.lock() {
+            Ok(output) => output,
+            Err(_) => {
+                error!("The offline output is poisoned.");
+                return;
+            }
+        };
+
+        for msg in msgs {
+            let line = msg.to_string();
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+
+            match *output {
+                Some(ref mut w) => {
+                    if let Err(e) = writeln!(w, "{}", line).and_then(|()| w.flush()) {
+                        error!("Failed to write {:?} to the offline output: {}", line, e);
+                    }
+                }
+                None => info!("Would send to server {:?}: {:?}", server_id, line),
+            }
+        }
+    }
+}
+
+/// Returns the text of a configuration for a test: that of a bot with the nickname `testbot` and
+/// no console, with the given further settings, and, unless those list the servers, with the
+/// single server `simulated`.
+#[cfg(test)]
+pub(super) fn test_config(extra: &str) -> String {
+    let mut config = format!("nickname: testbot\nconsole: false\n{}", extra);
+
+    if !extra.lines().any(|line| line.starts_with("servers:")) {
+        config.push_str(
+            "servers:\n  \
+             - name: simulated\n    \
+             host: irc.invalid\n    \
+             port: 6697\n",
+        );
+    }
+
+    config
+}
+
+/// Runs a bot offline, without connecting to any server, handling the lines of the given input as
+/// though it had received them from the first server in its configuration, and writing the lines
+/// that it would send in response to the given output, so that a configuration, the loading of
+/// modules, and the behaviour of bot commands may be tried out quickly.
+///
+/// The other arguments are those of [`run`]. The input, which may be read from a file or from
+/// standard input, should have a raw IRC message per line, such as
+/// `:alice!alice@example.com PRIVMSG #channel :testbot: help`; blank lines and lines that begin
+/// with `#` are skipped. Before the first line, the bot is told that it has registered its
+/// connection to the server, so that it runs its modules' connection handlers. Each line is
+/// handled completely, and the bot's response written, before the next is read.
+///
+/// The bot does not open its HTTP listener or its control socket, nor run its periodic handlers,
+/// but bot modules that fetch documents from the Web still do so.
+///
+/// [`run`]: <fn.run.html>
+pub fn simulate<Cfg, ModlData, ErrF, ModlCtor, Modls, R, W>(
+    config: Cfg,
+    module_data_path: ModlData,
+    error_handler: ErrF,
+    modules: Modls,
+    input: R,
+    output: W,
+) where
+    Cfg: IntoConfig,
+    ModlData: Into<PathBuf>,
+    ErrF: ErrorHandler,
+    Modls: IntoIterator<Item = ModlCtor>,
+    ModlCtor: Fn() -> Module,
+    R: BufRead,
+    W: Write + Send + 'static,
+{
+    let bot = match OfflineBot::new(
+        config,
+        module_data_path.into(),
+        error_handler,
+        modules,
+        Some(Box::new(output)),
+    ) {
+        Some(bot) => bot,
+        None => return,
+    };
+
+    let server_id = match bot
+        .state()
+        .servers
+        .keys()
+        .min_by_key(|server_id| server_id.config_idx)
+    {
+        Some(&server_id) => server_id,
+        None => {
+            error!("Terminal error: The configuration specifies no servers.");
+            return;
+        }
+    };
+
+    let nick = match bot.state().nick(server_id) {
+        Ok(nick) => nick,
+        Err(e) => {
+            error!("Terminal error: {}", e);
+            return;
+        }
+    };
+
+    for line in &[
+        format!(":simulation.invalid 001 {} :Welcome", nick),
+        format!(":simulation.invalid 376 {} :End of /MOTD command.", nick),
+    ] {
+        bot.handle(server_id, line.parse::<Message>().map_err(Into::into));
+    }
+
+    for (i, line) in input.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to read line {} of the input: {}", i + 1, e);
+                break;
+            }
+        };
+
+        let line = line.trim();
+
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        bot.handle(server_id, line.parse::<Message>().map_err(Into::into));
+    }
+
+    bot.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ErrorReaction;
+    use modules;
+    use std::env;
+    use testing::SharedOutput;
+
+    #[test]
+    fn simulation() {
+        let output = SharedOutput::default();
+
+        simulate(
+            test_config(""),
+            env::temp_dir(),
+            |e| {
+                error!("{}", e);
+                ErrorReaction::Proceed
+            },
+            vec![modules::default],
+            &b"# A comment\n\n:alice!alice@example.com PRIVMSG testbot :ping\n"[..],
+            output.clone(),
+        );
+
+        assert_eq!(output.text(), "PRIVMSG alice :pong\n");
+    }
+}
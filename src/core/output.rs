@@ -0,0 +1,126 @@
+use super::LibReaction;
+use super::MsgTags;
+use super::ServerId;
+use super::State;
+use irc::client::prelude as aatxe;
+use irc::proto::Message;
+
+/// A `PRIVMSG` or `NOTICE` that the bot is about to send, as passed to the output filters that
+/// modules register with [`ModuleBuilder::output_filter`]
+///
+/// [`ModuleBuilder::output_filter`]: <struct.ModuleBuilder.html#method.output_filter>
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct OutgoingMsg {
+    pub server_id: ServerId,
+
+    /// The channel or user to whom the message is to be sent
+    pub target: String,
+
+    /// The text of the message, which, for CTCP messages such as actions, includes the delimiting
+    /// `\u{1}` characters
+    pub text: String,
+
+    /// Whether the message is to be sent as a `NOTICE` rather than as a `PRIVMSG`
+    pub is_notice: bool,
+
+    /// The IRCv3 message tags to attach to the message, which are omitted if the server has not
+    /// enabled the `message-tags` capability
+    pub tags: MsgTags,
+}
+
+/// What is to become of a message that an output filter has been given
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OutputVerdict {
+    /// Pass the message, as the filter may have rewritten it, on to the next filter, or else send
+    /// it.
+    Send,
+
+    /// Don't send the message at all.
+    Drop,
+}
+
+impl OutgoingMsg {
+    fn from_aatxe(server_id: ServerId, msg: &Message) -> Option<Self> {
+        let (target, text, is_notice) = match msg.command {
+            aatxe::Command::PRIVMSG(ref target, ref text) => (target, text, false),
+            aatxe::Command::NOTICE(ref target, ref text) => (target, text, true),
+            _ => return None,
+        };
+
+        Some(OutgoingMsg {
+            server_id,
+            target: target.clone(),
+            text: text.clone(),
+            is_notice,
+            tags: MsgTags::from_aatxe(msg.tags.clone()),
+        })
+    }
+
+    fn into_aatxe(self, prefix: Option<String>, tags_enabled: bool) -> Message {
+        let OutgoingMsg {
+            server_id: _,
+            target,
+            text,
+            is_notice,
+            tags,
+        } = self;
+
+        Message {
+            tags: if tags_enabled { tags.to_aatxe() } else { None },
+            prefix,
+            command: if is_notice {
+                aatxe::Command::NOTICE(target, text)
+            } else {
+                aatxe::Command::PRIVMSG(target, text)
+            },
+        }
+    }
+}
+
+/// Passes each `PRIVMSG` and `NOTICE` in the given output through the loaded modules' output
+/// filters, returning what remains to be sent, if anything.
+pub(super) fn filter_output(
+    state: &State,
+    server_id: ServerId,
+    output: LibReaction<Message>,
+) -> Option<LibReaction<Message>> {
+    match output {
+        LibReaction::RawMsg(msg) => {
+            let mut outgoing = match OutgoingMsg::from_aatxe(server_id, &msg) {
+                Some(outgoing) => outgoing,
+                None => return Some(LibReaction::RawMsg(msg)),
+            };
+
+            let original = outgoing.clone();
+
+            if state.run_output_filters(&mut outgoing) == OutputVerdict::Drop {
+                debug!("Output filters dropped {:?}", msg);
+                return None;
+            }
+
+            if outgoing == original {
+                return Some(LibReaction::RawMsg(msg));
+            }
+
+            let tags_enabled = state
+                .capability_enabled(server_id, "message-tags")
+                .unwrap_or(false);
+
+            Some(LibReaction::RawMsg(
+                outgoing.into_aatxe(msg.prefix, tags_enabled),
+            ))
+        }
+        LibReaction::Multi(outputs) => {
+            let outputs = outputs
+                .into_iter()
+                .filter_map(|output| filter_output(state, server_id, output))
+                .collect::<Vec<_>>();
+
+            if outputs.is_empty() {
+                None
+            } else {
+                Some(LibReaction::Multi(outputs))
+            }
+        }
+    }
+}
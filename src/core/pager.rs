@@ -0,0 +1,240 @@
+use super::LibReaction;
+use super::MsgDest;
+use super::MsgPrefix;
+use super::Result;
+use super::State;
+use irc::client::prelude as aatxe;
+use irc::proto::Message;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use util::irc::casemapped_str_cmp;
+use util::irc::CaseMapping;
+
+/// How many users' held output to remember per server, beyond which the output held for the user
+/// who least recently used a command is forgotten
+const MAX_USERS: usize = 64;
+
+/// The output of commands held, per the configuration field `page length`, for the users who used
+/// the commands on a server, in the order in which the users last used commands
+#[derive(Debug, Default)]
+pub(super) struct PendingPages {
+    users: Vec<(String, VecDeque<Message>)>,
+}
+
+impl PendingPages {
+    fn position(&self, casemapping: CaseMapping, nick: &str) -> Option<usize> {
+        self.users
+            .iter()
+            .position(|(n, _)| casemapped_str_cmp(casemapping, &n[..], nick) == Ordering::Equal)
+    }
+
+    /// Holds the given messages for the given user, in place of any held for the user before.
+    fn hold(&mut self, casemapping: CaseMapping, nick: &str, msgs: VecDeque<Message>) {
+        if let Some(i) = self.position(casemapping, nick) {
+            self.users.remove(i);
+        }
+
+        if self.users.len() >= MAX_USERS {
+            self.users.remove(0);
+        }
+
+        self.users.push((nick.to_owned(), msgs));
+    }
+
+    /// Takes up to the given number of the messages held for the given user, or all of them if no
+    /// number is given, returning them along with the number of messages still held, or `None` if
+    /// no messages are held for the user.
+    fn take(
+        &mut self,
+        casemapping: CaseMapping,
+        nick: &str,
+        n: Option<usize>,
+    ) -> Option<(Vec<Message>, usize)> {
+        let i = self.position(casemapping, nick)?;
+
+        let page = {
+            let msgs = &mut self.users[i].1;
+            let len = msgs.len();
+            msgs.drain(..n.unwrap_or(len).min(len)).collect::<Vec<_>>()
+        };
+
+        let remaining = self.users[i].1.len();
+
+        if remaining == 0 {
+            self.users.remove(i);
+        }
+
+        Some((page, remaining))
+    }
+}
+
+/// Returns the first page of the given output of a command used by the given user at the given
+/// destination, holding the rest for the user, if the output takes more messages than the
+/// configuration field `page length` allows; or else returns the output unchanged.
+pub(super) fn paginate(
+    state: &State,
+    origin: MsgDest,
+    user: MsgPrefix,
+    output: LibReaction<Message>,
+) -> Result<LibReaction<Message>> {
+    let (page_length, nick) = match (state.config().page_length, user.nick) {
+        (Some(n), Some(nick)) => (n, nick),
+        _ => return Ok(output),
+    };
+
+    let mut msgs = Vec::new();
+    flatten(output, &mut msgs);
+
+    if msgs.len() <= page_length {
+        return Ok(LibReaction::Multi(
+            msgs.into_iter().map(LibReaction::RawMsg).collect(),
+        ));
+    }
+
+    let mut msgs = VecDeque::from(msgs);
+    let rest = msgs.split_off(page_length);
+    let remaining = rest.len();
+
+    {
+        let mut server = state.write_server(origin.server_id)?;
+        let casemapping = server.capabilities.casemapping;
+        server.pending_pages.hold(casemapping, nick, rest);
+    }
+
+    Ok(mk_page(msgs.into_iter().collect(), remaining, &|n| {
+        more_note_text(state, origin, user, n)
+    }))
+}
+
+/// Returns the next page of the output held for the given user, who asked for it at the given
+/// destination, or `None` if no output is held for the user.
+pub(super) fn next_page(
+    state: &State,
+    origin: MsgDest,
+    user: MsgPrefix,
+) -> Result<Option<LibReaction<Message>>> {
+    let page_length = state.config().page_length;
+    let nick = match user.nick {
+        Some(nick) => nick,
+        None => return Ok(None),
+    };
+
+    let taken = {
+        let mut server = state.write_server(origin.server_id)?;
+        let casemapping = server.capabilities.casemapping;
+
+        server.pending_pages.take(casemapping, nick, page_length)
+    };
+
+    Ok(taken.map(|(page, remaining)| {
+        mk_page(page, remaining, &|n| more_note_text(state, origin, user, n))
+    }))
+}
+
+/// Returns the text of the note that the given number of messages remain to be sent to the given
+/// user.
+fn more_note_text(state: &State, origin: MsgDest, user: MsgPrefix, remaining: usize) -> String {
+    state.localize(
+        origin,
+        user,
+        if remaining == 1 {
+            "more: one message"
+        } else {
+            "more: messages"
+        },
+        &[("count", &remaining.to_string())],
+    )
+}
+
+/// Returns the given page of messages, followed, if any messages remain to be sent, by a note of
+/// how many, with the text that `note` gives for that number.
+fn mk_page(
+    page: Vec<Message>,
+    remaining: usize,
+    note: &Fn(usize) -> String,
+) -> LibReaction<Message> {
+    let note = match page.last() {
+        Some(last) if remaining > 0 => similar_msg(last, note(remaining)),
+        _ => None,
+    };
+
+    LibReaction::Multi(
+        page.into_iter()
+            .chain(note)
+            .map(LibReaction::RawMsg)
+            .collect(),
+    )
+}
+
+/// Returns a message with the given text, sent in the same way as the given message, such as the
+/// last message of a page.
+pub(super) fn similar_msg(msg: &Message, text: String) -> Option<Message> {
+    let command = match msg.command {
+        aatxe::Command::PRIVMSG(ref target, _) => aatxe::Command::PRIVMSG(target.clone(), text),
+        aatxe::Command::NOTICE(ref target, _) => aatxe::Command::NOTICE(target.clone(), text),
+        _ => return None,
+    };
+
+    Some(Message {
+        tags: msg.tags.clone(),
+        prefix: None,
+        command,
+    })
+}
+
+pub(super) fn flatten(output: LibReaction<Message>, msgs: &mut Vec<Message>) {
+    match output {
+        LibReaction::RawMsg(msg) => msgs.push(msg),
+        LibReaction::Multi(outputs) => {
+            for output in outputs {
+                flatten(output, msgs)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::i18n;
+    use super::*;
+
+    fn privmsg(text: &str) -> Message {
+        aatxe::Command::PRIVMSG("#chan".into(), text.into()).into()
+    }
+
+    #[test]
+    fn pages() {
+        let mut pages = PendingPages::default();
+        let cm = CaseMapping::Rfc1459;
+
+        pages.hold(
+            cm,
+            "Nick[]",
+            (0..5).map(|i| privmsg(&i.to_string())).collect(),
+        );
+
+        let (page, remaining) = pages.take(cm, "nick{}", Some(2)).unwrap();
+        assert_eq!(page, vec![privmsg("0"), privmsg("1")]);
+        assert_eq!(remaining, 3);
+
+        let note = |n: usize| {
+            i18n::render(
+                i18n::built_in("more: messages"),
+                &[("count", &n.to_string())],
+            )
+        };
+
+        let mut out = Vec::new();
+        flatten(mk_page(page, remaining, &note), &mut out);
+        assert_eq!(
+            out.last().unwrap().command,
+            aatxe::Command::PRIVMSG(
+                "#chan".into(),
+                "(3 more messages; use the command `more` to see them)".into()
+            )
+        );
+
+        assert_eq!(pages.take(cm, "NICK[]", None).unwrap().1, 0);
+        assert!(pages.take(cm, "NICK[]", None).is_none());
+    }
+}
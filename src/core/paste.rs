@@ -0,0 +1,229 @@
+//! Offloading of oversized command output to paste services, as configured with the field
+//! `paste`.
+//!
+//! When a command's output would take more messages than the configuration allows, the bot
+//! uploads the text of the output to the configured paste service and replies with a link to it
+//! instead. The service may be the bot itself (`bot`), which keeps recent pastes in memory and
+//! serves them from its own HTTP listener at `/paste/<id>`; a generic service to which text is
+//! uploaded as an HTML form (`HTTP form`); or any service that a bot module provides (see
+//! [`ModuleBuilder::paste_service`]). Should the upload fail, the output is sent as usual.
+//!
+//! [`ModuleBuilder::paste_service`]: <../struct.ModuleBuilder.html#method.paste_service>
+
+use super::config::Paste;
+use super::fetch;
+use super::pager;
+use super::ErrorKind;
+use super::FetchLimits;
+use super::LibReaction;
+use super::MsgDest;
+use super::MsgPrefix;
+use super::Result;
+use super::State;
+use irc::client::prelude as aatxe;
+use irc::proto::Message;
+use rand::Rng;
+use std::collections::VecDeque;
+use std::panic::RefUnwindSafe;
+use std::panic::UnwindSafe;
+use std::str;
+use std::sync::MutexGuard;
+use url::Url;
+use util::sha256;
+
+/// How many pastes the bot keeps to serve itself, beyond which the oldest is forgotten
+const MAX_HOSTED_PASTES: usize = 64;
+
+/// The number of random bytes of which the identifier of a paste that the bot serves itself is
+/// made
+const PASTE_ID_LEN: usize = 8;
+
+/// A paste service, to which the bot may upload oversized command output
+pub trait PasteService: Send + Sync + UnwindSafe + RefUnwindSafe + 'static {
+    /// Returns the name of the service, by which the configuration field `paste: service` refers
+    /// to it.
+    fn name(&self) -> &str;
+
+    /// Uploads the given text, returning the URL at which it may be read.
+    fn paste(&self, text: &str) -> Result<String>;
+}
+
+/// The pastes that the bot serves from its own HTTP listener, oldest first, with their identifiers
+#[derive(Debug, Default)]
+pub(super) struct HostedPastes {
+    pastes: VecDeque<(String, String)>,
+}
+
+impl HostedPastes {
+    fn insert(&mut self, id: String, text: String) {
+        if self.pastes.len() >= MAX_HOSTED_PASTES {
+            self.pastes.pop_front();
+        }
+
+        self.pastes.push_back((id, text));
+    }
+
+    fn get(&self, id: &str) -> Option<&str> {
+        self.pastes
+            .iter()
+            .find(|(i, _)| i == id)
+            .map(|(_, text)| &text[..])
+    }
+}
+
+impl State {
+    fn lock_hosted_pastes(&self) -> Result<MutexGuard<HostedPastes>> {
+        self.hosted_pastes
+            .lock()
+            .map_err(|_| ErrorKind::LockPoisoned("the hosted pastes".into()).into())
+    }
+
+    /// Returns the text of the paste with the given identifier that the bot serves itself, if the
+    /// bot still has it.
+    pub(super) fn hosted_paste(&self, id: &str) -> Result<Option<String>> {
+        Ok(self.lock_hosted_pastes()?.get(id).map(ToOwned::to_owned))
+    }
+
+    /// Uploads the given text to the configured paste service, returning the URL at which it may
+    /// be read.
+    fn paste(&self, cfg: &Paste, text: &str) -> Result<String> {
+        match &cfg.service[..] {
+            "bot" => {
+                let mut bytes = [0u8; PASTE_ID_LEN];
+                self.rng()?.fill(&mut bytes);
+
+                let id = sha256::to_hex(&bytes);
+                let base = cfg.public_url.as_ref().ok_or_else(|| {
+                    ErrorKind::Config("paste: public URL".into(), "is not set".into())
+                })?;
+                let url = format!("{}/paste/{}", base.as_str().trim_end_matches('/'), id);
+
+                self.lock_hosted_pastes()?.insert(id, text.to_owned());
+
+                Ok(url)
+            }
+            "HTTP form" => {
+                let url = cfg
+                    .url
+                    .as_ref()
+                    .ok_or_else(|| ErrorKind::Config("paste: URL".into(), "is not set".into()))?;
+
+                post_form(url, &cfg.field, text)
+            }
+            name => match self.paste_service(name) {
+                Some(service) => service.paste(text),
+                None => Err(ErrorKind::Config(
+                    "paste: service".into(),
+                    format!("names no paste service of any loaded module: {:?}", name),
+                )
+                .into()),
+            },
+        }
+    }
+}
+
+/// Returns the given output of a command used by the given user at the given destination, unless
+/// the output takes more messages than the configuration field `paste: lines` allows, in which
+/// case its text is uploaded to the configured paste service and a link to it is returned in its
+/// place.
+pub(super) fn offload(
+    state: &State,
+    origin: MsgDest,
+    user: MsgPrefix,
+    output: LibReaction<Message>,
+) -> LibReaction<Message> {
+    let config = state.config();
+
+    let cfg = match config.paste {
+        Some(ref cfg) => cfg,
+        None => return output,
+    };
+
+    let mut msgs = Vec::new();
+    pager::flatten(output, &mut msgs);
+
+    let text = msgs
+        .iter()
+        .filter_map(|msg| match msg.command {
+            aatxe::Command::PRIVMSG(_, ref text) | aatxe::Command::NOTICE(_, ref text) => {
+                Some(&text[..])
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    let link = match msgs.first() {
+        Some(first) if text.len() > cfg.lines => match state.paste(cfg, &text.join("\n")) {
+            Ok(url) => pager::similar_msg(
+                first,
+                state.localize(origin, user, "paste: link", &[("url", &url)]),
+            ),
+            Err(e) => {
+                warn!(
+                    "Failed to upload oversized output to a paste service: {}",
+                    e
+                );
+                None
+            }
+        },
+        _ => None,
+    };
+
+    match link {
+        Some(link) => LibReaction::RawMsg(link),
+        None => LibReaction::Multi(msgs.into_iter().map(LibReaction::RawMsg).collect()),
+    }
+}
+
+/// Uploads the given text to the given URL as the value of the given field of a form, returning
+/// the URL to which the service redirects, or else the first line of its response.
+fn post_form(url: &Url, field: &str, text: &str) -> Result<String> {
+    let (status, location, body) =
+        fetch::post_form(url, &[(field, text)], &FetchLimits::default())?;
+
+    let link = match status {
+        300..=399 => location.unwrap_or_default(),
+        200..=299 => str::from_utf8(&body)
+            .unwrap_or_default()
+            .lines()
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_owned(),
+        _ => bail!(ErrorKind::FetchFailed(
+            url.to_string(),
+            format!("the server responded with status {}", status).into()
+        )),
+    };
+
+    match url.join(&link) {
+        Ok(ref link) if !link.cannot_be_a_base() && link.as_str() != url.as_str() => {
+            Ok(link.as_str().to_owned())
+        }
+        _ => bail!(ErrorKind::FetchFailed(
+            url.to_string(),
+            "the server's response gave no link to the paste".into()
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosted_pastes() {
+        let mut pastes = HostedPastes::default();
+
+        for i in 0..=MAX_HOSTED_PASTES {
+            pastes.insert(i.to_string(), format!("paste {}", i));
+        }
+
+        assert_eq!(pastes.get("0"), None);
+        assert_eq!(pastes.get("1"), Some("paste 1"));
+        assert_eq!(
+            pastes.get(&MAX_HOSTED_PASTES.to_string()),
+            Some(&format!("paste {}", MAX_HOSTED_PASTES)[..])
+        );
+    }
+}
@@ -13,6 +13,8 @@ lazy_static! {
         option_env!("IRC_BOT_RS_GIT_VERSION"),
         option_env!("CARGO_PKG_VERSION"),
     ]);
+    pub(super) static ref COMMIT_STR: &'static str =
+        choose(&[option_env!("IRC_BOT_RS_GIT_COMMIT")]);
     pub(super) static ref HOMEPAGE_STR: &'static str = choose(&[option_env!("CARGO_PKG_HOMEPAGE")]);
     pub(super) static ref BRIEF_CREDITS_STRING: String = format!(
         "Built with <{url}> {ver}",
@@ -38,6 +40,12 @@ impl State {
         &VERSION_STR
     }
 
+    /// Returns a `&str` containing either the abbreviated hash of the Git commit from which the
+    /// bot framework was built or the text `"<unknown>"`.
+    pub fn framework_commit_str(&self) -> &'static str {
+        &COMMIT_STR
+    }
+
     /// Returns a `&str` containing either a [Uniform Resource Locator (URL)][URI] for a Web page
     /// containing information about the bot framework, or the text `"<unknown>"`.
     ///
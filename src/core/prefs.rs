@@ -0,0 +1,361 @@
+//! Preferences that users set for themselves, such as the locale in which the bot replies to them,
+//! which bot modules may read and which the bot keeps in the configured `preferences file`.
+//!
+//! A user's preferences are identified by the services account as which the user is logged in,
+//! if the bot knows it (see [`State::user_account`]), or else by the username and hostname of the
+//! user's message prefix, so that they follow the user across changes of nickname.
+//!
+//! [`State::user_account`]: <../struct.State.html#method.user_account>
+
+use super::ErrorKind;
+use super::MsgPrefix;
+use super::ReplyRoute;
+use super::Result;
+use super::ServerId;
+use super::State;
+use serde_yaml;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::RwLockWriteGuard;
+use util::irc::casefold;
+
+/// The preference of the time zone in which the bot is to show times to the user, as an offset
+/// from UTC, such as `UTC+02:00`
+pub const PREF_TIMEZONE: &str = "timezone";
+
+/// The preference of the locale in which the bot is to reply to the user (see [`State::locale`])
+///
+/// [`State::locale`]: <struct.State.html#method.locale>
+pub const PREF_LOCALE: &str = "locale";
+
+/// The preference of the way in which the bot is to reply to the user's commands that it would
+/// otherwise answer in the channel in which they were used: `notice` or `private`
+pub const PREF_REPLY_STYLE: &str = "reply-style";
+
+/// The preference of whether the bot may log the user's messages to channels: `on` or `off`
+pub const PREF_LOGGING: &str = "logging";
+
+/// The names of the preferences that users may set, with descriptions of their values
+pub const USER_PREFS: &[(&str, &str)] = &[
+    (PREF_TIMEZONE, "an offset from UTC, such as `UTC+02:00`"),
+    (PREF_LOCALE, "one of the bot's locales"),
+    (PREF_REPLY_STYLE, "`notice` or `private`"),
+    (PREF_LOGGING, "`on` or `off`"),
+];
+
+/// The preferences that users have set, by the name of the server and then by the key identifying
+/// the user
+#[derive(Debug, Default)]
+pub(super) struct UserPrefs {
+    servers: BTreeMap<String, BTreeMap<String, BTreeMap<String, String>>>,
+
+    /// The file in which the preferences are stored, if one is configured
+    path: Option<PathBuf>,
+}
+
+impl UserPrefs {
+    /// Loads the preferences stored in the given file, if one is given and it exists.
+    pub(super) fn load(path: Option<PathBuf>) -> Result<Self> {
+        let servers = match path {
+            Some(ref path) => match fs::File::open(path) {
+                Ok(file) => serde_yaml::from_reader(file)?,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+                Err(e) => return Err(e.into()),
+            },
+            None => BTreeMap::new(),
+        };
+
+        Ok(UserPrefs { servers, path })
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        fs::write(path, serde_yaml::to_string(&self.servers)?)?;
+
+        Ok(())
+    }
+}
+
+impl State {
+    /// Returns the preference with the given name of the user with the given message prefix on
+    /// the given server, if the user has set it.
+    pub fn user_pref(
+        &self,
+        server_id: ServerId,
+        user: MsgPrefix,
+        name: &str,
+    ) -> Result<Option<String>> {
+        Ok(self.user_prefs(server_id, user)?.remove(name))
+    }
+
+    /// Returns all the preferences that the user with the given message prefix on the given server
+    /// has set, by name.
+    pub fn user_prefs(
+        &self,
+        server_id: ServerId,
+        user: MsgPrefix,
+    ) -> Result<BTreeMap<String, String>> {
+        let server = self.server_name(server_id)?;
+        let keys = self.user_pref_keys(server_id, user)?;
+
+        let prefs = self
+            .prefs
+            .read()
+            .map_err(|_| ErrorKind::LockPoisoned("the users' preferences".into()))?;
+
+        Ok(prefs
+            .servers
+            .get(&server)
+            .and_then(|users| keys.iter().filter_map(|key| users.get(key)).next())
+            .cloned()
+            .unwrap_or_default())
+    }
+
+    /// Sets the preference with the given name of the user with the given message prefix on the
+    /// given server to the given value, or, if `value` is `None`, unsets it, and stores the
+    /// preferences in the configured `preferences file`, if any. The preference must be one of
+    /// those listed in [`USER_PREFS`], and the value must be valid for it.
+    ///
+    /// [`USER_PREFS`]: <constant.USER_PREFS.html>
+    pub fn set_user_pref(
+        &self,
+        server_id: ServerId,
+        user: MsgPrefix,
+        name: &str,
+        value: Option<&str>,
+    ) -> Result<()> {
+        let value = match value {
+            Some(value) => Some(
+                self.validate_user_pref(name, value)
+                    .map_err(|problem| ErrorKind::InvalidUserPref(name.to_owned(), problem))?,
+            ),
+            None => None,
+        };
+
+        ensure!(
+            USER_PREFS.iter().any(|&(n, _)| n == name),
+            ErrorKind::InvalidUserPref(name.to_owned(), "is not a known preference".into())
+        );
+
+        let server = self.server_name(server_id)?;
+        let keys = self.user_pref_keys(server_id, user)?;
+        let key = match keys.first() {
+            Some(key) => key.clone(),
+            None => bail!(ErrorKind::InvalidUserPref(
+                name.to_owned(),
+                "cannot be set for a user whose username and hostname are unknown".into()
+            )),
+        };
+
+        let mut prefs = self.write_prefs()?;
+
+        let no_users = {
+            let users = prefs.servers.entry(server.clone()).or_default();
+
+            // Preferences set before the user logged in follow the user to the account.
+            let mut user_prefs = keys
+                .iter()
+                .filter_map(|key| users.remove(key))
+                .next()
+                .unwrap_or_default();
+
+            match value {
+                Some(value) => user_prefs.insert(name.to_owned(), value),
+                None => user_prefs.remove(name),
+            };
+
+            if !user_prefs.is_empty() {
+                users.insert(key, user_prefs);
+            }
+
+            users.is_empty()
+        };
+
+        if no_users {
+            prefs.servers.remove(&server);
+        }
+
+        prefs.save()
+    }
+
+    /// Returns the way in which the bot is to reply to a command that the user with the given
+    /// message prefix used, given the way that the command's configuration specifies.
+    pub(super) fn user_reply_route(
+        &self,
+        server_id: ServerId,
+        user: MsgPrefix,
+        route: ReplyRoute,
+    ) -> Result<ReplyRoute> {
+        let style = match route {
+            ReplyRoute::SameDest | ReplyRoute::PrivateIfOver(_) => {
+                self.user_pref(server_id, user, PREF_REPLY_STYLE)?
+            }
+            ReplyRoute::Notice | ReplyRoute::Private => None,
+        };
+
+        Ok(match style.as_ref().map(|s| &s[..]) {
+            Some("notice") => ReplyRoute::Notice,
+            Some("private") => ReplyRoute::Private,
+            _ => route,
+        })
+    }
+
+    /// Returns whether the user with the given message prefix on the given server has opted out of
+    /// having messages logged.
+    pub(super) fn user_opted_out_of_logging(
+        &self,
+        server_id: ServerId,
+        user: MsgPrefix,
+    ) -> Result<bool> {
+        Ok(self.user_pref(server_id, user, PREF_LOGGING)? == Some("off".into()))
+    }
+
+    /// Returns the keys by which the preferences of the user with the given message prefix may be
+    /// stored, that by which they are to be stored first.
+    fn user_pref_keys(&self, server_id: ServerId, user: MsgPrefix) -> Result<Vec<String>> {
+        let mut keys = Vec::new();
+
+        if let Some(nick) = user.nick {
+            if let Some(account) = self.user_account(server_id, nick)? {
+                keys.push(format!(
+                    "account:{}",
+                    casefold(self.casemapping(server_id)?, &account)
+                ));
+            }
+        }
+
+        if let (Some(username), Some(host)) = (user.user, user.host) {
+            keys.push(format!(
+                "host:{}@{}",
+                username.trim_start_matches('~'),
+                host.to_ascii_lowercase()
+            ));
+        }
+
+        Ok(keys)
+    }
+
+    /// Checks that the given value is valid for the preference with the given name, returning it
+    /// in its canonical form, or else describes the problem.
+    fn validate_user_pref(&self, name: &str, value: &str) -> ::std::result::Result<String, String> {
+        let value = value.trim();
+
+        match name {
+            PREF_TIMEZONE => parse_utc_offset(value)
+                .map(format_utc_offset)
+                .ok_or_else(|| "should be an offset from UTC, such as `UTC+02:00`".into()),
+            PREF_LOCALE => self
+                .locales()
+                .into_iter()
+                .find(|locale| locale.eq_ignore_ascii_case(value))
+                .ok_or_else(|| format!("should be one of {}", self.locales().join(", "))),
+            PREF_REPLY_STYLE | PREF_LOGGING => {
+                let choices: &[&str] = if name == PREF_LOGGING {
+                    &["on", "off"]
+                } else {
+                    &["notice", "private"]
+                };
+
+                choices
+                    .iter()
+                    .find(|choice| choice.eq_ignore_ascii_case(value))
+                    .map(|&choice| choice.to_owned())
+                    .ok_or_else(|| format!("should be `{}`", choices.join("` or `")))
+            }
+            _ => Ok(value.to_owned()),
+        }
+    }
+
+    fn write_prefs<'a>(&'a self) -> Result<RwLockWriteGuard<'a, UserPrefs>> {
+        Ok(self
+            .prefs
+            .write()
+            .map_err(|_| ErrorKind::LockPoisoned("the users' preferences".into()))?)
+    }
+}
+
+/// Parses an offset from UTC of the form `UTC`, `UTC+H`, `UTC-HH:MM`, or `+HHMM`, returning it in
+/// minutes.
+pub fn parse_utc_offset(s: &str) -> Option<i32> {
+    let s = if s
+        .get(..3)
+        .filter(|p| p.eq_ignore_ascii_case("UTC"))
+        .is_some()
+    {
+        &s[3..]
+    } else {
+        s
+    };
+
+    if s.is_empty() {
+        return Some(0);
+    }
+
+    let sign = match s.as_bytes()[0] {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+
+    let digits = s[1..].replace(':', "");
+
+    if digits.is_empty() || digits.len() > 4 || !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+
+    let (hours, minutes) = if digits.len() <= 2 {
+        (digits.parse::<i32>().ok()?, 0)
+    } else {
+        let split = digits.len() - 2;
+        (
+            digits[..split].parse::<i32>().ok()?,
+            digits[split..].parse::<i32>().ok()?,
+        )
+    };
+
+    if hours > 14 || minutes >= 60 {
+        return None;
+    }
+
+    Some(sign * (hours * 60 + minutes))
+}
+
+fn format_utc_offset(minutes: i32) -> String {
+    if minutes == 0 {
+        return "UTC".into();
+    }
+
+    format!(
+        "UTC{}{:02}:{:02}",
+        if minutes < 0 { '-' } else { '+' },
+        minutes.abs() / 60,
+        minutes.abs() % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn utc_offsets() {
+        assert_eq!(parse_utc_offset("UTC"), Some(0));
+        assert_eq!(parse_utc_offset("utc+2"), Some(120));
+        assert_eq!(parse_utc_offset("UTC-03:30"), Some(-210));
+        assert_eq!(parse_utc_offset("+0545"), Some(345));
+        assert_eq!(parse_utc_offset("UTC+15"), None);
+        assert_eq!(parse_utc_offset("UTC+1:75"), None);
+        assert_eq!(parse_utc_offset("Europe/Berlin"), None);
+        assert_eq!(parse_utc_offset("+"), None);
+
+        assert_eq!(format_utc_offset(0), "UTC");
+        assert_eq!(format_utc_offset(-210), "UTC-03:30");
+        assert_eq!(format_utc_offset(345), "UTC+05:45");
+    }
+}
@@ -0,0 +1,326 @@
+use super::irc_send::push_to_outbox;
+use super::ErrorContext;
+use super::LibReaction;
+use super::PresenceHandler;
+use super::Result;
+use super::ServerId;
+use super::State;
+use irc::client::prelude as aatxe;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use util;
+use util::irc::casemapped_str_cmp;
+use util::irc::CaseMapping;
+
+/// How often to ask the server, with `ISON`, whether monitored users are online, on servers that
+/// don't support `MONITOR`
+const ISON_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How many nicknames to list in each `MONITOR` or `ISON` message sent, so as to stay well within
+/// the limit on the length of an IRC message
+const NICKS_PER_MSG: usize = 10;
+
+/// Whether a monitored user is online, as passed to handlers given to [`State::monitor`]
+///
+/// [`State::monitor`]: <struct.State.html#method.monitor>
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Presence {
+    Online,
+    Offline,
+}
+
+/// The users whose presence on a server is being monitored
+#[derive(Debug, Default)]
+pub(super) struct PresenceWatches {
+    watches: Vec<Watch>,
+
+    /// The lists of nicknames about which `ISON` queries have been sent and not yet answered, in
+    /// the order in which they were sent
+    pending_ison_queries: VecDeque<Vec<String>>,
+}
+
+#[derive(CustomDebug)]
+struct Watch {
+    nick: String,
+
+    /// The user's presence, if the server has reported it since the bot connected
+    presence: Option<Presence>,
+
+    #[debug(skip)]
+    handlers: Vec<Arc<PresenceHandler>>,
+}
+
+impl PresenceWatches {
+    fn position(&self, casemapping: CaseMapping, nick: &str) -> Option<usize> {
+        self.watches.iter().position(|watch| {
+            casemapped_str_cmp(casemapping, &watch.nick[..], nick) == Ordering::Equal
+        })
+    }
+
+    fn nicks(&self) -> Vec<String> {
+        self.watches
+            .iter()
+            .map(|watch| watch.nick.clone())
+            .collect()
+    }
+}
+
+impl State {
+    /// Calls the given handler whenever the user with the given nickname on the given server
+    /// comes online or goes offline, as well as once the user's presence is first known.
+    ///
+    /// This uses the IRCv3 `MONITOR` command if the server supports it, as it advertises with the
+    /// `RPL_ISUPPORT` parameter `MONITOR`, in which case handlers are called as soon as the server
+    /// announces a change. Otherwise, the bot polls the server with `ISON` every minute.
+    ///
+    /// Handlers are called while the bot is handling the message from the server that announced
+    /// the change; they therefore should return promptly. Monitoring continues across reconnections
+    /// until [`State::unmonitor`] is called.
+    ///
+    /// [`State::unmonitor`]: <struct.State.html#method.unmonitor>
+    pub fn monitor(
+        &self,
+        server_id: ServerId,
+        nick: &str,
+        handler: Box<PresenceHandler>,
+    ) -> Result<()> {
+        let (newly_monitored, subscribe_now) = {
+            let mut server = self.write_server(server_id)?;
+            let casemapping = server.capabilities.casemapping;
+            let subscribe_now = server.motd_finished && supports_monitor(&server.capabilities);
+
+            match server.presence_watches.position(casemapping, nick) {
+                Some(i) => {
+                    server.presence_watches.watches[i]
+                        .handlers
+                        .push(handler.into());
+                    (false, false)
+                }
+                None => {
+                    server.presence_watches.watches.push(Watch {
+                        nick: nick.to_owned(),
+                        presence: None,
+                        handlers: vec![handler.into()],
+                    });
+                    (true, subscribe_now)
+                }
+            }
+        };
+
+        if newly_monitored && subscribe_now {
+            self.send_monitor_cmd(server_id, "+", &[nick.to_owned()]);
+        }
+
+        Ok(())
+    }
+
+    /// Stops monitoring the presence of the user with the given nickname on the given server,
+    /// removing all handlers given for that user to [`State::monitor`].
+    ///
+    /// [`State::monitor`]: <struct.State.html#method.monitor>
+    pub fn unmonitor(&self, server_id: ServerId, nick: &str) -> Result<()> {
+        let unsubscribe_now = {
+            let mut server = self.write_server(server_id)?;
+            let casemapping = server.capabilities.casemapping;
+            let unsubscribe_now = server.motd_finished && supports_monitor(&server.capabilities);
+
+            match server.presence_watches.position(casemapping, nick) {
+                Some(i) => {
+                    server.presence_watches.watches.remove(i);
+                    unsubscribe_now
+                }
+                None => false,
+            }
+        };
+
+        if unsubscribe_now {
+            self.send_monitor_cmd(server_id, "-", &[nick.to_owned()]);
+        }
+
+        Ok(())
+    }
+
+    fn send_monitor_cmd(&self, server_id: ServerId, subcmd: &str, nicks: &[String]) {
+        for chunk in nicks.chunks(NICKS_PER_MSG) {
+            push_to_outbox(
+                &self.outbox,
+                server_id,
+                LibReaction::RawMsg(
+                    aatxe::Command::MONITOR(subcmd.to_owned(), Some(chunk.join(","))).into(),
+                ),
+            );
+        }
+    }
+}
+
+fn supports_monitor(caps: &super::ServerCapabilities) -> bool {
+    caps.params.contains_key("MONITOR")
+}
+
+/// Subscribes anew to the presence of all monitored users, upon having connected to the given
+/// server.
+pub(super) fn resubscribe(state: &State, server_id: ServerId) -> Result<()> {
+    let nicks = {
+        let mut server = state.write_server(server_id)?;
+        let use_monitor = supports_monitor(&server.capabilities);
+        let watches = &mut server.presence_watches;
+
+        for watch in &mut watches.watches {
+            watch.presence = None;
+        }
+
+        watches.pending_ison_queries.clear();
+
+        if !use_monitor {
+            return Ok(());
+        }
+
+        watches.nicks()
+    };
+
+    state.send_monitor_cmd(server_id, "+", &nicks);
+
+    Ok(())
+}
+
+/// Periodically asks the given server, with `ISON`, whether monitored users are online, if the
+/// server doesn't support `MONITOR`, until the connection with the given generation number has
+/// been replaced by a new one.
+pub(super) fn ison_poller_main(
+    state: Arc<State>,
+    server_id: ServerId,
+    generation: u64,
+) -> Result<()> {
+    loop {
+        thread::sleep(ISON_POLL_INTERVAL);
+
+        let queries = {
+            let mut server = state.write_server(server_id)?;
+
+            if server.connection_generation != generation {
+                return Ok(());
+            }
+
+            if !server.motd_finished || supports_monitor(&server.capabilities) {
+                continue;
+            }
+
+            let watches = &mut server.presence_watches;
+
+            let queries = watches
+                .nicks()
+                .chunks(NICKS_PER_MSG)
+                .map(|chunk| chunk.to_vec())
+                .collect::<Vec<_>>();
+
+            watches.pending_ison_queries.extend(queries.iter().cloned());
+
+            queries
+        };
+
+        for nicks in queries {
+            push_to_outbox(
+                &state.outbox,
+                server_id,
+                LibReaction::RawMsg(aatxe::Command::ISON(nicks).into()),
+            );
+        }
+    }
+}
+
+/// Handles a `RPL_MONONLINE` or `RPL_MONOFFLINE` message, which lists users, by nickname or full
+/// `nick!user@host` prefix, whose presence has changed.
+pub(super) fn handle_monitor_reply(
+    state: &State,
+    server_id: ServerId,
+    targets: &str,
+    presence: Presence,
+) -> Result<()> {
+    for target in targets.split(',').filter(|s| !s.is_empty()) {
+        let nick = target.split('!').next().unwrap_or(target);
+
+        update_presence(state, server_id, nick, presence)?;
+    }
+
+    Ok(())
+}
+
+/// Handles a `RPL_ISON` message, which lists which of the nicknames in the oldest unanswered
+/// `ISON` query are online.
+pub(super) fn handle_ison_reply(state: &State, server_id: ServerId, online: &str) -> Result<()> {
+    let (casemapping, queried) = {
+        let mut server = state.write_server(server_id)?;
+        let casemapping = server.capabilities.casemapping;
+
+        match server.presence_watches.pending_ison_queries.pop_front() {
+            Some(queried) => (casemapping, queried),
+            None => return Ok(()),
+        }
+    };
+
+    for nick in &queried {
+        let presence = if online
+            .split_whitespace()
+            .any(|s| casemapped_str_cmp(casemapping, s, &nick[..]) == Ordering::Equal)
+        {
+            Presence::Online
+        } else {
+            Presence::Offline
+        };
+
+        update_presence(state, server_id, nick, presence)?;
+    }
+
+    Ok(())
+}
+
+fn update_presence(
+    state: &State,
+    server_id: ServerId,
+    nick: &str,
+    presence: Presence,
+) -> Result<()> {
+    let handlers = {
+        let mut server = state.write_server(server_id)?;
+        let casemapping = server.capabilities.casemapping;
+
+        let watch = match server.presence_watches.position(casemapping, nick) {
+            Some(i) => &mut server.presence_watches.watches[i],
+            None => return Ok(()),
+        };
+
+        if watch.presence == Some(presence) {
+            return Ok(());
+        }
+
+        watch.presence = Some(presence);
+        watch.handlers.clone()
+    };
+
+    for handler in handlers {
+        let err = match util::run_handler("presence handler", nick.to_owned(), || {
+            handler.run(state, server_id, nick, presence)
+        }) {
+            Ok(Ok(())) => continue,
+            Ok(Err(e)) | Err(e) => e,
+        };
+
+        let err = err.with_context(ErrorContext {
+            server: Some(state.server_socket_addr_dbg_string(server_id)),
+            ..Default::default()
+        });
+
+        let reaction = state.handle_err(
+            Some(server_id),
+            err,
+            format!("in presence handler for {:?}", nick),
+        );
+
+        push_to_outbox(&state.outbox, server_id, reaction);
+    }
+
+    Ok(())
+}
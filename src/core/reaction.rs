@@ -1,5 +1,8 @@
+use super::MsgTags;
+use super::ServerId;
 use std::borrow::Cow;
 use std::fmt;
+use std::path::PathBuf;
 
 #[derive(Debug)]
 pub enum Reaction {
@@ -9,13 +12,66 @@ pub enum Reaction {
     Reply(Cow<'static, str>),
     Replies(Cow<'static, [Cow<'static, str>]>),
     RawMsg(Cow<'static, str>),
+
+    /// React by performing the given action (as with the IRC client command `/me`), in the same
+    /// channel or one-to-one conversation as the message being reacted to.
+    Action(Cow<'static, str>),
+
+    /// React by offering a DCC CHAT session to the user who sent the message being reacted to.
+    /// Lines that the user sends in the session will be handled as if the user had sent them to
+    /// the bot in one-to-one messaging. This requires that the `DCC` configuration setting
+    /// `address` be set.
+    OfferDccChat,
+
+    /// React by offering the file at the given path, by DCC SEND, to the user who sent the message
+    /// being reacted to. The file must be within one of the directories listed in the `DCC`
+    /// configuration setting `send directories`, and no larger than is allowed by the setting
+    /// `max send size`.
+    OfferFile(PathBuf),
+
+    /// React by closing the bot's connection to the given server (see [`State::disconnect`]).
+    ///
+    /// [`State::disconnect`]: <struct.State.html#method.disconnect>
+    Disconnect(ServerId),
+
+    /// React by closing the bot's connection to the given server and then connecting to the
+    /// server anew (see [`State::reconnect`]).
+    ///
+    /// [`State::reconnect`]: <struct.State.html#method.reconnect>
+    Reconnect(ServerId),
+
     Quit(Option<Cow<'static, str>>),
+
+    /// React by sending the next page of the output of commands held for the user who sent the
+    /// message being reacted to, per the configuration field `page length`, or a note that no such
+    /// output is held.
+    NextPage,
+
+    /// React as with the given reaction, attaching the given IRCv3 client-only message tags, such
+    /// as `+draft/reply`, to the messages that it sends. The names of client-only tags begin with
+    /// `+`; no other tags may be attached. The tags are omitted if the server has not enabled the
+    /// `message-tags` capability.
+    WithTags(Box<Reaction>, MsgTags),
 }
 
 #[derive(Debug)]
 pub enum ErrorReaction {
     Proceed,
     Quit(Option<Cow<'static, str>>),
+
+    /// React by closing the bot's connection to the server in connection with which the error
+    /// occurred, with the given quit message, and then connecting to the server anew (see
+    /// [`State::reconnect`]). An error that did not occur in connection with a particular server
+    /// is proceeded past.
+    ///
+    /// [`State::reconnect`]: <struct.State.html#method.reconnect>
+    Reconnect(Option<Cow<'static, str>>),
+
+    /// React by sending the given text by private message to each of the bot's administrators
+    /// for whom a nickname is configured, on the server in connection with which the error
+    /// occurred or, if it did not occur in connection with a particular server, on every server,
+    /// and then proceeding.
+    NotifyAdmins(Cow<'static, str>),
 }
 
 /// Copied from `yak-irc`'s `Reaction`.
@@ -0,0 +1,267 @@
+//! Recording of the bot's sessions with servers, as configured with the field `session recording
+//! file`, and offline replay of recorded sessions.
+//!
+//! A recording has a line for each line that the bot received from a server or sent to one,
+//! other than the lines with which it registered its connection, made of four fields separated by
+//! tabs: the time, in the format of the IRCv3 `server-time` tag; the name of the server, from the
+//! configuration; `in` or `out`; and the line itself. The function [`replay`] feeds the lines
+//! that the bot received back through its message handling, without connecting to any server, so
+//! that the bot's response to them may be reproduced.
+//!
+//! [`replay`]: <../fn.replay.html>
+
+use super::irc_msgs::format_server_time;
+use super::offline::OfflineBot;
+use super::ErrorHandler;
+use super::IntoConfig;
+use super::LibReaction;
+use super::Module;
+use super::Result;
+use super::ServerId;
+use super::State;
+use irc::proto::Message;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufRead;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// Whether a recorded line was received or sent by the bot
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(super) enum Direction {
+    In,
+    Out,
+}
+
+impl Direction {
+    fn as_str(self) -> &'static str {
+        match self {
+            Direction::In => "in",
+            Direction::Out => "out",
+        }
+    }
+}
+
+/// The file to which the bot's session is being recorded, if any
+#[derive(Debug, Default)]
+pub(super) struct SessionRecorder {
+    file: Option<File>,
+}
+
+impl SessionRecorder {
+    /// Opens the given file, if one is given, to append a recording to it.
+    pub(super) fn open(path: Option<&Path>) -> Result<Self> {
+        let file = match path {
+            Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+            None => None,
+        };
+
+        Ok(SessionRecorder { file })
+    }
+}
+
+/// A line of a recording
+#[derive(Debug, Eq, PartialEq)]
+struct Record<'a> {
+    time: &'a str,
+    server: &'a str,
+    direction: Direction,
+    line: &'a str,
+}
+
+impl<'a> Record<'a> {
+    fn parse(s: &'a str) -> Option<Self> {
+        let mut fields = s.trim_end_matches(&['\r', '\n'][..]).splitn(4, '\t');
+
+        let time = fields.next()?;
+        let server = fields.next()?;
+        let direction = match fields.next()? {
+            "in" => Direction::In,
+            "out" => Direction::Out,
+            _ => return None,
+        };
+        let line = fields.next()?;
+
+        Some(Record {
+            time,
+            server,
+            direction,
+            line,
+        })
+    }
+
+    fn to_line(&self) -> String {
+        format!(
+            "{}\t{}\t{}\t{}\n",
+            self.time,
+            self.server,
+            self.direction.as_str(),
+            self.line
+        )
+    }
+}
+
+impl State {
+    /// Appends the given message, received from or sent to the given server, to the recording of
+    /// the bot's session, if one is being made.
+    pub(super) fn record_msg(&self, server_id: ServerId, direction: Direction, msg: &Message) {
+        if let Err(e) = self.try_record_msg(server_id, direction, msg) {
+            error!("Failed to record message {:?}: {}", msg.to_string(), e);
+        }
+    }
+
+    fn try_record_msg(
+        &self,
+        server_id: ServerId,
+        direction: Direction,
+        msg: &Message,
+    ) -> Result<()> {
+        let mut recorder = match self.session_recorder.lock() {
+            Ok(recorder) => recorder,
+            Err(_) => return Ok(()),
+        };
+
+        let file = match recorder.file {
+            Some(ref mut file) => file,
+            None => return Ok(()),
+        };
+
+        let line = msg.to_string();
+        let record = Record {
+            time: &format_server_time(SystemTime::now()),
+            server: &self.get_server_config(server_id)?.name,
+            direction,
+            line: line.trim_end_matches(&['\r', '\n'][..]),
+        };
+
+        file.write_all(record.to_line().as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Appends the messages of the given reaction, sent to the given server, to the recording of
+    /// the bot's session, if one is being made.
+    pub(super) fn record_reaction(&self, server_id: ServerId, reaction: &LibReaction<Message>) {
+        match *reaction {
+            LibReaction::RawMsg(ref msg) => self.record_msg(server_id, Direction::Out, msg),
+            LibReaction::Multi(ref reactions) => {
+                for reaction in reactions {
+                    self.record_reaction(server_id, reaction)
+                }
+            }
+        }
+    }
+}
+
+/// Replays the lines that a bot received in the given recording of its session (see the
+/// configuration field `session recording file`), handling each as the function [`run`] would
+/// have, but without connecting to any server.
+///
+/// The arguments are those of [`run`], with the recording, which may be read from a file or from
+/// standard input. The bot's configuration should name the servers as they were named when the
+/// session was recorded; lines from servers by other names are skipped. Rather than being sent,
+/// the messages with which the bot responds are logged, at the level `info`. The bot's
+/// connection handlers run as the recorded server messages trigger them, and once the recording
+/// ends, the bot runs its modules' unload handlers and returns.
+///
+/// [`run`]: <fn.run.html>
+pub fn replay<Cfg, ModlData, ErrF, ModlCtor, Modls, R>(
+    config: Cfg,
+    module_data_path: ModlData,
+    error_handler: ErrF,
+    modules: Modls,
+    recording: R,
+) where
+    Cfg: IntoConfig,
+    ModlData: Into<PathBuf>,
+    ErrF: ErrorHandler,
+    Modls: IntoIterator<Item = ModlCtor>,
+    ModlCtor: Fn() -> Module,
+    R: BufRead,
+{
+    let bot = match OfflineBot::new(
+        config,
+        module_data_path.into(),
+        error_handler,
+        modules,
+        None,
+    ) {
+        Some(bot) => bot,
+        None => return,
+    };
+
+    for (i, line) in recording.lines().enumerate() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                error!("Failed to read line {} of the recording: {}", i + 1, e);
+                break;
+            }
+        };
+
+        let record = match Record::parse(&line) {
+            Some(record) => record,
+            None => {
+                warn!(
+                    "Skipping malformed line {} of the recording: {:?}",
+                    i + 1,
+                    line
+                );
+                continue;
+            }
+        };
+
+        if record.direction != Direction::In {
+            continue;
+        }
+
+        let server_id = match bot.state().server_named(record.server) {
+            Some(server_id) => server_id,
+            None => {
+                warn!(
+                    "Skipping line {} of the recording, from unknown server {:?}.",
+                    i + 1,
+                    record.server
+                );
+                continue;
+            }
+        };
+
+        debug!(
+            "Replaying line {} of the recording: {:?}",
+            i + 1,
+            record.line
+        );
+
+        bot.handle(
+            server_id,
+            record.line.parse::<Message>().map_err(Into::into),
+        );
+    }
+
+    bot.finish();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let record = Record {
+            time: "2019-01-01T00:00:00.000Z",
+            server: "example",
+            direction: Direction::In,
+            line: ":alice!a@example.com PRIVMSG #chan :tab\there",
+        };
+
+        assert_eq!(Record::parse(&record.to_line()), Some(record));
+        assert_eq!(
+            Record::parse("2019-01-01T00:00:00.000Z\texample\tsideways\tPING :x"),
+            None
+        );
+        assert_eq!(Record::parse("2019-01-01T00:00:00.000Z\texample"), None);
+    }
+}
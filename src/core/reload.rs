@@ -0,0 +1,305 @@
+use super::config::Channel;
+use super::config::Config;
+use super::irc_send::push_to_outbox;
+use super::ErrorKind;
+use super::LibReaction;
+use super::Result;
+use super::ServerId;
+use super::State;
+use irc::client::prelude as aatxe;
+use std::sync::Arc;
+
+/// The quit message with which the bot disconnects from a server to reconnect to it with new
+/// connection settings
+const RECONFIGURE_QUIT_MSG: &str = "Reconnecting to apply new settings";
+
+impl State {
+    /// Reloads the bot's configuration from the file from which it was read, which is possible only
+    /// if it was read with [`Config::try_from_path`]. The bot reloads its configuration in this way
+    /// upon receiving the signal `SIGHUP`, too.
+    ///
+    /// Settings that the bot consults as it runs, such as `admins` and per-channel settings, take
+    /// effect immediately. The bot joins the channels newly listed for each server and parts those
+    /// no longer listed, and reconnects to each server whose connection settings have changed,
+    /// such as its hostname or the bot's nickname. The list of servers, the `HTTP` address, the
+    /// `control socket`, the `console`, the `STS policy file`, and the `outbox` settings cannot be
+    /// changed without restarting the bot; changes to them are ignored, with a warning.
+    ///
+    /// [`Config::try_from_path`]: <struct.Config.html#method.try_from_path>
+    pub fn reload_config(&self) -> Result<()> {
+        let old = self.config();
+        let new = old.reread()?;
+
+        ensure!(
+            new.servers.len() == old.servers.len(),
+            ErrorKind::Config(
+                "servers".into(),
+                "cannot be added or removed without restarting the bot".into()
+            )
+        );
+
+        for &(changed, setting) in &[
+            (new.http.address != old.http.address, "HTTP: address"),
+            (new.control_socket != old.control_socket, "control socket"),
+            (new.console != old.console, "console"),
+            (new.aliases_file != old.aliases_file, "aliases file"),
+            (new.prefs_file != old.prefs_file, "preferences file"),
+            (
+                new.sts_policy_file != old.sts_policy_file,
+                "STS policy file",
+            ),
+            (
+                new.outbox.capacity != old.outbox.capacity
+                    || new.outbox.overflow_policy != old.outbox.overflow_policy,
+                "outbox",
+            ),
+        ] {
+            if changed {
+                warn!(
+                    "The setting {:?} has changed, but will take effect only when the bot is \
+                     restarted.",
+                    setting
+                );
+            }
+        }
+
+        let new = Arc::new(new);
+
+        *self
+            .config
+            .write()
+            .map_err(|_| ErrorKind::LockPoisoned("the configuration".into()))? = new.clone();
+
+        for &server_id in self.servers.keys() {
+            if let Err(e) = self.apply_server_config(server_id, &old, &new) {
+                error!(
+                    "Failed to apply new configuration to server {}: {}",
+                    self.server_socket_addr_dbg_string(server_id),
+                    e
+                );
+            }
+        }
+
+        info!("Reloaded configuration.");
+
+        Ok(())
+    }
+
+    /// Reconnects to the given server if its connection settings have changed from the old
+    /// configuration to the new, or else joins and parts channels as the new configuration
+    /// requires.
+    fn apply_server_config(&self, server_id: ServerId, old: &Config, new: &Config) -> Result<()> {
+        let idx = usize::from(server_id.config_idx.0);
+
+        let new_aatxe_config = new
+            .aatxe_configs
+            .iter()
+            .find(|(i, _)| *i == server_id.config_idx)
+            .map(|(_, cfg)| cfg.clone())
+            .ok_or(ErrorKind::UnknownServer(server_id))?;
+
+        let registered = {
+            let mut server = self.write_server(server_id)?;
+
+            if *server.aatxe_config != *new_aatxe_config {
+                server.aatxe_config = new_aatxe_config;
+                drop(server);
+
+                info!(
+                    "Connection settings for server {} have changed; reconnecting.",
+                    self.server_socket_addr_dbg_string(server_id)
+                );
+
+                return self.reconnect(server_id, Some(RECONFIGURE_QUIT_MSG.into()));
+            }
+
+            server.motd_finished
+        };
+
+        if !registered {
+            // The channels in the new configuration will be joined upon registration.
+            return Ok(());
+        }
+
+        let (joins, parts) = self.channel_changes(
+            server_id,
+            &old.servers[idx].channels,
+            &new.servers[idx].channels,
+        )?;
+
+        for chan in joins {
+            push_to_outbox(
+                &self.outbox,
+                server_id,
+                LibReaction::RawMsg(aatxe::Command::JOIN(chan.to_owned(), None, None).into()),
+            );
+        }
+
+        for chan in parts {
+            push_to_outbox(
+                &self.outbox,
+                server_id,
+                LibReaction::RawMsg(aatxe::Command::PART(chan.to_owned(), None).into()),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns the names of the channels that are listed in `new` but not in `old`, which the bot
+    /// is to join, and of those that are listed in `old` but not in `new`, which it is to part,
+    /// comparing names according to the given server's case-mapping rules.
+    fn channel_changes<'a>(
+        &self,
+        server_id: ServerId,
+        old: &'a [Channel],
+        new: &'a [Channel],
+    ) -> Result<(Vec<&'a str>, Vec<&'a str>)> {
+        let mut joins = Vec::new();
+        let mut parts = Vec::new();
+
+        for chan in new {
+            if !self.lists_channel(server_id, old, &chan.name)? {
+                joins.push(&chan.name[..]);
+            }
+        }
+
+        for chan in old {
+            if !self.lists_channel(server_id, new, &chan.name)? {
+                parts.push(&chan.name[..]);
+            }
+        }
+
+        Ok((joins, parts))
+    }
+
+    fn lists_channel(&self, server_id: ServerId, chans: &[Channel], name: &str) -> Result<bool> {
+        for chan in chans {
+            if self.nicks_eq(server_id, &chan.name, name)? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::offline;
+    use core::offline::OfflineBot;
+    use core::IntoConfig;
+    use core::Module;
+    use std::env;
+    use std::fs;
+    use std::path::Path;
+    use std::process;
+
+    /// Returns a configuration listing the server `simulated`, with the given hostname and
+    /// channels, followed by the servers of the given names.
+    fn config_text(host: &str, channels: &[&str], others: &[&str]) -> String {
+        let mut servers = format!(
+            "servers:\n  \
+             - name: simulated\n    \
+             host: {}\n    \
+             port: 6697\n    \
+             channels:\n",
+            host
+        );
+
+        for chan in channels {
+            servers.push_str(&format!("      - name: '{}'\n", chan));
+        }
+
+        for name in others {
+            servers.push_str(&format!(
+                "  - {{name: {0}, host: irc.{0}.invalid, port: 6697}}\n",
+                name
+            ));
+        }
+
+        offline::test_config(&servers)
+    }
+
+    fn offline_bot(path: &Path) -> OfflineBot {
+        OfflineBot::for_test_config(Config::try_from_path(path), Vec::<fn() -> Module>::new())
+    }
+
+    #[test]
+    fn channel_changes() {
+        let bot = OfflineBot::for_test("", Vec::<fn() -> Module>::new());
+        let state = bot.state();
+        let server_id = state.server_ids()[0];
+
+        let chans = |names: &[&str]| {
+            let mut cfg = config_text("irc.invalid", names, &[])
+                .into_config()
+                .unwrap();
+            cfg.servers.remove(0).channels
+        };
+
+        let (old, new) = (chans(&["#a", "#b"]), chans(&["#B", "#c"]));
+        let (joins, parts) = state.channel_changes(server_id, &old, &new).unwrap();
+        assert_eq!(joins, ["#c"]);
+        assert_eq!(parts, ["#a"]);
+
+        let (joins, parts) = state.channel_changes(server_id, &old, &old).unwrap();
+        assert!(joins.is_empty() && parts.is_empty());
+    }
+
+    #[test]
+    fn reload() {
+        let path = env::temp_dir().join(format!("irc-bot-test-reload-{}", process::id()));
+        fs::write(&path, config_text("irc.invalid", &["#a", "#b"], &["other"])).unwrap();
+        let bot = offline_bot(&path);
+        let state = bot.state();
+        let server_id = state.server_ids()[0];
+
+        // Changing the channels alone calls for no reconnection.
+        fs::write(&path, config_text("irc.invalid", &["#b", "#c"], &["other"])).unwrap();
+        state.reload_config().unwrap();
+        assert_eq!(&state.config().servers[0].channels[1].name[..], "#c");
+        assert!(!state.read_server(server_id).unwrap().reconnect_requested);
+
+        // Servers can be neither added nor removed.
+        let rejected_for_servers = |result: Result<()>| match result {
+            Err(e) => match *e.kind() {
+                ErrorKind::Config(ref key, ref problem) => {
+                    key == "servers" && problem.contains("added or removed")
+                }
+                _ => false,
+            },
+            Ok(()) => false,
+        };
+
+        fs::write(
+            &path,
+            config_text("irc.invalid", &["#b", "#c"], &["other", "another"]),
+        )
+        .unwrap();
+        assert!(rejected_for_servers(state.reload_config()));
+
+        fs::write(&path, config_text("irc.invalid", &["#b", "#c"], &[])).unwrap();
+        assert!(rejected_for_servers(state.reload_config()));
+        assert_eq!(state.config().servers.len(), 2);
+
+        // Changing a server's connection settings calls for reconnection.
+        fs::write(
+            &path,
+            config_text("irc.example.org", &["#b", "#c"], &["other"]),
+        )
+        .unwrap();
+        state.reload_config().unwrap();
+        assert_eq!(state.config().servers[0].host, "irc.example.org");
+        assert!(state.read_server(server_id).unwrap().reconnect_requested);
+        assert!(
+            !state
+                .read_server(state.server_ids()[1])
+                .unwrap()
+                .reconnect_requested
+        );
+
+        fs::remove_file(&path).unwrap();
+    }
+}
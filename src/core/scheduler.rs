@@ -0,0 +1,27 @@
+//! The thread on which modules' periodic handlers (see [`ModuleBuilder::periodic`]) are run.
+//!
+//! [`ModuleBuilder::periodic`]: <../struct.ModuleBuilder.html#method.periodic>
+
+use super::Result;
+use super::State;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How often to check whether any periodic handler is due to be run, or the bot is shutting down
+const TICK: Duration = Duration::from_secs(1);
+
+pub(super) fn scheduler_main(state: Arc<State>) -> Result<()> {
+    let start = Instant::now();
+    let mut last_runs = BTreeMap::new();
+
+    while !state.is_shutting_down() {
+        thread::sleep(TICK);
+
+        state.run_due_periodic_handlers(start, &mut last_runs);
+    }
+
+    Ok(())
+}
@@ -0,0 +1,207 @@
+//! Shadow bans, with which the bot's administrators may quietly stop the bot from answering users
+//! who abuse it, without the confrontation of an open ban.
+//!
+//! A shadow-banned user's messages are still handled as usual, so that the user's commands are
+//! parsed and run, but the replies that the bot would send in answer to them are discarded, or, if
+//! the configuration names a `review channel`, sent there instead, marked with the user and the
+//! channel in which they would have been sent. Users are matched by masks of the form
+//! `nick!user@host`, in which `*` matches any sequence of characters and `?` any one character, and
+//! in which the nickname is compared according to the server's case-mapping rules.
+
+use super::irc_msgs::OwningMsgPrefix;
+use super::pager;
+use super::LibReaction;
+use super::Result;
+use super::ServerId;
+use super::State;
+use irc::client::prelude as aatxe;
+use irc::proto::Message;
+use std::collections::BTreeMap;
+use util::irc::casefold;
+use util::irc::CaseMapping;
+
+/// The masks that the bot's administrators have shadow-banned or unbanned on a server while the
+/// bot runs, by their normalized forms, overriding the configured `shadow bans`
+#[derive(Debug, Default)]
+pub(super) struct ShadowBanToggles {
+    masks: BTreeMap<String, bool>,
+}
+
+impl State {
+    /// Shadow-bans, on the given server, the users matching the given mask, such as
+    /// `*!*@example.com`, or, if `banned` is `false`, lifts such a ban, overriding the configured
+    /// `shadow bans` until the bot is restarted. A mask without `@` is taken as a nickname, and
+    /// one with `@` but without `!` as a username and hostname.
+    pub fn set_shadow_banned(&self, server_id: ServerId, mask: &str, banned: bool) -> Result<()> {
+        let mut server = self.write_server(server_id)?;
+        let mask = normalize_mask(server.capabilities.casemapping, mask);
+
+        server.shadow_bans.masks.insert(mask, banned);
+
+        Ok(())
+    }
+
+    /// Returns the masks, in their normalized forms, of the users who are shadow-banned on the
+    /// given server, whether by the configuration or by the bot's administrators.
+    pub fn shadow_bans(&self, server_id: ServerId) -> Result<Vec<String>> {
+        let server = self.read_server(server_id)?;
+        let casemapping = server.capabilities.casemapping;
+
+        let mut masks = self
+            .config()
+            .shadow_bans
+            .masks
+            .iter()
+            .map(|mask| normalize_mask(casemapping, mask))
+            .filter(|mask| server.shadow_bans.masks.get(mask) != Some(&false))
+            .collect::<Vec<_>>();
+
+        masks.extend(
+            server
+                .shadow_bans
+                .masks
+                .iter()
+                .filter(|&(_, &banned)| banned)
+                .map(|(mask, _)| mask.clone()),
+        );
+
+        masks.sort();
+        masks.dedup();
+
+        Ok(masks)
+    }
+
+    /// Returns whether the user with the given message prefix is shadow-banned on the given
+    /// server.
+    pub(super) fn is_shadow_banned(&self, server_id: ServerId, prefix: &str) -> Result<bool> {
+        let casemapping = self.casemapping(server_id)?;
+        let prefix = casefold(casemapping, prefix);
+
+        Ok(self
+            .shadow_bans(server_id)?
+            .iter()
+            .any(|mask| mask_matches(mask, &prefix)))
+    }
+}
+
+/// Returns the given reaction to a message that the user with the given message prefix sent to
+/// the given target, unless the user is shadow-banned, in which case its `PRIVMSG`s and `NOTICE`s
+/// are redirected to the configured `review channel`, if any, and the rest is discarded.
+pub(super) fn divert(
+    state: &State,
+    server_id: ServerId,
+    invoker: &OwningMsgPrefix,
+    target: &str,
+    reaction: Option<LibReaction<Message>>,
+) -> Option<LibReaction<Message>> {
+    let reaction = reaction?;
+
+    match state.is_shadow_banned(server_id, invoker.as_str()) {
+        Ok(false) => return Some(reaction),
+        Ok(true) => {}
+        Err(e) => {
+            warn!("Failed to check whether a user is shadow-banned: {}", e);
+            return Some(reaction);
+        }
+    }
+
+    let channel = state.config().shadow_bans.review_channel.clone()?;
+
+    let mut reviewed = Vec::new();
+    pager::flatten(reaction, &mut reviewed);
+
+    let reviewed = reviewed
+        .into_iter()
+        .filter_map(|msg| match msg.command {
+            aatxe::Command::PRIVMSG(_, text) | aatxe::Command::NOTICE(_, text) => {
+                Some(LibReaction::RawMsg(
+                    aatxe::Command::PRIVMSG(
+                        channel.clone(),
+                        format!(
+                            "[shadow ban] {} in {}: {}",
+                            invoker.parse().nick.unwrap_or("*"),
+                            target,
+                            text
+                        ),
+                    )
+                    .into(),
+                ))
+            }
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    if reviewed.is_empty() {
+        None
+    } else {
+        Some(LibReaction::Multi(reviewed))
+    }
+}
+
+/// Completes the given mask to the form `nick!user@host` and folds its case.
+fn normalize_mask(casemapping: CaseMapping, mask: &str) -> String {
+    let mask = mask.trim();
+
+    let mask = if !mask.contains('@') {
+        format!("{}!*@*", mask)
+    } else if !mask.contains('!') {
+        format!("*!{}", mask)
+    } else {
+        mask.to_owned()
+    };
+
+    casefold(casemapping, &mask)
+}
+
+/// Returns whether the given mask, in which `*` matches any sequence of characters and `?` any one
+/// character, matches the whole of the given text.
+fn mask_matches(mask: &str, text: &str) -> bool {
+    let mask = mask.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+
+    let (mut m, mut t) = (0, 0);
+
+    // The position in the mask just past the last `*`, and the position in the text that it was
+    // last taken to match up to
+    let mut backtrack = None;
+
+    while t < text.len() {
+        if m < mask.len() && mask[m] == '*' {
+            m += 1;
+            backtrack = Some((m, t));
+        } else if m < mask.len() && (mask[m] == '?' || mask[m] == text[t]) {
+            m += 1;
+            t += 1;
+        } else if let Some((star_m, star_t)) = backtrack {
+            m = star_m;
+            t = star_t + 1;
+            backtrack = Some((star_m, t));
+        } else {
+            return false;
+        }
+    }
+
+    mask[m..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masks() {
+        let cm = CaseMapping::Rfc1459;
+
+        assert_eq!(normalize_mask(cm, "Troll"), "troll!*@*");
+        assert_eq!(normalize_mask(cm, "*@Example.COM"), "*!*@example.com");
+        assert_eq!(normalize_mask(cm, "a[b]!x@y"), "a{b}!x@y");
+
+        assert!(mask_matches("*!*@example.com", "troll!~t@example.com"));
+        assert!(mask_matches("troll!*@*", "troll!~t@example.com"));
+        assert!(mask_matches("t?oll*!*@*.com", "troll2!t@a.example.com"));
+        assert!(!mask_matches("*!*@example.com", "troll!t@example.com.evil"));
+        assert!(!mask_matches("troll!*@*", "trollish!t@example.com"));
+        assert!(mask_matches("*", ""));
+        assert!(!mask_matches("?", ""));
+    }
+}
@@ -1,22 +1,36 @@
+use super::bridge;
 use super::config;
+use super::console;
+use super::irc_msgs::IrcCaseInsensitive;
 use super::irc_msgs::OwningMsgPrefix;
+use super::irc_send::push_to_outbox;
 use super::BotCommand;
 use super::ErrorKind;
+use super::LibReaction;
 use super::MsgPrefix;
+use super::ReplyRoute;
 use super::Result;
 use super::Server;
+use super::ServerCapabilities;
 use super::ServerConfigIndex;
 use super::ServerId;
 use super::State;
 use irc::client::prelude as aatxe;
 use rand::StdRng;
+use serde::de::DeserializeOwned;
+use serde_yaml;
 use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::ops::Deref;
 use std::path::Path;
+use std::sync::Arc;
 use std::sync::LockResult;
 use std::sync::MutexGuard;
 use std::sync::RwLock;
 use std::sync::RwLockReadGuard;
 use std::sync::RwLockWriteGuard;
+use util::irc::casemapped_str_cmp;
+use util::irc::CaseMapping;
 
 impl State {
     pub fn nick(&self, server_id: ServerId) -> Result<String> {
@@ -27,49 +41,182 @@ impl State {
             .map(ToOwned::to_owned)
     }
 
+    /// Returns whether the given nickname is the bot's own nickname on the given server, comparing
+    /// the nicknames according to the server's case-mapping rules.
+    pub(crate) fn is_own_nick(&self, server_id: ServerId, nick: &str) -> Result<bool> {
+        self.nicks_eq(server_id, nick, &self.nick(server_id)?)
+    }
+
+    /// Returns whether the given nicknames are equivalent on the given server, per the server's
+    /// case-mapping rules.
+    pub(crate) fn nicks_eq(&self, server_id: ServerId, nick_1: &str, nick_2: &str) -> Result<bool> {
+        Ok(casemapped_str_cmp(self.casemapping(server_id)?, nick_1, nick_2) == Ordering::Equal)
+    }
+
+    /// Returns the features and limits that the given server has advertised with `RPL_ISUPPORT`.
+    pub fn server_capabilities(&self, server_id: ServerId) -> Result<ServerCapabilities> {
+        Ok(self.read_server(server_id)?.capabilities.clone())
+    }
+
+    /// Returns the name of the IRC network to which the given server belongs, as advertised by the
+    /// server with the `RPL_ISUPPORT` parameter `NETWORK`, if the server has advertised it.
+    pub fn network_name(&self, server_id: ServerId) -> Result<Option<String>> {
+        Ok(self
+            .read_server(server_id)?
+            .capabilities
+            .network()
+            .map(ToOwned::to_owned))
+    }
+
+    /// Returns the hostname and port number by which the bot connects to the given server, as
+    /// configured.
+    pub fn server_addr(&self, server_id: ServerId) -> Result<(String, u16)> {
+        let server = self.read_server(server_id)?;
+        let cfg = &server.aatxe_config;
+
+        Ok((cfg.server()?.to_owned(), cfg.port()))
+    }
+
+    pub(super) fn casemapping(&self, server_id: ServerId) -> Result<CaseMapping> {
+        Ok(self.read_server(server_id)?.capabilities.casemapping)
+    }
+
+    /// Returns the name that the configuration gives the given server.
+    pub fn server_name(&self, server_id: ServerId) -> Result<String> {
+        Ok(self.get_server_config(server_id)?.name.clone())
+    }
+
+    /// Returns the identifier of the server with the given name in the configuration, if any.
+    pub(super) fn server_named(&self, name: &str) -> Option<ServerId> {
+        self.servers.keys().cloned().find(|&server_id| {
+            self.get_server_config(server_id)
+                .ok()
+                .filter(|cfg| cfg.name == name)
+                .is_some()
+        })
+    }
+
+    /// Returns the IDs of the servers to which the bot is configured to connect.
+    pub fn server_ids(&self) -> Vec<ServerId> {
+        self.servers.keys().cloned().collect()
+    }
+
     pub fn module_data_path(&self) -> Result<&Path> {
         Ok(self.module_data_path.as_ref())
     }
 
-    pub fn command(&self, name: &str) -> Result<Option<&BotCommand>> {
-        Ok(self.commands.get(name))
+    /// Returns the settings that the configuration field `module settings` gives for the module
+    /// with the given name, or the default settings if it gives none.
+    pub fn module_settings<T>(&self, module: &str) -> Result<T>
+    where
+        T: DeserializeOwned + Default,
+    {
+        match self.config().module_settings.get(module) {
+            Some(settings) => serde_yaml::from_value(settings.clone()).map_err(|e| {
+                ErrorKind::Config(format!("module settings: {}", module), e.to_string()).into()
+            }),
+            None => Ok(Default::default()),
+        }
+    }
+
+    /// Looks up a bot command by name, comparing names case-insensitively according to the given
+    /// server's case-mapping rules.
+    pub fn command(&self, server_id: ServerId, name: &str) -> Result<Option<Arc<BotCommand>>> {
+        if let Some(cmd) = self.read_commands().get(name) {
+            return Ok(Some(cmd.clone()));
+        }
+
+        let name = IrcCaseInsensitive::new(self.casemapping(server_id)?, name);
+
+        Ok(self
+            .read_commands()
+            .iter()
+            .find(|&(k, _)| name == k.as_ref())
+            .map(|(_, cmd)| cmd.clone()))
+    }
+
+    /// Returns how the output of the given bot command is to be delivered: as the configuration
+    /// specifies, or else as the command's module specifies.
+    pub fn reply_route(&self, server_id: ServerId, cmd_name: &str) -> Result<ReplyRoute> {
+        let cmd = match self.command(server_id, cmd_name)? {
+            Some(cmd) => cmd,
+            None => return Ok(ReplyRoute::default()),
+        };
+
+        Ok(match self.config().command_replies.get(cmd.name.as_ref()) {
+            Some(&route) => route,
+            None => cmd.reply_route,
+        })
     }
 
     pub fn command_names(&self) -> Result<Vec<Cow<'static, str>>> {
-        Ok(self.commands.keys().cloned().collect())
+        Ok(self.read_commands().keys().cloned().collect())
     }
 
-    pub fn have_admin(
-        &self,
-        MsgPrefix {
+    /// Returns whether the given server has acknowledged enabling the IRCv3 capability with the
+    /// given name, such as `message-tags`, for its current connection.
+    pub fn capability_enabled(&self, server_id: ServerId, cap: &str) -> Result<bool> {
+        Ok(self.read_server(server_id)?.enabled_caps.contains(cap))
+    }
+
+    /// Returns whether the user with the given message prefix on the given server is an
+    /// administrator of the bot, either by matching one of the configured `admins` or by having
+    /// authenticated with the command `auth` within the configured session length. Nicknames and
+    /// services account names are compared according to the server's case-mapping rules, and
+    /// hostnames are compared ASCII-case-insensitively. The senders of messages from bridged
+    /// services are never administrators, as their nicknames are not their own on the server.
+    pub fn have_admin(&self, server_id: ServerId, prefix: MsgPrefix) -> Result<bool> {
+        if console::is_console_prefix(prefix) {
+            return Ok(true);
+        }
+
+        if bridge::is_bridge_prefix(prefix) {
+            return Ok(false);
+        }
+
+        if let Some(nick) = prefix.nick {
+            if self.has_admin_session(server_id, nick)? {
+                return Ok(true);
+            }
+        }
+
+        let MsgPrefix {
             nick: nick_1,
             user: user_1,
             host: host_1,
-        }: MsgPrefix,
-    ) -> Result<bool> {
-        Ok(self.config.admins.iter().any(
+        } = prefix;
+
+        let casemapping = self.casemapping(server_id)?;
+
+        let account_1 = match nick_1 {
+            Some(nick) => self.user_account(server_id, nick)?,
+            None => None,
+        };
+
+        Ok(self.config().admins.iter().any(
             |&config::Admin {
                  nick: ref nick_2,
                  user: ref user_2,
                  host: ref host_2,
+                 account: ref account_2,
              }| {
-                check_admin_cred(nick_1, nick_2)
-                    && check_admin_cred(user_1, user_2)
-                    && check_admin_cred(host_1, host_2)
+                check_admin_cred(nick_1, nick_2, |cdt, ctl| {
+                    IrcCaseInsensitive::new(casemapping, cdt) == ctl
+                }) && check_admin_cred(user_1, user_2, |cdt, ctl| cdt == ctl)
+                    && check_admin_cred(host_1, host_2, str::eq_ignore_ascii_case)
+                    && check_admin_cred(
+                        account_1.as_ref().map(|s| &s[..]),
+                        account_2,
+                        |cdt, ctl| IrcCaseInsensitive::new(casemapping, cdt) == ctl,
+                    )
             },
         ))
     }
 
-    // TODO: This is server-specific.
     // TODO: This should be named `read_stored_msg_prefix`, because it may not be our actual
     // current message prefix.
-    pub(super) fn read_msg_prefix(
-        &self,
-        _server_id: ServerId,
-    ) -> Result<RwLockReadGuard<OwningMsgPrefix>> {
-        self.msg_prefix
-            .read()
-            .map_err(|_| ErrorKind::LockPoisoned("stored message prefix".into()).into())
+    pub(super) fn read_msg_prefix(&self, server_id: ServerId) -> Result<OwningMsgPrefix> {
+        Ok(self.read_server(server_id)?.msg_prefix.clone())
     }
 
     pub(super) fn read_server(&self, server_id: ServerId) -> Result<RwLockReadGuard<Server>> {
@@ -95,15 +242,32 @@ impl State {
         }
     }
 
-    pub(super) fn get_server_config(&self, server_id: ServerId) -> Result<&config::Server> {
+    /// Returns the bot's current configuration, which may be replaced by reloading it.
+    pub(super) fn config(&self) -> Arc<config::Config> {
+        match self.config.read() {
+            Ok(config) => config.clone(),
+            // The lock is only ever held to replace or clone the `Arc`, so its contents are intact.
+            Err(poisoned_guard) => poisoned_guard.into_inner().clone(),
+        }
+    }
+
+    pub(super) fn get_server_config(&self, server_id: ServerId) -> Result<ServerConfigRef> {
         let ServerId {
             config_idx: ServerConfigIndex(idx),
             ..
         } = server_id;
-        self.config
-            .servers
-            .get::<usize>(idx.into())
-            .ok_or_else(|| ErrorKind::UnknownServer(server_id).into())
+
+        let config = self.config();
+
+        ensure!(
+            usize::from(idx) < config.servers.len(),
+            ErrorKind::UnknownServer(server_id)
+        );
+
+        Ok(ServerConfigRef {
+            config,
+            idx: idx.into(),
+        })
     }
 
     /// Runs the given function, passing as argument the `irc` crate `IrcClient` corresponding to
@@ -149,6 +313,22 @@ impl State {
             .ok_or(ErrorKind::UnknownServer(server_id))?)
     }
 
+    /// Queues a raw IRC protocol message, such as `"PRIVMSG #channel :Hello"`, to be sent to the
+    /// given server.
+    ///
+    /// This allows modules to send messages outside of the handling of a bot command or trigger,
+    /// e.g., from a handler registered with [`ModuleBuilder::on_connect`].
+    ///
+    /// [`ModuleBuilder::on_connect`]: <struct.ModuleBuilder.html#method.on_connect>
+    pub fn send_raw_msg(&self, server_id: ServerId, msg: &str) -> Result<()> {
+        // Check that the server exists.
+        drop(self.read_server(server_id)?);
+
+        push_to_outbox(&self.outbox, server_id, LibReaction::RawMsg(msg.parse()?));
+
+        Ok(())
+    }
+
     /// Allows access to a random number generator that's stored centrally, to avoid the cost of
     /// repeatedly initializing one.
     pub fn rng(&self) -> Result<MutexGuard<StdRng>> {
@@ -170,12 +350,31 @@ impl State {
 
 /// Check a field of a (nick, user, host) triple representing some user (the "candidate") against
 /// the corresponding field of a like triple representing an authorized administrator of the bot
-/// (the "control"). Returns whether the given candidate field matches the control.
-fn check_admin_cred(candidate: Option<&str>, control: &Option<String>) -> bool {
+/// (the "control"). Returns whether the given candidate field matches the control, using the given
+/// function to compare the field's values.
+/// A reference to the configuration of a server, which keeps the configuration of which it is a part
+/// alive even if the configuration is reloaded meanwhile
+pub(super) struct ServerConfigRef {
+    config: Arc<config::Config>,
+    idx: usize,
+}
+
+impl Deref for ServerConfigRef {
+    type Target = config::Server;
+
+    fn deref(&self) -> &config::Server {
+        &self.config.servers[self.idx]
+    }
+}
+
+fn check_admin_cred<F>(candidate: Option<&str>, control: &Option<String>, eq: F) -> bool
+where
+    F: FnOnce(&str, &str) -> bool,
+{
     match (candidate, control) {
         (Some(cdt), &Some(ref ctl)) => {
-            // If a field is set in both candidate and control, the values must be equal.
-            cdt == ctl
+            // If a field is set in both candidate and control, the values must be equivalent.
+            eq(cdt, ctl)
         }
         (_, &None) => {
             // All candidates match against a field that is unset in the control record.
@@ -0,0 +1,64 @@
+//! Statistics about the bot's running, such as are reported by the command `stats` of the module
+//! `default`.
+
+use super::Result;
+use super::ServerId;
+use super::State;
+use std::fs;
+use std::time::Duration;
+
+impl State {
+    /// Returns how long the bot has been running.
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Returns how long ago the bot finished registering with the given server on the current
+    /// connection, or `None` if it is not connected and registered.
+    pub fn connection_age(&self, server_id: ServerId) -> Result<Option<Duration>> {
+        Ok(self
+            .read_server(server_id)?
+            .registered_since
+            .map(|t| t.elapsed()))
+    }
+
+    /// Returns the numbers of messages that the bot has received from and sent to the given
+    /// server since it started, in that order.
+    pub fn msg_counts(&self, server_id: ServerId) -> (u64, u64) {
+        self.metrics.msg_counts(server_id)
+    }
+
+    /// Returns the size, in bytes, of the bot's resident memory, or `None` if it cannot be
+    /// determined, as on systems without `/proc`.
+    pub fn memory_usage(&self) -> Option<u64> {
+        let status = fs::read_to_string("/proc/self/status").ok()?;
+
+        parse_vm_rss(&status)
+    }
+}
+
+/// Returns the resident set size, in bytes, given in the field `VmRSS` of the given contents of
+/// `/proc/self/status`.
+fn parse_vm_rss(status: &str) -> Option<u64> {
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let mut words = line["VmRSS:".len()..].split_whitespace();
+
+    match (words.next()?.parse::<u64>().ok()?, words.next()) {
+        (kb, Some("kB")) => Some(kb * 1024),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vm_rss() {
+        assert_eq!(
+            parse_vm_rss("Name:\tbot\nVmHWM:\t   2048 kB\nVmRSS:\t   1536 kB\nThreads:\t4\n"),
+            Some(1536 * 1024)
+        );
+        assert_eq!(parse_vm_rss("Name:\tbot\n"), None);
+    }
+}
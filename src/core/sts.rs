@@ -0,0 +1,293 @@
+use super::ErrorKind;
+use super::Result;
+use super::ServerId;
+use super::State;
+use irc::client::prelude as aatxe;
+use serde_yaml;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// The quit message with which the bot disconnects from a server to reconnect to it with TLS, per
+/// the server's STS policy
+const UPGRADE_QUIT_MSG: &str = "Upgrading to a secure connection";
+
+/// The IRCv3 strict transport security (STS) policies that servers have advertised, by hostname
+#[derive(Debug, Default)]
+pub(super) struct StsPolicies {
+    policies: BTreeMap<String, Policy>,
+
+    /// The file in which the policies are cached, if one is configured
+    path: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct Policy {
+    /// The port on which the server accepts connections with TLS
+    port: u16,
+
+    /// When the policy expires, or `None` if the policy has been advertised over a connection
+    /// without TLS, in which case it only directs the bot to reconnect with TLS, and is neither
+    /// persisted nor expired until the server advertises its duration over a secure connection
+    expiry: Option<SystemTime>,
+}
+
+/// A policy as stored in the cache file
+#[derive(Debug, Deserialize, Serialize)]
+struct StoredPolicy {
+    port: u16,
+
+    /// The policy's expiry time, in seconds since the Unix epoch
+    expiry: u64,
+}
+
+/// The value of the capability `sts`, such as `port=6697,duration=2592000`
+#[derive(Debug, Default, Eq, PartialEq)]
+struct StsValue {
+    port: Option<u16>,
+    duration: Option<u64>,
+}
+
+impl StsPolicies {
+    /// Loads the policies cached in the given file, if one is given and it exists.
+    pub(super) fn load(path: Option<PathBuf>) -> Result<Self> {
+        let mut policies = BTreeMap::new();
+
+        if let Some(ref path) = path {
+            match fs::File::open(path) {
+                Ok(file) => {
+                    let stored: BTreeMap<String, StoredPolicy> = serde_yaml::from_reader(file)?;
+
+                    policies.extend(stored.into_iter().map(|(host, policy)| {
+                        let expiry = UNIX_EPOCH + Duration::from_secs(policy.expiry);
+                        let policy = Policy {
+                            port: policy.port,
+                            expiry: Some(expiry),
+                        };
+                        (host, policy)
+                    }));
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(StsPolicies { policies, path })
+    }
+
+    /// Returns the unexpired policy for the given host, if there is one.
+    fn get(&self, host: &str) -> Option<Policy> {
+        self.policies
+            .get(&host.to_ascii_lowercase())
+            .cloned()
+            .filter(|policy| match policy.expiry {
+                Some(expiry) => expiry > SystemTime::now(),
+                None => true,
+            })
+    }
+
+    fn set(&mut self, host: &str, policy: Option<Policy>) {
+        let host = host.to_ascii_lowercase();
+
+        match policy {
+            Some(policy) => self.policies.insert(host, policy),
+            None => self.policies.remove(&host),
+        };
+
+        match self.save() {
+            Ok(()) => {}
+            Err(e) => error!("Failed to save STS policies: {}", e),
+        }
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = match self.path {
+            Some(ref path) => path,
+            None => return Ok(()),
+        };
+
+        let now = SystemTime::now();
+
+        let stored = self
+            .policies
+            .iter()
+            .filter_map(|(host, policy)| {
+                let expiry = policy.expiry.filter(|&expiry| expiry > now)?;
+                let expiry = expiry.duration_since(UNIX_EPOCH).ok()?.as_secs();
+                Some((
+                    host,
+                    StoredPolicy {
+                        port: policy.port,
+                        expiry,
+                    },
+                ))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        fs::write(path, serde_yaml::to_string(&stored)?)?;
+
+        Ok(())
+    }
+}
+
+/// Returns the configuration with which to connect to the given server, which, if the server has
+/// an unexpired STS policy, is upgraded to use TLS on the port given in the policy, and records
+/// whether the connection is to use TLS.
+pub(super) fn prepare_connection(state: &State, server_id: ServerId) -> Result<Arc<aatxe::Config>> {
+    let mut server = state.write_server(server_id)?;
+    let cfg = server.aatxe_config.clone();
+
+    let policy = state
+        .sts_policies
+        .read()
+        .ok()
+        .and_then(|policies| policies.get(cfg.server().ok()?));
+
+    let cfg = match policy {
+        Some(Policy { port, .. }) if !cfg.use_ssl() || cfg.port() != port => {
+            debug!(
+                "[{}] Connecting with TLS on port {}, per the server's STS policy.",
+                server.socket_addr_string, port
+            );
+
+            Arc::new(aatxe::Config {
+                use_ssl: Some(true),
+                port: Some(port),
+                ..(*cfg).clone()
+            })
+        }
+        _ => cfg,
+    };
+
+    server.connection_secure = cfg.use_ssl();
+
+    Ok(cfg)
+}
+
+/// Handles a server's `CAP LS` or `CAP NEW` message, which lists the IRCv3 capabilities that the
+/// server supports, among which may be `sts`, which advertises the server's STS policy.
+pub(super) fn handle_cap_list(state: &State, server_id: ServerId, caps: &str) -> Result<()> {
+    let value = match caps
+        .split_whitespace()
+        .find(|cap| cap.starts_with("sts="))
+        .map(|cap| parse_sts_value(&cap["sts=".len()..]))
+    {
+        Some(value) => value,
+        None => return Ok(()),
+    };
+
+    let (host, port, secure, socket_addr_string) = {
+        let server = state.read_server(server_id)?;
+
+        (
+            server.aatxe_config.server()?.to_owned(),
+            server.aatxe_config.port(),
+            server.connection_secure,
+            server.socket_addr_string.clone(),
+        )
+    };
+
+    let mut policies = state
+        .sts_policies
+        .write()
+        .map_err(|_| ErrorKind::LockPoisoned("STS policies".into()))?;
+
+    match (secure, value) {
+        (
+            true,
+            StsValue {
+                duration: Some(0), ..
+            },
+        ) => {
+            debug!(
+                "[{}] Server has withdrawn its STS policy.",
+                socket_addr_string
+            );
+            policies.set(&host, None);
+        }
+        (
+            true,
+            StsValue {
+                duration: Some(duration),
+                ..
+            },
+        ) => {
+            let port = policies.get(&host).map(|p| p.port).unwrap_or(port);
+
+            policies.set(
+                &host,
+                Some(Policy {
+                    port,
+                    expiry: Some(SystemTime::now() + Duration::from_secs(duration)),
+                }),
+            );
+        }
+        (
+            false,
+            StsValue {
+                port: Some(port), ..
+            },
+        ) => {
+            info!(
+                "[{}] Server has advertised an STS policy; reconnecting with TLS on port {}.",
+                socket_addr_string, port
+            );
+
+            if policies.get(&host).is_none() {
+                policies.set(&host, Some(Policy { port, expiry: None }));
+            }
+
+            drop(policies);
+
+            state.reconnect(server_id, Some(UPGRADE_QUIT_MSG.into()))?;
+        }
+        _ => {}
+    }
+
+    Ok(())
+}
+
+fn parse_sts_value(value: &str) -> StsValue {
+    let mut result = StsValue::default();
+
+    for key_value in value.split(',') {
+        let mut key_value = key_value.splitn(2, '=');
+
+        match (key_value.next(), key_value.next()) {
+            (Some("port"), Some(port)) => result.port = port.parse().ok(),
+            (Some("duration"), Some(duration)) => result.duration = duration.parse().ok(),
+            _ => {}
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sts_values() {
+        assert_eq!(
+            parse_sts_value("port=6697,duration=2592000,preload"),
+            StsValue {
+                port: Some(6697),
+                duration: Some(2_592_000),
+            }
+        );
+        assert_eq!(
+            parse_sts_value("duration=0"),
+            StsValue {
+                port: None,
+                duration: Some(0),
+            }
+        );
+        assert_eq!(parse_sts_value("port=huge"), StsValue::default());
+    }
+}
@@ -14,6 +14,7 @@ use std::ops::DerefMut;
 use std::sync::Arc;
 use std::sync::RwLock;
 use std::sync::RwLockReadGuard;
+use std::time::Instant;
 use util;
 use uuid::Uuid;
 
@@ -27,6 +28,10 @@ pub struct Trigger {
 
     pub priority: TriggerPriority,
 
+    /// Whether the trigger is matched against messages that aren't addressed to the bot, as well
+    /// as against those that are
+    pub always_watching: bool,
+
     #[debug(skip)]
     pub(super) handler: Arc<TriggerHandler>,
 
@@ -45,7 +50,9 @@ pub enum TriggerAttr {
     /// Use this attribute for triggers that should trigger even on messages that aren't addressed
     /// to the bot.
     ///
-    /// As of 2018-01-11, this doesn't actually do anything yet.
+    /// Such a trigger is matched against the whole text of each message that isn't addressed to
+    /// the bot, as well as, like other triggers, against the text that follows the bot's nickname
+    /// or command prefix in each message that is.
     AlwaysWatching,
 }
 
@@ -86,15 +93,31 @@ impl Trigger {
     }
 }
 
+impl State {
+    /// Returns whether any loaded trigger is to be matched against messages that aren't addressed
+    /// to the bot.
+    pub(super) fn has_always_watching_triggers(&self) -> bool {
+        self.read_triggers()
+            .values()
+            .any(|triggers| triggers.iter().any(|t| t.always_watching))
+    }
+}
+
 /// Returns `None` if no trigger matched.
+///
+/// The argument `is_action` should specify whether the message whose text is given was an action
+/// (as sent with the IRC client command `/me`), and `addressed`, whether it was addressed to the
+/// bot; if not, only triggers with the attribute `AlwaysWatching` are considered.
 pub(super) fn run_any_matching(
     state: &State,
     text: &str,
     msg_metadata: &MsgMetadata,
+    is_action: bool,
+    addressed: bool,
 ) -> Result<Option<BotCmdResult>> {
     let mut trigger = None;
 
-    for (_priority, triggers) in state.triggers.iter().rev() {
+    for (_priority, triggers) in state.read_triggers().iter().rev() {
         if triggers.is_empty() {
             continue;
         }
@@ -102,10 +125,12 @@ pub(super) fn run_any_matching(
         if let Some(t) = triggers
             .rand_iter()
             .with_rng(state.rng()?.deref_mut())
+            .filter(|t| addressed || t.always_watching)
+            .filter(|t| !state.module_disabled(&t.provider.name))
             .filter(|t| t.read_regex().map(|rx| rx.is_match(text)).unwrap_or(false))
             .next()
         {
-            trigger = Some(t);
+            trigger = Some(t.clone());
             break;
         }
     }
@@ -117,9 +142,11 @@ pub(super) fn run_any_matching(
 
     let ctx = HandlerContext {
         state,
-        this_feature: ModuleFeatureRef::Trigger(trigger),
+        this_feature: ModuleFeatureRef::Trigger(&trigger),
         request_origin: msg_metadata.dest,
         invoker: msg_metadata.prefix,
+        request_tags: msg_metadata.tags,
+        request_is_action: is_action,
         __nonexhaustive: (),
     };
 
@@ -128,9 +155,25 @@ pub(super) fn run_any_matching(
          trigger didn't match!",
     );
 
-    Ok(Some(util::run_handler(
-        "trigger",
-        trigger.name.clone(),
-        || trigger.handler.run(ctx, args),
-    )?))
+    let start = Instant::now();
+    let result = util::run_handler("trigger", trigger.name.clone(), || {
+        trigger.handler.run(ctx, args)
+    });
+
+    let elapsed = start.elapsed();
+
+    state
+        .metrics
+        .record_handler_run("trigger", &trigger.name, elapsed);
+
+    state.record_module_run(
+        &trigger.provider.name,
+        elapsed,
+        match result {
+            Ok(BotCmdResult::LibErr(_)) | Ok(BotCmdResult::BotErrMsg(_)) | Err(_) => true,
+            Ok(_) => false,
+        },
+    );
+
+    Ok(Some(result?))
 }
@@ -0,0 +1,515 @@
+use super::FloodKind;
+use super::Result;
+use super::ServerId;
+use super::State;
+use std::cmp::Ordering;
+use util::irc::casemapped_str_cmp;
+use util::irc::CaseMapping;
+
+/// An identifier that the bot assigns to an IRC user on a server, which stays the same while the
+/// user changes nicknames and while the user is separated from the bot by a netsplit
+///
+/// User IDs are not persistent: a user who leaves the network and later returns, or whom the bot
+/// sees again after reconnecting to the server, is assigned a new ID.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct UserId(u64);
+
+/// A change in a user's nickname or presence, as passed to handlers set with
+/// [`ModuleBuilder::on_user_event`]
+///
+/// [`ModuleBuilder::on_user_event`]: <struct.ModuleBuilder.html#method.on_user_event>
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum UserEvent {
+    /// The user changed nicknames.
+    NickChange {
+        user: UserId,
+        old_nick: String,
+        new_nick: String,
+    },
+
+    /// The user quit the server, or was separated from the bot by a netsplit, as indicated by
+    /// `kind`.
+    Quit {
+        user: UserId,
+        nick: String,
+        reason: Option<String>,
+        kind: QuitKind,
+    },
+
+    /// The user, having been separated from the bot by a netsplit, has reappeared.
+    NetsplitReturn { user: UserId, nick: String },
+
+    /// The given users were separated from the bot by a netsplit between the given servers, as
+    /// the server announced with an IRCv3 `netsplit` batch.
+    ///
+    /// Where the server announces netsplits thus, this event is delivered once per netsplit,
+    /// rather than a `Quit` event per user.
+    Netsplit {
+        servers: Option<(String, String)>,
+        users: Vec<(UserId, String)>,
+    },
+
+    /// The given users, having been separated from the bot by a netsplit between the given
+    /// servers, have reappeared, as the server announced with an IRCv3 `netjoin` batch.
+    ///
+    /// Where the server announces the ends of netsplits thus, this event is delivered once per
+    /// netsplit, rather than a `NetsplitReturn` event per user.
+    Netjoin {
+        servers: Option<(String, String)>,
+        users: Vec<(UserId, String)>,
+    },
+
+    /// The user flooded the given channel in the given way, as detected per the configuration
+    /// field `flood protection`, so that the bot ignores the user's commands for a while.
+    Flood {
+        user: UserId,
+        nick: String,
+        channel: String,
+        kind: FloodKind,
+    },
+}
+
+/// Why a user quit
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum QuitKind {
+    /// The user was separated from the bot by a netsplit and may well reappear shortly, as
+    /// indicated by a quit message naming two servers, such as `*.net *.split`.
+    Netsplit,
+
+    /// The user left the network.
+    Departure,
+}
+
+/// The users that the bot has seen on a server
+#[derive(Debug, Default)]
+pub(super) struct UserTable {
+    users: Vec<TrackedUser>,
+
+    /// The number to be used for the next user ID assigned, which is not reset when the table is
+    /// cleared, so that IDs are never reused
+    next_id: u64,
+}
+
+#[derive(Debug)]
+struct TrackedUser {
+    id: UserId,
+    nick: String,
+
+    /// Whether the user is separated from the bot by a netsplit
+    split: bool,
+
+    /// The services account as which the server reports that the user is logged in, if known
+    account: Option<String>,
+}
+
+impl UserTable {
+    fn position(&self, casemapping: CaseMapping, nick: &str) -> Option<usize> {
+        self.users.iter().position(|user| {
+            casemapped_str_cmp(casemapping, &user.nick[..], nick) == Ordering::Equal
+        })
+    }
+
+    fn new_id(&mut self) -> UserId {
+        let id = UserId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    fn insert(&mut self, nick: &str, split: bool) -> UserId {
+        let id = self.new_id();
+
+        self.users.push(TrackedUser {
+            id,
+            nick: nick.to_owned(),
+            split,
+            account: None,
+        });
+
+        id
+    }
+
+    /// Records that a user with the given nickname is present, returning the user's ID and
+    /// whether the user has just returned from a netsplit.
+    pub(super) fn sighted(&mut self, casemapping: CaseMapping, nick: &str) -> (UserId, bool) {
+        match self.position(casemapping, nick) {
+            Some(i) => {
+                let user = &mut self.users[i];
+                let returned = user.split;
+                user.split = false;
+                (user.id, returned)
+            }
+            None => (self.insert(nick, false), false),
+        }
+    }
+
+    /// Records that the user with the nickname `old_nick` is now known as `new_nick`, returning
+    /// the user's ID.
+    pub(super) fn renamed(
+        &mut self,
+        casemapping: CaseMapping,
+        old_nick: &str,
+        new_nick: &str,
+    ) -> UserId {
+        let old_pos = self.position(casemapping, old_nick);
+
+        // Whoever last held the new nickname, e.g., a user lost in a netsplit, has evidently
+        // released it.
+        if let Some(i) = self.position(casemapping, new_nick) {
+            if Some(i) != old_pos {
+                self.users.remove(i);
+            }
+        }
+
+        match self.position(casemapping, old_nick) {
+            Some(i) => {
+                let user = &mut self.users[i];
+                user.nick = new_nick.to_owned();
+                user.split = false;
+                user.id
+            }
+            None => self.insert(new_nick, false),
+        }
+    }
+
+    /// Records that the user with the given nickname has quit, returning the user's ID. A user
+    /// lost in a netsplit is remembered, so that the user keeps the same ID on returning.
+    pub(super) fn quit(&mut self, casemapping: CaseMapping, nick: &str, kind: QuitKind) -> UserId {
+        match (self.position(casemapping, nick), kind) {
+            (Some(i), QuitKind::Netsplit) => {
+                let user = &mut self.users[i];
+                user.split = true;
+                user.id
+            }
+            (Some(i), QuitKind::Departure) => self.users.remove(i).id,
+            (None, QuitKind::Netsplit) => self.insert(nick, true),
+            (None, QuitKind::Departure) => self.new_id(),
+        }
+    }
+
+    fn id_of(&self, casemapping: CaseMapping, nick: &str) -> Option<UserId> {
+        self.position(casemapping, nick)
+            .map(|i| &self.users[i])
+            .filter(|user| !user.split)
+            .map(|user| user.id)
+    }
+
+    fn nick_of(&self, id: UserId) -> Option<&str> {
+        self.users
+            .iter()
+            .find(|user| user.id == id && !user.split)
+            .map(|user| &user.nick[..])
+    }
+
+    /// Records the services account as which the user with the given nickname is logged in, or
+    /// that the user is not logged in.
+    pub(super) fn set_account(
+        &mut self,
+        casemapping: CaseMapping,
+        nick: &str,
+        account: Option<String>,
+    ) {
+        let i = match self.position(casemapping, nick) {
+            Some(i) => i,
+            None => {
+                self.insert(nick, false);
+                self.users.len() - 1
+            }
+        };
+
+        self.users[i].account = account;
+    }
+
+    fn account_of(&self, casemapping: CaseMapping, nick: &str) -> Option<&str> {
+        self.position(casemapping, nick)
+            .map(|i| &self.users[i])
+            .filter(|user| !user.split)
+            .and_then(|user| user.account.as_ref())
+            .map(|account| &account[..])
+    }
+
+    /// Forgets all users, e.g., upon reconnecting to the server.
+    pub(super) fn clear(&mut self) {
+        self.users.clear()
+    }
+}
+
+impl State {
+    /// Returns the ID of the user on the given server who currently has the given nickname, if
+    /// the bot has seen such a user.
+    pub fn user_id(&self, server_id: ServerId, nick: &str) -> Result<Option<UserId>> {
+        let server = self.read_server(server_id)?;
+
+        Ok(server.users.id_of(server.capabilities.casemapping, nick))
+    }
+
+    /// Returns the services account as which the user on the given server with the given nickname
+    /// is logged in, if the bot knows this.
+    ///
+    /// Accounts are tracked by means of the IRCv3 capabilities `account-notify` and
+    /// `extended-join`, so this returns `None` on servers that don't support them, as well as for
+    /// users who are not logged in or who have not joined a channel since the bot did.
+    pub fn user_account(&self, server_id: ServerId, nick: &str) -> Result<Option<String>> {
+        let server = self.read_server(server_id)?;
+
+        Ok(server
+            .users
+            .account_of(server.capabilities.casemapping, nick)
+            .map(ToOwned::to_owned))
+    }
+
+    /// Returns the current nickname of the user on the given server with the given ID, if the
+    /// user is still present, as far as the bot knows.
+    pub fn user_nick(&self, server_id: ServerId, user: UserId) -> Result<Option<String>> {
+        Ok(self
+            .read_server(server_id)?
+            .users
+            .nick_of(user)
+            .map(ToOwned::to_owned))
+    }
+}
+
+/// Records the presence of a user seen joining a channel, speaking, or listed in a channel's
+/// `NAMES`.
+pub(super) fn handle_user_sighting(state: &State, server_id: ServerId, nick: &str) -> Result<()> {
+    let (user, returned) = {
+        let mut server = state.write_server(server_id)?;
+        let casemapping = server.capabilities.casemapping;
+        server.users.sighted(casemapping, nick)
+    };
+
+    if returned {
+        state.run_user_event_handlers(
+            server_id,
+            &UserEvent::NetsplitReturn {
+                user,
+                nick: nick.to_owned(),
+            },
+        );
+    }
+
+    Ok(())
+}
+
+/// Records the services account as which a user is logged in, as reported by an `ACCOUNT` message
+/// or by an extended `JOIN` message, in which the account name `*` signifies that the user is not
+/// logged in.
+pub(super) fn handle_account(
+    state: &State,
+    server_id: ServerId,
+    nick: &str,
+    account: &str,
+) -> Result<()> {
+    let mut server = state.write_server(server_id)?;
+    let casemapping = server.capabilities.casemapping;
+    server
+        .users
+        .set_account(casemapping, nick, parse_account(account));
+
+    Ok(())
+}
+
+pub(super) fn parse_account(account: &str) -> Option<String> {
+    if account == "*" {
+        None
+    } else {
+        Some(account.to_owned())
+    }
+}
+
+pub(super) fn handle_nick_change(
+    state: &State,
+    server_id: ServerId,
+    old_nick: &str,
+    new_nick: &str,
+) -> Result<()> {
+    let user = {
+        let mut server = state.write_server(server_id)?;
+        let casemapping = server.capabilities.casemapping;
+        let user = server.users.renamed(casemapping, old_nick, new_nick);
+        server.admin_auth.end(user);
+        user
+    };
+
+    state.run_user_event_handlers(
+        server_id,
+        &UserEvent::NickChange {
+            user,
+            old_nick: old_nick.to_owned(),
+            new_nick: new_nick.to_owned(),
+        },
+    );
+
+    Ok(())
+}
+
+pub(super) fn handle_quit(
+    state: &State,
+    server_id: ServerId,
+    nick: &str,
+    reason: Option<String>,
+) -> Result<()> {
+    let kind = match reason {
+        Some(ref reason) if is_netsplit_reason(reason) => QuitKind::Netsplit,
+        _ => QuitKind::Departure,
+    };
+
+    let user = {
+        let mut server = state.write_server(server_id)?;
+        let casemapping = server.capabilities.casemapping;
+        let user = server.users.quit(casemapping, nick, kind);
+        server.admin_auth.end(user);
+        user
+    };
+
+    state.run_user_event_handlers(
+        server_id,
+        &UserEvent::Quit {
+            user,
+            nick: nick.to_owned(),
+            reason,
+            kind,
+        },
+    );
+
+    Ok(())
+}
+
+/// Records that the users with the given nicknames were lost in a netsplit between the given
+/// servers, as announced in a `netsplit` batch.
+pub(super) fn handle_netsplit(
+    state: &State,
+    server_id: ServerId,
+    servers: Option<(String, String)>,
+    nicks: Vec<String>,
+) -> Result<()> {
+    let users = {
+        let mut server = state.write_server(server_id)?;
+        let casemapping = server.capabilities.casemapping;
+
+        nicks
+            .into_iter()
+            .map(|nick| {
+                let user = server.users.quit(casemapping, &nick, QuitKind::Netsplit);
+                server.admin_auth.end(user);
+                (user, nick)
+            })
+            .collect()
+    };
+
+    state.run_user_event_handlers(server_id, &UserEvent::Netsplit { servers, users });
+
+    Ok(())
+}
+
+/// Records that the users with the given nicknames have returned from a netsplit between the
+/// given servers, as announced in a `netjoin` batch.
+pub(super) fn handle_netjoin(
+    state: &State,
+    server_id: ServerId,
+    servers: Option<(String, String)>,
+    nicks: Vec<String>,
+) -> Result<()> {
+    let users = {
+        let mut server = state.write_server(server_id)?;
+        let casemapping = server.capabilities.casemapping;
+
+        nicks
+            .into_iter()
+            .map(|nick| (server.users.sighted(casemapping, &nick).0, nick))
+            .collect()
+    };
+
+    state.run_user_event_handlers(server_id, &UserEvent::Netjoin { servers, users });
+
+    Ok(())
+}
+
+/// Returns whether the given quit message is one that servers send on behalf of users lost in a
+/// netsplit, which names the two servers between which the network split, e.g.,
+/// `irc.example.net hub.example.net`, or `*.net *.split` on networks that hide their servers'
+/// names.
+fn is_netsplit_reason(reason: &str) -> bool {
+    fn is_server_name(s: &str) -> bool {
+        s.contains('.')
+            && !s.starts_with('.')
+            && !s.ends_with('.')
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '*')
+    }
+
+    let mut words = reason.split(' ');
+
+    match (words.next(), words.next(), words.next()) {
+        (Some(server_1), Some(server_2), None) => {
+            is_server_name(server_1) && is_server_name(server_2)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CM: CaseMapping = CaseMapping::Rfc1459;
+
+    #[test]
+    fn netsplit_reasons() {
+        assert!(is_netsplit_reason("*.net *.split"));
+        assert!(is_netsplit_reason("irc.example.net hub.example.net"));
+        assert!(!is_netsplit_reason("Quit: *.net *.split"));
+        assert!(!is_netsplit_reason("Ping timeout: 240 seconds"));
+        assert!(!is_netsplit_reason("Quit: see you later"));
+        assert!(!is_netsplit_reason("bye."));
+        assert!(!is_netsplit_reason(""));
+    }
+
+    #[test]
+    fn ids_survive_renames_and_netsplits() {
+        let mut table = UserTable::default();
+
+        let (alice, returned) = table.sighted(CM, "alice");
+        assert!(!returned);
+        assert_eq!(table.sighted(CM, "ALICE"), (alice, false));
+
+        assert_eq!(table.renamed(CM, "alice", "alice_away"), alice);
+        assert_eq!(table.id_of(CM, "alice"), None);
+        assert_eq!(table.id_of(CM, "Alice_Away"), Some(alice));
+        assert_eq!(table.nick_of(alice), Some("alice_away"));
+
+        assert_eq!(table.quit(CM, "alice_away", QuitKind::Netsplit), alice);
+        assert_eq!(table.id_of(CM, "alice_away"), None);
+        assert_eq!(table.sighted(CM, "alice_away"), (alice, true));
+
+        assert_eq!(table.quit(CM, "alice_away", QuitKind::Departure), alice);
+        let (new_alice, returned) = table.sighted(CM, "alice_away");
+        assert!(!returned);
+        assert_ne!(new_alice, alice);
+    }
+
+    #[test]
+    fn accounts_follow_users() {
+        let mut table = UserTable::default();
+
+        table.sighted(CM, "carol");
+        assert_eq!(table.account_of(CM, "carol"), None);
+
+        table.set_account(CM, "carol", Some("carol".into()));
+        table.renamed(CM, "carol", "carol|afk");
+        assert_eq!(table.account_of(CM, "CAROL|AFK"), Some("carol"));
+
+        table.set_account(CM, "carol|afk", None);
+        assert_eq!(table.account_of(CM, "carol|afk"), None);
+    }
+
+    #[test]
+    fn ids_are_not_reused_after_clearing() {
+        let mut table = UserTable::default();
+
+        let (bob, _) = table.sighted(CM, "bob");
+        table.clear();
+        let (new_bob, _) = table.sighted(CM, "bob");
+
+        assert_ne!(new_bob, bob);
+    }
+}
@@ -0,0 +1,154 @@
+use super::config::Webhook;
+use super::http::Request;
+use super::http::Response;
+use super::State;
+use serde_yaml;
+use serde_yaml::Value;
+use util::sha256;
+
+/// Handles a `POST` request to the given webhook endpoint, announcing the rendered template in the
+/// webhook's channels if the request is authorized.
+pub(super) fn handle_request(state: &State, webhook: &Webhook, request: &Request) -> Response {
+    let token = request
+        .header("Authorization")
+        .and_then(|auth| {
+            let mut auth = auth.splitn(2, ' ');
+            match (auth.next(), auth.next()) {
+                (Some("Bearer"), Some(token)) => Some(token.trim().to_owned()),
+                _ => None,
+            }
+        })
+        .or_else(|| request.query_param("token"));
+
+    match token {
+        Some(ref token) if sha256::constant_time_eq(token, &webhook.token) => {}
+        _ => return Response::text(401, "Unauthorized\n"),
+    }
+
+    // JSON is YAML, for the purposes of webhooks' payloads.
+    let payload: Value = match serde_yaml::from_slice(&request.body) {
+        Ok(payload) => payload,
+        Err(e) => return Response::text(400, format!("Malformed payload: {}\n", e)),
+    };
+
+    let text = render_template(&webhook.template, &payload);
+
+    for &server_id in state.servers.keys() {
+        let server_name = match state.get_server_config(server_id) {
+            Ok(cfg) => cfg.name.clone(),
+            Err(e) => {
+                warn!("Failed to look up server {:?}: {}", server_id, e);
+                continue;
+            }
+        };
+
+        match webhook.server {
+            Some(ref name) if *name != server_name => continue,
+            _ => {}
+        }
+
+        for channel in &webhook.channels {
+            for line in text.lines().filter(|line| !line.trim().is_empty()) {
+                if let Err(e) = state.send_privmsg(server_id, channel, line) {
+                    warn!(
+                        "Failed to announce webhook {:?} in {:?}: {}",
+                        webhook.path, channel, e
+                    );
+                }
+            }
+        }
+    }
+
+    Response::text(202, "Accepted\n")
+}
+
+/// Renders the given template, replacing each placeholder of the form `{a.b.c}` with the first
+/// line of the value at that path in the given payload, with its runs of whitespace and control
+/// characters, such as carriage returns, each collapsed to a single space, so that a value can't
+/// smuggle further IRC messages into the one that the bot sends.
+fn render_template(template: &str, payload: &Value) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                output.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                output.push('}');
+            }
+            '{' => {
+                let path = chars.by_ref().take_while(|&c| c != '}').collect::<String>();
+
+                if let Some(value) = lookup(payload, path.trim()) {
+                    let line = value.lines().next().unwrap_or_default();
+
+                    output.push_str(
+                        &line
+                            .split(|c: char| c.is_whitespace() || c.is_control())
+                            .filter(|word| !word.is_empty())
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                    );
+                }
+            }
+            c => output.push(c),
+        }
+    }
+
+    output
+}
+
+/// Returns the scalar value at the given dot-separated path in the given payload, formatted as a
+/// string.
+fn lookup(payload: &Value, path: &str) -> Option<String> {
+    let value = path
+        .split('.')
+        .filter(|key| !key.is_empty())
+        .try_fold(payload, |value, key| match *value {
+            Value::Mapping(ref map) => map.get(&Value::String(key.to_owned())),
+            Value::Sequence(ref seq) => key.parse::<usize>().ok().and_then(|i| seq.get(i)),
+            _ => None,
+        })?;
+
+    match *value {
+        Value::String(ref s) => Some(s.clone()),
+        Value::Number(ref n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        Value::Null | Value::Sequence(_) | Value::Mapping(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn templates() {
+        let payload: Value = serde_yaml::from_str(
+            r#"{"repository": {"full_name": "w/bot"}, "commits": [{"message": "Fix\n\nDetails"}],
+                "forced": false, "size": 3, "after": null}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            render_template(
+                "[{repository.full_name}] {commits.0.message} ({size}, {forced}){after}{{x}}",
+                &payload
+            ),
+            "[w/bot] Fix (3, false){x}"
+        );
+        assert_eq!(render_template("{missing.key} {commits.9}", &payload), " ");
+
+        let payload: Value =
+            serde_yaml::from_str(r#"{"title": "x\rQUIT :pwned", "ref": "a\u0000b \t c"}"#).unwrap();
+
+        assert_eq!(
+            render_template("{title} {ref}", &payload),
+            "x QUIT :pwned a b c"
+        );
+    }
+}
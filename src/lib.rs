@@ -1,11 +1,14 @@
-#![recursion_limit = "128"]
+#![recursion_limit = "256"]
 #![deny(unsafe_code)]
 
+extern crate atty;
 extern crate clockpro_cache;
 extern crate crossbeam_channel;
+extern crate futures;
 extern crate inlinable_string;
 extern crate irc;
 extern crate itertools;
+extern crate native_tls;
 extern crate quantiles;
 extern crate rand;
 extern crate rando;
@@ -13,10 +16,14 @@ extern crate ref_slice;
 extern crate regex;
 extern crate serde;
 extern crate serde_yaml;
+#[cfg(unix)]
+extern crate signal_hook;
 extern crate smallbitvec;
 extern crate smallvec;
 extern crate string_cache;
 extern crate strum;
+extern crate tokio_core;
+extern crate toml;
 extern crate try_map;
 extern crate url;
 extern crate url_serde;
@@ -48,7 +55,10 @@ extern crate quickcheck;
 
 pub use self::core::*;
 
+// This is declared first so that its macros may be used in the other modules.
+#[macro_use]
+mod core;
+
 pub mod modules;
+pub mod testing;
 pub mod util;
-
-mod core;
@@ -0,0 +1,420 @@
+use core::BotCmdAuthLvl as Auth;
+use core::*;
+use std::borrow::Cow;
+use try_map::FallibleMapExt;
+use util;
+use util::to_cow_owned;
+use util::yaml::str::YAML_STR_CHAN;
+use util::yaml::str::YAML_STR_CMD;
+use util::yaml::str::YAML_STR_MSG;
+use util::yaml::str::YAML_STR_NAME;
+use util::yaml::str::YAML_STR_SERVER;
+use util::yaml::str::YAML_STR_TO;
+use util::yaml::FW_SYNTAX_CHECK_FAIL;
+use yaml_rust::Yaml;
+
+pub fn mk() -> Module {
+    mk_module("admin")
+        .command(
+            "say",
+            "{to: '<target>', msg: '<message>'}",
+            "Have the bot send the given message to the given channel or user.",
+            Auth::Admin,
+            Box::new(say),
+            &[],
+        )
+        .command(
+            "raw",
+            "<line>",
+            "Have the bot send the given line to the server verbatim, as an IRC protocol message. \
+             Note that a line containing the character '#' or ':' will need to be enclosed in \
+             quotation marks.",
+            Auth::Admin,
+            Box::new(raw),
+            &[],
+        )
+        .command(
+            "nick",
+            "<nickname>",
+            "Have the bot change its nickname to the given nickname.",
+            Auth::Admin,
+            Box::new(nick),
+            &[],
+        )
+        .command(
+            "alias",
+            "{name: '<alias>', cmd: '<command line>'}",
+            "Define an alias for the given command line, so that a message addressed to the bot \
+             that begins with the alias is taken as beginning with the command line instead. Any \
+             occurrences of `%args%` in the command line are replaced with the rest of such a \
+             message; if there are none, the rest of the message is appended to the command line.",
+            Auth::Admin,
+            Box::new(alias),
+            &[],
+        )
+        .command(
+            "unalias",
+            "<alias>",
+            "Remove an alias defined with the command `alias`.",
+            Auth::Admin,
+            Box::new(unalias),
+            &[],
+        )
+        .command(
+            "enable",
+            "{cmd: '<command>', chan: '[channel]'}",
+            "Allow the given command, or, if it is given as `module:` followed by the name of a \
+             module, all commands of that module, to be used in the given channel (defaults to \
+             the current channel), until the bot is restarted.",
+            Auth::Admin,
+            Box::new(enable),
+            &[],
+        )
+        .command(
+            "disable",
+            "{cmd: '<command>', chan: '[channel]'}",
+            "Prevent the given command, or, if it is given as `module:` followed by the name of a \
+             module, all commands of that module, from being used in the given channel (defaults \
+             to the current channel), until the bot is restarted.",
+            Auth::Admin,
+            Box::new(disable),
+            &[],
+        )
+        .command(
+            "shadowban",
+            "<mask>",
+            "Quietly stop the bot from replying to the users matching the given mask, such as \
+             `*!*@example.com`, until the bot is restarted. Their commands are still run, but the \
+             replies are discarded or sent to the configured review channel instead.",
+            Auth::Admin,
+            Box::new(shadowban),
+            &[],
+        )
+        .command(
+            "unshadowban",
+            "<mask>",
+            "Lift a shadow ban on the users matching the given mask, until the bot is restarted.",
+            Auth::Admin,
+            Box::new(unshadowban),
+            &[],
+        )
+        .command(
+            "shadowbans",
+            "",
+            "List the masks of the users who are shadow-banned on this server.",
+            Auth::Admin,
+            Box::new(shadowbans),
+            &[],
+        )
+        .typed_command(
+            "modules",
+            "List the bot's modules, marking those that have been disabled for exceeding their \
+             configured `module limits`; or, with `stats`, report the resources that each module \
+             has used since the bot started: how many times its handlers have run, how many of \
+             those runs have failed, how long the runs have taken in all, and how much space its \
+             data files take up.",
+            Auth::Admin,
+            typed_cmd!(|ctx, what: Option<String>| modules(ctx, what)),
+            &[],
+        )
+        .command(
+            "reload-config",
+            "",
+            "Have the bot reload its configuration file.",
+            Auth::Admin,
+            Box::new(reload_config),
+            &[],
+        )
+        .command(
+            "reconnect",
+            "{server: '[server]', msg: '[message]'}",
+            "Have the bot disconnect from the server with the given name in its configuration \
+             (defaults to the current server), with an optional quit message, and then connect to \
+             it anew, leaving its connections to other servers be.",
+            Auth::Admin,
+            Box::new(reconnect),
+            &[],
+        )
+        .end()
+}
+
+fn say(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        ..
+    }: HandlerContext,
+    arg: &Yaml,
+) -> Result<Reaction> {
+    let arg = arg.as_hash().expect(FW_SYNTAX_CHECK_FAIL);
+
+    let param = |key, name| {
+        util::yaml::scalar_to_str(
+            arg.get(key).expect(FW_SYNTAX_CHECK_FAIL),
+            Cow::Borrowed,
+            name,
+        )
+    };
+
+    let target = param(&*YAML_STR_TO, "the value of the parameter `to`")?;
+    let msg = param(&*YAML_STR_MSG, "the value of the parameter `msg`")?;
+
+    state.send_privmsg(server_id, &target, &msg)?;
+
+    Ok(Reaction::None)
+}
+
+fn raw(_: HandlerContext, arg: &Yaml) -> Result<Reaction> {
+    Ok(Reaction::RawMsg(util::yaml::scalar_to_str(
+        arg,
+        to_cow_owned,
+        "the argument to the command `raw`",
+    )?))
+}
+
+fn nick(_: HandlerContext, arg: &Yaml) -> Result<Reaction> {
+    Ok(Reaction::RawMsg(
+        format!(
+            "NICK {}",
+            util::yaml::scalar_to_str(arg, Cow::Borrowed, "the argument to the command `nick`")?
+        )
+        .into(),
+    ))
+}
+
+fn alias(HandlerContext { state, .. }: HandlerContext, arg: &Yaml) -> Result<Reaction> {
+    let arg = arg.as_hash().expect(FW_SYNTAX_CHECK_FAIL);
+
+    let param = |key, name| {
+        util::yaml::scalar_to_str(
+            arg.get(key).expect(FW_SYNTAX_CHECK_FAIL),
+            Cow::Borrowed,
+            name,
+        )
+    };
+
+    let name = param(&*YAML_STR_NAME, "the value of the parameter `name`")?;
+    let cmd = param(&*YAML_STR_CMD, "the value of the parameter `cmd`")?;
+
+    state.set_alias(&name, &cmd)?;
+
+    Ok(Reaction::Reply(format!("Alias `{}` defined.", name).into()))
+}
+
+fn unalias(HandlerContext { state, .. }: HandlerContext, arg: &Yaml) -> Result<Reaction> {
+    let name =
+        util::yaml::scalar_to_str(arg, Cow::Borrowed, "the argument to the command `unalias`")?;
+
+    Ok(Reaction::Reply(
+        if state.remove_alias(&name)? {
+            format!("Alias `{}` removed.", name)
+        } else {
+            format!("There is no alias `{}` to remove.", name)
+        }
+        .into(),
+    ))
+}
+
+fn enable(ctx: HandlerContext, arg: &Yaml) -> Result<BotCmdResult> {
+    toggle_command(ctx, arg, true)
+}
+
+fn disable(ctx: HandlerContext, arg: &Yaml) -> Result<BotCmdResult> {
+    toggle_command(ctx, arg, false)
+}
+
+fn toggle_command(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, target },
+        ..
+    }: HandlerContext,
+    arg: &Yaml,
+    enabled: bool,
+) -> Result<BotCmdResult> {
+    let arg = arg.as_hash().expect(FW_SYNTAX_CHECK_FAIL);
+
+    let cmd = util::yaml::scalar_to_str(
+        arg.get(&YAML_STR_CMD).expect(FW_SYNTAX_CHECK_FAIL),
+        Cow::Borrowed,
+        "the value of the parameter `cmd`",
+    )?;
+
+    let chan = arg.get(&YAML_STR_CHAN).try_map(|y| {
+        util::yaml::scalar_to_str(y, Cow::Borrowed, "the value of the parameter `chan`")
+    })?;
+
+    let chan = match (chan, target) {
+        (Some(c), _) => c,
+        (None, t) if t == state.nick(server_id).unwrap_or("".into()) => {
+            return Ok(BotCmdResult::ArgMissing1To1("channel".into()))
+        }
+        (None, t) => t.into(),
+    };
+
+    state.set_command_enabled(server_id, &chan, &cmd, enabled)?;
+
+    Ok(Reaction::Reply(
+        format!(
+            "`{}` {} in {}.",
+            cmd,
+            if enabled { "enabled" } else { "disabled" },
+            chan
+        )
+        .into(),
+    )
+    .into())
+}
+
+fn shadowban(ctx: HandlerContext, arg: &Yaml) -> Result<Reaction> {
+    toggle_shadow_ban(ctx, arg, true)
+}
+
+fn unshadowban(ctx: HandlerContext, arg: &Yaml) -> Result<Reaction> {
+    toggle_shadow_ban(ctx, arg, false)
+}
+
+fn toggle_shadow_ban(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        ..
+    }: HandlerContext,
+    arg: &Yaml,
+    banned: bool,
+) -> Result<Reaction> {
+    let mask = util::yaml::scalar_to_str(arg, Cow::Borrowed, "the mask")?;
+
+    state.set_shadow_banned(server_id, &mask, banned)?;
+
+    Ok(Reaction::Reply(
+        format!(
+            "Shadow ban on `{}` {}.",
+            mask,
+            if banned { "imposed" } else { "lifted" }
+        )
+        .into(),
+    ))
+}
+
+fn shadowbans(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        ..
+    }: HandlerContext,
+    _: &Yaml,
+) -> Result<Reaction> {
+    let masks = state.shadow_bans(server_id)?;
+
+    Ok(Reaction::Reply(
+        if masks.is_empty() {
+            "No one is shadow-banned.".to_owned()
+        } else {
+            format!("Shadow-banned: {}", masks.join(", "))
+        }
+        .into(),
+    ))
+}
+
+fn modules(
+    HandlerContext { state, .. }: HandlerContext,
+    what: Option<String>,
+) -> Result<BotCmdResult> {
+    let usage = state.module_usage()?;
+
+    match what.as_ref().map(|s| &s[..]) {
+        None => Ok(Reaction::Reply(
+            format!(
+                "Modules: {}",
+                usage
+                    .iter()
+                    .map(|(name, usage)| if usage.disabled {
+                        format!("{} (disabled)", name)
+                    } else {
+                        name.to_owned()
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into(),
+        )
+        .into()),
+        Some("stats") => {
+            let replies = usage
+                .iter()
+                .map(|(name, usage)| {
+                    let storage = match state.module_storage_size(name) {
+                        Ok(bytes) => format!("{} bytes", bytes),
+                        Err(e) => {
+                            warn!("Failed to measure storage of module {:?}: {}", name, e);
+                            "an unknown amount".to_owned()
+                        }
+                    };
+
+                    format!(
+                        "{name}: {invocations} handler runs ({errors} failed), taking {ms} ms in \
+                         all; {storage} of data{disabled}.",
+                        name = name,
+                        invocations = usage.invocations,
+                        errors = usage.errors,
+                        ms = usage.run_time.as_secs() * 1000
+                            + u64::from(usage.run_time.subsec_millis()),
+                        storage = storage,
+                        disabled = if usage.disabled { "; disabled" } else { "" },
+                    )
+                    .into()
+                })
+                .collect::<Vec<_>>();
+
+            Ok(Reaction::Msgs(replies.into()).into())
+        }
+        Some(_) => Ok(BotCmdResult::SyntaxErr),
+    }
+}
+
+fn reload_config(HandlerContext { state, .. }: HandlerContext, _: &Yaml) -> Result<Reaction> {
+    state.reload_config()?;
+
+    Ok(Reaction::Reply("Configuration reloaded.".into()))
+}
+
+fn reconnect(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        ..
+    }: HandlerContext,
+    arg: &Yaml,
+) -> Result<Reaction> {
+    let arg = arg.as_hash().expect(FW_SYNTAX_CHECK_FAIL);
+
+    let server = arg.get(&YAML_STR_SERVER).try_map(|y| {
+        util::yaml::scalar_to_str(y, Cow::Borrowed, "the value of the parameter `server`")
+    })?;
+
+    let comment = arg.get(&YAML_STR_MSG).try_map(|y| {
+        util::yaml::scalar_to_str(y, to_cow_owned, "the value of the parameter `msg`")
+    })?;
+
+    match server {
+        Some(name) => {
+            state.reconnect_server(&name, comment)?;
+
+            // A reply on the server being reconnected to would likely be lost with the
+            // connection, but one on another server is not.
+            if state.server_name(server_id)? == name {
+                Ok(Reaction::None)
+            } else {
+                Ok(Reaction::Reply(
+                    format!("Reconnecting to {:?}.", name).into(),
+                ))
+            }
+        }
+        None => {
+            state.reconnect(server_id, comment)?;
+
+            Ok(Reaction::None)
+        }
+    }
+}
@@ -4,6 +4,7 @@ use regex::Captures;
 use std::borrow::Cow;
 use try_map::FallibleMapExt;
 use util;
+use util::fmt::human_duration;
 use util::to_cow_owned;
 use util::yaml::str::YAML_STR_CHAN;
 use util::yaml::str::YAML_STR_CMD;
@@ -41,6 +42,15 @@ pub fn mk() -> Module {
             Box::new(quit),
             &[],
         )
+        .command(
+            "dcc-chat",
+            "",
+            "Have the bot offer you a DCC CHAT session, in which you may use the bot's commands as \
+             in one-to-one messaging.",
+            Auth::Admin,
+            Box::new(dcc_chat),
+            &[],
+        )
         .command(
             "ping",
             "",
@@ -66,6 +76,47 @@ pub fn mk() -> Module {
             Box::new(help),
             &[],
         )
+        .command(
+            "more",
+            "",
+            "Request the next page of the output of a command that produced more messages than the \
+             bot sends at once.",
+            Auth::Public,
+            Box::new(more),
+            &[],
+        )
+        .typed_command(
+            "stats",
+            "Request statistics about the bot, such as its uptime, version, and memory usage, and \
+             the age, lag, and message counts of its connections to servers; or, with `commands`, \
+             which only administrators may use, about the use of its commands.",
+            Auth::Public,
+            typed_cmd!(|ctx, what: Option<String>| stats(ctx, what)),
+            &[],
+        )
+        .typed_command(
+            "get",
+            "Show the preferences that you have set, or the given one.",
+            Auth::Public,
+            typed_cmd!(|ctx, name: Option<String>| get(ctx, name)),
+            &[],
+        )
+        .typed_command(
+            "set",
+            "Set the given preference, such as `timezone`, `locale`, `reply-style`, or `logging`, \
+             to the given value, or unset it if no value is given.",
+            Auth::Public,
+            typed_cmd!(|ctx, name: String, value: Option<RestOfLine>| set(ctx, &name, value)),
+            &[],
+        )
+        .typed_command(
+            "auth",
+            "Request a challenge with which to authenticate yourself as an administrator of the \
+             bot, or answer it with the given response. Use this command in private.",
+            Auth::Public,
+            typed_cmd!(|ctx, response: Option<String>| auth(ctx, response)),
+            &[],
+        )
         .trigger(
             "yes?",
             "^$",
@@ -137,6 +188,14 @@ fn quit(_: HandlerContext, arg: &Yaml) -> Result<Reaction> {
     Ok(Reaction::Quit(comment))
 }
 
+fn dcc_chat(_: HandlerContext, _: &Yaml) -> BotCmdResult {
+    Reaction::OfferDccChat.into()
+}
+
+fn more(_: HandlerContext, _: &Yaml) -> BotCmdResult {
+    Reaction::NextPage.into()
+}
+
 fn ping(_: HandlerContext, _: &Yaml) -> BotCmdResult {
     Reaction::Reply("pong".into()).into()
 }
@@ -154,17 +213,43 @@ fn bot_fw_info(HandlerContext { state, .. }: HandlerContext, _: &Yaml) -> BotCmd
     .into()
 }
 
-fn help(HandlerContext { state, .. }: HandlerContext, arg: &Yaml) -> BotCmdResult {
+fn help(
+    HandlerContext {
+        state,
+        request_origin,
+        invoker,
+        ..
+    }: HandlerContext,
+    arg: &Yaml,
+) -> BotCmdResult {
+    let localize =
+        |key, params: &[(&str, &str)]| state.localize(request_origin, invoker, key, params);
+
     let arg = arg.as_hash();
 
     let cmd = arg.and_then(|m| m.get(&YAML_STR_CMD));
     let list = arg.and_then(|m| m.get(&YAML_STR_LIST));
 
     if [cmd, list].iter().filter(|x| x.is_some()).count() > 1 {
-        return Reaction::Msg("Please ask for help with one thing at a time.".into()).into();
+        return Reaction::Msg(localize("help: one thing at a time", &[]).into()).into();
     }
 
     if let Some(&Yaml::String(ref cmd_name)) = cmd {
+        let cmd = match state.command(request_origin.server_id, cmd_name) {
+            Ok(Some(c)) => c,
+            Ok(None) => {
+                return Reaction::Msg(
+                    localize(
+                        "help: command not found",
+                        &[("command", &format!("{:?}", cmd_name))],
+                    )
+                    .into(),
+                )
+                .into()
+            }
+            Err(e) => return BotCmdResult::LibErr(e),
+        };
+
         let &BotCommand {
             ref name,
             ref provider,
@@ -172,19 +257,24 @@ fn help(HandlerContext { state, .. }: HandlerContext, arg: &Yaml) -> BotCmdResul
             ref usage_str,
             ref help_msg,
             ..
-        } = match state.command(cmd_name) {
-            Ok(Some(c)) => c,
-            Ok(None) => {
-                return Reaction::Msg(format!("Command {:?} not found.", cmd_name).into()).into()
-            }
-            Err(e) => return BotCmdResult::LibErr(e),
-        };
+        } = &*cmd;
 
         Reaction::Msgs(
             vec![
-                format!("= Help for command {:?}:", name).into(),
-                format!("- [module {:?}, auth level {:?}]", provider.name, auth_lvl).into(),
-                format!("- Syntax: {} {}", name, usage_str).into(),
+                localize("help: command", &[("command", &format!("{:?}", name))]).into(),
+                localize(
+                    "help: command details",
+                    &[
+                        ("module", &format!("{:?}", provider.name)),
+                        ("auth level", &format!("{:?}", auth_lvl)),
+                    ],
+                )
+                .into(),
+                localize(
+                    "help: command syntax",
+                    &[("command", name), ("syntax", usage_str)],
+                )
+                .into(),
                 help_msg.clone(),
             ]
             .into(),
@@ -192,20 +282,28 @@ fn help(HandlerContext { state, .. }: HandlerContext, arg: &Yaml) -> BotCmdResul
         .into()
     } else if let Some(&Yaml::String(ref list_name)) = list {
         let list_names = ["commands", "lists"];
+        let lists = format!("{:?}", list_names);
 
         if list_name == "commands" {
-            Reaction::Msg(format!("Available commands: {:?}", state.command_names()).into()).into()
+            Reaction::Msg(
+                localize(
+                    "help: commands",
+                    &[("commands", &format!("{:?}", state.command_names()))],
+                )
+                .into(),
+            )
+            .into()
         } else if list_name == "lists" {
-            Reaction::Msg(format!("Available lists: {:?}", list_names).into()).into()
+            Reaction::Msg(localize("help: lists", &[("lists", &lists)]).into()).into()
         } else {
             if list_names.contains(&list_name.as_ref()) {
                 error!("Help list {:?} declared but not defined.", list_name);
             }
 
             Reaction::Msg(
-                format!(
-                    "List {:?} not found. Available lists: {:?}",
-                    list_name, list_names
+                localize(
+                    "help: list not found",
+                    &[("list", &format!("{:?}", list_name)), ("lists", &lists)],
                 )
                 .into(),
             )
@@ -214,12 +312,11 @@ fn help(HandlerContext { state, .. }: HandlerContext, arg: &Yaml) -> BotCmdResul
     } else {
         Reaction::Msgs(
             vec![
-                "For help with a command named 'foo', try `help cmd: foo`.".into(),
-                "To see a list of all available commands, try `help list: commands`.".into(),
-                format!(
-                    "For this bot software's documentation, including an introduction to the \
-                     command syntax, see <{homepage}>",
-                    homepage = state.framework_homepage_url_str(),
+                localize("help: introduction", &[]).into(),
+                localize("help: introduction to lists", &[]).into(),
+                localize(
+                    "help: documentation",
+                    &[("homepage", state.framework_homepage_url_str())],
                 )
                 .into(),
             ]
@@ -229,6 +326,277 @@ fn help(HandlerContext { state, .. }: HandlerContext, arg: &Yaml) -> BotCmdResul
     }
 }
 
+fn get(
+    HandlerContext {
+        state,
+        request_origin,
+        invoker,
+        ..
+    }: HandlerContext,
+    name: Option<String>,
+) -> Result<Reaction> {
+    let localize =
+        |key, params: &[(&str, &str)]| state.localize(request_origin, invoker, key, params);
+
+    let prefs = state.user_prefs(request_origin.server_id, invoker)?;
+
+    let reply = match name {
+        Some(name) => match prefs.get(&name) {
+            Some(value) => localize("preference", &[("name", &name), ("value", value)]),
+            None => localize("preference not set", &[("name", &name)]),
+        },
+        None if prefs.is_empty() => localize(
+            "no preferences",
+            &[(
+                "preferences",
+                &USER_PREFS
+                    .iter()
+                    .map(|&(name, values)| format!("{} ({})", name, values))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            )],
+        ),
+        None => localize(
+            "preferences",
+            &[(
+                "preferences",
+                &prefs
+                    .iter()
+                    .map(|(name, value)| format!("{}: {}", name, value))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            )],
+        ),
+    };
+
+    Ok(Reaction::Reply(reply.into()))
+}
+
+fn set(
+    HandlerContext {
+        state,
+        request_origin,
+        invoker,
+        ..
+    }: HandlerContext,
+    name: &str,
+    value: Option<RestOfLine>,
+) -> Result<Reaction> {
+    let name = name.to_lowercase();
+
+    state.set_user_pref(
+        request_origin.server_id,
+        invoker,
+        &name,
+        value.as_ref().map(|v| &v.0[..]),
+    )?;
+
+    let reply = match state.user_pref(request_origin.server_id, invoker, &name)? {
+        Some(value) => state.localize(
+            request_origin,
+            invoker,
+            "preference set",
+            &[("name", &name), ("value", &value)],
+        ),
+        None => state.localize(
+            request_origin,
+            invoker,
+            "preference unset",
+            &[("name", &name)],
+        ),
+    };
+
+    Ok(Reaction::Reply(reply.into()))
+}
+
+fn stats(ctx: HandlerContext, what: Option<String>) -> Result<BotCmdResult> {
+    match what.as_ref().map(|s| &s[..]) {
+        None => Ok(bot_stats(ctx)?.into()),
+        Some("commands") => {
+            if ctx
+                .state
+                .have_admin(ctx.request_origin.server_id, ctx.invoker)?
+            {
+                Ok(command_stats(ctx)?.into())
+            } else {
+                Ok(BotCmdResult::Unauthorized)
+            }
+        }
+        Some(_) => Ok(BotCmdResult::SyntaxErr),
+    }
+}
+
+fn bot_stats(
+    HandlerContext {
+        state,
+        request_origin,
+        invoker,
+        ..
+    }: HandlerContext,
+) -> Result<Reaction> {
+    let localize =
+        |key, params: &[(&str, &str)]| state.localize(request_origin, invoker, key, params);
+
+    let memory = match state.memory_usage() {
+        Some(bytes) => format!("{:.1} MiB", bytes as f64 / (1024.0 * 1024.0)),
+        None => localize("stats: unknown", &[]),
+    };
+
+    let mut replies = vec![localize(
+        "stats: bot",
+        &[
+            ("version", state.framework_version_str()),
+            ("commit", state.framework_commit_str()),
+            ("uptime", &human_duration(state.uptime())),
+            ("memory", &memory),
+            ("modules", &state.module_names().join(", ")),
+        ],
+    )];
+
+    for server_id in state.server_ids() {
+        let server = state.server_name(server_id)?;
+        let (received, sent) = state.msg_counts(server_id);
+        let (received, sent) = (received.to_string(), sent.to_string());
+
+        replies.push(match state.connection_age(server_id)? {
+            Some(age) => {
+                let lag = match state.server_lag(server_id)? {
+                    Some(lag) => format!(
+                        "{} ms",
+                        lag.as_secs() * 1000 + u64::from(lag.subsec_millis())
+                    ),
+                    None => localize("stats: unknown", &[]),
+                };
+
+                localize(
+                    "stats: connected",
+                    &[
+                        ("server", &server),
+                        ("age", &human_duration(age)),
+                        ("lag", &lag),
+                        ("received", &received),
+                        ("sent", &sent),
+                    ],
+                )
+            }
+            None => localize(
+                "stats: disconnected",
+                &[
+                    ("server", &server),
+                    ("received", &received),
+                    ("sent", &sent),
+                ],
+            ),
+        });
+    }
+
+    Ok(Reaction::Msgs(
+        replies
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<_>>()
+            .into(),
+    ))
+}
+
+fn command_stats(
+    HandlerContext {
+        state,
+        request_origin,
+        invoker,
+        ..
+    }: HandlerContext,
+) -> Result<Reaction> {
+    let localize =
+        |key, params: &[(&str, &str)]| state.localize(request_origin, invoker, key, params);
+
+    let mut usage = state.command_usage()?;
+
+    for name in state.command_names()? {
+        usage.entry(name.into_owned()).or_default();
+    }
+
+    let mut usage = usage.into_iter().collect::<Vec<_>>();
+    usage.sort_by(|(a_name, a), (b_name, b)| {
+        b.invocations
+            .cmp(&a.invocations)
+            .then_with(|| a_name.cmp(b_name))
+    });
+
+    let replies = usage
+        .iter()
+        .map(|(name, usage)| {
+            if usage.invocations == 0 {
+                return localize("stats: command unused", &[("command", name)]);
+            }
+
+            let latency = |percentile| match usage.latency_percentile(percentile) {
+                Some(t) => format!("{} ms", t.as_secs() * 1000 + u64::from(t.subsec_millis())),
+                None => localize("stats: unknown", &[]),
+            };
+
+            localize(
+                "stats: command",
+                &[
+                    ("command", name),
+                    ("uses", &usage.invocations.to_string()),
+                    ("errors", &usage.errors.to_string()),
+                    ("timeouts", &usage.timeouts.to_string()),
+                    ("p50", &latency(50)),
+                    ("p90", &latency(90)),
+                    ("p99", &latency(99)),
+                ],
+            )
+        })
+        .map(Into::into)
+        .collect::<Vec<_>>();
+
+    Ok(Reaction::Msgs(replies.into()))
+}
+
+fn auth(
+    HandlerContext {
+        state,
+        request_origin,
+        invoker,
+        ..
+    }: HandlerContext,
+    response: Option<String>,
+) -> Result<Reaction> {
+    let localize =
+        |key, params: &[(&str, &str)]| state.localize(request_origin, invoker, key, params);
+
+    let MsgDest { server_id, target } = request_origin;
+
+    if state
+        .server_capabilities(server_id)?
+        .is_channel_name(target)
+    {
+        return Ok(Reaction::Reply(localize("auth: use privately", &[]).into()));
+    }
+
+    let nick = match invoker.nick {
+        Some(nick) if state.admin_auth_enabled() => nick,
+        _ => return Ok(Reaction::Reply(localize("auth: unavailable", &[]).into())),
+    };
+
+    let reply = match response {
+        None => match state.issue_admin_challenge(server_id, nick)? {
+            Some(challenge) => localize("auth: challenge", &[("challenge", &challenge)]),
+            None => localize("auth: unavailable", &[]),
+        },
+        Some(response) => match state.answer_admin_challenge(server_id, nick, &response)? {
+            Some(session_length) => localize(
+                "auth: granted",
+                &[("seconds", &session_length.as_secs().to_string())],
+            ),
+            None => localize("auth: denied", &[]),
+        },
+    };
+
+    Ok(Reaction::Reply(reply.into()))
+}
+
 fn empty_msg_trigger(_: HandlerContext, _: Captures) -> Reaction {
     Reaction::Msg("Yes?".into())
 }
@@ -0,0 +1,390 @@
+use core::BotCmdAuthLvl as Auth;
+use core::*;
+use serde_yaml;
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+
+/// The prefix that marks a factoid's value as the key of another factoid, of which the factoid is
+/// an alias
+const ALIAS_PREFIX: &str = "<alias>";
+
+/// The prefix that marks a factoid's value as a reply to be given as it is, rather than as
+/// `key is value`
+const REPLY_PREFIX: &str = "<reply>";
+
+/// How many aliases to follow in looking up a factoid before giving up, lest aliases form a cycle
+const MAX_ALIAS_DEPTH: usize = 5;
+
+/// This bot module remembers factoids that users teach it, and recites them when asked.
+///
+/// A message addressed to the bot of the form `X is Y`, if it doesn't begin with the name of a
+/// command, teaches the bot that X is Y, replacing anything that it has learned about X before.
+/// Thereafter, a message addressed to the bot consisting of X, perhaps followed by a question
+/// mark, is answered with `X is Y`. Keys are compared ignoring case and extra whitespace.
+///
+/// If Y begins with `<reply>`, the rest of Y is given as the answer on its own; if Y begins with
+/// `<alias>`, the rest of Y is taken as another key, whose factoid is recited in place of X's.
+/// In answers, `$nick` is replaced with the nickname of the user asking, and `$channel`, with the
+/// name of the channel in which the question was asked.
+///
+/// The command `literal X` shows X's factoid without interpreting it, and `forget X` makes the
+/// bot forget it. An administrator of the bot may lock a factoid with `factoid-lock X`, so that
+/// only administrators may change or forget it, and unlock it with `factoid-unlock X`.
+///
+/// Factoids are stored in the file `factoids.yaml` in the module data directory, so that they are
+/// kept when the bot is restarted. Each server has its own factoids.
+pub fn mk() -> Module {
+    mk_module("factoids")
+        .on_load(Box::new(on_load))
+        .on_unload(Box::new(on_unload))
+        .on_unknown_command(Box::new(on_unknown_command))
+        .typed_command(
+            "literal",
+            "Show the given factoid as it was taught, without interpreting it.",
+            Auth::Public,
+            typed_cmd!(|ctx, key: RestOfLine| literal(ctx, &key.0)),
+            &[],
+        )
+        .typed_command(
+            "forget",
+            "Make the bot forget the given factoid.",
+            Auth::Public,
+            typed_cmd!(|ctx, key: RestOfLine| forget(ctx, &key.0)),
+            &[],
+        )
+        .typed_command(
+            "factoid-lock",
+            "Lock the given factoid, so that only the bot's administrators may change it.",
+            Auth::Admin,
+            typed_cmd!(|ctx, key: RestOfLine| set_locked(ctx, &key.0, true)),
+            &[],
+        )
+        .typed_command(
+            "factoid-unlock",
+            "Unlock the given factoid, so that anyone may change it.",
+            Auth::Admin,
+            typed_cmd!(|ctx, key: RestOfLine| set_locked(ctx, &key.0, false)),
+            &[],
+        )
+        .end()
+}
+
+lazy_static! {
+    static ref FACTOIDS: Mutex<BTreeMap<String, BTreeMap<String, Factoid>>> =
+        Mutex::new(Default::default());
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Factoid {
+    /// The key as it was given when the factoid was taught
+    key: String,
+
+    value: String,
+
+    /// The nickname of the user who taught the bot the factoid
+    set_by: String,
+
+    #[serde(default)]
+    locked: bool,
+}
+
+/// Returns the given key with its case and whitespace normalized, and any question mark at its
+/// end removed.
+fn normalize_key(key: &str) -> String {
+    key.trim()
+        .trim_end_matches('?')
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Splits a message of the form `X is Y` into X and Y.
+fn parse_definition(text: &str) -> Option<(&str, &str)> {
+    let i = text.to_ascii_lowercase().find(" is ")?;
+    let (key, value) = (text[..i].trim(), text[i + 4..].trim());
+
+    if key.is_empty() || value.is_empty() {
+        return None;
+    }
+
+    Some((key, value))
+}
+
+/// Returns the factoid with the given key, following aliases.
+fn look_up<'a>(factoids: &'a BTreeMap<String, Factoid>, key: &str) -> Option<&'a Factoid> {
+    let mut factoid = factoids.get(&normalize_key(key))?;
+
+    for _ in 0..MAX_ALIAS_DEPTH {
+        if !factoid.value.starts_with(ALIAS_PREFIX) {
+            break;
+        }
+
+        factoid = factoids.get(&normalize_key(&factoid.value[ALIAS_PREFIX.len()..]))?;
+    }
+
+    Some(factoid)
+}
+
+/// Returns the answer to give to the question consisting of the key of the given factoid, asked
+/// by the given user in the given channel.
+fn recite(factoid: &Factoid, nick: &str, channel: &str) -> String {
+    let answer = if factoid.value.starts_with(REPLY_PREFIX) {
+        factoid.value[REPLY_PREFIX.len()..].trim().to_owned()
+    } else {
+        format!("{} is {}", factoid.key, factoid.value)
+    };
+
+    answer.replace("$nick", nick).replace("$channel", channel)
+}
+
+fn lock_factoids() -> Result<MutexGuard<'static, BTreeMap<String, BTreeMap<String, Factoid>>>> {
+    FACTOIDS
+        .lock()
+        .map_err(|_| ErrorKind::LockPoisoned("the factoids".into()).into())
+}
+
+fn on_load(state: &State) -> Result<()> {
+    let path = state.module_data_path()?.join("factoids.yaml");
+
+    if !path.exists() {
+        return Ok(());
+    }
+
+    *lock_factoids()? = serde_yaml::from_reader(fs::File::open(path)?)?;
+
+    Ok(())
+}
+
+fn on_unload(state: &State) -> Result<()> {
+    let factoids = lock_factoids()?;
+
+    save(state, &factoids)
+}
+
+fn save(state: &State, factoids: &BTreeMap<String, BTreeMap<String, Factoid>>) -> Result<()> {
+    let dir = state.module_data_path()?;
+
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join("factoids.yaml"), serde_yaml::to_string(factoids)?)?;
+
+    Ok(())
+}
+
+fn on_unknown_command(
+    state: &State,
+    metadata: &MsgMetadata,
+    cmd_name: &str,
+    cmd_args: &str,
+) -> Option<Result<Reaction>> {
+    let text = format!("{} {}", cmd_name, cmd_args);
+
+    match parse_definition(&text) {
+        Some((key, value)) => Some(learn(state, metadata, key, value)),
+        None => answer(state, metadata, &text).transpose(),
+    }
+}
+
+fn learn(state: &State, metadata: &MsgMetadata, key: &str, value: &str) -> Result<Reaction> {
+    let server_id = metadata.dest.server_id;
+    let server = state.server_name(server_id)?;
+    let mut factoids = lock_factoids()?;
+
+    {
+        let factoids = factoids.entry(server).or_insert_with(Default::default);
+        let normalized = normalize_key(key);
+
+        let locked = factoids
+            .get(&normalized)
+            .map(|factoid| factoid.locked)
+            .unwrap_or(false);
+
+        if locked && !state.have_admin(server_id, metadata.prefix)? {
+            return Ok(Reaction::Reply(
+                format!("The factoid {:?} is locked.", key).into(),
+            ));
+        }
+
+        factoids.insert(
+            normalized,
+            Factoid {
+                key: key.to_owned(),
+                value: value.to_owned(),
+                set_by: metadata.prefix.nick.unwrap_or_default().to_owned(),
+                locked,
+            },
+        );
+    }
+
+    save(state, &factoids)?;
+
+    Ok(Reaction::Reply("OK.".into()))
+}
+
+fn answer(state: &State, metadata: &MsgMetadata, key: &str) -> Result<Option<Reaction>> {
+    let server = state.server_name(metadata.dest.server_id)?;
+    let factoids = lock_factoids()?;
+
+    Ok(factoids
+        .get(&server)
+        .and_then(|factoids| look_up(factoids, key))
+        .map(|factoid| {
+            Reaction::Msg(
+                recite(
+                    factoid,
+                    metadata.prefix.nick.unwrap_or_default(),
+                    metadata.dest.target,
+                )
+                .into(),
+            )
+        }))
+}
+
+fn literal(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        ..
+    }: HandlerContext,
+    key: &str,
+) -> Result<Reaction> {
+    let server = state.server_name(server_id)?;
+    let factoids = lock_factoids()?;
+
+    Ok(Reaction::Reply(
+        match factoids
+            .get(&server)
+            .and_then(|factoids| factoids.get(&normalize_key(key)))
+        {
+            Some(factoid) => format!(
+                "{} is {} (taught by {}{})",
+                factoid.key,
+                factoid.value,
+                factoid.set_by,
+                if factoid.locked { "; locked" } else { "" }
+            ),
+            None => format!("I don't know anything about {:?}.", key),
+        }
+        .into(),
+    ))
+}
+
+fn forget(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        invoker,
+        ..
+    }: HandlerContext,
+    key: &str,
+) -> Result<Reaction> {
+    let server = state.server_name(server_id)?;
+    let mut factoids = lock_factoids()?;
+
+    {
+        let factoids = match factoids.get_mut(&server) {
+            Some(factoids) => factoids,
+            None => return Ok(Reaction::Reply("I didn't know that anyway.".into())),
+        };
+        let normalized = normalize_key(key);
+
+        match factoids.get(&normalized) {
+            Some(factoid) if factoid.locked && !state.have_admin(server_id, invoker)? => {
+                return Ok(Reaction::Reply(
+                    format!("The factoid {:?} is locked.", key).into(),
+                ));
+            }
+            Some(_) => {}
+            None => return Ok(Reaction::Reply("I didn't know that anyway.".into())),
+        }
+
+        factoids.remove(&normalized);
+    }
+
+    save(state, &factoids)?;
+
+    Ok(Reaction::Reply(format!("I've forgotten {:?}.", key).into()))
+}
+
+fn set_locked(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        ..
+    }: HandlerContext,
+    key: &str,
+    locked: bool,
+) -> Result<Reaction> {
+    let server = state.server_name(server_id)?;
+    let mut factoids = lock_factoids()?;
+
+    match factoids
+        .get_mut(&server)
+        .and_then(|factoids| factoids.get_mut(&normalize_key(key)))
+    {
+        Some(factoid) => factoid.locked = locked,
+        None => {
+            return Ok(Reaction::Reply(
+                format!("I don't know anything about {:?}.", key).into(),
+            ))
+        }
+    }
+
+    save(state, &factoids)?;
+
+    Ok(Reaction::Reply(
+        format!(
+            "The factoid {:?} is now {}.",
+            key,
+            if locked { "locked" } else { "unlocked" }
+        )
+        .into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn definitions() {
+        assert_eq!(
+            parse_definition("Rust  IS a language"),
+            Some(("Rust", "a language"))
+        );
+        assert_eq!(
+            parse_definition("the answer is 42 is it not"),
+            Some(("the answer", "42 is it not"))
+        );
+        assert_eq!(parse_definition("is it?"), None);
+        assert_eq!(normalize_key(" The  Answer? "), "the answer");
+    }
+
+    #[test]
+    fn reciting() {
+        let factoid = |key: &str, value: &str| Factoid {
+            key: key.into(),
+            value: value.into(),
+            set_by: "alice".into(),
+            locked: false,
+        };
+
+        let mut factoids = BTreeMap::new();
+        factoids.insert("rust".into(), factoid("Rust", "a language"));
+        factoids.insert(
+            "greet".into(),
+            factoid("greet", "<reply>Hi, $nick, in $channel!"),
+        );
+        factoids.insert("hello".into(), factoid("hello", "<alias>greet"));
+        factoids.insert("loop".into(), factoid("loop", "<alias>loop"));
+
+        let ask = |key| look_up(&factoids, key).map(|f| recite(f, "bob", "#c"));
+
+        assert_eq!(ask("RUST?"), Some("Rust is a language".into()));
+        assert_eq!(ask("hello"), Some("Hi, bob, in #c!".into()));
+        assert_eq!(ask("loop"), Some("loop is <alias>loop".into()));
+        assert_eq!(ask("nothing"), None);
+    }
+}
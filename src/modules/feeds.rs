@@ -0,0 +1,521 @@
+use core::*;
+use serde_yaml;
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use std::time::Instant;
+use url::Url;
+use util::html::decode_char_refs;
+
+/// How often to check whether any feed is due to be polled
+const TICK: Duration = Duration::from_secs(60);
+
+/// How many entries' GUIDs to remember for each feed, at most
+const MAX_REMEMBERED_GUIDS: usize = 500;
+
+/// How many new entries of a feed to announce after one poll, at most, lest a feed flood its
+/// channels
+const MAX_ANNOUNCED_PER_POLL: usize = 5;
+
+/// This bot module polls RSS and Atom feeds and announces their new entries in channels.
+///
+/// The feeds are listed in the module's settings, which are given in the configuration field
+/// `module settings`, under the key `feeds`, in the field `feeds`. The value of this field should
+/// be a sequence of mappings, each of which describes a feed, with the following fields, listed
+/// by their keys:
+///
+/// - `url` — The value of this field should be a string, which is the `http` or `https` URL of
+/// the feed.
+///
+/// - `channels` — The value of this field should be a sequence of strings, which are the
+/// channels in which to announce the feed's new entries.
+///
+/// - `server` — The value of this field, if specified, should be the name of the server in whose
+/// channels alone to announce the entries. This field is optional; if it is not specified, the
+/// entries are announced in the given channels on every server.
+///
+/// - `name` — The value of this field, if specified, should be a string, which is the name by
+/// which to refer to the feed in announcements. This field is optional; its value defaults to
+/// the feed's own title.
+///
+/// - `template` — The value of this field, if specified, should be a string, which is the
+/// template of each announcement, in which `{feed}` is replaced with the feed's name, `{title}`,
+/// with the entry's title, and `{link}`, with the entry's link. This field is optional; its value
+/// defaults to `[{feed}] {title} <{link}>`.
+///
+/// - `interval` — The value of this field, if specified, should be a positive integer, which is
+/// to be used as the number of seconds to wait between polls of the feed. This field is
+/// optional; its value defaults to 900, i.e., fifteen minutes. Feeds are polled no more often
+/// than once a minute.
+///
+/// Entries are told apart by their GUIDs, or, lacking those, by their links. The GUIDs of the
+/// entries seen lately are stored in the file `feeds.yaml` in the module data directory, so that
+/// entries are not announced again when the bot is restarted. When a feed is polled for the
+/// first time, its entries are recorded as seen without being announced.
+pub fn mk() -> Module {
+    mk_module("feeds")
+        .on_load(Box::new(on_load))
+        .on_unload(Box::new(on_unload))
+        .periodic(TICK, Box::new(poll))
+        .end()
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Settings {
+    #[serde(default)]
+    feeds: Vec<FeedSettings>,
+}
+
+#[derive(Debug, Deserialize)]
+struct FeedSettings {
+    url: String,
+
+    channels: Vec<String>,
+
+    #[serde(default)]
+    server: Option<String>,
+
+    #[serde(default)]
+    name: Option<String>,
+
+    #[serde(default = "default_template")]
+    template: String,
+
+    #[serde(default = "default_interval")]
+    interval: u64,
+}
+
+fn default_template() -> String {
+    "[{feed}] {title} <{link}>".into()
+}
+
+fn default_interval() -> u64 {
+    15 * 60
+}
+
+lazy_static! {
+    static ref FEEDS: Mutex<Feeds> = Mutex::new(Default::default());
+}
+
+#[derive(Debug, Default)]
+struct Feeds {
+    /// The GUIDs of the entries seen lately in each feed, oldest first, by the feed's URL
+    seen: BTreeMap<String, Vec<String>>,
+
+    /// When each feed, by URL, was last polled
+    last_polled: BTreeMap<String, Instant>,
+}
+
+/// A feed, as parsed from an RSS or Atom document
+#[derive(Debug, Default)]
+struct Feed {
+    title: Option<String>,
+
+    /// The feed's entries, in the order in which the document lists them, which is usually
+    /// newest first
+    entries: Vec<Entry>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Entry {
+    guid: String,
+    title: String,
+    link: String,
+}
+
+fn lock_feeds() -> Result<MutexGuard<'static, Feeds>> {
+    FEEDS
+        .lock()
+        .map_err(|_| ErrorKind::LockPoisoned("the feeds' state".into()).into())
+}
+
+fn on_load(state: &State) -> Result<()> {
+    let path = state.module_data_path()?.join("feeds.yaml");
+
+    if !path.exists() {
+        return Ok(());
+    }
+
+    lock_feeds()?.seen = serde_yaml::from_reader(fs::File::open(path)?)?;
+
+    Ok(())
+}
+
+fn on_unload(state: &State) -> Result<()> {
+    let feeds = lock_feeds()?;
+
+    save(state, &feeds)
+}
+
+fn save(state: &State, feeds: &Feeds) -> Result<()> {
+    let dir = state.module_data_path()?;
+
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join("feeds.yaml"), serde_yaml::to_string(&feeds.seen)?)?;
+
+    Ok(())
+}
+
+/// Polls each feed that is due to be polled.
+fn poll(state: &State) -> Result<()> {
+    let settings: Settings = state.module_settings("feeds")?;
+
+    for feed in &settings.feeds {
+        {
+            let mut feeds = lock_feeds()?;
+
+            let due = feeds
+                .last_polled
+                .get(&feed.url)
+                .map(|time| time.elapsed() >= Duration::from_secs(feed.interval))
+                .unwrap_or(true);
+
+            if !due {
+                continue;
+            }
+
+            feeds.last_polled.insert(feed.url.clone(), Instant::now());
+        }
+
+        if let Err(e) = poll_feed(state, feed) {
+            warn!("Failed to poll the feed {:?}: {}", feed.url, e);
+        }
+    }
+
+    Ok(())
+}
+
+fn poll_feed(state: &State, settings: &FeedSettings) -> Result<()> {
+    let url = Url::parse(&settings.url).map_err(|e| {
+        ErrorKind::FetchFailed(settings.url.clone(), format!("bad URL: {}", e).into())
+    })?;
+
+    let doc = fetch(&url, &Default::default())?;
+
+    if doc.status != 200 {
+        bail!(ErrorKind::FetchFailed(
+            settings.url.clone(),
+            format!("the server answered with status {}", doc.status).into()
+        ));
+    }
+
+    let feed = parse_feed(&String::from_utf8_lossy(&doc.body));
+
+    let new = {
+        let mut feeds = lock_feeds()?;
+        let first_poll = !feeds.seen.contains_key(&settings.url);
+
+        let new = record_entries(
+            feeds
+                .seen
+                .entry(settings.url.clone())
+                .or_insert_with(Default::default),
+            feed.entries,
+        );
+
+        save(state, &feeds)?;
+
+        if first_poll {
+            return Ok(());
+        }
+
+        new
+    };
+
+    let name = settings
+        .name
+        .clone()
+        .or(feed.title)
+        .unwrap_or_else(|| url.host_str().unwrap_or_default().to_owned());
+
+    let announced = &new[new.len().saturating_sub(MAX_ANNOUNCED_PER_POLL)..];
+
+    for server_id in state.server_ids() {
+        match settings.server {
+            Some(ref server) if *server != state.server_name(server_id)? => continue,
+            _ => {}
+        }
+
+        for entry in announced {
+            let text = render(
+                &settings.template,
+                &[
+                    ("feed", &name),
+                    ("title", &entry.title),
+                    ("link", &entry.link),
+                ],
+            );
+
+            for channel in &settings.channels {
+                state.send_privmsg(server_id, channel, &text)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Records the given entries, in the order in which a feed lists them, among the given GUIDs of
+/// the feed's entries seen before, returning those not seen before, oldest first.
+fn record_entries(seen: &mut Vec<String>, entries: Vec<Entry>) -> Vec<Entry> {
+    let mut new = Vec::new();
+
+    for entry in entries.into_iter().rev() {
+        if !seen.contains(&entry.guid) {
+            seen.push(entry.guid.clone());
+            new.push(entry);
+        }
+    }
+
+    if seen.len() > MAX_REMEMBERED_GUIDS {
+        let excess = seen.len() - MAX_REMEMBERED_GUIDS;
+        seen.drain(..excess);
+    }
+
+    new
+}
+
+/// Renders the given template, replacing each placeholder of the form `{name}` with the value of
+/// the field of that name. Placeholders naming no field are left in place.
+fn render(template: &str, fields: &[(&str, &str)]) -> String {
+    let mut output = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(i) = rest.find('{') {
+        output.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        let field = rest.find('}').and_then(|end| {
+            fields
+                .iter()
+                .find(|&&(name, _)| name == &rest[1..end])
+                .map(|&(_, value)| (end, value))
+        });
+
+        match field {
+            Some((end, value)) => {
+                output.push_str(value);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                output.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    output.push_str(rest);
+    output
+}
+
+/// Parses the given RSS or Atom document. This is done leniently, by looking for the elements of
+/// interest rather than by parsing the document as XML, so that the many malformed feeds found in
+/// the wild may be read as well.
+fn parse_feed(xml: &str) -> Feed {
+    let title = elements(xml, "title")
+        .first()
+        .map(|&(_, content)| text(content))
+        .filter(|title| !title.is_empty());
+
+    let entries = elements(xml, "item")
+        .into_iter()
+        .chain(elements(xml, "entry"))
+        .filter_map(|(_, content)| parse_entry(content))
+        .collect();
+
+    Feed { title, entries }
+}
+
+/// Parses the content of an RSS `item` or an Atom `entry` element.
+fn parse_entry(content: &str) -> Option<Entry> {
+    let child = |name| {
+        elements(content, name)
+            .first()
+            .map(|&(_, content)| text(content))
+            .filter(|text| !text.is_empty())
+    };
+
+    // RSS gives the link as the content of the `link` element; Atom, as its `href` attribute.
+    let link = elements(content, "link")
+        .into_iter()
+        .filter_map(|(tag, content)| match attribute(tag, "href") {
+            Some(href) => match attribute(tag, "rel") {
+                None => Some(href),
+                Some(ref rel) if rel == "alternate" => Some(href),
+                Some(_) => None,
+            },
+            None => Some(text(content)),
+        })
+        .find(|link| !link.is_empty());
+
+    let title = child("title");
+    let guid = child("guid")
+        .or_else(|| child("id"))
+        .or_else(|| link.clone())
+        .or_else(|| title.clone())?;
+
+    Some(Entry {
+        guid,
+        title: title.unwrap_or_else(|| "(untitled)".into()),
+        link: link.unwrap_or_default(),
+    })
+}
+
+/// Returns the attributes and the content of each element with the given name in the given XML,
+/// not counting elements nested in others of the same name.
+fn elements<'a>(xml: &'a str, name: &str) -> Vec<(&'a str, &'a str)> {
+    let start_tag = format!("<{}", name);
+    let end_tag = format!("</{}>", name);
+
+    let mut found = Vec::new();
+    let mut rest = xml;
+
+    while let Some(i) = rest.find(&start_tag) {
+        rest = &rest[i + start_tag.len()..];
+
+        if !rest.starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace()) {
+            continue;
+        }
+
+        let attrs_len = match rest.find('>') {
+            Some(len) => len,
+            None => break,
+        };
+        let attrs = &rest[..attrs_len];
+        rest = &rest[attrs_len + 1..];
+
+        if attrs.ends_with('/') {
+            found.push((attrs.trim_end_matches('/'), ""));
+            continue;
+        }
+
+        match rest.find(&end_tag) {
+            Some(len) => {
+                found.push((attrs, &rest[..len]));
+                rest = &rest[len + end_tag.len()..];
+            }
+            None => break,
+        }
+    }
+
+    found
+}
+
+/// Returns the value of the attribute with the given name among the given attributes of an
+/// element, with its character references decoded.
+fn attribute(attrs: &str, name: &str) -> Option<String> {
+    for &quote in &['"', '\''] {
+        let prefix = format!("{}={}", name, quote);
+        let mut rest = attrs;
+
+        while let Some(i) = rest.find(&prefix) {
+            let preceded_by_space = rest[..i].ends_with(char::is_whitespace);
+            rest = &rest[i + prefix.len()..];
+
+            if preceded_by_space {
+                let len = rest.find(quote)?;
+                return Some(decode_char_refs(&rest[..len]));
+            }
+        }
+    }
+
+    None
+}
+
+/// Returns the text of the given content of an element, with its character references decoded, or
+/// taken as it is if it is a `CDATA` section, and its whitespace collapsed.
+fn text(content: &str) -> String {
+    let content = content.trim();
+
+    let text = if content.starts_with("<![CDATA[") && content.ends_with("]]>") {
+        content["<![CDATA[".len()..content.len() - "]]>".len()].to_owned()
+    } else {
+        decode_char_refs(content)
+    };
+
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parsing() {
+        let rss = r#"<?xml version="1.0"?>
+            <rss version="2.0"><channel>
+              <title>Example &amp; Co.</title>
+              <item>
+                <title><![CDATA[Second <post>]]></title>
+                <link>https://example.com/2</link>
+                <guid isPermaLink="false">post-2</guid>
+              </item>
+              <item><title>First post</title><link>https://example.com/1</link></item>
+            </channel></rss>"#;
+
+        let feed = parse_feed(rss);
+        assert_eq!(feed.title, Some("Example & Co.".into()));
+        assert_eq!(
+            feed.entries,
+            vec![
+                Entry {
+                    guid: "post-2".into(),
+                    title: "Second <post>".into(),
+                    link: "https://example.com/2".into(),
+                },
+                Entry {
+                    guid: "https://example.com/1".into(),
+                    title: "First post".into(),
+                    link: "https://example.com/1".into(),
+                },
+            ]
+        );
+
+        let atom = r#"<feed xmlns="http://www.w3.org/2005/Atom">
+              <title type="text">Atom feed</title>
+              <entry>
+                <id>urn:uuid:1</id>
+                <title>An entry</title>
+                <link rel="edit" href="https://example.com/edit/1"/>
+                <link href='https://example.com/1?a=1&amp;b=2' />
+              </entry>
+            </feed>"#;
+
+        let feed = parse_feed(atom);
+        assert_eq!(feed.title, Some("Atom feed".into()));
+        assert_eq!(
+            feed.entries,
+            vec![Entry {
+                guid: "urn:uuid:1".into(),
+                title: "An entry".into(),
+                link: "https://example.com/1?a=1&b=2".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn deduplicating_and_rendering() {
+        let entry = |guid: &str| Entry {
+            guid: guid.into(),
+            title: format!("Post {}", guid),
+            link: format!("https://example.com/{}", guid),
+        };
+
+        let mut seen = vec!["1".to_owned()];
+        let new = record_entries(&mut seen, vec![entry("3"), entry("2"), entry("1")]);
+
+        assert_eq!(new, vec![entry("2"), entry("3")]);
+        assert_eq!(seen, vec!["1", "2", "3"]);
+        assert!(record_entries(&mut seen, vec![entry("3")]).is_empty());
+
+        assert_eq!(
+            render(
+                "[{feed}] {title} <{link}> {other}",
+                &[("feed", "Blog"), ("title", "{link}"), ("link", "x")]
+            ),
+            "[Blog] {link} <x> {other}"
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use core::BotCmdAuthLvl as Auth;
+use core::*;
+use regex::Captures;
+use serde_yaml;
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use std::time::Instant;
+use util::irc::casefold;
+
+/// How many times any one user may change karma within `RATE_LIMIT_WINDOW`
+const MAX_CHANGES_PER_WINDOW: usize = 5;
+
+const RATE_LIMIT_WINDOW: Duration = Duration::from_secs(10 * 60);
+
+/// How many things to list in answer to `karma top`
+const TOP_COUNT: usize = 5;
+
+/// The pattern of an expression that changes a thing's karma, capturing the thing, perhaps in
+/// parentheses, and the operator
+const KARMA_REGEX: &str =
+    r"(?:^|\s)(\([^()]+\)|[^\s()+-](?:[^\s()]*[^\s()+-])?)(\+\+|--)(?:$|[\s.,;:!?])";
+
+/// This bot module keeps score of the karma of things, which users raise by saying `thing++` and
+/// lower by saying `thing--`, whether or not they address the bot. A thing whose name contains
+/// spaces may be given in parentheses, as in `(rust lang)++`.
+///
+/// Users cannot change their own karma, and each user may change karma no more than five times in
+/// ten minutes. The scores are stored in the file `karma.yaml` in the module data directory, so
+/// that they are kept when the bot is restarted.
+///
+/// The command `karma <thing>` shows the karma of a thing, and `karma top`, the things with the
+/// most karma.
+pub fn mk() -> Module {
+    mk_module("karma")
+        .on_load(Box::new(on_load))
+        .on_unload(Box::new(on_unload))
+        .typed_command(
+            "karma",
+            "Show the karma of the given thing, or, given `top`, the things with the most karma.",
+            Auth::Public,
+            typed_cmd!(|ctx, thing: RestOfLine| karma(ctx, &thing.0)),
+            &[],
+        )
+        .trigger(
+            "karma",
+            KARMA_REGEX,
+            "Raise the karma of a thing with `thing++`, or lower it with `thing--`.",
+            TriggerPriority::Low,
+            Box::new(karma_trigger),
+            &[TriggerAttr::AlwaysWatching],
+        )
+        .end()
+}
+
+lazy_static! {
+    static ref KARMA: Mutex<Karma> = Mutex::new(Default::default());
+}
+
+#[derive(Debug, Default)]
+struct Karma {
+    /// The score of each thing, by the name of the server and the thing's name in lowercase
+    scores: BTreeMap<String, BTreeMap<String, i64>>,
+
+    /// When each user, by the name of the server and the user's nickname, folded per the server's
+    /// case-mapping rules, last changed karma, within `RATE_LIMIT_WINDOW`
+    recent_changes: BTreeMap<(String, String), Vec<Instant>>,
+}
+
+fn lock_karma() -> Result<MutexGuard<'static, Karma>> {
+    KARMA
+        .lock()
+        .map_err(|_| ErrorKind::LockPoisoned("the karma scores".into()).into())
+}
+
+fn on_load(state: &State) -> Result<()> {
+    let path = state.module_data_path()?.join("karma.yaml");
+
+    if !path.exists() {
+        return Ok(());
+    }
+
+    lock_karma()?.scores = serde_yaml::from_reader(fs::File::open(path)?)?;
+
+    Ok(())
+}
+
+fn on_unload(state: &State) -> Result<()> {
+    let karma = lock_karma()?;
+
+    save(state, &karma)
+}
+
+fn save(state: &State, karma: &Karma) -> Result<()> {
+    let dir = state.module_data_path()?;
+
+    fs::create_dir_all(dir)?;
+    fs::write(
+        dir.join("karma.yaml"),
+        serde_yaml::to_string(&karma.scores)?,
+    )?;
+
+    Ok(())
+}
+
+/// Returns the name under which the karma of the thing with the given name, as it was given,
+/// perhaps in parentheses, is kept.
+fn thing_key(thing: &str) -> String {
+    let thing = if thing.starts_with('(') && thing.ends_with(')') {
+        &thing[1..thing.len() - 1]
+    } else {
+        thing
+    };
+
+    thing
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+fn karma_trigger(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        invoker,
+        ..
+    }: HandlerContext,
+    args: Captures,
+) -> Result<Reaction> {
+    let nick = match invoker.nick {
+        Some(nick) => nick,
+        None => return Ok(Reaction::None),
+    };
+
+    let thing = thing_key(&args[1]);
+    let change = if &args[2] == "++" { 1 } else { -1 };
+
+    if thing.is_empty() {
+        return Ok(Reaction::None);
+    }
+
+    if state.nicks_eq(server_id, &thing, nick)? {
+        return Ok(Reaction::Reply("You can't change your own karma.".into()));
+    }
+
+    let server = state.server_name(server_id)?;
+    let casemapping = state.server_capabilities(server_id)?.casemapping;
+
+    let score = {
+        let mut karma = lock_karma()?;
+
+        {
+            let changes = karma
+                .recent_changes
+                .entry((server.clone(), casefold(casemapping, nick)))
+                .or_insert_with(Vec::new);
+
+            changes.retain(|t| t.elapsed() < RATE_LIMIT_WINDOW);
+
+            if changes.len() >= MAX_CHANGES_PER_WINDOW {
+                return Ok(Reaction::Reply(
+                    "You've changed enough karma for now; please try again later.".into(),
+                ));
+            }
+
+            changes.push(Instant::now());
+        }
+
+        let score = {
+            let score = karma
+                .scores
+                .entry(server)
+                .or_insert_with(Default::default)
+                .entry(thing.clone())
+                .or_insert(0);
+
+            *score += change;
+            *score
+        };
+
+        save(state, &karma)?;
+
+        score
+    };
+
+    Ok(Reaction::Msg(
+        format!("{}'s karma is now {}.", thing, score).into(),
+    ))
+}
+
+fn karma(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        ..
+    }: HandlerContext,
+    thing: &str,
+) -> Result<Reaction> {
+    let server = state.server_name(server_id)?;
+    let karma = lock_karma()?;
+    let scores = karma.scores.get(&server);
+
+    if thing == "top" {
+        let mut top = scores
+            .map(|scores| scores.iter().collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        if top.is_empty() {
+            return Ok(Reaction::Reply("Nothing has any karma yet.".into()));
+        }
+
+        top.sort_by(|(_, a), (_, b)| b.cmp(a));
+
+        return Ok(Reaction::Reply(
+            format!(
+                "The things with the most karma: {}",
+                top.iter()
+                    .take(TOP_COUNT)
+                    .map(|(thing, score)| format!("{} ({})", thing, score))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into(),
+        ));
+    }
+
+    let key = thing_key(thing);
+    let score = scores
+        .and_then(|scores| scores.get(&key))
+        .cloned()
+        .unwrap_or(0);
+
+    Ok(Reaction::Reply(
+        format!("{}'s karma is {}.", key, score).into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    #[test]
+    fn karma_expressions() {
+        let rx = Regex::new(KARMA_REGEX).unwrap();
+        let find = |text| {
+            rx.captures(text)
+                .map(|c| (thing_key(&c[1]), c[2].to_owned()))
+        };
+
+        assert_eq!(find("rust++"), Some(("rust".into(), "++".into())));
+        assert_eq!(
+            find("I think Bob-- deserves it"),
+            Some(("bob".into(), "--".into()))
+        );
+        assert_eq!(
+            find("(Rust  Lang)++!"),
+            Some(("rust lang".into(), "++".into()))
+        );
+        assert_eq!(find("x+++"), None);
+        assert_eq!(find("i++j"), None);
+        assert_eq!(find("++"), None);
+    }
+}
@@ -1,13 +1,36 @@
+pub use self::admin::mk as admin;
 pub use self::default::mk as default;
+pub use self::factoids::mk as factoids;
+pub use self::feeds::mk as feeds;
+pub use self::karma::mk as karma;
 pub use self::quote::mk as quote;
+pub use self::quotebook::mk as quotebook;
+pub use self::relay::mk as relay;
+pub use self::sed::mk as sed;
+pub use self::seen::mk as seen;
+pub use self::tell::mk as tell;
 pub use self::test::mk as test;
+pub use self::url_titles::mk as url_titles;
 use core::Module;
 
+mod admin;
 mod default;
+mod factoids;
+mod feeds;
+mod karma;
 mod quote;
+mod quotebook;
+mod relay;
+mod sed;
+mod seen;
+mod tell;
 mod test;
+mod url_titles;
 
 /// A list of all bot modules provided by this library, suitable for passing to [`run`].
 ///
 /// [`run`]: <../fn.run.html>
-pub const ALL: &[fn() -> Module] = &[default, quote, test];
+pub const ALL: &[fn() -> Module] = &[
+    admin, default, factoids, feeds, karma, quote, quotebook, relay, sed, seen, tell, test,
+    url_titles,
+];
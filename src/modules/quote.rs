@@ -488,7 +488,12 @@ struct QuoteParams<'a> {
 
 // TODO: Add a parameter controlling whether quotations may be abridged.
 fn prepare_quote_params<'arg>(
-    &HandlerContext { state, invoker, .. }: &HandlerContext,
+    &HandlerContext {
+        state,
+        request_origin,
+        invoker,
+        ..
+    }: &HandlerContext,
     arg: &'arg Yaml,
 ) -> std::result::Result<QuoteParams<'arg>, BotCmdResult> {
     let arg = arg.as_hash().expect(FW_SYNTAX_CHECK_FAIL);
@@ -496,7 +501,7 @@ fn prepare_quote_params<'arg>(
     let first_admin_param_used = admin_param_keys.iter().find(|k| arg.get(k).is_some());
 
     if let Some(admin_param_key) = first_admin_param_used {
-        if !state.have_admin(invoker)? {
+        if !state.have_admin(request_origin.server_id, invoker)? {
             return Err(BotCmdResult::ParamUnauthorized(any_to_str(
                 admin_param_key,
                 Cow::Borrowed,
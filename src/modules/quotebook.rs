@@ -0,0 +1,430 @@
+use core::BotCmdAuthLvl as Auth;
+use core::*;
+use rand::Rng;
+use serde_yaml;
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+/// How many of a channel's most recent messages to search for the message to be quoted by
+/// `quote-grab`
+const GRAB_SEARCH_DEPTH: usize = 100;
+
+/// How many quotations' IDs to list, besides the first match's text, in answer to `quote-search`
+const MAX_LISTED_MATCHES: usize = 10;
+
+/// This bot module keeps a book of quotations that users add while the bot runs, as opposed to
+/// the database of quotations that the module [`quote`] loads from files.
+///
+/// Quotations are added with the command `quote-add <text>`, or taken from the channel's recent
+/// messages with `quote-grab <nick>`, which quotes the last message that the given user sent to
+/// the channel. Each quotation is assigned a number, by which it may be shown with
+/// `quote-get <number>` or removed, by the user who added it or by an administrator of the bot,
+/// with `quote-del <number>`. `quote-random` shows a quotation chosen at random, and
+/// `quote-search <text>` shows a quotation containing the given text, ignoring case, and lists the
+/// numbers of any others.
+///
+/// The book is stored in the file `quotebook.yaml` in the module data directory, so that it is
+/// kept when the bot is restarted. Each server has its own book.
+///
+/// [`quote`]: <fn.quote.html>
+pub fn mk() -> Module {
+    mk_module("quotebook")
+        .on_load(Box::new(on_load))
+        .on_unload(Box::new(on_unload))
+        .typed_command(
+            "quote-add",
+            "Add the given quotation to the bot's book of quotations.",
+            Auth::Public,
+            typed_cmd!(|ctx, text: RestOfLine| add(ctx, text.0)),
+            &[],
+        )
+        .typed_command(
+            "quote-grab",
+            "Add the last message that the given user sent to this channel to the bot's book of \
+             quotations.",
+            Auth::Public,
+            typed_cmd!(|ctx, nick: Nick| grab(ctx, &nick.0)),
+            &[],
+        )
+        .typed_command(
+            "quote-del",
+            "Remove the quotation with the given number, which you added, from the bot's book of \
+             quotations.",
+            Auth::Public,
+            typed_cmd!(|ctx, number: u64| del(ctx, number)),
+            &[],
+        )
+        .typed_command(
+            "quote-get",
+            "Show the quotation with the given number from the bot's book of quotations.",
+            Auth::Public,
+            typed_cmd!(|ctx, number: u64| get(ctx, number)),
+            &[],
+        )
+        .typed_command(
+            "quote-random",
+            "Show a quotation chosen at random from the bot's book of quotations.",
+            Auth::Public,
+            typed_cmd!(|ctx| random(ctx)),
+            &[],
+        )
+        .typed_command(
+            "quote-search",
+            "Show a quotation containing the given text from the bot's book of quotations.",
+            Auth::Public,
+            typed_cmd!(|ctx, text: RestOfLine| search(ctx, &text.0)),
+            &[],
+        )
+        .end()
+}
+
+lazy_static! {
+    static ref BOOKS: Mutex<BTreeMap<String, Book>> = Mutex::new(Default::default());
+}
+
+/// The quotations added on a server
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Book {
+    quotes: Vec<Quote>,
+
+    /// The number to be assigned to the next quotation added, which is not reused when
+    /// quotations are removed
+    next_number: u64,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Quote {
+    number: u64,
+    text: String,
+
+    /// The nickname of the user who added the quotation
+    added_by: String,
+
+    /// The time at which the quotation was added, in seconds since the Unix epoch
+    time: u64,
+}
+
+impl Book {
+    fn add(&mut self, text: String, added_by: &str) -> u64 {
+        let number = self.next_number.max(1);
+        self.next_number = number + 1;
+
+        self.quotes.push(Quote {
+            number,
+            text,
+            added_by: added_by.to_owned(),
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        });
+
+        number
+    }
+
+    fn get(&self, number: u64) -> Option<&Quote> {
+        self.quotes.iter().find(|quote| quote.number == number)
+    }
+
+    fn search<'a>(&'a self, text: &str) -> Vec<&'a Quote> {
+        let text = text.to_lowercase();
+
+        self.quotes
+            .iter()
+            .filter(|quote| quote.text.to_lowercase().contains(&text))
+            .collect()
+    }
+}
+
+impl Quote {
+    fn show(&self) -> String {
+        format!("[#{}] {}", self.number, self.text)
+    }
+}
+
+fn lock_books() -> Result<MutexGuard<'static, BTreeMap<String, Book>>> {
+    BOOKS
+        .lock()
+        .map_err(|_| ErrorKind::LockPoisoned("the book of quotations".into()).into())
+}
+
+fn on_load(state: &State) -> Result<()> {
+    let path = state.module_data_path()?.join("quotebook.yaml");
+
+    if !path.exists() {
+        return Ok(());
+    }
+
+    *lock_books()? = serde_yaml::from_reader(fs::File::open(path)?)?;
+
+    Ok(())
+}
+
+fn on_unload(state: &State) -> Result<()> {
+    let books = lock_books()?;
+
+    save(state, &books)
+}
+
+fn save(state: &State, books: &BTreeMap<String, Book>) -> Result<()> {
+    let dir = state.module_data_path()?;
+
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join("quotebook.yaml"), serde_yaml::to_string(books)?)?;
+
+    Ok(())
+}
+
+/// Runs the given function on the book of quotations of the given server, and then saves the
+/// books.
+fn modify_book<F, T>(state: &State, server_id: ServerId, f: F) -> Result<T>
+where
+    F: FnOnce(&mut Book) -> T,
+{
+    let server = state.server_name(server_id)?;
+    let mut books = lock_books()?;
+
+    let result = f(books.entry(server).or_insert_with(Default::default));
+
+    save(state, &books)?;
+
+    Ok(result)
+}
+
+/// Runs the given function on the book of quotations of the given server, unless the book is
+/// empty, in which case the user is told so.
+fn read_book<F>(state: &State, server_id: ServerId, f: F) -> Result<Reaction>
+where
+    F: FnOnce(&Book) -> Reaction,
+{
+    let server = state.server_name(server_id)?;
+    let books = lock_books()?;
+
+    Ok(match books.get(&server) {
+        Some(book) if !book.quotes.is_empty() => f(book),
+        _ => Reaction::Reply("I have no quotations yet.".into()),
+    })
+}
+
+fn add(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        invoker,
+        ..
+    }: HandlerContext,
+    text: String,
+) -> Result<Reaction> {
+    let added_by = invoker.nick.unwrap_or_default();
+    let number = modify_book(state, server_id, |book| book.add(text, added_by))?;
+
+    Ok(Reaction::Reply(
+        format!("Added quotation #{}.", number).into(),
+    ))
+}
+
+fn grab(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, target },
+        invoker,
+        ..
+    }: HandlerContext,
+    nick: &str,
+) -> Result<Reaction> {
+    if !state
+        .server_capabilities(server_id)?
+        .is_channel_name(target)
+    {
+        return Ok(Reaction::Reply(
+            "Quotations can be grabbed only in channels.".into(),
+        ));
+    }
+
+    let added_by = invoker.nick.unwrap_or_default();
+
+    if state.nicks_eq(server_id, nick, added_by)? {
+        return Ok(Reaction::Reply("You can't quote yourself.".into()));
+    }
+
+    let mut msgs = state.recent_messages(server_id, target, GRAB_SEARCH_DEPTH)?;
+    msgs.reverse();
+
+    let msg = {
+        let mut found = None;
+
+        for msg in msgs {
+            if state.nicks_eq(server_id, &msg.nick, nick)? {
+                found = Some(msg);
+                break;
+            }
+        }
+
+        match found {
+            Some(msg) => msg,
+            None => {
+                return Ok(Reaction::Reply(
+                    format!("I haven't seen {} say anything here lately.", nick).into(),
+                ))
+            }
+        }
+    };
+
+    let text = if msg.is_action {
+        format!("* {} {}", msg.nick, msg.text)
+    } else {
+        format!("<{}> {}", msg.nick, msg.text)
+    };
+
+    let number = modify_book(state, server_id, |book| book.add(text, added_by))?;
+
+    Ok(Reaction::Reply(
+        format!("Added quotation #{}.", number).into(),
+    ))
+}
+
+fn del(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        invoker,
+        ..
+    }: HandlerContext,
+    number: u64,
+) -> Result<Reaction> {
+    let server = state.server_name(server_id)?;
+
+    let added_by = lock_books()?
+        .get(&server)
+        .and_then(|book| book.get(number))
+        .map(|quote| quote.added_by.clone());
+
+    let added_by = match added_by {
+        Some(added_by) => added_by,
+        None => {
+            return Ok(Reaction::Reply(
+                format!("There is no quotation #{}.", number).into(),
+            ))
+        }
+    };
+
+    let allowed = match invoker.nick {
+        Some(nick) if state.nicks_eq(server_id, nick, &added_by)? => true,
+        _ => state.have_admin(server_id, invoker)?,
+    };
+
+    if !allowed {
+        return Ok(Reaction::Reply(
+            format!(
+                "Only {} or an administrator of mine may remove quotation #{}.",
+                added_by, number
+            )
+            .into(),
+        ));
+    }
+
+    modify_book(state, server_id, |book| {
+        book.quotes.retain(|quote| quote.number != number)
+    })?;
+
+    Ok(Reaction::Reply(
+        format!("Removed quotation #{}.", number).into(),
+    ))
+}
+
+fn get(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        ..
+    }: HandlerContext,
+    number: u64,
+) -> Result<Reaction> {
+    read_book(state, server_id, |book| match book.get(number) {
+        Some(quote) => Reaction::Msg(quote.show().into()),
+        None => Reaction::Reply(format!("There is no quotation #{}.", number).into()),
+    })
+}
+
+fn random(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        ..
+    }: HandlerContext,
+) -> Result<Reaction> {
+    let mut rng = state.rng()?;
+
+    read_book(state, server_id, |book| {
+        let quote = &book.quotes[rng.gen_range(0, book.quotes.len())];
+
+        Reaction::Msg(quote.show().into())
+    })
+}
+
+fn search(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        ..
+    }: HandlerContext,
+    text: &str,
+) -> Result<Reaction> {
+    read_book(state, server_id, |book| {
+        let matches = book.search(text);
+
+        let (first, others) = match matches.split_first() {
+            Some(split) => split,
+            None => return Reaction::Reply("No quotation contains that.".into()),
+        };
+
+        if others.is_empty() {
+            return Reaction::Msg(first.show().into());
+        }
+
+        let mut listed = others
+            .iter()
+            .take(MAX_LISTED_MATCHES)
+            .map(|quote| format!("#{}", quote.number))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        if others.len() > MAX_LISTED_MATCHES {
+            listed.push_str(", ...");
+        }
+
+        Reaction::Msgs(
+            vec![
+                first.show().into(),
+                format!("Also matching: {}", listed).into(),
+            ]
+            .into(),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbering_and_searching() {
+        let mut book = Book::default();
+
+        assert_eq!(book.add("<alice> Hello, world!".into(), "bob"), 1);
+        assert_eq!(book.add("<carol> hello again".into(), "bob"), 2);
+
+        book.quotes.retain(|quote| quote.number != 2);
+        assert_eq!(book.add("<dave> bye".into(), "bob"), 3);
+
+        let numbers = |quotes: Vec<&Quote>| quotes.iter().map(|q| q.number).collect::<Vec<_>>();
+
+        assert_eq!(numbers(book.search("HELLO")), vec![1]);
+        assert_eq!(numbers(book.search("<")), vec![1, 3]);
+        assert_eq!(book.get(3).map(Quote::show), Some("[#3] <dave> bye".into()));
+    }
+}
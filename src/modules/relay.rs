@@ -0,0 +1,252 @@
+use core::*;
+use std::cmp::Ordering;
+use util;
+use util::irc::casemapped_str_cmp;
+
+/// This bot module relays messages between channels on different servers, so that, e.g.,
+/// `#chan` on one network and `#chan` on another may talk with each other through the bot.
+///
+/// Each message sent to a linked channel is repeated in each of the other channels of its link,
+/// in the form `<nick@server> text`, where `server` is the name that the configuration gives the
+/// server from which the message came. A zero-width space is inserted into each relayed nickname,
+/// so that it doesn't highlight a user of the same nickname on the other side.
+///
+/// The links are given in the module's settings, which are given in the configuration field
+/// `module settings`, under the key `relay`. The fields of its settings follow, listed by their
+/// keys:
+///
+/// - `links` — The value of this field, if specified, should be a sequence of mappings, each of
+/// which describes a link between channels, with the following fields:
+///
+///   - `channels` — The value of this field should be a sequence of strings, each of the form
+///   `#channel@server`, where `server` is the name of a server in the configuration, which are
+///   the channels to be linked.
+///
+///   - `relay joins` — The value of this field, if specified, should be a boolean, which is
+///   whether to relay users' joining the linked channels. This field is optional; its value
+///   defaults to `false`.
+///
+///   - `relay parts` — The value of this field, if specified, should be a boolean, which is
+///   whether to relay users' leaving the linked channels. This field is optional; its value
+///   defaults to `false`.
+///
+///   This field is optional; its value defaults to an empty sequence.
+///
+/// - `ignored nicks` — The value of this field, if specified, should be a sequence of strings,
+/// which are the nicknames of users whose messages not to relay, such as other bots that relay
+/// messages between the same channels, lest messages be relayed back and forth without end. This
+/// field is optional.
+///
+/// The bot never relays its own messages, nor notices, which are customarily sent by bots.
+pub fn mk() -> Module {
+    mk_module("relay").input_filter(0, Box::new(relay)).end()
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Settings {
+    #[serde(default)]
+    links: Vec<Link>,
+
+    #[serde(default, rename = "ignored nicks")]
+    ignored_nicks: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Link {
+    channels: Vec<String>,
+
+    #[serde(default, rename = "relay joins")]
+    relay_joins: bool,
+
+    #[serde(default, rename = "relay parts")]
+    relay_parts: bool,
+}
+
+/// Splits a linked channel's name of the form `#channel@server` into the channel and the server.
+fn parse_endpoint(endpoint: &str) -> Option<(&str, &str)> {
+    let i = endpoint.rfind('@')?;
+    let (channel, server) = (&endpoint[..i], &endpoint[i + 1..]);
+
+    if channel.is_empty() || server.is_empty() {
+        return None;
+    }
+
+    Some((channel, server))
+}
+
+/// Returns the text with which to relay the given message, sent to `channel` on `server` by the
+/// user with the given nickname, or `None` if it isn't to be relayed.
+fn relayed_text(
+    link: &Link,
+    server: &str,
+    nick: &str,
+    command: &str,
+    channel: &str,
+    params: &[String],
+) -> Option<String> {
+    let nick = format!(
+        "{}@{}",
+        util::zwsp_munge(nick, Some(nick)).collect::<String>(),
+        server
+    );
+
+    match command {
+        "PRIVMSG" => {
+            let text = params.get(1)?;
+
+            if text.starts_with("\x01ACTION ") {
+                Some(format!(
+                    "* {} {}",
+                    nick,
+                    text["\x01ACTION ".len()..].trim_end_matches('\x01')
+                ))
+            } else if text.starts_with('\x01') {
+                // Other CTCP requests are meant for the bot alone.
+                None
+            } else {
+                Some(format!("<{}> {}", nick, text))
+            }
+        }
+        "JOIN" if link.relay_joins => Some(format!("* {} has joined {}", nick, channel)),
+        "PART" if link.relay_parts => Some(match params.get(1) {
+            Some(reason) if !reason.is_empty() => {
+                format!("* {} has left {} ({})", nick, channel, reason)
+            }
+            _ => format!("* {} has left {}", nick, channel),
+        }),
+        _ => None,
+    }
+}
+
+/// Relays the given message to the channels linked with the channel to which it was sent, if
+/// any, passing the message on unchanged.
+fn relay(state: &State, msg: &mut IncomingMsg) -> Result<InputVerdict> {
+    let nick = match msg.prefix {
+        Some(ref prefix) => prefix.split('!').next().unwrap_or_default(),
+        None => return Ok(InputVerdict::Handle),
+    };
+
+    let channel = match (&msg.command[..], msg.params.first()) {
+        ("PRIVMSG", Some(target)) | ("JOIN", Some(target)) | ("PART", Some(target)) => target,
+        _ => return Ok(InputVerdict::Handle),
+    };
+
+    let settings: Settings = state.module_settings("relay")?;
+
+    if settings.links.is_empty() || state.is_own_nick(msg.server_id, nick)? {
+        return Ok(InputVerdict::Handle);
+    }
+
+    for ignored in &settings.ignored_nicks {
+        if state.nicks_eq(msg.server_id, nick, ignored)? {
+            return Ok(InputVerdict::Handle);
+        }
+    }
+
+    let server = state.server_name(msg.server_id)?;
+    let casemapping = state.server_capabilities(msg.server_id)?.casemapping;
+    let is_source = |endpoint: &(&str, &str)| {
+        endpoint.1 == server
+            && casemapped_str_cmp(casemapping, endpoint.0, &channel[..]) == Ordering::Equal
+    };
+
+    for link in &settings.links {
+        let endpoints = link
+            .channels
+            .iter()
+            .filter_map(|endpoint| parse_endpoint(endpoint))
+            .collect::<Vec<_>>();
+
+        if !endpoints.iter().any(&is_source) {
+            continue;
+        }
+
+        let text = match relayed_text(link, &server, nick, &msg.command, channel, &msg.params) {
+            Some(text) => text,
+            None => continue,
+        };
+
+        for server_id in state.server_ids() {
+            let dest_server = state.server_name(server_id)?;
+
+            for &(dest_channel, _) in endpoints
+                .iter()
+                .filter(|endpoint| endpoint.1 == dest_server && !is_source(endpoint))
+            {
+                state.send_privmsg(server_id, dest_channel, &text)?;
+            }
+        }
+    }
+
+    Ok(InputVerdict::Handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use testing::SharedOutput;
+
+    #[test]
+    fn relaying() {
+        assert_eq!(parse_endpoint("#chan@libera"), Some(("#chan", "libera")));
+        assert_eq!(parse_endpoint("#a@b@oftc"), Some(("#a@b", "oftc")));
+        assert_eq!(parse_endpoint("#chan"), None);
+        assert_eq!(parse_endpoint("#chan@"), None);
+
+        let link = Link {
+            channels: Vec::new(),
+            relay_joins: true,
+            relay_parts: false,
+        };
+        let relay = |command, params: &[&str]| {
+            let params = params.iter().map(|&p| p.to_owned()).collect::<Vec<_>>();
+            relayed_text(&link, "oftc", "bob", command, "#c", &params)
+        };
+
+        assert_eq!(
+            relay("PRIVMSG", &["#c", "hi"]),
+            Some("<b\u{200B}ob@oftc> hi".into())
+        );
+        assert_eq!(
+            relay("PRIVMSG", &["#c", "\x01ACTION waves\x01"]),
+            Some("* b\u{200B}ob@oftc waves".into())
+        );
+        assert_eq!(relay("PRIVMSG", &["#c", "\x01VERSION\x01"]), None);
+        assert_eq!(
+            relay("JOIN", &["#c"]),
+            Some("* b\u{200B}ob@oftc has joined #c".into())
+        );
+        assert_eq!(relay("PART", &["#c", "bye"]), None);
+    }
+
+    #[test]
+    fn relaying_between_servers() {
+        let output = SharedOutput::default();
+
+        simulate(
+            "nickname: testbot\n\
+             console: false\n\
+             module settings:\n  \
+             relay:\n    \
+             links:\n      \
+             - channels: ['#chan@a', '#relay@b']\n    \
+             ignored nicks: [otherbot]\n\
+             servers:\n  \
+             - {name: a, host: irc.a.invalid, port: 6697}\n  \
+             - {name: b, host: irc.b.invalid, port: 6697}\n",
+            env::temp_dir(),
+            |_| ErrorReaction::Proceed,
+            vec![mk],
+            &b":alice!alice@example.com PRIVMSG #chan :hi\n\
+               :alice!alice@example.com PRIVMSG #elsewhere :hello\n\
+               :testbot!testbot@example.com PRIVMSG #chan :<bob@b> echoed\n\
+               :OtherBot!otherbot@example.com PRIVMSG #chan :<carol@c> relayed\n"[..],
+            output.clone(),
+        );
+
+        // The message is relayed to the other server, and to it alone, while the bot's own
+        // messages and those of the ignored bot are not relayed back.
+        assert_eq!(output.text(), "PRIVMSG #relay :<a\u{200B}lice@a> hi\n");
+    }
+}
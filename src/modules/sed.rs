@@ -0,0 +1,265 @@
+use core::*;
+use regex::Captures;
+use regex::Regex;
+use regex::RegexBuilder;
+use util;
+
+/// The pattern of a message asking for a correction, capturing the nickname of the user whose
+/// message is to be corrected, if it is addressed to one, and the substitution
+const CORRECTION_REGEX: &str = r"^(?:([^\s:,]+)[:,]\s*)?(s/.*)$";
+
+/// The maximum length, in bytes, of a substitution's pattern
+const MAX_PATTERN_LEN: usize = 256;
+
+/// The maximum size, in bytes, of a substitution's pattern once compiled
+const MAX_COMPILED_SIZE: usize = 256 * 1024;
+
+/// How much of a corrected message to show, in characters
+const MAX_CORRECTION_CHARS: usize = 400;
+
+/// This bot module corrects users' messages in the manner of `sed`, whether or not they address
+/// the bot.
+///
+/// A message of the form `s/pattern/replacement/flags` sent to a channel, with the last slash
+/// optional, is answered with the last message that its sender sent to the channel with the
+/// regular expression `pattern` replaced with `replacement`: `nick meant: corrected message`. A
+/// message of the form `nick: s/pattern/replacement/flags` corrects the last message of the user
+/// `nick` instead. In the replacement, `&` stands for the whole match, and `\1` through `\9` for
+/// the groups that the pattern captures; slashes are written as `\/` in either. The flags are `g`,
+/// to replace every match rather than the first alone, and `i`, to ignore case.
+///
+/// Only the messages that the bot remembers (see [`State::history`]) may be corrected. As the
+/// regular expressions' engine runs in time linear in the length of the text searched, and
+/// patterns' length and compiled size are limited, users' patterns cannot keep the bot busy for
+/// long.
+///
+/// [`State::history`]: <../struct.State.html#method.history>
+pub fn mk() -> Module {
+    mk_module("sed")
+        .trigger(
+            "sed",
+            CORRECTION_REGEX,
+            "Correct your last message with `s/pattern/replacement/`, or another user's with \
+             `nick: s/pattern/replacement/`.",
+            TriggerPriority::Low,
+            Box::new(correct),
+            &[TriggerAttr::AlwaysWatching],
+        )
+        .end()
+}
+
+lazy_static! {
+    static ref CORRECTION: Regex =
+        Regex::new(CORRECTION_REGEX).expect(util::STATIC_REGEX_PARSE_ERR_MSG);
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct Substitution {
+    pattern: String,
+
+    /// The replacement, in the syntax of `Regex::replace` rather than that of `sed`
+    replacement: String,
+
+    global: bool,
+    ignore_case: bool,
+}
+
+/// Parses a substitution of the form `s/pattern/replacement/flags`.
+fn parse_substitution(text: &str) -> Option<Substitution> {
+    if !text.starts_with("s/") {
+        return None;
+    }
+
+    let mut parts = Vec::new();
+    let mut part = String::new();
+    let mut chars = text[2..].chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('/') => part.push('/'),
+                Some(c) => {
+                    part.push('\\');
+                    part.push(c);
+                }
+                None => part.push('\\'),
+            },
+            '/' if parts.len() < 2 => {
+                parts.push(part.clone());
+                part.clear();
+            }
+            c => part.push(c),
+        }
+    }
+
+    parts.push(part);
+
+    let (pattern, replacement, flags) = match parts.len() {
+        2 => (&parts[0], &parts[1], ""),
+        3 => (&parts[0], &parts[1], &parts[2][..]),
+        _ => return None,
+    };
+
+    if pattern.is_empty() || !flags.chars().all(|c| c == 'g' || c == 'i') {
+        return None;
+    }
+
+    Some(Substitution {
+        pattern: pattern.to_owned(),
+        replacement: sed_replacement(replacement),
+        global: flags.contains('g'),
+        ignore_case: flags.contains('i'),
+    })
+}
+
+/// Translates the given replacement from the syntax of `sed` into that of `Regex::replace`.
+fn sed_replacement(replacement: &str) -> String {
+    let mut translated = String::with_capacity(replacement.len());
+    let mut chars = replacement.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => translated.push_str("${0}"),
+            '$' => translated.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(d) if d.is_ascii_digit() => {
+                    translated.push_str("${");
+                    translated.push(d);
+                    translated.push('}');
+                }
+                Some('$') => translated.push_str("$$"),
+                Some(c) => translated.push(c),
+                None => translated.push('\\'),
+            },
+            c => translated.push(c),
+        }
+    }
+
+    translated
+}
+
+/// Returns whether the given message asks for a correction, and so is not to be corrected itself.
+fn is_correction(text: &str) -> bool {
+    CORRECTION
+        .captures(text)
+        .and_then(|c| parse_substitution(&c[2]))
+        .is_some()
+}
+
+impl Substitution {
+    fn regex(&self) -> Option<Regex> {
+        if self.pattern.len() > MAX_PATTERN_LEN {
+            return None;
+        }
+
+        RegexBuilder::new(&self.pattern)
+            .case_insensitive(self.ignore_case)
+            .size_limit(MAX_COMPILED_SIZE)
+            .dfa_size_limit(MAX_COMPILED_SIZE)
+            .build()
+            .ok()
+    }
+
+    fn apply(&self, rx: &Regex, text: &str) -> String {
+        if self.global {
+            rx.replace_all(text, &self.replacement[..]).into_owned()
+        } else {
+            rx.replace(text, &self.replacement[..]).into_owned()
+        }
+    }
+}
+
+fn correct(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, target },
+        invoker,
+        ..
+    }: HandlerContext,
+    args: Captures,
+) -> Result<Reaction> {
+    let corrector = match invoker.nick {
+        Some(nick) => nick,
+        None => return Ok(Reaction::None),
+    };
+
+    let substitution = match parse_substitution(&args[2]) {
+        Some(substitution) => substitution,
+        None => return Ok(Reaction::None),
+    };
+
+    let rx = match substitution.regex() {
+        Some(rx) => rx,
+        None => return Ok(Reaction::Reply("I can't use that pattern.".into())),
+    };
+
+    let nick = args.get(1).map(|m| m.as_str()).unwrap_or(corrector);
+
+    let mut history = state.history(server_id, target)?;
+    history.reverse();
+
+    let mut found = None;
+
+    for msg in history {
+        if state.nicks_eq(server_id, &msg.nick, nick)?
+            && !is_correction(&msg.text)
+            && rx.is_match(&msg.text)
+        {
+            found = Some(msg);
+            break;
+        }
+    }
+
+    let msg = match found {
+        Some(msg) => msg,
+        None => return Ok(Reaction::None),
+    };
+
+    let corrected = substitution.apply(&rx, &msg.text);
+    let corrected = match corrected.char_indices().nth(MAX_CORRECTION_CHARS) {
+        Some((i, _)) => format!("{}...", &corrected[..i]),
+        None => corrected,
+    };
+    let corrected = if msg.is_action {
+        format!("* {} {}", msg.nick, corrected)
+    } else {
+        corrected
+    };
+
+    Ok(Reaction::Msg(
+        if state.nicks_eq(server_id, nick, corrector)? {
+            format!("{} meant: {}", msg.nick, corrected)
+        } else {
+            format!("{} thinks {} meant: {}", corrector, msg.nick, corrected)
+        }
+        .into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitutions() {
+        let sub = |text| {
+            let sub = parse_substitution(text)?;
+            let rx = sub.regex()?;
+            Some(sub.apply(&rx, "the cat sat on the mat"))
+        };
+
+        assert_eq!(sub("s/cat/dog/"), Some("the dog sat on the mat".into()));
+        assert_eq!(sub("s/at/og/g"), Some("the cog sog on the mog".into()));
+        assert_eq!(sub("s/THE/a/i"), Some("a cat sat on the mat".into()));
+        assert_eq!(
+            sub(r"s/(c)(at)/\2\1 [&] $1/"),
+            Some("the atc [cat] $1 sat on the mat".into())
+        );
+        assert_eq!(sub(r"s/ on /\/"), Some("the cat sat/the mat".into()));
+        assert_eq!(sub("s/cat"), None);
+        assert_eq!(sub("s//dog/"), None);
+        assert_eq!(sub("s/cat/dog/x"), None);
+        assert_eq!(sub("s/a/b/c/d"), None);
+        assert_eq!(sub("s/(/x/"), None);
+    }
+}
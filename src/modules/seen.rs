@@ -0,0 +1,267 @@
+use core::BotCmdAuthLvl as Auth;
+use core::*;
+use serde_yaml;
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use util::fmt::human_duration;
+use util::irc::casefold;
+
+/// How long to wait after saving the record of sightings before saving it again, at most, as
+/// users are seen
+const SAVE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+/// How much of a message to quote in a sighting, in characters
+const MAX_QUOTED_CHARS: usize = 100;
+
+/// This bot module records when each user was last seen, where, and doing what, and answers the
+/// command `seen` with this information.
+///
+/// Users are seen sending messages to channels, joining, leaving, and being kicked from channels,
+/// changing their nicknames, and quitting. The record of sightings is stored in the file
+/// `seen.yaml` in the module data directory, so that it is kept when the bot is restarted.
+pub fn mk() -> Module {
+    mk_module("seen")
+        .on_load(Box::new(on_load))
+        .on_unload(Box::new(on_unload))
+        .input_filter(0, Box::new(observe))
+        .typed_command(
+            "seen",
+            "Ask when the bot last saw the given user, where, and doing what.",
+            Auth::Public,
+            typed_cmd!(|ctx, nick: Nick| seen(ctx, &nick.0)),
+            &[],
+        )
+        .end()
+}
+
+lazy_static! {
+    static ref SIGHTINGS: Mutex<Sightings> = Mutex::new(Default::default());
+}
+
+#[derive(Debug, Default)]
+struct Sightings {
+    /// The last sighting of each user, by the name of the server and the user's nickname, folded
+    /// per the server's case-mapping rules
+    users: BTreeMap<String, BTreeMap<String, Sighting>>,
+
+    last_saved: Option<Instant>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Sighting {
+    nick: String,
+
+    /// The time of the sighting, in seconds since the Unix epoch
+    time: u64,
+
+    channel: Option<String>,
+
+    /// What the user was doing, e.g., "joining the channel"
+    action: String,
+}
+
+fn lock_sightings() -> Result<MutexGuard<'static, Sightings>> {
+    SIGHTINGS
+        .lock()
+        .map_err(|_| ErrorKind::LockPoisoned("the record of users seen".into()).into())
+}
+
+fn on_load(state: &State) -> Result<()> {
+    let path = state.module_data_path()?.join("seen.yaml");
+
+    if !path.exists() {
+        return Ok(());
+    }
+
+    lock_sightings()?.users = serde_yaml::from_reader(fs::File::open(path)?)?;
+
+    Ok(())
+}
+
+fn on_unload(state: &State) -> Result<()> {
+    let mut sightings = lock_sightings()?;
+
+    save(state, &mut sightings)
+}
+
+fn save(state: &State, sightings: &mut Sightings) -> Result<()> {
+    let dir = state.module_data_path()?;
+
+    fs::create_dir_all(dir)?;
+    fs::write(
+        dir.join("seen.yaml"),
+        serde_yaml::to_string(&sightings.users)?,
+    )?;
+
+    sightings.last_saved = Some(Instant::now());
+
+    Ok(())
+}
+
+/// Records a sighting of each user of whom the given message tells, passing the message on
+/// unchanged.
+fn observe(state: &State, msg: &mut IncomingMsg) -> Result<InputVerdict> {
+    let nick = match msg.prefix {
+        Some(ref prefix) => prefix.split('!').next().unwrap_or_default(),
+        None => return Ok(InputVerdict::Handle),
+    };
+
+    let param = |i: usize| msg.params.get(i).map(|p| &p[..]);
+
+    let capabilities = state.server_capabilities(msg.server_id)?;
+
+    let mut seen = Vec::new();
+
+    match (&msg.command[..], param(0), param(1)) {
+        ("PRIVMSG", Some(target), Some(text)) if capabilities.is_channel_name(target) => {
+            let action = match Ctcp::parse(text) {
+                Some(ref ctcp) if ctcp.is_action() => {
+                    format!("doing something: * {} {}", nick, quote(ctcp.params))
+                }
+                Some(_) => return Ok(InputVerdict::Handle),
+                None => format!("saying: {}", quote(text)),
+            };
+
+            seen.push((nick, Some(target), action));
+        }
+        ("JOIN", Some(channel), _) => seen.push((nick, Some(channel), "joining".into())),
+        ("PART", Some(channel), reason) => seen.push((nick, Some(channel), leaving(reason))),
+        ("KICK", Some(channel), Some(kicked)) => {
+            seen.push((kicked, Some(channel), format!("being kicked by {}", nick)))
+        }
+        ("QUIT", reason, _) => seen.push((nick, None, quitting(reason))),
+        ("NICK", Some(new_nick), _) => {
+            seen.push((nick, None, format!("changing nickname to {}", new_nick)));
+            seen.push((new_nick, None, format!("changing nickname from {}", nick)));
+        }
+        _ => {}
+    }
+
+    if seen.is_empty() {
+        return Ok(InputVerdict::Handle);
+    }
+
+    let server = state.server_name(msg.server_id)?;
+    let casemapping = capabilities.casemapping;
+    let time = msg.tags.server_time().unwrap_or_else(SystemTime::now);
+    let time = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    let mut sightings = lock_sightings()?;
+
+    {
+        let users = sightings
+            .users
+            .entry(server)
+            .or_insert_with(Default::default);
+
+        for (nick, channel, action) in seen {
+            users.insert(
+                casefold(casemapping, nick),
+                Sighting {
+                    nick: nick.to_owned(),
+                    time,
+                    channel: channel.map(ToOwned::to_owned),
+                    action,
+                },
+            );
+        }
+    }
+
+    let due = match sightings.last_saved {
+        Some(t) => t.elapsed() >= SAVE_INTERVAL,
+        None => true,
+    };
+
+    if due {
+        save(state, &mut sightings)?;
+    }
+
+    Ok(InputVerdict::Handle)
+}
+
+fn leaving(reason: Option<&str>) -> String {
+    match reason {
+        Some(reason) if !reason.is_empty() => format!("leaving, saying: {}", quote(reason)),
+        _ => "leaving".into(),
+    }
+}
+
+fn quitting(reason: Option<&str>) -> String {
+    match reason {
+        Some(reason) if !reason.is_empty() => format!("quitting, saying: {}", quote(reason)),
+        _ => "quitting".into(),
+    }
+}
+
+/// Returns the given text, quoted and truncated to `MAX_QUOTED_CHARS` characters.
+fn quote(text: &str) -> String {
+    match text.char_indices().nth(MAX_QUOTED_CHARS) {
+        Some((i, _)) => format!("\"{}...\"", &text[..i]),
+        None => format!("\"{}\"", text),
+    }
+}
+
+fn seen(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        ..
+    }: HandlerContext,
+    nick: &str,
+) -> Result<Reaction> {
+    let server = state.server_name(server_id)?;
+    let casemapping = state.server_capabilities(server_id)?.casemapping;
+
+    let sighting = lock_sightings()?
+        .users
+        .get(&server)
+        .and_then(|users| users.get(&casefold(casemapping, nick)))
+        .cloned();
+
+    let sighting = match sighting {
+        Some(sighting) => sighting,
+        None => return Ok(Reaction::Reply(format!("I haven't seen {}.", nick).into())),
+    };
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    Ok(Reaction::Reply(
+        format!(
+            "{nick} was last seen {ago} ago{channel}, {action}.",
+            nick = sighting.nick,
+            ago = human_duration(Duration::from_secs(now.saturating_sub(sighting.time))),
+            channel = sighting
+                .channel
+                .map(|c| format!(" in {}", c))
+                .unwrap_or_default(),
+            action = sighting.action,
+        )
+        .into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote("hi"), "\"hi\"");
+        assert_eq!(
+            quote(&"é".repeat(MAX_QUOTED_CHARS + 1)),
+            format!("\"{}...\"", "é".repeat(MAX_QUOTED_CHARS))
+        );
+    }
+}
@@ -0,0 +1,359 @@
+use core::BotCmdAuthLvl as Auth;
+use core::*;
+use serde_yaml;
+use std::collections::BTreeMap;
+use std::fs;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+use util::fmt::human_duration;
+use util::irc::casefold;
+use util::irc::CaseMapping;
+
+/// How long a memo is kept for delivery before it is discarded
+const MEMO_LIFETIME: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How many undelivered memos may be left for any one user
+const MAX_MEMOS_PER_RECIPIENT: usize = 10;
+
+/// How many undelivered memos any one user may have left
+const MAX_MEMOS_PER_SENDER: usize = 20;
+
+/// This bot module lets users leave memos for one another, with the command `tell`.
+///
+/// A memo is delivered when its recipient next speaks in a channel or to the bot, or joins a
+/// channel, in which case the memo is delivered there, or else when the bot learns that the
+/// recipient has come online, in which case the memo is delivered by private message. Memos are
+/// stored in the file `tell.yaml` in the module data directory, so that they are kept when the
+/// bot is restarted, and are discarded if they have gone undelivered for 30 days.
+pub fn mk() -> Module {
+    mk_module("tell")
+        .on_load(Box::new(on_load))
+        .on_unload(Box::new(on_unload))
+        .on_connect(Box::new(on_connect))
+        .input_filter(0, Box::new(observe))
+        .typed_command(
+            "tell",
+            "Leave a memo for the given user, to be delivered when the bot next sees the user.",
+            Auth::Public,
+            typed_cmd!(|ctx, nick: Nick, text: RestOfLine| tell(ctx, &nick.0, text.0)),
+            &[],
+        )
+        .end()
+}
+
+lazy_static! {
+    static ref MEMOS: Mutex<Memos> = Mutex::new(Default::default());
+}
+
+#[derive(Debug, Default)]
+struct Memos {
+    /// The undelivered memos, by the name of the server, in the order in which they were left
+    memos: BTreeMap<String, Vec<Memo>>,
+
+    /// The servers and users, by nickname folded per the servers' case-mapping rules, whose
+    /// presence is being monitored so as to deliver memos to them
+    monitored: Vec<(ServerId, String)>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Memo {
+    from: String,
+    to: String,
+    text: String,
+
+    /// The time at which the memo was left, in seconds since the Unix epoch
+    time: u64,
+}
+
+impl Memos {
+    fn discard_expired(&mut self, now: u64) {
+        for memos in self.memos.values_mut() {
+            memos.retain(|memo| now.saturating_sub(memo.time) < MEMO_LIFETIME.as_secs());
+        }
+    }
+
+    /// Removes and returns the memos left on the given server for the user with the given
+    /// nickname.
+    fn take(&mut self, server: &str, casemapping: CaseMapping, nick: &str) -> Vec<Memo> {
+        let memos = match self.memos.get_mut(server) {
+            Some(memos) => memos,
+            None => return Vec::new(),
+        };
+        let nick = casefold(casemapping, nick);
+
+        let (taken, kept) = memos
+            .drain(..)
+            .partition(|memo| casefold(casemapping, &memo.to) == nick);
+
+        *memos = kept;
+
+        taken
+    }
+}
+
+fn lock_memos() -> Result<MutexGuard<'static, Memos>> {
+    MEMOS
+        .lock()
+        .map_err(|_| ErrorKind::LockPoisoned("the record of memos".into()).into())
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn on_load(state: &State) -> Result<()> {
+    let path = state.module_data_path()?.join("tell.yaml");
+
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let mut memos = lock_memos()?;
+
+    memos.memos = serde_yaml::from_reader(fs::File::open(path)?)?;
+    memos.discard_expired(now());
+
+    Ok(())
+}
+
+fn on_unload(state: &State) -> Result<()> {
+    let memos = lock_memos()?;
+
+    save(state, &memos)
+}
+
+fn save(state: &State, memos: &Memos) -> Result<()> {
+    let dir = state.module_data_path()?;
+
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join("tell.yaml"), serde_yaml::to_string(&memos.memos)?)?;
+
+    Ok(())
+}
+
+/// Monitors the presence of the recipients of the memos left on the given server, upon
+/// connecting to it.
+fn on_connect(state: &State, server_id: ServerId) -> Result<()> {
+    let server = state.server_name(server_id)?;
+
+    let recipients = lock_memos()?
+        .memos
+        .get(&server)
+        .map(|memos| memos.iter().map(|memo| memo.to.clone()).collect())
+        .unwrap_or_else(Vec::new);
+
+    for nick in recipients {
+        monitor(state, server_id, &nick)?;
+    }
+
+    Ok(())
+}
+
+fn monitor(state: &State, server_id: ServerId, nick: &str) -> Result<()> {
+    let casemapping = state.server_capabilities(server_id)?.casemapping;
+    let watch = (server_id, casefold(casemapping, nick));
+
+    {
+        let mut memos = lock_memos()?;
+
+        if memos.monitored.contains(&watch) {
+            return Ok(());
+        }
+
+        memos.monitored.push(watch);
+    }
+
+    state.monitor(server_id, nick, Box::new(on_presence))
+}
+
+fn on_presence(state: &State, server_id: ServerId, nick: &str, presence: Presence) -> Result<()> {
+    if presence == Presence::Online {
+        deliver(state, server_id, nick, nick)?;
+    }
+
+    Ok(())
+}
+
+/// Delivers any memos left for the given user to the given channel or user, passing the message
+/// on unchanged.
+fn observe(state: &State, msg: &mut IncomingMsg) -> Result<InputVerdict> {
+    let nick = match msg.prefix {
+        Some(ref prefix) => prefix.split('!').next().unwrap_or_default(),
+        None => return Ok(InputVerdict::Handle),
+    };
+
+    let target = match (&msg.command[..], msg.params.first()) {
+        ("PRIVMSG", Some(target)) | ("JOIN", Some(target)) => target,
+        _ => return Ok(InputVerdict::Handle),
+    };
+
+    if state.is_own_nick(msg.server_id, nick)? {
+        return Ok(InputVerdict::Handle);
+    }
+
+    let dest = if state
+        .server_capabilities(msg.server_id)?
+        .is_channel_name(target)
+    {
+        target
+    } else {
+        nick
+    };
+
+    deliver(state, msg.server_id, nick, dest)?;
+
+    Ok(InputVerdict::Handle)
+}
+
+fn deliver(state: &State, server_id: ServerId, nick: &str, dest: &str) -> Result<()> {
+    let server = state.server_name(server_id)?;
+    let casemapping = state.server_capabilities(server_id)?.casemapping;
+
+    let taken = {
+        let mut memos = lock_memos()?;
+        let taken = memos.take(&server, casemapping, nick);
+
+        if taken.is_empty() {
+            return Ok(());
+        }
+
+        let watch = (server_id, casefold(casemapping, nick));
+        memos.monitored.retain(|w| *w != watch);
+
+        save(state, &memos)?;
+
+        taken
+    };
+
+    state.unmonitor(server_id, nick)?;
+
+    let now = now();
+
+    for memo in taken {
+        state.send_privmsg(
+            server_id,
+            dest,
+            &format!(
+                "{}: {} left you a memo {} ago: {}",
+                nick,
+                memo.from,
+                human_duration(Duration::from_secs(now.saturating_sub(memo.time))),
+                memo.text
+            ),
+        )?;
+    }
+
+    Ok(())
+}
+
+fn tell(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, .. },
+        invoker,
+        ..
+    }: HandlerContext,
+    nick: &str,
+    text: String,
+) -> Result<Reaction> {
+    let from = match invoker.nick {
+        Some(from) => from,
+        None => return Ok(Reaction::None),
+    };
+
+    if state.is_own_nick(server_id, nick)? {
+        return Ok(Reaction::Reply("I'm listening.".into()));
+    }
+
+    if state.nicks_eq(server_id, nick, from)? {
+        return Ok(Reaction::Reply("You can tell yourself that.".into()));
+    }
+
+    let server = state.server_name(server_id)?;
+    let casemapping = state.server_capabilities(server_id)?.casemapping;
+
+    {
+        let mut memos = lock_memos()?;
+        let now = now();
+
+        memos.discard_expired(now);
+
+        {
+            let pending = memos.memos.entry(server).or_insert_with(Vec::new);
+
+            let count = |who: &str, of: fn(&Memo) -> &str| {
+                let who = casefold(casemapping, who);
+
+                pending
+                    .iter()
+                    .filter(|memo| casefold(casemapping, of(memo)) == who)
+                    .count()
+            };
+
+            if count(nick, |memo| &memo.to) >= MAX_MEMOS_PER_RECIPIENT {
+                return Ok(Reaction::Reply(
+                    format!("{} already has as many memos waiting as I can keep.", nick).into(),
+                ));
+            }
+
+            if count(from, |memo| &memo.from) >= MAX_MEMOS_PER_SENDER {
+                return Ok(Reaction::Reply(
+                    "You have already left as many memos as I can keep for you.".into(),
+                ));
+            }
+
+            pending.push(Memo {
+                from: from.to_owned(),
+                to: nick.to_owned(),
+                text,
+                time: now,
+            });
+        }
+
+        save(state, &memos)?;
+    }
+
+    monitor(state, server_id, nick)?;
+
+    Ok(Reaction::Reply(
+        format!("I'll pass that on when {} is around.", nick).into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taking_memos() {
+        let memo = |to: &str, time| Memo {
+            from: "alice".into(),
+            to: to.into(),
+            text: "hi".into(),
+            time,
+        };
+
+        let mut memos = Memos::default();
+        memos.memos.insert(
+            "irc.example.net".into(),
+            vec![
+                memo("Bob", 0),
+                memo("carol", 0),
+                memo("BOB", MEMO_LIFETIME.as_secs()),
+            ],
+        );
+
+        memos.discard_expired(MEMO_LIFETIME.as_secs());
+
+        let taken = memos.take("irc.example.net", CaseMapping::Ascii, "bob");
+        assert_eq!(taken.len(), 1);
+        assert_eq!(taken[0].to, "BOB");
+        assert_eq!(memos.memos["irc.example.net"].len(), 0);
+    }
+}
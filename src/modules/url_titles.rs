@@ -0,0 +1,311 @@
+use core::*;
+use regex::Captures;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::MutexGuard;
+use std::time::Duration;
+use std::time::Instant;
+use url::Url;
+use util::html::decode_char_refs;
+use util::irc::casemapped_str_cmp;
+
+/// How long to remember the title of a page
+const CACHE_LIFETIME: Duration = Duration::from_secs(60 * 60);
+
+/// How many pages' titles to remember at most
+const CACHE_CAPACITY: usize = 256;
+
+/// How much of a title to announce, in characters
+const MAX_TITLE_CHARS: usize = 200;
+
+const URL_REGEX: &str = r#"https?://[^\s<>"]+"#;
+
+/// This bot module announces the titles of the Web pages whose URLs users send to channels, in the
+/// form `[title] example.com`.
+///
+/// The module announces titles only in the channels listed in its settings, which are given in
+/// the configuration field `module settings`, under the key `url-titles`. The fields of its
+/// settings follow, listed by their keys:
+///
+/// - `channels` — The value of this field, if specified, should be a sequence of strings, which
+/// are the channels in which to announce titles. This field is optional; its value defaults to an
+/// empty sequence, so that no titles are announced.
+///
+/// - `allowed domains` — The value of this field, if specified, should be a sequence of strings,
+/// which are the domains, including their subdomains, from which alone to fetch pages. This field
+/// is optional; if it is not specified, pages are fetched from any domain not denied.
+///
+/// - `denied domains` — The value of this field, if specified, should be a sequence of strings,
+/// which are the domains, including their subdomains, from which not to fetch pages. This field
+/// is optional.
+///
+/// - `max size` — The value of this field, if specified, should be a positive integer, which is
+/// to be used as the number of bytes of each page to read, at most, in search of its title. This
+/// field is optional; its value defaults to 256 KiB (262144 bytes).
+///
+/// - `timeout` — The value of this field, if specified, should be a positive integer, which is to
+/// be used as the number of seconds to wait for each page, at most. This field is optional; its
+/// value defaults to 10.
+///
+/// Titles are remembered for an hour, so that a page is not fetched again each time its URL is
+/// sent.
+pub fn mk() -> Module {
+    mk_module("url-titles")
+        .trigger(
+            "url-title",
+            URL_REGEX,
+            "Announce the title of the Web page at a URL sent to the channel.",
+            TriggerPriority::Low,
+            Box::new(announce_title),
+            &[TriggerAttr::AlwaysWatching],
+        )
+        .end()
+}
+
+#[derive(Debug, Deserialize)]
+struct Settings {
+    #[serde(default)]
+    channels: Vec<String>,
+
+    #[serde(default, rename = "allowed domains")]
+    allowed_domains: Option<Vec<String>>,
+
+    #[serde(default, rename = "denied domains")]
+    denied_domains: Vec<String>,
+
+    #[serde(default = "default_max_size", rename = "max size")]
+    max_size: usize,
+
+    #[serde(default = "default_timeout")]
+    timeout: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            channels: Default::default(),
+            allowed_domains: Default::default(),
+            denied_domains: Default::default(),
+            max_size: default_max_size(),
+            timeout: default_timeout(),
+        }
+    }
+}
+
+fn default_max_size() -> usize {
+    256 * 1024
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+/// The titles of the pages fetched lately, or `None` for those that had none, by URL, with the
+/// times at which they were fetched
+type Cache = BTreeMap<String, (Option<String>, Instant)>;
+
+lazy_static! {
+    static ref CACHE: Mutex<Cache> = Mutex::new(Default::default());
+}
+
+fn lock_cache() -> Result<MutexGuard<'static, Cache>> {
+    CACHE
+        .lock()
+        .map_err(|_| ErrorKind::LockPoisoned("the cache of page titles".into()).into())
+}
+
+/// Returns whether the given host is the given domain or one of its subdomains.
+fn in_domain(host: &str, domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.');
+
+    host.eq_ignore_ascii_case(domain)
+        || (host.len() > domain.len()
+            && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain)
+            && host[..host.len() - domain.len()].ends_with('.'))
+}
+
+/// Removes from the end of the given URL any punctuation that likely ends the sentence containing
+/// the URL rather than the URL itself.
+fn trim_url(url: &str) -> &str {
+    let mut url = url;
+
+    loop {
+        let trimmed = url.trim_end_matches(|c| ".,;:!?'".contains(c));
+
+        url = if trimmed.ends_with(')') && !trimmed.contains('(') {
+            &trimmed[..trimmed.len() - 1]
+        } else {
+            trimmed
+        };
+
+        if url == trimmed {
+            return url;
+        }
+    }
+}
+
+fn announce_title(
+    HandlerContext {
+        state,
+        request_origin: MsgDest { server_id, target },
+        ..
+    }: HandlerContext,
+    args: Captures,
+) -> Result<Reaction> {
+    let settings: Settings = state.module_settings("url-titles")?;
+    let casemapping = state.server_capabilities(server_id)?.casemapping;
+
+    if !settings
+        .channels
+        .iter()
+        .any(|c| casemapped_str_cmp(casemapping, &c[..], target) == Ordering::Equal)
+    {
+        return Ok(Reaction::None);
+    }
+
+    let url = match Url::parse(trim_url(&args[0])) {
+        Ok(url) => url,
+        Err(_) => return Ok(Reaction::None),
+    };
+
+    let host = url.host_str().unwrap_or_default().to_owned();
+
+    let allowed = match settings.allowed_domains {
+        Some(ref domains) => domains.iter().any(|d| in_domain(&host, d)),
+        None => true,
+    };
+
+    if !allowed || settings.denied_domains.iter().any(|d| in_domain(&host, d)) {
+        return Ok(Reaction::None);
+    }
+
+    let title = match title(&settings, &url) {
+        Ok(Some(title)) => title,
+        Ok(None) => return Ok(Reaction::None),
+        Err(e) => {
+            debug!("Not announcing the title of {:?}: {}", url.as_str(), e);
+            return Ok(Reaction::None);
+        }
+    };
+
+    Ok(Reaction::Msg(
+        format!("[{}] {}", title, host.trim_start_matches("www.")).into(),
+    ))
+}
+
+/// Returns the title of the page at the given URL, from the cache if it is there.
+fn title(settings: &Settings, url: &Url) -> Result<Option<String>> {
+    {
+        let mut cache = lock_cache()?;
+
+        let expired = cache
+            .iter()
+            .filter(|(_, (_, time))| time.elapsed() >= CACHE_LIFETIME)
+            .map(|(url, _)| url.clone())
+            .collect::<Vec<_>>();
+
+        for url in expired {
+            cache.remove(&url);
+        }
+
+        if let Some((title, _)) = cache.get(url.as_str()) {
+            return Ok(title.clone());
+        }
+    }
+
+    let limits = FetchLimits {
+        max_size: settings.max_size,
+        timeout: Duration::from_secs(settings.timeout),
+        ..Default::default()
+    };
+
+    let doc = fetch(url, &limits)?;
+
+    let is_html = doc
+        .content_type
+        .as_ref()
+        .map(|t| {
+            let t = t.to_ascii_lowercase();
+            t.starts_with("text/html") || t.starts_with("application/xhtml+xml")
+        })
+        .unwrap_or(false);
+
+    let title = if doc.status == 200 && is_html {
+        extract_title(&String::from_utf8_lossy(&doc.body))
+    } else {
+        None
+    };
+
+    let mut cache = lock_cache()?;
+
+    if cache.len() >= CACHE_CAPACITY {
+        let oldest = cache
+            .iter()
+            .min_by_key(|(_, (_, time))| *time)
+            .map(|(url, _)| url.clone());
+
+        if let Some(oldest) = oldest {
+            cache.remove(&oldest);
+        }
+    }
+
+    cache.insert(url.to_string(), (title.clone(), Instant::now()));
+
+    Ok(title)
+}
+
+/// Returns the text of the `title` element of the given HTML document, with its character
+/// references decoded and its whitespace collapsed, truncated to `MAX_TITLE_CHARS` characters.
+fn extract_title(html: &str) -> Option<String> {
+    let lowercase = html.to_ascii_lowercase();
+
+    let start = lowercase.find("<title")?;
+    let start = start + lowercase[start..].find('>')? + 1;
+    let end = start + lowercase[start..].find("</title")?;
+
+    let title = decode_char_refs(&html[start..end])
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    if title.is_empty() {
+        return None;
+    }
+
+    Some(match title.char_indices().nth(MAX_TITLE_CHARS) {
+        Some((i, _)) => format!("{}...", &title[..i]),
+        None => title,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn titles() {
+        assert_eq!(
+            extract_title(
+                "<html><head><TITLE lang=en>\n  Fish &amp; Chips &#8212; &#x41;&bogus;\n</title>"
+            ),
+            Some("Fish & Chips \u{2014} A&bogus;".into())
+        );
+        assert_eq!(extract_title("<title> </title>"), None);
+        assert_eq!(extract_title("<p>No title</p>"), None);
+    }
+
+    #[test]
+    fn urls_and_domains() {
+        assert_eq!(trim_url("https://example.com/a."), "https://example.com/a");
+        assert_eq!(trim_url("https://example.com/a)."), "https://example.com/a");
+        assert_eq!(
+            trim_url("https://en.wikipedia.org/wiki/Rust_(language)"),
+            "https://en.wikipedia.org/wiki/Rust_(language)"
+        );
+
+        assert!(in_domain("example.com", "example.com"));
+        assert!(in_domain("www.Example.com", "example.com"));
+        assert!(!in_domain("badexample.com", "example.com"));
+    }
+}
@@ -0,0 +1,488 @@
+//! A fake IRC server, for testing bot modules without a real network.
+//!
+//! A [`FakeServer`] listens on the loopback interface and follows a [`Script`] of lines to expect
+//! from the bot and lines to send it, answering the bot's `PING`s along the way. The function
+//! [`run`] runs a bot with given modules against such a server until the script is complete, so
+//! that a module's handlers may be tested from an ordinary `#[test]` function:
+//!
+//! ```no_run
+//! use irc_bot::modules;
+//! use irc_bot::testing;
+//! use irc_bot::testing::Script;
+//!
+//! let script = Script::new()
+//!     .register("testbot")
+//!     .send(":alice!alice@example.com PRIVMSG testbot :ping")
+//!     .expect(r"^PRIVMSG alice :pong$");
+//!
+//! assert_eq!(testing::run(script, "", vec![modules::default]), Ok(()));
+//! ```
+//!
+//! [`FakeServer`]: <struct.FakeServer.html>
+//! [`Script`]: <struct.Script.html>
+//! [`run`]: <fn.run.html>
+
+use core;
+use core::mk_module;
+use core::ErrorReaction;
+use core::Module;
+use core::State;
+use regex;
+use regex::Regex;
+use std::env;
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::Shutdown;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How long to wait, by default, for each line that a script expects
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the bot to exit once a script is complete
+const EXIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often the fake server checks whether the time allowed for an expected line has run out
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A script for a [`FakeServer`] to follow, as a sequence of lines to expect from the bot and to
+/// send it
+///
+/// [`FakeServer`]: <struct.FakeServer.html>
+#[derive(Clone, Debug)]
+pub struct Script {
+    steps: Vec<Step>,
+    timeout: Duration,
+}
+
+#[derive(Clone, Debug)]
+enum Step {
+    Expect(Regex),
+    Send(String),
+}
+
+impl Script {
+    pub fn new() -> Self {
+        Script {
+            steps: Vec::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Adds a step at which the server waits for a line from the bot that matches the given
+    /// regular expression, ignoring any lines that do not.
+    ///
+    /// Panics if the regular expression is invalid.
+    pub fn expect(mut self, pattern: &str) -> Self {
+        let regex = Regex::new(pattern)
+            .unwrap_or_else(|e| panic!("Invalid regex {:?} in test script: {}", pattern, e));
+
+        self.steps.push(Step::Expect(regex));
+
+        self
+    }
+
+    /// Adds a step at which the server sends the given line to the bot.
+    pub fn send<S>(mut self, line: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.steps.push(Step::Send(line.into()));
+
+        self
+    }
+
+    /// Adds the steps by which a bot with the given nickname registers with the server: the
+    /// server expects `NICK` and `USER` and then sends the welcome numeric and the end of its
+    /// message of the day, after which the bot regards itself as connected.
+    pub fn register(self, nick: &str) -> Self {
+        self.expect(&format!("^NICK :?{}$", regex::escape(nick)))
+            .expect("^USER ")
+            .send(format!(":fake.example 001 {} :Welcome", nick))
+            .send(format!(":fake.example 376 {} :End of /MOTD command.", nick))
+    }
+
+    /// Sets how long the server waits for each line that the script expects before giving up,
+    /// which is ten seconds by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+
+        self
+    }
+}
+
+impl Default for Script {
+    fn default() -> Self {
+        Script::new()
+    }
+}
+
+/// A fake IRC server, which listens for a single connection on the loopback interface and
+/// follows a [`Script`]
+///
+/// Once its script is complete, the server keeps answering `PING`s until the bot sends `QUIT` or
+/// closes the connection.
+///
+/// [`Script`]: <struct.Script.html>
+#[derive(Debug)]
+pub struct FakeServer {
+    addr: SocketAddr,
+    outcome: mpsc::Receiver<Result<(), String>>,
+}
+
+impl FakeServer {
+    /// Starts a server that follows the given script, on a thread of its own.
+    pub fn start(script: Script) -> io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let addr = listener.local_addr()?;
+        let (tx, outcome) = mpsc::channel();
+
+        thread::Builder::new()
+            .name(format!("fake IRC server[{}]", addr))
+            .spawn(move || {
+                let _ = tx.send(serve(&listener, &script));
+            })?;
+
+        Ok(FakeServer { addr, outcome })
+    }
+
+    /// Returns the address at which the server listens.
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns a configuration, in YAML, for a bot with the given nickname to connect to the
+    /// server, without its console.
+    pub fn config(&self, nick: &str) -> String {
+        format!(
+            "nickname: {nick:?}\n\
+             console: false\n\
+             servers:\n  \
+             - name: fake\n    \
+             host: {host:?}\n    \
+             port: {port}\n    \
+             TLS: false\n",
+            nick = nick,
+            host = self.addr.ip().to_string(),
+            port = self.addr.port(),
+        )
+    }
+
+    /// Blocks until the script is complete, returning `Ok(())`, or has failed, returning an
+    /// error message saying how.
+    pub fn wait(&self) -> Result<(), String> {
+        self.outcome
+            .recv()
+            .unwrap_or_else(|_| Err("The fake IRC server's thread panicked.".into()))
+    }
+}
+
+/// Runs a bot with the nickname `testbot` and the given modules against a [`FakeServer`] that
+/// follows the given script, until the script is complete or has failed, and then shuts the bot
+/// down, returning the outcome as [`FakeServer::wait`] does.
+///
+/// The given YAML text, which may be empty, is appended to the configuration of the bot, which
+/// it may therefore extend with top-level fields other than `nickname`, `console`, and
+/// `servers`, such as `module settings`.
+///
+/// [`FakeServer`]: <struct.FakeServer.html>
+/// [`FakeServer::wait`]: <struct.FakeServer.html#method.wait>
+pub fn run<Modls, ModlCtor>(script: Script, config: &str, modules: Modls) -> Result<(), String>
+where
+    Modls: IntoIterator<Item = ModlCtor>,
+    ModlCtor: Fn() -> Module + Send + 'static,
+{
+    let server = FakeServer::start(script).map_err(|e| e.to_string())?;
+    let config = format!("{}{}", server.config("testbot"), config);
+    let done = Arc::new(AtomicBool::new(false));
+    let (exited_tx, exited) = mpsc::channel();
+
+    let mut ctors = modules
+        .into_iter()
+        .map(|ctor| Box::new(ctor) as Box<Fn() -> Module + Send>)
+        .collect::<Vec<_>>();
+
+    {
+        let done = done.clone();
+        ctors.push(Box::new(move || mk_shutdown_module(done.clone())));
+    }
+
+    thread::Builder::new()
+        .name("bot under test".into())
+        .spawn(move || {
+            core::run(
+                config,
+                env::temp_dir(),
+                |e| {
+                    error!("{}", e);
+                    ErrorReaction::Proceed
+                },
+                ctors.iter().map(|ctor| move || (**ctor)()),
+            );
+
+            let _ = exited_tx.send(());
+        })
+        .map_err(|e| e.to_string())?;
+
+    let outcome = server.wait();
+
+    done.store(true, Ordering::SeqCst);
+
+    if exited.recv_timeout(EXIT_TIMEOUT).is_err() {
+        warn!("The bot under test did not exit in time.");
+    }
+
+    outcome
+}
+
+/// Runs a bot with the given configuration, on a thread of its own, with no modules but one that
+/// shuts the bot down once the given flag has been set.
+#[cfg(test)]
+pub(crate) fn spawn_bot(config: String, done: Arc<AtomicBool>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        core::run(
+            config,
+            env::temp_dir(),
+            |_| ErrorReaction::Proceed,
+            vec![move || mk_shutdown_module(done.clone())],
+        )
+    })
+}
+
+/// Accepts a connection from a bot with the nickname `testbot` on the given listener within the
+/// default timeout, and registers it, without ever answering a `PING`, for tests of how the bot
+/// copes with a server that misbehaves.
+#[cfg(test)]
+pub(crate) fn accept_silently(listener: &TcpListener) -> BufReader<TcpStream> {
+    let stream =
+        accept(listener, Instant::now() + DEFAULT_TIMEOUT).unwrap_or_else(|e| panic!("{}", e));
+    stream.set_read_timeout(Some(DEFAULT_TIMEOUT)).unwrap();
+
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+
+    while !line.starts_with("USER ") {
+        line.clear();
+        assert!(reader.read_line(&mut line).unwrap() > 0);
+    }
+
+    write!(
+        reader.get_mut(),
+        ":fake.example 001 testbot :Welcome\r\n\
+         :fake.example 376 testbot :End of /MOTD command.\r\n"
+    )
+    .unwrap();
+
+    reader
+}
+
+/// Reads lines from the bot over the given connection until it sends `QUIT` or closes the
+/// connection, and then closes the connection, as a real server would.
+#[cfg(test)]
+pub(crate) fn await_quit(mut reader: BufReader<TcpStream>) {
+    let mut line = String::new();
+
+    while !line.starts_with("QUIT") {
+        line.clear();
+
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+    }
+}
+
+/// An output that may be read after being handed to a bot, such as one that [`core::simulate`]
+/// runs
+///
+/// [`core::simulate`]: <../fn.simulate.html>
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub(crate) struct SharedOutput(Arc<::std::sync::Mutex<Vec<u8>>>);
+
+#[cfg(test)]
+impl SharedOutput {
+    /// Returns what has been written to the output so far.
+    pub(crate) fn text(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().unwrap()).into_owned()
+    }
+}
+
+#[cfg(test)]
+impl Write for SharedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Returns a module that shuts the bot down once the given flag has been set.
+fn mk_shutdown_module(done: Arc<AtomicBool>) -> Module {
+    mk_module("testing")
+        .periodic(
+            Duration::from_secs(1),
+            Box::new(move |state: &State| {
+                if done.load(Ordering::SeqCst) {
+                    state.shutdown(None)
+                } else {
+                    Ok(())
+                }
+            }),
+        )
+        .end()
+}
+
+fn serve(listener: &TcpListener, script: &Script) -> Result<(), String> {
+    let stream = accept(listener, Instant::now() + script.timeout)?;
+
+    stream
+        .set_read_timeout(Some(POLL_INTERVAL))
+        .map_err(|e| e.to_string())?;
+
+    let mut writer = stream.try_clone().map_err(|e| e.to_string())?;
+    let mut reader = BufReader::new(stream);
+    let mut buf = Vec::new();
+
+    for step in &script.steps {
+        match *step {
+            Step::Send(ref line) => {
+                write!(writer, "{}\r\n", line).map_err(|e| e.to_string())?;
+            }
+            Step::Expect(ref regex) => {
+                let deadline = Instant::now() + script.timeout;
+
+                let expecting = || format!("expecting a line matching {:?}", regex.as_str());
+
+                loop {
+                    match read_line(&mut reader, &mut writer, &mut buf, deadline)
+                        .map_err(|e| format!("{} ({})", e, expecting()))?
+                    {
+                        Some(ref line) if regex.is_match(line) => break,
+                        Some(_) => {}
+                        None => {
+                            return Err(format!("The bot closed the connection ({}).", expecting()))
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Keep answering `PING`s until the bot leaves, so that it does not time out and reconnect, and
+    // then close the connection, as a real server would.
+    thread::spawn(move || {
+        loop {
+            match read_line(
+                &mut reader,
+                &mut writer,
+                &mut buf,
+                Instant::now() + EXIT_TIMEOUT,
+            ) {
+                Ok(Some(ref line)) if !line.starts_with("QUIT") => {}
+                _ => break,
+            }
+        }
+
+        let _ = write!(writer, "ERROR :Closing link\r\n");
+        let _ = writer.shutdown(Shutdown::Both);
+    });
+
+    Ok(())
+}
+
+fn accept(listener: &TcpListener, deadline: Instant) -> Result<TcpStream, String> {
+    listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false).map_err(|e| e.to_string())?;
+                return Ok(stream);
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock && Instant::now() < deadline => {
+                thread::sleep(POLL_INTERVAL)
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                return Err("The bot did not connect to the fake IRC server in time.".into())
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+/// Reads the next line from the bot, answering any `PING`s, or returns `None` if the bot has
+/// closed the connection.
+fn read_line(
+    reader: &mut BufReader<TcpStream>,
+    writer: &mut TcpStream,
+    buf: &mut Vec<u8>,
+    deadline: Instant,
+) -> Result<Option<String>, String> {
+    loop {
+        match reader.read_until(b'\n', buf) {
+            Ok(0) => return Ok(None),
+            Ok(_) if buf.ends_with(b"\n") => {
+                let line = String::from_utf8_lossy(buf)
+                    .trim_end_matches(&['\r', '\n'][..])
+                    .to_owned();
+                buf.clear();
+
+                trace!("Fake IRC server received: {:?}", line);
+
+                if line.starts_with("PING ") {
+                    write!(writer, "PONG{}\r\n", &line["PING".len()..])
+                        .map_err(|e| e.to_string())?;
+                    continue;
+                }
+
+                return Ok(Some(line));
+            }
+            Ok(_) => return Ok(None),
+            Err(ref e)
+                if (e.kind() == io::ErrorKind::WouldBlock
+                    || e.kind() == io::ErrorKind::TimedOut)
+                    && Instant::now() < deadline => {}
+            Err(ref e)
+                if e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut =>
+            {
+                return Err("Timed out waiting for a line from the bot.".into())
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use modules;
+
+    #[test]
+    fn ping() {
+        let script = Script::new()
+            .register("testbot")
+            .send(":alice!alice@example.com PRIVMSG testbot :ping")
+            .expect(r"^PRIVMSG alice :pong$");
+
+        assert_eq!(run(script, "", vec![modules::default]), Ok(()));
+
+        let script = Script::new()
+            .timeout(Duration::from_secs(1))
+            .register("testbot")
+            .expect("^PRIVMSG alice ");
+
+        assert!(run(script, "", vec![modules::default]).is_err());
+    }
+}
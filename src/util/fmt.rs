@@ -2,6 +2,8 @@ use core;
 use std::any::Any;
 use std::borrow::Cow;
 use std::fmt;
+use std::fmt::Write;
+use std::time::Duration;
 use uuid::Uuid;
 
 pub(crate) struct FmtAny<'a>(pub(crate) &'a Any);
@@ -44,3 +46,79 @@ impl_fmt!(fmt::Debug {
 pub(crate) fn debug_uuid(uuid: &Uuid, formatter: &mut fmt::Formatter) -> fmt::Result {
     write!(formatter, "{}", uuid.hyphenated())
 }
+
+/// Formats the given duration for people to read, in terms of its two largest units, e.g., "3 days
+/// and 4 hours", or "less than a minute".
+pub(crate) fn human_duration(d: Duration) -> String {
+    const UNITS: &[(u64, &str)] = &[
+        (7 * 24 * 60 * 60, "week"),
+        (24 * 60 * 60, "day"),
+        (60 * 60, "hour"),
+        (60, "minute"),
+    ];
+
+    let mut secs = d.as_secs();
+
+    let parts = UNITS
+        .iter()
+        .filter_map(|&(unit_secs, name)| {
+            let n = secs / unit_secs;
+            secs %= unit_secs;
+
+            match n {
+                0 => None,
+                1 => Some(format!("1 {}", name)),
+                n => Some(format!("{} {}s", n, name)),
+            }
+        })
+        .collect::<Vec<_>>();
+
+    match parts.len() {
+        0 => "less than a minute".into(),
+        1 => parts[0].clone(),
+        _ => format!("{} and {}", parts[0], parts[1]),
+    }
+}
+
+/// Formats the given string as a JSON string literal.
+pub(crate) fn json_str(s: &str) -> String {
+    let mut output = String::with_capacity(s.len() + 2);
+
+    output.push('"');
+
+    for c in s.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(output, "\\u{:04x}", c as u32);
+            }
+            c => output.push(c),
+        }
+    }
+
+    output.push('"');
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn human_durations() {
+        let d = Duration::from_secs;
+
+        assert_eq!(human_duration(d(59)), "less than a minute");
+        assert_eq!(human_duration(d(60)), "1 minute");
+        assert_eq!(
+            human_duration(d(2 * 3600 + 5 * 60 + 7)),
+            "2 hours and 5 minutes"
+        );
+        assert_eq!(human_duration(d(8 * 86_400 + 60)), "1 week and 1 day");
+    }
+}
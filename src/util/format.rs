@@ -0,0 +1,327 @@
+//! The formatting codes of IRC messages, as introduced by mIRC, with which text may be colored,
+//! emboldened, underlined, etc.
+
+use std::borrow::Cow;
+use std::fmt::Display;
+use std::fmt::Write;
+use std::iter::Peekable;
+use std::str::Chars;
+
+pub const BOLD: char = '\u{02}';
+
+/// Followed by a foreground color code and, optionally, a comma and a background color code, each
+/// of one or two digits, this code sets the colors of the text that follows; alone, it resets
+/// them.
+pub const COLOR: char = '\u{03}';
+
+/// As `COLOR`, but with colors given as six hexadecimal digits, `RRGGBB`
+pub const HEX_COLOR: char = '\u{04}';
+
+pub const MONOSPACE: char = '\u{11}';
+
+/// Swaps the foreground and background colors of the text that follows.
+pub const REVERSE: char = '\u{16}';
+
+pub const ITALIC: char = '\u{1D}';
+
+pub const STRIKETHROUGH: char = '\u{1E}';
+
+pub const UNDERLINE: char = '\u{1F}';
+
+/// Resets all formatting of the text that follows.
+pub const RESET: char = '\u{0F}';
+
+/// The colors that have standard codes, in the order of their codes, from `0` through `15`
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Color {
+    White,
+    Black,
+    Blue,
+    Green,
+    Red,
+    Brown,
+    Magenta,
+    Orange,
+    Yellow,
+    LightGreen,
+    Cyan,
+    LightCyan,
+    LightBlue,
+    Pink,
+    Grey,
+    LightGrey,
+}
+
+impl Color {
+    /// Returns the color's code, as written after `COLOR`.
+    pub fn code(self) -> u8 {
+        self as u8
+    }
+}
+
+/// The code that, as either a foreground or a background color code, leaves the color as the
+/// client displays it by default
+const DEFAULT_COLOR_CODE: u8 = 99;
+
+/// A combination of formatting, which may be applied to text
+///
+/// # Examples
+///
+/// ```
+/// use irc_bot::util::format::Color;
+/// use irc_bot::util::format::Style;
+///
+/// assert_eq!(
+///     Style::new().bold().color(Color::Red).apply("alert"),
+///     "\u{2}\u{3}04alert\u{f}"
+/// );
+/// ```
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Style {
+    bold: bool,
+    italic: bool,
+    underline: bool,
+    strikethrough: bool,
+    monospace: bool,
+    reverse: bool,
+    color: Option<Color>,
+    background: Option<Color>,
+}
+
+impl Style {
+    /// Returns a style with no formatting, to which formatting may be added with the other
+    /// methods.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn bold(self) -> Self {
+        Style { bold: true, ..self }
+    }
+
+    pub fn italic(self) -> Self {
+        Style {
+            italic: true,
+            ..self
+        }
+    }
+
+    pub fn underline(self) -> Self {
+        Style {
+            underline: true,
+            ..self
+        }
+    }
+
+    pub fn strikethrough(self) -> Self {
+        Style {
+            strikethrough: true,
+            ..self
+        }
+    }
+
+    pub fn monospace(self) -> Self {
+        Style {
+            monospace: true,
+            ..self
+        }
+    }
+
+    pub fn reverse(self) -> Self {
+        Style {
+            reverse: true,
+            ..self
+        }
+    }
+
+    /// Sets the foreground color.
+    pub fn color(self, color: Color) -> Self {
+        Style {
+            color: Some(color),
+            ..self
+        }
+    }
+
+    /// Sets the background color.
+    pub fn background(self, color: Color) -> Self {
+        Style {
+            background: Some(color),
+            ..self
+        }
+    }
+
+    /// Returns the formatting codes that begin text in this style.
+    pub fn codes(&self) -> String {
+        let mut codes = String::new();
+
+        for &(enabled, code) in &[
+            (self.bold, BOLD),
+            (self.italic, ITALIC),
+            (self.underline, UNDERLINE),
+            (self.strikethrough, STRIKETHROUGH),
+            (self.monospace, MONOSPACE),
+            (self.reverse, REVERSE),
+        ] {
+            if enabled {
+                codes.push(code);
+            }
+        }
+
+        // Two-digit codes are written, so that text beginning with a digit isn't mistaken for
+        // part of a code.
+        match (self.color, self.background) {
+            (None, None) => {}
+            (fg, None) => {
+                let _ = write!(codes, "{}{:02}", COLOR, color_code(fg));
+            }
+            (fg, Some(bg)) => {
+                let _ = write!(codes, "{}{:02},{:02}", COLOR, color_code(fg), bg.code());
+            }
+        }
+
+        codes
+    }
+
+    /// Returns the given text in this style, followed by `RESET`.
+    pub fn apply<S>(&self, text: S) -> String
+    where
+        S: Display,
+    {
+        format!("{}{}{}", self.codes(), text, RESET)
+    }
+}
+
+fn color_code(color: Option<Color>) -> u8 {
+    color.map(Color::code).unwrap_or(DEFAULT_COLOR_CODE)
+}
+
+/// Returns the given text in bold, followed by `RESET`.
+pub fn bold<S>(text: S) -> String
+where
+    S: Display,
+{
+    Style::new().bold().apply(text)
+}
+
+/// Returns the given text underlined, followed by `RESET`.
+pub fn underline<S>(text: S) -> String
+where
+    S: Display,
+{
+    Style::new().underline().apply(text)
+}
+
+/// Returns the given text in the given color, followed by `RESET`.
+pub fn colored<S>(color: Color, text: S) -> String
+where
+    S: Display,
+{
+    Style::new().color(color).apply(text)
+}
+
+/// Returns the given text with all formatting codes, including the colors given after `COLOR` and
+/// `HEX_COLOR`, removed.
+pub fn strip_formatting<'a>(text: &'a str) -> Cow<'a, str> {
+    if !text.chars().any(is_formatting_code) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let (max_digits, radix) = match c {
+            COLOR => (2, 10),
+            HEX_COLOR => (6, 16),
+            c if is_formatting_code(c) => continue,
+            c => {
+                output.push(c);
+                continue;
+            }
+        };
+
+        if skip_digits(&mut chars, max_digits, radix) > 0 && chars.peek() == Some(&',') {
+            let mut lookahead = chars.clone();
+            lookahead.next();
+
+            if next_is_digit(&mut lookahead, radix) {
+                chars = lookahead;
+                skip_digits(&mut chars, max_digits, radix);
+            }
+        }
+    }
+
+    Cow::Owned(output)
+}
+
+/// Skips up to the given number of digits in the given radix, returning how many were skipped.
+fn skip_digits(chars: &mut Peekable<Chars>, max_digits: usize, radix: u32) -> usize {
+    let mut digits = 0;
+
+    while digits < max_digits && next_is_digit(chars, radix) {
+        chars.next();
+        digits += 1;
+    }
+
+    digits
+}
+
+fn next_is_digit(chars: &mut Peekable<Chars>, radix: u32) -> bool {
+    match chars.peek() {
+        Some(c) => c.is_digit(radix),
+        None => false,
+    }
+}
+
+fn is_formatting_code(c: char) -> bool {
+    [
+        BOLD,
+        COLOR,
+        HEX_COLOR,
+        MONOSPACE,
+        REVERSE,
+        ITALIC,
+        STRIKETHROUGH,
+        UNDERLINE,
+        RESET,
+    ]
+    .contains(&c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn styles() {
+        assert_eq!(Style::new().apply("plain"), "plain\u{f}");
+        assert_eq!(bold("b"), "\u{2}b\u{f}");
+        assert_eq!(
+            Style::new()
+                .underline()
+                .color(Color::White)
+                .background(Color::LightGrey)
+                .apply(1),
+            "\u{1f}\u{3}00,151\u{f}"
+        );
+        assert_eq!(Style::new().background(Color::Blue).codes(), "\u{3}99,02");
+    }
+
+    #[test]
+    fn stripping() {
+        assert_eq!(strip_formatting("plain, text"), "plain, text");
+        assert_eq!(
+            strip_formatting("\u{2}bot\u{2}: \u{3}4,12help\u{3} \u{3}032 \u{3},x"),
+            "bot: help 2 ,x"
+        );
+        assert_eq!(strip_formatting("\u{3}04,text"), ",text");
+        assert_eq!(
+            strip_formatting("\u{4}FF0000,00ff00red\u{f}\u{1d}\u{1f}\u{16}!"),
+            "red!"
+        );
+        assert_eq!(
+            strip_formatting("\u{1}ACTION waves\u{1}"),
+            "\u{1}ACTION waves\u{1}"
+        );
+    }
+}
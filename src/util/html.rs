@@ -0,0 +1,50 @@
+//! Utilities for handling HTML and XML text.
+
+/// Decodes the character references in the given text, whether named, for the few names common
+/// in titles, or numeric. References that aren't understood are left in place.
+pub(crate) fn decode_char_refs(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(i) = rest.find('&') {
+        decoded.push_str(&rest[..i]);
+        rest = &rest[i..];
+
+        let reference = rest[1..]
+            .find(';')
+            .filter(|&len| len <= 10)
+            .map(|len| &rest[1..=len]);
+
+        let c = reference.and_then(|reference| match reference {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "nbsp" => Some(' '),
+            _ if reference.starts_with("#x") || reference.starts_with("#X") => {
+                u32::from_str_radix(&reference[2..], 16)
+                    .ok()
+                    .and_then(::std::char::from_u32)
+            }
+            _ if reference.starts_with('#') => {
+                reference[1..].parse().ok().and_then(::std::char::from_u32)
+            }
+            _ => None,
+        });
+
+        match (c, reference) {
+            (Some(c), Some(reference)) => {
+                decoded.push(c);
+                rest = &rest[reference.len() + 2..];
+            }
+            _ => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
@@ -39,10 +39,64 @@ lazy_static! {
     ).expect(STATIC_REGEX_PARSE_ERR_MSG);
 }
 
+/// A set of rules for case-folding nicknames and channel names, as advertised by an IRC server
+/// with the `CASEMAPPING` [`RPL_ISUPPORT`] parameter.
+///
+/// The default, as for servers that don't advertise a case-mapping, is `Rfc1459`.
+///
+/// [`RPL_ISUPPORT`]: <https://modern.ircdocs.horse/#rplisupport-005>
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CaseMapping {
+    /// Only the US-ASCII letters `A` through `Z` are folded to `a` through `z`.
+    Ascii,
+
+    /// As `Ascii`, with `[`, `]`, `\`, and `~` additionally folded to `{`, `}`, `|`, and `^`,
+    /// respectively.
+    Rfc1459,
+
+    /// As `Rfc1459`, except that `~` and `^` are not considered equivalent.
+    StrictRfc1459,
+}
+
+impl Default for CaseMapping {
+    fn default() -> Self {
+        CaseMapping::Rfc1459
+    }
+}
+
+impl FromStr for CaseMapping {
+    type Err = ();
+
+    /// Parses the value of a `CASEMAPPING` [`RPL_ISUPPORT`] parameter.
+    ///
+    /// [`RPL_ISUPPORT`]: <https://modern.ircdocs.horse/#rplisupport-005>
+    fn from_str(s: &str) -> StdResult<Self, ()> {
+        match s {
+            "ascii" => Ok(CaseMapping::Ascii),
+            "rfc1459" => Ok(CaseMapping::Rfc1459),
+            "strict-rfc1459" => Ok(CaseMapping::StrictRfc1459),
+            _ => Err(()),
+        }
+    }
+}
+
 /// Compares two strings case-insensitively, using the IRC rules for case-folding.
 ///
 /// This function optimizes for comparing short strings such as nicknames and channel names.
+///
+/// This is equivalent to `casemapped_str_cmp(CaseMapping::Rfc1459, x, y)`.
 pub fn case_insensitive_str_cmp<S1, S2>(x: S1, y: S2) -> Ordering
+where
+    S1: Into<InlinableString>,
+    S2: Into<InlinableString>,
+{
+    casemapped_str_cmp(CaseMapping::Rfc1459, x, y)
+}
+
+/// Compares two strings case-insensitively, using the given IRC case-folding rules.
+///
+/// This function optimizes for comparing short strings such as nicknames and channel names.
+pub fn casemapped_str_cmp<S1, S2>(mapping: CaseMapping, x: S1, y: S2) -> Ordering
 where
     S1: Into<InlinableString>,
     S2: Into<InlinableString>,
@@ -58,24 +112,44 @@ where
     let mut x = Buffer::from(x.as_bytes());
     let mut y = Buffer::from(y.as_bytes());
 
-    fn finish_irc_lowercasing(s: &mut Buffer) {
-        for mut c in s {
-            *c = match c {
-                b'[' => b'{',
-                b']' => b'}',
-                b'\\' => b'|',
-                b'~' => b'^',
-                _ => continue,
-            }
+    fn finish_irc_lowercasing(mapping: CaseMapping, s: &mut Buffer) {
+        if mapping == CaseMapping::Ascii {
+            return;
+        }
+
+        for c in s {
+            *c = fold_special_byte(mapping, *c);
         }
     }
 
-    finish_irc_lowercasing(&mut x);
-    finish_irc_lowercasing(&mut y);
+    finish_irc_lowercasing(mapping, &mut x);
+    finish_irc_lowercasing(mapping, &mut y);
 
     x.cmp(&y)
 }
 
+/// Folds the given string's case using the given IRC case-folding rules, so that strings that
+/// the rules consider equal are folded to the same string, for use as, e.g., keys in maps.
+pub fn casefold(mapping: CaseMapping, s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            c if !c.is_ascii() => c,
+            c => fold_special_byte(mapping, c.to_ascii_lowercase() as u8) as char,
+        })
+        .collect()
+}
+
+/// Folds the characters other than letters that the given rules consider uppercase.
+fn fold_special_byte(mapping: CaseMapping, c: u8) -> u8 {
+    match c {
+        b'[' if mapping != CaseMapping::Ascii => b'{',
+        b']' if mapping != CaseMapping::Ascii => b'}',
+        b'\\' if mapping != CaseMapping::Ascii => b'|',
+        b'~' if mapping == CaseMapping::Rfc1459 => b'^',
+        c => c,
+    }
+}
+
 /// A string type representing the name of an IRC channel.
 ///
 /// This wrapper around an interned string (specifically, a Servo [`Atom`]) ensures that the string
@@ -236,5 +310,12 @@ mod tests {
 
             ChannelName::to_string(&cn) == ToString::to_string(&cn)
         }
+
+        fn casefold_agrees_with_cmp(a: String, b: String) -> bool {
+            let m = CaseMapping::Rfc1459;
+
+            (casefold(m, &a) == casefold(m, &b))
+                == (casemapped_str_cmp(m, &a[..], &b[..]) == Ordering::Equal)
+        }
     }
 }
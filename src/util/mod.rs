@@ -5,9 +5,12 @@ use std::borrow::Cow;
 use std::panic;
 
 pub(crate) mod fmt;
+pub mod format;
+pub(crate) mod html;
 pub mod irc;
 pub(crate) mod lock;
 pub mod regex;
+pub(crate) mod sha256;
 pub mod yaml;
 
 pub(crate) const STATIC_REGEX_PARSE_ERR_MSG: &str =
@@ -146,6 +149,26 @@ impl<'a> ExactSizeIterator for Munge<'a> {
     }
 }
 
+/// Returns the Levenshtein distance between the given strings, i.e., the number of insertions,
+/// deletions, and substitutions of `char`s needed to turn one into the other.
+pub(crate) fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    row[b.len()]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -214,6 +237,16 @@ mod tests {
         );
     }
 
+    #[test]
+    fn edit_distance_examples() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("wether", "weather"), 1);
+        assert_eq!(edit_distance("weather", "wether"), 1);
+        assert_eq!(edit_distance("hlep", "help"), 2);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+        assert_eq!(edit_distance("", "ping"), 4);
+    }
+
     quickcheck! {
         fn zwsp_munge_exact_size(string: String, needles: Vec<String>) -> () {
             let it = zwsp_munge(&string, needles);
@@ -69,11 +69,14 @@ pub mod str {
         pub static ref YAML_STR_ID: Yaml = mk_str("id");
         pub static ref YAML_STR_LIST: Yaml = mk_str("list");
         pub static ref YAML_STR_MSG: Yaml = mk_str("msg");
+        pub static ref YAML_STR_NAME: Yaml = mk_str("name");
         pub static ref YAML_STR_R: Yaml = mk_str("r");
         pub static ref YAML_STR_REGEX: Yaml = mk_str("regex");
         pub static ref YAML_STR_S: Yaml = mk_str("s");
+        pub static ref YAML_STR_SERVER: Yaml = mk_str("server");
         pub static ref YAML_STR_STRING: Yaml = mk_str("string");
         pub static ref YAML_STR_TAG: Yaml = mk_str("tag");
+        pub static ref YAML_STR_TO: Yaml = mk_str("to");
     }
 }
 